@@ -0,0 +1,355 @@
+use bytes::{BufMut, BytesMut};
+use nom::bytes::streaming::take;
+use nom::number::complete::le_u32;
+use nom::IResult;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::encode::{self, Decodable, Encodable, HexError};
+use crate::transaction::Transaction;
+use crate::wallet::{hash256, Parse, Serialize, U256};
+
+/// Block hash, displayed in reversed byte order like transaction ids
+#[derive(Debug, PartialOrd, PartialEq, Clone, Hash, Eq, Default)]
+pub struct BlockHash([u8; 32]);
+impl Copy for BlockHash {}
+
+impl AsRef<[u8]> for BlockHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Display for BlockHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self))
+    }
+}
+
+impl BlockHash {
+    /// Parse the little endian wire bytes
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let mut buf = [0u8; 32];
+        let (input, hash) = take(32usize)(input)?;
+        buf.copy_from_slice(hash);
+        buf.reverse();
+        Ok((input, BlockHash(buf)))
+    }
+
+    pub fn to_little_endian(&self) -> Vec<u8> {
+        self.0.iter().rev().cloned().collect()
+    }
+}
+
+#[derive(Fail, Debug)]
+pub enum BlockHashError {
+    #[fail(display = "block hash must be 32 bytes")]
+    InvalidLength,
+    #[fail(display = "hex str decode str error")]
+    HexDecodeError,
+}
+
+impl FromStr for BlockHash {
+    type Err = BlockHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s.trim()).map_err(|_| BlockHashError::HexDecodeError)?;
+        if bytes.len() != 32 {
+            return Err(BlockHashError::InvalidLength);
+        }
+        let mut content = [0u8; 32];
+        content.copy_from_slice(&bytes);
+        Ok(BlockHash(content))
+    }
+}
+
+/// Expected hashes for a block at difficulty 1, 2^256 / the difficulty 1 target
+const DIFFICULTY_1_WORK: f64 = 4_295_032_833.000_015;
+
+/// How many times harder than difficulty 1, the `0x1d00ffff` target, `bits` is to meet,
+/// computed the way Bitcoin Core's `getdifficulty` does
+pub fn bits_to_difficulty(bits: u32) -> f64 {
+    let mut shift = (bits >> 24) & 0xff;
+    let mut difficulty = f64::from(0xffff) / f64::from(bits & 0x00ff_ffff);
+    while shift < 29 {
+        difficulty *= 256.0;
+        shift += 1;
+    }
+    while shift > 29 {
+        difficulty /= 256.0;
+        shift -= 1;
+    }
+    difficulty
+}
+
+/// Target a `difficulty` stands for, the difficulty 1 target divided by it. Rounded to the
+/// 53 bits an f64 holds, a difficulty too small for 256 bits gives the largest target.
+pub fn difficulty_to_target(difficulty: f64) -> U256 {
+    if difficulty.is_nan() || difficulty <= 0.0 {
+        return U256::max_value();
+    }
+    // 0xffff * 2^208 / difficulty as mantissa * 2^shift
+    let mut mantissa = f64::from(0xffff) / difficulty;
+    if mantissa == 0.0 {
+        return U256::zero();
+    }
+    if mantissa.is_infinite() {
+        return U256::max_value();
+    }
+    let mut shift = 208i32;
+    while mantissa < (1u64 << 52) as f64 {
+        mantissa *= 2.0;
+        shift -= 1;
+    }
+    while mantissa >= (1u64 << 53) as f64 {
+        mantissa /= 2.0;
+        shift += 1;
+    }
+    let mantissa = U256::from(mantissa as u64);
+    if shift > 256 - 53 {
+        U256::max_value()
+    } else if shift >= 0 {
+        mantissa << shift as usize
+    } else if shift > -53 {
+        mantissa >> (-shift) as usize
+    } else {
+        U256::zero()
+    }
+}
+
+/// Hashes a second the network did over consecutive `headers`, the work of all but the
+/// first divided by the time they span. Timestamps need not increase, so the span runs
+/// from the earliest to the latest. None for fewer than two headers or no time between
+/// them.
+pub fn estimate_hashrate(headers: &[BlockHeader]) -> Option<f64> {
+    let earliest = headers.iter().map(|header| header.timestamp).min()?;
+    let latest = headers.iter().map(|header| header.timestamp).max()?;
+    if latest == earliest {
+        return None;
+    }
+    let work: f64 = headers[1..]
+        .iter()
+        .map(|header| bits_to_difficulty(header.bits) * DIFFICULTY_1_WORK)
+        .sum();
+    Some(work / f64::from(latest - earliest))
+}
+
+/// 80 bytes block header
+#[derive(Debug, PartialEq, Clone, Hash, Eq)]
+pub struct BlockHeader {
+    pub version: u32,
+    pub prev_block: BlockHash,
+    pub merkle_root: [u8; 32],
+    pub timestamp: u32,
+    pub bits: u32,
+    pub nonce: u32,
+}
+impl Copy for BlockHeader {}
+
+impl BlockHeader {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, version) = le_u32(input)?;
+        let (input, prev_block) = BlockHash::parse(input)?;
+        let (input, merkle_root) = take(32usize)(input)?;
+        let (input, timestamp) = le_u32(input)?;
+        let (input, bits) = le_u32(input)?;
+        let (input, nonce) = le_u32(input)?;
+
+        // merkle root stays in wire order
+        let mut root = [0u8; 32];
+        root.copy_from_slice(merkle_root);
+        Ok((
+            input,
+            BlockHeader {
+                version,
+                prev_block,
+                merkle_root: root,
+                timestamp,
+                bits,
+                nonce,
+            },
+        ))
+    }
+
+    /// Parse the 80 header bytes, like `getblockheader <hash> false` prints
+    pub fn from_hex(hex: &str) -> Result<Self, HexError> {
+        encode::decode_hex(hex, "block header")
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.serialize())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(80);
+        buf.put_u32_le(self.version);
+        buf.put(self.prev_block.to_little_endian());
+        buf.put(&self.merkle_root[..]);
+        buf.put_u32_le(self.timestamp);
+        buf.put_u32_le(self.bits);
+        buf.put_u32_le(self.nonce);
+        buf.take().to_vec()
+    }
+
+    pub fn hash(&self) -> BlockHash {
+        BlockHash::parse(&hash256(&self.serialize()))
+            .expect("hash256 is 32 bytes")
+            .1
+    }
+}
+
+impl Encodable for BlockHeader {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let bytes = self.serialize();
+        buf.extend_from_slice(&bytes);
+        bytes.len()
+    }
+}
+
+impl Decodable for BlockHeader {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        BlockHeader::parse(input)
+    }
+}
+
+impl Serialize for BlockHeader {
+    fn serialize_bytes(&self) -> Vec<u8> {
+        self.serialize()
+    }
+}
+
+impl Parse for BlockHeader {
+    fn parse_bytes(bytes: &[u8]) -> Option<Self> {
+        match BlockHeader::parse(bytes) {
+            Ok((rest, header)) if rest.is_empty() => Some(header),
+            _ => None,
+        }
+    }
+}
+
+/// A header and its transactions, coinbase first
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub txs: Vec<Transaction>,
+}
+
+impl Block {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, header) = BlockHeader::parse(input)?;
+        let (input, txs) = Vec::<Transaction>::consensus_decode(input)?;
+        Ok((input, Block { header, txs }))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        encode::serialize(self)
+    }
+
+    pub fn hash(&self) -> BlockHash {
+        self.header.hash()
+    }
+}
+
+impl Encodable for Block {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        self.header.consensus_encode(buf) + self.txs.consensus_encode(buf)
+    }
+}
+
+impl Decodable for Block {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        Block::parse(input)
+    }
+}
+
+mod test {
+    use super::{
+        bits_to_difficulty, difficulty_to_target, estimate_hashrate, Block, BlockHash, BlockHeader,
+    };
+    use crate::wallet::Hex;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_block_header() {
+        let data = hex!("020000208ec39428b17323fa0ddec8e887b4a7c53b8c0a0a220cfd0000000000000000005b0750fce0a889502d40508d39576821155e9c9e3f5c3157f961db38fd8b25be1e77a759e93c0118a4ffd71d");
+        let (rest, header) = BlockHeader::parse(&data[..]).unwrap();
+        assert!(rest.is_empty());
+
+        assert_eq!(header.version, 0x2000_0002);
+        assert_eq!(
+            header.prev_block,
+            BlockHash::from_str("000000000000000000fd0c220a0a8c3bc5a7b487e8c8de0dfa2373b12894c38e")
+                .unwrap()
+        );
+        assert_eq!(header.timestamp, 0x59a7_771e);
+        assert_eq!(header.bits, 0x1801_3ce9);
+        assert_eq!(header.nonce, 0x1dd7_ffa4);
+        assert_eq!(
+            format!("{}", header.hash()),
+            "0000000000000000007e9e4c586439b0cdbe13b1370bdd9435d76a644d047523".to_string()
+        );
+        assert_eq!(header.hex(), hex::encode(&data[..]));
+    }
+
+    #[test]
+    fn test_genesis_block() {
+        let data = hex!("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000");
+        let (rest, block) = Block::parse(&data[..]).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            format!("{}", block.hash()),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        assert_eq!(block.txs.len(), 1);
+        // a single transaction is its own merkle root
+        assert_eq!(
+            block.txs[0].id().to_string(),
+            hex::encode(
+                block
+                    .header
+                    .merkle_root
+                    .iter()
+                    .rev()
+                    .cloned()
+                    .collect::<Vec<u8>>()
+            )
+        );
+        assert_eq!(block.serialize(), data.to_vec());
+    }
+
+    #[test]
+    fn test_difficulty() {
+        use crate::mining::{bits_to_target, target_to_bits};
+        use crate::wallet::U256;
+
+        assert_eq!(bits_to_difficulty(0x1d00_ffff), 1.0);
+        // the header of test_block_header, mined in August 2017
+        assert!((bits_to_difficulty(0x1801_3ce9) - 888_171_856_257.320_6).abs() < 1e-3);
+        assert_eq!(difficulty_to_target(1.0), bits_to_target(0x1d00_ffff));
+        for bits in &[0x1d00_ffffu32, 0x1801_3ce9, 0x1703_4219, 0x207f_ffff] {
+            let target = difficulty_to_target(bits_to_difficulty(*bits));
+            assert_eq!(target_to_bits(target), *bits);
+        }
+        assert_eq!(difficulty_to_target(0.0), U256::max_value());
+        assert_eq!(difficulty_to_target(1e-80), U256::max_value());
+        assert_eq!(difficulty_to_target(1e80), U256::zero());
+
+        let header = BlockHeader {
+            version: 1,
+            prev_block: BlockHash::default(),
+            merkle_root: [0u8; 32],
+            timestamp: 1_500_000_000,
+            bits: 0x1d00_ffff,
+            nonce: 0,
+        };
+        let mut headers = vec![header; 4];
+        // out of order timestamps still span 1800 seconds
+        for (header, offset) in headers.iter_mut().zip(&[0, 1200, 600, 1800]) {
+            header.timestamp += offset;
+        }
+        let hashrate = estimate_hashrate(&headers).unwrap();
+        assert!((hashrate - 3.0 * 4_295_032_833.0 / 1800.0).abs() < 1e-3);
+        assert_eq!(estimate_hashrate(&headers[..1]), None);
+        assert_eq!(estimate_hashrate(&[]), None);
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use crate::block::Block;
+
+pub const MAINNET_MAGIC: [u8; 4] = [0xf9, 0xbe, 0xb4, 0xd9];
+pub const TESTNET_MAGIC: [u8; 4] = [0x0b, 0x11, 0x09, 0x07];
+pub const TESTNET4_MAGIC: [u8; 4] = [0x1c, 0x16, 0x3f, 0x28];
+pub const SIGNET_MAGIC: [u8; 4] = [0x0a, 0x03, 0xcf, 0x40];
+pub const REGTEST_MAGIC: [u8; 4] = [0xfa, 0xbf, 0xb5, 0xda];
+
+/// A serialized block can not be larger than the 4M weight limit
+pub const MAX_BLOCK_SIZE: usize = 4_000_000;
+
+/// Streams the blocks of one `blk*.dat` file. Every record is the network magic, a little
+/// endian size and the block; anything between records, the zero padding Bitcoin Core
+/// preallocates, a record cut short by a crash or one that does not parse is skipped.
+/// Blocks come in the order they were written, not in height order.
+pub struct BlockFileReader<R: Read> {
+    reader: BufReader<R>,
+    magic: [u8; 4],
+    xor_key: Option<[u8; 8]>,
+    /// Position in the file, the xor key is applied by file offset
+    offset: u64,
+    /// Bytes of a rejected record, scanned again before reading on
+    pending: VecDeque<u8>,
+    skipped: usize,
+}
+
+impl<R: Read> BlockFileReader<R> {
+    pub fn new(reader: R, magic: [u8; 4]) -> Self {
+        BlockFileReader {
+            reader: BufReader::new(reader),
+            magic,
+            xor_key: None,
+            offset: 0,
+            pending: VecDeque::new(),
+            skipped: 0,
+        }
+    }
+
+    /// Bitcoin Core 28 obfuscates block files with the key in `blocks/xor.dat`
+    pub fn with_xor_key(mut self, key: [u8; 8]) -> Self {
+        if key != [0u8; 8] {
+            self.xor_key = Some(key);
+        }
+        self
+    }
+
+    /// Records found but not returned, partial or malformed
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut byte = [0u8; 1];
+        loop {
+            match self.reader.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        self.unmask(&mut byte);
+        Ok(Some(byte[0]))
+    }
+
+    /// Fill as much of `buf` as the file has, returns the length read
+    fn read_up_to(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut len = 0;
+        while len < buf.len() {
+            match self.pending.pop_front() {
+                Some(byte) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                None => break,
+            }
+        }
+        while len < buf.len() {
+            match self.reader.read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.unmask(&mut buf[len..len + n]);
+                    len += n;
+                }
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(len)
+    }
+
+    /// Undo the obfuscation of bytes freshly read at `self.offset`
+    fn unmask(&mut self, bytes: &mut [u8]) {
+        if let Some(key) = self.xor_key {
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte ^= key[((self.offset + i as u64) % 8) as usize];
+            }
+        }
+        self.offset += bytes.len() as u64;
+    }
+
+    /// Read up to the next magic, false at the end of the file
+    fn seek_magic(&mut self) -> io::Result<bool> {
+        let mut window = [0u8; 4];
+        let mut filled = 0;
+        while let Some(byte) = self.read_byte()? {
+            window = [window[1], window[2], window[3], byte];
+            filled += 1;
+            if filled >= 4 && window == self.magic {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn next_block(&mut self) -> io::Result<Option<Block>> {
+        while self.seek_magic()? {
+            let mut size = [0u8; 4];
+            let len = self.read_up_to(&mut size)?;
+            if len < 4 {
+                self.skipped += 1;
+                return Ok(None);
+            }
+            let size = u32::from_le_bytes(size) as usize;
+            if size < 80 || size > MAX_BLOCK_SIZE {
+                self.skipped += 1;
+                // the size bytes come before whatever a cut short record left pending
+                for byte in size_bytes(size).iter().rev() {
+                    self.pending.push_front(*byte);
+                }
+                continue;
+            }
+
+            let mut payload = vec![0u8; size];
+            let len = self.read_up_to(&mut payload)?;
+            payload.truncate(len);
+            match Block::parse(&payload) {
+                Ok((rest, block)) if len == size && rest.is_empty() => return Ok(Some(block)),
+                _ => {
+                    // a cut short record may have swallowed the start of the next one
+                    self.skipped += 1;
+                    let mut retry: VecDeque<u8> = size_bytes(size).iter().cloned().collect();
+                    retry.extend(payload);
+                    retry.append(&mut self.pending);
+                    self.pending = retry;
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn size_bytes(size: usize) -> [u8; 4] {
+    (size as u32).to_le_bytes()
+}
+
+impl<R: Read> Iterator for BlockFileReader<R> {
+    type Item = io::Result<Block>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_block().transpose()
+    }
+}
+
+/// Every `blk*.dat` file of a Bitcoin Core `blocks` directory, in file number order
+pub struct BlockFiles {
+    paths: VecDeque<PathBuf>,
+    magic: [u8; 4],
+    xor_key: [u8; 8],
+    current: Option<BlockFileReader<File>>,
+    skipped: usize,
+}
+
+impl BlockFiles {
+    pub fn open<P: AsRef<Path>>(blocks_dir: P, magic: [u8; 4]) -> io::Result<Self> {
+        let blocks_dir = blocks_dir.as_ref();
+        let mut paths: Vec<PathBuf> = fs::read_dir(blocks_dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .map(|name| name.starts_with("blk") && name.ends_with(".dat"))
+                    .unwrap_or(false)
+            })
+            .collect();
+        // blk00000.dat, blk00001.dat, ... sort by name
+        paths.sort();
+
+        let mut xor_key = [0u8; 8];
+        match fs::read(blocks_dir.join("xor.dat")) {
+            Ok(key) if key.len() == 8 => xor_key.copy_from_slice(&key),
+            Ok(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "xor.dat is not 8 bytes",
+                ))
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(BlockFiles {
+            paths: paths.into(),
+            magic,
+            xor_key,
+            current: None,
+            skipped: 0,
+        })
+    }
+
+    /// Records skipped in every file read so far
+    pub fn skipped(&self) -> usize {
+        self.skipped + self.current.as_ref().map_or(0, BlockFileReader::skipped)
+    }
+}
+
+impl Iterator for BlockFiles {
+    type Item = io::Result<Block>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(reader) = self.current.as_mut() {
+                if let Some(item) = reader.next() {
+                    return Some(item);
+                }
+            }
+            if let Some(reader) = self.current.take() {
+                self.skipped += reader.skipped();
+            }
+            let path = self.paths.pop_front()?;
+            match File::open(path) {
+                Ok(file) => {
+                    self.current =
+                        Some(BlockFileReader::new(file, self.magic).with_xor_key(self.xor_key))
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+mod test {
+    use super::{BlockFileReader, BlockFiles, MAINNET_MAGIC};
+    use crate::block::Block;
+    use std::fs;
+
+    const GENESIS: [u8; 285] = hex!("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000");
+
+    fn record(payload: &[u8], size: usize) -> Vec<u8> {
+        let mut buf = MAINNET_MAGIC.to_vec();
+        buf.extend_from_slice(&(size as u32).to_le_bytes());
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    fn blocks_file() -> Vec<u8> {
+        let mut file = vec![0u8; 7];
+        file.extend(record(&GENESIS, GENESIS.len()));
+        // a crash left half a block, the next record starts inside its claimed size
+        file.extend(record(&GENESIS[..100], GENESIS.len()));
+        file.extend(record(&GENESIS, GENESIS.len()));
+        // a size no block can have
+        file.extend(record(&[], 10));
+        file.extend(record(&GENESIS, GENESIS.len()));
+        file.extend(vec![0u8; 64]);
+        // cut at the end of the file
+        file.extend(record(&GENESIS[..200], GENESIS.len()));
+        file
+    }
+
+    #[test]
+    fn test_block_file_reader() {
+        let genesis = Block::parse(&GENESIS).unwrap().1;
+        let file = blocks_file();
+
+        let mut reader = BlockFileReader::new(&file[..], MAINNET_MAGIC);
+        let blocks: Vec<Block> = reader.by_ref().map(Result::unwrap).collect();
+        assert_eq!(blocks, vec![genesis.clone(); 3]);
+        assert_eq!(reader.skipped(), 3);
+
+        // the same file obfuscated like Bitcoin Core 28 writes it
+        let key = hex!("0102030405060708");
+        let masked: Vec<u8> = file
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ key[i % 8])
+            .collect();
+        let reader = BlockFileReader::new(&masked[..], MAINNET_MAGIC).with_xor_key(key);
+        assert_eq!(reader.map(Result::unwrap).count(), 3);
+
+        // a cut short record swallows a bad size record and the start of the next block
+        let mut file = record(&GENESIS[..100], GENESIS.len());
+        file.extend(record(&[], 10));
+        file.extend(record(&GENESIS, GENESIS.len()));
+        let mut reader = BlockFileReader::new(&file[..], MAINNET_MAGIC);
+        let blocks: Vec<Block> = reader.by_ref().map(Result::unwrap).collect();
+        assert_eq!(blocks, vec![genesis]);
+        assert_eq!(reader.skipped(), 2);
+    }
+
+    #[test]
+    fn test_block_files() {
+        let dir = std::env::temp_dir().join(format!("blockfile-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let key = hex!("a1b2c3d4e5f60718");
+        let masked: Vec<u8> = blocks_file()
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ key[i % 8])
+            .collect();
+        fs::write(dir.join("xor.dat"), &key).unwrap();
+        fs::write(dir.join("blk00001.dat"), &masked).unwrap();
+        fs::write(dir.join("blk00000.dat"), &masked[..masked.len() / 2]).unwrap();
+        fs::write(dir.join("rev00000.dat"), &masked).unwrap();
+
+        let mut files = BlockFiles::open(&dir, MAINNET_MAGIC).unwrap();
+        assert_eq!(files.by_ref().map(Result::unwrap).count(), 1 + 3);
+        assert_eq!(files.skipped(), 2 + 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+pub enum CancelError {
+    #[fail(display = "operation cancelled")]
+    Cancelled,
+    #[fail(display = "operation timed out")]
+    TimedOut,
+}
+impl Copy for CancelError {}
+
+/// Stops a long running operation from another thread, e.g. a GUI's cancel button, or
+/// once a deadline passes. Clones share the flag, cancelling one cancels all of them.
+/// Operations check it between steps, a step already waiting on the network only notices
+/// when it returns.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// Time out at `deadline`
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Time out `timeout` from now
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.deadline(Instant::now() + timeout)
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Time left until the deadline, None without one
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Err once cancelled or past the deadline, cancelling wins when both are
+    pub fn check(&self) -> Result<(), CancelError> {
+        if self.is_cancelled() {
+            Err(CancelError::Cancelled)
+        } else if self.remaining() == Some(Duration::from_secs(0)) {
+            Err(CancelError::TimedOut)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+mod test {
+    use super::{CancelError, CancelToken};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_cancel_token() {
+        let token = CancelToken::new();
+        assert_eq!(token.check(), Ok(()));
+        assert_eq!(token.remaining(), None);
+        let shared = token.clone();
+        shared.cancel();
+        assert_eq!(token.check(), Err(CancelError::Cancelled));
+
+        let token = CancelToken::new().timeout(Duration::from_secs(3600));
+        assert_eq!(token.check(), Ok(()));
+        assert!(token.remaining().unwrap() > Duration::from_secs(3500));
+        let token = CancelToken::new().deadline(Instant::now());
+        assert_eq!(token.check(), Err(CancelError::TimedOut));
+        token.cancel();
+        assert_eq!(token.check(), Err(CancelError::Cancelled));
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::block::{Block, BlockHash};
+use crate::transaction::{PrevoutResolver, ScriptPubKeyType, Transaction, TxBackend};
+
+/// Figures of one block
+#[derive(Debug, PartialEq, Clone)]
+pub struct BlockStats {
+    pub hash: BlockHash,
+    /// Transactions with the coinbase
+    pub tx_count: usize,
+    /// Fees the transactions besides the coinbase pay, None when an output one of them
+    /// spends could not be looked up
+    pub total_fees: Option<u64>,
+    /// Transactions besides the coinbase with a witness
+    pub segwit_txs: usize,
+    /// Outputs of each type, the coinbase's included
+    pub output_types: HashMap<ScriptPubKeyType, usize>,
+}
+
+impl BlockStats {
+    /// Share of the transactions besides the coinbase that carry a witness, in percent.
+    /// None for a block with only the coinbase.
+    pub fn segwit_percent(&self) -> Option<f64> {
+        percent(self.segwit_txs, self.tx_count.saturating_sub(1))
+    }
+}
+
+fn percent(part: usize, whole: usize) -> Option<f64> {
+    if whole == 0 {
+        return None;
+    }
+    Some(part as f64 * 100.0 / whole as f64)
+}
+
+fn output_types(txs: &[Transaction]) -> HashMap<ScriptPubKeyType, usize> {
+    let mut types = HashMap::new();
+    for output in txs.iter().flat_map(|tx| tx.outputs.iter()) {
+        *types
+            .entry(output.script_pub_key.script_type())
+            .or_insert(0) += 1;
+    }
+    types
+}
+
+/// Stats of consecutive blocks, one report per block in order. Spent outputs are looked
+/// up to get the fees, the ones created by earlier blocks of the range without asking the
+/// backend, so the outputs of every block stay in memory.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ChainStats {
+    pub blocks: Vec<BlockStats>,
+}
+
+impl ChainStats {
+    /// Go through `blocks`, looking up outputs from before them in `backend`. A block
+    /// whose spent outputs could not all be looked up, for any reason, gets no fees.
+    pub fn collect<'a, I, B>(blocks: I, backend: &mut B, testnet: bool) -> Self
+    where
+        I: IntoIterator<Item = &'a Block>,
+        B: TxBackend,
+    {
+        let mut resolver = PrevoutResolver::new();
+        let blocks = blocks
+            .into_iter()
+            .map(|block| {
+                let total_fees = resolver
+                    .resolve(block.txs.clone(), backend, testnet)
+                    .ok()
+                    .and_then(|resolved| {
+                        resolved
+                            .iter()
+                            .filter(|tx| !tx.tx.is_coinbase())
+                            .map(|tx| tx.fee())
+                            .sum::<Option<u64>>()
+                    });
+                BlockStats {
+                    hash: block.hash(),
+                    tx_count: block.txs.len(),
+                    total_fees,
+                    segwit_txs: block
+                        .txs
+                        .iter()
+                        .filter(|tx| !tx.is_coinbase() && tx.has_witness())
+                        .count(),
+                    output_types: output_types(&block.txs),
+                }
+            })
+            .collect();
+        ChainStats { blocks }
+    }
+
+    pub fn tx_count(&self) -> usize {
+        self.blocks.iter().map(|block| block.tx_count).sum()
+    }
+
+    /// None when some block's fees are unknown
+    pub fn total_fees(&self) -> Option<u64> {
+        self.blocks.iter().map(|block| block.total_fees).sum()
+    }
+
+    /// Share of all transactions besides the coinbases that carry a witness, in percent
+    pub fn segwit_percent(&self) -> Option<f64> {
+        let segwit_txs = self.blocks.iter().map(|block| block.segwit_txs).sum();
+        percent(segwit_txs, self.tx_count() - self.blocks.len())
+    }
+
+    /// Outputs of each type over all blocks
+    pub fn output_types(&self) -> HashMap<ScriptPubKeyType, usize> {
+        let mut types = HashMap::new();
+        for (script_type, count) in self.blocks.iter().flat_map(|block| &block.output_types) {
+            *types.entry(*script_type).or_insert(0) += count;
+        }
+        types
+    }
+}
+
+impl Display for ChainStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "blocks {}", self.blocks.len())?;
+        writeln!(f, "txs {}", self.tx_count())?;
+        match self.total_fees() {
+            Some(fees) => writeln!(f, "fees {} sat", fees)?,
+            None => writeln!(f, "fees unknown")?,
+        }
+        if let Some(segwit_percent) = self.segwit_percent() {
+            writeln!(f, "segwit {:.1}%", segwit_percent)?;
+        }
+        // most common first, unknown witness versions each on their own line
+        let mut types: Vec<(String, usize)> = self
+            .output_types()
+            .into_iter()
+            .map(|(script_type, count)| match script_type {
+                ScriptPubKeyType::WitnessUnknown(version) => {
+                    (format!("{} v{}", script_type, version), count)
+                }
+                _ => (script_type.to_string(), count),
+            })
+            .collect();
+        types.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        for (name, count) in types {
+            writeln!(f, "{} {}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+mod test {
+    use super::ChainStats;
+    use crate::transaction::{ScriptPubKey, ScriptPubKeyType, Transaction, TxBackend, TxHash};
+    use failure::Error;
+
+    /// Knows no transactions at all
+    struct Offline;
+
+    impl TxBackend for Offline {
+        fn fetch_many(
+            &mut self,
+            _tx_ids: &[TxHash],
+            _testnet: bool,
+        ) -> Result<Vec<Transaction>, Error> {
+            Err(failure::err_msg("offline"))
+        }
+    }
+
+    #[test]
+    fn test_chain_stats() {
+        use crate::testkit::TxGenerator;
+
+        let mut generator = TxGenerator::new(7);
+        let mut blocks = generator.blocks(30);
+        let stats = ChainStats::collect(&blocks, &mut Offline, false);
+        assert_eq!(stats.blocks.len(), 30);
+        assert_eq!(stats.blocks[3].hash, blocks[3].hash());
+        let tx_count: usize = blocks.iter().map(|block| block.txs.len()).sum();
+        assert_eq!(stats.tx_count(), tx_count);
+        // every generated spend pays 1000 sat, out of outputs of earlier blocks
+        assert_eq!(stats.total_fees(), Some(1000 * (tx_count as u64 - 30)));
+        let segwit_percent = stats.segwit_percent().unwrap();
+        assert!(segwit_percent > 0.0 && segwit_percent < 100.0);
+        // the first block spends nothing, it only has the coinbase
+        assert_eq!(stats.blocks[0].segwit_percent(), None);
+
+        let types = stats.output_types();
+        let outputs: usize = blocks
+            .iter()
+            .flat_map(|block| block.txs.iter())
+            .map(|tx| tx.outputs.len())
+            .sum();
+        assert_eq!(types.values().sum::<usize>(), outputs);
+        assert!(types[&ScriptPubKeyType::WitnessV1Taproot] > 0);
+        assert!(!types.contains_key(&ScriptPubKeyType::NonStandard));
+
+        // without the earlier blocks the outputs they created are unknown
+        let stats = ChainStats::collect(&blocks[20..], &mut Offline, false);
+        assert_eq!(stats.blocks[1].total_fees, None);
+        assert_eq!(stats.total_fees(), None);
+
+        blocks[29].txs[0].outputs[0].script_pub_key = ScriptPubKey {
+            content: [&[0x53, 0x02][..], &[1, 2]].concat(),
+        };
+        let stats = ChainStats::collect(&blocks[29..], &mut Offline, false);
+        assert_eq!(
+            stats.output_types()[&ScriptPubKeyType::WitnessUnknown(3)],
+            1
+        );
+        assert!(stats.to_string().contains("\nwitness_unknown v3 1\n"));
+    }
+}
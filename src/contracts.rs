@@ -0,0 +1,481 @@
+mod lightning;
+
+use sha2::{Digest, Sha256};
+
+use crate::script::{OpCode, Script, ScriptNum};
+use crate::transaction::{ScriptPubKey, Transaction, TxInputSequence, TxLocktime, TxVersion};
+use crate::wallet::S256Point;
+pub use lightning::{derive_pubkey, derive_revocation_pubkey};
+
+/// Sequence of an input that keeps the locktime enforced
+const SEQUENCE_ENABLE_LOCKTIME: u32 = 0xffff_fffe;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum ContractError {
+    #[fail(display = "contract has no spend path {}", _0)]
+    UnknownPath(usize),
+    #[fail(display = "input index {} out of range", _0)]
+    InputIndexOutOfRange(usize),
+    #[fail(display = "missing signature for key {}", _0)]
+    MissingSignature(String),
+    #[fail(display = "missing hashlock preimage")]
+    MissingPreimage,
+    #[fail(display = "preimage does not hash to the payment hash")]
+    WrongPreimage,
+}
+
+/// One element the spender puts on the witness stack, before the witness script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitnessItem {
+    /// Signature of the key with this compressed sec
+    Signature([u8; 33]),
+    /// Preimage of the hashlock
+    Preimage,
+    /// OP_IF branch selector, true is 0x01 and false the empty push
+    Branch(bool),
+    /// Empty element, the extra pop of OP_CHECKMULTISIG
+    Dummy,
+    /// Public key revealed in the witness, compressed sec
+    PublicKey([u8; 33]),
+}
+
+/// A way to spend a contract, its witness in stack order and the timelocks it needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpendPath {
+    pub name: String,
+    pub witness_template: Vec<WitnessItem>,
+    /// Absolute locktime checked by OP_CHECKLOCKTIMEVERIFY
+    pub locktime: Option<u32>,
+    /// Relative delay checked by OP_CHECKSEQUENCEVERIFY, set as the input sequence
+    pub sequence: Option<u32>,
+}
+
+/// A P2WSH contract, the witness script and every way to redeem it
+#[derive(Debug, Clone)]
+pub struct Contract {
+    pub script: Script,
+    pub paths: Vec<SpendPath>,
+    payment_hash: Option<[u8; 32]>,
+}
+
+/// Push `num` the minimal way, OP_0 .. OP_16 for small numbers
+fn push_num(script: &mut Script, num: i64) {
+    match num {
+        0 => script.push_opcode(OpCode::Op0),
+        1..=16 => script.push_opcode(OpCode::from(0x50 + num as u8)),
+        _ => script.push_data_ele(&ScriptNum::from(num).encode()),
+    }
+}
+
+impl Contract {
+    /// `<locktime> OP_CHECKLOCKTIMEVERIFY OP_DROP <key> OP_CHECKSIG`, spendable by `key`
+    /// once the chain passes `locktime`
+    pub fn timelock(locktime: u32, key: &S256Point) -> Self {
+        let mut script = Script::new();
+        push_num(&mut script, i64::from(locktime));
+        script.push_opcode(OpCode::OpCheckLockTimeVerify);
+        script.push_opcode(OpCode::OpDrop);
+        script.push_data_ele(&key.compressed_sec());
+        script.push_opcode(OpCode::OpCheckSig);
+
+        Contract {
+            script,
+            paths: vec![SpendPath {
+                name: "timelock".to_string(),
+                witness_template: vec![WitnessItem::Signature(key.compressed_sec())],
+                locktime: Some(locktime),
+                sequence: None,
+            }],
+            payment_hash: None,
+        }
+    }
+
+    /// Lightning style HTLC, `receiver` claims with the sha256 preimage of `payment_hash`,
+    /// `sender` takes the coins back after `timeout`
+    ///
+    /// `OP_IF OP_SHA256 <payment_hash> OP_EQUALVERIFY <receiver>
+    ///  OP_ELSE <timeout> OP_CHECKLOCKTIMEVERIFY OP_DROP <sender> OP_ENDIF OP_CHECKSIG`
+    pub fn htlc(
+        payment_hash: [u8; 32],
+        receiver: &S256Point,
+        sender: &S256Point,
+        timeout: u32,
+    ) -> Self {
+        let mut script = Script::new();
+        script.push_opcode(OpCode::OpIf);
+        script.push_opcode(OpCode::OpSha256);
+        script.push_data_ele(&payment_hash);
+        script.push_opcode(OpCode::OpEqualVerify);
+        script.push_data_ele(&receiver.compressed_sec());
+        script.push_opcode(OpCode::OpElse);
+        push_num(&mut script, i64::from(timeout));
+        script.push_opcode(OpCode::OpCheckLockTimeVerify);
+        script.push_opcode(OpCode::OpDrop);
+        script.push_data_ele(&sender.compressed_sec());
+        script.push_opcode(OpCode::OpEndIf);
+        script.push_opcode(OpCode::OpCheckSig);
+
+        Contract {
+            script,
+            paths: vec![
+                SpendPath {
+                    name: "claim".to_string(),
+                    witness_template: vec![
+                        WitnessItem::Signature(receiver.compressed_sec()),
+                        WitnessItem::Preimage,
+                        WitnessItem::Branch(true),
+                    ],
+                    locktime: None,
+                    sequence: None,
+                },
+                SpendPath {
+                    name: "refund".to_string(),
+                    witness_template: vec![
+                        WitnessItem::Signature(sender.compressed_sec()),
+                        WitnessItem::Branch(false),
+                    ],
+                    locktime: Some(timeout),
+                    sequence: None,
+                },
+            ],
+            payment_hash: Some(payment_hash),
+        }
+    }
+
+    /// `OP_2 <a> <b> <c> OP_3 OP_CHECKMULTISIG`, one path per pair of keys
+    pub fn escrow(a: &S256Point, b: &S256Point, c: &S256Point) -> Self {
+        let keys = [a.compressed_sec(), b.compressed_sec(), c.compressed_sec()];
+        let mut script = Script::new();
+        push_num(&mut script, 2);
+        for key in &keys {
+            script.push_data_ele(key);
+        }
+        push_num(&mut script, 3);
+        script.push_opcode(OpCode::OpCheckMultisig);
+
+        let names = ["a", "b", "c"];
+        let mut paths = Vec::new();
+        for (i, j) in &[(0, 1), (0, 2), (1, 2)] {
+            paths.push(SpendPath {
+                name: format!("{}+{}", names[*i], names[*j]),
+                // signatures in the order of their keys
+                witness_template: vec![
+                    WitnessItem::Dummy,
+                    WitnessItem::Signature(keys[*i]),
+                    WitnessItem::Signature(keys[*j]),
+                ],
+                locktime: None,
+                sequence: None,
+            });
+        }
+
+        Contract {
+            script,
+            paths,
+            payment_hash: None,
+        }
+    }
+
+    pub fn witness_script(&self) -> Vec<u8> {
+        self.script
+            .raw_serialize()
+            .expect("contract scripts only push short elements")
+    }
+
+    /// `OP_0 <sha256(witness script)>`
+    pub fn script_pubkey(&self) -> ScriptPubKey {
+        let mut content = vec![0x00, 0x20];
+        content.extend_from_slice(&Sha256::digest(&self.witness_script()));
+        ScriptPubKey { content }
+    }
+
+    /// Witness stack for `path`, signatures are DER with the sighash byte and looked up
+    /// by the compressed sec of their key
+    pub fn witness(
+        &self,
+        path: usize,
+        signatures: &[([u8; 33], Vec<u8>)],
+        preimage: Option<&[u8]>,
+    ) -> Result<Vec<Vec<u8>>, ContractError> {
+        let path = self
+            .paths
+            .get(path)
+            .ok_or(ContractError::UnknownPath(path))?;
+        let mut witness = Vec::with_capacity(path.witness_template.len() + 1);
+        for item in &path.witness_template {
+            let element = match item {
+                WitnessItem::Signature(key) => signatures
+                    .iter()
+                    .find(|(sec, _)| sec == key)
+                    .map(|(_, sig)| sig.clone())
+                    .ok_or_else(|| ContractError::MissingSignature(hex::encode(&key[..])))?,
+                WitnessItem::Preimage => {
+                    let preimage = preimage.ok_or(ContractError::MissingPreimage)?;
+                    if self.payment_hash.as_ref().map(|hash| &hash[..])
+                        != Some(&Sha256::digest(preimage)[..])
+                    {
+                        return Err(ContractError::WrongPreimage);
+                    }
+                    preimage.to_vec()
+                }
+                WitnessItem::Branch(true) => vec![0x01],
+                WitnessItem::Branch(false) | WitnessItem::Dummy => vec![],
+                WitnessItem::PublicKey(key) => key.to_vec(),
+            };
+            witness.push(element);
+        }
+        witness.push(self.witness_script());
+        Ok(witness)
+    }
+
+    /// Fill the witness of `tx.inputs[input_index]` for `path`. An absolute timelock also
+    /// raises the transaction locktime and enables it through the input sequence, a
+    /// relative one becomes the input sequence of a version 2 transaction.
+    pub fn redeem(
+        &self,
+        tx: &mut Transaction,
+        input_index: usize,
+        path: usize,
+        signatures: &[([u8; 33], Vec<u8>)],
+        preimage: Option<&[u8]>,
+    ) -> Result<(), ContractError> {
+        let witness = self.witness(path, signatures, preimage)?;
+        let (locktime, sequence) = (self.paths[path].locktime, self.paths[path].sequence);
+        let input = tx
+            .inputs
+            .get_mut(input_index)
+            .ok_or(ContractError::InputIndexOutOfRange(input_index))?;
+        input.witness = witness;
+        if locktime.is_some() {
+            input.sequence = TxInputSequence::new(SEQUENCE_ENABLE_LOCKTIME);
+        }
+        if let Some(sequence) = sequence {
+            input.sequence = TxInputSequence::new(sequence);
+        }
+
+        if let Some(locktime) = locktime {
+            if u32::from(tx.locktime) < locktime {
+                tx.locktime = TxLocktime::new(locktime);
+            }
+        }
+        if sequence.is_some() && u32::from(tx.version) < 2 {
+            tx.version = TxVersion::new(2);
+        }
+        Ok(())
+    }
+}
+
+mod test {
+    use super::{Contract, ContractError, WitnessItem};
+    use crate::script::ScriptError;
+    use crate::transaction::{
+        OutPoint, ScriptPubKey, Transaction, TxFetcher, TxHash, TxInput, TxLocktime, TxOutput,
+        TxVersion, VerifyError, SIGHASH_ALL,
+    };
+    use crate::wallet::{PrivateKey, S256Point, U256};
+    use sha2::{Digest, Sha256};
+
+    /// A version 1 transaction spending a funding output that pays to `contract`, redeemed
+    /// through `path` with signatures of `keys`, and the fetcher that knows the funding
+    pub(super) fn spend(
+        contract: &Contract,
+        path: usize,
+        keys: &[&PrivateKey],
+        preimage: Option<&[u8]>,
+    ) -> (Transaction, TxFetcher) {
+        let prev = Transaction::new(
+            TxVersion::new(2),
+            vec![TxInput::builder(OutPoint::new(TxHash::new(&[3; 32]).unwrap().1, 0)).build()],
+            vec![TxOutput {
+                amount: 50_000.into(),
+                script_pub_key: contract.script_pubkey(),
+            }],
+            TxLocktime::new(0),
+            false,
+        );
+        let fetcher = TxFetcher::new();
+        fetcher.insert(prev.clone());
+
+        let mut tx = Transaction::new(
+            TxVersion::new(1),
+            vec![TxInput::builder(OutPoint::new(prev.id(), 0)).build()],
+            vec![TxOutput {
+                amount: 49_000.into(),
+                script_pub_key: contract.script_pubkey(),
+            }],
+            TxLocktime::new(0),
+            false,
+        );
+        // the locktime, sequence and version the path sets are signed too
+        let unsigned: Vec<_> = keys
+            .iter()
+            .map(|key| (key.point.compressed_sec(), vec![]))
+            .collect();
+        contract
+            .redeem(&mut tx, 0, path, &unsigned, preimage)
+            .unwrap();
+        let script_code = ScriptPubKey {
+            content: contract.witness_script(),
+        };
+        let z = tx.sig_hash_segwit_v0(0, &script_code, 50_000, SIGHASH_ALL);
+        let signatures: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                let mut sig = key.sign(U256::from_little_endian(&z)).der();
+                sig.push(SIGHASH_ALL as u8);
+                (key.point.compressed_sec(), sig)
+            })
+            .collect();
+        contract
+            .redeem(&mut tx, 0, path, &signatures, preimage)
+            .unwrap();
+        (tx, fetcher)
+    }
+
+    fn key(n: u8) -> S256Point {
+        let mut point = S256Point::gen_point();
+        for _ in 1..n {
+            point = point + S256Point::gen_point();
+        }
+        point
+    }
+
+    fn tx() -> Transaction {
+        let data = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac00000000");
+        Transaction::parse(&data[..]).unwrap().1
+    }
+
+    #[test]
+    fn test_timelock() {
+        let contract = Contract::timelock(600_000, &key(1));
+        assert_eq!(
+            contract.script.asm(),
+            format!(
+                "600000 OP_CHECKLOCKTIMEVERIFY OP_DROP {} OP_CHECKSIG",
+                hex::encode(&key(1).compressed_sec()[..])
+            )
+        );
+        assert_eq!(contract.script_pubkey().content.len(), 34);
+
+        let mut tx = tx();
+        let sig = vec![0x30, 0x01];
+        contract
+            .redeem(
+                &mut tx,
+                0,
+                0,
+                &[(key(1).compressed_sec(), sig.clone())],
+                None,
+            )
+            .unwrap();
+        assert_eq!(tx.inputs[0].witness, vec![sig, contract.witness_script()]);
+        assert_eq!(u32::from(tx.locktime), 600_000);
+        assert_eq!(tx.inputs[0].sequence.sequence(), 0xffff_fffe);
+    }
+
+    #[test]
+    fn test_htlc() {
+        let preimage = [7u8; 32];
+        let mut payment_hash = [0u8; 32];
+        payment_hash.copy_from_slice(&Sha256::digest(&preimage));
+        let contract = Contract::htlc(payment_hash, &key(1), &key(2), 100);
+
+        let sig = vec![0x30, 0x02];
+        let claim = contract
+            .witness(
+                0,
+                &[(key(1).compressed_sec(), sig.clone())],
+                Some(&preimage),
+            )
+            .unwrap();
+        assert_eq!(
+            claim,
+            vec![
+                sig.clone(),
+                preimage.to_vec(),
+                vec![0x01],
+                contract.witness_script()
+            ]
+        );
+        assert_eq!(
+            contract.witness(
+                0,
+                &[(key(1).compressed_sec(), sig.clone())],
+                Some(&[0u8; 32])
+            ),
+            Err(ContractError::WrongPreimage)
+        );
+        assert_eq!(
+            contract.witness(1, &[(key(1).compressed_sec(), sig.clone())], None),
+            Err(ContractError::MissingSignature(hex::encode(
+                &key(2).compressed_sec()[..]
+            )))
+        );
+        assert_eq!(contract.paths[1].locktime, Some(100));
+    }
+
+    #[test]
+    fn test_escrow() {
+        let contract = Contract::escrow(&key(1), &key(2), &key(3));
+        assert_eq!(contract.paths.len(), 3);
+        assert_eq!(contract.paths[1].name, "a+c");
+        assert_eq!(contract.paths[1].witness_template[0], WitnessItem::Dummy);
+        assert!(contract.script.asm().starts_with("2 "));
+        assert!(contract.script.asm().ends_with(" 3 OP_CHECKMULTISIG"));
+
+        let witness = contract
+            .witness(
+                2,
+                &[
+                    (key(3).compressed_sec(), vec![3]),
+                    (key(2).compressed_sec(), vec![2]),
+                ],
+                None,
+            )
+            .unwrap();
+        assert_eq!(witness[..3], [vec![], vec![2], vec![3]]);
+    }
+
+    #[test]
+    fn test_spend_paths() {
+        let (alice, bob, carol) = (
+            PrivateKey::new(U256::from(1u32)),
+            PrivateKey::new(U256::from(2u32)),
+            PrivateKey::new(U256::from(3u32)),
+        );
+
+        let timelock = Contract::timelock(600_000, &alice.point);
+        let (tx, fetcher) = spend(&timelock, 0, &[&alice], None);
+        assert_eq!(tx.verify_input(0, &fetcher), Ok(true));
+        let mut early = tx.clone();
+        early.locktime = TxLocktime::new(599_999);
+        assert_eq!(
+            early.verify_input(0, &fetcher),
+            Err(VerifyError::Script(ScriptError::UnsatisfiedLocktime))
+        );
+
+        let preimage = [7u8; 32];
+        let mut payment_hash = [0u8; 32];
+        payment_hash.copy_from_slice(&Sha256::digest(&preimage));
+        let htlc = Contract::htlc(payment_hash, &alice.point, &bob.point, 100);
+        let (tx, fetcher) = spend(&htlc, 0, &[&alice], Some(&preimage));
+        assert_eq!(tx.verify_input(0, &fetcher), Ok(true));
+        let (tx, fetcher) = spend(&htlc, 1, &[&bob], None);
+        assert_eq!(tx.verify_input(0, &fetcher), Ok(true));
+        assert_eq!(u32::from(tx.locktime), 100);
+        // a signature of the receiver does not take the refund path
+        let mut stolen = tx.clone();
+        stolen.inputs[0].witness[0] =
+            spend(&htlc, 0, &[&alice], Some(&preimage)).0.inputs[0].witness[0].clone();
+        assert_eq!(stolen.verify_input(0, &fetcher), Ok(false));
+
+        let escrow = Contract::escrow(&alice.point, &bob.point, &carol.point);
+        for (path, keys) in [[&alice, &bob], [&alice, &carol], [&bob, &carol]]
+            .iter()
+            .enumerate()
+        {
+            let (tx, fetcher) = spend(&escrow, path, keys, None);
+            assert_eq!(tx.verify_input(0, &fetcher), Ok(true));
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use ripemd160::Ripemd160;
+use sha2::{Digest, Sha256};
+
+use super::{push_num, Contract, SpendPath, WitnessItem};
+use crate::script::{OpCode, Script};
+use crate::wallet::{S256Point, U256};
+
+fn sha256_scalar(first: &S256Point, second: &S256Point) -> U256 {
+    let mut buf = Vec::with_capacity(66);
+    buf.extend_from_slice(&first.compressed_sec());
+    buf.extend_from_slice(&second.compressed_sec());
+    U256::from_big_endian(&Sha256::digest(&buf))
+}
+
+/// BOLT3 `basepoint + SHA256(per_commitment_point || basepoint) * G`, gives the
+/// localpubkey, htlcpubkeys and delayedpubkey of a commitment
+pub fn derive_pubkey(basepoint: &S256Point, per_commitment_point: &S256Point) -> S256Point {
+    *basepoint + S256Point::gen_point() * sha256_scalar(per_commitment_point, basepoint)
+}
+
+/// BOLT3 revocationpubkey, only computable as a private key once the per commitment
+/// secret is revealed
+pub fn derive_revocation_pubkey(
+    revocation_basepoint: &S256Point,
+    per_commitment_point: &S256Point,
+) -> S256Point {
+    *revocation_basepoint * sha256_scalar(revocation_basepoint, per_commitment_point)
+        + *per_commitment_point * sha256_scalar(per_commitment_point, revocation_basepoint)
+}
+
+/// `OP_DUP OP_HASH160 <RIPEMD160(SHA256(revocationpubkey))> OP_EQUAL OP_IF OP_CHECKSIG
+/// OP_ELSE <remote_htlcpubkey> OP_SWAP OP_SIZE 32 OP_EQUAL`, shared by both HTLC scripts
+fn htlc_prefix(revocation: &S256Point, remote_htlc: &S256Point) -> Script {
+    let mut script = Script::new();
+    script.push_opcode(OpCode::OpDup);
+    script.push_opcode(OpCode::OpHash160);
+    script.push_data_ele(&revocation.hash160(true).to_vec());
+    script.push_opcode(OpCode::OpEqual);
+    script.push_opcode(OpCode::OpIf);
+    script.push_opcode(OpCode::OpCheckSig);
+    script.push_opcode(OpCode::OpElse);
+    script.push_data_ele(&remote_htlc.compressed_sec());
+    script.push_opcode(OpCode::OpSwap);
+    script.push_opcode(OpCode::OpSize);
+    push_num(&mut script, 32);
+    script.push_opcode(OpCode::OpEqual);
+    script
+}
+
+/// `OP_HASH160 <RIPEMD160(payment_hash)> OP_EQUALVERIFY`
+fn push_payment_hash_check(script: &mut Script, payment_hash: &[u8; 32]) {
+    script.push_opcode(OpCode::OpHash160);
+    script.push_data_ele(&Ripemd160::digest(payment_hash));
+    script.push_opcode(OpCode::OpEqualVerify);
+}
+
+/// `2 OP_SWAP <local_htlcpubkey> 2 OP_CHECKMULTISIG`
+fn push_two_of_two(script: &mut Script, local_htlc: &S256Point) {
+    push_num(script, 2);
+    script.push_opcode(OpCode::OpSwap);
+    script.push_data_ele(&local_htlc.compressed_sec());
+    push_num(script, 2);
+    script.push_opcode(OpCode::OpCheckMultisig);
+}
+
+fn revoked_path(revocation: &S256Point) -> SpendPath {
+    SpendPath {
+        name: "revoked".to_string(),
+        witness_template: vec![
+            WitnessItem::Signature(revocation.compressed_sec()),
+            WitnessItem::PublicKey(revocation.compressed_sec()),
+        ],
+        locktime: None,
+        sequence: None,
+    }
+}
+
+impl Contract {
+    /// BOLT3 to_local output, the remote side takes it with the revocation key of a revoked
+    /// commitment, the local side after `to_self_delay` blocks
+    ///
+    /// `OP_IF <revocationpubkey> OP_ELSE <to_self_delay> OP_CHECKSEQUENCEVERIFY OP_DROP
+    ///  <local_delayedpubkey> OP_ENDIF OP_CHECKSIG`
+    pub fn to_local(revocation: &S256Point, local_delayed: &S256Point, to_self_delay: u16) -> Self {
+        let mut script = Script::new();
+        script.push_opcode(OpCode::OpIf);
+        script.push_data_ele(&revocation.compressed_sec());
+        script.push_opcode(OpCode::OpElse);
+        push_num(&mut script, i64::from(to_self_delay));
+        script.push_opcode(OpCode::OpCheckSequenceVerify);
+        script.push_opcode(OpCode::OpDrop);
+        script.push_data_ele(&local_delayed.compressed_sec());
+        script.push_opcode(OpCode::OpEndIf);
+        script.push_opcode(OpCode::OpCheckSig);
+
+        Contract {
+            script,
+            paths: vec![
+                SpendPath {
+                    name: "revoked".to_string(),
+                    witness_template: vec![
+                        WitnessItem::Signature(revocation.compressed_sec()),
+                        WitnessItem::Branch(true),
+                    ],
+                    locktime: None,
+                    sequence: None,
+                },
+                SpendPath {
+                    name: "delayed".to_string(),
+                    witness_template: vec![
+                        WitnessItem::Signature(local_delayed.compressed_sec()),
+                        WitnessItem::Branch(false),
+                    ],
+                    locktime: None,
+                    sequence: Some(u32::from(to_self_delay)),
+                },
+            ],
+            payment_hash: None,
+        }
+    }
+
+    /// BOLT3 to_remote output of anchor channels, `<remotepubkey> OP_CHECKSIGVERIFY
+    /// 1 OP_CHECKSEQUENCEVERIFY`
+    pub fn to_remote(remote: &S256Point) -> Self {
+        let mut script = Script::new();
+        script.push_data_ele(&remote.compressed_sec());
+        script.push_opcode(OpCode::OpCheckSigVerify);
+        push_num(&mut script, 1);
+        script.push_opcode(OpCode::OpCheckSequenceVerify);
+
+        Contract {
+            script,
+            paths: vec![SpendPath {
+                name: "remote".to_string(),
+                witness_template: vec![WitnessItem::Signature(remote.compressed_sec())],
+                locktime: None,
+                sequence: Some(1),
+            }],
+            payment_hash: None,
+        }
+    }
+
+    /// BOLT3 offered HTLC output, the remote node claims it with the payment preimage,
+    /// the local node times it out through the 2-of-2 HTLC-timeout transaction
+    pub fn offered_htlc(
+        revocation: &S256Point,
+        local_htlc: &S256Point,
+        remote_htlc: &S256Point,
+        payment_hash: [u8; 32],
+    ) -> Self {
+        let mut script = htlc_prefix(revocation, remote_htlc);
+        script.push_opcode(OpCode::OpNotIf);
+        script.push_opcode(OpCode::OpDrop);
+        push_two_of_two(&mut script, local_htlc);
+        script.push_opcode(OpCode::OpElse);
+        push_payment_hash_check(&mut script, &payment_hash);
+        script.push_opcode(OpCode::OpCheckSig);
+        script.push_opcode(OpCode::OpEndIf);
+        script.push_opcode(OpCode::OpEndIf);
+
+        Contract {
+            script,
+            paths: vec![
+                revoked_path(revocation),
+                SpendPath {
+                    name: "timeout".to_string(),
+                    witness_template: vec![
+                        WitnessItem::Dummy,
+                        WitnessItem::Signature(remote_htlc.compressed_sec()),
+                        WitnessItem::Signature(local_htlc.compressed_sec()),
+                        WitnessItem::Branch(false),
+                    ],
+                    locktime: None,
+                    sequence: None,
+                },
+                SpendPath {
+                    name: "success".to_string(),
+                    witness_template: vec![
+                        WitnessItem::Signature(remote_htlc.compressed_sec()),
+                        WitnessItem::Preimage,
+                    ],
+                    locktime: None,
+                    sequence: None,
+                },
+            ],
+            payment_hash: Some(payment_hash),
+        }
+    }
+
+    /// BOLT3 received HTLC output, the local node claims it with the payment preimage
+    /// through the 2-of-2 HTLC-success transaction, the remote node after `cltv_expiry`
+    pub fn received_htlc(
+        revocation: &S256Point,
+        local_htlc: &S256Point,
+        remote_htlc: &S256Point,
+        payment_hash: [u8; 32],
+        cltv_expiry: u32,
+    ) -> Self {
+        let mut script = htlc_prefix(revocation, remote_htlc);
+        script.push_opcode(OpCode::OpIf);
+        push_payment_hash_check(&mut script, &payment_hash);
+        push_two_of_two(&mut script, local_htlc);
+        script.push_opcode(OpCode::OpElse);
+        script.push_opcode(OpCode::OpDrop);
+        push_num(&mut script, i64::from(cltv_expiry));
+        script.push_opcode(OpCode::OpCheckLockTimeVerify);
+        script.push_opcode(OpCode::OpDrop);
+        script.push_opcode(OpCode::OpCheckSig);
+        script.push_opcode(OpCode::OpEndIf);
+        script.push_opcode(OpCode::OpEndIf);
+
+        Contract {
+            script,
+            paths: vec![
+                revoked_path(revocation),
+                SpendPath {
+                    name: "success".to_string(),
+                    witness_template: vec![
+                        WitnessItem::Dummy,
+                        WitnessItem::Signature(remote_htlc.compressed_sec()),
+                        WitnessItem::Signature(local_htlc.compressed_sec()),
+                        WitnessItem::Preimage,
+                    ],
+                    locktime: None,
+                    sequence: None,
+                },
+                SpendPath {
+                    name: "timeout".to_string(),
+                    witness_template: vec![
+                        WitnessItem::Signature(remote_htlc.compressed_sec()),
+                        WitnessItem::Branch(false),
+                    ],
+                    locktime: Some(cltv_expiry),
+                    sequence: None,
+                },
+            ],
+            payment_hash: Some(payment_hash),
+        }
+    }
+}
+
+mod test {
+    use super::{derive_pubkey, derive_revocation_pubkey};
+    use crate::contracts::test::spend;
+    use crate::contracts::Contract;
+    use crate::script::ScriptError;
+    use crate::transaction::{TxInputSequence, VerifyError};
+    use crate::wallet::{PrivateKey, S256Point, U256};
+    use sha2::{Digest, Sha256};
+
+    fn point(sec: &[u8]) -> S256Point {
+        S256Point::parse_sec(sec).unwrap()
+    }
+
+    #[test]
+    fn test_key_derivation() {
+        // BOLT3 appendix E
+        let base_point = point(&hex!(
+            "036d6caac248af96f6afa7f904f550253a0f3ef3f5aa2fe6838a95b216691468e2"
+        ));
+        let per_commitment_point = point(&hex!(
+            "025f7117a78150fe2ef97db7cfc83bd57b2e2c0d0dd25eaf467a4a1c2a45ce1486"
+        ));
+        assert_eq!(
+            derive_pubkey(&base_point, &per_commitment_point).compressed_sec()[..],
+            hex!("0235f2dbfaa89b57ec7b055afe29849ef7ddfeb1cefdb9ebdc43f5494984db29e5")[..]
+        );
+        assert_eq!(
+            derive_revocation_pubkey(&base_point, &per_commitment_point).compressed_sec()[..],
+            hex!("02916e326636d19c33f13e8c0c3a03dd157f332f3e99c317c141dd865eb01f8ff0")[..]
+        );
+    }
+
+    #[test]
+    fn test_to_local() {
+        // BOLT3 appendix C
+        let revocation = point(&hex!(
+            "0212a140cd0c6539d07cd08dfe09984dec3251ea808b892efeac3ede9402bf2b19"
+        ));
+        let local_delayed = point(&hex!(
+            "03fd5960528dc152014952efdb702a88f71e3c1653b2314431701ec77e57fde83c"
+        ));
+        let contract = Contract::to_local(&revocation, &local_delayed, 144);
+        assert_eq!(
+            contract.witness_script(),
+            hex!("63210212a140cd0c6539d07cd08dfe09984dec3251ea808b892efeac3ede9402bf2b1967029000b2752103fd5960528dc152014952efdb702a88f71e3c1653b2314431701ec77e57fde83c68ac").to_vec()
+        );
+        assert_eq!(contract.paths[1].sequence, Some(144));
+    }
+
+    #[test]
+    fn test_to_remote() {
+        let remote = PrivateKey::new(U256::from(3u32));
+        let contract = Contract::to_remote(&remote.point);
+        assert_eq!(
+            contract.script.asm(),
+            format!(
+                "{} OP_CHECKSIGVERIFY 1 OP_CHECKSEQUENCEVERIFY",
+                hex::encode(&remote.point.compressed_sec()[..])
+            )
+        );
+
+        let (tx, fetcher) = spend(&contract, 0, &[&remote], None);
+        assert_eq!(tx.inputs[0].sequence.sequence(), 1);
+        assert_eq!(tx.verify_input(0, &fetcher), Ok(true));
+    }
+
+    #[test]
+    fn test_spend_to_local() {
+        let revocation = PrivateKey::new(U256::from(1u32));
+        let local_delayed = PrivateKey::new(U256::from(4u32));
+        let contract = Contract::to_local(&revocation.point, &local_delayed.point, 144);
+
+        let (tx, fetcher) = spend(&contract, 0, &[&revocation], None);
+        assert_eq!(tx.verify_input(0, &fetcher), Ok(true));
+        let (tx, fetcher) = spend(&contract, 1, &[&local_delayed], None);
+        assert_eq!(tx.verify_input(0, &fetcher), Ok(true));
+
+        // the delay is checked before the signature
+        let mut early = tx.clone();
+        early.inputs[0].sequence = TxInputSequence::new(143);
+        assert_eq!(
+            early.verify_input(0, &fetcher),
+            Err(VerifyError::Script(ScriptError::UnsatisfiedLocktime))
+        );
+    }
+
+    #[test]
+    fn test_spend_htlcs() {
+        let revocation = PrivateKey::new(U256::from(1u32));
+        let local_htlc = PrivateKey::new(U256::from(2u32));
+        let remote_htlc = PrivateKey::new(U256::from(3u32));
+        let preimage = [9u8; 32];
+        let mut payment_hash = [0u8; 32];
+        payment_hash.copy_from_slice(&Sha256::digest(&preimage));
+
+        let offered = Contract::offered_htlc(
+            &revocation.point,
+            &local_htlc.point,
+            &remote_htlc.point,
+            payment_hash,
+        );
+        let revoked = spend(&offered, 0, &[&revocation], None);
+        let timeout = spend(&offered, 1, &[&remote_htlc, &local_htlc], None);
+        let success = spend(&offered, 2, &[&remote_htlc], Some(&preimage));
+        for (tx, fetcher) in &[revoked, timeout, success] {
+            assert_eq!(tx.verify_input(0, fetcher), Ok(true));
+        }
+
+        let received = Contract::received_htlc(
+            &revocation.point,
+            &local_htlc.point,
+            &remote_htlc.point,
+            payment_hash,
+            500,
+        );
+        let revoked = spend(&received, 0, &[&revocation], None);
+        let success = spend(&received, 1, &[&remote_htlc, &local_htlc], Some(&preimage));
+        let timeout = spend(&received, 2, &[&remote_htlc], None);
+        assert_eq!(u32::from(timeout.0.locktime), 500);
+        for (tx, fetcher) in &[revoked, success, timeout] {
+            assert_eq!(tx.verify_input(0, fetcher), Ok(true));
+        }
+    }
+
+    #[test]
+    fn test_htlc_scripts() {
+        let revocation = point(&hex!(
+            "0212a140cd0c6539d07cd08dfe09984dec3251ea808b892efeac3ede9402bf2b19"
+        ));
+        let local_htlc = point(&hex!(
+            "030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e7"
+        ));
+        let remote_htlc = point(&hex!(
+            "0394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b"
+        ));
+        let payment_hash = [0x22u8; 32];
+        // BOLT3 appendix C, offered HTLC 2 and received HTLC 0
+        let bolt3_offered = Contract::offered_htlc(
+            &revocation,
+            &local_htlc,
+            &remote_htlc,
+            Sha256::digest(&[0x02u8; 32]).into(),
+        );
+        assert_eq!(
+            bolt3_offered.witness_script(),
+            hex!("76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868").to_vec()
+        );
+        let bolt3_received = Contract::received_htlc(
+            &revocation,
+            &local_htlc,
+            &remote_htlc,
+            Sha256::digest(&[0x00u8; 32]).into(),
+            500,
+        );
+        assert_eq!(
+            bolt3_received.witness_script(),
+            hex!("76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a914b8bcb07f6344b42ab04250c86a6e8b75d3fdbbc688527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f401b175ac6868").to_vec()
+        );
+
+        let offered = Contract::offered_htlc(&revocation, &local_htlc, &remote_htlc, payment_hash);
+        let asm = offered.script.asm();
+        assert!(asm.starts_with("OP_DUP OP_HASH160 "));
+        assert!(asm.ends_with("OP_EQUALVERIFY OP_CHECKSIG OP_ENDIF OP_ENDIF"));
+        assert!(asm.contains("OP_SIZE 32 OP_EQUAL OP_NOTIF OP_DROP 2 OP_SWAP"));
+
+        let received =
+            Contract::received_htlc(&revocation, &local_htlc, &remote_htlc, payment_hash, 500);
+        let asm = received.script.asm();
+        assert!(asm.contains("OP_DROP 500 OP_CHECKLOCKTIMEVERIFY OP_DROP OP_CHECKSIG"));
+        assert_eq!(received.paths[2].locktime, Some(500));
+
+        let witness = received
+            .witness(0, &[(revocation.compressed_sec(), vec![0x30])], None)
+            .unwrap();
+        assert_eq!(witness[1], revocation.compressed_sec().to_vec());
+    }
+}
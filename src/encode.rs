@@ -0,0 +1,202 @@
+use nom::error::ErrorKind;
+use nom::IResult;
+
+use crate::script::ScriptError;
+use crate::transaction::Varint;
+
+/// Consensus encoding, the byte format of the p2p network and the block files
+pub trait Encodable {
+    /// Append the encoding to `buf`, returns the number of bytes written
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize;
+}
+
+/// Consensus decoding, the inverse of `Encodable`
+pub trait Decodable: Sized {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self>;
+}
+
+pub fn serialize<T: Encodable + ?Sized>(value: &T) -> Vec<u8> {
+    let mut buf = Vec::new();
+    value.consensus_encode(&mut buf);
+    buf
+}
+
+/// Decode a value taking the whole of `bytes`, None on malformed or trailing bytes
+pub fn deserialize<T: Decodable>(bytes: &[u8]) -> Option<T> {
+    match T::consensus_decode(bytes) {
+        Ok((rest, value)) if rest.is_empty() => Some(value),
+        _ => None,
+    }
+}
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum HexError {
+    #[fail(display = "invalid hex: {}", _0)]
+    InvalidHex(String),
+    #[fail(display = "hex does not encode a {}", _0)]
+    Malformed(&'static str),
+    #[fail(display = "{} trailing bytes after the {}", _1, _0)]
+    TrailingBytes(&'static str, usize),
+    #[fail(display = "malformed script: {}", _0)]
+    Script(ScriptError),
+}
+
+/// Decode `hex` to bytes, ignoring surrounding whitespace
+pub fn hex_bytes(hex: &str) -> Result<Vec<u8>, HexError> {
+    hex::decode(hex.trim()).map_err(|e| HexError::InvalidHex(e.to_string()))
+}
+
+/// Decode a value taking the whole of the bytes `hex` encodes, `what` names it in errors
+pub fn decode_hex<T: Decodable>(hex: &str, what: &'static str) -> Result<T, HexError> {
+    let bytes = hex_bytes(hex)?;
+    match T::consensus_decode(&bytes) {
+        Ok((rest, value)) if rest.is_empty() => Ok(value),
+        Ok((rest, _)) => Err(HexError::TrailingBytes(what, rest.len())),
+        Err(_) => Err(HexError::Malformed(what)),
+    }
+}
+
+/// nom error for a decoder that fails on its own checks rather than on missing bytes
+pub(crate) fn decode_error<T>(input: &[u8]) -> IResult<&[u8], T> {
+    Err(nom::Err::Error((input, ErrorKind::Verify)))
+}
+
+/// Varint count then every item
+impl<T: Encodable> Encodable for Vec<T> {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let mut len = Varint::from(self.len() as u64).consensus_encode(buf);
+        for item in self {
+            len += item.consensus_encode(buf);
+        }
+        len
+    }
+}
+
+impl<T: Decodable> Decodable for Vec<T> {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (mut input, count) = Varint::consensus_decode(input)?;
+        // the count is untrusted, let the items prove it
+        let mut items = Vec::new();
+        for _ in 0..Into::<u64>::into(count) {
+            let (rest, item) = T::consensus_decode(input)?;
+            items.push(item);
+            input = rest;
+        }
+        Ok((input, items))
+    }
+}
+
+mod test {
+    use super::{deserialize, serialize, Decodable, Encodable, HexError};
+    use crate::block::BlockHeader;
+    use crate::transaction::{Transaction, TxInput, TxOutput, Varint};
+    use std::fmt::Debug;
+
+    fn round_trip<T: Encodable + Decodable + PartialEq + Debug>(bytes: &[u8]) -> T {
+        let value: T = deserialize(bytes).unwrap();
+        assert_eq!(serialize(&value), bytes.to_vec());
+        assert_eq!(value.consensus_encode(&mut Vec::new()), bytes.len());
+        value
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let tx = round_trip::<Transaction>(&hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600"));
+        round_trip::<Vec<TxInput>>(&serialize(&tx.inputs));
+        let outputs = round_trip::<Vec<TxOutput>>(&serialize(&tx.outputs));
+        assert_eq!(outputs, tx.outputs);
+
+        round_trip::<BlockHeader>(&hex!("020000208ec39428b17323fa0ddec8e887b4a7c53b8c0a0a220cfd0000000000000000005b0750fce0a889502d40508d39576821155e9c9e3f5c3157f961db38fd8b25be1e77a759e93c0118a4ffd71d"));
+        round_trip::<Varint>(&hex!("fd0302"));
+        round_trip::<Varint>(&hex!("fe00000100"));
+
+        // a count larger than the items given
+        assert_eq!(deserialize::<Vec<TxOutput>>(&hex!("05")), None);
+    }
+
+    #[test]
+    fn test_from_hex() {
+        use crate::script::{Script, ScriptError};
+
+        let raw = "0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600";
+        let tx = Transaction::from_hex(&format!("{}\n", raw)).unwrap();
+        assert_eq!(tx.to_hex(), raw);
+        assert_eq!(
+            Transaction::from_hex(&raw[..raw.len() - 1]),
+            Err(HexError::InvalidHex("Odd number of digits".to_string()))
+        );
+        assert_eq!(
+            Transaction::from_hex(&raw[..raw.len() - 2]),
+            Err(HexError::Malformed("transaction"))
+        );
+        assert_eq!(
+            Transaction::from_hex(&format!("{}0000", raw)),
+            Err(HexError::TrailingBytes("transaction", 2))
+        );
+
+        let header = "020000208ec39428b17323fa0ddec8e887b4a7c53b8c0a0a220cfd0000000000000000005b0750fce0a889502d40508d39576821155e9c9e3f5c3157f961db38fd8b25be1e77a759e93c0118a4ffd71d";
+        assert_eq!(BlockHeader::from_hex(header).unwrap().to_hex(), header);
+        assert_eq!(
+            BlockHeader::from_hex(&header[..158])
+                .unwrap_err()
+                .to_string(),
+            "hex does not encode a block header"
+        );
+
+        let script_pubkey = "76a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac";
+        let script = Script::from_hex(script_pubkey).unwrap();
+        assert_eq!(script.to_hex(), script_pubkey);
+        assert_eq!(
+            script.asm(),
+            "OP_DUP OP_HASH160 bc3b654dca7e56b04dca18f2566cdaf02e8d9ada OP_EQUALVERIFY OP_CHECKSIG"
+        );
+        assert!(matches!(
+            Script::from_hex("4c"),
+            Err(HexError::Script(ScriptError::TruncatedPushLength { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_round_trip_generated() {
+        use crate::block::Block;
+        use crate::mining::merkle_root;
+        use crate::testkit::TxGenerator;
+        use crate::transaction::ScriptPubKeyType;
+
+        let mut generator = TxGenerator::new(7);
+        let mut segwit = 0;
+        for _ in 0..500 {
+            let tx = generator.tx();
+            let bytes = serialize(&tx);
+            assert_eq!(round_trip::<Transaction>(&bytes), tx);
+            assert_eq!(tx.serialized_len(), bytes.len());
+            assert_eq!(tx.has_witness(), bytes[4] == 0);
+            assert!(tx.vsize() <= bytes.len());
+            assert!(
+                tx.outputs
+                    .iter()
+                    .all(|output| output.script_pub_key.script_type()
+                        != ScriptPubKeyType::NonStandard)
+            );
+            if tx.has_witness() {
+                segwit += 1;
+            }
+        }
+        assert!(segwit > 100 && segwit < 500);
+
+        for block in generator.blocks(20) {
+            assert_eq!(round_trip::<Block>(&block.serialize()), block);
+            let hashes: Vec<[u8; 32]> = block
+                .txs
+                .iter()
+                .map(|tx| {
+                    let mut hash = [0u8; 32];
+                    hash.copy_from_slice(&tx.hash());
+                    hash
+                })
+                .collect();
+            assert_eq!(merkle_root(&hashes), block.header.merkle_root);
+            assert!(block.txs[0].is_coinbase());
+        }
+    }
+}
@@ -0,0 +1,711 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use crate::block::{bits_to_difficulty, estimate_hashrate, BlockHash, BlockHeader};
+use crate::mining::{bits_to_target, check_proof_of_work};
+use crate::storage::{Storage, StorageError};
+use crate::wallet::U256;
+
+/// Start height and header of a stored chain
+const CHAIN_START: &[u8] = b"Cs";
+/// Little endian hash -> height, header
+const CHAIN_HEADER: &[u8] = b"Ch";
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum HeaderError {
+    #[fail(display = "header {} does not build on a known header", _0)]
+    Orphan(BlockHash),
+    #[fail(display = "header {} does not meet its target", _0)]
+    InvalidProofOfWork(BlockHash),
+    #[fail(
+        display = "header {} at height {} is not the checkpoint {}",
+        got, height, expected
+    )]
+    CheckpointMismatch {
+        height: u32,
+        expected: BlockHash,
+        got: BlockHash,
+    },
+    #[fail(display = "header {} forks off below the last checkpoint", _0)]
+    ForkBelowCheckpoint(BlockHash),
+}
+
+/// Mainnet blocks every node agrees on, the checkpoints Bitcoin Core used to ship
+const MAINNET_CHECKPOINTS: &[(u32, &str)] = &[
+    (
+        11111,
+        "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d",
+    ),
+    (
+        33333,
+        "000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6",
+    ),
+    (
+        74000,
+        "0000000000573993a3c9e41ce34471c079dcf5f52a0e824a81e7f953b8661a20",
+    ),
+    (
+        105000,
+        "00000000000291ce28027faea320c8d2b054b2e0fe44a773f3eefb151d6bdc97",
+    ),
+    (
+        134444,
+        "00000000000005b12ffd4cd315cd34ffd4a594f430ac814c91184a0d42d2b0fe",
+    ),
+    (
+        168000,
+        "000000000000099e61ea72015e79632f216fe6cb33d7899acb35b75c8303b763",
+    ),
+    (
+        193000,
+        "000000000000059f452a5f7340de6682a977387c17010ff6e6c3bd83ca8b1317",
+    ),
+    (
+        210000,
+        "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e",
+    ),
+    (
+        216116,
+        "00000000000001b4f4b433e81ee46494af945cf96014816a4e2370f11b23df4e",
+    ),
+    (
+        225430,
+        "00000000000001c108384350f74090433e7fcf79a606b8e797f065b130575932",
+    ),
+    (
+        250000,
+        "000000000000003887df1f29024b06fc2200b55f8af8f35453d7be294df2d214",
+    ),
+    (
+        279000,
+        "0000000000000001ae8c72a0b0c301f67e3afca10e819efa9041e458e9bd7e40",
+    ),
+    (
+        295000,
+        "00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983",
+    ),
+];
+
+/// Hard coded height to hash pins, a header at a pinned height must have that hash
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Checkpoints(BTreeMap<u32, BlockHash>);
+
+impl Checkpoints {
+    pub fn new() -> Self {
+        Checkpoints::default()
+    }
+
+    pub fn mainnet() -> Self {
+        MAINNET_CHECKPOINTS
+            .iter()
+            .fold(Checkpoints::new(), |checkpoints, (height, hash)| {
+                checkpoints.with(
+                    *height,
+                    BlockHash::from_str(hash).expect("checkpoint hashes are valid"),
+                )
+            })
+    }
+
+    pub fn with(mut self, height: u32, hash: BlockHash) -> Self {
+        self.0.insert(height, hash);
+        self
+    }
+
+    pub fn get(&self, height: u32) -> Option<&BlockHash> {
+        self.0.get(&height)
+    }
+
+    /// The highest checkpoint, headers below it can not be reorganized away
+    pub fn last(&self) -> Option<(u32, BlockHash)> {
+        self.0
+            .iter()
+            .next_back()
+            .map(|(height, hash)| (*height, *hash))
+    }
+
+    /// Err when `height` is pinned to another hash
+    pub fn check(&self, height: u32, hash: &BlockHash) -> Result<(), HeaderError> {
+        match self.0.get(&height) {
+            Some(expected) if expected != hash => Err(HeaderError::CheckpointMismatch {
+                height,
+                expected: *expected,
+                got: *hash,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What a new header did to the active chain, in the order it happened
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum ChainEvent {
+    Connected {
+        height: u32,
+        hash: BlockHash,
+    },
+    /// Left the active chain in a reorg, the tip goes first
+    Disconnected {
+        height: u32,
+        hash: BlockHash,
+    },
+}
+impl Copy for ChainEvent {}
+
+/// Expected hashes to find a hash below `target`, 2^256 / (target + 1)
+pub fn header_work(bits: u32) -> U256 {
+    let target = bits_to_target(bits);
+    if target.is_zero() {
+        return U256::zero();
+    }
+    // 2^256 does not fit, but (2^256 - target - 1) / (target + 1) + 1 is the same
+    (!target / (target + U256::one())) + U256::one()
+}
+
+fn stored_header(entry: &ChainEntry) -> Vec<u8> {
+    [&entry.height.to_le_bytes()[..], &entry.header.serialize()].concat()
+}
+
+fn parse_stored_header(bytes: &[u8]) -> Result<(u32, BlockHeader), StorageError> {
+    if bytes.len() != 84 {
+        return Err(StorageError::Corrupt);
+    }
+    let mut height = [0u8; 4];
+    height.copy_from_slice(&bytes[..4]);
+    let (_, header) = BlockHeader::parse(&bytes[4..]).map_err(|_| StorageError::Corrupt)?;
+    Ok((u32::from_le_bytes(height), header))
+}
+
+#[derive(Debug, Clone)]
+struct ChainEntry {
+    header: BlockHeader,
+    height: u32,
+    /// Work of this header and every one before it down to the start
+    chain_work: U256,
+}
+
+/// Headers from a trusted start, genesis or a checkpoint, building on any known header.
+/// The tip with the most work is active, a heavier fork takes over with disconnect and
+/// connect events. Headers must meet their own target and agree with the checkpoints, and
+/// no fork may start below the last checkpoint once it is active. Blocks at or below an
+/// assumed valid block in the active chain may skip script and signature checks.
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    start_height: u32,
+    entries: HashMap<BlockHash, ChainEntry>,
+    /// Active chain hashes from the start
+    active: Vec<BlockHash>,
+    /// Headers nothing builds on yet
+    tips: Vec<BlockHash>,
+    checkpoints: Checkpoints,
+    assume_valid: Option<BlockHash>,
+    /// Accepted since the last save
+    unsaved: Vec<BlockHash>,
+}
+
+impl HeaderChain {
+    /// Start from the genesis header
+    pub fn new(genesis: BlockHeader) -> Self {
+        HeaderChain::from_checkpoint(0, genesis)
+    }
+
+    /// Start from a header trusted to be at `height`, skipping everything before it
+    pub fn from_checkpoint(height: u32, header: BlockHeader) -> Self {
+        let hash = header.hash();
+        let mut entries = HashMap::new();
+        entries.insert(
+            hash,
+            ChainEntry {
+                header,
+                height,
+                chain_work: header_work(header.bits),
+            },
+        );
+        HeaderChain {
+            start_height: height,
+            entries,
+            active: vec![hash],
+            tips: vec![hash],
+            checkpoints: Checkpoints::new(),
+            assume_valid: None,
+            unsaved: Vec::new(),
+        }
+    }
+
+    /// The chain saved in `storage`, None if there is none. Equal work tips may come back
+    /// with another one active, checkpoints and the assumed valid block are not stored.
+    pub fn load<S: Storage>(storage: &S) -> Result<Option<Self>, StorageError> {
+        let start = match storage.get(CHAIN_START)? {
+            Some(start) => start,
+            None => return Ok(None),
+        };
+        let (height, header) = parse_stored_header(&start)?;
+        let mut chain = HeaderChain::from_checkpoint(height, header);
+
+        let mut headers = storage
+            .scan_prefix(CHAIN_HEADER)?
+            .iter()
+            .map(|(_, value)| parse_stored_header(value))
+            .collect::<Result<Vec<_>, _>>()?;
+        // parents before children
+        headers.sort_by_key(|(height, _)| *height);
+        for (_, header) in headers {
+            chain.accept(header).map_err(|_| StorageError::Corrupt)?;
+        }
+        chain.unsaved.clear();
+        Ok(Some(chain))
+    }
+
+    /// Write the headers accepted since the last save
+    pub fn save<S: Storage>(&mut self, storage: &mut S) -> Result<(), StorageError> {
+        let start = &self.entries[&self.active[0]];
+        storage.put(CHAIN_START, &stored_header(start))?;
+        for hash in &self.unsaved {
+            let key = [CHAIN_HEADER, &hash.to_little_endian()].concat();
+            storage.put(&key, &stored_header(&self.entries[hash]))?;
+        }
+        self.unsaved.clear();
+        Ok(())
+    }
+
+    pub fn with_checkpoints(mut self, checkpoints: Checkpoints) -> Self {
+        self.checkpoints = checkpoints;
+        self
+    }
+
+    /// Blocks up to `hash` are taken as having valid scripts once it is active
+    pub fn with_assume_valid(mut self, hash: BlockHash) -> Self {
+        self.assume_valid = Some(hash);
+        self
+    }
+
+    /// Add `headers` in order, returns the events of all of them. Headers before a failing
+    /// one stay, with their events lost along with the error.
+    pub fn connect(&mut self, headers: &[BlockHeader]) -> Result<Vec<ChainEvent>, HeaderError> {
+        let mut events = Vec::new();
+        for header in headers {
+            events.extend(self.accept(*header)?);
+        }
+        Ok(events)
+    }
+
+    /// Add a header building on any known one, a header already known does nothing
+    pub fn accept(&mut self, header: BlockHeader) -> Result<Vec<ChainEvent>, HeaderError> {
+        let hash = header.hash();
+        if self.entries.contains_key(&hash) {
+            return Ok(vec![]);
+        }
+        let (height, parent_work) = match self.entries.get(&header.prev_block) {
+            Some(parent) => (parent.height + 1, parent.chain_work),
+            None => return Err(HeaderError::Orphan(hash)),
+        };
+        if !check_proof_of_work(&header, bits_to_target(header.bits)) {
+            return Err(HeaderError::InvalidProofOfWork(hash));
+        }
+        self.checkpoints.check(height, &hash)?;
+        if let Some((checkpoint_height, checkpoint)) = self.checkpoints.last() {
+            if height < checkpoint_height && self.is_active(&checkpoint) {
+                return Err(HeaderError::ForkBelowCheckpoint(hash));
+            }
+        }
+
+        let chain_work = parent_work + header_work(header.bits);
+        self.entries.insert(
+            hash,
+            ChainEntry {
+                header,
+                height,
+                chain_work,
+            },
+        );
+        self.tips.retain(|tip| *tip != header.prev_block);
+        self.tips.push(hash);
+        self.unsaved.push(hash);
+
+        if chain_work > self.entries[&self.tip().1].chain_work {
+            Ok(self.activate(hash))
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    /// Make the chain ending at `hash` active
+    fn activate(&mut self, hash: BlockHash) -> Vec<ChainEvent> {
+        // walk back from the new tip to the first header already active
+        let mut branch = Vec::new();
+        let mut cursor = hash;
+        while !self.is_active(&cursor) {
+            branch.push(cursor);
+            cursor = self.entries[&cursor].header.prev_block;
+        }
+        let fork_height = self.entries[&cursor].height;
+
+        let mut events = Vec::new();
+        while self.tip().0 > fork_height {
+            let (height, hash) = self.tip();
+            self.active.pop();
+            events.push(ChainEvent::Disconnected { height, hash });
+        }
+        for hash in branch.into_iter().rev() {
+            self.active.push(hash);
+            events.push(ChainEvent::Connected {
+                height: self.tip().0,
+                hash,
+            });
+        }
+        events
+    }
+
+    /// Height and hash of the active tip
+    pub fn tip(&self) -> (u32, BlockHash) {
+        (
+            self.start_height + self.active.len() as u32 - 1,
+            self.active[self.active.len() - 1],
+        )
+    }
+
+    /// Every header nothing builds on, the active tip among them, with its height and
+    /// chain work
+    pub fn tips(&self) -> Vec<(BlockHash, u32, U256)> {
+        self.tips
+            .iter()
+            .map(|hash| {
+                let entry = &self.entries[hash];
+                (*hash, entry.height, entry.chain_work)
+            })
+            .collect()
+    }
+
+    pub fn start_height(&self) -> u32 {
+        self.start_height
+    }
+
+    /// Active header at `height`
+    pub fn header(&self, height: u32) -> Option<&BlockHeader> {
+        self.hash(height).map(|hash| &self.entries[hash].header)
+    }
+
+    /// Active hash at `height`
+    pub fn hash(&self, height: u32) -> Option<&BlockHash> {
+        self.active
+            .get(height.checked_sub(self.start_height)? as usize)
+    }
+
+    /// Active hashes for a `getheaders` locator, the tip first and then back in doubling
+    /// steps after the first ten, the start last
+    pub fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = Vec::new();
+        let mut index = self.active.len() - 1;
+        let mut step = 1;
+        loop {
+            locator.push(self.active[index]);
+            if index == 0 {
+                return locator;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            index = index.saturating_sub(step);
+        }
+    }
+
+    /// Height of any known header, active or not
+    pub fn height(&self, hash: &BlockHash) -> Option<u32> {
+        self.entries.get(hash).map(|entry| entry.height)
+    }
+
+    pub fn is_active(&self, hash: &BlockHash) -> bool {
+        match self.height(hash) {
+            Some(height) => self.hash(height) == Some(hash),
+            None => false,
+        }
+    }
+
+    /// Work of the active chain from the start
+    pub fn chain_work(&self) -> U256 {
+        self.entries[&self.tip().1].chain_work
+    }
+
+    /// Difficulty of the active tip
+    pub fn difficulty(&self) -> f64 {
+        bits_to_difficulty(self.entries[&self.tip().1].header.bits)
+    }
+
+    /// Hashes a second over the last `blocks` blocks of the active chain, see
+    /// `estimate_hashrate`. Bitcoin Core's `getnetworkhashps` looks at 120.
+    pub fn estimate_hashrate(&self, blocks: u32) -> Option<f64> {
+        let from = self.active.len().saturating_sub(blocks as usize + 1);
+        let headers: Vec<BlockHeader> = self.active[from..]
+            .iter()
+            .map(|hash| self.entries[hash].header)
+            .collect();
+        estimate_hashrate(&headers)
+    }
+
+    /// Height of the assumed valid block, None until it is in the active chain
+    pub fn assume_valid_height(&self) -> Option<u32> {
+        self.assume_valid
+            .filter(|hash| self.is_active(hash))
+            .and_then(|hash| self.height(&hash))
+    }
+
+    /// Whether a block at `height` needs its scripts and signatures checked, false at or
+    /// below the assumed valid block once it is active
+    pub fn needs_script_checks(&self, height: u32) -> bool {
+        match self.assume_valid_height() {
+            Some(assume_valid) => height > assume_valid,
+            None => true,
+        }
+    }
+}
+
+mod test {
+    use super::{ChainEvent, Checkpoints, HeaderChain, HeaderError};
+    use crate::block::{BlockHash, BlockHeader};
+    use crate::mining::{bits_to_target, mine};
+
+    /// Regtest difficulty headers on top of `prev`
+    fn mine_headers(prev: &BlockHeader, count: usize, salt: u8) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::with_capacity(count);
+        let mut prev_block = prev.hash();
+        for i in 0..count {
+            let header = BlockHeader {
+                version: 0x2000_0000,
+                prev_block,
+                merkle_root: [salt; 32],
+                timestamp: prev.timestamp + 600 * (i as u32 + 1),
+                bits: 0x207f_ffff,
+                nonce: 0,
+            };
+            let header = mine(header, bits_to_target(header.bits)).unwrap();
+            prev_block = header.hash();
+            headers.push(header);
+        }
+        headers
+    }
+
+    fn genesis() -> BlockHeader {
+        mine_headers(
+            &BlockHeader {
+                version: 1,
+                prev_block: BlockHash::default(),
+                merkle_root: [0u8; 32],
+                timestamp: 1_296_688_602,
+                bits: 0x207f_ffff,
+                nonce: 0,
+            },
+            1,
+            0,
+        )[0]
+    }
+
+    fn connected(headers: &[BlockHeader], from: u32) -> Vec<ChainEvent> {
+        headers
+            .iter()
+            .enumerate()
+            .map(|(i, header)| ChainEvent::Connected {
+                height: from + i as u32,
+                hash: header.hash(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_header_sync() {
+        let genesis = genesis();
+        let headers = mine_headers(&genesis, 10, 1);
+        let mut chain = HeaderChain::new(genesis).with_assume_valid(headers[5].hash());
+        assert!(chain.needs_script_checks(1));
+        assert_eq!(
+            chain.connect(&headers[..4]),
+            Ok(connected(&headers[..4], 1))
+        );
+
+        // skipping a header
+        assert_eq!(
+            chain.connect(&headers[5..]),
+            Err(HeaderError::Orphan(headers[5].hash()))
+        );
+        // a nonce that misses the target, unless the miner got very lucky
+        let mut bad = headers[4];
+        bad.bits = 0x0300_0001;
+        assert_eq!(
+            chain.connect(&[bad]),
+            Err(HeaderError::InvalidProofOfWork(bad.hash()))
+        );
+
+        assert_eq!(
+            chain.connect(&headers[4..]),
+            Ok(connected(&headers[4..], 5))
+        );
+        assert_eq!(chain.connect(&headers[4..]), Ok(vec![]));
+        assert_eq!(chain.tip(), (10, headers[9].hash()));
+        let locator = chain.locator();
+        assert_eq!(locator.len(), 11);
+        assert_eq!(locator[0], headers[9].hash());
+        assert_eq!(locator[10], *chain.hash(0).unwrap());
+        assert_eq!(chain.height(&headers[2].hash()), Some(3));
+        assert_eq!(chain.header(6), Some(&headers[5]));
+        assert_eq!(chain.assume_valid_height(), Some(6));
+        assert!(!chain.needs_script_checks(6));
+        assert!(chain.needs_script_checks(7));
+
+        // a regtest block is about 2 hashes, one every 10 minutes
+        assert!(chain.difficulty() < 1e-9);
+        let hashrate = chain.estimate_hashrate(120).unwrap();
+        assert!((hashrate * 600.0 - 2.0).abs() < 1e-5);
+        assert_eq!(chain.estimate_hashrate(5), chain.estimate_hashrate(10));
+        assert_eq!(HeaderChain::new(genesis).estimate_hashrate(120), None);
+    }
+
+    #[test]
+    fn test_reorg() {
+        let genesis = genesis();
+        let main = mine_headers(&genesis, 3, 1);
+        let fork = mine_headers(&main[0], 3, 2);
+        let mut chain = HeaderChain::new(genesis);
+        chain.connect(&main).unwrap();
+
+        // as much work as the active chain is not enough
+        assert_eq!(chain.connect(&fork[..2]), Ok(vec![]));
+        assert_eq!(chain.tip(), (3, main[2].hash()));
+        assert_eq!(chain.tips().len(), 2);
+        assert!(!chain.is_active(&fork[1].hash()));
+
+        let work = chain.chain_work();
+        assert_eq!(
+            chain.accept(fork[2]),
+            Ok(vec![
+                ChainEvent::Disconnected {
+                    height: 3,
+                    hash: main[2].hash(),
+                },
+                ChainEvent::Disconnected {
+                    height: 2,
+                    hash: main[1].hash(),
+                },
+                ChainEvent::Connected {
+                    height: 2,
+                    hash: fork[0].hash(),
+                },
+                ChainEvent::Connected {
+                    height: 3,
+                    hash: fork[1].hash(),
+                },
+                ChainEvent::Connected {
+                    height: 4,
+                    hash: fork[2].hash(),
+                },
+            ])
+        );
+        assert!(chain.chain_work() > work);
+        assert_eq!(chain.tip(), (4, fork[2].hash()));
+        assert_eq!(chain.hash(1), Some(&main[0].hash()));
+        assert_eq!(chain.height(&main[2].hash()), Some(3));
+        assert!(!chain.is_active(&main[2].hash()));
+    }
+
+    #[test]
+    fn test_simulated_reorgs() {
+        use crate::testkit::TestChain;
+        let mut main = TestChain::new(10);
+        let mut chain = main.header_chain();
+        assert_eq!(chain.tip(), main.tip());
+
+        // a branch with as much work stays inactive
+        let tie = main.fork(6, 4);
+        assert_eq!(tie.feed(&mut chain), Ok(vec![]));
+        assert_eq!(chain.tips().len(), 2);
+
+        let heavier = main.heavier_fork(4);
+        assert_eq!(heavier.fork_height(&main), 4);
+        assert_eq!(heavier.feed(&mut chain), Ok(main.reorg_events(&heavier)));
+        assert_eq!(chain.tip(), heavier.tip());
+
+        // the old chain catches up and takes over again
+        main.extend(3);
+        assert_eq!(
+            chain.connect(main.headers_after(10)),
+            Ok(heavier.reorg_events(&main))
+        );
+        assert_eq!(chain.tip(), (13, main.tip().1));
+        assert_eq!(chain.tips().len(), 3);
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let genesis = genesis();
+        let headers = mine_headers(&genesis, 4, 1);
+        let fork = mine_headers(&genesis, 4, 2);
+        let checkpoints = Checkpoints::new().with(3, headers[2].hash());
+        assert_eq!(checkpoints.last(), Some((3, headers[2].hash())));
+
+        let mut chain = HeaderChain::new(genesis).with_checkpoints(checkpoints.clone());
+        assert_eq!(
+            chain.connect(&fork),
+            Err(HeaderError::CheckpointMismatch {
+                height: 3,
+                expected: headers[2].hash(),
+                got: fork[2].hash(),
+            })
+        );
+        assert_eq!(chain.tip(), (2, fork[1].hash()));
+        // the checkpointed chain takes over once it has more work
+        chain.connect(&headers).unwrap();
+        assert_eq!(chain.tip(), (4, headers[3].hash()));
+        // and nothing may fork below the checkpoint any more
+        let late = mine_headers(&genesis, 1, 3);
+        assert_eq!(
+            chain.accept(late[0]),
+            Err(HeaderError::ForkBelowCheckpoint(late[0].hash()))
+        );
+
+        // starting at the checkpoint skips everything before it
+        let mut chain = HeaderChain::from_checkpoint(3, headers[2]).with_checkpoints(checkpoints);
+        assert_eq!(
+            chain.connect(&headers[3..]),
+            Ok(connected(&headers[3..], 4))
+        );
+        assert_eq!(chain.hash(2), None);
+        assert_eq!(chain.hash(4), Some(&headers[3].hash()));
+
+        let mainnet = Checkpoints::mainnet();
+        assert_eq!(
+            mainnet.get(210_000).unwrap().to_string(),
+            "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e"
+        );
+        assert_eq!(mainnet.last().unwrap().0, 295_000);
+    }
+
+    #[test]
+    fn test_header_work() {
+        use super::header_work;
+        use crate::wallet::U256;
+        // the genesis difficulty, 2^32 hashes a block
+        assert_eq!(header_work(0x1d00_ffff), U256::from(0x0001_0001_0001u64));
+        assert_eq!(header_work(0x207f_ffff), U256::from(2u8));
+    }
+
+    #[test]
+    fn test_save_load() {
+        use crate::storage::MemoryStorage;
+
+        let genesis = genesis();
+        let main = mine_headers(&genesis, 3, 1);
+        let fork = mine_headers(&main[0], 1, 2);
+        let mut storage = MemoryStorage::new();
+        assert!(HeaderChain::load(&storage).unwrap().is_none());
+
+        let mut chain = HeaderChain::new(genesis);
+        chain.connect(&main[..2]).unwrap();
+        chain.save(&mut storage).unwrap();
+        chain.connect(&[main[2], fork[0]]).unwrap();
+        chain.save(&mut storage).unwrap();
+        assert_eq!(storage.len(), 5);
+
+        let loaded = HeaderChain::load(&storage).unwrap().unwrap();
+        assert_eq!(loaded.tip(), chain.tip());
+        assert_eq!(loaded.chain_work(), chain.chain_work());
+        assert_eq!(loaded.tips().len(), 2);
+        assert_eq!(loaded.height(&fork[0].hash()), Some(2));
+    }
+}
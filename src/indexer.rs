@@ -0,0 +1,386 @@
+use failure::Error;
+use sha2::{Digest, Sha256};
+
+use crate::block::{Block, BlockHash};
+use crate::storage::{Storage, StorageError};
+use crate::transaction::{OutPoint, ScriptBackend, ScriptPubKey, TxHash};
+
+const TIP: &[u8] = b"t";
+/// outpoint -> script hash, amount, for every unspent output
+const OUTPOINT: u8 = b'o';
+/// script hash, outpoint -> amount, height
+const UNSPENT: u8 = b'u';
+/// script hash, big endian height, txid -> nothing, every transaction touching the script
+const HISTORY: u8 = b'h';
+
+#[derive(Fail, Debug)]
+pub enum IndexError {
+    #[fail(display = "{}", _0)]
+    Storage(StorageError),
+    #[fail(display = "block {} does not extend the indexed tip {}", block, tip)]
+    NotConnected { block: BlockHash, tip: BlockHash },
+    #[fail(display = "malformed index entry")]
+    Corrupt,
+}
+
+impl From<StorageError> for IndexError {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::Corrupt => IndexError::Corrupt,
+            e => IndexError::Storage(e),
+        }
+    }
+}
+
+/// Electrum style script hash, the sha256 of the script pubkey
+pub fn script_hash(script_pub_key: &ScriptPubKey) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&Sha256::digest(&script_pub_key.content));
+    hash
+}
+
+fn key(prefix: u8, parts: &[&[u8]]) -> Vec<u8> {
+    let mut key = vec![prefix];
+    parts.iter().for_each(|part| key.extend_from_slice(part));
+    key
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(buf)
+}
+
+fn parse_out_point(bytes: &[u8]) -> Result<OutPoint, IndexError> {
+    match OutPoint::parse(bytes) {
+        Ok((rest, out_point)) if rest.is_empty() => Ok(out_point),
+        _ => Err(IndexError::Corrupt),
+    }
+}
+
+/// Script hash to outpoint index built block by block, the core of a personal block
+/// explorer. Blocks must be given in chain order, each extending the last one indexed, and
+/// the store keeps the tip so indexing picks up where it stopped.
+pub struct Indexer<S: Storage> {
+    store: S,
+    start_height: u32,
+}
+
+impl<S: Storage> Indexer<S> {
+    pub fn new(store: S) -> Self {
+        Indexer {
+            store,
+            start_height: 0,
+        }
+    }
+
+    /// Height of the first block indexed into an empty store, for indexes that do not
+    /// start at genesis
+    pub fn with_start_height(mut self, height: u32) -> Self {
+        self.start_height = height;
+        self
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Height and hash of the last block indexed
+    pub fn tip(&self) -> Result<Option<(u32, BlockHash)>, IndexError> {
+        match self.store.get(TIP)? {
+            Some(ref tip) if tip.len() == 36 => {
+                let hash = BlockHash::parse(&tip[4..])
+                    .map_err(|_| IndexError::Corrupt)?
+                    .1;
+                Ok(Some((u32_at(tip, 0), hash)))
+            }
+            Some(_) => Err(IndexError::Corrupt),
+            None => Ok(None),
+        }
+    }
+
+    /// Index the outputs `block` creates and remove the ones it spends, returns its height
+    pub fn index_block(&mut self, block: &Block) -> Result<u32, IndexError> {
+        let height = match self.tip()? {
+            Some((height, tip)) if tip == block.header.prev_block => height + 1,
+            Some((_, tip)) => {
+                return Err(IndexError::NotConnected {
+                    block: block.hash(),
+                    tip,
+                })
+            }
+            None => self.start_height,
+        };
+        let height_key = height.to_be_bytes();
+
+        for tx in &block.txs {
+            let txid = tx.id();
+            let txid_bytes = txid.to_little_endian();
+
+            if !tx.is_coinbase() {
+                for input in &tx.inputs {
+                    let out_point = input.out_point().serialize();
+                    let outpoint_key = key(OUTPOINT, &[&out_point]);
+                    // outputs from before the index started are unknown
+                    let funding = match self.store.get(&outpoint_key)? {
+                        Some(funding) => funding,
+                        None => continue,
+                    };
+                    if funding.len() != 40 {
+                        return Err(IndexError::Corrupt);
+                    }
+                    let hash = &funding[..32];
+                    self.store.delete(&outpoint_key)?;
+                    self.store.delete(&key(UNSPENT, &[hash, &out_point]))?;
+                    self.store
+                        .put(&key(HISTORY, &[hash, &height_key, &txid_bytes]), &[])?;
+                }
+            }
+
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                // OP_RETURN outputs can never be spent
+                if output.script_pub_key.content.first() == Some(&0x6a) {
+                    continue;
+                }
+                let hash = script_hash(&output.script_pub_key);
+                let out_point = OutPoint::new(txid, vout as u32).serialize();
+                let amount = u64::from(output.amount).to_le_bytes();
+
+                let mut funding = hash.to_vec();
+                funding.extend_from_slice(&amount);
+                self.store.put(&key(OUTPOINT, &[&out_point]), &funding)?;
+
+                let mut unspent = amount.to_vec();
+                unspent.extend_from_slice(&height.to_le_bytes());
+                self.store
+                    .put(&key(UNSPENT, &[&hash, &out_point]), &unspent)?;
+                self.store
+                    .put(&key(HISTORY, &[&hash, &height_key, &txid_bytes]), &[])?;
+            }
+        }
+
+        let mut tip = height.to_le_bytes().to_vec();
+        tip.extend_from_slice(&block.hash().to_little_endian());
+        self.store.put(TIP, &tip)?;
+        Ok(height)
+    }
+
+    /// Unspent outputs paying `script_pub_key`, with their amount and the height that
+    /// created them
+    pub fn utxos(
+        &self,
+        script_pub_key: &ScriptPubKey,
+    ) -> Result<Vec<(OutPoint, u64, u32)>, IndexError> {
+        let prefix = key(UNSPENT, &[&script_hash(script_pub_key)]);
+        self.store
+            .scan_prefix(&prefix)?
+            .into_iter()
+            .map(|(key, value)| {
+                if value.len() != 12 {
+                    return Err(IndexError::Corrupt);
+                }
+                let out_point = parse_out_point(&key[prefix.len()..])?;
+                Ok((out_point, u64_at(&value, 0), u32_at(&value, 8)))
+            })
+            .collect()
+    }
+
+    pub fn balance(&self, script_pub_key: &ScriptPubKey) -> Result<u64, IndexError> {
+        Ok(self
+            .utxos(script_pub_key)?
+            .iter()
+            .map(|(_, amount, _)| amount)
+            .sum())
+    }
+
+    /// Every transaction paying to or spending from `script_pub_key`, oldest first
+    pub fn history(&self, script_pub_key: &ScriptPubKey) -> Result<Vec<(u32, TxHash)>, IndexError> {
+        let prefix = key(HISTORY, &[&script_hash(script_pub_key)]);
+        self.store
+            .scan_prefix(&prefix)?
+            .into_iter()
+            .map(|(key, _)| {
+                let entry = &key[prefix.len()..];
+                if entry.len() != 36 {
+                    return Err(IndexError::Corrupt);
+                }
+                let mut height = [0u8; 4];
+                height.copy_from_slice(&entry[..4]);
+                let txid = TxHash::parse(&entry[4..])
+                    .map_err(|_| IndexError::Corrupt)?
+                    .1;
+                Ok((u32::from_be_bytes(height), txid))
+            })
+            .collect()
+    }
+}
+
+/// The index answers for whatever network its blocks came from, `testnet` is ignored
+impl<S: Storage> ScriptBackend for Indexer<S> {
+    fn script_history(
+        &mut self,
+        script_pub_key: &ScriptPubKey,
+        _testnet: bool,
+    ) -> Result<Vec<TxHash>, Error> {
+        Ok(self
+            .history(script_pub_key)?
+            .into_iter()
+            .map(|(_, txid)| txid)
+            .collect())
+    }
+
+    fn script_utxos(
+        &mut self,
+        script_pub_key: &ScriptPubKey,
+        _testnet: bool,
+    ) -> Result<Vec<(OutPoint, u64, u32)>, Error> {
+        Ok(self.utxos(script_pub_key)?)
+    }
+}
+
+mod test {
+    use super::{IndexError, Indexer};
+    use crate::block::{Block, BlockHeader};
+    use crate::storage::MemoryStorage;
+    use crate::transaction::{
+        OutPoint, PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxInput, TxInputSequence,
+        TxLocktime, TxOutput, TxVersion,
+    };
+
+    const GENESIS: [u8; 285] = hex!("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000");
+
+    fn p2wpkh(byte: u8) -> ScriptPubKey {
+        let mut content = vec![0x00, 0x14];
+        content.extend_from_slice(&[byte; 20]);
+        ScriptPubKey { content }
+    }
+
+    fn spend(from: &[OutPoint], to: &[(ScriptPubKey, u64)]) -> Transaction {
+        let inputs = from
+            .iter()
+            .map(|out_point| {
+                TxInput::new(
+                    out_point.txid,
+                    PreTxIndex::new(out_point.vout),
+                    ScriptSig { content: vec![] },
+                    TxInputSequence::new(0xffff_ffff),
+                )
+            })
+            .collect();
+        let outputs = to
+            .iter()
+            .map(|(script_pub_key, amount)| TxOutput {
+                amount: (*amount).into(),
+                script_pub_key: script_pub_key.clone(),
+            })
+            .collect();
+        Transaction::new(
+            TxVersion::new(2),
+            inputs,
+            outputs,
+            TxLocktime::new(0),
+            false,
+        )
+    }
+
+    fn child(parent: &Block, txs: Vec<Transaction>) -> Block {
+        let mut header: BlockHeader = parent.header;
+        header.prev_block = parent.hash();
+        Block { header, txs }
+    }
+
+    #[test]
+    fn test_indexer() {
+        let genesis = Block::parse(&GENESIS).unwrap().1;
+        let satoshi = genesis.txs[0].outputs[0].script_pub_key.clone();
+        let (alice, bob) = (p2wpkh(0xaa), p2wpkh(0xbb));
+
+        let mut indexer = Indexer::new(MemoryStorage::new());
+        assert_eq!(indexer.index_block(&genesis).unwrap(), 0);
+        assert_eq!(indexer.balance(&satoshi).unwrap(), 50_0000_0000);
+
+        let tx1 = spend(
+            &[OutPoint::new(genesis.txs[0].id(), 0)],
+            &[(alice.clone(), 30_0000_0000), (bob.clone(), 20_0000_0000)],
+        );
+        // spent in the block that creates it
+        let tx2 = spend(
+            &[OutPoint::new(tx1.id(), 1)],
+            &[(alice.clone(), 19_0000_0000)],
+        );
+        let block1 = child(&genesis, vec![tx1.clone(), tx2.clone()]);
+        assert_eq!(indexer.index_block(&block1).unwrap(), 1);
+
+        assert_eq!(indexer.balance(&satoshi).unwrap(), 0);
+        assert_eq!(indexer.balance(&bob).unwrap(), 0);
+        assert_eq!(indexer.balance(&alice).unwrap(), 49_0000_0000);
+        assert_eq!(
+            indexer.utxos(&alice).unwrap().len(),
+            2,
+            "both of alice's outputs are unspent"
+        );
+        let mut history = indexer.history(&bob).unwrap();
+        history.sort();
+        let mut expected = vec![(1, tx1.id()), (1, tx2.id())];
+        expected.sort();
+        assert_eq!(history, expected);
+
+        // the store alone carries the index to the next run
+        let mut indexer = Indexer::new(indexer.into_store());
+        assert_eq!(indexer.tip().unwrap(), Some((1, block1.hash())));
+        let tx3 = spend(
+            &[OutPoint::new(tx2.id(), 0)],
+            &[(bob.clone(), 18_0000_0000)],
+        );
+        let block2 = child(&block1, vec![tx3.clone()]);
+        assert_eq!(indexer.index_block(&block2).unwrap(), 2);
+        assert_eq!(indexer.balance(&alice).unwrap(), 30_0000_0000);
+        assert_eq!(
+            indexer.utxos(&bob).unwrap(),
+            vec![(OutPoint::new(tx3.id(), 0), 18_0000_0000, 2)]
+        );
+
+        match indexer.index_block(&block1) {
+            Err(IndexError::NotConnected { block, tip }) => {
+                assert_eq!(block, block1.hash());
+                assert_eq!(tip, block2.hash());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_index_generated() {
+        use crate::testkit::TxGenerator;
+        use std::collections::HashMap;
+
+        let mut generator = TxGenerator::new(42);
+        let blocks = generator.blocks(60);
+        let mut indexer = Indexer::new(MemoryStorage::new()).with_start_height(1);
+        for (height, block) in blocks.iter().enumerate() {
+            assert_eq!(indexer.index_block(block).unwrap(), height as u32 + 1);
+        }
+
+        // every script's balance is what the generator left unspent for it
+        let mut expected: HashMap<Vec<u8>, (u64, usize)> = HashMap::new();
+        for utxo in generator.utxos() {
+            let entry = expected
+                .entry(utxo.script_pub_key.content.clone())
+                .or_default();
+            entry.0 += utxo.amount;
+            entry.1 += 1;
+        }
+        assert!(expected.len() > 20);
+        for (content, (balance, count)) in expected {
+            let script_pub_key = ScriptPubKey { content };
+            assert_eq!(indexer.balance(&script_pub_key).unwrap(), balance);
+            assert_eq!(indexer.utxos(&script_pub_key).unwrap().len(), count);
+        }
+    }
+}
@@ -5,10 +5,32 @@ extern crate hex_literal;
 extern crate uint;
 #[macro_use]
 extern crate failure;
+#[macro_use]
+extern crate bitflags;
 
+mod block;
+mod blockfile;
+mod cancel;
+mod chain_stats;
+mod contracts;
+mod encode;
+mod headers;
+mod indexer;
+mod metrics;
+mod mining;
+mod network;
+mod proxy;
+mod psbt;
+#[cfg(feature = "rates")]
+mod rates;
 mod script;
+mod storage;
+#[cfg(test)]
+mod testkit;
 mod transaction;
+mod versionbits;
 mod wallet;
+mod watchlist;
 
 fn main() {
     println!("Hello, world!");
@@ -0,0 +1,452 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use failure::Error;
+
+use crate::block::{Block, BlockHash};
+use crate::transaction::{
+    BlockBackend, ChainBackend, OutPoint, ScriptBackend, ScriptPubKey, Transaction, TxBackend,
+    TxHash,
+};
+
+/// Headers connected to a header chain
+pub const HEADERS_SYNCED: &str = "bitcoin_headers_synced_total";
+/// Headers connected per second over the last sync
+pub const HEADERS_PER_SECOND: &str = "bitcoin_headers_per_second";
+/// Transactions whose inputs were verified
+pub const TXS_VERIFIED: &str = "bitcoin_txs_verified_total";
+/// Transactions verified per second over the last batch
+pub const TXS_VERIFIED_PER_SECOND: &str = "bitcoin_txs_verified_per_second";
+/// Cache lookups, labeled with the `cache` and a `result` of `hit` or `miss`
+pub const CACHE_LOOKUPS: &str = "bitcoin_cache_lookups_total";
+/// Backend round trips, labeled with the `backend` and the `method`
+pub const BACKEND_REQUEST_SECONDS: &str = "bitcoin_backend_request_seconds";
+
+/// Histogram buckets in seconds, Prometheus' defaults
+pub const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Where subsystems report what they do. Every method does nothing by default, so an
+/// implementation picks the kinds it cares about.
+pub trait Metrics: Send + Sync {
+    /// Add `by` to a counter
+    fn increment(&self, _name: &str, _labels: &[(&str, &str)], _by: u64) {}
+
+    fn set_gauge(&self, _name: &str, _labels: &[(&str, &str)], _value: f64) {}
+
+    /// Record one value of a histogram, e.g. a latency in seconds
+    fn observe(&self, _name: &str, _labels: &[(&str, &str)], _value: f64) {}
+}
+
+/// Drops everything, the default of every subsystem
+#[derive(Debug, Clone, Default)]
+pub struct NoopMetrics;
+impl Copy for NoopMetrics {}
+
+impl Metrics for NoopMetrics {}
+
+pub fn noop() -> Arc<dyn Metrics> {
+    Arc::new(NoopMetrics)
+}
+
+/// A cache hit or miss
+pub fn record_lookup(metrics: &dyn Metrics, cache: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    metrics.increment(CACHE_LOOKUPS, &[("cache", cache), ("result", result)], 1);
+}
+
+/// Run `request` and observe how long it took as a `backend` round trip
+pub fn time_request<T, F: FnOnce() -> T>(
+    metrics: &dyn Metrics,
+    backend: &str,
+    method: &str,
+    request: F,
+) -> T {
+    let start = Instant::now();
+    let result = request();
+    metrics.observe(
+        BACKEND_REQUEST_SECONDS,
+        &[("backend", backend), ("method", method)],
+        start.elapsed().as_secs_f64(),
+    );
+    result
+}
+
+/// `count` per second since `start`, None when no time has passed
+pub fn rate(count: usize, start: Instant) -> Option<f64> {
+    let seconds = start.elapsed().as_secs_f64();
+    if seconds > 0.0 {
+        Some(count as f64 / seconds)
+    } else {
+        None
+    }
+}
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Counter(u64),
+    Gauge(f64),
+    /// Count per bucket, then the sum and count of every value
+    Histogram(Vec<u64>, f64, u64),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Counter(_) => "counter",
+            Value::Gauge(_) => "gauge",
+            Value::Histogram(..) => "histogram",
+        }
+    }
+}
+
+/// Keeps every metric in memory and renders them in the Prometheus text format for a
+/// `/metrics` endpoint to serve
+#[derive(Debug, Default)]
+pub struct PrometheusMetrics {
+    metrics: Mutex<BTreeMap<String, BTreeMap<Labels, Value>>>,
+}
+
+impl PrometheusMetrics {
+    pub fn new() -> Self {
+        PrometheusMetrics::default()
+    }
+
+    fn update<F: FnOnce(&mut Value)>(
+        &self,
+        name: &str,
+        labels: &[(&str, &str)],
+        empty: Value,
+        update: F,
+    ) {
+        let mut labels: Labels = labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        labels.sort();
+        let mut metrics = self.metrics.lock().expect("metrics lock poisoned");
+        let value = metrics
+            .entry(name.to_string())
+            .or_default()
+            .entry(labels)
+            .or_insert(empty);
+        update(value);
+    }
+
+    fn get(&self, name: &str, labels: &[(&str, &str)]) -> Option<Value> {
+        let mut labels: Labels = labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        labels.sort();
+        let metrics = self.metrics.lock().expect("metrics lock poisoned");
+        metrics.get(name)?.get(&labels).cloned()
+    }
+
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        match self.get(name, labels) {
+            Some(Value::Counter(count)) => count,
+            _ => 0,
+        }
+    }
+
+    pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        match self.get(name, labels) {
+            Some(Value::Gauge(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Values a histogram has seen
+    pub fn observations(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        match self.get(name, labels) {
+            Some(Value::Histogram(_, _, count)) => count,
+            _ => 0,
+        }
+    }
+
+    /// Share of lookups in `cache` that hit, None before the first one
+    pub fn hit_rate(&self, cache: &str) -> Option<f64> {
+        let hits = self.counter(CACHE_LOOKUPS, &[("cache", cache), ("result", "hit")]);
+        let misses = self.counter(CACHE_LOOKUPS, &[("cache", cache), ("result", "miss")]);
+        if hits + misses == 0 {
+            None
+        } else {
+            Some(hits as f64 / (hits + misses) as f64)
+        }
+    }
+
+    /// Prometheus text exposition format, version 0.0.4
+    pub fn render(&self) -> String {
+        let metrics = self.metrics.lock().expect("metrics lock poisoned");
+        let mut out = String::new();
+        for (name, series) in metrics.iter() {
+            let kind = match series.values().next() {
+                Some(value) => value.kind(),
+                None => continue,
+            };
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            for (labels, value) in series {
+                match value {
+                    Value::Counter(count) => {
+                        writeln!(out, "{}{} {}", name, render_labels(labels, None), count)
+                    }
+                    Value::Gauge(value) => {
+                        writeln!(out, "{}{} {}", name, render_labels(labels, None), value)
+                    }
+                    Value::Histogram(buckets, sum, count) => {
+                        for (bound, bucket) in DEFAULT_BUCKETS.iter().zip(buckets) {
+                            let le = bound.to_string();
+                            writeln!(
+                                out,
+                                "{}_bucket{} {}",
+                                name,
+                                render_labels(labels, Some(&le)),
+                                bucket
+                            )
+                            .unwrap();
+                        }
+                        writeln!(
+                            out,
+                            "{}_bucket{} {}",
+                            name,
+                            render_labels(labels, Some("+Inf")),
+                            count
+                        )
+                        .unwrap();
+                        let labels = render_labels(labels, None);
+                        writeln!(out, "{}_sum{} {}", name, labels, sum).unwrap();
+                        writeln!(out, "{}_count{} {}", name, labels, count)
+                    }
+                }
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+fn render_labels(labels: &[(String, String)], le: Option<&str>) -> String {
+    let mut rendered: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect();
+    if let Some(le) = le {
+        rendered.push(format!("le=\"{}\"", le));
+    }
+    if rendered.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", rendered.join(","))
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn increment(&self, name: &str, labels: &[(&str, &str)], by: u64) {
+        self.update(name, labels, Value::Counter(0), |value| {
+            if let Value::Counter(count) = value {
+                *count += by;
+            }
+        });
+    }
+
+    fn set_gauge(&self, name: &str, labels: &[(&str, &str)], new: f64) {
+        self.update(name, labels, Value::Gauge(0.0), |value| {
+            if let Value::Gauge(gauge) = value {
+                *gauge = new;
+            }
+        });
+    }
+
+    fn observe(&self, name: &str, labels: &[(&str, &str)], observed: f64) {
+        let empty = Value::Histogram(vec![0; DEFAULT_BUCKETS.len()], 0.0, 0);
+        self.update(name, labels, empty, |value| {
+            if let Value::Histogram(buckets, sum, count) = value {
+                for (bound, bucket) in DEFAULT_BUCKETS.iter().zip(buckets.iter_mut()) {
+                    if observed <= *bound {
+                        *bucket += 1;
+                    }
+                }
+                *sum += observed;
+                *count += 1;
+            }
+        });
+    }
+}
+
+/// A backend reporting the latency of every call it makes, e.g. for the address
+/// discovery of a wallet restore
+pub struct MeasuredBackend<B> {
+    backend: B,
+    name: String,
+    metrics: Arc<dyn Metrics>,
+}
+
+impl<B> MeasuredBackend<B> {
+    /// Calls are labeled `backend=name`
+    pub fn new(backend: B, name: &str, metrics: Arc<dyn Metrics>) -> Self {
+        MeasuredBackend {
+            backend,
+            name: name.to_string(),
+            metrics,
+        }
+    }
+
+    pub fn into_inner(self) -> B {
+        self.backend
+    }
+}
+
+impl<B: ChainBackend> ChainBackend for MeasuredBackend<B> {
+    fn tip_height(&mut self, testnet: bool) -> Result<u32, Error> {
+        let backend = &mut self.backend;
+        time_request(&*self.metrics, &self.name, "tip_height", || {
+            backend.tip_height(testnet)
+        })
+    }
+
+    fn current_height(&mut self, testnet: bool) -> Result<u32, Error> {
+        let backend = &mut self.backend;
+        time_request(&*self.metrics, &self.name, "current_height", || {
+            backend.current_height(testnet)
+        })
+    }
+
+    fn block_hash(&mut self, height: u32, testnet: bool) -> Result<BlockHash, Error> {
+        let backend = &mut self.backend;
+        time_request(&*self.metrics, &self.name, "block_hash", || {
+            backend.block_hash(height, testnet)
+        })
+    }
+
+    fn tx_confirmation(
+        &mut self,
+        tx_id: TxHash,
+        testnet: bool,
+    ) -> Result<Option<(u32, BlockHash)>, Error> {
+        let backend = &mut self.backend;
+        time_request(&*self.metrics, &self.name, "tx_confirmation", || {
+            backend.tx_confirmation(tx_id, testnet)
+        })
+    }
+}
+
+impl<B: BlockBackend> BlockBackend for MeasuredBackend<B> {
+    fn block(&mut self, block_hash: BlockHash, testnet: bool) -> Result<Block, Error> {
+        let backend = &mut self.backend;
+        time_request(&*self.metrics, &self.name, "block", || {
+            backend.block(block_hash, testnet)
+        })
+    }
+}
+
+impl<B: ScriptBackend> ScriptBackend for MeasuredBackend<B> {
+    fn script_history(
+        &mut self,
+        script_pub_key: &ScriptPubKey,
+        testnet: bool,
+    ) -> Result<Vec<TxHash>, Error> {
+        let backend = &mut self.backend;
+        time_request(&*self.metrics, &self.name, "script_history", || {
+            backend.script_history(script_pub_key, testnet)
+        })
+    }
+
+    fn script_utxos(
+        &mut self,
+        script_pub_key: &ScriptPubKey,
+        testnet: bool,
+    ) -> Result<Vec<(OutPoint, u64, u32)>, Error> {
+        let backend = &mut self.backend;
+        time_request(&*self.metrics, &self.name, "script_utxos", || {
+            backend.script_utxos(script_pub_key, testnet)
+        })
+    }
+}
+
+impl<B: TxBackend> TxBackend for MeasuredBackend<B> {
+    fn fetch_many(&mut self, tx_ids: &[TxHash], testnet: bool) -> Result<Vec<Transaction>, Error> {
+        let backend = &mut self.backend;
+        time_request(&*self.metrics, &self.name, "fetch_many", || {
+            backend.fetch_many(tx_ids, testnet)
+        })
+    }
+}
+
+mod test {
+    use super::{
+        record_lookup, MeasuredBackend, Metrics, PrometheusMetrics, BACKEND_REQUEST_SECONDS,
+        HEADERS_SYNCED,
+    };
+    use crate::transaction::{OutPoint, ScriptBackend, ScriptPubKey, TxHash};
+    use failure::Error;
+    use std::sync::Arc;
+
+    struct EmptyBackend;
+
+    impl ScriptBackend for EmptyBackend {
+        fn script_history(&mut self, _: &ScriptPubKey, _: bool) -> Result<Vec<TxHash>, Error> {
+            Ok(vec![])
+        }
+
+        fn script_utxos(
+            &mut self,
+            _: &ScriptPubKey,
+            _: bool,
+        ) -> Result<Vec<(OutPoint, u64, u32)>, Error> {
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn test_prometheus() {
+        let metrics = Arc::new(PrometheusMetrics::new());
+        metrics.increment(HEADERS_SYNCED, &[], 2000);
+        metrics.increment(HEADERS_SYNCED, &[], 10);
+        metrics.set_gauge("bitcoin_peers", &[("network", "main")], 8.0);
+        record_lookup(&*metrics, "tx", true);
+        record_lookup(&*metrics, "tx", true);
+        record_lookup(&*metrics, "tx", false);
+        metrics.observe("latency", &[("backend", "a\"b")], 0.3);
+        metrics.observe("latency", &[("backend", "a\"b")], 20.0);
+
+        assert_eq!(metrics.counter(HEADERS_SYNCED, &[]), 2010);
+        assert_eq!(metrics.hit_rate("tx"), Some(2.0 / 3.0));
+        assert_eq!(metrics.hit_rate("header"), None);
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "# TYPE bitcoin_headers_synced_total counter\nbitcoin_headers_synced_total 2010\n"
+        ));
+        assert!(rendered.contains("bitcoin_peers{network=\"main\"} 8\n"));
+        assert!(rendered.contains("bitcoin_cache_lookups_total{cache=\"tx\",result=\"miss\"} 1\n"));
+        assert!(rendered.contains("latency_bucket{backend=\"a\\\"b\",le=\"0.25\"} 0\n"));
+        assert!(rendered.contains("latency_bucket{backend=\"a\\\"b\",le=\"0.5\"} 1\n"));
+        assert!(rendered.contains("latency_bucket{backend=\"a\\\"b\",le=\"+Inf\"} 2\n"));
+        assert!(rendered.contains("latency_sum{backend=\"a\\\"b\"} 20.3\n"));
+
+        let mut backend = MeasuredBackend::new(EmptyBackend, "electrum", metrics.clone());
+        let script_pub_key = ScriptPubKey {
+            content: vec![0x51],
+        };
+        backend.script_history(&script_pub_key, false).unwrap();
+        backend.script_history(&script_pub_key, false).unwrap();
+        assert_eq!(
+            metrics.observations(
+                BACKEND_REQUEST_SECONDS,
+                &[("method", "script_history"), ("backend", "electrum")]
+            ),
+            2
+        );
+    }
+}
@@ -0,0 +1,571 @@
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::transaction::{
+    PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxHash, TxInput, TxInputSequence, TxLocktime,
+    TxOutput, TxVersion, Varint, MAX_BLOCK_SIGOPS_COST,
+};
+use crate::wallet::{hash256, U256};
+
+/// Consensus limit on the weight of a block
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+/// Weight kept free for the coinbase while picking transactions, like Bitcoin Core
+pub const COINBASE_RESERVED_WEIGHT: usize = 4_000;
+/// Sigop cost kept free for the coinbase, like Bitcoin Core
+pub const COINBASE_RESERVED_SIGOPS_COST: usize = 400;
+/// Blocks between two halvings of the subsidy
+pub const HALVING_INTERVAL: u32 = 210_000;
+/// Header version with only the BIP9 top bits set
+pub const TEMPLATE_VERSION: u32 = 0x2000_0000;
+/// OP_RETURN, push 36 and the BIP141 commitment header
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// New coins a block at `height` may create, halving every `HALVING_INTERVAL` blocks
+pub fn block_subsidy(height: u32) -> u64 {
+    let halvings = height / HALVING_INTERVAL;
+    if halvings >= 64 {
+        return 0;
+    }
+    (50 * 100_000_000u64) >> halvings
+}
+
+/// Merkle root of hashes in wire byte order, an odd last hash pairs with itself
+pub fn merkle_root(hashes: &[[u8; 32]]) -> [u8; 32] {
+    if hashes.is_empty() {
+        return [0u8; 32];
+    }
+    let mut level = hashes.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let right = pair.get(1).unwrap_or(&pair[0]);
+                let mut root = [0u8; 32];
+                root.copy_from_slice(&hash256(&[&pair[0][..], &right[..]].concat()));
+                root
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// BIP141 commitment to the wtxids of `txs`, the coinbase counting as all zeros
+pub fn witness_commitment(txs: &[Transaction], reserved_value: &[u8; 32]) -> [u8; 32] {
+    let wtxids: Vec<[u8; 32]> = std::iter::once([0u8; 32])
+        .chain(txs.iter().map(|tx| {
+            let mut wtxid = [0u8; 32];
+            wtxid.copy_from_slice(&hash256(&tx.serialize()));
+            wtxid
+        }))
+        .collect();
+    let root = merkle_root(&wtxids);
+    let mut commitment = [0u8; 32];
+    commitment.copy_from_slice(&hash256(&[&root[..], &reserved_value[..]].concat()));
+    commitment
+}
+
+/// BIP34 height push: OP_0, OP_1 to OP_16 or the minimal little endian number
+fn height_push(height: u32) -> Vec<u8> {
+    match height {
+        0 => vec![0x00],
+        1..=16 => vec![0x50 + height as u8],
+        _ => {
+            let mut num = height.to_le_bytes().to_vec();
+            while num.len() > 1 && num[num.len() - 1] == 0 && num[num.len() - 2] & 0x80 == 0 {
+                num.pop();
+            }
+            if num[num.len() - 1] & 0x80 != 0 {
+                num.push(0);
+            }
+            [&[num.len() as u8][..], &num].concat()
+        }
+    }
+}
+
+/// Coinbase paying `value` to `payout`, its script sig starts with the BIP34 height followed
+/// by a push of `extra_nonce`. With a witness commitment the reserved value is all zeros.
+pub fn coinbase(
+    height: u32,
+    extra_nonce: &[u8],
+    payout: ScriptPubKey,
+    value: u64,
+    witness_commitment: Option<[u8; 32]>,
+) -> Transaction {
+    let mut script_sig = height_push(height);
+    if !extra_nonce.is_empty() {
+        script_sig.push(extra_nonce.len() as u8);
+        script_sig.extend_from_slice(extra_nonce);
+    }
+    // consensus wants at least 2 bytes
+    if script_sig.len() < 2 {
+        script_sig.push(0x00);
+    }
+
+    let mut input = TxInput::new(
+        TxHash::new(&[0u8; 32]).expect("32 bytes").1,
+        PreTxIndex::new(0xffff_ffff),
+        ScriptSig {
+            content: script_sig,
+        },
+        TxInputSequence::new(0xffff_ffff),
+    );
+    let mut outputs = vec![TxOutput {
+        amount: value.into(),
+        script_pub_key: payout,
+    }];
+    if let Some(commitment) = witness_commitment {
+        input.witness = vec![vec![0u8; 32]];
+        outputs.push(TxOutput {
+            amount: 0.into(),
+            script_pub_key: ScriptPubKey {
+                content: [&WITNESS_COMMITMENT_HEADER[..], &commitment[..]].concat(),
+            },
+        });
+    }
+    Transaction::new(
+        TxVersion::new(2),
+        vec![input],
+        outputs,
+        TxLocktime::new(0),
+        false,
+    )
+}
+
+/// Target a compact `bits` encodes, coefficient * 256^(exponent - 3)
+pub fn bits_to_target(bits: u32) -> U256 {
+    let exponent = bits >> 24;
+    let coefficient = U256::from(bits & 0x007f_ffff);
+    if exponent <= 3 {
+        coefficient >> (8 * (3 - exponent) as usize)
+    } else {
+        coefficient << (8 * (exponent - 3) as usize)
+    }
+}
+
+/// Compact encoding of `target`, rounded down to 3 bytes of precision
+pub fn target_to_bits(target: U256) -> u32 {
+    let mut size = (target.bits() as u32 + 7) / 8;
+    let mut coefficient = if size <= 3 {
+        target.low_u32() << (8 * (3 - size))
+    } else {
+        (target >> (8 * (size - 3) as usize)).low_u32()
+    };
+    // the top bit of the coefficient would read as a sign
+    if coefficient & 0x0080_0000 != 0 {
+        coefficient >>= 8;
+        size += 1;
+    }
+    coefficient | size << 24
+}
+
+/// Whether the header hash, read as a number, is at most `target`
+pub fn check_proof_of_work(header: &BlockHeader, target: U256) -> bool {
+    U256::from_big_endian(header.hash().as_ref()) <= target
+}
+
+/// Try every nonce from the header's on until the hash meets `target`, None once they run
+/// out and the timestamp or coinbase has to change. Only sensible on regtest.
+pub fn mine(mut header: BlockHeader, target: U256) -> Option<BlockHeader> {
+    loop {
+        if check_proof_of_work(&header, target) {
+            return Some(header);
+        }
+        header.nonce = header.nonce.checked_add(1)?;
+    }
+}
+
+/// A transaction waiting for a block with the fee it pays
+#[derive(Debug, PartialEq, Clone)]
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    pub fee: u64,
+    /// BIP141 sigop cost, only the legacy sigops unless the spent outputs were given
+    pub sigop_cost: usize,
+}
+
+impl MempoolEntry {
+    pub fn new(tx: Transaction, fee: u64) -> Self {
+        let sigop_cost = tx.total_sigops(&[]);
+        MempoolEntry {
+            tx,
+            fee,
+            sigop_cost,
+        }
+    }
+
+    /// Count the P2SH and witness sigops too, `prevouts` in input order
+    pub fn with_prevouts(mut self, prevouts: &[TxOutput]) -> Self {
+        self.sigop_cost = self.tx.total_sigops(prevouts);
+        self
+    }
+}
+
+/// Ancestors of `index` among the entries not yet picked, itself last and every parent
+/// before its children
+fn package(index: usize, parents: &[Vec<usize>], picked: &[bool], out: &mut Vec<usize>) {
+    if picked[index] || out.contains(&index) {
+        return;
+    }
+    for &parent in &parents[index] {
+        package(parent, parents, picked, out);
+    }
+    out.push(index);
+}
+
+/// Entries to mine in order within `max_weight` and the block sigop cost left after the
+/// coinbase. Like Bitcoin Core the package with the best fee rate goes first, a
+/// transaction with its unpicked in-mempool ancestors, so a high fee child pulls in its
+/// low fee parent.
+pub fn select_transactions(entries: Vec<MempoolEntry>, max_weight: usize) -> Vec<MempoolEntry> {
+    let ids: Vec<TxHash> = entries.iter().map(|entry| entry.tx.id()).collect();
+    let weights: Vec<usize> = entries.iter().map(|entry| entry.tx.weight()).collect();
+    let parents: Vec<Vec<usize>> = entries
+        .iter()
+        .map(|entry| {
+            let mut parents: Vec<usize> = entry
+                .tx
+                .inputs
+                .iter()
+                .filter_map(|input| ids.iter().position(|id| *id == input.pre_tx_id))
+                .collect();
+            parents.dedup();
+            parents
+        })
+        .collect();
+
+    let mut picked = vec![false; entries.len()];
+    let mut order = Vec::new();
+    let mut weight = 0;
+    let mut sigop_cost = 0;
+    let max_sigop_cost = MAX_BLOCK_SIGOPS_COST - COINBASE_RESERVED_SIGOPS_COST;
+    loop {
+        let mut best: Option<(Vec<usize>, u64, usize)> = None;
+        for index in 0..entries.len() {
+            if picked[index] {
+                continue;
+            }
+            let mut members = Vec::new();
+            package(index, &parents, &picked, &mut members);
+            let fee: u64 = members.iter().map(|&i| entries[i].fee).sum();
+            let package_weight: usize = members.iter().map(|&i| weights[i]).sum();
+            let package_sigop_cost: usize = members.iter().map(|&i| entries[i].sigop_cost).sum();
+            if weight + package_weight > max_weight
+                || sigop_cost + package_sigop_cost > max_sigop_cost
+            {
+                continue;
+            }
+            let better = match &best {
+                None => true,
+                Some((_, best_fee, best_weight)) => {
+                    u128::from(fee) * *best_weight as u128
+                        > u128::from(*best_fee) * package_weight as u128
+                }
+            };
+            if better {
+                best = Some((members, fee, package_weight));
+            }
+        }
+        match best {
+            Some((members, _, package_weight)) => {
+                weight += package_weight;
+                sigop_cost += members
+                    .iter()
+                    .map(|&i| entries[i].sigop_cost)
+                    .sum::<usize>();
+                for index in members {
+                    picked[index] = true;
+                    order.push(index);
+                }
+            }
+            None => break,
+        }
+    }
+
+    let mut entries: Vec<Option<MempoolEntry>> = entries.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .map(|index| entries[index].take().expect("picked once"))
+        .collect()
+}
+
+/// A block ready to mine: header with the merkle root filled in and a zero nonce, the
+/// coinbase and the picked transactions
+#[derive(Debug, PartialEq, Clone)]
+pub struct BlockTemplate {
+    pub header: BlockHeader,
+    pub height: u32,
+    pub coinbase: Transaction,
+    pub txs: Vec<Transaction>,
+    /// Fees of `txs`, claimed by the coinbase with the subsidy
+    pub fees: u64,
+}
+
+impl BlockTemplate {
+    /// Template on top of `prev_block` paying the subsidy and fees to `payout`
+    pub fn assemble(
+        prev_block: BlockHash,
+        height: u32,
+        bits: u32,
+        timestamp: u32,
+        payout: ScriptPubKey,
+        entries: Vec<MempoolEntry>,
+    ) -> Self {
+        let selected = select_transactions(
+            entries,
+            MAX_BLOCK_WEIGHT - 80 * 4 - COINBASE_RESERVED_WEIGHT,
+        );
+        let fees = selected.iter().map(|entry| entry.fee).sum();
+        let txs: Vec<Transaction> = selected.into_iter().map(|entry| entry.tx).collect();
+        let coinbase = coinbase(
+            height,
+            &[],
+            payout,
+            block_subsidy(height) + fees,
+            Some(witness_commitment(&txs, &[0u8; 32])),
+        );
+        let mut template = BlockTemplate {
+            header: BlockHeader {
+                version: TEMPLATE_VERSION,
+                prev_block,
+                merkle_root: [0u8; 32],
+                timestamp,
+                bits,
+                nonce: 0,
+            },
+            height,
+            coinbase,
+            txs,
+            fees,
+        };
+        template.header.merkle_root = template.merkle_root();
+        template
+    }
+
+    pub fn target(&self) -> U256 {
+        bits_to_target(self.header.bits)
+    }
+
+    /// Merkle root of the coinbase and transactions txids
+    pub fn merkle_root(&self) -> [u8; 32] {
+        let txids: Vec<[u8; 32]> = std::iter::once(&self.coinbase)
+            .chain(self.txs.iter())
+            .map(|tx| {
+                let mut txid = [0u8; 32];
+                txid.copy_from_slice(&tx.hash());
+                txid
+            })
+            .collect();
+        merkle_root(&txids)
+    }
+
+    /// Block weight with the header and transaction count
+    pub fn weight(&self) -> usize {
+        (80 + Varint::encoded_len(self.txs.len() as u64 + 1)) * 4
+            + self.coinbase.weight()
+            + self.txs.iter().map(|tx| tx.weight()).sum::<usize>()
+    }
+
+    /// Mine the header and put the block together, None if no nonce meets the target
+    pub fn mine(&self) -> Option<Block> {
+        let header = mine(self.header, self.target())?;
+        Some(self.to_block(header))
+    }
+
+    /// The block with `header`, coinbase first
+    pub fn to_block(&self, header: BlockHeader) -> Block {
+        Block {
+            header,
+            txs: std::iter::once(self.coinbase.clone())
+                .chain(self.txs.iter().cloned())
+                .collect(),
+        }
+    }
+}
+
+mod test {
+    use super::{
+        bits_to_target, block_subsidy, check_proof_of_work, coinbase, merkle_root,
+        select_transactions, target_to_bits, witness_commitment, BlockTemplate, MempoolEntry,
+    };
+    use crate::block::{Block, BlockHash};
+    use crate::transaction::{
+        PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxHash, TxInput, TxInputSequence,
+        TxLocktime, TxOutput, TxVersion,
+    };
+    use crate::wallet::U256;
+
+    fn spend(pre_tx_id: TxHash, amount: u64) -> Transaction {
+        let input = TxInput::new(
+            pre_tx_id,
+            PreTxIndex::new(0),
+            ScriptSig { content: vec![] },
+            TxInputSequence::new(0xffff_ffff),
+        );
+        let output = TxOutput {
+            amount: amount.into(),
+            script_pub_key: ScriptPubKey {
+                content: vec![0x51],
+            },
+        };
+        Transaction::new(
+            TxVersion::new(2),
+            vec![input],
+            vec![output],
+            TxLocktime::new(0),
+            false,
+        )
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let hashes: Vec<[u8; 32]> = [
+            "c117ea8ec828342f4dfb0ad6bd140e03a50720ece40169ee38bdc15d9eb64cf5",
+            "c131474164b412e3406696da1ee20ab0fc9bf41c8f05fa8ceea7a08d672d7cc5",
+            "f391da6ecfeed1814efae39e7fcb3838ae0b02c02ae7d0a5848a66947c0727b0",
+            "3d238a92a94532b946c90e19c49351c763696cff3db400485b813aecb8a13181",
+            "10092f2633be5f3ce349bf9ddbde36caa3dd10dfa0ec8106bce23acbff637dae",
+            "7d37b3d54fa6a64869084bfd2e831309118b9e833610e6228adacdbd1b4ba161",
+            "8118a77e542892fe15ae3fc771a4abfd2f5d5d5997544c3487ac36b5c85170fc",
+            "dff6879848c2c9b62fe652720b8df5272093acfaa45a43cdb3696fe2466a3877",
+            "b825c0745f46ac58f7d3759e6dc535a1fec7820377f24d4c2c6ad2cc55c0cb59",
+            "95513952a04bd8992721e9b7e2937f1c04ba31e0469fbe615a78197f68f52b7c",
+            "2e6d722e5e4dbdf2447ddecc9f7dabb8e299bae921c99ad5b0184cd9eb8e5908",
+            "b13a750047bc0bdceb2473e5fe488c2596d7a7124b4e716fdd29b046ef99bbf0",
+        ]
+        .iter()
+        .map(|h| {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(&hex::decode(h).unwrap());
+            hash
+        })
+        .collect();
+        assert_eq!(
+            hex::encode(merkle_root(&hashes)),
+            "acbcab8bcc1af95d8d563b77d24c3d19b18f1486383d75a5085c4e86c86beed6"
+        );
+
+        let genesis = hex!("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000");
+        let (_, block) = Block::parse(&genesis[..]).unwrap();
+        let mut txid = [0u8; 32];
+        txid.copy_from_slice(&block.txs[0].hash());
+        assert_eq!(merkle_root(&[txid]), block.header.merkle_root);
+        assert!(check_proof_of_work(
+            &block.header,
+            bits_to_target(block.header.bits)
+        ));
+        assert!(!check_proof_of_work(&block.header, U256::from(1u8)));
+    }
+
+    #[test]
+    fn test_bits_and_subsidy() {
+        assert_eq!(
+            bits_to_target(0x1d00_ffff),
+            U256::from(0xffffu32) << (8 * 26)
+        );
+        for bits in &[0x1d00_ffffu32, 0x1801_3ce9, 0x207f_ffff, 0x0312_3456] {
+            assert_eq!(target_to_bits(bits_to_target(*bits)), *bits);
+        }
+        // 0x80 would be a sign bit, the exponent grows instead
+        assert_eq!(target_to_bits(U256::from(0x80u8)), 0x0200_8000);
+
+        assert_eq!(block_subsidy(0), 5_000_000_000);
+        assert_eq!(block_subsidy(209_999), 5_000_000_000);
+        assert_eq!(block_subsidy(210_000), 2_500_000_000);
+        assert_eq!(block_subsidy(840_000), 312_500_000);
+        assert_eq!(block_subsidy(64 * 210_000), 0);
+    }
+
+    #[test]
+    fn test_coinbase() {
+        let payout = ScriptPubKey {
+            content: vec![0x51],
+        };
+        let tx = coinbase(1, &[], payout.clone(), 50, None);
+        assert!(tx.is_coinbase());
+        assert_eq!(tx.inputs[0].script_sig.content, vec![0x51, 0x00]);
+        let tx = coinbase(500_000, &[0xab], payout.clone(), 50, None);
+        assert_eq!(
+            tx.inputs[0].script_sig.content,
+            vec![0x03, 0x20, 0xa1, 0x07, 0x01, 0xab]
+        );
+        // 128 needs a zero byte so it does not read as negative
+        let tx = coinbase(128, &[], payout.clone(), 50, Some([7u8; 32]));
+        assert_eq!(tx.inputs[0].script_sig.content, vec![0x02, 0x80, 0x00]);
+        assert_eq!(tx.inputs[0].witness, vec![vec![0u8; 32]]);
+        assert_eq!(
+            hex::encode(&tx.outputs[1].script_pub_key.content[..6]),
+            "6a24aa21a9ed"
+        );
+    }
+
+    #[test]
+    fn test_select_transactions() {
+        let parent = spend(TxHash::new(&[1u8; 32]).unwrap().1, 1000);
+        let child = spend(parent.id(), 900);
+        let other = spend(TxHash::new(&[2u8; 32]).unwrap().1, 1000);
+        let weight = parent.weight();
+        let entries = vec![
+            MempoolEntry::new(child.clone(), 5000),
+            MempoolEntry::new(other.clone(), 2000),
+            MempoolEntry::new(parent.clone(), 100),
+        ];
+
+        // the child pays for its parent, 5100 over two beats 2000 over one
+        let picked: Vec<Transaction> = select_transactions(entries.clone(), weight * 3)
+            .into_iter()
+            .map(|entry| entry.tx)
+            .collect();
+        assert_eq!(picked, vec![parent.clone(), child.clone(), other.clone()]);
+
+        // room for one only, the child can not go alone
+        let picked: Vec<Transaction> = select_transactions(entries, weight)
+            .into_iter()
+            .map(|entry| entry.tx)
+            .collect();
+        assert_eq!(picked, vec![other.clone()]);
+
+        // a package over the block sigop cost left is skipped
+        let mut heavy = MempoolEntry::new(spend(TxHash::new(&[3u8; 32]).unwrap().1, 1000), 9000);
+        heavy.sigop_cost = 79_700;
+        let entries = vec![heavy, MempoolEntry::new(other.clone(), 2000)];
+        let picked = select_transactions(entries, weight * 3);
+        assert_eq!(picked.len(), 1);
+        assert_eq!(picked[0].tx, other);
+    }
+
+    #[test]
+    fn test_template_mine() {
+        let parent = spend(TxHash::new(&[1u8; 32]).unwrap().1, 1000);
+        let mut child = spend(parent.id(), 900);
+        child.inputs[0].witness = vec![vec![1u8]];
+        let payout = ScriptPubKey {
+            content: vec![0x51],
+        };
+        let template = BlockTemplate::assemble(
+            BlockHash::default(),
+            300,
+            0x207f_ffff,
+            1_700_000_000,
+            payout,
+            vec![
+                MempoolEntry::new(child.clone(), 300),
+                MempoolEntry::new(parent.clone(), 200),
+            ],
+        );
+        assert_eq!(template.fees, 500);
+        assert_eq!(template.txs, vec![parent, child]);
+        assert_eq!(
+            u64::from(template.coinbase.outputs[0].amount),
+            5_000_000_000 + 500
+        );
+        assert_eq!(
+            template.coinbase.outputs[1].script_pub_key.content[6..],
+            witness_commitment(&template.txs, &[0u8; 32])
+        );
+
+        let block = template.mine().unwrap();
+        assert!(check_proof_of_work(&block.header, template.target()));
+        assert_eq!(block.header.merkle_root, template.merkle_root());
+        assert_eq!(block.txs.len(), 3);
+        // witness bytes are discounted
+        assert!(template.weight() < block.serialize().len() * 4);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::blockfile::{MAINNET_MAGIC, REGTEST_MAGIC, SIGNET_MAGIC, TESTNET4_MAGIC, TESTNET_MAGIC};
+
+mod addrv2;
+mod compact_block;
+mod framing;
+mod message;
+mod misbehavior;
+mod node;
+mod services;
+
+pub use addrv2::{
+    AddrError, AddrNetwork, AddrV2, AddrV2Entry, AddrV2Message, AddressBook, MAX_ADDRV2_SIZE,
+    MAX_ADDR_TO_SEND,
+};
+pub use compact_block::{
+    BlockTxn, CompactBlock, CompactBlockError, GetBlockTxn, PartialBlock, PrefilledTx,
+};
+pub use framing::FrameDecoder;
+pub use message::{
+    max_payload_size, FeeFilterMessage, GetDataMessage, GetHeadersMessage, HeadersMessage,
+    InvMessage, Inventory, MempoolMessage, Message, MessageError, NetAddress, NetworkEnvelope,
+    NotFoundMessage, PingMessage, PongMessage, SendAddrV2Message, SendHeadersMessage,
+    VerAckMessage, VersionMessage, ADDRV2_VERSION, FEEFILTER_VERSION, MAX_HEADERS_RESULTS,
+    MSG_BLOCK, MSG_TX, MSG_WITNESS_FLAG, MSG_WITNESS_TX, PROTOCOL_VERSION, SENDHEADERS_VERSION,
+};
+pub use misbehavior::{BanList, Misbehavior, MisbehaviorPolicy, PeerScores, Verdict};
+pub use node::{NodeError, SimpleNode};
+pub use services::ServiceFlags;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum NetworkError {
+    #[fail(display = "a network named {} is already registered", _0)]
+    DuplicateName(String),
+    #[fail(display = "network magic {} is already registered", _0)]
+    DuplicateMagic(String),
+}
+
+/// Parameters telling one chain's addresses, keys and messages apart from another's
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    pub name: String,
+    /// First bytes of every p2p message and blk*.dat record
+    pub magic: [u8; 4],
+    pub default_port: u16,
+    /// Base58 version byte of P2PKH addresses
+    pub p2pkh_prefix: u8,
+    /// Base58 version byte of P2SH addresses
+    pub p2sh_prefix: u8,
+    /// Base58 version byte of WIF private keys
+    pub wif_prefix: u8,
+    /// Human readable part of segwit addresses
+    pub bech32_hrp: String,
+}
+
+impl Network {
+    pub fn mainnet() -> Self {
+        Network {
+            name: "main".to_string(),
+            magic: MAINNET_MAGIC,
+            default_port: 8333,
+            p2pkh_prefix: 0x00,
+            p2sh_prefix: 0x05,
+            wif_prefix: 0x80,
+            bech32_hrp: "bc".to_string(),
+        }
+    }
+
+    pub fn testnet() -> Self {
+        Network {
+            name: "test".to_string(),
+            magic: TESTNET_MAGIC,
+            default_port: 18333,
+            p2pkh_prefix: 0x6f,
+            p2sh_prefix: 0xc4,
+            wif_prefix: 0xef,
+            bech32_hrp: "tb".to_string(),
+        }
+    }
+
+    /// BIP94 testnet, addresses and keys look like testnet3 ones
+    pub fn testnet4() -> Self {
+        Network {
+            name: "testnet4".to_string(),
+            magic: TESTNET4_MAGIC,
+            default_port: 48333,
+            ..Network::testnet()
+        }
+    }
+
+    pub fn signet() -> Self {
+        Network {
+            name: "signet".to_string(),
+            magic: SIGNET_MAGIC,
+            default_port: 38333,
+            ..Network::testnet()
+        }
+    }
+
+    pub fn regtest() -> Self {
+        Network {
+            name: "regtest".to_string(),
+            magic: REGTEST_MAGIC,
+            default_port: 18444,
+            bech32_hrp: "bcrt".to_string(),
+            ..Network::testnet()
+        }
+    }
+
+    /// The network the `testnet` flags used across the crate stand for
+    pub fn from_testnet(testnet: bool) -> Self {
+        if testnet {
+            Network::testnet()
+        } else {
+            Network::mainnet()
+        }
+    }
+}
+
+/// Known networks, the built in ones and any registered at runtime
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkRegistry {
+    networks: Vec<Network>,
+}
+
+impl Default for NetworkRegistry {
+    fn default() -> Self {
+        NetworkRegistry {
+            networks: vec![
+                Network::mainnet(),
+                Network::testnet(),
+                Network::testnet4(),
+                Network::signet(),
+                Network::regtest(),
+            ],
+        }
+    }
+}
+
+impl NetworkRegistry {
+    /// Main, test, testnet4, signet and regtest
+    pub fn new() -> Self {
+        NetworkRegistry::default()
+    }
+
+    /// Add a custom network, its name and magic have to be new. Several networks may
+    /// share prefixes and an HRP, like the test networks do.
+    pub fn register(&mut self, network: Network) -> Result<(), NetworkError> {
+        if self.by_name(&network.name).is_some() {
+            return Err(NetworkError::DuplicateName(network.name));
+        }
+        if self.by_magic(network.magic).is_some() {
+            return Err(NetworkError::DuplicateMagic(hex::encode(network.magic)));
+        }
+        self.networks.push(network);
+        Ok(())
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Network> {
+        self.networks.iter().find(|network| network.name == name)
+    }
+
+    pub fn by_magic(&self, magic: [u8; 4]) -> Option<&Network> {
+        self.networks.iter().find(|network| network.magic == magic)
+    }
+
+    /// Every network whose segwit addresses use `hrp`
+    pub fn by_hrp<'a>(&'a self, hrp: &'a str) -> impl Iterator<Item = &'a Network> + 'a {
+        self.networks
+            .iter()
+            .filter(move |network| network.bech32_hrp == hrp)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Network> {
+        self.networks.iter()
+    }
+}
+
+mod test {
+    use super::{Network, NetworkError, NetworkRegistry};
+    use crate::transaction::ScriptPubKey;
+    use crate::wallet::bech32::decode_segwit_address;
+
+    #[test]
+    fn test_registry() {
+        let mut registry = NetworkRegistry::new();
+        assert_eq!(
+            registry.by_name("testnet4").unwrap().magic,
+            [0x1c, 0x16, 0x3f, 0x28]
+        );
+        assert_eq!(
+            registry.by_magic([0xfa, 0xbf, 0xb5, 0xda]),
+            Some(&Network::regtest())
+        );
+        assert_eq!(registry.by_hrp("tb").count(), 3);
+
+        let custom = Network {
+            name: "fork".to_string(),
+            magic: [0xde, 0xad, 0xbe, 0xef],
+            default_port: 9333,
+            p2pkh_prefix: 0x30,
+            p2sh_prefix: 0x32,
+            wif_prefix: 0xb0,
+            bech32_hrp: "fk".to_string(),
+        };
+        registry.register(custom.clone()).unwrap();
+        assert_eq!(registry.by_name("fork"), Some(&custom));
+        assert_eq!(
+            registry.register(custom.clone()),
+            Err(NetworkError::DuplicateName("fork".to_string()))
+        );
+        assert_eq!(
+            registry.register(Network {
+                name: "other".to_string(),
+                ..custom.clone()
+            }),
+            Err(NetworkError::DuplicateMagic("deadbeef".to_string()))
+        );
+
+        let p2wpkh = ScriptPubKey {
+            content: hex!("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1").to_vec(),
+        };
+        let address = p2wpkh.network_address(&custom).unwrap();
+        assert!(address.starts_with("fk1q"));
+        assert_eq!(
+            decode_segwit_address("fk", &address).unwrap(),
+            (0, hex!("1d0f172a0ecb48aee1be1f2687d2963ae33f71a1").to_vec())
+        );
+        let address = p2wpkh.network_address(&Network::regtest()).unwrap();
+        assert!(decode_segwit_address("bcrt", &address).is_ok());
+        let p2pkh = ScriptPubKey {
+            content: hex!("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").to_vec(),
+        };
+        assert_eq!(
+            p2pkh.network_address(&Network::testnet4()),
+            p2pkh.address(true)
+        );
+    }
+}
@@ -0,0 +1,660 @@
+use nom::bytes::complete::take;
+use nom::number::complete::{be_u16, le_u32, le_u8};
+use nom::IResult;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use super::message::Message;
+use super::ServiceFlags;
+use crate::encode::{decode_error, Decodable, Encodable};
+use crate::transaction::Varint;
+use crate::wallet::bech32::convert_bits;
+
+/// Longest address of any network, unknown ones included
+pub const MAX_ADDRV2_SIZE: usize = 512;
+/// Most addresses in one `addr` or `addrv2` message
+pub const MAX_ADDR_TO_SEND: usize = 1000;
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+const TORV3_VERSION: u8 = 3;
+/// `::ffff:0:0/96`, IPv4 addresses in IPv6 form
+const IPV4_MAPPED_PREFIX: [u8; 12] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff];
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum AddrError {
+    #[fail(display = "{} is not an IP, onion or i2p address", _0)]
+    Unparseable(String),
+    #[fail(display = "{} has a bad checksum or version", _0)]
+    InvalidOnion(String),
+}
+
+/// BIP155 network of an address
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum AddrNetwork {
+    Ipv4,
+    Ipv6,
+    TorV3,
+    I2p,
+    Cjdns,
+    /// Not understood here, Tor v2 included, relayed as is but never connected to
+    Unknown(u8),
+}
+impl Copy for AddrNetwork {}
+
+impl AddrNetwork {
+    /// BIP155 network id
+    pub fn id(self) -> u8 {
+        match self {
+            AddrNetwork::Ipv4 => 1,
+            AddrNetwork::Ipv6 => 2,
+            AddrNetwork::TorV3 => 4,
+            AddrNetwork::I2p => 5,
+            AddrNetwork::Cjdns => 6,
+            AddrNetwork::Unknown(id) => id,
+        }
+    }
+
+    /// Address length the network requires, None for unknown ones
+    pub fn address_len(self) -> Option<usize> {
+        match self {
+            AddrNetwork::Ipv4 => Some(4),
+            AddrNetwork::Ipv6 | AddrNetwork::Cjdns => Some(16),
+            AddrNetwork::TorV3 | AddrNetwork::I2p => Some(32),
+            AddrNetwork::Unknown(_) => None,
+        }
+    }
+}
+
+impl From<u8> for AddrNetwork {
+    fn from(id: u8) -> Self {
+        match id {
+            1 => AddrNetwork::Ipv4,
+            2 => AddrNetwork::Ipv6,
+            4 => AddrNetwork::TorV3,
+            5 => AddrNetwork::I2p,
+            6 => AddrNetwork::Cjdns,
+            id => AddrNetwork::Unknown(id),
+        }
+    }
+}
+
+/// A peer address of any BIP155 network
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum AddrV2 {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    /// ed25519 public key of the onion service
+    TorV3([u8; 32]),
+    /// SHA256 of the I2P destination
+    I2p([u8; 32]),
+    Cjdns(Ipv6Addr),
+    Unknown {
+        network: u8,
+        address: Vec<u8>,
+    },
+}
+
+impl AddrV2 {
+    pub fn network(&self) -> AddrNetwork {
+        match self {
+            AddrV2::Ipv4(_) => AddrNetwork::Ipv4,
+            AddrV2::Ipv6(_) => AddrNetwork::Ipv6,
+            AddrV2::TorV3(_) => AddrNetwork::TorV3,
+            AddrV2::I2p(_) => AddrNetwork::I2p,
+            AddrV2::Cjdns(_) => AddrNetwork::Cjdns,
+            AddrV2::Unknown { network, .. } => AddrNetwork::Unknown(*network),
+        }
+    }
+
+    /// The address bytes as BIP155 carries them
+    pub fn address(&self) -> Vec<u8> {
+        match self {
+            AddrV2::Ipv4(ip) => ip.octets().to_vec(),
+            AddrV2::Ipv6(ip) | AddrV2::Cjdns(ip) => ip.octets().to_vec(),
+            AddrV2::TorV3(key) | AddrV2::I2p(key) => key.to_vec(),
+            AddrV2::Unknown { address, .. } => address.clone(),
+        }
+    }
+
+    /// Build from a network id and address bytes, None when the length is wrong for
+    /// the network. IPv4 mapped into IPv6, `::ffff:0:0/96`, has its own network and is
+    /// refused as IPv6.
+    pub fn from_parts(network: u8, address: &[u8]) -> Option<Self> {
+        let network = AddrNetwork::from(network);
+        if let Some(len) = network.address_len() {
+            if address.len() != len {
+                return None;
+            }
+        }
+        let mut key = [0u8; 32];
+        let mut octets = [0u8; 16];
+        Some(match network {
+            AddrNetwork::Ipv4 => AddrV2::Ipv4(Ipv4Addr::new(
+                address[0], address[1], address[2], address[3],
+            )),
+            AddrNetwork::Ipv6 => {
+                octets.copy_from_slice(address);
+                if octets[..12] == IPV4_MAPPED_PREFIX {
+                    return None;
+                }
+                AddrV2::Ipv6(Ipv6Addr::from(octets))
+            }
+            AddrNetwork::Cjdns => {
+                octets.copy_from_slice(address);
+                AddrV2::Cjdns(Ipv6Addr::from(octets))
+            }
+            AddrNetwork::TorV3 => {
+                key.copy_from_slice(address);
+                AddrV2::TorV3(key)
+            }
+            AddrNetwork::I2p => {
+                key.copy_from_slice(address);
+                AddrV2::I2p(key)
+            }
+            AddrNetwork::Unknown(network) => AddrV2::Unknown {
+                network,
+                address: address.to_vec(),
+            },
+        })
+    }
+}
+
+/// The `addr` form, IPv4 mapped into IPv6
+impl From<Ipv6Addr> for AddrV2 {
+    fn from(ip: Ipv6Addr) -> Self {
+        match ip.octets() {
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => {
+                AddrV2::Ipv4(Ipv4Addr::new(a, b, c, d))
+            }
+            _ => AddrV2::Ipv6(ip),
+        }
+    }
+}
+
+impl Display for AddrV2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddrV2::Ipv4(ip) => write!(f, "{}", ip),
+            AddrV2::Ipv6(ip) | AddrV2::Cjdns(ip) => write!(f, "{}", ip),
+            AddrV2::TorV3(key) => {
+                let mut data = key.to_vec();
+                data.extend_from_slice(&onion_checksum(key));
+                data.push(TORV3_VERSION);
+                write!(f, "{}.onion", base32_encode(&data))
+            }
+            AddrV2::I2p(hash) => write!(f, "{}.b32.i2p", base32_encode(hash)),
+            AddrV2::Unknown { network, address } => {
+                write!(f, "unknown{}:{}", network, hex::encode(address))
+            }
+        }
+    }
+}
+
+/// IPv4, IPv6, `.onion` v3 and `.b32.i2p` addresses
+impl FromStr for AddrV2 {
+    type Err = AddrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unparseable = || AddrError::Unparseable(s.to_string());
+        if let Some(host) = s.strip_suffix(".onion") {
+            let data = base32_decode(host).ok_or_else(unparseable)?;
+            if data.len() != 35 {
+                return Err(unparseable());
+            }
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&data[..32]);
+            if data[32..34] != onion_checksum(&key) || data[34] != TORV3_VERSION {
+                return Err(AddrError::InvalidOnion(s.to_string()));
+            }
+            return Ok(AddrV2::TorV3(key));
+        }
+        if let Some(host) = s.strip_suffix(".b32.i2p") {
+            let data = base32_decode(host).ok_or_else(unparseable)?;
+            return AddrV2::from_parts(AddrNetwork::I2p.id(), &data).ok_or_else(unparseable);
+        }
+        if let Ok(ip) = s.parse::<Ipv4Addr>() {
+            return Ok(AddrV2::Ipv4(ip));
+        }
+        s.parse::<Ipv6Addr>()
+            .map(AddrV2::from)
+            .map_err(|_| unparseable())
+    }
+}
+
+/// Lowercase RFC 4648 base32 without padding, as onion and i2p host names use it
+fn base32_encode(data: &[u8]) -> String {
+    convert_bits(data, 8, 5, true)
+        .expect("8 bit bytes always convert")
+        .into_iter()
+        .map(|d| BASE32_ALPHABET[d as usize] as char)
+        .collect()
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let data = s
+        .bytes()
+        .map(|c| {
+            BASE32_ALPHABET
+                .iter()
+                .position(|&a| a == c.to_ascii_lowercase())
+                .map(|d| d as u8)
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    convert_bits(&data, 5, 8, false).ok()
+}
+
+/// First two bytes of SHA3-256(".onion checksum" || pubkey || version), rend-spec-v3
+fn onion_checksum(key: &[u8; 32]) -> [u8; 2] {
+    let mut data = b".onion checksum".to_vec();
+    data.extend_from_slice(key);
+    data.push(TORV3_VERSION);
+    let hash = sha3_256(&data);
+    [hash[0], hash[1]]
+}
+
+const KECCAK_ROUND_CONSTANTS: [u64; 24] = [
+    0x0000_0000_0000_0001,
+    0x0000_0000_0000_8082,
+    0x8000_0000_0000_808a,
+    0x8000_0000_8000_8000,
+    0x0000_0000_0000_808b,
+    0x0000_0000_8000_0001,
+    0x8000_0000_8000_8081,
+    0x8000_0000_0000_8009,
+    0x0000_0000_0000_008a,
+    0x0000_0000_0000_0088,
+    0x0000_0000_8000_8009,
+    0x0000_0000_8000_000a,
+    0x0000_0000_8000_808b,
+    0x8000_0000_0000_008b,
+    0x8000_0000_0000_8089,
+    0x8000_0000_0000_8003,
+    0x8000_0000_0000_8002,
+    0x8000_0000_0000_0080,
+    0x0000_0000_0000_800a,
+    0x8000_0000_8000_000a,
+    0x8000_0000_8000_8081,
+    0x8000_0000_0000_8080,
+    0x0000_0000_8000_0001,
+    0x8000_0000_8000_8008,
+];
+const KECCAK_ROTATIONS: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+const KECCAK_LANES: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+fn keccak_f(state: &mut [u64; 25]) {
+    for round_constant in KECCAK_ROUND_CONSTANTS.iter() {
+        // theta
+        let mut columns = [0u64; 5];
+        for (x, column) in columns.iter_mut().enumerate() {
+            *column = state[x] ^ state[x + 5] ^ state[x + 10] ^ state[x + 15] ^ state[x + 20];
+        }
+        for x in 0..5 {
+            let t = columns[(x + 4) % 5] ^ columns[(x + 1) % 5].rotate_left(1);
+            for y in 0..5 {
+                state[5 * y + x] ^= t;
+            }
+        }
+        // rho and pi
+        let mut last = state[1];
+        for (&lane, &rotation) in KECCAK_LANES.iter().zip(KECCAK_ROTATIONS.iter()) {
+            let next = state[lane];
+            state[lane] = last.rotate_left(rotation);
+            last = next;
+        }
+        // chi
+        for y in 0..5 {
+            let mut row = [0u64; 5];
+            row.copy_from_slice(&state[5 * y..5 * y + 5]);
+            for x in 0..5 {
+                state[5 * y + x] ^= !row[(x + 1) % 5] & row[(x + 2) % 5];
+            }
+        }
+        // iota
+        state[0] ^= round_constant;
+    }
+}
+
+/// FIPS 202 SHA3-256, only needed for onion checksums
+fn sha3_256(data: &[u8]) -> [u8; 32] {
+    const RATE: usize = 136;
+    let mut padded = data.to_vec();
+    padded.push(0x06);
+    padded.resize((padded.len() + RATE - 1) / RATE * RATE, 0);
+    *padded.last_mut().expect("at least one block") |= 0x80;
+
+    let mut state = [0u64; 25];
+    for block in padded.chunks(RATE) {
+        for (lane, bytes) in state.iter_mut().zip(block.chunks(8)) {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(bytes);
+            *lane ^= u64::from_le_bytes(word);
+        }
+        keccak_f(&mut state);
+    }
+    let mut hash = [0u8; 32];
+    for (bytes, lane) in hash.chunks_mut(8).zip(state.iter()) {
+        bytes.copy_from_slice(&lane.to_le_bytes());
+    }
+    hash
+}
+
+/// One entry of `addrv2`, a peer and when it was last seen
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AddrV2Entry {
+    /// Unix time the peer was last seen
+    pub time: u32,
+    pub services: ServiceFlags,
+    pub addr: AddrV2,
+    pub port: u16,
+}
+
+impl Encodable for AddrV2Entry {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        buf.extend_from_slice(&self.time.to_le_bytes());
+        Varint::from(self.services.bits()).consensus_encode(buf);
+        buf.push(self.addr.network().id());
+        let address = self.addr.address();
+        Varint::from(address.len() as u64).consensus_encode(buf);
+        buf.extend_from_slice(&address);
+        buf.extend_from_slice(&self.port.to_be_bytes());
+        buf.len() - start
+    }
+}
+
+/// An entry, None when its address is well formed but refused by `AddrV2::from_parts`
+fn decode_entry(input: &[u8]) -> IResult<&[u8], Option<AddrV2Entry>> {
+    let (input, time) = le_u32(input)?;
+    let (input, services) = Varint::consensus_decode(input)?;
+    let services = ServiceFlags::from_bits_truncate(services.into());
+    let (input, network) = le_u8(input)?;
+    let (rest, len) = Varint::consensus_decode(input)?;
+    let len = Into::<u64>::into(len) as usize;
+    if len > MAX_ADDRV2_SIZE {
+        return decode_error(input);
+    }
+    let (rest, address) = take(len)(rest)?;
+    if AddrNetwork::from(network)
+        .address_len()
+        .map_or(false, |expected| expected != len)
+    {
+        return decode_error(input);
+    }
+    let (rest, port) = be_u16(rest)?;
+    Ok((
+        rest,
+        AddrV2::from_parts(network, address).map(|addr| AddrV2Entry {
+            time,
+            services,
+            addr,
+            port,
+        }),
+    ))
+}
+
+impl Decodable for AddrV2Entry {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        match decode_entry(input)? {
+            (rest, Some(entry)) => Ok((rest, entry)),
+            (_, None) => decode_error(input),
+        }
+    }
+}
+
+/// BIP155 peer addresses of any network, sent instead of `addr` after `sendaddrv2`
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct AddrV2Message {
+    pub addrs: Vec<AddrV2Entry>,
+}
+
+impl Message for AddrV2Message {
+    const COMMAND: &'static str = "addrv2";
+}
+
+impl Encodable for AddrV2Message {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        self.addrs.consensus_encode(buf)
+    }
+}
+
+/// Entries with a refused address are dropped like Bitcoin Core does, the rest of the
+/// message still counts
+impl Decodable for AddrV2Message {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (mut rest, count) = Varint::consensus_decode(input)?;
+        let count = Into::<u64>::into(count) as usize;
+        if count > MAX_ADDR_TO_SEND {
+            return decode_error(input);
+        }
+        let mut addrs = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (next, entry) = decode_entry(rest)?;
+            addrs.extend(entry);
+            rest = next;
+        }
+        Ok((rest, AddrV2Message { addrs }))
+    }
+}
+
+/// Peers heard of, by address and port. Only addresses of networks we can reach are
+/// handed out for connecting, the others are still kept to relay.
+#[derive(Debug, Clone)]
+pub struct AddressBook {
+    entries: HashMap<(AddrV2, u16), AddrV2Entry>,
+    reachable: HashSet<AddrNetwork>,
+}
+
+impl Default for AddressBook {
+    fn default() -> Self {
+        AddressBook {
+            entries: HashMap::new(),
+            reachable: [AddrNetwork::Ipv4, AddrNetwork::Ipv6]
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+impl AddressBook {
+    /// IPv4 and IPv6 are reachable
+    pub fn new() -> Self {
+        AddressBook::default()
+    }
+
+    /// The networks we can connect to, e.g. add Tor v3 behind a Tor proxy
+    pub fn reachable(mut self, networks: &[AddrNetwork]) -> Self {
+        self.reachable = networks.iter().cloned().collect();
+        self
+    }
+
+    pub fn is_reachable(&self, addr: &AddrV2) -> bool {
+        self.reachable.contains(&addr.network())
+    }
+
+    /// Add or refresh a peer, an entry older than the known one changes nothing.
+    /// Returns whether the peer is new.
+    pub fn add(&mut self, entry: AddrV2Entry) -> bool {
+        let key = (entry.addr.clone(), entry.port);
+        match self.entries.get_mut(&key) {
+            Some(known) => {
+                if entry.time > known.time {
+                    *known = entry;
+                }
+                false
+            }
+            None => {
+                self.entries.insert(key, entry);
+                true
+            }
+        }
+    }
+
+    /// Add every address of a message, returns how many were new
+    pub fn add_message(&mut self, message: &AddrV2Message) -> usize {
+        message
+            .addrs
+            .iter()
+            .filter(|entry| self.add((*entry).clone()))
+            .count()
+    }
+
+    pub fn get(&self, addr: &AddrV2, port: u16) -> Option<&AddrV2Entry> {
+        self.entries.get(&(addr.clone(), port))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Every known peer, reachable or not
+    pub fn iter(&self) -> impl Iterator<Item = &AddrV2Entry> {
+        self.entries.values()
+    }
+
+    /// Peers we can connect to, most recently seen first
+    pub fn reachable_entries(&self) -> Vec<&AddrV2Entry> {
+        let mut entries: Vec<_> = self
+            .iter()
+            .filter(|entry| self.is_reachable(&entry.addr))
+            .collect();
+        entries.sort_by(|a, b| b.time.cmp(&a.time));
+        entries
+    }
+}
+
+mod test {
+    use super::{
+        sha3_256, AddrError, AddrNetwork, AddrV2, AddrV2Entry, AddrV2Message, AddressBook,
+    };
+    use crate::encode::{deserialize, serialize};
+    use crate::network::ServiceFlags;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_addrv2() {
+        assert_eq!(
+            sha3_256(b""),
+            hex!("a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a")
+        );
+        assert_eq!(
+            sha3_256(&[0xa3; 200]),
+            hex!("79f38adec5c20307a98ef76e8324afbfd46cfd81b22e3973c65fa1bd9de31787")
+        );
+
+        // Bitcoin Core's BIP155 vectors
+        let onion = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion";
+        let tor = AddrV2::TorV3(hex!(
+            "79bcc625184b05194975c28b66b66b0469f7f6556fb1ac3189a79b40dda32f1f"
+        ));
+        assert_eq!(onion.parse::<AddrV2>(), Ok(tor.clone()));
+        assert_eq!(tor.to_string(), onion);
+        let typo = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryc.onion";
+        assert_eq!(
+            typo.parse::<AddrV2>(),
+            Err(AddrError::InvalidOnion(typo.to_string()))
+        );
+        let i2p = "ukeu3k5oycgaauneqgtnvselmt4yemvoilkln7jpvamvfx7dnkdq.b32.i2p";
+        let garlic = AddrV2::I2p(hex!(
+            "a2894dabaec08c0051a481a6dac88b64f98232ae42d4b6fd2fa81952dfe36a87"
+        ));
+        assert_eq!(i2p.parse::<AddrV2>(), Ok(garlic.clone()));
+        assert_eq!(garlic.to_string(), i2p);
+        assert_eq!(
+            "::ffff:1.2.3.4".parse::<AddrV2>(),
+            Ok(AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)))
+        );
+
+        let entry = AddrV2Entry {
+            time: 0x5f5e_1000,
+            services: ServiceFlags::NETWORK | ServiceFlags::WITNESS,
+            addr: tor.clone(),
+            port: 8333,
+        };
+        let message = AddrV2Message {
+            addrs: vec![
+                entry.clone(),
+                AddrV2Entry {
+                    addr: AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)),
+                    ..entry.clone()
+                },
+                AddrV2Entry {
+                    addr: AddrV2::Unknown {
+                        network: 3,
+                        address: vec![0xf1; 10],
+                    },
+                    ..entry.clone()
+                },
+            ],
+        };
+        let raw = serialize(&message);
+        assert_eq!(raw[..14], hex!("0300105e5f09042079bcc625184b")[..]);
+        assert_eq!(deserialize::<AddrV2Message>(&raw), Some(message.clone()));
+        // a known network with the wrong length and IPv4 passed off as IPv6
+        assert_eq!(
+            deserialize::<AddrV2Entry>(&hex!("000000000001050102030405208d")),
+            None
+        );
+        assert_eq!(
+            deserialize::<AddrV2Entry>(&hex!("0000000000021000000000000000000000ffff01020304208d")),
+            None
+        );
+        // only the mapped prefix, IPv4 compatible addresses like :: and ::1 are IPv6
+        for ip in &[Ipv6Addr::UNSPECIFIED, Ipv6Addr::LOCALHOST] {
+            assert_eq!(
+                AddrV2::from_parts(AddrNetwork::Ipv6.id(), &ip.octets()),
+                Some(AddrV2::Ipv6(*ip))
+            );
+        }
+        // a refused entry is dropped, the message still decodes
+        let mut raw_mapped = vec![0x02];
+        raw_mapped.extend_from_slice(&hex!("0000000000021000000000000000000000ffff01020304208d"));
+        raw_mapped.extend_from_slice(&hex!("0000000000010401020304208d"));
+        assert_eq!(
+            deserialize::<AddrV2Message>(&raw_mapped)
+                .unwrap()
+                .addrs
+                .iter()
+                .map(|entry| entry.addr.clone())
+                .collect::<Vec<_>>(),
+            vec![AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4))]
+        );
+        assert_eq!(
+            deserialize::<AddrV2Entry>(&hex!("0000000000010401020304208d"))
+                .unwrap()
+                .addr,
+            AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4))
+        );
+
+        let mut book = AddressBook::new();
+        assert_eq!(book.add_message(&message), 3);
+        assert_eq!(book.add_message(&message), 0);
+        assert_eq!(
+            book.reachable_entries()
+                .iter()
+                .map(|entry| entry.addr.network())
+                .collect::<Vec<_>>(),
+            vec![AddrNetwork::Ipv4]
+        );
+        let newer = AddrV2Entry {
+            time: entry.time + 60,
+            ..entry.clone()
+        };
+        assert!(!book.add(newer.clone()));
+        assert_eq!(book.get(&tor, 8333), Some(&newer));
+        assert_eq!(book.len(), 3);
+
+        let book = book.reachable(&[AddrNetwork::TorV3, AddrNetwork::I2p]);
+        assert_eq!(book.reachable_entries(), vec![&newer]);
+    }
+}
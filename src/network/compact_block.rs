@@ -0,0 +1,543 @@
+use nom::number::complete::{le_u16, le_u32, le_u64};
+use nom::IResult;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::encode::{self, decode_error, Decodable, Encodable};
+use crate::mining::merkle_root;
+use crate::transaction::{Transaction, Varint};
+use crate::wallet::hash256;
+
+/// Short ids are the low 6 bytes of the SipHash
+const SHORT_ID_MASK: u64 = 0xffff_ffff_ffff;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum CompactBlockError {
+    #[fail(display = "transaction index {} is out of the block", _0)]
+    InvalidIndex(usize),
+    #[fail(display = "two transactions of the block share a short id")]
+    ShortIdCollision,
+    #[fail(display = "the transactions are for block {}", _0)]
+    WrongBlock(BlockHash),
+    #[fail(display = "{} transactions were sent for {} missing", got, missing)]
+    WrongTxCount { missing: usize, got: usize },
+    #[fail(display = "transactions are still missing")]
+    Incomplete,
+    #[fail(display = "the reconstructed block does not match its merkle root")]
+    MerkleRootMismatch,
+}
+
+/// SipHash-2-4 of `data` keyed with `k0` and `k1`
+fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        0x736f_6d65_7073_6575 ^ k0,
+        0x646f_7261_6e64_6f6d ^ k1,
+        0x6c79_6765_6e65_7261 ^ k0,
+        0x7465_6462_7974_6573 ^ k1,
+    ];
+    let round = |v: &mut [u64; 4]| {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    };
+    let compress = |v: &mut [u64; 4], m: u64| {
+        v[3] ^= m;
+        round(v);
+        round(v);
+        v[0] ^= m;
+    };
+
+    let chunks = data.chunks_exact(8);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(chunk);
+        compress(&mut v, u64::from_le_bytes(word));
+    }
+    let mut last = [0u8; 8];
+    last[..tail.len()].copy_from_slice(tail);
+    last[7] = data.len() as u8;
+    compress(&mut v, u64::from_le_bytes(last));
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+fn parse_varint(input: &[u8]) -> IResult<&[u8], u64> {
+    let (input, varint) = Varint::consensus_decode(input)?;
+    Ok((input, varint.into()))
+}
+
+/// Differentially encoded indexes, each one the gap to the previous index
+fn parse_indexes(input: &[u8]) -> IResult<&[u8], Vec<usize>> {
+    let (mut input, count) = parse_varint(input)?;
+    let mut indexes = Vec::new();
+    let mut next = 0u64;
+    for _ in 0..count {
+        let (rest, gap) = parse_varint(input)?;
+        next = match next.checked_add(gap) {
+            // indexes are u16 on the wire side of Bitcoin Core
+            Some(index) if index <= u64::from(u16::MAX) => index,
+            _ => return decode_error(input),
+        };
+        indexes.push(next as usize);
+        next += 1;
+        input = rest;
+    }
+    Ok((input, indexes))
+}
+
+fn encode_indexes(indexes: &[usize], buf: &mut Vec<u8>) -> usize {
+    let mut len = Varint::from(indexes.len() as u64).consensus_encode(buf);
+    let mut next = 0;
+    for index in indexes {
+        len += Varint::from((index - next) as u64).consensus_encode(buf);
+        next = index + 1;
+    }
+    len
+}
+
+/// A transaction sent along with the short ids, the coinbase at least
+#[derive(Debug, PartialEq, Clone)]
+pub struct PrefilledTx {
+    pub index: usize,
+    pub tx: Transaction,
+}
+
+/// BIP152 `cmpctblock`, a header with 6 byte short ids of its transactions
+#[derive(Debug, PartialEq, Clone)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    pub nonce: u64,
+    pub short_ids: Vec<u64>,
+    pub prefilled: Vec<PrefilledTx>,
+}
+
+impl CompactBlock {
+    /// Compact form of `block` with only its coinbase prefilled, short ids commit to wtxids
+    pub fn from_block(block: &Block, nonce: u64) -> Self {
+        let mut compact = CompactBlock {
+            header: block.header,
+            nonce,
+            short_ids: vec![],
+            prefilled: vec![],
+        };
+        for (index, tx) in block.txs.iter().enumerate() {
+            if index == 0 {
+                compact.prefilled.push(PrefilledTx {
+                    index,
+                    tx: tx.clone(),
+                });
+            } else {
+                compact.short_ids.push(compact.short_id(tx));
+            }
+        }
+        compact
+    }
+
+    /// SipHash keys, from the sha256 of the header and nonce
+    pub fn short_id_keys(&self) -> (u64, u64) {
+        let mut preimage = self.header.serialize();
+        preimage.extend_from_slice(&self.nonce.to_le_bytes());
+        let hash = Sha256::digest(&preimage);
+        let mut k0 = [0u8; 8];
+        let mut k1 = [0u8; 8];
+        k0.copy_from_slice(&hash[..8]);
+        k1.copy_from_slice(&hash[8..16]);
+        (u64::from_le_bytes(k0), u64::from_le_bytes(k1))
+    }
+
+    pub fn short_id(&self, tx: &Transaction) -> u64 {
+        let (k0, k1) = self.short_id_keys();
+        siphash24(k0, k1, &hash256(&tx.serialize())) & SHORT_ID_MASK
+    }
+
+    /// Transactions in the block, prefilled or not
+    pub fn tx_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled.len()
+    }
+
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, header) = BlockHeader::parse(input)?;
+        let (input, nonce) = le_u64(input)?;
+        let (mut input, count) = parse_varint(input)?;
+        let mut short_ids = Vec::new();
+        for _ in 0..count {
+            let (rest, low) = le_u32(input)?;
+            let (rest, high) = le_u16(rest)?;
+            short_ids.push(u64::from(low) | u64::from(high) << 32);
+            input = rest;
+        }
+        let (mut input, count) = parse_varint(input)?;
+        let mut prefilled = Vec::new();
+        let mut next = 0u64;
+        for _ in 0..count {
+            let (rest, gap) = parse_varint(input)?;
+            let (rest, tx) = Transaction::parse(rest)?;
+            next = match next.checked_add(gap) {
+                Some(index) if index <= u64::from(u16::MAX) => index,
+                _ => return decode_error(input),
+            };
+            prefilled.push(PrefilledTx {
+                index: next as usize,
+                tx,
+            });
+            next += 1;
+            input = rest;
+        }
+        Ok((
+            input,
+            CompactBlock {
+                header,
+                nonce,
+                short_ids,
+                prefilled,
+            },
+        ))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        encode::serialize(self)
+    }
+}
+
+impl Encodable for CompactBlock {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        self.header.consensus_encode(buf);
+        buf.extend_from_slice(&self.nonce.to_le_bytes());
+        Varint::from(self.short_ids.len() as u64).consensus_encode(buf);
+        for short_id in &self.short_ids {
+            buf.extend_from_slice(&short_id.to_le_bytes()[..6]);
+        }
+        Varint::from(self.prefilled.len() as u64).consensus_encode(buf);
+        let mut next = 0;
+        for prefilled in &self.prefilled {
+            Varint::from((prefilled.index - next) as u64).consensus_encode(buf);
+            prefilled.tx.consensus_encode(buf);
+            next = prefilled.index + 1;
+        }
+        buf.len() - start
+    }
+}
+
+impl Decodable for CompactBlock {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        CompactBlock::parse(input)
+    }
+}
+
+/// BIP152 `getblocktxn`, the transactions a compact block could not be rebuilt without
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GetBlockTxn {
+    pub block_hash: BlockHash,
+    pub indexes: Vec<usize>,
+}
+
+impl GetBlockTxn {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, block_hash) = BlockHash::parse(input)?;
+        let (input, indexes) = parse_indexes(input)?;
+        Ok((
+            input,
+            GetBlockTxn {
+                block_hash,
+                indexes,
+            },
+        ))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        encode::serialize(self)
+    }
+}
+
+impl Encodable for GetBlockTxn {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        buf.extend_from_slice(&self.block_hash.to_little_endian());
+        32 + encode_indexes(&self.indexes, buf)
+    }
+}
+
+impl Decodable for GetBlockTxn {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        GetBlockTxn::parse(input)
+    }
+}
+
+/// BIP152 `blocktxn`, the answer to a `getblocktxn` in the order it asked
+#[derive(Debug, PartialEq, Clone)]
+pub struct BlockTxn {
+    pub block_hash: BlockHash,
+    pub txs: Vec<Transaction>,
+}
+
+impl BlockTxn {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, block_hash) = BlockHash::parse(input)?;
+        let (input, txs) = Vec::<Transaction>::consensus_decode(input)?;
+        Ok((input, BlockTxn { block_hash, txs }))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        encode::serialize(self)
+    }
+}
+
+impl Encodable for BlockTxn {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        buf.extend_from_slice(&self.block_hash.to_little_endian());
+        32 + self.txs.consensus_encode(buf)
+    }
+}
+
+impl Decodable for BlockTxn {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        BlockTxn::parse(input)
+    }
+}
+
+/// A compact block being rebuilt, transactions come from the prefilled ones, the
+/// mempool and finally a `blocktxn` for the rest
+#[derive(Debug, Clone)]
+pub struct PartialBlock {
+    header: BlockHeader,
+    txs: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// Place the prefilled transactions and every mempool transaction matching a short
+    /// id. A short id two mempool transactions match is left for the peer to send.
+    pub fn new<'a, I>(compact: &CompactBlock, mempool: I) -> Result<Self, CompactBlockError>
+    where
+        I: IntoIterator<Item = &'a Transaction>,
+    {
+        let count = compact.tx_count();
+        let mut txs = vec![None; count];
+        for prefilled in &compact.prefilled {
+            match txs.get_mut(prefilled.index) {
+                Some(slot @ None) => *slot = Some(prefilled.tx.clone()),
+                _ => return Err(CompactBlockError::InvalidIndex(prefilled.index)),
+            }
+        }
+
+        // short ids fill the slots the prefilled transactions left, in order
+        let mut slots = HashMap::new();
+        let mut empty = (0..count).filter(|index| txs[*index].is_none());
+        for short_id in &compact.short_ids {
+            let index = empty.next().expect("as many empty slots as short ids");
+            if slots.insert(*short_id, index).is_some() {
+                return Err(CompactBlockError::ShortIdCollision);
+            }
+        }
+
+        let (k0, k1) = compact.short_id_keys();
+        let mut matched = vec![0usize; count];
+        for tx in mempool {
+            let short_id = siphash24(k0, k1, &hash256(&tx.serialize())) & SHORT_ID_MASK;
+            if let Some(index) = slots.get(&short_id) {
+                matched[*index] += 1;
+                txs[*index] = if matched[*index] == 1 {
+                    Some(tx.clone())
+                } else {
+                    None
+                };
+            }
+        }
+        Ok(PartialBlock {
+            header: compact.header,
+            txs,
+        })
+    }
+
+    /// Indexes of the transactions still missing
+    pub fn missing(&self) -> Vec<usize> {
+        (0..self.txs.len())
+            .filter(|index| self.txs[*index].is_none())
+            .collect()
+    }
+
+    pub fn get_block_txn(&self) -> GetBlockTxn {
+        GetBlockTxn {
+            block_hash: self.header.hash(),
+            indexes: self.missing(),
+        }
+    }
+
+    /// Fill the missing transactions from the answer to `get_block_txn`
+    pub fn fill(mut self, block_txn: BlockTxn) -> Result<Block, CompactBlockError> {
+        if block_txn.block_hash != self.header.hash() {
+            return Err(CompactBlockError::WrongBlock(block_txn.block_hash));
+        }
+        let missing = self.missing();
+        if missing.len() != block_txn.txs.len() {
+            return Err(CompactBlockError::WrongTxCount {
+                missing: missing.len(),
+                got: block_txn.txs.len(),
+            });
+        }
+        for (index, tx) in missing.into_iter().zip(block_txn.txs) {
+            self.txs[index] = Some(tx);
+        }
+        self.into_block()
+    }
+
+    /// The block once nothing is missing. A mempool transaction matching a short id by
+    /// chance fails the merkle root check, the full block has to be requested then.
+    pub fn into_block(self) -> Result<Block, CompactBlockError> {
+        let txs = self
+            .txs
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .ok_or(CompactBlockError::Incomplete)?;
+        let txids: Vec<[u8; 32]> = txs
+            .iter()
+            .map(|tx| {
+                let mut txid = [0u8; 32];
+                txid.copy_from_slice(&tx.hash());
+                txid
+            })
+            .collect();
+        if merkle_root(&txids) != self.header.merkle_root {
+            return Err(CompactBlockError::MerkleRootMismatch);
+        }
+        Ok(Block {
+            header: self.header,
+            txs,
+        })
+    }
+}
+
+mod test {
+    use super::{siphash24, BlockTxn, CompactBlock, CompactBlockError, GetBlockTxn, PartialBlock};
+    use crate::block::{Block, BlockHash, BlockHeader};
+    use crate::encode::deserialize;
+    use crate::mining::{coinbase, merkle_root};
+    use crate::transaction::{
+        PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxHash, TxInput, TxInputSequence,
+        TxLocktime, TxOutput, TxVersion,
+    };
+
+    fn spend(vout: u32) -> Transaction {
+        Transaction::new(
+            TxVersion::new(2),
+            vec![TxInput::new(
+                TxHash::new(&[3; 32]).unwrap().1,
+                PreTxIndex::new(vout),
+                ScriptSig { content: vec![] },
+                TxInputSequence::new(0xffff_ffff),
+            )],
+            vec![TxOutput {
+                amount: 1000.into(),
+                script_pub_key: ScriptPubKey {
+                    content: vec![0x51],
+                },
+            }],
+            TxLocktime::new(0),
+            false,
+        )
+    }
+
+    #[test]
+    fn test_compact_block() {
+        // reference SipHash-2-4 vectors, key 00..0f
+        let (k0, k1) = (0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908);
+        assert_eq!(siphash24(k0, k1, &[]), 0x726f_db47_dd0e_0e31);
+        assert_eq!(
+            siphash24(k0, k1, &[0, 1, 2, 3, 4, 5, 6, 7]),
+            0x93f5_f579_9a93_2462
+        );
+
+        let mut txs = vec![coinbase(
+            800_000,
+            &[1, 2, 3],
+            ScriptPubKey {
+                content: vec![0x51],
+            },
+            625_000_000,
+            None,
+        )];
+        txs.extend((0..5).map(spend));
+        let txids: Vec<[u8; 32]> = txs
+            .iter()
+            .map(|tx| {
+                let mut txid = [0u8; 32];
+                txid.copy_from_slice(&tx.hash());
+                txid
+            })
+            .collect();
+        let header = BlockHeader {
+            version: 0x2000_0000,
+            prev_block: BlockHash::default(),
+            merkle_root: merkle_root(&txids),
+            timestamp: 1_700_000_000,
+            bits: 0x207f_ffff,
+            nonce: 7,
+        };
+        let block = Block { header, txs };
+
+        let compact = CompactBlock::from_block(&block, 0x1122_3344_5566_7788);
+        assert_eq!(compact.short_ids.len(), 5);
+        assert!(compact.short_ids.iter().all(|id| *id < 1 << 48));
+        let bytes = compact.serialize();
+        assert_eq!(
+            bytes.len(),
+            80 + 8 + 1 + 5 * 6 + 1 + 1 + block.txs[0].serialize().len()
+        );
+        assert_eq!(deserialize::<CompactBlock>(&bytes), Some(compact.clone()));
+
+        // the mempool knows two of the five, plus one unrelated transaction
+        let mempool = vec![block.txs[2].clone(), spend(99), block.txs[4].clone()];
+        let partial = PartialBlock::new(&compact, &mempool).unwrap();
+        assert_eq!(partial.missing(), vec![1, 3, 5]);
+        let request = partial.get_block_txn();
+        assert_eq!(request.serialize()[32..], [3, 1, 1, 1]);
+        assert_eq!(
+            deserialize::<GetBlockTxn>(&request.serialize()),
+            Some(request.clone())
+        );
+
+        let answer = BlockTxn {
+            block_hash: block.hash(),
+            txs: request
+                .indexes
+                .iter()
+                .map(|index| block.txs[*index].clone())
+                .collect(),
+        };
+        assert_eq!(
+            deserialize::<BlockTxn>(&answer.serialize()),
+            Some(answer.clone())
+        );
+        assert_eq!(partial.clone().fill(answer.clone()).unwrap(), block);
+
+        let mut short = answer.clone();
+        short.txs.pop();
+        assert_eq!(
+            partial.clone().fill(short),
+            Err(CompactBlockError::WrongTxCount { missing: 3, got: 2 })
+        );
+        let mut wrong = answer;
+        wrong.txs[0] = spend(42);
+        assert_eq!(
+            partial.clone().fill(wrong),
+            Err(CompactBlockError::MerkleRootMismatch)
+        );
+        assert_eq!(partial.into_block(), Err(CompactBlockError::Incomplete));
+
+        // a full mempool rebuilds the block without a round trip
+        let partial = PartialBlock::new(&compact, &block.txs[1..]).unwrap();
+        assert_eq!(partial.into_block().unwrap(), block);
+    }
+}
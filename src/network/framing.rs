@@ -0,0 +1,158 @@
+use super::message::{max_payload_size, MessageError, NetworkEnvelope};
+
+/// Bytes of the magic, command, length and checksum
+const HEADER_SIZE: usize = 24;
+
+/// Splits a byte stream from a peer into envelopes. Bytes are pushed as they arrive and
+/// envelopes taken out once complete, so a peer trickling bytes can't block anything.
+/// Nothing is allocated for a payload before its length passed the limit of its command,
+/// and the buffer never holds more than one frame. A bad frame is dropped with an error
+/// and decoding carries on from the next network magic.
+#[derive(Debug, Clone)]
+pub struct FrameDecoder {
+    magic: [u8; 4],
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new(magic: [u8; 4]) -> Self {
+        FrameDecoder {
+            magic,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Bytes of an unfinished frame
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Drop the first `len` buffered bytes and whatever follows up to the next magic,
+    /// returns how many were dropped
+    fn resync(&mut self, len: usize) -> usize {
+        let next_magic = self.buffer[len..]
+            .windows(4)
+            .position(|window| window == self.magic)
+            .map(|at| len + at)
+            // a magic may be split over two pushes
+            .unwrap_or_else(|| len.max(self.buffer.len().saturating_sub(3)));
+        self.buffer.drain(..next_magic);
+        next_magic
+    }
+
+    /// The next complete envelope, None until enough bytes were pushed
+    pub fn next(&mut self) -> Result<Option<NetworkEnvelope>, MessageError> {
+        if self.buffer.len() >= 4 && self.buffer[..4] != self.magic {
+            let skipped = self.resync(0);
+            return Err(MessageError::Resynchronized(skipped));
+        }
+        if self.buffer.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let command = match NetworkEnvelope::parse_command(&self.buffer[4..16]) {
+            Ok(command) => command,
+            Err(e) => {
+                self.resync(4);
+                return Err(e);
+            }
+        };
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&self.buffer[16..20]);
+        let size = u32::from_le_bytes(length) as usize;
+        if size > max_payload_size(&command) {
+            // the length can't be trusted to skip the payload
+            self.resync(HEADER_SIZE);
+            return Err(MessageError::Oversized { command, size });
+        }
+        if self.buffer.len() < HEADER_SIZE + size {
+            return Ok(None);
+        }
+
+        let frame = HEADER_SIZE + size;
+        let valid =
+            self.buffer[20..24] == NetworkEnvelope::checksum(&self.buffer[HEADER_SIZE..frame]);
+        let envelope = NetworkEnvelope {
+            magic: self.magic,
+            command,
+            payload: if valid {
+                self.buffer[HEADER_SIZE..frame].to_vec()
+            } else {
+                vec![]
+            },
+        };
+        self.buffer.drain(..frame);
+        if valid {
+            Ok(Some(envelope))
+        } else {
+            Err(MessageError::BadChecksum(envelope.command))
+        }
+    }
+}
+
+mod test {
+    use super::FrameDecoder;
+    use crate::network::{MessageError, Misbehavior, NetworkEnvelope, PingMessage};
+
+    #[test]
+    fn test_frame_decoder() {
+        let magic = [0xfa, 0xbf, 0xb5, 0xda];
+        let ping = NetworkEnvelope::new(&PingMessage { nonce: 7 }, magic).serialize();
+        let mut decoder = FrameDecoder::new(magic);
+
+        // one byte at a time
+        for byte in &ping[..ping.len() - 1] {
+            decoder.push(&[*byte]);
+            assert_eq!(decoder.next(), Ok(None));
+        }
+        decoder.push(&ping[ping.len() - 1..]);
+        let envelope = decoder.next().unwrap().unwrap();
+        assert_eq!(envelope.message(), Ok(PingMessage { nonce: 7 }));
+        assert_eq!(decoder.buffered(), 0);
+
+        // garbage before a frame
+        decoder.push(&[1, 2, 3, 0xfa, 5]);
+        decoder.push(&ping);
+        assert_eq!(decoder.next(), Err(MessageError::Resynchronized(5)));
+        assert!(decoder.next().unwrap().is_some());
+
+        // a corrupt checksum drops the frame only
+        let mut corrupt = ping.clone();
+        corrupt[20] ^= 0xff;
+        decoder.push(&corrupt);
+        decoder.push(&ping);
+        let err = decoder.next().unwrap_err();
+        assert_eq!(err, MessageError::BadChecksum("ping".to_string()));
+        assert_eq!(err.misbehavior(), Some(Misbehavior::BadChecksum));
+        assert!(decoder.next().unwrap().is_some());
+
+        // a length over the limit of the command fails before the payload arrives
+        let mut oversized = ping[..24].to_vec();
+        oversized[16..20].copy_from_slice(&4_000_000u32.to_le_bytes());
+        decoder.push(&oversized);
+        decoder.push(&ping);
+        assert_eq!(
+            decoder.next(),
+            Err(MessageError::Oversized {
+                command: "ping".to_string(),
+                size: 4_000_000
+            })
+        );
+        assert!(decoder.next().unwrap().is_some());
+
+        let mut bad_command = ping.clone();
+        bad_command[9] = 0x01;
+        decoder.push(&bad_command);
+        assert_eq!(decoder.next(), Err(MessageError::InvalidCommand));
+        assert_eq!(decoder.buffered(), 3);
+
+        let mut huge = ping[..24].to_vec();
+        huge[4..16].copy_from_slice(b"block\0\0\0\0\0\0\0");
+        huge[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(NetworkEnvelope::read_from(&mut &huge[..], magic).is_err());
+    }
+}
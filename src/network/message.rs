@@ -0,0 +1,855 @@
+use nom::bytes::complete::take;
+use nom::number::complete::{be_u16, le_u32, le_u64, le_u8};
+use nom::IResult;
+use std::io::Read;
+use std::net::Ipv6Addr;
+
+use super::{Misbehavior, ServiceFlags};
+use crate::block::{BlockHash, BlockHeader};
+use crate::encode::{self, decode_error, Decodable, Encodable};
+use crate::transaction::{Transaction, TxHash, Varint};
+use crate::wallet::hash256;
+
+/// Protocol version this crate speaks, with BIP130 `sendheaders`
+pub const PROTOCOL_VERSION: u32 = 70016;
+/// Oldest peer version still spoken to, the one of Bitcoin Core
+pub const MIN_PEER_PROTO_VERSION: u32 = 31800;
+/// First version peers may be asked for header announcements
+pub const SENDHEADERS_VERSION: u32 = 70012;
+/// First version understanding `feefilter`, BIP133
+pub const FEEFILTER_VERSION: u32 = 70013;
+/// First version that may send `sendaddrv2`, BIP155
+pub const ADDRV2_VERSION: u32 = 70016;
+/// Longest user agent a peer may send, BIP14
+pub const MAX_USER_AGENT_LENGTH: usize = 256;
+/// Headers in a full `headers` answer, fewer means the peer has no more
+pub const MAX_HEADERS_RESULTS: usize = 2000;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum MessageError {
+    #[fail(display = "message for network magic {}", _0)]
+    WrongMagic(String),
+    #[fail(display = "{} payload does not match its checksum", _0)]
+    BadChecksum(String),
+    #[fail(display = "{} payload does not parse", _0)]
+    Unparseable(String),
+    #[fail(display = "expected {}, got {}", expected, got)]
+    UnexpectedCommand { expected: String, got: String },
+    #[fail(display = "{} payload of {} bytes is over its limit", command, size)]
+    Oversized { command: String, size: usize },
+    #[fail(display = "command is not NUL padded ASCII")]
+    InvalidCommand,
+    #[fail(display = "skipped {} bytes to the next network magic", _0)]
+    Resynchronized(usize),
+}
+
+impl MessageError {
+    /// What the peer is blamed for, None for a message that was valid but unwanted
+    pub fn misbehavior(&self) -> Option<Misbehavior> {
+        match self {
+            MessageError::BadChecksum(_) => Some(Misbehavior::BadChecksum),
+            MessageError::Oversized { .. } => Some(Misbehavior::OversizedMessage),
+            MessageError::WrongMagic(_)
+            | MessageError::Unparseable(_)
+            | MessageError::InvalidCommand
+            | MessageError::Resynchronized(_) => Some(Misbehavior::UnparseableMessage),
+            MessageError::UnexpectedCommand { .. } => None,
+        }
+    }
+}
+
+/// Largest payload of any message, a block
+pub const MAX_PAYLOAD_SIZE: usize = 4_000_000;
+
+/// Largest payload a well behaved peer sends for `command`, checked before anything is
+/// allocated for it
+pub fn max_payload_size(command: &str) -> usize {
+    match command {
+        "verack" | "sendheaders" | "sendaddrv2" | "getaddr" | "mempool" => 0,
+        "ping" | "pong" | "feefilter" => 8,
+        "version" => 1024,
+        // 101 locator hashes like Bitcoin Core
+        "getheaders" | "getblocks" => 4 + 1 + 101 * 32 + 32,
+        "headers" => 3 + MAX_HEADERS_RESULTS * 81,
+        "inv" | "getdata" | "notfound" => 5 + 50_000 * 36,
+        "addr" => 3 + 1000 * 30,
+        // BIP155 addresses are up to 512 bytes
+        "addrv2" => 3 + 1000 * (4 + 9 + 1 + 3 + 512 + 2),
+        _ => MAX_PAYLOAD_SIZE,
+    }
+}
+
+/// A p2p message payload, the consensus encoding of its fields
+pub trait Message: Encodable + Decodable {
+    const COMMAND: &'static str;
+}
+
+/// Magic, command, length and checksum around every p2p payload
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct NetworkEnvelope {
+    pub magic: [u8; 4],
+    pub command: String,
+    pub payload: Vec<u8>,
+}
+
+impl NetworkEnvelope {
+    pub fn new<M: Message>(message: &M, magic: [u8; 4]) -> Self {
+        NetworkEnvelope {
+            magic,
+            command: M::COMMAND.to_string(),
+            payload: encode::serialize(message),
+        }
+    }
+
+    pub(crate) fn checksum(payload: &[u8]) -> [u8; 4] {
+        let mut checksum = [0u8; 4];
+        checksum.copy_from_slice(&hash256(payload)[..4]);
+        checksum
+    }
+
+    /// Command field, NUL padded to 12 bytes
+    fn command_bytes(command: &str) -> [u8; 12] {
+        let mut bytes = [0u8; 12];
+        bytes[..command.len()].copy_from_slice(command.as_bytes());
+        bytes
+    }
+
+    /// Printable ASCII then only NULs
+    pub(crate) fn parse_command(bytes: &[u8]) -> Result<String, MessageError> {
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        let (command, padding) = bytes.split_at(end);
+        if !command.iter().all(|b| (0x20..0x7f).contains(b)) || padding.iter().any(|b| *b != 0) {
+            return Err(MessageError::InvalidCommand);
+        }
+        Ok(String::from_utf8_lossy(command).to_string())
+    }
+
+    /// An envelope whose payload matches its checksum
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, magic) = take(4usize)(input)?;
+        let (input, command) = take(12usize)(input)?;
+        let (input, length) = le_u32(input)?;
+        let (input, checksum) = take(4usize)(input)?;
+        let command = match Self::parse_command(command) {
+            Ok(command) if length as usize <= max_payload_size(&command) => command,
+            _ => return decode_error(input),
+        };
+        let (rest, payload) = take(length as usize)(input)?;
+        if checksum != Self::checksum(payload) {
+            return decode_error(input);
+        }
+        let mut envelope_magic = [0u8; 4];
+        envelope_magic.copy_from_slice(magic);
+        Ok((
+            rest,
+            NetworkEnvelope {
+                magic: envelope_magic,
+                command,
+                payload: payload.to_vec(),
+            },
+        ))
+    }
+
+    /// Read the next envelope of the network with `magic` off `reader`, the payload
+    /// length is checked against the command's limit before it is allocated
+    pub fn read_from<R: Read>(reader: &mut R, magic: [u8; 4]) -> Result<Self, failure::Error> {
+        let mut header = [0u8; 24];
+        reader.read_exact(&mut header)?;
+        if header[..4] != magic {
+            return Err(MessageError::WrongMagic(hex::encode(&header[..4])).into());
+        }
+        let command = Self::parse_command(&header[4..16])?;
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&header[16..20]);
+        let size = u32::from_le_bytes(length) as usize;
+        if size > max_payload_size(&command) {
+            return Err(MessageError::Oversized { command, size }.into());
+        }
+        let mut payload = vec![0u8; size];
+        reader.read_exact(&mut payload)?;
+        if header[20..24] != Self::checksum(&payload) {
+            return Err(MessageError::BadChecksum(command).into());
+        }
+        Ok(NetworkEnvelope {
+            magic,
+            command,
+            payload,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(24 + self.payload.len());
+        buf.extend_from_slice(&self.magic);
+        buf.extend_from_slice(&Self::command_bytes(&self.command));
+        buf.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&Self::checksum(&self.payload));
+        buf.extend_from_slice(&self.payload);
+        buf
+    }
+
+    /// The payload as `M`, which has to be this envelope's command
+    pub fn message<M: Message>(&self) -> Result<M, MessageError> {
+        if self.command != M::COMMAND {
+            return Err(MessageError::UnexpectedCommand {
+                expected: M::COMMAND.to_string(),
+                got: self.command.clone(),
+            });
+        }
+        encode::deserialize(&self.payload)
+            .ok_or_else(|| MessageError::Unparseable(self.command.clone()))
+    }
+}
+
+/// A peer address as `version` carries it, IPv4 mapped into IPv6
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct NetAddress {
+    pub services: ServiceFlags,
+    pub ip: Ipv6Addr,
+    pub port: u16,
+}
+impl Copy for NetAddress {}
+
+impl Default for NetAddress {
+    fn default() -> Self {
+        NetAddress {
+            services: ServiceFlags::empty(),
+            ip: Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0, 0),
+            port: 8333,
+        }
+    }
+}
+
+impl Encodable for NetAddress {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        buf.extend_from_slice(&self.services.bits().to_le_bytes());
+        buf.extend_from_slice(&self.ip.octets());
+        buf.extend_from_slice(&self.port.to_be_bytes());
+        26
+    }
+}
+
+impl Decodable for NetAddress {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, services) = le_u64(input)?;
+        let services = ServiceFlags::from_bits_truncate(services);
+        let (input, ip) = take(16usize)(input)?;
+        let (input, port) = be_u16(input)?;
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(ip);
+        Ok((
+            input,
+            NetAddress {
+                services,
+                ip: Ipv6Addr::from(octets),
+                port,
+            },
+        ))
+    }
+}
+
+fn parse_var_bytes(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    let (input, len) = Varint::consensus_decode(input)?;
+    take(Into::<u64>::into(len) as usize)(input)
+}
+
+/// Opens every connection, each side tells the other what it is and what it has
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VersionMessage {
+    pub version: u32,
+    pub services: ServiceFlags,
+    pub timestamp: u64,
+    pub receiver: NetAddress,
+    pub sender: NetAddress,
+    pub nonce: u64,
+    pub user_agent: String,
+    pub latest_block: u32,
+    /// Whether the peer wants transactions announced before any BIP37 filter is set
+    pub relay: bool,
+}
+
+impl Default for VersionMessage {
+    fn default() -> Self {
+        VersionMessage {
+            version: PROTOCOL_VERSION,
+            services: ServiceFlags::empty(),
+            timestamp: 0,
+            receiver: NetAddress::default(),
+            sender: NetAddress::default(),
+            nonce: 0,
+            user_agent: "/programmingbitcoin:0.1/".to_string(),
+            latest_block: 0,
+            relay: false,
+        }
+    }
+}
+
+impl VersionMessage {
+    /// A version with the current time and a random nonce, for connections to real peers
+    pub fn new(latest_block: u32) -> Self {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        VersionMessage {
+            timestamp,
+            nonce: rand::random(),
+            latest_block,
+            ..VersionMessage::default()
+        }
+    }
+
+    /// BIP14 user agent, e.g. `/mywallet:1.2/programmingbitcoin:0.1/`
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Height of our best block
+    pub fn start_height(mut self, height: u32) -> Self {
+        self.latest_block = height;
+        self
+    }
+
+    /// Whether the peer should announce transactions to us
+    pub fn relay(mut self, relay: bool) -> Self {
+        self.relay = relay;
+        self
+    }
+
+    /// Random per connection, a peer sending back our own nonce is ourselves
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    pub fn services(mut self, services: ServiceFlags) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Address of the peer as we see it
+    pub fn receiver(mut self, receiver: NetAddress) -> Self {
+        self.receiver = receiver;
+        self
+    }
+}
+
+impl Message for VersionMessage {
+    const COMMAND: &'static str = "version";
+}
+
+impl Encodable for VersionMessage {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(&self.services.bits().to_le_bytes());
+        buf.extend_from_slice(&self.timestamp.to_le_bytes());
+        self.receiver.consensus_encode(buf);
+        self.sender.consensus_encode(buf);
+        buf.extend_from_slice(&self.nonce.to_le_bytes());
+        Varint::from(self.user_agent.len() as u64).consensus_encode(buf);
+        buf.extend_from_slice(self.user_agent.as_bytes());
+        buf.extend_from_slice(&self.latest_block.to_le_bytes());
+        buf.push(self.relay as u8);
+        buf.len() - start
+    }
+}
+
+impl Decodable for VersionMessage {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, version) = le_u32(input)?;
+        let (input, services) = le_u64(input)?;
+        let services = ServiceFlags::from_bits_truncate(services);
+        let (input, timestamp) = le_u64(input)?;
+        let (input, receiver) = NetAddress::consensus_decode(input)?;
+        let (input, sender) = NetAddress::consensus_decode(input)?;
+        let (input, nonce) = le_u64(input)?;
+        let (input, user_agent) = parse_var_bytes(input)?;
+        if user_agent.len() > MAX_USER_AGENT_LENGTH {
+            return decode_error(input);
+        }
+        let (input, latest_block) = le_u32(input)?;
+        // relay was added in 70001, older peers stop before it
+        let (input, relay) = if input.is_empty() {
+            (input, 1)
+        } else {
+            le_u8(input)?
+        };
+        Ok((
+            input,
+            VersionMessage {
+                version,
+                services,
+                timestamp,
+                receiver,
+                sender,
+                nonce,
+                user_agent: String::from_utf8_lossy(user_agent).to_string(),
+                latest_block,
+                relay: relay != 0,
+            },
+        ))
+    }
+}
+
+/// A message without payload
+macro_rules! empty_message {
+    ($name:ident, $command:expr) => {
+        #[derive(Debug, PartialEq, Eq, Clone, Default)]
+        pub struct $name;
+        impl Copy for $name {}
+
+        impl Message for $name {
+            const COMMAND: &'static str = $command;
+        }
+
+        impl Encodable for $name {
+            fn consensus_encode(&self, _buf: &mut Vec<u8>) -> usize {
+                0
+            }
+        }
+
+        impl Decodable for $name {
+            fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+                Ok((input, $name))
+            }
+        }
+    };
+}
+
+empty_message!(VerAckMessage, "verack");
+// BIP130, announce new blocks with `headers` rather than `inv`
+empty_message!(SendHeadersMessage, "sendheaders");
+// BIP35, announce the whole mempool with `inv`
+empty_message!(MempoolMessage, "mempool");
+// BIP155, gossip addresses with `addrv2`, only valid before `verack`
+empty_message!(SendAddrV2Message, "sendaddrv2");
+
+/// A message carrying only a nonce
+macro_rules! nonce_message {
+    ($name:ident, $command:expr) => {
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub struct $name {
+            pub nonce: u64,
+        }
+        impl Copy for $name {}
+
+        impl Message for $name {
+            const COMMAND: &'static str = $command;
+        }
+
+        impl Encodable for $name {
+            fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+                buf.extend_from_slice(&self.nonce.to_le_bytes());
+                8
+            }
+        }
+
+        impl Decodable for $name {
+            fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+                let (input, nonce) = le_u64(input)?;
+                Ok((input, $name { nonce }))
+            }
+        }
+    };
+}
+
+nonce_message!(PingMessage, "ping");
+nonce_message!(PongMessage, "pong");
+
+/// BIP133, don't announce transactions paying less than `fee_rate`
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct FeeFilterMessage {
+    /// sat per 1000 vbytes
+    pub fee_rate: u64,
+}
+impl Copy for FeeFilterMessage {}
+
+impl FeeFilterMessage {
+    /// Whether a transaction paying `fee` for `vsize` vbytes gets past the filter
+    pub fn accepts(&self, fee: u64, vsize: usize) -> bool {
+        fee.saturating_mul(1000) >= self.fee_rate.saturating_mul(vsize as u64)
+    }
+}
+
+impl Message for FeeFilterMessage {
+    const COMMAND: &'static str = "feefilter";
+}
+
+impl Encodable for FeeFilterMessage {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        buf.extend_from_slice(&self.fee_rate.to_le_bytes());
+        8
+    }
+}
+
+impl Decodable for FeeFilterMessage {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, fee_rate) = le_u64(input)?;
+        Ok((input, FeeFilterMessage { fee_rate }))
+    }
+}
+
+impl Message for Transaction {
+    const COMMAND: &'static str = "tx";
+}
+
+/// Headers after the first locator hash the peer knows, up to `stop` or 2000
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GetHeadersMessage {
+    pub version: u32,
+    pub locator: Vec<BlockHash>,
+    /// All zeros for as many as the peer sends
+    pub stop: BlockHash,
+}
+
+impl GetHeadersMessage {
+    pub fn new(locator: Vec<BlockHash>) -> Self {
+        GetHeadersMessage {
+            version: PROTOCOL_VERSION,
+            locator,
+            stop: BlockHash::default(),
+        }
+    }
+}
+
+impl Message for GetHeadersMessage {
+    const COMMAND: &'static str = "getheaders";
+}
+
+impl Encodable for GetHeadersMessage {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        Varint::from(self.locator.len() as u64).consensus_encode(buf);
+        for hash in &self.locator {
+            buf.extend_from_slice(&hash.to_little_endian());
+        }
+        buf.extend_from_slice(&self.stop.to_little_endian());
+        buf.len() - start
+    }
+}
+
+impl Decodable for GetHeadersMessage {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, version) = le_u32(input)?;
+        let (mut input, count) = Varint::consensus_decode(input)?;
+        let mut locator = Vec::new();
+        for _ in 0..Into::<u64>::into(count) {
+            let (rest, hash) = BlockHash::parse(input)?;
+            locator.push(hash);
+            input = rest;
+        }
+        let (input, stop) = BlockHash::parse(input)?;
+        Ok((
+            input,
+            GetHeadersMessage {
+                version,
+                locator,
+                stop,
+            },
+        ))
+    }
+}
+
+/// Headers answering `getheaders` or announcing new blocks, each followed by an empty
+/// transaction count
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct HeadersMessage {
+    pub headers: Vec<BlockHeader>,
+}
+
+impl Message for HeadersMessage {
+    const COMMAND: &'static str = "headers";
+}
+
+impl Encodable for HeadersMessage {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        Varint::from(self.headers.len() as u64).consensus_encode(buf);
+        for header in &self.headers {
+            header.consensus_encode(buf);
+            buf.push(0);
+        }
+        buf.len() - start
+    }
+}
+
+impl Decodable for HeadersMessage {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (mut input, count) = Varint::consensus_decode(input)?;
+        let mut headers = Vec::new();
+        for _ in 0..Into::<u64>::into(count) {
+            let (rest, header) = BlockHeader::parse(input)?;
+            let (rest, tx_count) = le_u8(rest)?;
+            if tx_count != 0 {
+                return decode_error(rest);
+            }
+            headers.push(header);
+            input = rest;
+        }
+        Ok((input, HeadersMessage { headers }))
+    }
+}
+
+/// `inv` type of a block
+pub const MSG_BLOCK: u32 = 2;
+/// `inv` type of a transaction
+pub const MSG_TX: u32 = 1;
+/// Set on `getdata` kinds asking for witness data, BIP144
+pub const MSG_WITNESS_FLAG: u32 = 1 << 30;
+pub const MSG_WITNESS_TX: u32 = MSG_TX | MSG_WITNESS_FLAG;
+
+/// One announced or requested object
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Inventory {
+    pub kind: u32,
+    /// In wire byte order
+    pub hash: [u8; 32],
+}
+impl Copy for Inventory {}
+
+impl Inventory {
+    pub fn tx(tx_id: &TxHash) -> Self {
+        Inventory::new(MSG_TX, tx_id)
+    }
+
+    /// The transaction with its witness data
+    pub fn witness_tx(tx_id: &TxHash) -> Self {
+        Inventory::new(MSG_WITNESS_TX, tx_id)
+    }
+
+    fn new(kind: u32, tx_id: &TxHash) -> Self {
+        let mut hash = [0; 32];
+        hash.copy_from_slice(&tx_id.to_little_endian());
+        Inventory { kind, hash }
+    }
+
+    /// Whether the item is a transaction, with or without witness
+    pub fn is_tx(&self) -> bool {
+        self.kind & !MSG_WITNESS_FLAG == MSG_TX
+    }
+
+    /// Id of a transaction item
+    pub fn tx_id(&self) -> TxHash {
+        TxHash::parse(&self.hash).expect("hash is 32 bytes").1
+    }
+}
+
+/// A list of inventory items
+macro_rules! inventory_message {
+    ($name:ident, $command:expr) => {
+        #[derive(Debug, PartialEq, Eq, Clone, Default)]
+        pub struct $name {
+            pub items: Vec<Inventory>,
+        }
+
+        impl Message for $name {
+            const COMMAND: &'static str = $command;
+        }
+
+        impl Encodable for $name {
+            fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+                let start = buf.len();
+                Varint::from(self.items.len() as u64).consensus_encode(buf);
+                for item in &self.items {
+                    buf.extend_from_slice(&item.kind.to_le_bytes());
+                    buf.extend_from_slice(&item.hash);
+                }
+                buf.len() - start
+            }
+        }
+
+        impl Decodable for $name {
+            fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+                let (mut input, count) = Varint::consensus_decode(input)?;
+                let mut items = Vec::new();
+                for _ in 0..Into::<u64>::into(count) {
+                    let (rest, kind) = le_u32(input)?;
+                    let (rest, hash) = take(32usize)(rest)?;
+                    let mut item = Inventory {
+                        kind,
+                        hash: [0; 32],
+                    };
+                    item.hash.copy_from_slice(hash);
+                    items.push(item);
+                    input = rest;
+                }
+                Ok((input, $name { items }))
+            }
+        }
+    };
+}
+
+// objects a peer has, blocks are announced this way unless `sendheaders` was asked for
+inventory_message!(InvMessage, "inv");
+// objects asked for, each comes as its own `tx` or `block`
+inventory_message!(GetDataMessage, "getdata");
+// requested objects the peer doesn't have
+inventory_message!(NotFoundMessage, "notfound");
+
+mod test {
+    use super::{
+        FeeFilterMessage, GetDataMessage, GetHeadersMessage, HeadersMessage, Inventory,
+        MessageError, NetworkEnvelope, VerAckMessage, VersionMessage,
+    };
+    use crate::block::BlockHash;
+    use crate::encode::{deserialize, serialize};
+    use crate::transaction::TxHash;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_messages() {
+        let raw = hex!("f9beb4d976657261636b000000000000000000005df6e0e2");
+        let (_, envelope) = NetworkEnvelope::parse(&raw).unwrap();
+        assert_eq!(envelope.command, "verack");
+        assert_eq!(envelope.message::<VerAckMessage>(), Ok(VerAckMessage));
+        assert_eq!(envelope.serialize(), raw.to_vec());
+        assert_eq!(
+            NetworkEnvelope::read_from(&mut &raw[..], [0xf9, 0xbe, 0xb4, 0xd9]).unwrap(),
+            envelope
+        );
+        assert_eq!(
+            envelope.message::<VersionMessage>(),
+            Err(MessageError::UnexpectedCommand {
+                expected: "version".to_string(),
+                got: "verack".to_string()
+            })
+        );
+        let mut corrupt = raw;
+        corrupt[23] ^= 1;
+        assert!(NetworkEnvelope::parse(&corrupt).is_err());
+        let err =
+            NetworkEnvelope::read_from(&mut &corrupt[..], [0xf9, 0xbe, 0xb4, 0xd9]).unwrap_err();
+        assert_eq!(
+            err.downcast::<MessageError>().unwrap(),
+            MessageError::BadChecksum("verack".to_string())
+        );
+
+        let version = VersionMessage {
+            version: 70015,
+            ..VersionMessage::default()
+        };
+        let raw = hex!("7f11010000000000000000000000000000000000000000000000000000000000000000000000ffff00000000208d000000000000000000000000000000000000ffff00000000208d0000000000000000182f70726f6772616d6d696e67626974636f696e3a302e312f0000000000");
+        assert_eq!(serialize(&version), raw.to_vec());
+        assert_eq!(deserialize::<VersionMessage>(&raw), Some(version.clone()));
+        let long = version.user_agent(&"a".repeat(257));
+        assert_eq!(deserialize::<VersionMessage>(&serialize(&long)), None);
+
+        let start =
+            BlockHash::from_str("0000000000000000001237f46acddf58578a37e213d2a6edc4884a2fcad05ba3")
+                .unwrap();
+        let get_headers = GetHeadersMessage {
+            version: 70015,
+            ..GetHeadersMessage::new(vec![start])
+        };
+        assert_eq!(serialize(&get_headers), hex!("7f11010001a35bd0ca2f4a88c4eda6d213e2378a5758dfcd6af437120000000000000000000000000000000000000000000000000000000000000000000000000000000000").to_vec());
+
+        let raw = hex!("0200000020df3b053dc46f162a9b00c7f0d5124e2676d47bbe7c5d0793a500000000000000ef445fef2ed495c275892206ca533e7411907971013ab83e3b47bd0d692d14d4dc7c835b67d8001ac157e670000000002030eb2540c41025690160a1014c577061596e32e426b712c7ca00000000000000768b89f07044e6130ead292a3f51951adbd2202df447d98789339937fd006bd44880835b67d8001ade09204600");
+        let headers = deserialize::<HeadersMessage>(&raw).unwrap();
+        assert_eq!(headers.headers.len(), 2);
+        assert_eq!(headers.headers[1].prev_block, headers.headers[0].hash());
+        assert_eq!(serialize(&headers), raw.to_vec());
+
+        let filter = FeeFilterMessage { fee_rate: 1000 };
+        assert_eq!(serialize(&filter), hex!("e803000000000000").to_vec());
+        assert!(filter.accepts(226, 226));
+        assert!(!filter.accepts(225, 226));
+
+        let tx_id =
+            TxHash::from_str("452c629d67e41baec3ac6f04fe744b4b9617f8f859c63b3002f8684e7a4fee03")
+                .unwrap();
+        let get_data = GetDataMessage {
+            items: vec![Inventory::witness_tx(&tx_id)],
+        };
+        let raw =
+            hex!("010100004003ee4f7a4e68f802303bc659f8f817964b4b74fe046facc3ae1be4679d622c45");
+        assert_eq!(serialize(&get_data), raw.to_vec());
+        let item = deserialize::<GetDataMessage>(&raw).unwrap().items[0];
+        assert!(item.is_tx());
+        assert_eq!(item.tx_id(), tx_id);
+    }
+
+    #[test]
+    fn test_snapshots() {
+        use super::{InvMessage, SendAddrV2Message, MSG_BLOCK};
+        use crate::network::{AddrV2, AddrV2Entry, AddrV2Message, Network, ServiceFlags};
+        use crate::testkit::Snapshot;
+
+        let magic = Network::mainnet().magic;
+        let version = VersionMessage {
+            timestamp: 1_700_000_000,
+            ..VersionMessage::new(840_000)
+        }
+        .services(ServiceFlags::NETWORK | ServiceFlags::WITNESS)
+        .nonce(0x0102_0304_0506_0708)
+        .relay(true);
+        let genesis =
+            BlockHash::from_str("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f")
+                .unwrap();
+        let tx_id =
+            TxHash::from_str("452c629d67e41baec3ac6f04fe744b4b9617f8f859c63b3002f8684e7a4fee03")
+                .unwrap();
+        let addrv2 = AddrV2Message {
+            addrs: vec![AddrV2Entry {
+                time: 1_700_000_000,
+                services: ServiceFlags::NETWORK,
+                addr: "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion"
+                    .parse::<AddrV2>()
+                    .unwrap(),
+                port: 8333,
+            }],
+        };
+
+        let mut snapshot = Snapshot::new("messages");
+        snapshot
+            .hex(
+                "version",
+                &NetworkEnvelope::new(&version, magic).serialize(),
+            )
+            .hex(
+                "verack",
+                &NetworkEnvelope::new(&VerAckMessage, magic).serialize(),
+            )
+            .hex(
+                "sendaddrv2",
+                &NetworkEnvelope::new(&SendAddrV2Message, magic).serialize(),
+            )
+            .hex(
+                "getheaders",
+                &NetworkEnvelope::new(&GetHeadersMessage::new(vec![genesis]), magic).serialize(),
+            )
+            .hex(
+                "inv",
+                &NetworkEnvelope::new(
+                    &InvMessage {
+                        items: vec![
+                            Inventory::tx(&tx_id),
+                            Inventory {
+                                kind: MSG_BLOCK,
+                                hash: [0xab; 32],
+                            },
+                        ],
+                    },
+                    magic,
+                )
+                .serialize(),
+            )
+            .hex(
+                "getdata",
+                &NetworkEnvelope::new(
+                    &GetDataMessage {
+                        items: vec![Inventory::witness_tx(&tx_id)],
+                    },
+                    magic,
+                )
+                .serialize(),
+            )
+            .hex(
+                "feefilter",
+                &NetworkEnvelope::new(&FeeFilterMessage { fee_rate: 1000 }, magic).serialize(),
+            )
+            .hex("addrv2", &NetworkEnvelope::new(&addrv2, magic).serialize());
+        snapshot.check();
+    }
+}
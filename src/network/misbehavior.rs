@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// A protocol violation a peer is blamed for
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum Misbehavior {
+    /// The payload does not hash to the envelope checksum
+    BadChecksum,
+    /// A known command whose payload does not parse
+    UnparseableMessage,
+    /// Blocks, transactions or headers nobody asked for
+    UnrequestedData,
+    /// Headers that don't connect or lack their proof of work
+    InvalidHeaders,
+    /// A payload over the limit of its command
+    OversizedMessage,
+}
+impl Copy for Misbehavior {}
+
+/// How much each violation costs a peer and what happens once it crossed the threshold.
+/// The defaults ban for a day at 100 points like Bitcoin Core, so a single invalid header
+/// is enough while a few stray messages are tolerated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MisbehaviorPolicy {
+    pub bad_checksum: u32,
+    pub unparseable_message: u32,
+    pub unrequested_data: u32,
+    pub invalid_headers: u32,
+    pub oversized_message: u32,
+    pub ban_threshold: u32,
+    pub ban_seconds: u64,
+}
+
+impl Default for MisbehaviorPolicy {
+    fn default() -> Self {
+        MisbehaviorPolicy {
+            bad_checksum: 20,
+            unparseable_message: 50,
+            unrequested_data: 10,
+            invalid_headers: 100,
+            oversized_message: 100,
+            ban_threshold: 100,
+            ban_seconds: 24 * 60 * 60,
+        }
+    }
+}
+
+impl MisbehaviorPolicy {
+    pub fn score(&self, misbehavior: Misbehavior) -> u32 {
+        match misbehavior {
+            Misbehavior::BadChecksum => self.bad_checksum,
+            Misbehavior::UnparseableMessage => self.unparseable_message,
+            Misbehavior::UnrequestedData => self.unrequested_data,
+            Misbehavior::InvalidHeaders => self.invalid_headers,
+            Misbehavior::OversizedMessage => self.oversized_message,
+        }
+    }
+}
+
+/// What to do with a peer after a violation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Keep,
+    /// Disconnect, the address is banned until the given unix time
+    Ban {
+        until: u64,
+    },
+}
+impl Copy for Verdict {}
+
+/// Addresses refused until a unix time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BanList {
+    bans: HashMap<IpAddr, u64>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        BanList::default()
+    }
+
+    /// Ban `addr` until `until`, an earlier ban is only ever extended
+    pub fn ban(&mut self, addr: IpAddr, until: u64) {
+        let entry = self.bans.entry(addr).or_insert(until);
+        *entry = (*entry).max(until);
+    }
+
+    pub fn unban(&mut self, addr: &IpAddr) -> bool {
+        self.bans.remove(addr).is_some()
+    }
+
+    pub fn is_banned(&self, addr: &IpAddr, now: u64) -> bool {
+        self.bans.get(addr).map_or(false, |until| now < *until)
+    }
+
+    /// Forget the bans over at `now`, returns how many
+    pub fn expire(&mut self, now: u64) -> usize {
+        let before = self.bans.len();
+        self.bans.retain(|_, until| now < *until);
+        before - self.bans.len()
+    }
+
+    /// Banned addresses and the end of their ban, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&IpAddr, &u64)> {
+        self.bans.iter()
+    }
+}
+
+/// Misbehavior scores of connected peers, banning those crossing the policy threshold
+#[derive(Debug, Clone, Default)]
+pub struct PeerScores {
+    policy: MisbehaviorPolicy,
+    scores: HashMap<IpAddr, u32>,
+    bans: BanList,
+}
+
+impl PeerScores {
+    pub fn new(policy: MisbehaviorPolicy) -> Self {
+        PeerScores {
+            policy,
+            ..PeerScores::default()
+        }
+    }
+
+    /// Keep the bans of an earlier session
+    pub fn bans(mut self, bans: BanList) -> Self {
+        self.bans = bans;
+        self
+    }
+
+    pub fn policy(&self) -> &MisbehaviorPolicy {
+        &self.policy
+    }
+
+    pub fn ban_list(&self) -> &BanList {
+        &self.bans
+    }
+
+    pub fn ban_list_mut(&mut self) -> &mut BanList {
+        &mut self.bans
+    }
+
+    pub fn score(&self, addr: &IpAddr) -> u32 {
+        self.scores.get(addr).cloned().unwrap_or(0)
+    }
+
+    /// Blame `addr` for `misbehavior`, once its score reaches the threshold it is banned
+    /// and its score forgotten
+    pub fn report(&mut self, addr: IpAddr, misbehavior: Misbehavior, now: u64) -> Verdict {
+        let score = self.scores.entry(addr).or_insert(0);
+        *score = score.saturating_add(self.policy.score(misbehavior));
+        if *score < self.policy.ban_threshold {
+            return Verdict::Keep;
+        }
+        self.scores.remove(&addr);
+        let until = now + self.policy.ban_seconds;
+        self.bans.ban(addr, until);
+        Verdict::Ban { until }
+    }
+
+    /// Whether to accept a connection from or make one to `addr`
+    pub fn may_connect(&self, addr: &IpAddr, now: u64) -> bool {
+        !self.bans.is_banned(addr, now)
+    }
+
+    /// Scores last as long as the connection
+    pub fn disconnected(&mut self, addr: &IpAddr) {
+        self.scores.remove(addr);
+    }
+}
+
+mod test {
+    use super::{BanList, Misbehavior, MisbehaviorPolicy, PeerScores, Verdict};
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_misbehavior() {
+        let peer: IpAddr = "203.0.113.7".parse().unwrap();
+        let other: IpAddr = "2001:db8::1".parse().unwrap();
+        let mut scores = PeerScores::new(MisbehaviorPolicy::default());
+
+        for _ in 0..4 {
+            assert_eq!(
+                scores.report(peer, Misbehavior::BadChecksum, 1000),
+                Verdict::Keep
+            );
+        }
+        assert_eq!(scores.score(&peer), 80);
+        assert_eq!(
+            scores.report(peer, Misbehavior::UnrequestedData, 1000),
+            Verdict::Keep
+        );
+        assert_eq!(
+            scores.report(peer, Misbehavior::UnrequestedData, 1000),
+            Verdict::Ban {
+                until: 1000 + 86_400
+            }
+        );
+        assert_eq!(scores.score(&peer), 0);
+        assert!(!scores.may_connect(&peer, 2000));
+        assert!(scores.may_connect(&peer, 1000 + 86_400));
+
+        scores.report(other, Misbehavior::UnparseableMessage, 1000);
+        scores.disconnected(&other);
+        assert_eq!(scores.score(&other), 0);
+
+        // a stricter policy bans at the first bad checksum, bans carry over
+        let strict = MisbehaviorPolicy {
+            bad_checksum: 100,
+            ban_seconds: 60,
+            ..MisbehaviorPolicy::default()
+        };
+        let mut scores = PeerScores::new(strict).bans(scores.ban_list().clone());
+        assert_eq!(
+            scores.report(other, Misbehavior::BadChecksum, 5000),
+            Verdict::Ban { until: 5060 }
+        );
+        assert!(!scores.may_connect(&peer, 5000));
+
+        let bans: &mut BanList = scores.ban_list_mut();
+        assert_eq!(bans.expire(5000), 0);
+        assert_eq!(bans.expire(90_000), 2);
+        bans.ban(peer, 10);
+        assert!(bans.unban(&peer));
+        assert_eq!(bans.iter().count(), 0);
+    }
+}
@@ -0,0 +1,739 @@
+use failure::Error;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::framing::FrameDecoder;
+use super::message::{
+    FeeFilterMessage, GetDataMessage, GetHeadersMessage, HeadersMessage, InvMessage, Inventory,
+    MempoolMessage, Message, NetworkEnvelope, PingMessage, PongMessage, SendAddrV2Message,
+    SendHeadersMessage, VerAckMessage, VersionMessage, ADDRV2_VERSION, FEEFILTER_VERSION,
+    MAX_HEADERS_RESULTS, MIN_PEER_PROTO_VERSION, MSG_BLOCK, SENDHEADERS_VERSION,
+};
+use super::{Network, ServiceFlags};
+use crate::block::BlockHeader;
+use crate::cancel::CancelToken;
+use crate::headers::{ChainEvent, HeaderChain, HeaderError};
+use crate::metrics::{self, Metrics};
+use crate::transaction::Transaction;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum NodeError {
+    #[fail(display = "peer protocol version {} is too old", _0)]
+    VersionTooLow(u32),
+    #[fail(display = "peer lacks services {:?}", _0)]
+    MissingServices(ServiceFlags),
+    #[fail(display = "connected to ourselves")]
+    SelfConnection,
+}
+
+/// A connection to one peer speaking the p2p protocol of `network`
+pub struct SimpleNode<S: Read + Write> {
+    stream: S,
+    magic: [u8; 4],
+    decoder: FrameDecoder,
+    /// Services a peer must have for the handshake to succeed
+    required_services: ServiceFlags,
+    /// Lower of our and the peer's version once the handshake is done
+    version: Option<u32>,
+    /// What the peer told about itself in the handshake
+    peer: Option<VersionMessage>,
+    /// Asked the peer to announce blocks with `headers`
+    sent_sendheaders: bool,
+    /// The peer asked for addresses as `addrv2`
+    peer_addrv2: bool,
+    /// Lowest fee rate we asked the peer to announce transactions at
+    fee_filter: FeeFilterMessage,
+    /// Lowest fee rate the peer wants transactions announced at
+    peer_fee_filter: FeeFilterMessage,
+    metrics: Arc<dyn Metrics>,
+    cancel: Option<CancelToken>,
+}
+
+impl SimpleNode<TcpStream> {
+    /// Connect to `host` on the default port of `network`
+    pub fn connect(host: &str, network: &Network) -> Result<Self, Error> {
+        let stream = TcpStream::connect((host, network.default_port))?;
+        Ok(SimpleNode::new(stream, network))
+    }
+}
+
+impl<S: Read + Write> SimpleNode<S> {
+    /// Speak over an already open stream, e.g. one through a proxy
+    pub fn new(stream: S, network: &Network) -> Self {
+        SimpleNode {
+            stream,
+            magic: network.magic,
+            decoder: FrameDecoder::new(network.magic),
+            required_services: ServiceFlags::empty(),
+            version: None,
+            peer: None,
+            sent_sendheaders: false,
+            peer_addrv2: false,
+            fee_filter: FeeFilterMessage::default(),
+            peer_fee_filter: FeeFilterMessage::default(),
+            metrics: metrics::noop(),
+            cancel: None,
+        }
+    }
+
+    /// Stop reading once `cancel` is cancelled or times out, the read fails with its
+    /// `CancelError`. Read timeouts of the stream, e.g. `TcpStream::set_read_timeout`,
+    /// become how often a quiet peer is checked for it instead of errors.
+    pub fn cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Report connected headers and the header sync rate to `metrics`
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Connect `headers` to `chain`, counting them once they are in
+    fn connect_headers(
+        &self,
+        chain: &mut HeaderChain,
+        headers: &[BlockHeader],
+    ) -> Result<Vec<ChainEvent>, HeaderError> {
+        let events = chain.connect(headers)?;
+        self.metrics
+            .increment(metrics::HEADERS_SYNCED, &[], headers.len() as u64);
+        Ok(events)
+    }
+
+    /// Refuse peers lacking `services` during the handshake
+    pub fn required_services(mut self, services: ServiceFlags) -> Self {
+        self.required_services = services;
+        self
+    }
+
+    pub fn send<M: Message>(&mut self, message: &M) -> Result<(), Error> {
+        let envelope = NetworkEnvelope::new(message, self.magic);
+        self.stream.write_all(&envelope.serialize())?;
+        Ok(())
+    }
+
+    /// Next message of any kind. A bad frame fails with its `MessageError` and is
+    /// dropped, the next call reads on after it.
+    pub fn read(&mut self) -> Result<NetworkEnvelope, Error> {
+        let mut chunk = [0u8; 8192];
+        loop {
+            if let Some(envelope) = self.decoder.next()? {
+                return Ok(envelope);
+            }
+            if let Some(cancel) = &self.cancel {
+                cancel.check()?;
+            }
+            let read = match self.stream.read(&mut chunk) {
+                Err(ref e)
+                    if self.cancel.is_some()
+                        && (e.kind() == io::ErrorKind::WouldBlock
+                            || e.kind() == io::ErrorKind::TimedOut) =>
+                {
+                    continue
+                }
+                read => read?,
+            };
+            if read == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            self.decoder.push(&chunk[..read]);
+        }
+    }
+
+    /// Skip to the next `M`, answering pings and noting fee filters on the way
+    pub fn wait_for<M: Message>(&mut self) -> Result<M, Error> {
+        loop {
+            let envelope = self.read()?;
+            if envelope.command == M::COMMAND {
+                return Ok(envelope.message()?);
+            }
+            self.handle_control(&envelope)?;
+        }
+    }
+
+    /// Messages about the connection rather than the chain
+    fn handle_control(&mut self, envelope: &NetworkEnvelope) -> Result<(), Error> {
+        match envelope.command.as_str() {
+            PingMessage::COMMAND => {
+                let ping: PingMessage = envelope.message()?;
+                self.send(&PongMessage { nonce: ping.nonce })?;
+            }
+            FeeFilterMessage::COMMAND => self.peer_fee_filter = envelope.message()?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Exchange `version` and `verack` and settle on the lower protocol version, then
+    /// ask for header announcements if both sides know BIP130. Peers too old or lacking
+    /// the required services are refused.
+    pub fn handshake(&mut self, version: &VersionMessage) -> Result<(), Error> {
+        self.send(version)?;
+        let (mut peer, mut verack) = (None, false);
+        while peer.is_none() || !verack {
+            let envelope = self.read()?;
+            match envelope.command.as_str() {
+                VersionMessage::COMMAND => {
+                    let message: VersionMessage = envelope.message()?;
+                    if message.version < MIN_PEER_PROTO_VERSION {
+                        return Err(NodeError::VersionTooLow(message.version).into());
+                    }
+                    if message.nonce != 0 && message.nonce == version.nonce {
+                        return Err(NodeError::SelfConnection.into());
+                    }
+                    if !message.services.contains(self.required_services) {
+                        let missing = self.required_services - message.services;
+                        return Err(NodeError::MissingServices(missing).into());
+                    }
+                    // BIP155 wants `sendaddrv2` before our `verack`
+                    if version.version.min(message.version) >= ADDRV2_VERSION {
+                        self.send(&SendAddrV2Message)?;
+                    }
+                    peer = Some(message);
+                    self.send(&VerAckMessage)?;
+                }
+                VerAckMessage::COMMAND => verack = true,
+                SendAddrV2Message::COMMAND if !verack => self.peer_addrv2 = true,
+                _ => self.handle_control(&envelope)?,
+            }
+        }
+        let peer = peer.expect("the loop ends with a version");
+        self.version = Some(version.version.min(peer.version));
+        self.peer = Some(peer);
+        if self.version >= Some(SENDHEADERS_VERSION) {
+            self.send(&SendHeadersMessage)?;
+            self.sent_sendheaders = true;
+        }
+        Ok(())
+    }
+
+    /// Negotiated protocol version once the handshake is done
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    /// The peer's `version`, its user agent, start height, relay flag and services, once
+    /// the handshake is done
+    pub fn peer(&self) -> Option<&VersionMessage> {
+        self.peer.as_ref()
+    }
+
+    pub fn peer_services(&self) -> ServiceFlags {
+        self.peer
+            .as_ref()
+            .map_or(ServiceFlags::empty(), |peer| peer.services)
+    }
+
+    /// Check the peer serves `services` before using a feature that needs them, e.g. no
+    /// compact filter requests to a peer without `COMPACT_FILTERS`
+    pub fn require(&self, services: ServiceFlags) -> Result<(), NodeError> {
+        let peer_services = self.peer_services();
+        if peer_services.contains(services) {
+            Ok(())
+        } else {
+            Err(NodeError::MissingServices(services - peer_services))
+        }
+    }
+
+    /// Whether the peer takes addresses of every BIP155 network as `addrv2`, otherwise
+    /// only IPv4 and IPv6 ones fit its `addr`
+    pub fn peer_wants_addrv2(&self) -> bool {
+        self.peer_addrv2
+    }
+
+    /// Whether new blocks come as `headers` announcements
+    pub fn announces_headers(&self) -> bool {
+        self.sent_sendheaders
+    }
+
+    /// Ask the peer not to announce transactions paying less than `fee_rate` sat per 1000
+    /// vbytes. False if the peer predates BIP133 and nothing was sent.
+    pub fn send_fee_filter(&mut self, fee_rate: u64) -> Result<bool, Error> {
+        if self.version < Some(FEEFILTER_VERSION) {
+            return Ok(false);
+        }
+        self.fee_filter = FeeFilterMessage { fee_rate };
+        self.send(&self.fee_filter.clone())?;
+        Ok(true)
+    }
+
+    /// The filter we sent, zero until `send_fee_filter`
+    pub fn fee_filter(&self) -> FeeFilterMessage {
+        self.fee_filter
+    }
+
+    /// The peer's filter, zero until it sends one
+    pub fn peer_fee_filter(&self) -> FeeFilterMessage {
+        self.peer_fee_filter
+    }
+
+    /// Whether a transaction paying `fee` should be announced to the peer at all
+    pub fn peer_wants_tx(&self, fee: u64, tx: &Transaction) -> bool {
+        self.peer_fee_filter.accepts(fee, tx.vsize())
+    }
+
+    /// Download the peer's mempool, only what passes our fee filter if one was sent.
+    /// BIP35 `mempool` is answered by peers offering `BLOOM` only. Messages other than
+    /// the answers are dropped, pings excepted.
+    pub fn fetch_mempool(&mut self) -> Result<Vec<Transaction>, Error> {
+        self.require(ServiceFlags::BLOOM)?;
+        self.send(&MempoolMessage)?;
+        let mut items = Vec::new();
+        // the peer answers in order, its pong comes after the last `inv`
+        self.until_pong(|envelope| {
+            if envelope.command == InvMessage::COMMAND {
+                let inv: InvMessage = envelope.message()?;
+                items.extend(inv.items.into_iter().filter(Inventory::is_tx));
+            }
+            Ok(())
+        })?;
+        if items.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let witness = self.peer_services().contains(ServiceFlags::WITNESS);
+        let items = items
+            .iter()
+            .map(|item| {
+                if witness {
+                    Inventory::witness_tx(&item.tx_id())
+                } else {
+                    Inventory::tx(&item.tx_id())
+                }
+            })
+            .collect();
+        self.send(&GetDataMessage { items })?;
+        // transactions gone in the meantime come back as `notfound`
+        let mut txs = Vec::new();
+        self.until_pong(|envelope| {
+            if envelope.command == Transaction::COMMAND {
+                txs.push(envelope.message()?);
+            }
+            Ok(())
+        })?;
+        Ok(txs)
+    }
+
+    /// Ping and hand every message before the pong to `handle`
+    fn until_pong<F>(&mut self, mut handle: F) -> Result<(), Error>
+    where
+        F: FnMut(&NetworkEnvelope) -> Result<(), Error>,
+    {
+        self.send(&PingMessage {
+            nonce: rand::random(),
+        })?;
+        loop {
+            let envelope = self.read()?;
+            // no other ping is ever in flight, the first pong answers this one
+            if envelope.command == PongMessage::COMMAND {
+                return Ok(());
+            }
+            self.handle_control(&envelope)?;
+            handle(&envelope)?;
+        }
+    }
+
+    /// Ask for the headers after the active tip of `chain`
+    pub fn get_headers(&mut self, chain: &HeaderChain) -> Result<(), Error> {
+        self.send(&GetHeadersMessage::new(chain.locator()))
+    }
+
+    /// Download headers into `chain` until the peer has no more
+    pub fn sync_headers(&mut self, chain: &mut HeaderChain) -> Result<Vec<ChainEvent>, Error> {
+        let start = Instant::now();
+        let mut synced = 0;
+        let mut events = Vec::new();
+        loop {
+            self.get_headers(chain)?;
+            let headers = self.wait_for::<HeadersMessage>()?.headers;
+            events.extend(self.connect_headers(chain, &headers)?);
+            synced += headers.len();
+            if headers.len() < MAX_HEADERS_RESULTS {
+                if let Some(rate) = metrics::rate(synced, start) {
+                    self.metrics
+                        .set_gauge(metrics::HEADERS_PER_SECOND, &[], rate);
+                }
+                return Ok(events);
+            }
+        }
+    }
+
+    /// Handle the next message, keeping `chain` in step with what the peer announces.
+    /// Announced headers that don't connect, or blocks announced with `inv`, start a
+    /// `getheaders` whose answer a later call connects.
+    pub fn process(&mut self, chain: &mut HeaderChain) -> Result<Vec<ChainEvent>, Error> {
+        let envelope = self.read()?;
+        match envelope.command.as_str() {
+            HeadersMessage::COMMAND => {
+                let headers = envelope.message::<HeadersMessage>()?.headers;
+                match self.connect_headers(chain, &headers) {
+                    Ok(events) => Ok(events),
+                    // more than one block was found since the last announcement
+                    Err(HeaderError::Orphan(hash))
+                        if Some(hash) == headers.first().map(|h| h.hash()) =>
+                    {
+                        self.get_headers(chain)?;
+                        Ok(vec![])
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+            InvMessage::COMMAND => {
+                let inv: InvMessage = envelope.message()?;
+                if inv.items.iter().any(|item| item.kind == MSG_BLOCK) {
+                    self.get_headers(chain)?;
+                }
+                Ok(vec![])
+            }
+            _ => {
+                self.handle_control(&envelope)?;
+                Ok(vec![])
+            }
+        }
+    }
+}
+
+mod test {
+    use super::{NodeError, SimpleNode};
+    use crate::block::{BlockHash, BlockHeader};
+    use crate::cancel::{CancelError, CancelToken};
+    use crate::headers::{ChainEvent, HeaderChain};
+    use crate::metrics::{PrometheusMetrics, HEADERS_SYNCED};
+    use crate::mining::{bits_to_target, mine};
+    use crate::network::message::{
+        FeeFilterMessage, GetDataMessage, GetHeadersMessage, HeadersMessage, InvMessage, Inventory,
+        NetworkEnvelope, NotFoundMessage, PingMessage, PongMessage, SendAddrV2Message,
+        VerAckMessage, VersionMessage, MSG_BLOCK, MSG_TX,
+    };
+    use crate::network::{Network, ServiceFlags};
+    use crate::transaction::Transaction;
+    use std::io::{Cursor, Read, Write};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Replays what the peer sent and keeps what the node wrote
+    struct MockPeer {
+        incoming: Cursor<Vec<u8>>,
+        outgoing: Vec<u8>,
+    }
+
+    impl Read for MockPeer {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.incoming.read(buf)
+        }
+    }
+
+    impl Write for MockPeer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.outgoing.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sent(outgoing: &[u8]) -> Vec<NetworkEnvelope> {
+        let mut envelopes = Vec::new();
+        let mut input = outgoing;
+        while !input.is_empty() {
+            let (rest, envelope) = NetworkEnvelope::parse(input).unwrap();
+            envelopes.push(envelope);
+            input = rest;
+        }
+        envelopes
+    }
+
+    fn mine_headers(prev: &BlockHeader, count: usize) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        let mut prev = *prev;
+        for _ in 0..count {
+            let header = BlockHeader {
+                version: 0x2000_0000,
+                prev_block: prev.hash(),
+                merkle_root: [0; 32],
+                timestamp: prev.timestamp + 600,
+                bits: 0x207f_ffff,
+                nonce: 0,
+            };
+            prev = mine(header, bits_to_target(header.bits)).unwrap();
+            headers.push(prev);
+        }
+        headers
+    }
+
+    #[test]
+    fn test_sendheaders() {
+        let network = Network::regtest();
+        let genesis = mine_headers(
+            &BlockHeader {
+                version: 1,
+                prev_block: BlockHash::default(),
+                merkle_root: [0; 32],
+                timestamp: 1_296_688_602,
+                bits: 0x207f_ffff,
+                nonce: 0,
+            },
+            1,
+        )[0];
+        let headers = mine_headers(&genesis, 4);
+
+        let peer_version = VersionMessage {
+            version: 70015,
+            ..VersionMessage::new(800_000)
+        }
+        .services(ServiceFlags::NETWORK | ServiceFlags::WITNESS)
+        .user_agent("/Satoshi:27.0.0/")
+        .relay(true)
+        .nonce(1);
+        let mut incoming = Vec::new();
+        let mut push = |envelope: NetworkEnvelope| incoming.extend(envelope.serialize());
+        push(NetworkEnvelope::new(&VerAckMessage, network.magic));
+        push(NetworkEnvelope::new(&peer_version, network.magic));
+        // answer to the initial sync
+        let synced = HeadersMessage {
+            headers: headers[..2].to_vec(),
+        };
+        push(NetworkEnvelope::new(
+            &PingMessage { nonce: 5 },
+            network.magic,
+        ));
+        push(NetworkEnvelope::new(&synced, network.magic));
+        // a block announced with headers, then one skipping a block
+        let announced = HeadersMessage {
+            headers: vec![headers[2]],
+        };
+        push(NetworkEnvelope::new(&announced, network.magic));
+        let gap = HeadersMessage {
+            headers: mine_headers(&headers[3], 1),
+        };
+        push(NetworkEnvelope::new(&gap, network.magic));
+
+        // peers without compact filters or older than BIP31 are refused
+        let mock = |incoming: &[u8]| MockPeer {
+            incoming: Cursor::new(incoming.to_vec()),
+            outgoing: Vec::new(),
+        };
+        let err = SimpleNode::new(mock(&incoming), &network)
+            .required_services(ServiceFlags::COMPACT_FILTERS | ServiceFlags::WITNESS)
+            .handshake(&VersionMessage::default())
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<NodeError>().unwrap(),
+            NodeError::MissingServices(ServiceFlags::COMPACT_FILTERS)
+        );
+        let ancient = VersionMessage {
+            version: 209,
+            ..VersionMessage::default()
+        };
+        let err = SimpleNode::new(
+            mock(&NetworkEnvelope::new(&ancient, network.magic).serialize()),
+            &network,
+        )
+        .handshake(&VersionMessage::default())
+        .unwrap_err();
+        assert_eq!(
+            err.downcast::<NodeError>().unwrap(),
+            NodeError::VersionTooLow(209)
+        );
+
+        let metrics = Arc::new(PrometheusMetrics::new());
+        let mut node = SimpleNode::new(mock(&incoming), &network)
+            .required_services(ServiceFlags::NETWORK)
+            .metrics(metrics.clone());
+        let ours = VersionMessage::new(10)
+            .user_agent("/watcher:0.1/programmingbitcoin:0.1/")
+            .nonce(2);
+        node.handshake(&ours).unwrap();
+        assert_eq!(node.version(), Some(70015));
+        let peer = node.peer().unwrap();
+        assert_eq!(peer.user_agent, "/Satoshi:27.0.0/");
+        assert_eq!((peer.latest_block, peer.relay), (800_000, true));
+        assert!(node.announces_headers());
+        assert!(node.peer_services().serves_blocks());
+        assert_eq!(node.require(ServiceFlags::WITNESS), Ok(()));
+        assert_eq!(
+            node.require(ServiceFlags::COMPACT_FILTERS),
+            Err(NodeError::MissingServices(ServiceFlags::COMPACT_FILTERS))
+        );
+
+        let mut chain = HeaderChain::new(genesis);
+        assert_eq!(node.sync_headers(&mut chain).unwrap().len(), 2);
+        assert_eq!(
+            node.process(&mut chain).unwrap(),
+            vec![ChainEvent::Connected {
+                height: 3,
+                hash: headers[2].hash()
+            }]
+        );
+        assert_eq!(node.process(&mut chain).unwrap(), vec![]);
+        assert_eq!(chain.tip().0, 3);
+        assert_eq!(metrics.counter(HEADERS_SYNCED, &[]), 3);
+
+        let sent = sent(&node.stream.outgoing);
+        assert_eq!(
+            sent.iter()
+                .map(|envelope| envelope.command.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "version",
+                "verack",
+                "sendheaders",
+                "getheaders",
+                "pong",
+                "getheaders"
+            ]
+        );
+        // the gap is asked for from the new tip
+        let last = sent[5].message::<GetHeadersMessage>().unwrap();
+        assert_eq!(last.locator[0], headers[2].hash());
+    }
+
+    #[test]
+    fn test_mempool() {
+        let network = Network::regtest();
+        let data = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let tx = Transaction::parse(&data).unwrap().1;
+        let gone = Inventory {
+            kind: MSG_TX,
+            hash: [2; 32],
+        };
+
+        let mock = |version: u32, services: ServiceFlags, answers: &[NetworkEnvelope]| {
+            let peer = VersionMessage {
+                version,
+                ..VersionMessage::default()
+            }
+            .services(services);
+            let mut incoming = NetworkEnvelope::new(&SendAddrV2Message, network.magic).serialize();
+            incoming.extend(NetworkEnvelope::new(&VerAckMessage, network.magic).serialize());
+            incoming.extend(NetworkEnvelope::new(&peer, network.magic).serialize());
+            for envelope in answers {
+                incoming.extend(envelope.serialize());
+            }
+            let mut node = SimpleNode::new(
+                MockPeer {
+                    incoming: Cursor::new(incoming),
+                    outgoing: Vec::new(),
+                },
+                &network,
+            );
+            node.handshake(&VersionMessage::default()).unwrap();
+            node
+        };
+
+        // BIP133 came after BIP130, BIP35 needs a peer serving bloom filters
+        let mut node = mock(70012, ServiceFlags::NETWORK, &[]);
+        assert!(!node.send_fee_filter(1000).unwrap());
+        assert_eq!(node.fee_filter().fee_rate, 0);
+        assert_eq!(
+            node.fetch_mempool()
+                .unwrap_err()
+                .downcast::<NodeError>()
+                .unwrap(),
+            NodeError::MissingServices(ServiceFlags::BLOOM)
+        );
+
+        let answers = vec![
+            NetworkEnvelope::new(&FeeFilterMessage { fee_rate: 2000 }, network.magic),
+            NetworkEnvelope::new(
+                &InvMessage {
+                    items: vec![
+                        Inventory::tx(&tx.id()),
+                        gone,
+                        Inventory {
+                            kind: MSG_BLOCK,
+                            hash: [1; 32],
+                        },
+                    ],
+                },
+                network.magic,
+            ),
+            NetworkEnvelope::new(&PongMessage { nonce: 0 }, network.magic),
+            NetworkEnvelope::new(&tx, network.magic),
+            NetworkEnvelope::new(&NotFoundMessage { items: vec![gone] }, network.magic),
+            NetworkEnvelope::new(&PongMessage { nonce: 0 }, network.magic),
+        ];
+        let mut node = mock(70016, ServiceFlags::NETWORK | ServiceFlags::BLOOM, &answers);
+        assert!(node.peer_wants_addrv2());
+        assert!(node.send_fee_filter(1000).unwrap());
+        assert_eq!(node.fetch_mempool().unwrap(), vec![tx.clone()]);
+        assert_eq!(node.peer_fee_filter().fee_rate, 2000);
+        let fee = 2 * tx.vsize() as u64;
+        assert!(node.peer_wants_tx(fee, &tx));
+        assert!(!node.peer_wants_tx(fee - 1, &tx));
+
+        let sent = sent(&node.stream.outgoing);
+        assert_eq!(
+            sent.iter()
+                .map(|envelope| envelope.command.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "version",
+                "sendaddrv2",
+                "verack",
+                "sendheaders",
+                "feefilter",
+                "mempool",
+                "ping",
+                "getdata",
+                "ping"
+            ]
+        );
+        assert_eq!(
+            sent[4].message::<FeeFilterMessage>().unwrap().fee_rate,
+            1000
+        );
+        // only the transactions are asked for, without witness from a non segwit peer
+        let get_data = sent[7].message::<GetDataMessage>().unwrap();
+        assert_eq!(get_data.items, vec![Inventory::tx(&tx.id()), gone]);
+        assert!(get_data.items.iter().all(|item| item.kind == MSG_TX));
+    }
+
+    /// A peer that never says anything, every read times out
+    struct QuietPeer;
+
+    impl Read for QuietPeer {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(1));
+            Err(std::io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl Write for QuietPeer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_cancel() {
+        let network = Network::regtest();
+        let mut node = SimpleNode::new(QuietPeer, &network);
+        assert!(node.read().is_err());
+
+        let cancel = CancelToken::new().timeout(Duration::from_millis(20));
+        let mut node = SimpleNode::new(QuietPeer, &network).cancel(cancel.clone());
+        let err = node.wait_for::<PongMessage>().unwrap_err();
+        assert_eq!(
+            err.downcast::<CancelError>().unwrap(),
+            CancelError::TimedOut
+        );
+
+        cancel.cancel();
+        let mut chain = HeaderChain::new(crate::testkit::genesis());
+        let err = node.sync_headers(&mut chain).unwrap_err();
+        assert_eq!(
+            err.downcast::<CancelError>().unwrap(),
+            CancelError::Cancelled
+        );
+    }
+}
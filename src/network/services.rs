@@ -0,0 +1,33 @@
+bitflags! {
+    /// What a peer serves, advertised in `version` and with every address. Bits this
+    /// crate does not know are dropped when parsing.
+    pub struct ServiceFlags: u64 {
+        /// Serves the full block chain
+        const NETWORK = 1 << 0;
+        /// BIP64 `getutxos`
+        const GETUTXO = 1 << 1;
+        /// BIP37 bloom filtered connections
+        const BLOOM = 1 << 2;
+        /// BIP144 blocks and transactions with witnesses
+        const WITNESS = 1 << 3;
+        /// BIP157 compact block filters
+        const COMPACT_FILTERS = 1 << 6;
+        /// BIP159 the last 288 blocks only
+        const NETWORK_LIMITED = 1 << 10;
+        /// BIP324 encrypted transport
+        const P2P_V2 = 1 << 11;
+    }
+}
+
+impl Default for ServiceFlags {
+    fn default() -> Self {
+        ServiceFlags::empty()
+    }
+}
+
+impl ServiceFlags {
+    /// Serves recent blocks at least, a full node or a pruned one
+    pub fn serves_blocks(self) -> bool {
+        self.intersects(ServiceFlags::NETWORK | ServiceFlags::NETWORK_LIMITED)
+    }
+}
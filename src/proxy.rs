@@ -0,0 +1,145 @@
+use rand::Rng;
+
+#[cfg(feature = "proxy")]
+use failure::Error;
+#[cfg(feature = "proxy")]
+use std::net::TcpStream;
+
+/// Tor's SOCKS port on localhost
+pub const TOR_SOCKS_PORT: u16 = 9050;
+
+/// A SOCKS5 proxy every connection is routed over, e.g. a local Tor daemon
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ProxyConfig {
+    pub host: String,
+    pub port: u16,
+    /// Fresh SOCKS credentials for every connection. Tor isolates streams by credentials,
+    /// so connections don't share a circuit and can't be linked by their exit.
+    pub isolate_streams: bool,
+}
+
+impl ProxyConfig {
+    pub fn new(host: &str, port: u16) -> Self {
+        ProxyConfig {
+            host: host.to_string(),
+            port,
+            isolate_streams: false,
+        }
+    }
+
+    /// A local Tor daemon with stream isolation
+    pub fn tor() -> Self {
+        ProxyConfig::new("127.0.0.1", TOR_SOCKS_PORT).isolate_streams(true)
+    }
+
+    pub fn isolate_streams(mut self, isolate_streams: bool) -> Self {
+        self.isolate_streams = isolate_streams;
+        self
+    }
+
+    /// `socks5h://`, host names are resolved by the proxy so lookups don't leak
+    pub fn url(&self) -> String {
+        format!("socks5h://{}:{}", self.host, self.port)
+    }
+
+    /// Username and password for a new connection, None without stream isolation
+    pub fn credentials(&self) -> Option<(String, String)> {
+        if !self.isolate_streams {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        Some((
+            format!("{:016x}", rng.gen::<u64>()),
+            format!("{:016x}", rng.gen::<u64>()),
+        ))
+    }
+
+    /// Open a TCP connection to `host:port` through the proxy, `host` may be an onion
+    /// address
+    #[cfg(feature = "proxy")]
+    pub fn connect(&self, host: &str, port: u16) -> Result<TcpStream, Error> {
+        let proxy = (self.host.as_str(), self.port);
+        let stream = match self.credentials() {
+            Some((username, password)) => socks::Socks5Stream::connect_with_password(
+                proxy,
+                (host, port),
+                &username,
+                &password,
+            )?,
+            None => socks::Socks5Stream::connect(proxy, (host, port))?,
+        };
+        Ok(stream.into_inner())
+    }
+
+    /// HTTP client sending its requests through the proxy, a client per request keeps
+    /// requests apart with stream isolation
+    #[cfg(feature = "proxy")]
+    pub fn http_client(&self) -> Result<reqwest::Client, Error> {
+        Ok(self.http_client_builder()?.build()?)
+    }
+
+    /// Builder of `http_client`, for more settings like a timeout
+    #[cfg(feature = "proxy")]
+    pub fn http_client_builder(&self) -> Result<reqwest::ClientBuilder, Error> {
+        let mut proxy = reqwest::Proxy::all(&self.url())?;
+        if let Some((username, password)) = self.credentials() {
+            proxy = proxy.basic_auth(&username, &password);
+        }
+        Ok(reqwest::Client::builder().proxy(proxy))
+    }
+}
+
+mod test {
+    use super::ProxyConfig;
+
+    #[test]
+    fn test_proxy_config() {
+        let tor = ProxyConfig::tor();
+        assert_eq!(tor.url(), "socks5h://127.0.0.1:9050");
+        let first = tor.credentials().unwrap();
+        assert_ne!(Some(first), tor.credentials());
+
+        let shared = ProxyConfig::new("10.0.0.1", 1080);
+        assert_eq!(shared.credentials(), None);
+    }
+
+    #[cfg(feature = "proxy")]
+    #[test]
+    fn test_proxy_connect() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        // a SOCKS5 server accepting one username/password CONNECT
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // username/password or no authentication, the former is picked
+            let mut greeting = [0u8; 4];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 2, 2, 0]);
+            stream.write_all(&[5, 2]).unwrap();
+
+            let mut auth = [0u8; 2 + 16 + 1 + 16];
+            stream.read_exact(&mut auth).unwrap();
+            assert_eq!(auth[..2], [1, 16]);
+            stream.write_all(&[1, 0]).unwrap();
+
+            let host = b"example.onion";
+            let mut request = vec![0u8; 5 + host.len() + 2];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request[..5], [5, 1, 0, 3, host.len() as u8]);
+            assert_eq!(&request[5..5 + host.len()], host);
+            assert_eq!(request[5 + host.len()..], [0x20, 0x8d]);
+            stream.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap();
+            stream.write_all(b"hi").unwrap();
+        });
+
+        let proxy = ProxyConfig::new("127.0.0.1", port).isolate_streams(true);
+        let mut stream = proxy.connect("example.onion", 8333).unwrap();
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"hi");
+        server.join().unwrap();
+    }
+}
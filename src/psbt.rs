@@ -0,0 +1,968 @@
+mod workflow;
+
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use crate::encode::Encodable;
+use crate::transaction::{
+    ScriptPubKey, ScriptSig, Transaction, TxInput, TxOutput, Varint, SIGHASH_ALL,
+};
+use crate::wallet::bip322::{base64_decode, base64_encode};
+use crate::wallet::{hash160, KeySource, PrivateKey, U256};
+pub use workflow::{PsbtRole, PsbtWorkflow, WorkflowError};
+
+const MAGIC: &[u8] = b"psbt\xff";
+
+const GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const IN_NON_WITNESS_UTXO: u8 = 0x00;
+const IN_WITNESS_UTXO: u8 = 0x01;
+const IN_PARTIAL_SIG: u8 = 0x02;
+const IN_SIGHASH_TYPE: u8 = 0x03;
+const IN_REDEEM_SCRIPT: u8 = 0x04;
+const IN_WITNESS_SCRIPT: u8 = 0x05;
+const IN_BIP32_DERIVATION: u8 = 0x06;
+const IN_FINAL_SCRIPTSIG: u8 = 0x07;
+const IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+const OUT_REDEEM_SCRIPT: u8 = 0x00;
+const OUT_WITNESS_SCRIPT: u8 = 0x01;
+const OUT_BIP32_DERIVATION: u8 = 0x02;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum PsbtError {
+    #[fail(display = "missing psbt magic bytes")]
+    InvalidMagic,
+    #[fail(display = "truncated or malformed psbt")]
+    Malformed,
+    #[fail(display = "duplicate key {}", _0)]
+    DuplicateKey(String),
+    #[fail(display = "psbt has no unsigned transaction")]
+    MissingUnsignedTx,
+    #[fail(display = "unsigned transaction has script sigs or witnesses")]
+    UnsignedTxHasScripts,
+    #[fail(display = "psbts are for different transactions")]
+    DifferentTransaction,
+    #[fail(display = "psbts disagree on {}", _0)]
+    Conflict(String),
+    #[fail(display = "input index {} out of range", _0)]
+    InputIndexOutOfRange(usize),
+    #[fail(display = "input {} has no spent output", _0)]
+    MissingUtxo(usize),
+    #[fail(
+        display = "input {} spends a script that cannot be signed or finalized",
+        _0
+    )]
+    UnsupportedScript(usize),
+    #[fail(display = "key is not used by input {}", _0)]
+    KeyNotInvolved(usize),
+    #[fail(display = "input {} lacks the signatures to finalize", _0)]
+    MissingSignatures(usize),
+    #[fail(display = "input {} is not finalized", _0)]
+    NotFinalized(usize),
+}
+
+/// What a PSBT knows about one input, BIP174 per input map
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PsbtInput {
+    pub non_witness_utxo: Option<Transaction>,
+    pub witness_utxo: Option<TxOutput>,
+    /// Compressed sec -> DER signature with the sighash byte
+    pub partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub sighash_type: Option<u32>,
+    pub redeem_script: Option<Vec<u8>>,
+    pub witness_script: Option<Vec<u8>>,
+    /// Compressed sec -> origin of the key
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeySource>,
+    pub final_script_sig: Option<Vec<u8>>,
+    pub final_script_witness: Option<Vec<Vec<u8>>>,
+    /// Full key -> value of records this crate does not know, kept for round trips
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl PsbtInput {
+    pub fn is_finalized(&self) -> bool {
+        self.final_script_sig.is_some() || self.final_script_witness.is_some()
+    }
+}
+
+/// Signing progress of one input, keys as compressed secs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputSignatures {
+    /// Keys with a partial signature
+    pub signed: Vec<Vec<u8>>,
+    /// Keys that can still sign. A key hash spend only knows its key from a signature
+    /// or the BIP32 derivations.
+    pub missing: Vec<Vec<u8>>,
+    /// Signatures still needed to finalize
+    pub needed: usize,
+    pub finalizable: bool,
+    pub finalized: bool,
+}
+
+/// BIP174 per output map
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PsbtOutput {
+    pub redeem_script: Option<Vec<u8>>,
+    pub witness_script: Option<Vec<u8>>,
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeySource>,
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+/// BIP174 partially signed transaction, version 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Psbt {
+    pub unsigned_tx: Transaction,
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub inputs: Vec<PsbtInput>,
+    pub outputs: Vec<PsbtOutput>,
+}
+
+/// How an input is signed: the script code and whether it is a segwit v0 spend
+enum SpendKind {
+    KeyHash { key_hash: Vec<u8>, segwit: bool },
+    Script { script: Vec<u8>, segwit: bool },
+}
+
+impl Psbt {
+    /// Creator role, `tx` must have empty script sigs and witnesses
+    pub fn new(tx: Transaction) -> Result<Self, PsbtError> {
+        if tx
+            .inputs
+            .iter()
+            .any(|input| !input.script_sig.content.is_empty() || !input.witness.is_empty())
+        {
+            return Err(PsbtError::UnsignedTxHasScripts);
+        }
+        Ok(Psbt {
+            inputs: vec![PsbtInput::default(); tx.inputs.len()],
+            outputs: vec![PsbtOutput::default(); tx.outputs.len()],
+            unsigned_tx: tx,
+            unknown: BTreeMap::new(),
+        })
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, PsbtError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(PsbtError::InvalidMagic);
+        }
+        let (global, mut rest) = read_map(&bytes[MAGIC.len()..])?;
+        let mut unsigned_tx = None;
+        let mut unknown = BTreeMap::new();
+        for (key, value) in global {
+            if key == [GLOBAL_UNSIGNED_TX] {
+                unsigned_tx = match Transaction::parse(&value) {
+                    Ok((left, tx)) if left.is_empty() => Some(tx),
+                    _ => return Err(PsbtError::Malformed),
+                };
+            } else {
+                unknown.insert(key, value);
+            }
+        }
+        let mut psbt = Psbt::new(unsigned_tx.ok_or(PsbtError::MissingUnsignedTx)?)?;
+        psbt.unknown = unknown;
+
+        for input in psbt.inputs.iter_mut() {
+            let (map, left) = read_map(rest)?;
+            rest = left;
+            for (key, value) in map {
+                input.insert_record(key, value)?;
+            }
+        }
+        for output in psbt.outputs.iter_mut() {
+            let (map, left) = read_map(rest)?;
+            rest = left;
+            for (key, value) in map {
+                match (key[0], key.len()) {
+                    (OUT_REDEEM_SCRIPT, 1) => output.redeem_script = Some(value),
+                    (OUT_WITNESS_SCRIPT, 1) => output.witness_script = Some(value),
+                    (OUT_BIP32_DERIVATION, _) => {
+                        let source = KeySource::parse(&value).ok_or(PsbtError::Malformed)?;
+                        output.bip32_derivation.insert(key[1..].to_vec(), source);
+                    }
+                    _ => {
+                        output.unknown.insert(key, value);
+                    }
+                }
+            }
+        }
+        if !rest.is_empty() {
+            return Err(PsbtError::Malformed);
+        }
+        Ok(psbt)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        write_pair(
+            &mut buf,
+            &[GLOBAL_UNSIGNED_TX],
+            &self.unsigned_tx.serialize_legacy(),
+        );
+        for (key, value) in &self.unknown {
+            write_pair(&mut buf, key, value);
+        }
+        buf.push(0x00);
+
+        for input in &self.inputs {
+            if let Some(tx) = &input.non_witness_utxo {
+                write_pair(&mut buf, &[IN_NON_WITNESS_UTXO], &tx.serialize());
+            }
+            if let Some(output) = &input.witness_utxo {
+                write_pair(&mut buf, &[IN_WITNESS_UTXO], &output.serialize());
+            }
+            for (key, sig) in &input.partial_sigs {
+                write_pair(&mut buf, &[&[IN_PARTIAL_SIG], &key[..]].concat(), sig);
+            }
+            if let Some(sighash_type) = input.sighash_type {
+                write_pair(&mut buf, &[IN_SIGHASH_TYPE], &sighash_type.to_le_bytes());
+            }
+            if let Some(script) = &input.redeem_script {
+                write_pair(&mut buf, &[IN_REDEEM_SCRIPT], script);
+            }
+            if let Some(script) = &input.witness_script {
+                write_pair(&mut buf, &[IN_WITNESS_SCRIPT], script);
+            }
+            for (key, source) in &input.bip32_derivation {
+                let record = [&[IN_BIP32_DERIVATION], &key[..]].concat();
+                write_pair(&mut buf, &record, &source.serialize());
+            }
+            if let Some(script_sig) = &input.final_script_sig {
+                write_pair(&mut buf, &[IN_FINAL_SCRIPTSIG], script_sig);
+            }
+            if let Some(witness) = &input.final_script_witness {
+                let mut value = Vec::new();
+                Varint::from(witness.len() as u64).consensus_encode(&mut value);
+                for item in witness {
+                    Varint::from(item.len() as u64).consensus_encode(&mut value);
+                    value.extend_from_slice(item);
+                }
+                write_pair(&mut buf, &[IN_FINAL_SCRIPTWITNESS], &value);
+            }
+            for (key, value) in &input.unknown {
+                write_pair(&mut buf, key, value);
+            }
+            buf.push(0x00);
+        }
+
+        for output in &self.outputs {
+            if let Some(script) = &output.redeem_script {
+                write_pair(&mut buf, &[OUT_REDEEM_SCRIPT], script);
+            }
+            if let Some(script) = &output.witness_script {
+                write_pair(&mut buf, &[OUT_WITNESS_SCRIPT], script);
+            }
+            for (key, source) in &output.bip32_derivation {
+                let record = [&[OUT_BIP32_DERIVATION], &key[..]].concat();
+                write_pair(&mut buf, &record, &source.serialize());
+            }
+            for (key, value) in &output.unknown {
+                write_pair(&mut buf, key, value);
+            }
+            buf.push(0x00);
+        }
+        buf
+    }
+
+    pub fn from_base64(s: &str) -> Result<Self, PsbtError> {
+        Psbt::parse(&base64_decode(s).ok_or(PsbtError::Malformed)?)
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64_encode(&self.serialize())
+    }
+
+    /// The output input `index` spends, from the witness utxo or the full previous
+    /// transaction. A previous transaction with another id than the outpoint is ignored.
+    pub fn spent_output(&self, index: usize) -> Result<TxOutput, PsbtError> {
+        let input = self
+            .inputs
+            .get(index)
+            .ok_or(PsbtError::InputIndexOutOfRange(index))?;
+        if let Some(output) = &input.witness_utxo {
+            return Ok(output.clone());
+        }
+        let out_point = self.unsigned_tx.inputs[index].out_point();
+        input
+            .non_witness_utxo
+            .as_ref()
+            .filter(|tx| tx.id() == out_point.txid)
+            .and_then(|tx| tx.outputs.get(out_point.vout as usize))
+            .cloned()
+            .ok_or(PsbtError::MissingUtxo(index))
+    }
+
+    /// Signer role, adds the signature of `key` for input `index` with its sighash type,
+    /// SIGHASH_ALL by default
+    pub fn sign(&mut self, index: usize, key: &PrivateKey) -> Result<(), PsbtError> {
+        let spent = self.spent_output(index)?;
+        let sec = key.point.compressed_sec().to_vec();
+        let script_code = match self.spend_kind(index, &spent)? {
+            SpendKind::KeyHash { key_hash, segwit } => {
+                if key_hash != key.point.hash160(true).to_vec() {
+                    return Err(PsbtError::KeyNotInvolved(index));
+                }
+                (p2pkh_script(&key_hash), segwit)
+            }
+            SpendKind::Script { script, segwit } => {
+                if !script.windows(sec.len()).any(|window| window == &sec[..]) {
+                    return Err(PsbtError::KeyNotInvolved(index));
+                }
+                (ScriptPubKey { content: script }, segwit)
+            }
+        };
+        let sighash_type = self.inputs[index].sighash_type.unwrap_or(SIGHASH_ALL);
+        let z = match script_code {
+            (code, true) => self.unsigned_tx.sig_hash_segwit_v0(
+                index,
+                &code,
+                u64::from(spent.amount),
+                sighash_type,
+            ),
+            (code, false) => self.unsigned_tx.sig_hash(index, &code, sighash_type),
+        };
+        let mut sig = key.sign(U256::from_little_endian(&z)).der();
+        sig.push(sighash_type as u8);
+        self.inputs[index].partial_sigs.insert(sec, sig);
+        Ok(())
+    }
+
+    fn spend_kind(&self, index: usize, spent: &TxOutput) -> Result<SpendKind, PsbtError> {
+        let input = &self.inputs[index];
+        let content = &spent.script_pub_key.content;
+        // the witness program, of the output or of a P2SH redeem script
+        let program = match classify(content) {
+            Some(Template::P2pkh(key_hash)) => {
+                return Ok(SpendKind::KeyHash {
+                    key_hash,
+                    segwit: false,
+                })
+            }
+            Some(Template::P2sh(hash)) => {
+                let redeem = input
+                    .redeem_script
+                    .as_ref()
+                    .filter(|redeem| hash160(redeem).to_vec() == hash)
+                    .ok_or(PsbtError::UnsupportedScript(index))?;
+                match classify(redeem) {
+                    Some(Template::P2wpkh(_)) | Some(Template::P2wsh(_)) => redeem,
+                    _ => {
+                        return Ok(SpendKind::Script {
+                            script: redeem.clone(),
+                            segwit: false,
+                        })
+                    }
+                }
+            }
+            _ => content,
+        };
+        match classify(program) {
+            Some(Template::P2wpkh(key_hash)) => Ok(SpendKind::KeyHash {
+                key_hash,
+                segwit: true,
+            }),
+            Some(Template::P2wsh(hash)) => {
+                let script = input
+                    .witness_script
+                    .as_ref()
+                    .ok_or(PsbtError::UnsupportedScript(index))?;
+                if Sha256::digest(script)[..] != hash[..] {
+                    return Err(PsbtError::UnsupportedScript(index));
+                }
+                Ok(SpendKind::Script {
+                    script: script.clone(),
+                    segwit: true,
+                })
+            }
+            _ => Err(PsbtError::UnsupportedScript(index)),
+        }
+    }
+
+    /// Combiner role, merges what `other` knows about the same transaction. Records both
+    /// have must agree, so merging in any order gives the same result.
+    pub fn combine(&mut self, other: &Psbt) -> Result<(), PsbtError> {
+        if self.unsigned_tx != other.unsigned_tx {
+            return Err(PsbtError::DifferentTransaction);
+        }
+        merge_map(&mut self.unknown, &other.unknown, "global records")?;
+        for (index, (ours, theirs)) in self.inputs.iter_mut().zip(&other.inputs).enumerate() {
+            let what = |field: &str| format!("input {} {}", index, field);
+            merge_option(
+                &mut ours.non_witness_utxo,
+                &theirs.non_witness_utxo,
+                what("non witness utxo"),
+            )?;
+            merge_option(
+                &mut ours.witness_utxo,
+                &theirs.witness_utxo,
+                what("witness utxo"),
+            )?;
+            merge_map(
+                &mut ours.partial_sigs,
+                &theirs.partial_sigs,
+                &what("signatures"),
+            )?;
+            merge_option(
+                &mut ours.sighash_type,
+                &theirs.sighash_type,
+                what("sighash type"),
+            )?;
+            merge_option(
+                &mut ours.redeem_script,
+                &theirs.redeem_script,
+                what("redeem script"),
+            )?;
+            merge_option(
+                &mut ours.witness_script,
+                &theirs.witness_script,
+                what("witness script"),
+            )?;
+            merge_map(
+                &mut ours.bip32_derivation,
+                &theirs.bip32_derivation,
+                &what("key origins"),
+            )?;
+            merge_option(
+                &mut ours.final_script_sig,
+                &theirs.final_script_sig,
+                what("final script sig"),
+            )?;
+            merge_option(
+                &mut ours.final_script_witness,
+                &theirs.final_script_witness,
+                what("final witness"),
+            )?;
+            merge_map(&mut ours.unknown, &theirs.unknown, &what("records"))?;
+        }
+        for (index, (ours, theirs)) in self.outputs.iter_mut().zip(&other.outputs).enumerate() {
+            let what = |field: &str| format!("output {} {}", index, field);
+            merge_option(
+                &mut ours.redeem_script,
+                &theirs.redeem_script,
+                what("redeem script"),
+            )?;
+            merge_option(
+                &mut ours.witness_script,
+                &theirs.witness_script,
+                what("witness script"),
+            )?;
+            merge_map(
+                &mut ours.bip32_derivation,
+                &theirs.bip32_derivation,
+                &what("key origins"),
+            )?;
+            merge_map(&mut ours.unknown, &theirs.unknown, &what("records"))?;
+        }
+        Ok(())
+    }
+
+    /// Which keys signed each input and which are still needed, for coordinators showing
+    /// progress. Fails like `finalize` on an input without its UTXO or with a script it
+    /// cannot finalize.
+    pub fn missing_signatures(&self) -> Result<Vec<InputSignatures>, PsbtError> {
+        let mut report = Vec::with_capacity(self.inputs.len());
+        for (index, input) in self.inputs.iter().enumerate() {
+            if input.is_finalized() {
+                report.push(InputSignatures {
+                    signed: vec![],
+                    missing: vec![],
+                    needed: 0,
+                    finalizable: true,
+                    finalized: true,
+                });
+                continue;
+            }
+            let spent = self.spent_output(index)?;
+            let (keys, needed): (Vec<Vec<u8>>, usize) = match self.spend_kind(index, &spent)? {
+                SpendKind::KeyHash { key_hash, .. } => {
+                    let mut keys: Vec<Vec<u8>> = input
+                        .partial_sigs
+                        .keys()
+                        .chain(input.bip32_derivation.keys())
+                        .filter(|sec| hash160(sec).to_vec() == key_hash)
+                        .cloned()
+                        .collect();
+                    keys.dedup();
+                    (keys, 1)
+                }
+                SpendKind::Script { script, .. } => {
+                    let (keys, needed) =
+                        script_keys(&script).ok_or(PsbtError::UnsupportedScript(index))?;
+                    (keys.into_iter().map(|key| key.to_vec()).collect(), needed)
+                }
+            };
+            let (signed, missing): (Vec<Vec<u8>>, Vec<Vec<u8>>) = keys
+                .into_iter()
+                .partition(|key| input.partial_sigs.contains_key(key));
+            let needed = needed.saturating_sub(signed.len());
+            report.push(InputSignatures {
+                signed,
+                missing,
+                needed,
+                finalizable: needed == 0,
+                finalized: false,
+            });
+        }
+        Ok(report)
+    }
+
+    /// Finalizer role, builds the script sig and witness of every input from its partial
+    /// signatures and drops the signing data. P2PKH, P2WPKH, P2SH-P2WPKH and single key or
+    /// multisig P2SH, P2WSH and P2SH-P2WSH scripts can be finalized.
+    pub fn finalize(&mut self) -> Result<(), PsbtError> {
+        for index in 0..self.inputs.len() {
+            if self.inputs[index].is_finalized() {
+                continue;
+            }
+            let spent = self.spent_output(index)?;
+            let input = &self.inputs[index];
+            let sig_of = |sec: &[u8]| input.partial_sigs.get(sec).cloned();
+            let missing = PsbtError::MissingSignatures(index);
+
+            let (script_sig, witness) = match self.spend_kind(index, &spent)? {
+                SpendKind::KeyHash { key_hash, segwit } => {
+                    let (sec, sig) = input
+                        .partial_sigs
+                        .iter()
+                        .find(|(sec, _)| hash160(sec).to_vec() == key_hash)
+                        .ok_or(missing)?;
+                    let stack = vec![sig.clone(), sec.clone()];
+                    if segwit {
+                        (vec![], Some(stack))
+                    } else {
+                        (push_all(&stack), None)
+                    }
+                }
+                SpendKind::Script { script, segwit } => {
+                    let mut stack = satisfy(&script, sig_of).ok_or(missing)?;
+                    stack.push(script);
+                    if segwit {
+                        (vec![], Some(stack))
+                    } else {
+                        (push_all(&stack), None)
+                    }
+                }
+            };
+            // nested segwit pushes its witness program
+            let script_sig = match (&input.redeem_script, &witness) {
+                (Some(redeem), Some(_)) => push_all(std::slice::from_ref(redeem)),
+                _ => script_sig,
+            };
+
+            let input = &mut self.inputs[index];
+            input.final_script_sig = Some(script_sig);
+            input.final_script_witness = witness;
+            input.partial_sigs.clear();
+            input.sighash_type = None;
+            input.redeem_script = None;
+            input.witness_script = None;
+            input.bip32_derivation.clear();
+        }
+        Ok(())
+    }
+
+    /// Extractor role, the signed transaction once every input is finalized
+    pub fn extract(&self) -> Result<Transaction, PsbtError> {
+        let mut tx = self.unsigned_tx.clone();
+        for (index, (tx_input, input)) in tx.inputs.iter_mut().zip(&self.inputs).enumerate() {
+            if !input.is_finalized() {
+                return Err(PsbtError::NotFinalized(index));
+            }
+            tx_input.script_sig = ScriptSig {
+                content: input.final_script_sig.clone().unwrap_or_default(),
+            };
+            tx_input.witness = input.final_script_witness.clone().unwrap_or_default();
+        }
+        Ok(tx)
+    }
+}
+
+impl PsbtInput {
+    fn insert_record(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), PsbtError> {
+        match (key[0], key.len()) {
+            (IN_NON_WITNESS_UTXO, 1) => {
+                self.non_witness_utxo = match Transaction::parse(&value) {
+                    Ok((left, tx)) if left.is_empty() => Some(tx),
+                    _ => return Err(PsbtError::Malformed),
+                }
+            }
+            (IN_WITNESS_UTXO, 1) => {
+                self.witness_utxo = match TxOutput::parse(&value) {
+                    Ok((left, output)) if left.is_empty() => Some(output),
+                    _ => return Err(PsbtError::Malformed),
+                }
+            }
+            (IN_PARTIAL_SIG, _) => {
+                self.partial_sigs.insert(key[1..].to_vec(), value);
+            }
+            (IN_SIGHASH_TYPE, 1) if value.len() == 4 => {
+                self.sighash_type =
+                    Some(u32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+            }
+            (IN_REDEEM_SCRIPT, 1) => self.redeem_script = Some(value),
+            (IN_WITNESS_SCRIPT, 1) => self.witness_script = Some(value),
+            (IN_BIP32_DERIVATION, _) => {
+                let source = KeySource::parse(&value).ok_or(PsbtError::Malformed)?;
+                self.bip32_derivation.insert(key[1..].to_vec(), source);
+            }
+            (IN_FINAL_SCRIPTSIG, 1) => self.final_script_sig = Some(value),
+            (IN_FINAL_SCRIPTWITNESS, 1) => {
+                self.final_script_witness = match TxInput::parse_witness(&value) {
+                    Ok((left, witness)) if left.is_empty() => Some(witness),
+                    _ => return Err(PsbtError::Malformed),
+                }
+            }
+            _ => {
+                self.unknown.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Standard templates the signer and finalizer understand, with their hash
+enum Template {
+    P2pkh(Vec<u8>),
+    P2sh(Vec<u8>),
+    P2wpkh(Vec<u8>),
+    P2wsh(Vec<u8>),
+}
+
+fn classify(script: &[u8]) -> Option<Template> {
+    match script {
+        [0x76, 0xa9, 0x14, hash @ .., 0x88, 0xac] if hash.len() == 20 => {
+            Some(Template::P2pkh(hash.to_vec()))
+        }
+        [0xa9, 0x14, hash @ .., 0x87] if hash.len() == 20 => Some(Template::P2sh(hash.to_vec())),
+        [0x00, 0x14, hash @ ..] if hash.len() == 20 => Some(Template::P2wpkh(hash.to_vec())),
+        [0x00, 0x20, hash @ ..] if hash.len() == 32 => Some(Template::P2wsh(hash.to_vec())),
+        _ => None,
+    }
+}
+
+/// Keys of `<key> OP_CHECKSIG` or `OP_m <key>... OP_n OP_CHECKMULTISIG` with the number of
+/// signatures needed
+fn script_keys(script: &[u8]) -> Option<(Vec<&[u8]>, usize)> {
+    match script {
+        [0x21, key @ .., 0xac] if key.len() == 33 => Some((vec![key], 1)),
+        [m @ 0x51..=0x60, keys @ .., n @ 0x51..=0x60, 0xae] => {
+            if keys.len() % 34 != 0 || keys.chunks(34).any(|chunk| chunk[0] != 0x21) {
+                return None;
+            }
+            let keys: Vec<&[u8]> = keys.chunks(34).map(|chunk| &chunk[1..]).collect();
+            let (m, n) = ((m - 0x50) as usize, (n - 0x50) as usize);
+            if keys.len() != n || m > n {
+                return None;
+            }
+            Some((keys, m))
+        }
+        _ => None,
+    }
+}
+
+/// Stack satisfying a single key or multisig script, signatures in key order
+fn satisfy<F: Fn(&[u8]) -> Option<Vec<u8>>>(script: &[u8], sig_of: F) -> Option<Vec<Vec<u8>>> {
+    let (keys, needed) = script_keys(script)?;
+    let sigs: Vec<Vec<u8>> = keys.iter().filter_map(|key| sig_of(key)).collect();
+    if sigs.len() < needed {
+        return None;
+    }
+    if keys.len() == 1 && script.last() == Some(&0xac) {
+        return Some(sigs);
+    }
+    // the extra element OP_CHECKMULTISIG pops
+    let mut stack = vec![vec![]];
+    stack.extend(sigs.into_iter().take(needed));
+    Some(stack)
+}
+
+fn p2pkh_script(key_hash: &[u8]) -> ScriptPubKey {
+    let mut content = vec![0x76, 0xa9, 0x14];
+    content.extend_from_slice(key_hash);
+    content.extend_from_slice(&[0x88, 0xac]);
+    ScriptPubKey { content }
+}
+
+/// Script sig pushing every element, the empty element as OP_0
+fn push_all(elements: &[Vec<u8>]) -> Vec<u8> {
+    let mut script = Vec::new();
+    for element in elements {
+        match element.len() {
+            0 => script.push(0x00),
+            len @ 1..=0x4b => script.push(len as u8),
+            len @ 0x4c..=0xff => script.extend_from_slice(&[0x4c, len as u8]),
+            len => {
+                script.push(0x4d);
+                script.extend_from_slice(&(len as u16).to_le_bytes());
+            }
+        }
+        script.extend_from_slice(element);
+    }
+    script
+}
+
+fn read_varint(input: &[u8]) -> Result<(usize, &[u8]), PsbtError> {
+    if input.is_empty() {
+        return Err(PsbtError::Malformed);
+    }
+    let (rest, len) = Varint::parse(input).map_err(|_| PsbtError::Malformed)?;
+    Ok((Into::<u64>::into(len) as usize, rest))
+}
+
+/// Key with its type byte and value
+type Record = (Vec<u8>, Vec<u8>);
+
+/// Records up to the 0x00 separator
+fn read_map(mut input: &[u8]) -> Result<(Vec<Record>, &[u8]), PsbtError> {
+    let mut map: Vec<Record> = Vec::new();
+    loop {
+        let (key_len, rest) = read_varint(input)?;
+        if key_len == 0 {
+            return Ok((map, rest));
+        }
+        if rest.len() < key_len {
+            return Err(PsbtError::Malformed);
+        }
+        let (key, rest) = rest.split_at(key_len);
+        let (value_len, rest) = read_varint(rest)?;
+        if rest.len() < value_len {
+            return Err(PsbtError::Malformed);
+        }
+        let (value, rest) = rest.split_at(value_len);
+        if map.iter().any(|(k, _)| k == key) {
+            return Err(PsbtError::DuplicateKey(hex::encode(key)));
+        }
+        map.push((key.to_vec(), value.to_vec()));
+        input = rest;
+    }
+}
+
+fn write_pair(buf: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    Varint::from(key.len() as u64).consensus_encode(buf);
+    buf.extend_from_slice(key);
+    Varint::from(value.len() as u64).consensus_encode(buf);
+    buf.extend_from_slice(value);
+}
+
+fn merge_option<T: Clone + PartialEq>(
+    ours: &mut Option<T>,
+    theirs: &Option<T>,
+    what: String,
+) -> Result<(), PsbtError> {
+    match (ours.as_ref(), theirs) {
+        (Some(a), Some(b)) if a != b => Err(PsbtError::Conflict(what)),
+        (None, Some(b)) => {
+            *ours = Some(b.clone());
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn merge_map<V: Clone + PartialEq>(
+    ours: &mut BTreeMap<Vec<u8>, V>,
+    theirs: &BTreeMap<Vec<u8>, V>,
+    what: &str,
+) -> Result<(), PsbtError> {
+    for (key, value) in theirs {
+        match ours.get(key) {
+            Some(existing) if existing != value => {
+                return Err(PsbtError::Conflict(what.to_string()))
+            }
+            Some(_) => {}
+            None => {
+                ours.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+mod test {
+    use super::{InputSignatures, Psbt, PsbtError};
+    use crate::transaction::{
+        PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxHash, TxInput, TxInputSequence,
+        TxLocktime, TxOutput, TxVersion, SIGHASH_ALL,
+    };
+    use crate::wallet::{KeySource, PrivateKey, Signature, U256};
+    use sha2::{Digest, Sha256};
+
+    fn unsigned(outputs: usize) -> Transaction {
+        Transaction::new(
+            TxVersion::new(2),
+            vec![TxInput::new(
+                TxHash::new(&[7; 32]).unwrap().1,
+                PreTxIndex::new(1),
+                ScriptSig { content: vec![] },
+                TxInputSequence::new(0xffff_fffd),
+            )],
+            (0..outputs)
+                .map(|i| TxOutput {
+                    amount: (40_000 + i as u64).into(),
+                    script_pub_key: ScriptPubKey {
+                        content: vec![0x51],
+                    },
+                })
+                .collect(),
+            TxLocktime::new(0),
+            false,
+        )
+    }
+
+    #[test]
+    fn test_p2wpkh_sign_finalize_extract() {
+        let key = PrivateKey::new(U256::from(8_675_309u32));
+        let mut psbt = Psbt::new(unsigned(1)).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOutput {
+            amount: 50_000.into(),
+            script_pub_key: key.point.p2wpkh_script(),
+        });
+        assert_eq!(psbt.finalize(), Err(PsbtError::MissingSignatures(0)));
+        assert_eq!(
+            psbt.sign(0, &PrivateKey::new(U256::from(1u32))),
+            Err(PsbtError::KeyNotInvolved(0))
+        );
+        psbt.sign(0, &key).unwrap();
+
+        let round_trip = Psbt::from_base64(&psbt.to_base64()).unwrap();
+        assert_eq!(round_trip, psbt);
+        assert!(psbt.to_base64().starts_with("cHNidP8B"));
+
+        assert_eq!(psbt.extract(), Err(PsbtError::NotFinalized(0)));
+        psbt.finalize().unwrap();
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+        let tx = psbt.extract().unwrap();
+        let witness = &tx.inputs[0].witness;
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness[1], key.point.compressed_sec().to_vec());
+
+        let mut script_code = vec![0x76, 0xa9, 0x14];
+        script_code.extend_from_slice(&key.point.hash160(true));
+        script_code.extend_from_slice(&[0x88, 0xac]);
+        let z = tx.sig_hash_segwit_v0(
+            0,
+            &ScriptPubKey {
+                content: script_code,
+            },
+            50_000,
+            SIGHASH_ALL,
+        );
+        let (sig, sighash_type) = witness[0].split_at(witness[0].len() - 1);
+        assert_eq!(sighash_type, [SIGHASH_ALL as u8]);
+        assert!(key.point.verify(z, Signature::parse_der(sig)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Psbt::parse(b"psbu\xff"), Err(PsbtError::InvalidMagic));
+        assert_eq!(
+            Psbt::parse(b"psbt\xff\x00"),
+            Err(PsbtError::MissingUnsignedTx)
+        );
+        let psbt = Psbt::new(unsigned(1)).unwrap();
+        let bytes = psbt.serialize();
+        assert_eq!(
+            Psbt::parse(&bytes[..bytes.len() - 1]),
+            Err(PsbtError::Malformed)
+        );
+        // the global unsigned transaction record twice
+        let record_len = bytes.len() - 5 - 3;
+        let doubled = [&bytes[..5 + record_len], &bytes[5..]].concat();
+        assert_eq!(
+            Psbt::parse(&doubled),
+            Err(PsbtError::DuplicateKey("00".to_string()))
+        );
+
+        let mut signed = unsigned(1);
+        signed.inputs[0].script_sig = ScriptSig {
+            content: vec![0x51],
+        };
+        assert_eq!(Psbt::new(signed), Err(PsbtError::UnsignedTxHasScripts));
+    }
+
+    #[test]
+    fn test_missing_signatures() {
+        let keys: Vec<PrivateKey> = (1..=3u32)
+            .map(|i| PrivateKey::new(U256::from(i * 1_000)))
+            .collect();
+        let secs: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|key| key.point.compressed_sec().to_vec())
+            .collect();
+        // 2 of 3 P2WSH
+        let mut witness_script = vec![0x52];
+        for sec in &secs {
+            witness_script.push(0x21);
+            witness_script.extend_from_slice(sec);
+        }
+        witness_script.extend_from_slice(&[0x53, 0xae]);
+        let mut program = vec![0x00, 0x20];
+        program.extend_from_slice(&Sha256::digest(&witness_script));
+
+        let mut psbt = Psbt::new(unsigned(1)).unwrap();
+        assert_eq!(psbt.missing_signatures(), Err(PsbtError::MissingUtxo(0)));
+        psbt.inputs[0].witness_utxo = Some(TxOutput {
+            amount: 50_000.into(),
+            script_pub_key: ScriptPubKey { content: program },
+        });
+        psbt.inputs[0].witness_script = Some(witness_script);
+        assert_eq!(
+            psbt.missing_signatures().unwrap(),
+            vec![InputSignatures {
+                signed: vec![],
+                missing: secs.clone(),
+                needed: 2,
+                finalizable: false,
+                finalized: false,
+            }]
+        );
+
+        psbt.sign(0, &keys[1]).unwrap();
+        let report = &psbt.missing_signatures().unwrap()[0];
+        assert_eq!(report.signed, vec![secs[1].clone()]);
+        assert_eq!(report.missing, vec![secs[0].clone(), secs[2].clone()]);
+        assert_eq!(report.needed, 1);
+        assert!(!report.finalizable);
+
+        psbt.sign(0, &keys[2]).unwrap();
+        let report = &psbt.missing_signatures().unwrap()[0];
+        assert_eq!(report.missing, vec![secs[0].clone()]);
+        assert!(report.finalizable);
+        psbt.finalize().unwrap();
+        assert!(psbt.missing_signatures().unwrap()[0].finalized);
+
+        // a key hash spend learns its key from the derivations
+        let mut psbt = Psbt::new(unsigned(1)).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOutput {
+            amount: 50_000.into(),
+            script_pub_key: keys[0].point.p2wpkh_script(),
+        });
+        assert_eq!(psbt.missing_signatures().unwrap()[0].missing.len(), 0);
+        psbt.inputs[0].bip32_derivation.insert(
+            secs[0].clone(),
+            KeySource::parse(&[0xd3, 0x4d, 0xb3, 0x3f]).unwrap(),
+        );
+        let report = &psbt.missing_signatures().unwrap()[0];
+        assert_eq!(report.missing, vec![secs[0].clone()]);
+        assert_eq!(report.needed, 1);
+    }
+
+    #[test]
+    fn test_snapshots() {
+        use crate::testkit::Snapshot;
+
+        let key = PrivateKey::new(U256::from(8_675_309u32));
+        let mut psbt = Psbt::new(unsigned(2)).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOutput {
+            amount: 50_000.into(),
+            script_pub_key: key.point.p2wpkh_script(),
+        });
+        let mut snapshot = Snapshot::new("psbt");
+        snapshot.hex("unsigned", &psbt.serialize());
+        psbt.sign(0, &key).unwrap();
+        snapshot.text("signed.base64", &psbt.to_base64());
+        psbt.finalize().unwrap();
+        snapshot
+            .hex("finalized", &psbt.serialize())
+            .hex("extracted", &psbt.extract().unwrap().serialize());
+        snapshot.check();
+    }
+}
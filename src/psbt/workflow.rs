@@ -0,0 +1,304 @@
+use std::fmt::Display;
+
+use super::{Psbt, PsbtError};
+use crate::transaction::Transaction;
+use crate::wallet::PrivateKey;
+
+/// BIP174 roles, in the order a PSBT usually passes through them
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum PsbtRole {
+    Creator,
+    Updater,
+    Signer,
+    Combiner,
+    Finalizer,
+    Extractor,
+}
+impl Copy for PsbtRole {}
+
+impl Display for PsbtRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            PsbtRole::Creator => "creator",
+            PsbtRole::Updater => "updater",
+            PsbtRole::Signer => "signer",
+            PsbtRole::Combiner => "combiner",
+            PsbtRole::Finalizer => "finalizer",
+            PsbtRole::Extractor => "extractor",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum WorkflowError {
+    #[fail(display = "the {} cannot act after the {}", role, last)]
+    OutOfOrder { role: PsbtRole, last: PsbtRole },
+    #[fail(display = "the unsigned transaction changed")]
+    TransactionChanged,
+    #[fail(display = "input {} changed after it was signed", _0)]
+    SignedInputChanged(usize),
+    #[fail(display = "{}", _0)]
+    Psbt(PsbtError),
+}
+
+impl From<PsbtError> for WorkflowError {
+    fn from(e: PsbtError) -> Self {
+        WorkflowError::Psbt(e)
+    }
+}
+
+/// One party's view of a multi party PSBT. Each step checks it comes at a point of the
+/// workflow where its role may act, and that it keeps the invariants earlier steps rely
+/// on: the unsigned transaction never changes, so no input or output appears once the
+/// creator is done, and nothing a signature commits to changes once an input is signed.
+#[derive(Debug, Clone)]
+pub struct PsbtWorkflow {
+    psbt: Psbt,
+    history: Vec<PsbtRole>,
+}
+
+impl PsbtWorkflow {
+    pub fn create(tx: Transaction) -> Result<Self, WorkflowError> {
+        Ok(PsbtWorkflow {
+            psbt: Psbt::new(tx)?,
+            history: vec![PsbtRole::Creator],
+        })
+    }
+
+    /// Continue with a PSBT another party created, e.g. as a cosigner
+    pub fn resume(psbt: Psbt) -> Self {
+        PsbtWorkflow {
+            psbt,
+            history: vec![PsbtRole::Creator],
+        }
+    }
+
+    pub fn psbt(&self) -> &Psbt {
+        &self.psbt
+    }
+
+    pub fn into_psbt(self) -> Psbt {
+        self.psbt
+    }
+
+    /// Every role that acted, in order
+    pub fn history(&self) -> &[PsbtRole] {
+        &self.history
+    }
+
+    fn check_order(&self, role: PsbtRole) -> Result<(), WorkflowError> {
+        let last = *self
+            .history
+            .last()
+            .expect("a workflow starts with its creator");
+        let allowed = match (last, role) {
+            (PsbtRole::Extractor, _) => false,
+            (PsbtRole::Finalizer, role) => role == PsbtRole::Extractor,
+            (_, PsbtRole::Creator) | (_, PsbtRole::Extractor) => false,
+            _ => true,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(WorkflowError::OutOfOrder { role, last })
+        }
+    }
+
+    /// Commit `updated` as the step of `role` once it kept the invariants
+    fn commit(&mut self, role: PsbtRole, updated: Psbt) -> Result<(), WorkflowError> {
+        if updated.unsigned_tx != self.psbt.unsigned_tx
+            || updated.inputs.len() != self.psbt.inputs.len()
+            || updated.outputs.len() != self.psbt.outputs.len()
+        {
+            return Err(WorkflowError::TransactionChanged);
+        }
+        for (index, (before, after)) in self.psbt.inputs.iter().zip(&updated.inputs).enumerate() {
+            let signed = !before.partial_sigs.is_empty();
+            // more signatures may come, nothing they commit to may move
+            let kept = before
+                .partial_sigs
+                .iter()
+                .all(|(key, sig)| after.partial_sigs.get(key) == Some(sig))
+                && before.non_witness_utxo == after.non_witness_utxo
+                && before.witness_utxo == after.witness_utxo
+                && before.sighash_type == after.sighash_type
+                && before.redeem_script == after.redeem_script
+                && before.witness_script == after.witness_script;
+            if signed && !kept {
+                return Err(WorkflowError::SignedInputChanged(index));
+            }
+        }
+        self.psbt = updated;
+        self.history.push(role);
+        Ok(())
+    }
+
+    /// Updater step, `update` adds what it knows, UTXOs, scripts and key origins
+    pub fn update<F: FnOnce(&mut Psbt)>(&mut self, update: F) -> Result<(), WorkflowError> {
+        self.check_order(PsbtRole::Updater)?;
+        let mut updated = self.psbt.clone();
+        update(&mut updated);
+        self.commit(PsbtRole::Updater, updated)
+    }
+
+    pub fn sign(&mut self, index: usize, key: &PrivateKey) -> Result<(), WorkflowError> {
+        self.check_order(PsbtRole::Signer)?;
+        let mut updated = self.psbt.clone();
+        updated.sign(index, key)?;
+        self.commit(PsbtRole::Signer, updated)
+    }
+
+    /// Combiner step, merges the PSBTs of the other parties in their serialization order
+    /// so every party combining the same set gets the same bytes
+    pub fn combine(&mut self, others: &[Psbt]) -> Result<(), WorkflowError> {
+        self.check_order(PsbtRole::Combiner)?;
+        if others
+            .iter()
+            .any(|other| other.unsigned_tx != self.psbt.unsigned_tx)
+        {
+            return Err(WorkflowError::TransactionChanged);
+        }
+        let mut others: Vec<Vec<u8>> = others.iter().map(|other| other.serialize()).collect();
+        others.sort();
+        let mut updated = self.psbt.clone();
+        for other in others {
+            updated.combine(&Psbt::parse(&other)?)?;
+        }
+        self.commit(PsbtRole::Combiner, updated)
+    }
+
+    pub fn finalize(&mut self) -> Result<(), WorkflowError> {
+        self.check_order(PsbtRole::Finalizer)?;
+        self.psbt.finalize()?;
+        self.history.push(PsbtRole::Finalizer);
+        Ok(())
+    }
+
+    pub fn extract(&mut self) -> Result<Transaction, WorkflowError> {
+        self.check_order(PsbtRole::Extractor)?;
+        let tx = self.psbt.extract()?;
+        self.history.push(PsbtRole::Extractor);
+        Ok(tx)
+    }
+}
+
+mod test {
+    use super::{PsbtRole, PsbtWorkflow, WorkflowError};
+    use crate::psbt::PsbtError;
+    use crate::transaction::{
+        PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxHash, TxInput, TxInputSequence,
+        TxLocktime, TxOutput, TxVersion,
+    };
+    use crate::wallet::{PrivateKey, U256};
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_two_of_two_workflow() {
+        let alice = PrivateKey::new(U256::from(0xa11ce_u32));
+        let bob = PrivateKey::new(U256::from(0xb0b_u32));
+        let mut witness_script = vec![0x52, 0x21];
+        witness_script.extend_from_slice(&alice.point.compressed_sec());
+        witness_script.push(0x21);
+        witness_script.extend_from_slice(&bob.point.compressed_sec());
+        witness_script.extend_from_slice(&[0x52, 0xae]);
+        let mut program = vec![0x00, 0x20];
+        program.extend_from_slice(&Sha256::digest(&witness_script));
+
+        let tx = Transaction::new(
+            TxVersion::new(2),
+            vec![TxInput::new(
+                TxHash::new(&[9; 32]).unwrap().1,
+                PreTxIndex::new(0),
+                ScriptSig { content: vec![] },
+                TxInputSequence::new(0xffff_fffd),
+            )],
+            vec![TxOutput {
+                amount: 90_000.into(),
+                script_pub_key: ScriptPubKey {
+                    content: vec![0x51],
+                },
+            }],
+            TxLocktime::new(0),
+            false,
+        );
+        let mut coordinator = PsbtWorkflow::create(tx).unwrap();
+        coordinator
+            .update(|psbt| {
+                psbt.inputs[0].witness_utxo = Some(TxOutput {
+                    amount: 100_000.into(),
+                    script_pub_key: ScriptPubKey {
+                        content: program.clone(),
+                    },
+                });
+                psbt.inputs[0].witness_script = Some(witness_script.clone());
+            })
+            .unwrap();
+        assert_eq!(
+            coordinator.update(|psbt| psbt.unsigned_tx.outputs.clear()),
+            Err(WorkflowError::TransactionChanged)
+        );
+        assert_eq!(
+            coordinator.extract(),
+            Err(WorkflowError::OutOfOrder {
+                role: PsbtRole::Extractor,
+                last: PsbtRole::Updater
+            })
+        );
+
+        // each cosigner signs its own copy
+        let mut cosigners: Vec<PsbtWorkflow> = [&alice, &bob]
+            .iter()
+            .map(|key| {
+                let mut cosigner = PsbtWorkflow::resume(coordinator.psbt().clone());
+                cosigner.sign(0, key).unwrap();
+                cosigner
+            })
+            .collect();
+        assert_eq!(
+            cosigners[0].update(|psbt| psbt.inputs[0].witness_script = None),
+            Err(WorkflowError::SignedInputChanged(0))
+        );
+        let signed: Vec<_> = cosigners.iter().map(|c| c.psbt().clone()).collect();
+
+        // the merge does not depend on the order the cosigners answered in
+        let mut reversed = coordinator.clone();
+        coordinator.combine(&signed).unwrap();
+        reversed
+            .combine(&[signed[1].clone(), signed[0].clone()])
+            .unwrap();
+        assert_eq!(coordinator.psbt().serialize(), reversed.psbt().serialize());
+        assert_eq!(coordinator.psbt().inputs[0].partial_sigs.len(), 2);
+
+        coordinator.finalize().unwrap();
+        assert_eq!(
+            coordinator.sign(0, &alice),
+            Err(WorkflowError::OutOfOrder {
+                role: PsbtRole::Signer,
+                last: PsbtRole::Finalizer
+            })
+        );
+        let tx = coordinator.extract().unwrap();
+        let witness = &tx.inputs[0].witness;
+        assert_eq!(witness.len(), 4);
+        assert!(witness[0].is_empty());
+        assert_eq!(witness[3], witness_script);
+        assert_eq!(
+            coordinator.history(),
+            &[
+                PsbtRole::Creator,
+                PsbtRole::Updater,
+                PsbtRole::Combiner,
+                PsbtRole::Finalizer,
+                PsbtRole::Extractor
+            ]
+        );
+
+        // a single signature does not satisfy 2 of 2
+        let mut lone = cosigners.remove(0);
+        assert_eq!(
+            lone.finalize(),
+            Err(WorkflowError::Psbt(PsbtError::MissingSignatures(0)))
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use failure::Error;
+
+use crate::transaction::TxOutputAmount;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum RateError {
+    #[fail(display = "no {} rate in the response", _0)]
+    UnknownCurrency(String),
+    #[fail(display = "rate response parse error")]
+    ParseError,
+}
+
+/// Price of one bitcoin in a fiat `currency`, e.g. "USD"
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeRate {
+    pub currency: String,
+    pub price: f64,
+}
+
+/// Source of exchange rates, an exchange api or a fixed rate in tests
+pub trait RateProvider {
+    fn rate(&mut self, currency: &str) -> Result<ExchangeRate, Error>;
+}
+
+/// Last prices of blockchain.info's `/ticker`, one request per lookup
+#[derive(Debug, Clone, Default)]
+pub struct BlockchainInfoRates;
+
+impl BlockchainInfoRates {
+    pub fn new() -> Self {
+        BlockchainInfoRates
+    }
+
+    /// `/ticker`, e.g. `{"USD":{"15m":61234.5,"last":61234.5,"symbol":"USD"},"EUR":{...}}`
+    fn parse_ticker(body: &str, currency: &str) -> Result<ExchangeRate, RateError> {
+        let ticker: serde_json::Value =
+            serde_json::from_str(body).map_err(|_| RateError::ParseError)?;
+        let entry = ticker
+            .get(currency)
+            .ok_or_else(|| RateError::UnknownCurrency(currency.to_string()))?;
+        match entry["last"].as_f64() {
+            Some(price) if price > 0.0 => Ok(ExchangeRate {
+                currency: currency.to_string(),
+                price,
+            }),
+            _ => Err(RateError::ParseError),
+        }
+    }
+}
+
+impl RateProvider for BlockchainInfoRates {
+    fn rate(&mut self, currency: &str) -> Result<ExchangeRate, Error> {
+        let body = reqwest::get("https://blockchain.info/ticker")?.text()?;
+        Ok(BlockchainInfoRates::parse_ticker(
+            &body,
+            &currency.to_uppercase(),
+        )?)
+    }
+}
+
+impl TxOutputAmount {
+    /// Value at `rate`, rounded to cents
+    pub fn to_fiat(&self, rate: &ExchangeRate) -> String {
+        let value = u64::from(*self) as f64 / 100_000_000.0 * rate.price;
+        format!("{:.2} {}", value, rate.currency)
+    }
+}
+
+mod test {
+    use super::{BlockchainInfoRates, ExchangeRate, RateError};
+    use crate::transaction::TxOutputAmount;
+
+    #[test]
+    fn test_rates() {
+        let body = r#"{"USD":{"15m":61234.5,"last":61234.5,"buy":61234.5,"sell":61234.5,"symbol":"USD"},
+                       "EUR":{"15m":56100.25,"last":56100.25,"symbol":"EUR"}}"#;
+        let eur = BlockchainInfoRates::parse_ticker(body, "EUR").unwrap();
+        assert_eq!(
+            eur,
+            ExchangeRate {
+                currency: "EUR".to_string(),
+                price: 56_100.25
+            }
+        );
+        assert_eq!(
+            BlockchainInfoRates::parse_ticker(body, "JPY"),
+            Err(RateError::UnknownCurrency("JPY".to_string()))
+        );
+        assert_eq!(
+            BlockchainInfoRates::parse_ticker("<html>", "USD"),
+            Err(RateError::ParseError)
+        );
+
+        assert_eq!(
+            TxOutputAmount::from(100_000_000).to_fiat(&eur),
+            "56100.25 EUR"
+        );
+        assert_eq!(TxOutputAmount::from(12_345).to_fiat(&eur), "6.93 EUR");
+        assert_eq!(TxOutputAmount::from(0).to_fiat(&eur), "0.00 EUR");
+    }
+}
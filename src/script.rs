@@ -1,37 +1,167 @@
+mod condition_stack;
+mod locktime;
+mod op_code;
 mod op_function;
+mod script_num;
+mod sighash_cache;
 mod stack_element;
+mod tapscript;
+mod verify_flags;
 
-use bytes::{BufMut, BytesMut};
-use nom::bytes::streaming::take;
-use nom::number::complete::{le_u16, le_u8};
 use nom::IResult;
 
+use std::convert::TryFrom;
 use std::ops::Add;
 
-use crate::transaction::Varint;
-use crate::wallet::{Hash256, Hex};
-use op_function::Stack;
-use stack_element::{OpCode, OperationType, StackElement};
+use crate::encode::{decode_error, hex_bytes, Decodable, Encodable, HexError};
+use crate::transaction::{ScriptPubKey, ScriptSig, Varint};
+use crate::wallet::{DerViolation, Hash256, Hex, Signature};
+use condition_stack::ConditionStack;
+use locktime::op_check_lock;
+pub use locktime::LockTimeContext;
+pub use op_code::OpCode;
+use op_function::{cast_to_bool, multisig_key_count, Stack, MAX_PUBKEYS_PER_MULTISIG};
+pub use script_num::{ScriptNum, ScriptNumError, DEFAULT_MAX_NUM_SIZE};
+pub use sighash_cache::SigHashCache;
+use stack_element::OperationType;
+pub use stack_element::StackElement;
+use tapscript::op_check_sig_tapscript;
+pub use tapscript::{is_op_success, verify_taproot_input, TapscriptContext};
+pub use verify_flags::VerifyFlags;
 
-#[derive(Fail, Debug)]
+/// Consensus limits checked by `Script::evaluate`
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+pub const MAX_STACK_SIZE: usize = 1000;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
 pub enum ScriptError {
-    #[fail(display = "parse hex script length error")]
-    ParseLengthError,
-    #[fail(display = "nom parse error")]
+    #[fail(display = "script length prefix parse error")]
     NomParseError,
+    #[fail(
+        display = "script length {} exceeds remaining {} bytes",
+        length, remaining
+    )]
+    LengthExceedsInput { length: usize, remaining: usize },
+    #[fail(
+        display = "{} at offset {} is missing its {} bytes length",
+        opcode, offset, width
+    )]
+    TruncatedPushLength {
+        opcode: String,
+        offset: usize,
+        width: usize,
+    },
+    #[fail(
+        display = "{} push of {} bytes at offset {} exceeds remaining {} bytes",
+        opcode, len, offset, remaining
+    )]
+    PushExceedsRemaining {
+        opcode: String,
+        offset: usize,
+        len: usize,
+        remaining: usize,
+    },
+    #[fail(display = "non minimal push at command {}", _0)]
+    NonMinimalPush(usize),
+    #[fail(display = "OP_IF/OP_NOTIF argument must be empty or 0x01")]
+    NonMinimalIf,
+    #[fail(display = "OP_ELSE, OP_ENDIF or the script end without a matching OP_IF")]
+    UnbalancedConditional,
+    #[fail(display = "script of {} bytes exceeds the 10000 bytes limit", _0)]
+    ScriptSizeLimit(usize),
+    #[fail(display = "push of {} bytes exceeds the 520 bytes element limit", _0)]
+    PushSizeLimit(usize),
+    #[fail(display = "{} non push opcodes exceed the 201 opcodes limit", _0)]
+    OpCountLimit(usize),
+    #[fail(
+        display = "stack and altstack depth {} exceeds the 1000 elements limit",
+        _0
+    )]
+    StackSizeLimit(usize),
+    #[fail(display = "tapscript signature checked against an empty public key")]
+    TapscriptEmptyPubkey,
+    #[fail(display = "tapscript signature checks exceed the sigops budget")]
+    SigopsBudgetExceeded,
+    #[fail(display = "invalid non empty schnorr signature")]
+    SchnorrSigInvalid,
+    #[fail(display = "op code: {} is disabled in tapscript", _0)]
+    DisabledInTapscript(u8),
+    #[fail(display = "op code: {} is disabled", _0)]
+    DisabledOpCode(u8),
+    #[fail(display = "witness script must leave exactly one element on the stack")]
+    CleanStack,
+    #[fail(display = "spent output is not a witness v1 program")]
+    NotTaproot,
+    #[fail(display = "witness is empty")]
+    EmptyWitness,
+    #[fail(display = "control block parse error")]
+    InvalidControlBlock,
+    #[fail(display = "script and control block do not commit to the output key")]
+    WitnessProgramMismatch,
+    #[fail(display = "non strict DER signature: {}", _0)]
+    SigDer(DerViolation),
+    #[fail(display = "OP_CHECKMULTISIG dummy element is not empty")]
+    SigNullDummy,
+    #[fail(display = "negative OP_CHECKLOCKTIMEVERIFY or OP_CHECKSEQUENCEVERIFY operand")]
+    NegativeLocktime,
+    #[fail(display = "locktime or sequence of the spending input does not satisfy the script")]
+    UnsatisfiedLocktime,
+    #[fail(display = "witness version {} is reserved for soft forks", _0)]
+    DiscourageUpgradableWitnessProgram(u8),
     #[fail(display = "serialize too long element error")]
     SerializeTooLongError,
     #[fail(display = "op code: {} evaluate error", _0)]
     OpCodeEvaluateError(u8),
 }
 
+fn push_name(opcode: u8) -> String {
+    OpCode::from(opcode).to_string()
+}
+
+/// OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_CHECKMULTISIG and OP_CHECKMULTISIGVERIFY
+fn is_check_sig(op_code: OpCode) -> bool {
+    match op_code {
+        OpCode::OpCheckSig
+        | OpCode::OpCheckSigVerify
+        | OpCode::OpCheckMultisig
+        | OpCode::OpCheckMultisigVerify => true,
+        _ => false,
+    }
+}
+
+/// Bytes `start..end` of a script that the lossy parser could not decode
+#[derive(Debug, PartialEq, Eq)]
+pub struct BadRegion {
+    pub start: usize,
+    pub end: usize,
+    pub error: ScriptError,
+}
+
+/// A script command as `Script::instructions` yields it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction<'a> {
+    /// Data pushed by a push opcode, empty for OP_0
+    PushBytes(&'a [u8]),
+    /// Any other opcode, OP_1NEGATE and OP_1 .. OP_16 included
+    Op(u8),
+}
+
+#[derive(Debug, Clone)]
 pub struct Script {
     cmds: Stack,
+    /// Pushes parsed with another opcode than the one `serialize` picks for their length,
+    /// as (command index, push opcode), so they round trip and MINIMALDATA can see them
+    non_canonical_pushes: Vec<(usize, u8)>,
 }
 
 impl Script {
     pub fn new() -> Self {
-        Script { cmds: Vec::new() }
+        Script {
+            cmds: Vec::new(),
+            non_canonical_pushes: Vec::new(),
+        }
     }
 
     pub fn push_opcode(&mut self, opcode: OpCode) {
@@ -42,120 +172,654 @@ impl Script {
         self.cmds.push(StackElement::DataElement(data.to_vec()))
     }
 
-    // todo
-    // How to chain the error of nom and failure
+    pub fn iter(&self) -> impl Iterator<Item = &StackElement> {
+        self.cmds.iter()
+    }
+
+    /// Number of commands, a push with its data counts once
+    pub fn len(&self) -> usize {
+        self.cmds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cmds.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&StackElement> {
+        self.cmds.get(index)
+    }
+
+    /// The commands with pushes as their data and opcodes as their byte
+    pub fn instructions(&self) -> impl Iterator<Item = Instruction<'_>> {
+        self.cmds.iter().map(|cmd| match cmd {
+            StackElement::DataElement(data) => Instruction::PushBytes(data),
+            StackElement::OpCode(OpCode::Op0) => Instruction::PushBytes(&[]),
+            StackElement::OpCode(op_code) => Instruction::Op(op_code.num()),
+        })
+    }
+
+    /// Parse a length prefixed script
     pub fn parse(input: &[u8]) -> Result<(&[u8], Self), ScriptError> {
-        let (input, (consumed_exactly_len, cmds)) =
-            Self::nom_parse(input).or(Err(ScriptError::NomParseError))?;
-        if !consumed_exactly_len {
-            Err(ScriptError::ParseLengthError)
-        } else {
-            Ok((input, Script { cmds }))
+        let (input, length) = Varint::parse(input).or(Err(ScriptError::NomParseError))?;
+        let length = Into::<u64>::into(length) as usize;
+        if length > input.len() {
+            return Err(ScriptError::LengthExceedsInput {
+                length,
+                remaining: input.len(),
+            });
+        }
+        let (content, input) = input.split_at(length);
+        match Self::parse_cmds(content) {
+            (script, None) => Ok((input, script)),
+            (_, Some(bad_region)) => Err(bad_region.error),
         }
     }
 
-    fn nom_parse(input: &[u8]) -> IResult<&[u8], (bool, Stack)> {
-        let (input, length) = Varint::parse(input)?;
-        let length = Into::<u64>::into(length) as usize;
-        let mut cmds = Vec::new();
-        let mut count = 0;
+    /// Parse raw script bytes without a length prefix, keeping every command before the
+    /// first malformed push and marking the undecodable tail instead of failing
+    pub fn parse_lossy(content: &[u8]) -> (Self, Vec<BadRegion>) {
+        let (script, bad_region) = Self::parse_cmds(content);
+        (script, bad_region.into_iter().collect())
+    }
+
+    /// Offsets are relative to the first script byte, after the length prefix
+    fn parse_cmds(content: &[u8]) -> (Self, Option<BadRegion>) {
+        let mut script = Script::new();
+        let mut offset = 0;
+
+        while offset < content.len() {
+            let start = offset;
+            let current = content[offset];
+            offset += 1;
 
-        let (mut outer_input, mut current) = (input, 0);
-        while count < length {
-            let (input, current) = le_u8(outer_input)?;
-            count += 1;
-
-            outer_input = if current >= 0x01 && current <= 0x4b {
-                let (input, bytes) = take(current)(input)?;
-                count += current as usize;
-                cmds.push(StackElement::DataElement(bytes.to_vec()));
-                input
-            } else if current == 0x4c {
-                // OP_PUSHDATA1
-                let (input, data_len) = le_u8(input)?;
-                count += 1;
-                let (input, bytes) = take(data_len)(input)?;
-                count += data_len as usize;
-                cmds.push(StackElement::DataElement(bytes.to_vec()));
-                input
-            } else if current == 0x4d {
-                // OP_PUSHDATA2
-                let (input, data_len) = le_u16(input)?;
-                count += 1;
-                let (input, bytes) = take(data_len)(input)?;
-                count += data_len as usize;
-                cmds.push(StackElement::DataElement(bytes.to_vec()));
-                input
-            } else {
-                let op_code = current;
-                cmds.push(StackElement::OpCode(OpCode::new(op_code)));
-                input
+            let width = match current {
+                0x4c => 1,
+                0x4d => 2,
+                0x4e => 4,
+                _ => 0,
             };
+            if current == 0x00 || current > 0x4e {
+                script.push_opcode(OpCode::from(current));
+                continue;
+            }
+            if offset + width > content.len() {
+                let error = ScriptError::TruncatedPushLength {
+                    opcode: push_name(current),
+                    offset: start,
+                    width,
+                };
+                return (script, Some(BadRegion::new(start, content.len(), error)));
+            }
+
+            let len = match current {
+                // OP_PUSHDATA1, OP_PUSHDATA2, OP_PUSHDATA4
+                0x4c | 0x4d | 0x4e => content[offset..offset + width]
+                    .iter()
+                    .rev()
+                    .fold(0usize, |acc, byte| (acc << 8) | *byte as usize),
+                _ => current as usize,
+            };
+            offset += width;
+            let remaining = content.len() - offset;
+            if len > remaining {
+                let error = ScriptError::PushExceedsRemaining {
+                    opcode: push_name(current),
+                    offset: start,
+                    len,
+                    remaining,
+                };
+                return (script, Some(BadRegion::new(start, content.len(), error)));
+            }
+            if current != push_opcode(len) {
+                script
+                    .non_canonical_pushes
+                    .push((script.cmds.len(), current));
+            }
+            script.push_data_ele(&content[offset..offset + len]);
+            offset += len;
         }
 
-        Ok((input, (count == length, cmds)))
+        (script, None)
     }
 
-    pub fn serialize(&self) -> Result<Vec<u8>, ScriptError> {
-        let mut buf_len = 9usize + 9 + 4;
-        for i in &self.cmds {
-            match i {
-                StackElement::OpCode(_) => buf_len += 1,
-                StackElement::DataElement(data) => buf_len += 1 + 9 + data.len(),
-            }
+    /// Parse raw script bytes without a length prefix, failing on a malformed push
+    pub fn from_raw(content: &[u8]) -> Result<Self, ScriptError> {
+        match Self::parse_cmds(content) {
+            (script, None) => Ok(script),
+            (_, Some(bad_region)) => Err(bad_region.error),
         }
+    }
 
-        let mut buf = BytesMut::with_capacity(buf_len);
-        for i in &self.cmds {
-            match i {
-                StackElement::OpCode(op_code) => buf.put_u8(op_code.num()),
+    /// Raw script bytes without the length prefix, pushes of any length included. The
+    /// exact bytes `from_raw` parsed, non minimal pushes stay as they were.
+    pub fn to_raw(&self) -> Vec<u8> {
+        let mut raw = Vec::new();
+        self.write_cmds(&mut raw, usize::MAX)
+            .expect("no push is longer than usize::MAX");
+        raw
+    }
+
+    /// `from_raw` of hex, like explorers show a script pubkey
+    pub fn from_hex(hex: &str) -> Result<Self, HexError> {
+        Self::from_raw(&hex_bytes(hex)?).map_err(HexError::Script)
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.to_raw())
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, ScriptError> {
+        let mut raw_ret = self.raw_serialize()?;
+        let mut ret = Varint::encode(raw_ret.len() as u64).unwrap().to_vec();
+        ret.append(&mut raw_ret);
+        Ok(ret)
+    }
+
+    /// Serialization without the length prefix
+    pub fn raw_serialize(&self) -> Result<Vec<u8>, ScriptError> {
+        let mut buf = Vec::new();
+        self.write_cmds(&mut buf, 520)?;
+        Ok(buf)
+    }
+
+    /// Append the commands to `buf`, failing on a push longer than `max_push`
+    fn write_cmds(&self, buf: &mut Vec<u8>, max_push: usize) -> Result<(), ScriptError> {
+        for (index, cmd) in self.cmds.iter().enumerate() {
+            match cmd {
+                StackElement::OpCode(op_code) => buf.push(op_code.num()),
                 StackElement::DataElement(data) => {
                     let len = data.len();
-                    if len < 0x4b {
-                        // less than 75 bytes
-                        buf.put(Varint::encode(len as u64).unwrap());
-                    } else if len > 75 && len < 0x100 {
-                        buf.put_u8(0x4c);
-                        buf.put(Varint::encode(len as u64).unwrap());
-                    } else if len >= 0x100 && len <= 520 {
-                        buf.put_u8(0x4d);
-                        buf.put(Varint::encode(len as u64).unwrap());
-                    } else {
+                    if len > max_push {
                         return Err(ScriptError::SerializeTooLongError);
                     }
-                    buf.put(data);
+                    let opcode = self.push_opcode_at(index, len);
+                    buf.push(opcode);
+                    match opcode {
+                        0x4c => buf.push(len as u8),
+                        0x4d => buf.extend_from_slice(&(len as u16).to_le_bytes()),
+                        0x4e => buf.extend_from_slice(&(len as u32).to_le_bytes()),
+                        _ => {}
+                    }
+                    buf.extend_from_slice(data);
                 }
             }
         }
-        let mut raw_ret = buf.take().to_vec();
-        buf.put(Varint::encode(raw_ret.len() as u64).unwrap());
-        let mut ret = buf.take().to_vec();
-        ret.append(&mut raw_ret);
-        Ok(ret)
+        Ok(())
+    }
+
+    /// Legacy script code after the OP_CODESEPARATOR at command `codesep_pos`, the last
+    /// one executed, with every OP_CODESEPARATOR removed
+    pub fn script_code(&self, codesep_pos: Option<usize>) -> Vec<u8> {
+        self.code_after(codesep_pos, false)
+    }
+
+    /// BIP143 script code of a witness script, the commands after the OP_CODESEPARATOR at
+    /// `codesep_pos` with the later OP_CODESEPARATORs kept
+    pub fn witness_script_code(&self, codesep_pos: Option<usize>) -> Vec<u8> {
+        self.code_after(codesep_pos, true)
+    }
+
+    fn code_after(&self, codesep_pos: Option<usize>, keep_codeseparators: bool) -> Vec<u8> {
+        let mut script = Script::new();
+        for (index, cmd) in self.cmds.iter().enumerate() {
+            if codesep_pos.map_or(false, |pos| index <= pos) {
+                continue;
+            }
+            if let StackElement::OpCode(OpCode::OpCodeseparator) = cmd {
+                if !keep_codeseparators {
+                    continue;
+                }
+            }
+            if let Some((_, opcode)) = self.non_canonical_pushes.iter().find(|(i, _)| *i == index) {
+                script
+                    .non_canonical_pushes
+                    .push((script.cmds.len(), *opcode));
+            }
+            script.cmds.push(cmd.clone());
+        }
+        script.raw_serialize().unwrap_or_default()
+    }
+
+    fn push_opcode_at(&self, index: usize, len: usize) -> u8 {
+        self.non_canonical_pushes
+            .iter()
+            .find(|(i, _)| *i == index)
+            .map_or_else(|| push_opcode(len), |(_, opcode)| *opcode)
+    }
+
+    /// Serialized size without the length prefix
+    fn byte_len(&self) -> usize {
+        self.cmds
+            .iter()
+            .enumerate()
+            .map(|(index, cmd)| match cmd {
+                StackElement::OpCode(_) => 1,
+                StackElement::DataElement(data) => {
+                    let width = match self.push_opcode_at(index, data.len()) {
+                        0x4c => 1,
+                        0x4d => 2,
+                        0x4e => 4,
+                        _ => 0,
+                    };
+                    1 + width + data.len()
+                }
+            })
+            .sum()
+    }
+
+    /// Size and element limits, checked over every command including the ones in branches
+    /// that are not executed. The opcode count is kept while the script runs.
+    fn check_limits(&self) -> Result<(), ScriptError> {
+        let size = self.byte_len();
+        if size > MAX_SCRIPT_SIZE {
+            return Err(ScriptError::ScriptSizeLimit(size));
+        }
+        for cmd in &self.cmds {
+            match cmd {
+                StackElement::DataElement(data) if data.len() > MAX_SCRIPT_ELEMENT_SIZE => {
+                    return Err(ScriptError::PushSizeLimit(data.len()));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn first_disabled_opcode(&self) -> Option<u8> {
+        self.cmds.iter().find_map(|cmd| match cmd {
+            StackElement::OpCode(op_code) if op_code.is_disabled() => Some(op_code.num()),
+            _ => None,
+        })
+    }
+
+    /// Signature checks the script may run, legacy sigops. OP_CHECKMULTISIG counts as 20
+    /// unless `accurate` and its key count is an OP_1 .. OP_16 right before it, the P2SH
+    /// and witness script way.
+    pub fn sigop_count(&self, accurate: bool) -> usize {
+        let mut count = 0;
+        let mut last: Option<OpCode> = None;
+        for cmd in &self.cmds {
+            let op_code = match cmd {
+                StackElement::OpCode(op_code) => *op_code,
+                StackElement::DataElement(_) => {
+                    last = None;
+                    continue;
+                }
+            };
+            match op_code {
+                OpCode::OpCheckSig | OpCode::OpCheckSigVerify => count += 1,
+                OpCode::OpCheckMultisig | OpCode::OpCheckMultisigVerify => {
+                    match last.and_then(|last| last.small_int()) {
+                        Some(n) if accurate && n > 0 => count += n as usize,
+                        _ => count += MAX_PUBKEYS_PER_MULTISIG as usize,
+                    }
+                }
+                _ => {}
+            }
+            last = Some(op_code);
+        }
+        count
+    }
+
+    /// Data of the last command when it is a push, the redeem script of a P2SH script sig
+    pub fn last_push(&self) -> Option<&[u8]> {
+        match self.cmds.last()? {
+            StackElement::DataElement(data) => Some(data),
+            StackElement::OpCode(_) => None,
+        }
+    }
+
+    /// Only data pushes and OP_0 .. OP_16
+    pub fn is_push_only(&self) -> bool {
+        self.cmds.iter().all(|cmd| match cmd {
+            StackElement::DataElement(_) => true,
+            StackElement::OpCode(op_code) => op_code.is_push(),
+        })
+    }
+
+    /// Items a push only script leaves on the stack, OP_0 .. OP_16 and OP_1NEGATE as
+    /// their numbers. None when it runs any other opcode.
+    pub fn pushed_items(&self) -> Option<Vec<Vec<u8>>> {
+        self.cmds
+            .iter()
+            .map(|cmd| match cmd {
+                StackElement::DataElement(data) => Some(data.clone()),
+                StackElement::OpCode(op_code) => {
+                    op_code.small_int().map(|num| ScriptNum::from(num).encode())
+                }
+            })
+            .collect()
+    }
+
+    /// Weight of the largest witness spending a P2WSH output of this script: the item
+    /// count, the satisfying items with their length prefixes and the script itself.
+    /// Signatures count 72 bytes with their sighash byte and keys are taken compressed.
+    /// None for scripts other than a key, a key hash or a bare multisig.
+    pub fn max_satisfaction_weight(&self) -> Option<usize> {
+        const SIG: usize = 1 + 72;
+        const KEY: usize = 1 + 33;
+        let small_int = |cmd: &StackElement| match cmd {
+            StackElement::OpCode(op_code) => match op_code.small_int() {
+                Some(num) if num > 0 => Some(num as usize),
+                _ => None,
+            },
+            _ => None,
+        };
+        let is_key = |cmd: &StackElement| match cmd {
+            StackElement::DataElement(data) => data.len() == 33 || data.len() == 65,
+            _ => false,
+        };
+        let is_op = |cmd: &StackElement, code: OpCode| match cmd {
+            StackElement::OpCode(op_code) => *op_code == code,
+            _ => false,
+        };
+        let cmds = &self.cmds;
+        // (item count, item bytes)
+        let (items, size) = match cmds.len() {
+            // <key> OP_CHECKSIG
+            2 if is_key(&cmds[0]) && is_op(&cmds[1], OpCode::OpCheckSig) => (1, SIG),
+            // OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG
+            5 if is_op(&cmds[0], OpCode::OpDup)
+                && is_op(&cmds[1], OpCode::OpHash160)
+                && cmds[2].as_bytes().map_or(false, |hash| hash.len() == 20)
+                && is_op(&cmds[3], OpCode::OpEqualVerify)
+                && is_op(&cmds[4], OpCode::OpCheckSig) =>
+            {
+                (2, SIG + KEY)
+            }
+            // m <key>... n OP_CHECKMULTISIG, plus the dummy element
+            len if len >= 4 && is_op(&cmds[len - 1], OpCode::OpCheckMultisig) => {
+                let m = small_int(&cmds[0])?;
+                let n = small_int(&cmds[len - 2])?;
+                if n != len - 3 || m > n || !cmds[1..len - 2].iter().all(is_key) {
+                    return None;
+                }
+                (m + 1, 1 + m * SIG)
+            }
+            _ => return None,
+        };
+        let script_len = self.raw_serialize().ok()?.len();
+        Some(
+            Varint::encoded_len(items as u64 + 1)
+                + size
+                + Varint::encoded_len(script_len as u64)
+                + script_len,
+        )
+    }
+
+    /// Script asm like Bitcoin Core, pushes up to 4 bytes are shown as numbers
+    pub fn asm(&self) -> String {
+        self.to_asm(false)
+    }
+
+    /// With `decode_sighash` a pushed DER signature is shown with its sighash type,
+    /// e.g. `3045...01` becomes `3045...[ALL]`, the way scriptSigs are decoded
+    pub fn to_asm(&self, decode_sighash: bool) -> String {
+        self.cmds
+            .iter()
+            .map(|cmd| match cmd {
+                StackElement::OpCode(op_code) => op_code.name().to_string(),
+                StackElement::DataElement(data) if data.len() <= 4 => {
+                    ScriptNum::decode(data, false, DEFAULT_MAX_NUM_SIZE)
+                        .expect("at most 4 bytes")
+                        .to_string()
+                }
+                StackElement::DataElement(data) => {
+                    let sighash = match data.last() {
+                        Some(0x01) => Some("[ALL]"),
+                        Some(0x02) => Some("[NONE]"),
+                        Some(0x03) => Some("[SINGLE]"),
+                        Some(0x81) => Some("[ALL|ANYONECANPAY]"),
+                        Some(0x82) => Some("[NONE|ANYONECANPAY]"),
+                        Some(0x83) => Some("[SINGLE|ANYONECANPAY]"),
+                        _ => None,
+                    };
+                    match sighash {
+                        Some(sighash)
+                            if decode_sighash && Signature::is_strict_der(data).is_ok() =>
+                        {
+                            format!("{}{}", hex::encode(&data[..data.len() - 1]), sighash)
+                        }
+                        _ => data.hex(),
+                    }
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// Whether MINIMALDATA rejects the push at command `index`
+    fn is_non_minimal_push(&self, index: usize, data: &[u8]) -> bool {
+        // a single byte 1..16 or 0x81 has to be OP_1..OP_16 or OP_1NEGATE
+        let small_num = data.len() == 1 && ((1..=16).contains(&data[0]) || data[0] == 0x81);
+        small_num || self.non_canonical_pushes.iter().any(|(i, _)| *i == index)
     }
 
     pub fn evaluate(&self, hash: Option<Hash256>) -> Result<bool, ScriptError> {
-        let mut cmds = self.cmds.clone();
-        let mut stack = Stack::new();
+        self.evaluate_with_flags(hash, VerifyFlags::default())
+    }
+
+    pub fn evaluate_with_flags(
+        &self,
+        hash: Option<Hash256>,
+        flags: VerifyFlags,
+    ) -> Result<bool, ScriptError> {
+        self.run(
+            Stack::new(),
+            SigChecker::Legacy(&mut |_, _| hash.expect("this op code need a hash256")),
+            None,
+            flags,
+        )
+    }
+
+    /// Evaluate with the signature hashes of the spending input, each computed once, and
+    /// its locktime and sequence
+    pub fn evaluate_with_cache(
+        &self,
+        cache: &mut SigHashCache,
+        flags: VerifyFlags,
+    ) -> Result<bool, ScriptError> {
+        let locks = cache.lock_time_context();
+        self.run(
+            Stack::new(),
+            SigChecker::Legacy(&mut |sighash_type, codesep_pos| {
+                cache.sig_hash(sighash_type, codesep_pos)
+            }),
+            Some(&locks),
+            flags,
+        )
+    }
+
+    /// Run as a segwit v0 witness script on the `witness` items, or as a P2SH redeem script
+    /// on the items its script sig pushed. Signature checks get their hash from `sig_hash`
+    /// like `evaluate_with_cache`, timelocks are checked against `locks`, without them
+    /// enforced timelocks fail.
+    pub fn evaluate_witness(
+        &self,
+        witness: &[Vec<u8>],
+        sig_hash: &mut dyn FnMut(u32, Option<usize>) -> Hash256,
+        locks: Option<&LockTimeContext>,
+        flags: VerifyFlags,
+    ) -> Result<bool, ScriptError> {
+        let stack = witness
+            .iter()
+            .map(|item| StackElement::DataElement(item.clone()))
+            .collect();
+        self.run(stack, SigChecker::Legacy(sig_hash), locks, flags)
+    }
+
+    /// Run as a script sig on an empty stack and return the items it leaves for the script
+    /// pubkey, signature checks and timelocks work like `evaluate_witness`
+    pub fn execute_script_sig(
+        &self,
+        sig_hash: &mut dyn FnMut(u32, Option<usize>) -> Hash256,
+        locks: Option<&LockTimeContext>,
+        flags: VerifyFlags,
+    ) -> Result<Vec<Vec<u8>>, ScriptError> {
+        let stack = self.execute(Stack::new(), SigChecker::Legacy(sig_hash), locks, flags)?;
+        Ok(stack
+            .iter()
+            .filter_map(|element| element.as_bytes().map(<[u8]>::to_vec))
+            .collect())
+    }
+
+    /// Run as a BIP342 tapscript leaf on the witness `stack`. Signatures are schnorr,
+    /// MINIMALIF always applies, the 10000 bytes and 201 opcodes limits are replaced by
+    /// the sigops budget of `context` and the script must leave exactly one true element.
+    pub fn evaluate_tapscript(
+        &self,
+        stack: Stack,
+        context: &mut TapscriptContext,
+    ) -> Result<bool, ScriptError> {
+        let locks = context.lock_time_context();
+        // taproot came after the timelock soft forks, they always apply
+        self.run(
+            stack,
+            SigChecker::Tapscript(context),
+            Some(&locks),
+            VerifyFlags::MINIMALIF
+                | VerifyFlags::CHECKLOCKTIMEVERIFY
+                | VerifyFlags::CHECKSEQUENCEVERIFY,
+        )
+    }
+
+    fn run(
+        &self,
+        stack: Stack,
+        checker: SigChecker,
+        locks: Option<&LockTimeContext>,
+        flags: VerifyFlags,
+    ) -> Result<bool, ScriptError> {
+        let tapscript = match checker {
+            SigChecker::Tapscript(_) => true,
+            SigChecker::Legacy(_) => false,
+        };
+        let mut stack = self.execute(stack, checker, locks, flags)?;
+        if tapscript || flags.contains(VerifyFlags::CLEANSTACK) {
+            return match stack.pop() {
+                Some(StackElement::DataElement(data)) if stack.is_empty() => {
+                    Ok(cast_to_bool(&data))
+                }
+                _ => Err(ScriptError::CleanStack),
+            };
+        }
+        Ok(stack.pop().map_or(false, |top| top.to_bool()))
+    }
+
+    /// Run the commands on `stack` and return the stack they leave
+    fn execute(
+        &self,
+        mut stack: Stack,
+        mut checker: SigChecker,
+        locks: Option<&LockTimeContext>,
+        flags: VerifyFlags,
+    ) -> Result<Stack, ScriptError> {
+        let tapscript = match checker {
+            SigChecker::Tapscript(_) => true,
+            SigChecker::Legacy(_) => false,
+        };
+        // witness items and the items a script sig pushed for its redeem script
+        for element in stack.iter() {
+            match element {
+                StackElement::DataElement(data) if data.len() > MAX_SCRIPT_ELEMENT_SIZE => {
+                    return Err(ScriptError::PushSizeLimit(data.len()));
+                }
+                _ => {}
+            }
+        }
+        if tapscript {
+            for element in self.cmds.iter() {
+                match element {
+                    StackElement::DataElement(data) if data.len() > MAX_SCRIPT_ELEMENT_SIZE => {
+                        return Err(ScriptError::PushSizeLimit(data.len()));
+                    }
+                    _ => {}
+                }
+            }
+            if stack.len() > MAX_STACK_SIZE {
+                return Err(ScriptError::StackSizeLimit(stack.len()));
+            }
+        } else {
+            self.check_limits()?;
+            #[cfg(not(feature = "op_experiments"))]
+            {
+                if let Some(code) = self.first_disabled_opcode() {
+                    return Err(ScriptError::DisabledOpCode(code));
+                }
+            }
+        }
+
+        let mut conditions = ConditionStack::new();
         let mut altstack = Stack::new();
+        // command index of the last OP_CODESEPARATOR that ran
+        let mut codesep_pos = None;
+        // tapscript has no opcode limit
+        let mut op_count = 0;
 
-        while cmds.len() > 0 {
-            let cmd = cmds.remove(0);
-            match cmd {
-                StackElement::DataElement(d) => stack.push(StackElement::DataElement(d)),
-                StackElement::OpCode(opcode) => {
-                    let opcode_num = opcode.num();
-                    let operation = opcode.operation();
-                    if opcode_num >= 99 && opcode_num <= 100 {
-                        match operation {
-                            OperationType::StackStack(operation) => {
-                                if !(*operation)(&mut stack, &mut cmds) {
-                                    return Err(ScriptError::OpCodeEvaluateError(opcode_num));
-                                }
-                            }
-                            _ => unreachable!(),
+        for (position, cmd) in self.cmds.iter().enumerate() {
+            let executing = conditions.all_true();
+            if let StackElement::OpCode(opcode) = cmd {
+                if !tapscript && !opcode.is_push() {
+                    op_count += 1;
+                    // every key of an executed multisig counts as an opcode too
+                    if executing
+                        && (*opcode == OpCode::OpCheckMultisig
+                            || *opcode == OpCode::OpCheckMultisigVerify)
+                    {
+                        op_count += multisig_key_count(&stack).unwrap_or(0);
+                    }
+                    if op_count > MAX_OPS_PER_SCRIPT {
+                        return Err(ScriptError::OpCountLimit(op_count));
+                    }
+                }
+            }
+            let opcode = match cmd {
+                StackElement::DataElement(d) => {
+                    if executing {
+                        // only pushes that run, like Core
+                        if flags.contains(VerifyFlags::MINIMALDATA)
+                            && self.is_non_minimal_push(position, d)
+                        {
+                            return Err(ScriptError::NonMinimalPush(position));
+                        }
+                        stack.push(StackElement::DataElement(d.clone()));
+                    }
+                    None
+                }
+                // branches not taken are only parsed, except for the conditionals themselves
+                StackElement::OpCode(opcode) if executing || opcode.is_conditional() => {
+                    Some(*opcode)
+                }
+                StackElement::OpCode(_) => None,
+            };
+            if let Some(opcode) = opcode {
+                let opcode_num = opcode.num();
+                if opcode == OpCode::OpIf || opcode == OpCode::OpNotIf {
+                    let mut taken = false;
+                    if executing {
+                        let condition = match stack.pop() {
+                            Some(StackElement::DataElement(d)) => d,
+                            _ => return Err(ScriptError::OpCodeEvaluateError(opcode_num)),
+                        };
+                        if (tapscript || flags.contains(VerifyFlags::MINIMALIF))
+                            && !(condition.is_empty() || condition == [1])
+                        {
+                            return Err(ScriptError::NonMinimalIf);
                         }
-                    } else if opcode_num >= 107 && opcode_num <= 108 {
+                        taken = cast_to_bool(&condition) == (opcode == OpCode::OpIf);
+                    }
+                    conditions.push(taken);
+                } else if opcode == OpCode::OpElse {
+                    if !conditions.toggle_top() {
+                        return Err(ScriptError::UnbalancedConditional);
+                    }
+                } else if opcode == OpCode::OpEndIf {
+                    if !conditions.pop() {
+                        return Err(ScriptError::UnbalancedConditional);
+                    }
+                } else {
+                    let operation = opcode.operation();
+                    if opcode == OpCode::OpToAltStack || opcode == OpCode::OpFromAltStack {
                         match operation {
                             OperationType::StackStack(operation) => {
                                 if !(*operation)(&mut stack, &mut altstack) {
@@ -164,48 +828,153 @@ impl Script {
                             }
                             _ => unreachable!(),
                         }
-                    } else if opcode_num >= 172 && opcode_num <= 175 {
+                    } else if opcode == OpCode::OpCheckLockTimeVerify
+                        || opcode == OpCode::OpCheckSequenceVerify
+                    {
+                        // NOPs before their soft forks
+                        let enforced = flags.contains(if opcode == OpCode::OpCheckLockTimeVerify {
+                            VerifyFlags::CHECKLOCKTIMEVERIFY
+                        } else {
+                            VerifyFlags::CHECKSEQUENCEVERIFY
+                        });
+                        if enforced {
+                            op_check_lock(
+                                opcode,
+                                &stack,
+                                locks,
+                                flags.contains(VerifyFlags::MINIMALDATA),
+                            )?;
+                        }
+                    } else if opcode == OpCode::OpCodeseparator {
+                        codesep_pos = Some(position);
+                    } else if tapscript && (is_check_sig(opcode) || opcode == OpCode::OpCheckSigAdd)
+                    {
+                        if let SigChecker::Tapscript(context) = &mut checker {
+                            let codesep_pos =
+                                codesep_pos.map_or(u32::max_value(), |pos| pos as u32);
+                            op_check_sig_tapscript(opcode_num, &mut stack, context, codesep_pos)?;
+                        }
+                    } else if is_check_sig(opcode) {
+                        let mut sig_hash = |sig: &[u8]| {
+                            if flags.contains(VerifyFlags::DERSIG) {
+                                Signature::is_strict_der(sig).map_err(ScriptError::SigDer)?;
+                            }
+                            // the sighash type is the last byte of the signature
+                            let sighash_type = u32::from(sig.last().cloned().unwrap_or(0));
+                            Ok(match &mut checker {
+                                SigChecker::Legacy(sig_hash) => sig_hash(sighash_type, codesep_pos),
+                                SigChecker::Tapscript(_) => unreachable!(),
+                            })
+                        };
                         match operation {
                             OperationType::StackSig(operation) => {
-                                if !(*operation)(
-                                    &mut stack,
-                                    hash.expect("this op code need a hash256"),
-                                ) {
+                                let null_dummy = flags.contains(VerifyFlags::NULLDUMMY);
+                                if !(*operation)(&mut stack, &mut sig_hash, null_dummy)? {
                                     return Err(ScriptError::OpCodeEvaluateError(opcode_num));
                                 }
                             }
                             _ => unreachable!(),
                         }
                     } else {
-                        match operation {
-                            OperationType::Stack(operation) => {
-                                if !(*operation)(&mut stack) {
-                                    return Err(ScriptError::OpCodeEvaluateError(opcode_num));
-                                }
+                        let ok = match operation {
+                            OperationType::Stack(operation) => (*operation)(&mut stack),
+                            OperationType::StackNum(operation) => {
+                                (*operation)(&mut stack, flags.contains(VerifyFlags::MINIMALDATA))
                             }
                             _ => unreachable!(),
+                        };
+                        if !ok {
+                            return Err(ScriptError::OpCodeEvaluateError(opcode_num));
                         }
                     }
                 }
             }
+            if stack.len() + altstack.len() > MAX_STACK_SIZE {
+                return Err(ScriptError::StackSizeLimit(stack.len() + altstack.len()));
+            }
+        }
+        if !conditions.is_empty() {
+            return Err(ScriptError::UnbalancedConditional);
         }
+        Ok(stack)
+    }
+}
+
+/// How signature opcodes get their message, legacy scripts ask for a sighash by type and
+/// position of the last OP_CODESEPARATOR that ran, tapscripts check schnorr signatures
+/// against the leaf
+enum SigChecker<'a, 'b> {
+    Legacy(&'a mut dyn FnMut(u32, Option<usize>) -> Hash256),
+    Tapscript(&'a mut TapscriptContext<'b>),
+}
+
+/// Push opcode `serialize` uses for `len` bytes, empty data is OP_0
+fn push_opcode(len: usize) -> u8 {
+    match len {
+        0 => 0x00,
+        1..=0x4b => len as u8,
+        0x4c..=0xff => 0x4c,
+        0x100..=0xffff => 0x4d,
+        _ => 0x4e,
+    }
+}
+
+impl BadRegion {
+    fn new(start: usize, end: usize, error: ScriptError) -> Self {
+        BadRegion { start, end, error }
+    }
+}
+
+/// Consensus encoding has no push limit, witness scripts may carry longer pushes
+impl Encodable for Script {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let mut raw = Vec::new();
+        self.write_cmds(&mut raw, usize::MAX)
+            .expect("no push is longer than usize::MAX");
+        let len = Varint::from(raw.len() as u64).consensus_encode(buf);
+        buf.extend_from_slice(&raw);
+        len + raw.len()
+    }
+}
 
-        if stack.is_empty() {
-            return Ok(false);
+impl Decodable for Script {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        match Script::parse(input) {
+            Ok((rest, script)) => Ok((rest, script)),
+            Err(_) => decode_error(input),
         }
-        if let Some(i) = stack.pop() {
-            match i {
-                StackElement::DataElement(data) => {
-                    if data.is_empty() {
-                        return Ok(false);
-                    }
-                }
-                _ => {
-                    return Ok(true);
-                }
-            }
+    }
+}
+
+impl TryFrom<&ScriptPubKey> for Script {
+    type Error = ScriptError;
+
+    fn try_from(script_pub_key: &ScriptPubKey) -> Result<Self, Self::Error> {
+        Script::from_raw(&script_pub_key.content)
+    }
+}
+
+impl TryFrom<&ScriptSig> for Script {
+    type Error = ScriptError;
+
+    fn try_from(script_sig: &ScriptSig) -> Result<Self, Self::Error> {
+        Script::from_raw(&script_sig.content)
+    }
+}
+
+impl From<&Script> for ScriptPubKey {
+    fn from(script: &Script) -> Self {
+        ScriptPubKey {
+            content: script.to_raw(),
+        }
+    }
+}
+
+impl From<&Script> for ScriptSig {
+    fn from(script: &Script) -> Self {
+        ScriptSig {
+            content: script.to_raw(),
         }
-        Ok(true)
     }
 }
 
@@ -218,37 +987,49 @@ impl Hex for Script {
 impl Add<&Self> for Script {
     type Output = Script;
     fn add(self, rhs: &Script) -> Self::Output {
+        let offset = self.cmds.len();
         let mut cmds = self.cmds;
         let mut rhs_cmds = rhs.cmds.clone();
         cmds.append(&mut rhs_cmds);
-        Script { cmds }
+
+        let mut non_canonical_pushes = self.non_canonical_pushes;
+        non_canonical_pushes.extend(
+            rhs.non_canonical_pushes
+                .iter()
+                .map(|(i, opcode)| (i + offset, *opcode)),
+        );
+        Script {
+            cmds,
+            non_canonical_pushes,
+        }
     }
 }
 
 impl Add<Self> for &Script {
     type Output = Script;
     fn add(self, rhs: &Script) -> Self::Output {
-        let mut cmds = self.cmds.clone();
-        let mut rhs_cmds = rhs.cmds.clone();
-        cmds.append(&mut rhs_cmds);
-        Script { cmds }
+        self.clone() + rhs
     }
 }
 
 mod test {
-    use crate::script::{OpCode, Script};
-    use crate::wallet::{FromHex, Hash256, Hex};
+    use crate::script::{
+        BadRegion, Instruction, LockTimeContext, OpCode, Script, ScriptError, SigHashCache,
+        StackElement, VerifyFlags,
+    };
+    use crate::transaction::Transaction;
+    use crate::wallet::{hash256, DerViolation, FromHex, Hash256, Hex, PrivateKey, U256};
 
     #[test]
     fn test_script_parse() {
         let data = hex!("6a47304402207899531a52d59a6de200179928ca900254a36b8dff8bb75f5f5d71b1cdc26125022008b422690b8461cb52c3cc30330b23d574351872b7c361e9aae3649071c1a7160121035d5c93d9ac96881f19ba1f686f15f009ded7c62efe85a872e6a19b43c15a2937");
         let (_data, script) = Script::parse(&data[..]).unwrap();
         assert_eq!(
-            script.cmds[0].hex(),
+            script.get(0).unwrap().hex(),
             "304402207899531a52d59a6de200179928ca900254a36b8dff8bb75f5f5d71b1cdc26125022008b422690b8461cb52c3cc30330b23d574351872b7c361e9aae3649071c1a71601".to_string()
         );
         assert_eq!(
-            script.cmds[1].hex(),
+            script.get(1).unwrap().hex(),
             "035d5c93d9ac96881f19ba1f686f15f009ded7c62efe85a872e6a19b43c15a2937".to_string()
         );
 
@@ -257,6 +1038,126 @@ mod test {
             "304402207899531a52d59a6de200179928ca900254a36b8dff8bb75f5f5d71b1cdc26125022008b422690b8461cb52c3cc30330b23d574351872b7c361e9aae3649071c1a71601035d5c93d9ac96881f19ba1f686f15f009ded7c62efe85a872e6a19b43c15a2937".to_string()
         );
     }
+    #[test]
+    fn test_script_instructions() {
+        // OP_0 <2 bytes> OP_1NEGATE OP_16 OP_CHECKSIG
+        let script = Script::from_raw(&hex!("0002abcd4f60ac")).unwrap();
+        assert_eq!(script.len(), 5);
+        assert!(!script.is_empty() && Script::new().is_empty());
+        assert_eq!(script.iter().count(), 5);
+        match script.get(1) {
+            Some(StackElement::DataElement(data)) => assert_eq!(data, &[0xab, 0xcd]),
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(script.get(5).is_none());
+        assert_eq!(
+            script.instructions().collect::<Vec<_>>(),
+            vec![
+                Instruction::PushBytes(&[]),
+                Instruction::PushBytes(&[0xab, 0xcd]),
+                Instruction::Op(0x4f),
+                Instruction::Op(0x60),
+                Instruction::Op(0xac),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack_element() {
+        let data = StackElement::DataElement(vec![0x00, 0x80]);
+        assert_eq!(data.as_bytes(), Some(&[0x00, 0x80][..]));
+        assert_eq!(data.as_op(), None);
+        assert!(!data.to_bool());
+        assert!(StackElement::DataElement(vec![0x80, 0x00]).to_bool());
+
+        let op = StackElement::OpCode(OpCode::OpDup);
+        assert_eq!(op.as_bytes(), None);
+        assert_eq!(op.as_op(), Some(OpCode::OpDup));
+        assert!(!op.to_bool());
+
+        // negative zero left on the stack is false
+        let mut script = Script::new();
+        script.push_data_ele(&[0x80]);
+        assert_eq!(script.evaluate(None), Ok(false));
+    }
+
+    #[test]
+    fn test_max_satisfaction_weight() {
+        let key = [0x02; 33];
+        // 2 of 3 multisig: dummy and two signatures, then the 105 bytes script
+        let mut multisig = Script::new();
+        multisig.push_opcode(OpCode::Op2);
+        for _ in 0..3 {
+            multisig.push_data_ele(&key);
+        }
+        multisig.push_opcode(OpCode::Op3);
+        multisig.push_opcode(OpCode::OpCheckMultisig);
+        assert_eq!(
+            multisig.max_satisfaction_weight(),
+            Some(1 + 1 + 2 * 73 + 1 + 105)
+        );
+
+        let mut single = Script::new();
+        single.push_data_ele(&key);
+        single.push_opcode(OpCode::OpCheckSig);
+        assert_eq!(single.max_satisfaction_weight(), Some(1 + 73 + 1 + 35));
+
+        let key_hash =
+            Script::parse_lossy(&hex!("76a914000102030405060708090a0b0c0d0e0f1011121388ac")).0;
+        assert_eq!(
+            key_hash.max_satisfaction_weight(),
+            Some(1 + 73 + 34 + 1 + 25)
+        );
+
+        // more keys required than listed
+        multisig.cmds[0] = StackElement::OpCode(OpCode::Op4);
+        assert_eq!(multisig.max_satisfaction_weight(), None);
+    }
+
+    #[test]
+    fn test_script_parse_errors() {
+        // OP_DUP then a PUSHDATA1 of 76 bytes with only 5 left
+        let data = hex!("08764c4c0102030405");
+        let err = Script::parse(&data[..]).err().unwrap();
+        assert_eq!(
+            err,
+            ScriptError::PushExceedsRemaining {
+                opcode: "OP_PUSHDATA1".to_string(),
+                offset: 1,
+                len: 76,
+                remaining: 5
+            }
+        );
+        assert_eq!(
+            format!("{}", err),
+            "OP_PUSHDATA1 push of 76 bytes at offset 1 exceeds remaining 5 bytes".to_string()
+        );
+
+        let err = Script::parse(&hex!("0a7600")[..]).err().unwrap();
+        assert_eq!(
+            err,
+            ScriptError::LengthExceedsInput {
+                length: 10,
+                remaining: 2
+            }
+        );
+
+        let (script, bad_regions) = Script::parse_lossy(&hex!("76a94d01")[..]);
+        assert_eq!(script.asm(), "OP_DUP OP_HASH160".to_string());
+        assert_eq!(
+            bad_regions,
+            vec![BadRegion {
+                start: 2,
+                end: 4,
+                error: ScriptError::TruncatedPushLength {
+                    opcode: "OP_PUSHDATA2".to_string(),
+                    offset: 2,
+                    width: 2
+                }
+            }]
+        );
+    }
+
     #[test]
     fn test_script_serialize() {
         let data = hex!("6a47304402207899531a52d59a6de200179928ca900254a36b8dff8bb75f5f5d71b1cdc26125022008b422690b8461cb52c3cc30330b23d574351872b7c361e9aae3649071c1a7160121035d5c93d9ac96881f19ba1f686f15f009ded7c62efe85a872e6a19b43c15a2937");
@@ -268,12 +1169,327 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_script_conversions() {
+        use crate::transaction::{ScriptPubKey, ScriptSig};
+        use std::convert::TryFrom;
+
+        // a one byte push with OP_PUSHDATA1 survives the round trip
+        let script_pub_key = ScriptPubKey {
+            content: hex!("4c01ff7576a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac").to_vec(),
+        };
+        let script = Script::try_from(&script_pub_key).unwrap();
+        assert_eq!(script.asm(), "-127 OP_DROP OP_DUP OP_HASH160 bc3b654dca7e56b04dca18f2566cdaf02e8d9ada OP_EQUALVERIFY OP_CHECKSIG");
+        assert_eq!(ScriptPubKey::from(&script), script_pub_key);
+
+        let script_sig = ScriptSig {
+            content: hex!("0051").to_vec(),
+        };
+        let script = Script::try_from(&script_sig).unwrap();
+        assert_eq!(ScriptSig::from(&script), script_sig);
+
+        let truncated = ScriptSig {
+            content: hex!("0502").to_vec(),
+        };
+        assert!(matches!(
+            Script::try_from(&truncated),
+            Err(ScriptError::PushExceedsRemaining { .. })
+        ));
+    }
+
+    #[test]
+    fn test_script_asm() {
+        let data = hex!("6a47304402207899531a52d59a6de200179928ca900254a36b8dff8bb75f5f5d71b1cdc26125022008b422690b8461cb52c3cc30330b23d574351872b7c361e9aae3649071c1a7160121035d5c93d9ac96881f19ba1f686f15f009ded7c62efe85a872e6a19b43c15a2937");
+        let (_data, script) = Script::parse(&data[..]).unwrap();
+        assert_eq!(
+            script.to_asm(true),
+            "304402207899531a52d59a6de200179928ca900254a36b8dff8bb75f5f5d71b1cdc26125022008b422690b8461cb52c3cc30330b23d574351872b7c361e9aae3649071c1a716[ALL] 035d5c93d9ac96881f19ba1f686f15f009ded7c62efe85a872e6a19b43c15a2937".to_string()
+        );
+
+        // OP_0 OP_16 <-1> OP_CHECKSIG OP_RETURN
+        let data = hex!("0600600181ac6a");
+        let (_data, script) = Script::parse(&data[..]).unwrap();
+        assert_eq!(script.asm(), "0 16 -1 OP_CHECKSIG OP_RETURN".to_string());
+    }
+
+    #[test]
+    fn test_script_minimal_data() {
+        // <5> pushed with OP_PUSHDATA1, then a 76 bytes push
+        let mut data = hex!("514c01054c4c").to_vec();
+        data.extend_from_slice(&[0xab; 76]);
+        let (_data, script) = Script::parse(&data[..]).unwrap();
+        assert_eq!(script.serialize().unwrap(), data);
+
+        assert!(script.evaluate(None).unwrap());
+        assert_eq!(
+            script
+                .evaluate_with_flags(None, VerifyFlags::MINIMALDATA)
+                .err(),
+            Some(ScriptError::NonMinimalPush(0))
+        );
+
+        let mut script = Script::new();
+        script.push_data_ele(&[0xab; 76]);
+        assert!(script
+            .evaluate_with_flags(None, VerifyFlags::MINIMALDATA)
+            .unwrap());
+        assert!(script.is_push_only());
+
+        // a single 0x00 byte is no small number, OP_0 pushes the empty element
+        let (_data, script) = Script::parse(&hex!("03010051")[..]).unwrap();
+        assert!(script
+            .evaluate_with_flags(None, VerifyFlags::MINIMALDATA)
+            .unwrap());
+
+        // OP_0 OP_IF <7 with OP_PUSHDATA1> OP_ENDIF OP_1, the padded push never runs
+        let (_data, script) = Script::parse(&hex!("0700634c01076851")[..]).unwrap();
+        assert!(script
+            .evaluate_with_flags(None, VerifyFlags::MINIMALDATA)
+            .unwrap());
+        let (_data, script) = Script::parse(&hex!("0751634c01076851")[..]).unwrap();
+        assert_eq!(
+            script
+                .evaluate_with_flags(None, VerifyFlags::MINIMALDATA)
+                .err(),
+            Some(ScriptError::NonMinimalPush(2))
+        );
+    }
+
+    #[test]
+    fn test_script_minimal_if() {
+        let script_with_condition = |cond: &[u8]| {
+            let mut script = Script::new();
+            script.push_data_ele(cond);
+            script.push_opcode(OpCode::OpIf);
+            script.push_data_ele(&[0xab; 2]);
+            script.push_opcode(OpCode::OpElse);
+            script.push_data_ele(&[]);
+            script.push_opcode(OpCode::OpEndIf);
+            script
+        };
+
+        let script = script_with_condition(&[2]);
+        assert!(script.evaluate(None).unwrap());
+        assert!(!script.is_push_only());
+        assert_eq!(
+            script
+                .evaluate_with_flags(None, VerifyFlags::MINIMALIF)
+                .err(),
+            Some(ScriptError::NonMinimalIf)
+        );
+
+        let script = script_with_condition(&[1]);
+        assert!(script
+            .evaluate_with_flags(None, VerifyFlags::MINIMALIF)
+            .unwrap());
+        let script = script_with_condition(&[0x80]);
+        assert!(!script.evaluate(None).unwrap());
+    }
+
+    #[test]
+    fn test_script_nested_if() {
+        let run = |raw: &[u8]| Script::from_raw(raw).unwrap().evaluate(None);
+        // OP_1 OP_IF OP_0 OP_IF OP_2 OP_ELSE OP_3 OP_ENDIF OP_ELSE OP_4 OP_ENDIF OP_3 OP_EQUAL
+        assert_eq!(run(&hex!("51630063526753686754685387")), Ok(true));
+        // a branch not taken is parsed but does not run, OP_RETURN included
+        assert_eq!(run(&hex!("00636a6851")), Ok(true));
+        // except for OP_VERIF, a conditional that runs in any branch
+        assert_eq!(
+            run(&hex!("0063656851")),
+            Err(ScriptError::OpCodeEvaluateError(0x65))
+        );
+        // each OP_ELSE switches the branch again
+        assert_eq!(run(&hex!("516367675268")), Ok(true));
+        assert_eq!(run(&hex!("006367675268")), Ok(false));
+        assert_eq!(
+            run(&hex!("6368")),
+            Err(ScriptError::OpCodeEvaluateError(0x63))
+        );
+        for unbalanced in &[&hex!("516351")[..], &hex!("5168"), &hex!("5167")] {
+            assert_eq!(run(unbalanced), Err(ScriptError::UnbalancedConditional));
+        }
+    }
+
+    #[test]
+    fn test_script_null_dummy() {
+        // <dummy> OP_0 OP_0 OP_CHECKMULTISIG, no signature checked against no key
+        let with_dummy = |dummy: &str| {
+            Script::from_raw(&hex::decode(format!("{}0000ae", dummy)).unwrap()).unwrap()
+        };
+        for dummy in &["00", "0100", "51"] {
+            assert_eq!(with_dummy(dummy).evaluate(None), Ok(true));
+            assert_eq!(
+                with_dummy(dummy).evaluate_with_flags(None, VerifyFlags::NULLDUMMY),
+                if *dummy == "00" {
+                    Ok(true)
+                } else {
+                    Err(ScriptError::SigNullDummy)
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_script_limits() {
+        let mut script = Script::new();
+        script.push_data_ele(&[0xab; 521]);
+        assert_eq!(
+            script.evaluate(None).err(),
+            Some(ScriptError::PushSizeLimit(521))
+        );
+
+        let mut script = Script::new();
+        script.push_data_ele(&[0xab; 2]);
+        (0..202).for_each(|_| script.push_opcode(OpCode::OpDup));
+        assert_eq!(
+            script.evaluate(None).err(),
+            Some(ScriptError::OpCountLimit(202))
+        );
+
+        let mut script = Script::new();
+        (0..1001).for_each(|_| script.push_data_ele(&[0xab]));
+        assert_eq!(
+            script.evaluate(None).err(),
+            Some(ScriptError::StackSizeLimit(1001))
+        );
+
+        let mut script = Script::new();
+        (0..20).for_each(|_| script.push_data_ele(&[0xab; 500]));
+        assert_eq!(
+            script.evaluate(None).err(),
+            Some(ScriptError::ScriptSizeLimit(20 * 503))
+        );
+
+        // 10 OP_CHECKMULTISIGVERIFY over 20 keys and no signature, each counts 21 opcodes
+        let mut script = Script::new();
+        for _ in 0..10 {
+            script.push_opcode(OpCode::Op0);
+            script.push_opcode(OpCode::Op0);
+            (0..20).for_each(|_| script.push_data_ele(&[0x02; 33]));
+            script.push_data_ele(&[20]);
+            script.push_opcode(OpCode::OpCheckMultisigVerify);
+        }
+        script.push_opcode(OpCode::Op1);
+        assert_eq!(
+            script.evaluate(None).err(),
+            Some(ScriptError::OpCountLimit(210))
+        );
+        // a multisig that does not run only counts once
+        let mut script = Script::new();
+        script.push_opcode(OpCode::Op0);
+        script.push_opcode(OpCode::OpIf);
+        (0..199).for_each(|_| script.push_opcode(OpCode::OpCheckMultisig));
+        script.push_opcode(OpCode::OpEndIf);
+        script.push_opcode(OpCode::Op1);
+        assert_eq!(script.evaluate(None), Ok(true));
+
+        // witness items have the element limit of pushes
+        let script = Script::from_raw(&[0x75, 0x51]).unwrap();
+        let witness = |len: usize| vec![vec![0xab; len]];
+        let no_hash = &mut |_, _| unreachable!();
+        assert_eq!(
+            script.evaluate_witness(&witness(520), no_hash, None, VerifyFlags::default()),
+            Ok(true)
+        );
+        assert_eq!(
+            script.evaluate_witness(&witness(600), no_hash, None, VerifyFlags::default()),
+            Err(ScriptError::PushSizeLimit(600))
+        );
+    }
+
+    #[test]
+    fn test_script_arithmetic() {
+        // OP_2 OP_3 OP_ADD OP_5 OP_NUMEQUAL
+        let (_, script) = Script::parse(&hex!("05525393559c")[..]).unwrap();
+        assert!(script.evaluate(None).unwrap());
+        // OP_1NEGATE OP_ABS OP_1 OP_16 OP_WITHIN
+        let (_, script) = Script::parse(&hex!("054f905160a5")[..]).unwrap();
+        assert!(script.evaluate(None).unwrap());
+        // the 5 bytes result of OP_1ADD can not be an operand again
+        let (_, script) = Script::parse(&hex!("0804ffffff7f8b8b51")[..]).unwrap();
+        assert_eq!(
+            script.evaluate(None).err(),
+            Some(ScriptError::OpCodeEvaluateError(0x8b))
+        );
+        // a padded operand only fails under MINIMALDATA
+        let mut script = Script::new();
+        script.push_data_ele(&[0x05, 0x00]);
+        script.push_opcode(OpCode::Op1Add);
+        assert!(script.evaluate(None).unwrap());
+        assert_eq!(
+            script
+                .evaluate_with_flags(None, VerifyFlags::MINIMALDATA)
+                .err(),
+            Some(ScriptError::OpCodeEvaluateError(0x8b))
+        );
+    }
+
+    #[test]
+    fn test_script_sig_hash_cache() {
+        let data = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let (_, tx) = Transaction::parse(&data[..]).unwrap();
+        let (_, script_sig) = Script::parse(&tx.inputs[0].script_sig.serialize()).unwrap();
+        let (_, script_pubkey) =
+            Script::parse(&hex!("1976a914a802fc56c704ce87c42d7c92eb75e7896bdc41ae88ac")[..])
+                .unwrap();
+
+        // <sig> <sec> OP_CHECKSIG twice, both signing the p2pkh script code
+        let mut check_sig = Script::new();
+        check_sig.push_opcode(OpCode::OpCheckSig);
+        let checked_twice = &(&script_sig + &check_sig) + &(&script_sig + &check_sig);
+
+        let mut cache = SigHashCache::new(&tx, 0, &script_pubkey);
+        assert!(cache.is_empty());
+        assert!(checked_twice
+            .evaluate_with_cache(&mut cache, VerifyFlags::default())
+            .unwrap());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_script_dersig() {
+        let mut script = Script::new();
+        script.push_data_ele(&hex!("30070202000102010101"));
+        script.push_data_ele(&hex!(
+            "0349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278a"
+        ));
+        script.push_opcode(OpCode::OpCheckSig);
+        assert_eq!(
+            script.evaluate_with_flags(None, VerifyFlags::DERSIG),
+            Err(ScriptError::SigDer(DerViolation::RPadding))
+        );
+
+        // without DERSIG a signature that does not parse fails the check, not the script
+        let sec = hex!("0349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278a");
+        let hash =
+            Hash256::from_hex(b"7c076ff316692a3d7eb3c3bb0f8b1488cf72e1afcd929e29307032997a838a3d");
+        let mut long_r = vec![0x30, 0x27, 0x02, 0x21, 0x01];
+        long_r.extend_from_slice(&[0x11; 32]);
+        long_r.extend_from_slice(&[0x02, 0x01, 0x01, 0x01]);
+        for sig in &[vec![0x01], hex!("300602010102").to_vec(), long_r] {
+            let mut script = Script::new();
+            script.push_data_ele(sig);
+            script.push_data_ele(&sec);
+            script.push_opcode(OpCode::OpCheckSig);
+            assert_eq!(script.evaluate(Some(hash)), Ok(false));
+
+            let mut script = Script::new();
+            script.push_data_ele(&[]);
+            script.push_data_ele(sig);
+            script.push_opcode(OpCode::Op1);
+            script.push_data_ele(&sec);
+            script.push_opcode(OpCode::Op1);
+            script.push_opcode(OpCode::OpCheckMultisig);
+            assert_eq!(script.evaluate(Some(hash)), Ok(false));
+        }
+    }
+
     #[test]
     fn test_script_evaluation() {
         let mut script_pubkey = Script::new();
         let sec_bytes = hex!("04887387e452b8eacc4acfde10d9aaf7f6d9a0f975aabb10d006e4da568744d06c61de6d95231cd89026e286df3b6ae4a894a3378e393e93a0f45b666329a0ae34");
         script_pubkey.push_data_ele(&sec_bytes);
-        script_pubkey.push_opcode(OpCode::new(0xac));
+        script_pubkey.push_opcode(OpCode::OpCheckSig);
 
         let mut script_sig = Script::new();
         let sig_bytes = hex!("3045022000eff69ef2b1bd93a66ed5219add4fb51e11a840f404876325a1e8ffe0529a2c022100c7207fee197d27c618aea621406f6bf5ef6fca38681d82b2f06fddbdce6feab601");
@@ -285,4 +1501,227 @@ mod test {
             Hash256::from_hex(b"7c076ff316692a3d7eb3c3bb0f8b1488cf72e1afcd929e29307032997a838a3d");
         assert!(combined_script.evaluate(Some(hash)).unwrap());
     }
+
+    #[test]
+    fn test_verify_opcodes() {
+        let hash =
+            Hash256::from_hex(b"7c076ff316692a3d7eb3c3bb0f8b1488cf72e1afcd929e29307032997a838a3d");
+        let keys: Vec<PrivateKey> = (1..=3u32)
+            .map(|i| PrivateKey::new(U256::from(i * 7_919)))
+            .collect();
+        let sig = |key: &PrivateKey| {
+            let mut sig = key.sign(U256::from_little_endian(&hash)).der();
+            sig.push(0x01);
+            sig
+        };
+        let script = |cmds: &[&[u8]], opcodes: &[u8]| {
+            let mut script = Script::new();
+            for cmd in cmds {
+                script.push_data_ele(cmd);
+            }
+            for opcode in opcodes {
+                script.push_opcode(OpCode::from(*opcode));
+            }
+            script
+        };
+        let sec = keys[0].point.compressed_sec();
+
+        // P2PKH: OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG
+        let p2pkh = |key_hash: &[u8]| {
+            let mut script = script(&[], &[0x76, 0xa9]);
+            script.push_data_ele(key_hash);
+            script.push_opcode(OpCode::OpEqualVerify);
+            script.push_opcode(OpCode::OpCheckSig);
+            script
+        };
+        let script_sig = script(&[&sig(&keys[0]), &sec], &[]);
+        let valid = script_sig.clone() + &p2pkh(&keys[0].point.hash160(true));
+        assert_eq!(valid.evaluate(Some(hash)), Ok(true));
+        let wrong_key = script_sig + &p2pkh(&keys[1].point.hash160(true));
+        assert_eq!(
+            wrong_key.evaluate(Some(hash)),
+            Err(ScriptError::OpCodeEvaluateError(0x88))
+        );
+
+        // OP_CHECKSIGVERIFY leaves nothing behind
+        let checked = script(&[&sig(&keys[0]), &sec], &[0xad, 0x51]);
+        assert_eq!(checked.evaluate(Some(hash)), Ok(true));
+        let failed = script(&[&sig(&keys[1]), &sec], &[0xad, 0x51]);
+        assert_eq!(
+            failed.evaluate(Some(hash)),
+            Err(ScriptError::OpCodeEvaluateError(0xad))
+        );
+
+        // 2 of 3, signatures in the order of their keys
+        let secs: Vec<[u8; 33]> = keys.iter().map(|key| key.point.compressed_sec()).collect();
+        let multisig = |code: u8| {
+            let mut script = script(&[], &[0x52]);
+            for sec in &secs {
+                script.push_data_ele(sec);
+            }
+            script.push_opcode(OpCode::Op3);
+            script.push_opcode(OpCode::from(code));
+            script
+        };
+        let spend = |first: &PrivateKey, second: &PrivateKey| {
+            let mut script = script(&[], &[0x00]);
+            script.push_data_ele(&sig(first));
+            script.push_data_ele(&sig(second));
+            script
+        };
+        let ordered = spend(&keys[0], &keys[2]) + &multisig(0xae);
+        assert_eq!(ordered.evaluate(Some(hash)), Ok(true));
+        let reversed = spend(&keys[2], &keys[0]) + &multisig(0xae);
+        assert_eq!(reversed.evaluate(Some(hash)), Ok(false));
+        let reversed = spend(&keys[2], &keys[0]) + &multisig(0xaf) + &script(&[], &[0x51]);
+        assert_eq!(
+            reversed.evaluate(Some(hash)),
+            Err(ScriptError::OpCodeEvaluateError(0xaf))
+        );
+        let verified = spend(&keys[1], &keys[2]) + &multisig(0xaf) + &script(&[], &[0x51]);
+        assert_eq!(verified.evaluate(Some(hash)), Ok(true));
+    }
+
+    #[test]
+    fn test_script_code_codeseparator() {
+        let key = PrivateKey::new(U256::from(7u32));
+        let sec = key.point.compressed_sec();
+        // OP_0 OP_IF OP_CODESEPARATOR OP_ENDIF OP_CODESEPARATOR <pk> OP_CHECKSIG
+        let mut script = Script::new();
+        script.push_opcode(OpCode::Op0);
+        script.push_opcode(OpCode::OpIf);
+        script.push_opcode(OpCode::OpCodeseparator);
+        script.push_opcode(OpCode::OpEndIf);
+        script.push_opcode(OpCode::OpCodeseparator);
+        script.push_data_ele(&sec);
+        script.push_opcode(OpCode::OpCheckSig);
+
+        let signed = [&[0x21][..], &sec, &[0xac]].concat();
+        assert_eq!(script.script_code(Some(4)), signed);
+        assert_eq!(
+            script.script_code(Some(2)),
+            [&[0x68, 0x21][..], &sec, &[0xac]].concat()
+        );
+        assert_eq!(
+            script.script_code(None),
+            [&[0x00, 0x63, 0x68, 0x21][..], &sec, &[0xac]].concat()
+        );
+
+        // the separator in the branch not taken does not move the script code
+        let mut sig = key.sign(U256::from_little_endian(&hash256(&signed))).der();
+        sig.push(0x01);
+        assert_eq!(
+            script.evaluate_witness(
+                &[sig],
+                &mut |_, codesep_pos| hash256(&script.script_code(codesep_pos)),
+                None,
+                VerifyFlags::default(),
+            ),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_stack_and_timelock_opcodes() {
+        let run = |hex: &str, locks: Option<&LockTimeContext>, flags: VerifyFlags| {
+            Script::from_hex(hex).unwrap().evaluate_witness(
+                &[],
+                &mut |_, _| unreachable!(),
+                locks,
+                flags,
+            )
+        };
+        let no_flags = VerifyFlags::default();
+        // <1> <2> OP_SWAP OP_DROP OP_1 OP_EQUAL, then OP_2
+        assert_eq!(run("51527c755187", None, no_flags), Ok(false));
+        assert_eq!(run("51527c755287", None, no_flags), Ok(true));
+        // <abc> OP_SIZE 3 OP_EQUALVERIFY OP_SHA256 <sha256(abc)> OP_EQUAL
+        assert_eq!(
+            run(
+                "03616263825388a820ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad87",
+                None,
+                no_flags
+            ),
+            Ok(true)
+        );
+        // OP_NOP OP_NOP1 OP_NOP4 OP_NOP10 OP_1
+        assert_eq!(run("61b0b3b951", None, no_flags), Ok(true));
+        assert_eq!(
+            run("75", None, no_flags),
+            Err(ScriptError::OpCodeEvaluateError(0x75))
+        );
+
+        // <144> OP_CHECKSEQUENCEVERIFY, <600000> OP_CHECKLOCKTIMEVERIFY
+        let csv = "029000b2";
+        let cltv = "03c02709b1";
+        let locks = LockTimeContext {
+            version: 2,
+            locktime: 600_000,
+            sequence: 144,
+        };
+        let enforced = VerifyFlags::CHECKLOCKTIMEVERIFY | VerifyFlags::CHECKSEQUENCEVERIFY;
+        // NOPs before the soft forks
+        assert_eq!(run(csv, None, no_flags), Ok(true));
+        assert_eq!(run(cltv, None, no_flags), Ok(true));
+        assert_eq!(run(csv, Some(&locks), enforced), Ok(true));
+        assert_eq!(run(cltv, Some(&locks), enforced), Ok(true));
+        assert_eq!(
+            run(cltv, None, enforced),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        let early = LockTimeContext {
+            locktime: 599_999,
+            sequence: 143,
+            ..locks
+        };
+        assert_eq!(
+            run(cltv, Some(&early), enforced),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        assert_eq!(
+            run(csv, Some(&early), enforced),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        // <-1> OP_CHECKLOCKTIMEVERIFY
+        assert_eq!(
+            run("4fb1", Some(&locks), enforced),
+            Err(ScriptError::NegativeLocktime)
+        );
+        // the disable flag of a sequence operand always passes
+        assert_eq!(run("050000008000b2", Some(&early), enforced), Ok(true));
+    }
+
+    #[test]
+    fn test_disabled_opcodes() {
+        // OP_0 OP_IF OP_CAT OP_ENDIF OP_1, the branch never runs
+        let script = Script::parse_lossy(&hex!("00637e6851")).0;
+        #[cfg(not(feature = "op_experiments"))]
+        assert_eq!(
+            script.evaluate(None),
+            Err(ScriptError::DisabledOpCode(0x7e))
+        );
+        #[cfg(feature = "op_experiments")]
+        assert_eq!(script.evaluate(None), Ok(true));
+    }
+
+    #[cfg(feature = "op_experiments")]
+    #[test]
+    fn test_experimental_opcodes() {
+        let run = |hex: &str| {
+            Script::parse_lossy(&hex::decode(hex).unwrap())
+                .0
+                .evaluate(None)
+        };
+        // "ab" "cd" OP_CAT "abcd" OP_EQUAL
+        assert_eq!(run("01ab01cd7e02abcd87"), Ok(true));
+        // "abcdef" 1 2 OP_SUBSTR "cdef" OP_EQUAL
+        assert_eq!(run("03abcdef51527f02cdef87"), Ok(true));
+        // "abcdef" 1 OP_LEFT "ab" OP_EQUAL
+        assert_eq!(run("03abcdef518001ab87"), Ok(true));
+        // "0f" "f0" OP_XOR "ff" OP_EQUAL
+        assert_eq!(run("010f01f08601ff87"), Ok(true));
+        // 6 3 OP_MUL 18 OP_EQUAL, 6 0 OP_DIV fails
+        assert_eq!(run("565395011287"), Ok(true));
+        assert_eq!(run("560096"), Err(ScriptError::OpCodeEvaluateError(0x96)));
+    }
 }
@@ -0,0 +1,87 @@
+/// The OP_IF branches a script is in, Bitcoin Core's vfExec. Only the depth and the first
+/// branch not taken are kept, so whether to execute is known in constant time however deep
+/// the nesting.
+#[derive(Debug, Default)]
+pub struct ConditionStack {
+    size: usize,
+    first_false: Option<usize>,
+}
+
+impl ConditionStack {
+    pub fn new() -> Self {
+        ConditionStack::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Every enclosing branch is taken, the commands run
+    pub fn all_true(&self) -> bool {
+        self.first_false.is_none()
+    }
+
+    /// Enter an OP_IF/OP_NOTIF branch
+    pub fn push(&mut self, taken: bool) {
+        if !taken && self.first_false.is_none() {
+            self.first_false = Some(self.size);
+        }
+        self.size += 1;
+    }
+
+    /// Leave the innermost branch at OP_ENDIF, false when there is none
+    pub fn pop(&mut self) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        self.size -= 1;
+        if self.first_false == Some(self.size) {
+            self.first_false = None;
+        }
+        true
+    }
+
+    /// Switch the innermost branch at OP_ELSE, false when there is none
+    pub fn toggle_top(&mut self) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let top = self.size - 1;
+        match self.first_false {
+            None => self.first_false = Some(top),
+            Some(first_false) if first_false == top => self.first_false = None,
+            // an outer branch is not taken, neither side of this one runs
+            Some(_) => {}
+        }
+        true
+    }
+}
+
+mod test {
+    use super::ConditionStack;
+
+    #[test]
+    fn test_condition_stack() {
+        let mut conditions = ConditionStack::new();
+        assert!(conditions.is_empty() && conditions.all_true());
+        assert!(!conditions.pop());
+        assert!(!conditions.toggle_top());
+
+        conditions.push(true);
+        conditions.push(false);
+        assert!(!conditions.all_true());
+        // the inner branch skipped, a branch in it stays skipped even when taken
+        conditions.push(true);
+        assert!(conditions.toggle_top());
+        assert!(!conditions.all_true());
+        assert!(conditions.pop());
+        assert!(conditions.toggle_top());
+        assert!(conditions.all_true());
+        assert!(conditions.toggle_top());
+        assert!(!conditions.all_true());
+        assert!(conditions.pop());
+        assert!(conditions.all_true());
+        assert!(conditions.pop());
+        assert!(conditions.is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use super::op_function::Stack;
+use super::script_num::ScriptNum;
+use super::{OpCode, ScriptError};
+use crate::transaction::{Transaction, LOCKTIME_THRESHOLD};
+
+/// Sequence of an input that opts out of the transaction locktime
+const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+/// BIP68 sequence bits: no relative lock, a lock in units of 512 seconds, and the value
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = 1 << 31;
+const SEQUENCE_LOCKTIME_TYPE_FLAG: i64 = 1 << 22;
+const SEQUENCE_LOCKTIME_MASK: i64 = 0x0000_ffff;
+
+/// Operands of OP_CHECKLOCKTIMEVERIFY and OP_CHECKSEQUENCEVERIFY may take 5 bytes, locktimes
+/// go up to 2^32 - 1
+const LOCKTIME_NUM_SIZE: usize = 5;
+
+/// What OP_CHECKLOCKTIMEVERIFY and OP_CHECKSEQUENCEVERIFY compare against, the version and
+/// locktime of the spending transaction and the sequence of the spending input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockTimeContext {
+    pub version: u32,
+    pub locktime: u32,
+    pub sequence: u32,
+}
+impl Copy for LockTimeContext {}
+
+impl LockTimeContext {
+    pub fn new(tx: &Transaction, input_index: usize) -> Self {
+        LockTimeContext {
+            version: u32::from(tx.version),
+            locktime: u32::from(tx.locktime),
+            sequence: tx.inputs[input_index].sequence.sequence(),
+        }
+    }
+
+    /// BIP65, `locktime` is a height when the transaction locktime is one and a time when
+    /// it is a time, it has passed, and the input does not opt out of the locktime
+    pub fn check_locktime(&self, locktime: i64) -> bool {
+        let (tx_locktime, threshold) = (i64::from(self.locktime), i64::from(LOCKTIME_THRESHOLD));
+        if (locktime < threshold) != (tx_locktime < threshold) {
+            return false;
+        }
+        locktime <= tx_locktime && self.sequence != SEQUENCE_FINAL
+    }
+
+    /// BIP112, the input sequence is a relative lock of the same unit as `sequence` and
+    /// at least as long
+    pub fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence = i64::from(self.sequence);
+        if self.version < 2 || tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return false;
+        }
+        let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let (tx_sequence, sequence) = (tx_sequence & mask, sequence & mask);
+        if (sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) != (tx_sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) {
+            return false;
+        }
+        sequence <= tx_sequence
+    }
+}
+
+/// OP_CHECKLOCKTIMEVERIFY or OP_CHECKSEQUENCEVERIFY against the top element, which stays.
+/// Without a spending transaction there is nothing to satisfy the lock.
+pub(super) fn op_check_lock(
+    opcode: OpCode,
+    stack: &Stack,
+    locks: Option<&LockTimeContext>,
+    require_minimal: bool,
+) -> Result<(), ScriptError> {
+    let lock = stack
+        .last()
+        .and_then(|top| top.as_bytes())
+        .and_then(|top| ScriptNum::decode(top, require_minimal, LOCKTIME_NUM_SIZE).ok())
+        .ok_or_else(|| ScriptError::OpCodeEvaluateError(opcode.num()))?
+        .value();
+    if lock < 0 {
+        return Err(ScriptError::NegativeLocktime);
+    }
+    let satisfied = match (opcode, locks) {
+        // a sequence with the disable flag leaves the input unlocked, for future upgrades
+        (OpCode::OpCheckSequenceVerify, _) if lock & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 => true,
+        (OpCode::OpCheckSequenceVerify, Some(locks)) => locks.check_sequence(lock),
+        (_, Some(locks)) => locks.check_locktime(lock),
+        (_, None) => false,
+    };
+    if satisfied {
+        Ok(())
+    } else {
+        Err(ScriptError::UnsatisfiedLocktime)
+    }
+}
+
+mod test {
+    use super::LockTimeContext;
+
+    #[test]
+    fn test_lock_time_context() {
+        let locks = LockTimeContext {
+            version: 2,
+            locktime: 600_000,
+            sequence: 144,
+        };
+        assert!(locks.check_locktime(600_000));
+        assert!(!locks.check_locktime(600_001));
+        // a time against a height
+        assert!(!locks.check_locktime(1_600_000_000));
+        let times = LockTimeContext {
+            locktime: 1_600_000_000,
+            ..locks
+        };
+        assert!(times.check_locktime(1_500_000_000));
+        let finished = LockTimeContext {
+            sequence: 0xffff_ffff,
+            ..locks
+        };
+        assert!(!finished.check_locktime(1));
+
+        assert!(locks.check_sequence(144));
+        assert!(locks.check_sequence(10));
+        assert!(!locks.check_sequence(145));
+        // 512 seconds units against blocks
+        assert!(!locks.check_sequence((1 << 22) | 1));
+        // only the value and the type bits count
+        assert!(locks.check_sequence(0x0100_0090));
+        let version_1 = LockTimeContext {
+            version: 1,
+            ..locks
+        };
+        assert!(!version_1.check_sequence(1));
+        let disabled = LockTimeContext {
+            sequence: (1 << 31) | 144,
+            ..locks
+        };
+        assert!(!disabled.check_sequence(1));
+    }
+}
@@ -0,0 +1,411 @@
+use std::fmt::{self, Display};
+
+/// Defines the opcode enum from its `Variant = byte, "name";` list, which must list every
+/// byte in order
+macro_rules! op_codes {
+    ($($variant:ident = $num:expr, $name:expr;)*) => {
+        /// Every script byte as an opcode, the direct pushes OP_PUSHBYTES_1 .. 75 and the
+        /// undefined 0xbb .. 0xfe included, so any byte converts to an opcode and back
+        #[repr(u8)]
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum OpCode {
+            $($variant = $num,)*
+        }
+
+        /// Opcodes indexed by their byte
+        const OP_CODES: [OpCode; 256] = [$(OpCode::$variant,)*];
+
+        impl OpCode {
+            /// Name as Bitcoin Core's opcode tables spell it, small integers as OP_n
+            pub fn canonical_name(&self) -> &'static str {
+                match self {
+                    $(OpCode::$variant => $name,)*
+                }
+            }
+        }
+    };
+}
+
+op_codes! {
+    Op0 = 0x00, "OP_0";
+    OpPushBytes1 = 0x01, "OP_PUSHBYTES_1";
+    OpPushBytes2 = 0x02, "OP_PUSHBYTES_2";
+    OpPushBytes3 = 0x03, "OP_PUSHBYTES_3";
+    OpPushBytes4 = 0x04, "OP_PUSHBYTES_4";
+    OpPushBytes5 = 0x05, "OP_PUSHBYTES_5";
+    OpPushBytes6 = 0x06, "OP_PUSHBYTES_6";
+    OpPushBytes7 = 0x07, "OP_PUSHBYTES_7";
+    OpPushBytes8 = 0x08, "OP_PUSHBYTES_8";
+    OpPushBytes9 = 0x09, "OP_PUSHBYTES_9";
+    OpPushBytes10 = 0x0a, "OP_PUSHBYTES_10";
+    OpPushBytes11 = 0x0b, "OP_PUSHBYTES_11";
+    OpPushBytes12 = 0x0c, "OP_PUSHBYTES_12";
+    OpPushBytes13 = 0x0d, "OP_PUSHBYTES_13";
+    OpPushBytes14 = 0x0e, "OP_PUSHBYTES_14";
+    OpPushBytes15 = 0x0f, "OP_PUSHBYTES_15";
+    OpPushBytes16 = 0x10, "OP_PUSHBYTES_16";
+    OpPushBytes17 = 0x11, "OP_PUSHBYTES_17";
+    OpPushBytes18 = 0x12, "OP_PUSHBYTES_18";
+    OpPushBytes19 = 0x13, "OP_PUSHBYTES_19";
+    OpPushBytes20 = 0x14, "OP_PUSHBYTES_20";
+    OpPushBytes21 = 0x15, "OP_PUSHBYTES_21";
+    OpPushBytes22 = 0x16, "OP_PUSHBYTES_22";
+    OpPushBytes23 = 0x17, "OP_PUSHBYTES_23";
+    OpPushBytes24 = 0x18, "OP_PUSHBYTES_24";
+    OpPushBytes25 = 0x19, "OP_PUSHBYTES_25";
+    OpPushBytes26 = 0x1a, "OP_PUSHBYTES_26";
+    OpPushBytes27 = 0x1b, "OP_PUSHBYTES_27";
+    OpPushBytes28 = 0x1c, "OP_PUSHBYTES_28";
+    OpPushBytes29 = 0x1d, "OP_PUSHBYTES_29";
+    OpPushBytes30 = 0x1e, "OP_PUSHBYTES_30";
+    OpPushBytes31 = 0x1f, "OP_PUSHBYTES_31";
+    OpPushBytes32 = 0x20, "OP_PUSHBYTES_32";
+    OpPushBytes33 = 0x21, "OP_PUSHBYTES_33";
+    OpPushBytes34 = 0x22, "OP_PUSHBYTES_34";
+    OpPushBytes35 = 0x23, "OP_PUSHBYTES_35";
+    OpPushBytes36 = 0x24, "OP_PUSHBYTES_36";
+    OpPushBytes37 = 0x25, "OP_PUSHBYTES_37";
+    OpPushBytes38 = 0x26, "OP_PUSHBYTES_38";
+    OpPushBytes39 = 0x27, "OP_PUSHBYTES_39";
+    OpPushBytes40 = 0x28, "OP_PUSHBYTES_40";
+    OpPushBytes41 = 0x29, "OP_PUSHBYTES_41";
+    OpPushBytes42 = 0x2a, "OP_PUSHBYTES_42";
+    OpPushBytes43 = 0x2b, "OP_PUSHBYTES_43";
+    OpPushBytes44 = 0x2c, "OP_PUSHBYTES_44";
+    OpPushBytes45 = 0x2d, "OP_PUSHBYTES_45";
+    OpPushBytes46 = 0x2e, "OP_PUSHBYTES_46";
+    OpPushBytes47 = 0x2f, "OP_PUSHBYTES_47";
+    OpPushBytes48 = 0x30, "OP_PUSHBYTES_48";
+    OpPushBytes49 = 0x31, "OP_PUSHBYTES_49";
+    OpPushBytes50 = 0x32, "OP_PUSHBYTES_50";
+    OpPushBytes51 = 0x33, "OP_PUSHBYTES_51";
+    OpPushBytes52 = 0x34, "OP_PUSHBYTES_52";
+    OpPushBytes53 = 0x35, "OP_PUSHBYTES_53";
+    OpPushBytes54 = 0x36, "OP_PUSHBYTES_54";
+    OpPushBytes55 = 0x37, "OP_PUSHBYTES_55";
+    OpPushBytes56 = 0x38, "OP_PUSHBYTES_56";
+    OpPushBytes57 = 0x39, "OP_PUSHBYTES_57";
+    OpPushBytes58 = 0x3a, "OP_PUSHBYTES_58";
+    OpPushBytes59 = 0x3b, "OP_PUSHBYTES_59";
+    OpPushBytes60 = 0x3c, "OP_PUSHBYTES_60";
+    OpPushBytes61 = 0x3d, "OP_PUSHBYTES_61";
+    OpPushBytes62 = 0x3e, "OP_PUSHBYTES_62";
+    OpPushBytes63 = 0x3f, "OP_PUSHBYTES_63";
+    OpPushBytes64 = 0x40, "OP_PUSHBYTES_64";
+    OpPushBytes65 = 0x41, "OP_PUSHBYTES_65";
+    OpPushBytes66 = 0x42, "OP_PUSHBYTES_66";
+    OpPushBytes67 = 0x43, "OP_PUSHBYTES_67";
+    OpPushBytes68 = 0x44, "OP_PUSHBYTES_68";
+    OpPushBytes69 = 0x45, "OP_PUSHBYTES_69";
+    OpPushBytes70 = 0x46, "OP_PUSHBYTES_70";
+    OpPushBytes71 = 0x47, "OP_PUSHBYTES_71";
+    OpPushBytes72 = 0x48, "OP_PUSHBYTES_72";
+    OpPushBytes73 = 0x49, "OP_PUSHBYTES_73";
+    OpPushBytes74 = 0x4a, "OP_PUSHBYTES_74";
+    OpPushBytes75 = 0x4b, "OP_PUSHBYTES_75";
+    OpPushData1 = 0x4c, "OP_PUSHDATA1";
+    OpPushData2 = 0x4d, "OP_PUSHDATA2";
+    OpPushData4 = 0x4e, "OP_PUSHDATA4";
+    Op1Negate = 0x4f, "OP_1NEGATE";
+    OpReserved = 0x50, "OP_RESERVED";
+    Op1 = 0x51, "OP_1";
+    Op2 = 0x52, "OP_2";
+    Op3 = 0x53, "OP_3";
+    Op4 = 0x54, "OP_4";
+    Op5 = 0x55, "OP_5";
+    Op6 = 0x56, "OP_6";
+    Op7 = 0x57, "OP_7";
+    Op8 = 0x58, "OP_8";
+    Op9 = 0x59, "OP_9";
+    Op10 = 0x5a, "OP_10";
+    Op11 = 0x5b, "OP_11";
+    Op12 = 0x5c, "OP_12";
+    Op13 = 0x5d, "OP_13";
+    Op14 = 0x5e, "OP_14";
+    Op15 = 0x5f, "OP_15";
+    Op16 = 0x60, "OP_16";
+    OpNop = 0x61, "OP_NOP";
+    OpVer = 0x62, "OP_VER";
+    OpIf = 0x63, "OP_IF";
+    OpNotIf = 0x64, "OP_NOTIF";
+    OpVerIf = 0x65, "OP_VERIF";
+    OpVerNotIf = 0x66, "OP_VERNOTIF";
+    OpElse = 0x67, "OP_ELSE";
+    OpEndIf = 0x68, "OP_ENDIF";
+    OpVerify = 0x69, "OP_VERIFY";
+    OpReturn = 0x6a, "OP_RETURN";
+    OpToAltStack = 0x6b, "OP_TOALTSTACK";
+    OpFromAltStack = 0x6c, "OP_FROMALTSTACK";
+    Op2Drop = 0x6d, "OP_2DROP";
+    Op2Dup = 0x6e, "OP_2DUP";
+    Op3Dup = 0x6f, "OP_3DUP";
+    Op2Over = 0x70, "OP_2OVER";
+    Op2Rot = 0x71, "OP_2ROT";
+    Op2Swap = 0x72, "OP_2SWAP";
+    OpIfDup = 0x73, "OP_IFDUP";
+    OpDepth = 0x74, "OP_DEPTH";
+    OpDrop = 0x75, "OP_DROP";
+    OpDup = 0x76, "OP_DUP";
+    OpNip = 0x77, "OP_NIP";
+    OpOver = 0x78, "OP_OVER";
+    OpPick = 0x79, "OP_PICK";
+    OpRoll = 0x7a, "OP_ROLL";
+    OpRot = 0x7b, "OP_ROT";
+    OpSwap = 0x7c, "OP_SWAP";
+    OpTuck = 0x7d, "OP_TUCK";
+    OpCat = 0x7e, "OP_CAT";
+    OpSubstr = 0x7f, "OP_SUBSTR";
+    OpLeft = 0x80, "OP_LEFT";
+    OpRight = 0x81, "OP_RIGHT";
+    OpSize = 0x82, "OP_SIZE";
+    OpInvert = 0x83, "OP_INVERT";
+    OpAnd = 0x84, "OP_AND";
+    OpOr = 0x85, "OP_OR";
+    OpXor = 0x86, "OP_XOR";
+    OpEqual = 0x87, "OP_EQUAL";
+    OpEqualVerify = 0x88, "OP_EQUALVERIFY";
+    OpReserved1 = 0x89, "OP_RESERVED1";
+    OpReserved2 = 0x8a, "OP_RESERVED2";
+    Op1Add = 0x8b, "OP_1ADD";
+    Op1Sub = 0x8c, "OP_1SUB";
+    Op2Mul = 0x8d, "OP_2MUL";
+    Op2Div = 0x8e, "OP_2DIV";
+    OpNegate = 0x8f, "OP_NEGATE";
+    OpAbs = 0x90, "OP_ABS";
+    OpNot = 0x91, "OP_NOT";
+    Op0NotEqual = 0x92, "OP_0NOTEQUAL";
+    OpAdd = 0x93, "OP_ADD";
+    OpSub = 0x94, "OP_SUB";
+    OpMul = 0x95, "OP_MUL";
+    OpDiv = 0x96, "OP_DIV";
+    OpMod = 0x97, "OP_MOD";
+    OpLShift = 0x98, "OP_LSHIFT";
+    OpRShift = 0x99, "OP_RSHIFT";
+    OpBoolAnd = 0x9a, "OP_BOOLAND";
+    OpBoolOr = 0x9b, "OP_BOOLOR";
+    OpNumEqual = 0x9c, "OP_NUMEQUAL";
+    OpNumEqualVerify = 0x9d, "OP_NUMEQUALVERIFY";
+    OpNumNotEqual = 0x9e, "OP_NUMNOTEQUAL";
+    OpLessThan = 0x9f, "OP_LESSTHAN";
+    OpGreaterThan = 0xa0, "OP_GREATERTHAN";
+    OpLessThanOrEqual = 0xa1, "OP_LESSTHANOREQUAL";
+    OpGreaterThanOrEqual = 0xa2, "OP_GREATERTHANOREQUAL";
+    OpMin = 0xa3, "OP_MIN";
+    OpMax = 0xa4, "OP_MAX";
+    OpWithin = 0xa5, "OP_WITHIN";
+    OpRipemd160 = 0xa6, "OP_RIPEMD160";
+    OpSha1 = 0xa7, "OP_SHA1";
+    OpSha256 = 0xa8, "OP_SHA256";
+    OpHash160 = 0xa9, "OP_HASH160";
+    OpHash256 = 0xaa, "OP_HASH256";
+    OpCodeseparator = 0xab, "OP_CODESEPARATOR";
+    OpCheckSig = 0xac, "OP_CHECKSIG";
+    OpCheckSigVerify = 0xad, "OP_CHECKSIGVERIFY";
+    OpCheckMultisig = 0xae, "OP_CHECKMULTISIG";
+    OpCheckMultisigVerify = 0xaf, "OP_CHECKMULTISIGVERIFY";
+    OpNop1 = 0xb0, "OP_NOP1";
+    OpCheckLockTimeVerify = 0xb1, "OP_CHECKLOCKTIMEVERIFY";
+    OpCheckSequenceVerify = 0xb2, "OP_CHECKSEQUENCEVERIFY";
+    OpNop4 = 0xb3, "OP_NOP4";
+    OpNop5 = 0xb4, "OP_NOP5";
+    OpNop6 = 0xb5, "OP_NOP6";
+    OpNop7 = 0xb6, "OP_NOP7";
+    OpNop8 = 0xb7, "OP_NOP8";
+    OpNop9 = 0xb8, "OP_NOP9";
+    OpNop10 = 0xb9, "OP_NOP10";
+    OpCheckSigAdd = 0xba, "OP_CHECKSIGADD";
+    OpUnknown187 = 0xbb, "OP_UNKNOWN_187";
+    OpUnknown188 = 0xbc, "OP_UNKNOWN_188";
+    OpUnknown189 = 0xbd, "OP_UNKNOWN_189";
+    OpUnknown190 = 0xbe, "OP_UNKNOWN_190";
+    OpUnknown191 = 0xbf, "OP_UNKNOWN_191";
+    OpUnknown192 = 0xc0, "OP_UNKNOWN_192";
+    OpUnknown193 = 0xc1, "OP_UNKNOWN_193";
+    OpUnknown194 = 0xc2, "OP_UNKNOWN_194";
+    OpUnknown195 = 0xc3, "OP_UNKNOWN_195";
+    OpUnknown196 = 0xc4, "OP_UNKNOWN_196";
+    OpUnknown197 = 0xc5, "OP_UNKNOWN_197";
+    OpUnknown198 = 0xc6, "OP_UNKNOWN_198";
+    OpUnknown199 = 0xc7, "OP_UNKNOWN_199";
+    OpUnknown200 = 0xc8, "OP_UNKNOWN_200";
+    OpUnknown201 = 0xc9, "OP_UNKNOWN_201";
+    OpUnknown202 = 0xca, "OP_UNKNOWN_202";
+    OpUnknown203 = 0xcb, "OP_UNKNOWN_203";
+    OpUnknown204 = 0xcc, "OP_UNKNOWN_204";
+    OpUnknown205 = 0xcd, "OP_UNKNOWN_205";
+    OpUnknown206 = 0xce, "OP_UNKNOWN_206";
+    OpUnknown207 = 0xcf, "OP_UNKNOWN_207";
+    OpUnknown208 = 0xd0, "OP_UNKNOWN_208";
+    OpUnknown209 = 0xd1, "OP_UNKNOWN_209";
+    OpUnknown210 = 0xd2, "OP_UNKNOWN_210";
+    OpUnknown211 = 0xd3, "OP_UNKNOWN_211";
+    OpUnknown212 = 0xd4, "OP_UNKNOWN_212";
+    OpUnknown213 = 0xd5, "OP_UNKNOWN_213";
+    OpUnknown214 = 0xd6, "OP_UNKNOWN_214";
+    OpUnknown215 = 0xd7, "OP_UNKNOWN_215";
+    OpUnknown216 = 0xd8, "OP_UNKNOWN_216";
+    OpUnknown217 = 0xd9, "OP_UNKNOWN_217";
+    OpUnknown218 = 0xda, "OP_UNKNOWN_218";
+    OpUnknown219 = 0xdb, "OP_UNKNOWN_219";
+    OpUnknown220 = 0xdc, "OP_UNKNOWN_220";
+    OpUnknown221 = 0xdd, "OP_UNKNOWN_221";
+    OpUnknown222 = 0xde, "OP_UNKNOWN_222";
+    OpUnknown223 = 0xdf, "OP_UNKNOWN_223";
+    OpUnknown224 = 0xe0, "OP_UNKNOWN_224";
+    OpUnknown225 = 0xe1, "OP_UNKNOWN_225";
+    OpUnknown226 = 0xe2, "OP_UNKNOWN_226";
+    OpUnknown227 = 0xe3, "OP_UNKNOWN_227";
+    OpUnknown228 = 0xe4, "OP_UNKNOWN_228";
+    OpUnknown229 = 0xe5, "OP_UNKNOWN_229";
+    OpUnknown230 = 0xe6, "OP_UNKNOWN_230";
+    OpUnknown231 = 0xe7, "OP_UNKNOWN_231";
+    OpUnknown232 = 0xe8, "OP_UNKNOWN_232";
+    OpUnknown233 = 0xe9, "OP_UNKNOWN_233";
+    OpUnknown234 = 0xea, "OP_UNKNOWN_234";
+    OpUnknown235 = 0xeb, "OP_UNKNOWN_235";
+    OpUnknown236 = 0xec, "OP_UNKNOWN_236";
+    OpUnknown237 = 0xed, "OP_UNKNOWN_237";
+    OpUnknown238 = 0xee, "OP_UNKNOWN_238";
+    OpUnknown239 = 0xef, "OP_UNKNOWN_239";
+    OpUnknown240 = 0xf0, "OP_UNKNOWN_240";
+    OpUnknown241 = 0xf1, "OP_UNKNOWN_241";
+    OpUnknown242 = 0xf2, "OP_UNKNOWN_242";
+    OpUnknown243 = 0xf3, "OP_UNKNOWN_243";
+    OpUnknown244 = 0xf4, "OP_UNKNOWN_244";
+    OpUnknown245 = 0xf5, "OP_UNKNOWN_245";
+    OpUnknown246 = 0xf6, "OP_UNKNOWN_246";
+    OpUnknown247 = 0xf7, "OP_UNKNOWN_247";
+    OpUnknown248 = 0xf8, "OP_UNKNOWN_248";
+    OpUnknown249 = 0xf9, "OP_UNKNOWN_249";
+    OpUnknown250 = 0xfa, "OP_UNKNOWN_250";
+    OpUnknown251 = 0xfb, "OP_UNKNOWN_251";
+    OpUnknown252 = 0xfc, "OP_UNKNOWN_252";
+    OpUnknown253 = 0xfd, "OP_UNKNOWN_253";
+    OpUnknown254 = 0xfe, "OP_UNKNOWN_254";
+    OpInvalidOpcode = 0xff, "OP_INVALIDOPCODE";
+}
+
+impl Copy for OpCode {}
+
+impl OpCode {
+    pub fn num(&self) -> u8 {
+        *self as u8
+    }
+
+    /// OP_0 .. OP_16, the opcodes a push only script may contain. OP_RESERVED is among them
+    /// like in Bitcoin Core, although it fails when run.
+    pub fn is_push(&self) -> bool {
+        self.num() <= OpCode::Op16.num()
+    }
+
+    /// OP_IF .. OP_ENDIF, which run even inside a branch not taken
+    pub fn is_conditional(&self) -> bool {
+        (OpCode::OpIf.num()..=OpCode::OpEndIf.num()).contains(&self.num())
+    }
+
+    /// Disabled since 2010 (CVE-2010-5137), a script containing one fails even where the
+    /// opcode does not run
+    pub fn is_disabled(&self) -> bool {
+        match self {
+            OpCode::OpCat
+            | OpCode::OpSubstr
+            | OpCode::OpLeft
+            | OpCode::OpRight
+            | OpCode::OpInvert
+            | OpCode::OpAnd
+            | OpCode::OpOr
+            | OpCode::OpXor
+            | OpCode::Op2Mul
+            | OpCode::Op2Div
+            | OpCode::OpMul
+            | OpCode::OpDiv
+            | OpCode::OpMod
+            | OpCode::OpLShift
+            | OpCode::OpRShift => true,
+            _ => false,
+        }
+    }
+
+    /// Small integer OP_0, OP_1NEGATE and OP_1 .. OP_16 pushes
+    pub fn small_int(&self) -> Option<i64> {
+        match self.num() {
+            0x00 => Some(0),
+            0x4f => Some(-1),
+            num @ 0x51..=0x60 => Some(i64::from(num - 0x50)),
+            _ => None,
+        }
+    }
+
+    /// Name used by Bitcoin Core's script asm, small integers are printed as numbers and
+    /// undefined opcodes as OP_UNKNOWN
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::Op0 => "0",
+            OpCode::Op1Negate => "-1",
+            OpCode::Op1 => "1",
+            OpCode::Op2 => "2",
+            OpCode::Op3 => "3",
+            OpCode::Op4 => "4",
+            OpCode::Op5 => "5",
+            OpCode::Op6 => "6",
+            OpCode::Op7 => "7",
+            OpCode::Op8 => "8",
+            OpCode::Op9 => "9",
+            OpCode::Op10 => "10",
+            OpCode::Op11 => "11",
+            OpCode::Op12 => "12",
+            OpCode::Op13 => "13",
+            OpCode::Op14 => "14",
+            OpCode::Op15 => "15",
+            OpCode::Op16 => "16",
+            _ if (0xbb..=0xfe).contains(&self.num()) => "OP_UNKNOWN",
+            _ => self.canonical_name(),
+        }
+    }
+}
+
+impl From<u8> for OpCode {
+    fn from(code: u8) -> Self {
+        OP_CODES[usize::from(code)]
+    }
+}
+
+impl From<OpCode> for u8 {
+    fn from(op_code: OpCode) -> Self {
+        op_code.num()
+    }
+}
+
+impl Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.canonical_name())
+    }
+}
+
+mod test {
+    use super::OpCode;
+
+    #[test]
+    fn test_op_code_round_trip() {
+        for code in 0..=255u8 {
+            assert_eq!(OpCode::from(code) as u8, code);
+        }
+        assert_eq!(OpCode::from(0x76), OpCode::OpDup);
+        assert_eq!(u8::from(OpCode::OpCheckSigAdd), 0xba);
+        assert_eq!(OpCode::OpPushBytes20.to_string(), "OP_PUSHBYTES_20");
+        assert_eq!(OpCode::Op16.to_string(), "OP_16");
+        assert_eq!(OpCode::Op16.name(), "16");
+        assert_eq!(OpCode::from(0xc0).to_string(), "OP_UNKNOWN_192");
+        assert_eq!(OpCode::from(0xc0).name(), "OP_UNKNOWN");
+        assert_eq!(OpCode::OpInvalidOpcode.name(), "OP_INVALIDOPCODE");
+
+        assert!(OpCode::Op0.is_push() && OpCode::OpReserved.is_push() && OpCode::Op16.is_push());
+        assert!(!OpCode::OpNop.is_push());
+        assert!(OpCode::OpVerIf.is_conditional() && OpCode::OpEndIf.is_conditional());
+        assert!(!OpCode::OpVerify.is_conditional());
+        assert!(OpCode::OpCat.is_disabled() && !OpCode::OpSize.is_disabled());
+        assert_eq!(OpCode::Op1Negate.small_int(), Some(-1));
+        assert_eq!(OpCode::Op7.small_int(), Some(7));
+        assert_eq!(OpCode::OpNop.small_int(), None);
+    }
+}
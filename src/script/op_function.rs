@@ -1,8 +1,15 @@
+use sha2::{Digest, Sha256};
+
+use super::script_num::{ScriptNum, DEFAULT_MAX_NUM_SIZE};
 use super::stack_element::StackElement;
+use super::ScriptError;
 use crate::wallet::{hash160, hash256, Hash256, Hex, S256Point, Signature};
 
 pub type Stack = Vec<StackElement>;
 
+/// Checks a signature's encoding and gives the message it signs, from its sighash byte
+pub type SigHasher<'a> = dyn FnMut(&[u8]) -> Result<Hash256, ScriptError> + 'a;
+
 impl Hex for Stack {
     fn hex(&self) -> String {
         let mut ret = String::new();
@@ -14,51 +21,221 @@ impl Hex for Stack {
 }
 
 pub fn op_dup(stack: &mut Stack) -> bool {
-    if stack.len() < 1 {
+    match stack.last().and_then(StackElement::as_bytes) {
+        Some(top) => {
+            let top = top.to_vec();
+            stack.push(StackElement::DataElement(top));
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn op_drop(stack: &mut Stack) -> bool {
+    stack.pop().is_some()
+}
+
+/// Swaps the top two elements
+pub fn op_swap(stack: &mut Stack) -> bool {
+    let len = stack.len();
+    if len < 2 {
         return false;
     }
-    let last = stack.last().unwrap();
-    match last {
-        StackElement::DataElement(d) => {
-            let d = (*d).clone();
-            stack.push(StackElement::DataElement(d));
+    stack.swap(len - 1, len - 2);
+    true
+}
+
+/// Pushes the length of the top element, which stays
+pub fn op_size(stack: &mut Stack) -> bool {
+    match stack.last().and_then(StackElement::as_bytes) {
+        Some(top) => {
+            let size = ScriptNum::from(top.len() as i64).encode();
+            stack.push(StackElement::DataElement(size));
+            true
         }
-        _ => unreachable!(),
+        None => false,
     }
-    true
 }
 
-pub fn op_hash256(stack: &mut Stack) -> bool {
-    if stack.len() < 1 {
-        return false;
+/// Replaces the top element with its sha256
+pub fn op_sha256(stack: &mut Stack) -> bool {
+    match stack.pop() {
+        Some(StackElement::DataElement(d)) => {
+            stack.push(StackElement::DataElement(Sha256::digest(&d).to_vec()));
+            true
+        }
+        _ => false,
     }
+}
 
-    let last = stack.last().unwrap();
-    match last {
-        StackElement::DataElement(d) => {
-            let d = (*d).clone();
+/// Replaces the top element with its hash256
+pub fn op_hash256(stack: &mut Stack) -> bool {
+    match stack.pop() {
+        Some(StackElement::DataElement(d)) => {
             let hash = hash256(&d[..]);
             stack.push(StackElement::DataElement(hash.to_vec()));
+            true
         }
-        _ => unreachable!(),
+        _ => false,
     }
-    true
 }
 
+/// Replaces the top element with its hash160
 pub fn op_hash160(stack: &mut Stack) -> bool {
-    if stack.len() < 1 {
+    match stack.pop() {
+        Some(StackElement::DataElement(d)) => {
+            let hash = hash160(&d[..]);
+            stack.push(StackElement::DataElement(hash.to_vec()));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Script truthiness, any non zero byte except a lone sign bit ("negative zero")
+pub fn cast_to_bool(data: &[u8]) -> bool {
+    for (i, byte) in data.iter().enumerate() {
+        if *byte != 0 {
+            return !(i == data.len() - 1 && *byte == 0x80);
+        }
+    }
+    false
+}
+
+/// OP_0, OP_1NEGATE and OP_1 .. OP_16
+pub fn op_push_num(stack: &mut Stack, num: i64) -> bool {
+    stack.push(StackElement::DataElement(ScriptNum::from(num).encode()));
+    true
+}
+
+fn pop_num(stack: &mut Stack, require_minimal: bool) -> Option<i64> {
+    let element = stack.pop()?;
+    ScriptNum::decode(element.as_bytes()?, require_minimal, DEFAULT_MAX_NUM_SIZE)
+        .ok()
+        .map(i64::from)
+}
+
+/// Numeric opcodes OP_1ADD .. OP_WITHIN, operands are at most 4 bytes
+pub fn op_arithmetic(code: u8, stack: &mut Stack, require_minimal: bool) -> bool {
+    let arity = match code {
+        0x8b..=0x92 => 1,
+        0xa5 => 3,
+        _ => 2,
+    };
+    if stack.len() < arity {
         return false;
     }
+    let mut nums = Vec::with_capacity(arity);
+    for _ in 0..arity {
+        match pop_num(stack, require_minimal) {
+            Some(num) => nums.insert(0, num),
+            None => return false,
+        }
+    }
 
-    let last = stack.last().unwrap();
-    match last {
-        StackElement::DataElement(d) => {
-            let d = (*d).clone();
-            let hash = hash160(&d[..]);
-            stack.push(StackElement::DataElement(hash.to_vec()));
+    let result = match (code, &nums[..]) {
+        (0x8b, [a]) => a + 1,
+        (0x8c, [a]) => a - 1,
+        (0x8f, [a]) => -a,
+        (0x90, [a]) => a.abs(),
+        (0x91, [a]) => (*a == 0) as i64,
+        (0x92, [a]) => (*a != 0) as i64,
+        (0x93, [a, b]) => a + b,
+        (0x94, [a, b]) => a - b,
+        (0x9a, [a, b]) => (*a != 0 && *b != 0) as i64,
+        (0x9b, [a, b]) => (*a != 0 || *b != 0) as i64,
+        (0x9c, [a, b]) | (0x9d, [a, b]) => (a == b) as i64,
+        (0x9e, [a, b]) => (a != b) as i64,
+        (0x9f, [a, b]) => (a < b) as i64,
+        (0xa0, [a, b]) => (a > b) as i64,
+        (0xa1, [a, b]) => (a <= b) as i64,
+        (0xa2, [a, b]) => (a >= b) as i64,
+        (0xa3, [a, b]) => *a.min(b),
+        (0xa4, [a, b]) => *a.max(b),
+        (0xa5, [x, min, max]) => (min <= x && x < max) as i64,
+        #[cfg(feature = "op_experiments")]
+        (0x8d, [a]) => a * 2,
+        #[cfg(feature = "op_experiments")]
+        (0x8e, [a]) => a >> 1,
+        #[cfg(feature = "op_experiments")]
+        (0x95, [a, b]) => a * b,
+        #[cfg(feature = "op_experiments")]
+        (0x96, [_, 0]) | (0x97, [_, 0]) => return false,
+        #[cfg(feature = "op_experiments")]
+        (0x96, [a, b]) => a / b,
+        #[cfg(feature = "op_experiments")]
+        (0x97, [a, b]) => a % b,
+        #[cfg(feature = "op_experiments")]
+        (0x98, [a, b]) if (0..32).contains(b) => a << b,
+        #[cfg(feature = "op_experiments")]
+        (0x99, [a, b]) if (0..32).contains(b) => a >> b,
+        _ => return false,
+    };
+
+    // OP_NUMEQUALVERIFY leaves nothing behind and fails the script when false
+    if code == 0x9d {
+        return result != 0;
+    }
+    op_push_num(stack, result)
+}
+
+/// OP_CAT, OP_SUBSTR, OP_LEFT, OP_RIGHT, OP_INVERT, OP_AND, OP_OR and OP_XOR as they
+/// were before being disabled, results stay within the 520 bytes element limit and the
+/// bitwise operands have to be the same size
+#[cfg(feature = "op_experiments")]
+pub fn op_experimental(code: u8, stack: &mut Stack, require_minimal: bool) -> bool {
+    let (nums, operands) = match code {
+        0x7f => (2, 1),
+        0x80 | 0x81 => (1, 1),
+        0x83 => (0, 1),
+        _ => (0, 2),
+    };
+    if stack.len() < nums + operands {
+        return false;
+    }
+    let mut args = Vec::with_capacity(nums);
+    for _ in 0..nums {
+        match pop_num(stack, require_minimal) {
+            Some(num) if num >= 0 => args.insert(0, num as usize),
+            _ => return false,
         }
-        _ => unreachable!(),
     }
+    let b = match stack.pop() {
+        Some(StackElement::DataElement(b)) => b,
+        _ => return false,
+    };
+    let result = match (code, &args[..]) {
+        (0x7e, _) => match stack.pop() {
+            Some(StackElement::DataElement(a)) => [&a[..], &b[..]].concat(),
+            _ => return false,
+        },
+        (0x7f, [begin, size]) => match b.get(*begin..begin.saturating_add(*size)) {
+            Some(part) => part.to_vec(),
+            None => return false,
+        },
+        (0x80, [size]) => b[..(*size).min(b.len())].to_vec(),
+        (0x81, [size]) => b[b.len() - (*size).min(b.len())..].to_vec(),
+        (0x83, _) => b.iter().map(|byte| !byte).collect(),
+        (0x84..=0x86, _) => {
+            let a = match stack.pop() {
+                Some(StackElement::DataElement(a)) if a.len() == b.len() => a,
+                _ => return false,
+            };
+            a.iter()
+                .zip(b.iter())
+                .map(|(x, y)| match code {
+                    0x84 => x & y,
+                    0x85 => x | y,
+                    _ => x ^ y,
+                })
+                .collect()
+        }
+        _ => return false,
+    };
+    if result.len() > 520 {
+        return false;
+    }
+    stack.push(StackElement::DataElement(result));
     true
 }
 
@@ -66,48 +243,142 @@ pub fn op_unknown(stack: &mut Stack) -> bool {
     false
 }
 
-pub fn op_check_sig(stack: &mut Stack, hash: Hash256) -> bool {
+pub fn op_verify(stack: &mut Stack) -> bool {
+    stack.pop().map_or(false, |top| top.to_bool())
+}
+
+pub fn op_equal(stack: &mut Stack) -> bool {
     if stack.len() < 2 {
         return false;
     }
-    let sec = stack.pop().expect("stack can not pop");
+    let a = stack.pop().expect("stack can not pop");
+    let b = stack.pop().expect("stack can not pop");
+    let equal = match (a.as_bytes(), b.as_bytes()) {
+        (Some(a), Some(b)) => a == b,
+        _ => return false,
+    };
+    stack.push(StackElement::DataElement(
+        ScriptNum::from(equal as i64).encode(),
+    ));
+    true
+}
 
-    let sig = stack.pop().expect("stack can not pop");
+/// OP_EQUAL then OP_VERIFY, fails the script right away on different elements
+pub fn op_equal_verify(stack: &mut Stack) -> bool {
+    op_equal(stack) && op_verify(stack)
+}
 
-    let point = S256Point::parse_sec(&sec);
-    let sig = Signature::parse_der(&sig[0..(sig.len() - 1)]);
+/// An empty or undecodable signature or an undecodable public key fails the check without
+/// failing the script
+fn check_ecdsa(sig: &[u8], sec: &[u8], sig_hash: &mut SigHasher) -> Result<bool, ScriptError> {
+    if sig.is_empty() {
+        return Ok(false);
+    }
+    let hash = sig_hash(sig)?;
+    let point = match S256Point::parse_sec(sec) {
+        Ok(point) => point,
+        Err(_) => return Ok(false),
+    };
+    match Signature::try_parse_der(&sig[0..(sig.len() - 1)]) {
+        Ok(signature) => Ok(point.verify(hash, signature)),
+        Err(_) => Ok(false),
+    }
+}
 
-    if point.verify(hash, sig) {
-        stack.push(StackElement::DataElement(encode_num(1)));
-    } else {
-        stack.push(StackElement::DataElement(encode_num(0)));
+/// OP_CHECKSIG and OP_CHECKSIGVERIFY
+pub fn op_check_sig(
+    code: u8,
+    stack: &mut Stack,
+    sig_hash: &mut SigHasher,
+) -> Result<bool, ScriptError> {
+    if stack.len() < 2 {
+        return Ok(false);
     }
-    true
+    let sec = stack.pop().expect("stack can not pop");
+    let sig = stack.pop().expect("stack can not pop");
+    let success = match (sig.as_bytes(), sec.as_bytes()) {
+        (Some(sig), Some(sec)) => check_ecdsa(sig, sec, sig_hash)?,
+        _ => return Ok(false),
+    };
+    if code == 0xad {
+        return Ok(success);
+    }
+    stack.push(StackElement::DataElement(
+        ScriptNum::from(success as i64).encode(),
+    ));
+    Ok(true)
 }
 
-fn encode_num(num: i8) -> Vec<u8> {
-    if num == 0 {
-        return vec![];
+/// Most public keys OP_CHECKMULTISIG takes
+pub const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+
+/// Number of keys an OP_CHECKMULTISIG on `stack` checks against, the top element, when
+/// it is a valid key count
+pub fn multisig_key_count(stack: &Stack) -> Option<usize> {
+    let top = stack.last()?.as_bytes()?;
+    match ScriptNum::decode(top, false, DEFAULT_MAX_NUM_SIZE).map(i64::from) {
+        Ok(n) if (0..=MAX_PUBKEYS_PER_MULTISIG).contains(&n) => Some(n as usize),
+        _ => None,
     }
-    let mut abs_num = num.abs() as u8;
-    let negative = if num < 0 { true } else { false };
+}
 
-    let mut result = vec![];
-    while abs_num != 0 {
-        result.push(abs_num & 0xff);
-        abs_num = abs_num.checked_shr(8).unwrap_or(0);
+/// OP_CHECKMULTISIG and OP_CHECKMULTISIGVERIFY. Signatures have to come in the order of
+/// their keys, each key is tried once and the check stops as soon as the keys left are
+/// fewer than the signatures left. The extra element the original implementation pops
+/// is popped too, with `null_dummy` it has to be empty.
+pub fn op_check_multisig(
+    code: u8,
+    stack: &mut Stack,
+    sig_hash: &mut SigHasher,
+    null_dummy: bool,
+) -> Result<bool, ScriptError> {
+    let key_count = match multisig_key_count(stack) {
+        Some(key_count) => key_count,
+        None => return Ok(false),
+    };
+    stack.pop();
+    if stack.len() < key_count + 1 {
+        return Ok(false);
+    }
+    // top of the stack first, the last key of the script
+    let keys: Vec<StackElement> = (0..key_count).filter_map(|_| stack.pop()).collect();
+    let sig_count = match pop_num(stack, false) {
+        Some(m) if m >= 0 && m as usize <= key_count => m as usize,
+        _ => return Ok(false),
+    };
+    if stack.len() < sig_count + 1 {
+        return Ok(false);
+    }
+    let sigs: Vec<StackElement> = (0..sig_count).filter_map(|_| stack.pop()).collect();
+    let dummy = stack.pop().expect("stack can not pop");
+    if null_dummy && dummy.as_bytes().map_or(true, |dummy| !dummy.is_empty()) {
+        return Err(ScriptError::SigNullDummy);
     }
 
-    if let Some(last) = result.last_mut() {
-        if *last & 0x80 > 0 {
-            if negative {
-                result.push(0x80_u8);
-            } else {
-                result.push(0);
+    let mut keys = keys.iter();
+    let mut success = true;
+    for (index, sig) in sigs.iter().enumerate() {
+        loop {
+            if keys.len() < sigs.len() - index {
+                success = false;
+                break;
+            }
+            let key = keys.next().expect("more keys than signatures left");
+            match (sig.as_bytes(), key.as_bytes()) {
+                (Some(sig), Some(key)) if check_ecdsa(sig, key, sig_hash)? => break,
+                (Some(_), Some(_)) => {}
+                _ => return Ok(false),
             }
-        } else if negative {
-            *last |= 0x80;
         }
+        if !success {
+            break;
+        }
+    }
+    if code == 0xaf {
+        return Ok(success);
     }
-    result
+    stack.push(StackElement::DataElement(
+        ScriptNum::from(success as i64).encode(),
+    ));
+    Ok(true)
 }
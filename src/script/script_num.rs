@@ -0,0 +1,138 @@
+use std::fmt::Display;
+
+/// Default size of a numeric operand, results may take one more byte
+pub const DEFAULT_MAX_NUM_SIZE: usize = 4;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum ScriptNumError {
+    #[fail(display = "script number of {} bytes overflows", _0)]
+    Overflow(usize),
+    #[fail(display = "script number is not minimally encoded")]
+    NonMinimal,
+}
+
+/// Script number, little endian magnitude with the sign in the highest bit of the last byte
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+pub struct ScriptNum(i64);
+impl Copy for ScriptNum {}
+
+impl Display for ScriptNum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<i64> for ScriptNum {
+    fn from(num: i64) -> Self {
+        ScriptNum(num)
+    }
+}
+
+impl From<ScriptNum> for i64 {
+    fn from(num: ScriptNum) -> i64 {
+        num.0
+    }
+}
+
+impl ScriptNum {
+    /// Operands longer than `max_size` overflow, with `require_minimal` any padding
+    /// byte is rejected like under MINIMALDATA
+    pub fn decode(
+        data: &[u8],
+        require_minimal: bool,
+        max_size: usize,
+    ) -> Result<Self, ScriptNumError> {
+        if data.len() > max_size {
+            return Err(ScriptNumError::Overflow(data.len()));
+        }
+        if require_minimal && !data.is_empty() {
+            // the last byte may only be zero (besides the sign) when the byte before
+            // needs its highest bit for the magnitude
+            let last = data[data.len() - 1];
+            if last & 0x7f == 0 && (data.len() == 1 || data[data.len() - 2] & 0x80 == 0) {
+                return Err(ScriptNumError::NonMinimal);
+            }
+        }
+        if data.is_empty() {
+            return Ok(ScriptNum(0));
+        }
+
+        let mut ret = 0i64;
+        for (i, byte) in data.iter().enumerate() {
+            ret |= i64::from(*byte) << (8 * i);
+        }
+        let sign_bit = 0x80i64 << (8 * (data.len() - 1));
+        if ret & sign_bit != 0 {
+            Ok(ScriptNum(-(ret & !sign_bit)))
+        } else {
+            Ok(ScriptNum(ret))
+        }
+    }
+
+    /// Minimal encoding, zero is the empty vector
+    pub fn encode(&self) -> Vec<u8> {
+        if self.0 == 0 {
+            return vec![];
+        }
+        let negative = self.0 < 0;
+        let mut abs_num = self.0.abs() as u64;
+
+        let mut result = vec![];
+        while abs_num != 0 {
+            result.push((abs_num & 0xff) as u8);
+            abs_num >>= 8;
+        }
+
+        let last = result.len() - 1;
+        if result[last] & 0x80 > 0 {
+            result.push(if negative { 0x80 } else { 0x00 });
+        } else if negative {
+            result[last] |= 0x80;
+        }
+        result
+    }
+
+    pub fn value(&self) -> i64 {
+        self.0
+    }
+}
+
+mod test {
+    use super::{ScriptNum, ScriptNumError};
+
+    #[test]
+    fn test_script_num_encode_decode() {
+        let cases: Vec<(i64, Vec<u8>)> = vec![
+            (0, vec![]),
+            (1, vec![0x01]),
+            (-1, vec![0x81]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x00]),
+            (-128, vec![0x80, 0x80]),
+            (255, vec![0xff, 0x00]),
+            (-255, vec![0xff, 0x80]),
+            (2_147_483_647, vec![0xff, 0xff, 0xff, 0x7f]),
+            (-2_147_483_647, vec![0xff, 0xff, 0xff, 0xff]),
+            (4_294_967_294, vec![0xfe, 0xff, 0xff, 0xff, 0x00]),
+        ];
+        for (num, bytes) in cases {
+            assert_eq!(ScriptNum::from(num).encode(), bytes);
+            assert_eq!(ScriptNum::decode(&bytes, true, 5), Ok(ScriptNum::from(num)));
+        }
+
+        assert_eq!(
+            ScriptNum::decode(&[0x00, 0x00, 0x00, 0x00, 0x01], false, 4),
+            Err(ScriptNumError::Overflow(5))
+        );
+        // negative zero and padded values are only accepted without MINIMALDATA
+        assert_eq!(ScriptNum::decode(&[0x80], false, 4), Ok(ScriptNum::from(0)));
+        assert_eq!(
+            ScriptNum::decode(&[0x80], true, 4),
+            Err(ScriptNumError::NonMinimal)
+        );
+        assert_eq!(
+            ScriptNum::decode(&[0x05, 0x00], true, 4),
+            Err(ScriptNumError::NonMinimal)
+        );
+    }
+}
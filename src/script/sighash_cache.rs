@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use super::{LockTimeContext, Script};
+use crate::transaction::{ScriptPubKey, Transaction};
+use crate::wallet::Hash256;
+
+/// Signature hashes of one input memoized for an evaluation, keyed by the sighash type and
+/// the position of the last OP_CODESEPARATOR that ran before the check, so a 15-of-15 CHECKMULTISIG hashes
+/// the transaction once per sighash type
+pub struct SigHashCache<'a> {
+    tx: &'a Transaction,
+    input_index: usize,
+    script_code: &'a Script,
+    digests: HashMap<(u32, Option<usize>), Hash256>,
+}
+
+impl<'a> SigHashCache<'a> {
+    /// `script_code` is the script being spent, the previous output's script pubkey
+    pub fn new(tx: &'a Transaction, input_index: usize, script_code: &'a Script) -> Self {
+        SigHashCache {
+            tx,
+            input_index,
+            script_code,
+            digests: HashMap::new(),
+        }
+    }
+
+    pub fn sig_hash(&mut self, sighash_type: u32, codesep_pos: Option<usize>) -> Hash256 {
+        let (tx, input_index, script_code) = (self.tx, self.input_index, self.script_code);
+        *self
+            .digests
+            .entry((sighash_type, codesep_pos))
+            .or_insert_with(|| {
+                let script_code = ScriptPubKey {
+                    content: script_code.script_code(codesep_pos),
+                };
+                tx.sig_hash(input_index, &script_code, sighash_type)
+            })
+    }
+
+    /// Locktime and sequence of the spending input
+    pub fn lock_time_context(&self) -> LockTimeContext {
+        LockTimeContext::new(self.tx, self.input_index)
+    }
+
+    /// Number of digests computed so far
+    pub fn len(&self) -> usize {
+        self.digests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+}
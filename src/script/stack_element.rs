@@ -1,7 +1,12 @@
-use std::ops::Deref;
-
-use super::op_function::{op_check_sig, op_dup, op_hash160, op_hash256, op_unknown, Stack};
-use crate::wallet::{Hash256, Hex};
+#[cfg(feature = "op_experiments")]
+use super::op_function::op_experimental;
+use super::op_function::{
+    cast_to_bool, op_arithmetic, op_check_multisig, op_check_sig, op_drop, op_dup, op_equal,
+    op_equal_verify, op_hash160, op_hash256, op_push_num, op_sha256, op_size, op_swap, op_unknown,
+    op_verify, SigHasher, Stack,
+};
+use super::{OpCode, ScriptError};
+use crate::wallet::Hex;
 
 #[derive(Debug, Clone)]
 pub enum StackElement {
@@ -9,62 +14,124 @@ pub enum StackElement {
     OpCode(OpCode),
 }
 
-impl Deref for StackElement {
-    type Target = [u8];
-    fn deref(&self) -> &Self::Target {
+impl StackElement {
+    /// Pushed data, None for an opcode
+    pub fn as_bytes(&self) -> Option<&[u8]> {
         match self {
-            StackElement::DataElement(ref data) => &*data,
-            _ => unreachable!(),
+            StackElement::DataElement(data) => Some(data),
+            StackElement::OpCode(_) => None,
         }
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct OpCode {
-    num: u8,
-    kind: OpCodeKind,
-}
+    pub fn as_op(&self) -> Option<OpCode> {
+        match self {
+            StackElement::OpCode(op_code) => Some(*op_code),
+            StackElement::DataElement(_) => None,
+        }
+    }
 
-#[derive(Debug, Clone)]
-pub enum OpCodeKind {
-    OpDup,
-    OpHash256,
-    OpHash160,
-    OpCheckSig,
-    Unknown,
+    /// Script truthiness of pushed data, an opcode is never true
+    pub fn to_bool(&self) -> bool {
+        self.as_bytes().map_or(false, cast_to_bool)
+    }
 }
 
 impl OpCode {
-    pub fn new(code: u8) -> Self {
-        let kind = match code {
-            0x76_u8 => OpCodeKind::OpDup,
-            0xaa_u8 => OpCodeKind::OpHash256,
-            0xa9_u8 => OpCodeKind::OpHash160,
-            0xac_u8 => OpCodeKind::OpCheckSig,
-            _ => OpCodeKind::Unknown,
-        };
-        OpCode { num: code, kind }
-    }
-
     pub fn operation(&self) -> OperationType {
-        match self.kind {
-            OpCodeKind::OpDup => OperationType::Stack(Box::new(op_dup)),
-            OpCodeKind::OpHash256 => OperationType::Stack(Box::new(op_hash256)),
-            OpCodeKind::OpHash160 => OperationType::Stack(Box::new(op_hash160)),
-            OpCodeKind::OpCheckSig => OperationType::StackSig(Box::new(op_check_sig)),
-            OpCodeKind::Unknown => OperationType::Stack(Box::new(op_unknown)),
+        let code = self.num();
+        if let Some(num) = self.small_int() {
+            return OperationType::Stack(Box::new(move |stack| op_push_num(stack, num)));
+        }
+        match self {
+            OpCode::Op1Add
+            | OpCode::Op1Sub
+            | OpCode::OpNegate
+            | OpCode::OpAbs
+            | OpCode::OpNot
+            | OpCode::Op0NotEqual
+            | OpCode::OpAdd
+            | OpCode::OpSub
+            | OpCode::OpBoolAnd
+            | OpCode::OpBoolOr
+            | OpCode::OpNumEqual
+            | OpCode::OpNumEqualVerify
+            | OpCode::OpNumNotEqual
+            | OpCode::OpLessThan
+            | OpCode::OpGreaterThan
+            | OpCode::OpLessThanOrEqual
+            | OpCode::OpGreaterThanOrEqual
+            | OpCode::OpMin
+            | OpCode::OpMax
+            | OpCode::OpWithin => {
+                OperationType::StackNum(Box::new(move |stack, require_minimal| {
+                    op_arithmetic(code, stack, require_minimal)
+                }))
+            }
+            #[cfg(feature = "op_experiments")]
+            OpCode::Op2Mul
+            | OpCode::Op2Div
+            | OpCode::OpMul
+            | OpCode::OpDiv
+            | OpCode::OpMod
+            | OpCode::OpLShift
+            | OpCode::OpRShift => {
+                OperationType::StackNum(Box::new(move |stack, require_minimal| {
+                    op_arithmetic(code, stack, require_minimal)
+                }))
+            }
+            #[cfg(feature = "op_experiments")]
+            OpCode::OpCat
+            | OpCode::OpSubstr
+            | OpCode::OpLeft
+            | OpCode::OpRight
+            | OpCode::OpInvert
+            | OpCode::OpAnd
+            | OpCode::OpOr
+            | OpCode::OpXor => OperationType::StackNum(Box::new(move |stack, require_minimal| {
+                op_experimental(code, stack, require_minimal)
+            })),
+            OpCode::OpNop
+            | OpCode::OpNop1
+            | OpCode::OpNop4
+            | OpCode::OpNop5
+            | OpCode::OpNop6
+            | OpCode::OpNop7
+            | OpCode::OpNop8
+            | OpCode::OpNop9
+            | OpCode::OpNop10 => OperationType::Stack(Box::new(|_| true)),
+            OpCode::OpDrop => OperationType::Stack(Box::new(op_drop)),
+            OpCode::OpSwap => OperationType::Stack(Box::new(op_swap)),
+            OpCode::OpSize => OperationType::Stack(Box::new(op_size)),
+            OpCode::OpSha256 => OperationType::Stack(Box::new(op_sha256)),
+            OpCode::OpDup => OperationType::Stack(Box::new(op_dup)),
+            OpCode::OpHash256 => OperationType::Stack(Box::new(op_hash256)),
+            OpCode::OpHash160 => OperationType::Stack(Box::new(op_hash160)),
+            OpCode::OpVerify => OperationType::Stack(Box::new(op_verify)),
+            OpCode::OpEqual => OperationType::Stack(Box::new(op_equal)),
+            OpCode::OpEqualVerify => OperationType::Stack(Box::new(op_equal_verify)),
+            OpCode::OpCheckSig
+            | OpCode::OpCheckSigVerify
+            | OpCode::OpCheckMultisig
+            | OpCode::OpCheckMultisigVerify => {
+                OperationType::StackSig(Box::new(move |stack, sig_hash, null_dummy| match code {
+                    0xac | 0xad => op_check_sig(code, stack, sig_hash),
+                    _ => op_check_multisig(code, stack, sig_hash, null_dummy),
+                }))
+            }
+            OpCode::OpCodeseparator => OperationType::Stack(Box::new(|_| true)),
+            _ => OperationType::Stack(Box::new(op_unknown)),
         }
-    }
-
-    pub fn num(&self) -> u8 {
-        self.num
     }
 }
 
 pub enum OperationType {
     Stack(Box<dyn Fn(&mut Stack) -> bool>),
-    StackSig(Box<dyn Fn(&mut Stack, Hash256) -> bool>),
+    /// Signature check, Ok(false) fails the opcode. The flag asks for an empty
+    /// OP_CHECKMULTISIG dummy element.
+    StackSig(Box<dyn Fn(&mut Stack, &mut SigHasher, bool) -> Result<bool, ScriptError>>),
     StackStack(Box<dyn Fn(&mut Stack, &mut Stack) -> bool>),
+    /// Numeric operation, the flag asks for minimally encoded operands
+    StackNum(Box<dyn Fn(&mut Stack, bool) -> bool>),
 }
 
 impl Hex for StackElement {
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+
+use super::{
+    LockTimeContext, Script, ScriptError, ScriptNum, Stack, StackElement, DEFAULT_MAX_NUM_SIZE,
+};
+use crate::transaction::{Transaction, TxOutput};
+use crate::wallet::taproot::{ControlBlock, TapLeaf, TAPROOT_LEAF_TAPSCRIPT};
+use crate::wallet::{SchnorrSignature, XOnlyPublicKey};
+
+/// Every non empty signature checked by a tapscript spends this much of the sigops budget
+pub const VALIDATION_WEIGHT_PER_SIGOP: i64 = 50;
+/// Starting budget on top of the witness size
+pub const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+
+/// BIP342 OP_SUCCESSx, any of them makes a tapscript succeed before it runs
+pub fn is_op_success(opcode: u8) -> bool {
+    match opcode {
+        80 | 98 | 126..=129 | 131..=134 | 137..=138 | 141..=142 | 149..=153 | 187..=254 => true,
+        _ => false,
+    }
+}
+
+/// What a tapscript needs from the spending transaction to check schnorr signatures
+pub struct TapscriptContext<'a> {
+    tx: &'a Transaction,
+    input_index: usize,
+    prevouts: &'a [TxOutput],
+    leaf_hash: [u8; 32],
+    annex: Option<&'a [u8]>,
+    sigops_budget: i64,
+    digests: HashMap<(u8, u32), [u8; 32]>,
+}
+
+impl<'a> TapscriptContext<'a> {
+    /// `witness_size` is the serialized size of the whole input witness, annex and control
+    /// block included
+    pub fn new(
+        tx: &'a Transaction,
+        input_index: usize,
+        prevouts: &'a [TxOutput],
+        leaf_hash: [u8; 32],
+        annex: Option<&'a [u8]>,
+        witness_size: usize,
+    ) -> Self {
+        TapscriptContext {
+            tx,
+            input_index,
+            prevouts,
+            leaf_hash,
+            annex,
+            sigops_budget: VALIDATION_WEIGHT_OFFSET + witness_size as i64,
+            digests: HashMap::new(),
+        }
+    }
+
+    pub fn sigops_budget(&self) -> i64 {
+        self.sigops_budget
+    }
+
+    /// Locktime and sequence of the spending input
+    pub fn lock_time_context(&self) -> LockTimeContext {
+        LockTimeContext::new(self.tx, self.input_index)
+    }
+
+    /// Check `sig` for `pubkey` the BIP342 way. An empty signature is false, an invalid
+    /// non empty one fails the script. Keys that are not 32 bytes are an upgrade path and
+    /// accept any non empty signature.
+    pub fn check_schnorr(
+        &mut self,
+        sig: &[u8],
+        pubkey: &[u8],
+        codesep_pos: u32,
+    ) -> Result<bool, ScriptError> {
+        if pubkey.is_empty() {
+            return Err(ScriptError::TapscriptEmptyPubkey);
+        }
+        if sig.is_empty() {
+            return Ok(false);
+        }
+        self.sigops_budget -= VALIDATION_WEIGHT_PER_SIGOP;
+        if self.sigops_budget < 0 {
+            return Err(ScriptError::SigopsBudgetExceeded);
+        }
+        if pubkey.len() != 32 {
+            return Ok(true);
+        }
+
+        let sighash_type = match sig.len() {
+            64 => 0x00,
+            65 if sig[64] != 0x00 => sig[64],
+            _ => return Err(ScriptError::SchnorrSigInvalid),
+        };
+        let (tx, input_index, prevouts, annex, leaf_hash) = (
+            self.tx,
+            self.input_index,
+            self.prevouts,
+            self.annex,
+            self.leaf_hash,
+        );
+        let msg = match self.digests.get(&(sighash_type, codesep_pos)) {
+            Some(msg) => *msg,
+            None => {
+                let msg = tx
+                    .sig_hash_taproot(
+                        input_index,
+                        prevouts,
+                        sighash_type,
+                        annex,
+                        Some((&leaf_hash, codesep_pos)),
+                    )
+                    .map_err(|_| ScriptError::SchnorrSigInvalid)?;
+                self.digests.insert((sighash_type, codesep_pos), msg);
+                msg
+            }
+        };
+
+        match (
+            SchnorrSignature::parse(&sig[0..64]),
+            XOnlyPublicKey::parse(pubkey),
+        ) {
+            (Some(signature), Some(key)) if signature.verify(&key, &msg) => Ok(true),
+            _ => Err(ScriptError::SchnorrSigInvalid),
+        }
+    }
+}
+
+/// Verify a witness v1 spend of `tx.inputs[input_index]`, by key path or by script path.
+/// `prevouts` are the outputs spent by every input of `tx`, in input order.
+pub fn verify_taproot_input(
+    tx: &Transaction,
+    input_index: usize,
+    prevouts: &[TxOutput],
+) -> Result<bool, ScriptError> {
+    let program = match prevouts.get(input_index) {
+        Some(prevout) => &prevout.script_pub_key.content,
+        None => return Err(ScriptError::NotTaproot),
+    };
+    if program.len() != 34 || program[0] != 0x51 || program[1] != 0x20 {
+        return Err(ScriptError::NotTaproot);
+    }
+    // None for a program that is no point, no signature nor script path can match it
+    let output_key = XOnlyPublicKey::parse(&program[2..]);
+
+    let input = tx.inputs.get(input_index).ok_or(ScriptError::NotTaproot)?;
+    let witness_size = input.serialize_witness().len();
+    let mut witness: Vec<&[u8]> = input.witness.iter().map(Vec::as_slice).collect();
+    if witness.is_empty() {
+        return Err(ScriptError::EmptyWitness);
+    }
+    let annex = match witness.last() {
+        Some(last) if witness.len() >= 2 && last.first() == Some(&0x50) => witness.pop(),
+        _ => None,
+    };
+
+    if witness.len() == 1 {
+        let sig = witness[0];
+        let sighash_type = match sig.len() {
+            64 => 0x00,
+            65 if sig[64] != 0x00 => sig[64],
+            _ => return Ok(false),
+        };
+        let msg = match tx.sig_hash_taproot(input_index, prevouts, sighash_type, annex, None) {
+            Ok(msg) => msg,
+            Err(_) => return Ok(false),
+        };
+        return Ok(match (SchnorrSignature::parse(&sig[0..64]), output_key) {
+            (Some(signature), Some(output_key)) => signature.verify(&output_key, &msg),
+            _ => false,
+        });
+    }
+
+    let control_block = witness.pop().expect("witness has two elements");
+    let leaf_script = witness.pop().expect("witness has two elements");
+    let control_block =
+        ControlBlock::parse(control_block).map_err(|_| ScriptError::InvalidControlBlock)?;
+    if !output_key.map_or(false, |output_key| {
+        control_block.verify(&output_key, leaf_script)
+    }) {
+        return Err(ScriptError::WitnessProgramMismatch);
+    }
+    if control_block.leaf_version != TAPROOT_LEAF_TAPSCRIPT {
+        // unknown leaf versions are left for future soft forks
+        return Ok(true);
+    }
+
+    let (script, bad_regions) = Script::parse_lossy(leaf_script);
+    if script.cmds.iter().any(|cmd| match cmd {
+        StackElement::OpCode(op_code) => is_op_success(op_code.num()),
+        _ => false,
+    }) {
+        return Ok(true);
+    }
+    if let Some(bad_region) = bad_regions.into_iter().next() {
+        return Err(bad_region.error);
+    }
+
+    let leaf_hash = TapLeaf::new(leaf_script.to_vec()).leaf_hash();
+    let mut context =
+        TapscriptContext::new(tx, input_index, prevouts, leaf_hash, annex, witness_size);
+    let stack: Stack = witness
+        .iter()
+        .map(|item| StackElement::DataElement(item.to_vec()))
+        .collect();
+    script.evaluate_tapscript(stack, &mut context)
+}
+
+/// OP_CHECKSIG, OP_CHECKSIGVERIFY and OP_CHECKSIGADD against the schnorr rules, the
+/// multisig opcodes are disabled in tapscript
+pub(super) fn op_check_sig_tapscript(
+    code: u8,
+    stack: &mut Stack,
+    context: &mut TapscriptContext,
+    codesep_pos: u32,
+) -> Result<(), ScriptError> {
+    match code {
+        0xac | 0xad => {
+            if stack.len() < 2 {
+                return Err(ScriptError::OpCodeEvaluateError(code));
+            }
+            let pubkey = stack.pop().expect("stack can not pop");
+            let sig = stack.pop().expect("stack can not pop");
+            let success = match (sig.as_bytes(), pubkey.as_bytes()) {
+                (Some(sig), Some(pubkey)) => context.check_schnorr(sig, pubkey, codesep_pos)?,
+                _ => return Err(ScriptError::OpCodeEvaluateError(code)),
+            };
+            if code == 0xad {
+                if !success {
+                    return Err(ScriptError::OpCodeEvaluateError(code));
+                }
+            } else {
+                stack.push(StackElement::DataElement(
+                    ScriptNum::from(success as i64).encode(),
+                ));
+            }
+            Ok(())
+        }
+        0xba => {
+            if stack.len() < 3 {
+                return Err(ScriptError::OpCodeEvaluateError(code));
+            }
+            let pubkey = stack.pop().expect("stack can not pop");
+            let num = stack.pop().expect("stack can not pop");
+            let sig = stack.pop().expect("stack can not pop");
+            let (sig, num, pubkey) = match (sig.as_bytes(), num.as_bytes(), pubkey.as_bytes()) {
+                (Some(sig), Some(num), Some(pubkey)) => (sig, num, pubkey),
+                _ => return Err(ScriptError::OpCodeEvaluateError(code)),
+            };
+            let num = ScriptNum::decode(num, true, DEFAULT_MAX_NUM_SIZE)
+                .map_err(|_| ScriptError::OpCodeEvaluateError(code))?;
+            let success = context.check_schnorr(sig, pubkey, codesep_pos)?;
+            stack.push(StackElement::DataElement(
+                ScriptNum::from(num.value() + success as i64).encode(),
+            ));
+            Ok(())
+        }
+        _ => Err(ScriptError::DisabledInTapscript(code)),
+    }
+}
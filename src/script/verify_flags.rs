@@ -0,0 +1,100 @@
+bitflags! {
+    /// Script verification rules on top of the base consensus checks
+    pub struct VerifyFlags: u32 {
+        /// Every push uses the smallest encoding, e.g. `OP_1` instead of `0x01 0x01`
+        const MINIMALDATA = 1 << 0;
+        /// The OP_IF/OP_NOTIF argument is exactly empty or `0x01`
+        const MINIMALIF = 1 << 1;
+        /// Non empty signatures are strict DER encoded as BIP66 requires
+        const DERSIG = 1 << 2;
+        /// The extra element OP_CHECKMULTISIG pops is empty, BIP147
+        const NULLDUMMY = 1 << 3;
+        /// Spends of witness versions reserved for soft forks fail instead of passing, the
+        /// relay policy rather than the consensus rule
+        const DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM = 1 << 4;
+        /// OP_CHECKLOCKTIMEVERIFY checks the locktime instead of doing nothing, BIP65
+        const CHECKLOCKTIMEVERIFY = 1 << 5;
+        /// OP_CHECKSEQUENCEVERIFY checks the input sequence instead of doing nothing, BIP112
+        const CHECKSEQUENCEVERIFY = 1 << 6;
+        /// Exactly one element is left on the stack, consensus for segwit v0 witness scripts
+        const CLEANSTACK = 1 << 7;
+    }
+}
+
+impl Default for VerifyFlags {
+    fn default() -> Self {
+        VerifyFlags::empty()
+    }
+}
+
+/// Heights the BIP66, BIP65, BIP112 and BIP147 soft forks activated at, on mainnet and on
+/// testnet3
+const MAINNET_ACTIVATIONS: [(u32, VerifyFlags); 4] = [
+    (363_725, VerifyFlags::DERSIG),
+    (388_381, VerifyFlags::CHECKLOCKTIMEVERIFY),
+    (419_328, VerifyFlags::CHECKSEQUENCEVERIFY),
+    (481_824, VerifyFlags::NULLDUMMY),
+];
+const TESTNET_ACTIVATIONS: [(u32, VerifyFlags); 4] = [
+    (330_776, VerifyFlags::DERSIG),
+    (581_885, VerifyFlags::CHECKLOCKTIMEVERIFY),
+    (770_112, VerifyFlags::CHECKSEQUENCEVERIFY),
+    (834_624, VerifyFlags::NULLDUMMY),
+];
+
+impl VerifyFlags {
+    /// The soft fork rules in force at the chain tip, strict DER signatures, both timelocks
+    /// and the empty multisig dummy
+    pub fn consensus() -> Self {
+        VerifyFlags::DERSIG
+            | VerifyFlags::CHECKLOCKTIMEVERIFY
+            | VerifyFlags::CHECKSEQUENCEVERIFY
+            | VerifyFlags::NULLDUMMY
+    }
+
+    /// The soft fork rules a block at `height` is checked with, to validate historical
+    /// blocks whose spends broke rules that came later
+    pub fn consensus_at_height(height: u32, testnet: bool) -> Self {
+        let activations = if testnet {
+            &TESTNET_ACTIVATIONS
+        } else {
+            &MAINNET_ACTIVATIONS
+        };
+        activations
+            .iter()
+            .filter(|(activation, _)| height >= *activation)
+            .fold(VerifyFlags::empty(), |flags, (_, flag)| flags | *flag)
+    }
+}
+
+mod test {
+    use super::VerifyFlags;
+
+    #[test]
+    fn test_consensus_at_height() {
+        assert_eq!(
+            VerifyFlags::consensus_at_height(0, false),
+            VerifyFlags::empty()
+        );
+        assert_eq!(
+            VerifyFlags::consensus_at_height(363_725, false),
+            VerifyFlags::DERSIG
+        );
+        assert_eq!(
+            VerifyFlags::consensus_at_height(419_327, false),
+            VerifyFlags::DERSIG | VerifyFlags::CHECKLOCKTIMEVERIFY
+        );
+        assert_eq!(
+            VerifyFlags::consensus_at_height(481_824, false),
+            VerifyFlags::consensus()
+        );
+        assert_eq!(
+            VerifyFlags::consensus_at_height(481_824, true),
+            VerifyFlags::DERSIG
+        );
+        assert_eq!(
+            VerifyFlags::consensus_at_height(834_624, true),
+            VerifyFlags::consensus()
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum StorageError {
+    #[fail(display = "storage backend error: {}", _0)]
+    Backend(String),
+    #[fail(display = "malformed stored entry")]
+    Corrupt,
+}
+
+/// Ordered byte key value storage the header chain, wallet store, transaction cache and
+/// indexer persist into. Each of them keeps to its own key prefix so one storage can hold
+/// all of them:
+///
+/// - `t`, `o`, `u`, `h` the indexer
+/// - `C` the header chain
+/// - `W` the wallet store
+/// - `X` the transaction cache
+pub trait Storage {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError>;
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError>;
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StorageError>;
+
+    /// Every entry whose key starts with `prefix`, in key order
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError>;
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStorage {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        self.entries.insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StorageError> {
+        self.entries.remove(key);
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+        Ok(self
+            .entries
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+}
+
+/// Storage on disk in a sled tree
+#[cfg(feature = "sled-store")]
+pub struct SledStorage {
+    db: sled::Db,
+    tree: sled::Tree,
+}
+
+#[cfg(feature = "sled-store")]
+impl SledStorage {
+    /// The default tree of the database at `path`
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, StorageError> {
+        let db = sled::open(path).map_err(backend_error)?;
+        let tree = db.open_tree("default").map_err(backend_error)?;
+        Ok(SledStorage { db, tree })
+    }
+
+    /// Another tree of the same database, to keep components apart on disk
+    pub fn tree(&self, name: &str) -> Result<Self, StorageError> {
+        let tree = self.db.open_tree(name).map_err(backend_error)?;
+        Ok(SledStorage {
+            db: self.db.clone(),
+            tree,
+        })
+    }
+
+    pub fn flush(&self) -> Result<(), StorageError> {
+        self.tree.flush().map_err(backend_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sled-store")]
+fn backend_error(e: sled::Error) -> StorageError {
+    StorageError::Backend(e.to_string())
+}
+
+#[cfg(feature = "sled-store")]
+impl Storage for SledStorage {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self
+            .tree
+            .get(key)
+            .map_err(backend_error)?
+            .map(|v| v.to_vec()))
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        self.tree.insert(key, value).map_err(backend_error)?;
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StorageError> {
+        self.tree.remove(key).map_err(backend_error)?;
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+        self.tree
+            .scan_prefix(prefix)
+            .map(|entry| {
+                entry
+                    .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                    .map_err(backend_error)
+            })
+            .collect()
+    }
+}
+
+mod test {
+    use super::{MemoryStorage, Storage};
+
+    #[test]
+    fn test_memory_storage() {
+        let mut storage = MemoryStorage::new();
+        storage.put(b"ab", b"1").unwrap();
+        storage.put(b"ac", b"2").unwrap();
+        storage.put(b"b", b"3").unwrap();
+        assert_eq!(storage.get(b"ab").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(
+            storage.scan_prefix(b"a").unwrap(),
+            vec![
+                (b"ab".to_vec(), b"1".to_vec()),
+                (b"ac".to_vec(), b"2".to_vec())
+            ]
+        );
+        storage.delete(b"ab").unwrap();
+        assert_eq!(storage.get(b"ab").unwrap(), None);
+        assert_eq!(storage.len(), 2);
+    }
+}
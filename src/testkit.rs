@@ -0,0 +1,608 @@
+//! Deterministic chains, blocks and transactions for unit tests, and golden file snapshots
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::headers::{ChainEvent, HeaderChain, HeaderError};
+use crate::mining::{
+    bits_to_target, block_subsidy, coinbase, merkle_root, mine, witness_commitment,
+};
+use crate::transaction::{
+    OutPoint, PreTxIndex, ScriptPubKey, ScriptPubKeyType, ScriptSig, Transaction, TxHash, TxInput,
+    TxInputSequence, TxLocktime, TxOutput, TxVersion,
+};
+
+/// Regtest difficulty, about every second nonce is a valid block
+pub const REGTEST_BITS: u32 = 0x207f_ffff;
+/// Timestamp of the regtest genesis block
+pub const REGTEST_GENESIS_TIME: u32 = 1_296_688_602;
+
+/// Mine `count` regtest headers on top of `prev`. `salt` goes into the merkle root so
+/// branches built on the same parent differ.
+pub fn mine_headers(prev: &BlockHeader, count: usize, salt: u32) -> Vec<BlockHeader> {
+    let mut headers: Vec<BlockHeader> = Vec::with_capacity(count);
+    let mut prev = *prev;
+    for _ in 0..count {
+        let mut merkle_root = [0u8; 32];
+        merkle_root[..4].copy_from_slice(&salt.to_le_bytes());
+        let header = BlockHeader {
+            version: 0x2000_0000,
+            prev_block: prev.hash(),
+            merkle_root,
+            timestamp: prev.timestamp + 600,
+            bits: REGTEST_BITS,
+            nonce: 0,
+        };
+        prev = mine(header, bits_to_target(header.bits)).expect("regtest target is easy");
+        headers.push(prev);
+    }
+    headers
+}
+
+/// A mined regtest genesis header, the same in every test
+pub fn genesis() -> BlockHeader {
+    mine(
+        BlockHeader {
+            version: 1,
+            prev_block: BlockHash::default(),
+            merkle_root: [0; 32],
+            timestamp: REGTEST_GENESIS_TIME,
+            bits: REGTEST_BITS,
+            nonce: 0,
+        },
+        bits_to_target(REGTEST_BITS),
+    )
+    .expect("regtest target is easy")
+}
+
+/// A header chain indexed by height, genesis at 0. Forks share the headers up to the
+/// fork point with the chain they came from.
+#[derive(Debug, Clone)]
+pub struct TestChain {
+    headers: Vec<BlockHeader>,
+    /// Merkle root salt of this branch's own headers
+    salt: u32,
+    /// Forks taken so far, each gets a salt of its own
+    forks: u32,
+}
+
+impl TestChain {
+    /// Genesis and `length` headers on top
+    pub fn new(length: usize) -> Self {
+        let genesis = genesis();
+        let mut headers = vec![genesis];
+        headers.extend(mine_headers(&genesis, length, 0));
+        TestChain {
+            headers,
+            salt: 0,
+            forks: 0,
+        }
+    }
+
+    pub fn genesis(&self) -> BlockHeader {
+        self.headers[0]
+    }
+
+    pub fn header(&self, height: u32) -> BlockHeader {
+        self.headers[height as usize]
+    }
+
+    /// Headers above `height`, what a peer at `height` is missing
+    pub fn headers_after(&self, height: u32) -> &[BlockHeader] {
+        &self.headers[height as usize + 1..]
+    }
+
+    pub fn tip(&self) -> (u32, BlockHash) {
+        let height = self.headers.len() - 1;
+        (height as u32, self.headers[height].hash())
+    }
+
+    /// Mine `count` more headers, returns them
+    pub fn extend(&mut self, count: usize) -> &[BlockHeader] {
+        let start = self.headers.len();
+        let tip = self.headers[start - 1];
+        self.headers.extend(mine_headers(&tip, count, self.salt));
+        &self.headers[start..]
+    }
+
+    /// A branch leaving this chain after `height` with `length` headers of its own
+    pub fn fork(&mut self, height: u32, length: usize) -> TestChain {
+        self.forks += 1;
+        let salt = self.salt.wrapping_mul(31).wrapping_add(self.forks);
+        let mut headers = self.headers[..=height as usize].to_vec();
+        headers.extend(mine_headers(&self.header(height), length, salt));
+        TestChain {
+            headers,
+            salt,
+            forks: 0,
+        }
+    }
+
+    /// The shortest branch after `height` with more work than this chain
+    pub fn heavier_fork(&mut self, height: u32) -> TestChain {
+        let length = self.tip().0 - height + 1;
+        self.fork(height, length as usize)
+    }
+
+    /// Height of the last header both chains share
+    pub fn fork_height(&self, other: &TestChain) -> u32 {
+        let shared = self
+            .headers
+            .iter()
+            .zip(other.headers.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        shared as u32 - 1
+    }
+
+    /// A `HeaderChain` that has connected every header
+    pub fn header_chain(&self) -> HeaderChain {
+        let mut chain = HeaderChain::new(self.genesis());
+        self.feed(&mut chain).expect("a test chain connects");
+        chain
+    }
+
+    /// Connect the headers `chain` lacks, as a peer serving this chain would send them
+    pub fn feed(&self, chain: &mut HeaderChain) -> Result<Vec<ChainEvent>, HeaderError> {
+        let known = self
+            .headers
+            .iter()
+            .rposition(|header| chain.height(&header.hash()).is_some())
+            .unwrap_or(0);
+        chain.connect(&self.headers[known + 1..])
+    }
+
+    /// The events of switching the active chain from this one to `to`
+    pub fn reorg_events(&self, to: &TestChain) -> Vec<ChainEvent> {
+        let fork = self.fork_height(to);
+        let disconnected = (fork + 1..=self.tip().0)
+            .rev()
+            .map(|height| ChainEvent::Disconnected {
+                height,
+                hash: self.header(height).hash(),
+            });
+        let connected = (fork + 1..=to.tip().0).map(|height| ChainEvent::Connected {
+            height,
+            hash: to.header(height).hash(),
+        });
+        disconnected.chain(connected).collect()
+    }
+}
+
+/// Distinct owners scripts are made for, few enough that scripts repeat across blocks
+const OWNERS: u8 = 8;
+/// Fee every generated spend pays
+const FEE: u64 = 1000;
+
+/// Standard output types the generator builds, each spent with a fitting input
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Kind {
+    PubKey,
+    PubKeyHash,
+    ScriptHash,
+    Multisig,
+    WitnessKeyHash,
+    WitnessScriptHash,
+    Taproot,
+}
+impl Copy for Kind {}
+
+const KINDS: [Kind; 7] = [
+    Kind::PubKey,
+    Kind::PubKeyHash,
+    Kind::ScriptHash,
+    Kind::Multisig,
+    Kind::WitnessKeyHash,
+    Kind::WitnessScriptHash,
+    Kind::Taproot,
+];
+
+/// An output the generator created and may spend in a later transaction
+#[derive(Debug, PartialEq, Clone)]
+pub struct GeneratedUtxo {
+    pub out_point: OutPoint,
+    pub amount: u64,
+    pub script_pub_key: ScriptPubKey,
+}
+
+/// Seeded random transactions and blocks that are well formed, though their signatures
+/// are noise. Blocks spend what earlier blocks created, coinbase maturity aside, so the
+/// unspent outputs are known to compare an index against.
+pub struct TxGenerator {
+    rng: StdRng,
+    utxos: Vec<GeneratedUtxo>,
+    tip: BlockHeader,
+    height: u32,
+}
+
+impl TxGenerator {
+    /// The same seed gives the same transactions and blocks. Blocks start at height 1 on
+    /// top of `genesis()`.
+    pub fn new(seed: u64) -> Self {
+        TxGenerator {
+            rng: StdRng::seed_from_u64(seed),
+            utxos: Vec::new(),
+            tip: genesis(),
+            height: 0,
+        }
+    }
+
+    /// Outputs of the generated blocks not spent yet
+    pub fn utxos(&self) -> &[GeneratedUtxo] {
+        &self.utxos
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.rng.gen()).collect()
+    }
+
+    fn pubkey(&mut self) -> Vec<u8> {
+        let mut key = vec![0x02 + self.rng.gen_range(0, 2)];
+        key.extend(self.bytes(32));
+        key
+    }
+
+    /// A DER signature of the usual length with a sighash byte
+    fn signature(&mut self) -> Vec<u8> {
+        let len = self.rng.gen_range(70, 73);
+        let mut sig = vec![0x30, len as u8 - 3];
+        sig.extend(self.bytes(len - 3));
+        sig.push(0x01);
+        sig
+    }
+
+    fn kind(&mut self) -> Kind {
+        KINDS[self.rng.gen_range(0, KINDS.len())]
+    }
+
+    fn script_pub_key_of(kind: Kind, owner: u8) -> ScriptPubKey {
+        let key = [&[0x02][..], &[owner; 32]].concat();
+        let content = match kind {
+            Kind::PubKey => [&[0x21][..], &key, &[0xac]].concat(),
+            Kind::PubKeyHash => [&[0x76, 0xa9, 0x14][..], &[owner; 20], &[0x88, 0xac]].concat(),
+            Kind::ScriptHash => [&[0xa9, 0x14][..], &[owner; 20], &[0x87]].concat(),
+            Kind::Multisig => [&[0x51, 0x21][..], &key, &[0x21], &key, &[0x52, 0xae]].concat(),
+            Kind::WitnessKeyHash => [&[0x00, 0x14][..], &[owner; 20]].concat(),
+            Kind::WitnessScriptHash => [&[0x00, 0x20][..], &[owner; 32]].concat(),
+            Kind::Taproot => [&[0x51, 0x20][..], &[owner; 32]].concat(),
+        };
+        ScriptPubKey { content }
+    }
+
+    /// A standard output script of one of a few owners
+    pub fn script_pub_key(&mut self) -> ScriptPubKey {
+        let kind = self.kind();
+        let owner = self.rng.gen_range(0, OWNERS);
+        TxGenerator::script_pub_key_of(kind, owner)
+    }
+
+    /// Script sig and witness spending an output of `kind`
+    fn unlock(&mut self, kind: Kind) -> (Vec<u8>, Vec<Vec<u8>>) {
+        match kind {
+            Kind::PubKey => (push(&self.signature()), vec![]),
+            Kind::PubKeyHash => {
+                let sig = push(&self.signature());
+                ([sig, push(&self.pubkey())].concat(), vec![])
+            }
+            Kind::ScriptHash => {
+                // redeem scripts long enough for OP_PUSHDATA1 and 2 and a 3 byte varint
+                let len = self.rng.gen_range(1, 521);
+                let redeem_script = self.bytes(len);
+                let sig = push(&self.signature());
+                ([sig, push(&redeem_script)].concat(), vec![])
+            }
+            Kind::Multisig => ([vec![0x00], push(&self.signature())].concat(), vec![]),
+            Kind::WitnessKeyHash => (vec![], vec![self.signature(), self.pubkey()]),
+            Kind::WitnessScriptHash => {
+                let len = self.rng.gen_range(1, 300);
+                (vec![], vec![self.signature(), self.bytes(len)])
+            }
+            Kind::Taproot => (vec![], vec![self.bytes(64)]),
+        }
+    }
+
+    fn input(&mut self, out_point: &OutPoint, kind: Kind) -> TxInput {
+        let (script_sig, witness) = self.unlock(kind);
+        let sequences = [0xffff_ffff, 0xffff_fffd, 0];
+        let mut input = TxInput::new(
+            out_point.txid,
+            PreTxIndex::new(out_point.vout),
+            ScriptSig {
+                content: script_sig,
+            },
+            TxInputSequence::new(sequences[self.rng.gen_range(0, 3)]),
+        );
+        input.witness = witness;
+        input
+    }
+
+    /// `value` split over `count` outputs, each getting at least one satoshi
+    fn outputs(&mut self, value: u64, count: usize) -> Vec<TxOutput> {
+        let mut left = value;
+        (0..count)
+            .map(|i| {
+                let amount = if i + 1 == count {
+                    left
+                } else {
+                    self.rng
+                        .gen_range(1, left - (count - i - 1) as u64 + 1)
+                        .min(left / 2)
+                        .max(1)
+                };
+                left -= amount;
+                TxOutput {
+                    amount: amount.into(),
+                    script_pub_key: self.script_pub_key(),
+                }
+            })
+            .collect()
+    }
+
+    fn transaction(&mut self, inputs: Vec<TxInput>, mut outputs: Vec<TxOutput>) -> Transaction {
+        if self.rng.gen_bool(0.2) {
+            let len = self.rng.gen_range(0, 81);
+            let data = self.bytes(len);
+            outputs.push(TxOutput {
+                amount: 0.into(),
+                script_pub_key: ScriptPubKey {
+                    content: [vec![0x6a], push(&data)].concat(),
+                },
+            });
+        }
+        Transaction::new(
+            TxVersion::new(self.rng.gen_range(1, 3)),
+            inputs,
+            outputs,
+            TxLocktime::new(if self.rng.gen_bool(0.5) {
+                0
+            } else {
+                self.rng.gen()
+            }),
+            false,
+        )
+    }
+
+    /// A transaction spending made up outputs, legacy or segwit. Now and then it has
+    /// enough outputs for a 3 byte count.
+    pub fn tx(&mut self) -> Transaction {
+        let inputs = (0..self.rng.gen_range(1, 5))
+            .map(|_| {
+                let txid = TxHash::new(&self.bytes(32)).expect("32 bytes").1;
+                let out_point = OutPoint::new(txid, self.rng.gen_range(0, 4));
+                let kind = self.kind();
+                self.input(&out_point, kind)
+            })
+            .collect();
+        let count = if self.rng.gen_bool(0.05) {
+            self.rng.gen_range(253, 260)
+        } else {
+            self.rng.gen_range(1, 4)
+        };
+        let value = self.rng.gen_range(count as u64, 21_000_000 * 100_000_000);
+        let outputs = self.outputs(value, count);
+        self.transaction(inputs, outputs)
+    }
+
+    /// A transaction spending up to three of the generated outputs, None once none are
+    /// left that cover the fee
+    fn spend(&mut self) -> Option<Transaction> {
+        let mut spent = Vec::new();
+        for _ in 0..self.rng.gen_range(1, 4) {
+            if self.utxos.is_empty() {
+                break;
+            }
+            let index = self.rng.gen_range(0, self.utxos.len());
+            spent.push(self.utxos.swap_remove(index));
+        }
+        let value: u64 = spent.iter().map(|utxo| utxo.amount).sum();
+        if value < FEE + 3 {
+            self.utxos.extend(spent);
+            return None;
+        }
+        let inputs = spent
+            .iter()
+            .map(|utxo| {
+                let kind = kind_of(&utxo.script_pub_key);
+                self.input(&utxo.out_point, kind)
+            })
+            .collect();
+        let count = self.rng.gen_range(1, 4);
+        let outputs = self.outputs(value - FEE, count);
+        Some(self.transaction(inputs, outputs))
+    }
+
+    fn add_utxos(&mut self, tx: &Transaction) {
+        let txid = tx.id();
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            if output.script_pub_key.content.first() == Some(&0x6a) {
+                continue;
+            }
+            self.utxos.push(GeneratedUtxo {
+                out_point: OutPoint::new(txid, vout as u32),
+                amount: output.amount.into(),
+                script_pub_key: output.script_pub_key.clone(),
+            });
+        }
+    }
+
+    /// The next block, mined on the previous one with a correct merkle root and, when
+    /// it has segwit spends, a witness commitment. Later transactions may spend outputs
+    /// of earlier ones in the same block.
+    pub fn block(&mut self) -> Block {
+        let mut txs = Vec::new();
+        for _ in 0..self.rng.gen_range(0, 8) {
+            match self.spend() {
+                Some(tx) => {
+                    self.add_utxos(&tx);
+                    txs.push(tx);
+                }
+                None => break,
+            }
+        }
+        self.height += 1;
+        let fees = FEE * txs.len() as u64;
+        let commitment = if txs.iter().any(Transaction::has_witness) {
+            Some(witness_commitment(&txs, &[0; 32]))
+        } else {
+            None
+        };
+        let extra_nonce = self.bytes(4);
+        let payout = self.script_pub_key();
+        let coinbase = coinbase(
+            self.height,
+            &extra_nonce,
+            payout,
+            block_subsidy(self.height) + fees,
+            commitment,
+        );
+        self.add_utxos(&coinbase);
+        txs.insert(0, coinbase);
+
+        let hashes: Vec<[u8; 32]> = txs
+            .iter()
+            .map(|tx| {
+                let mut hash = [0u8; 32];
+                hash.copy_from_slice(&tx.hash());
+                hash
+            })
+            .collect();
+        let header = BlockHeader {
+            version: 0x2000_0000,
+            prev_block: self.tip.hash(),
+            merkle_root: merkle_root(&hashes),
+            timestamp: self.tip.timestamp + 600,
+            bits: REGTEST_BITS,
+            nonce: 0,
+        };
+        self.tip = mine(header, bits_to_target(header.bits)).expect("regtest target is easy");
+        Block {
+            header: self.tip,
+            txs,
+        }
+    }
+
+    pub fn blocks(&mut self, count: usize) -> Vec<Block> {
+        (0..count).map(|_| self.block()).collect()
+    }
+}
+
+fn kind_of(script_pub_key: &ScriptPubKey) -> Kind {
+    match script_pub_key.script_type() {
+        ScriptPubKeyType::PubKey => Kind::PubKey,
+        ScriptPubKeyType::PubKeyHash => Kind::PubKeyHash,
+        ScriptPubKeyType::ScriptHash => Kind::ScriptHash,
+        ScriptPubKeyType::Multisig => Kind::Multisig,
+        ScriptPubKeyType::WitnessV0KeyHash => Kind::WitnessKeyHash,
+        ScriptPubKeyType::WitnessV0ScriptHash => Kind::WitnessScriptHash,
+        ScriptPubKeyType::WitnessV1Taproot => Kind::Taproot,
+        other => panic!("the generator makes no {} outputs", other),
+    }
+}
+
+/// Minimal push of `data` in a script
+fn push(data: &[u8]) -> Vec<u8> {
+    let mut script = match data.len() {
+        len if len < 0x4c => vec![len as u8],
+        len if len <= 0xff => vec![0x4c, len as u8],
+        len => [&[0x4d][..], &(len as u16).to_le_bytes()].concat(),
+    };
+    script.extend_from_slice(data);
+    script
+}
+
+/// Golden values of one test, kept in `testdata/snapshots/<name>.snap` as one
+/// `label = value` line each. Run the tests with `UPDATE_SNAPSHOTS=1` to write the file
+/// instead of comparing against it, then review the diff.
+pub struct Snapshot {
+    name: String,
+    entries: Vec<(String, String)>,
+}
+
+impl Snapshot {
+    pub fn new(name: &str) -> Self {
+        Snapshot {
+            name: name.to_string(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn hex(&mut self, label: &str, bytes: &[u8]) -> &mut Self {
+        self.text(label, &hex::encode(bytes))
+    }
+
+    pub fn text(&mut self, label: &str, value: &str) -> &mut Self {
+        assert!(
+            !self.entries.iter().any(|(known, _)| known == label),
+            "snapshot label {} is taken",
+            label
+        );
+        self.entries.push((label.to_string(), value.to_string()));
+        self
+    }
+
+    fn path(&self) -> PathBuf {
+        [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            "snapshots",
+            &format!("{}.snap", self.name),
+        ]
+        .iter()
+        .collect()
+    }
+
+    /// Panic listing every value that drifted from the golden file
+    pub fn check(&self) {
+        let path = self.path();
+        let rendered: String = self
+            .entries
+            .iter()
+            .map(|(label, value)| format!("{} = {}\n", label, value))
+            .collect();
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            fs::create_dir_all(path.parent().expect("snapshots have a directory")).unwrap();
+            fs::write(&path, rendered).unwrap();
+            return;
+        }
+        let golden = fs::read_to_string(&path).unwrap_or_else(|_| {
+            panic!(
+                "no snapshot at {}, run with UPDATE_SNAPSHOTS=1 to write it",
+                path.display()
+            )
+        });
+        let golden: Vec<(&str, &str)> = golden
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, " = ");
+                Some((parts.next()?, parts.next()?))
+            })
+            .collect();
+
+        let mut drift = Vec::new();
+        for (label, value) in &self.entries {
+            match golden.iter().find(|(known, _)| known == label) {
+                Some((_, expected)) if expected == value => {}
+                Some((_, expected)) => drift.push(format!(
+                    "{}:\n  expected {}\n  got      {}",
+                    label, expected, value
+                )),
+                None => drift.push(format!("{}: not in the snapshot", label)),
+            }
+        }
+        for (label, _) in &golden {
+            if !self.entries.iter().any(|(known, _)| known == label) {
+                drift.push(format!("{}: no longer produced", label));
+            }
+        }
+        if !drift.is_empty() {
+            panic!(
+                "snapshot {} drifted, run with UPDATE_SNAPSHOTS=1 if this is intended\n{}",
+                self.name,
+                drift.join("\n")
+            );
+        }
+    }
+}
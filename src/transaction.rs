@@ -1,30 +1,75 @@
+mod borrowed;
+mod builder;
+#[cfg(feature = "elements")]
+pub mod elements;
 mod locktime;
+mod prevouts;
+mod sigops;
+mod summary;
 mod tx_fetcher;
 mod tx_input;
 mod tx_output;
 mod tx_version;
 mod varint;
+mod verify;
 
-use crate::wallet::{hash256, Hash256, Hex};
+use crate::encode::{decode_hex, Decodable, Encodable, HexError};
+use crate::wallet::{hash256, tagged_hash, Hash256, Parse, Serialize};
 
 use bytes::{BufMut, BytesMut};
 use nom::IResult;
+use serde_json::json;
+use sha2::{Digest, Sha256};
 
-use crate::transaction::tx_input::TxHash;
-use locktime::TxLocktime;
+pub use borrowed::{ScriptRef, TransactionRef, TxInputRef, TxOutputRef};
+pub use builder::{
+    BuilderOptions, ChangePolicy, InputWeight, TransactionBuilder, DUST_LIMIT, P2PKH_INPUT,
+    P2PK_INPUT, P2SH_P2WPKH_INPUT, P2TR_KEY_PATH_INPUT, P2WPKH_INPUT,
+};
+pub use locktime::{LocktimeKind, TxLocktime, LOCKTIME_THRESHOLD};
 use nom::multi::count;
-use tx_input::TxInput;
-use tx_output::TxOutput;
-use tx_version::TxVersion;
+pub use prevouts::{PrevoutError, PrevoutResolver, ResolvedTx};
+pub use sigops::{MAX_BLOCK_SIGOPS_COST, WITNESS_SCALE_FACTOR};
+pub use summary::{InputSummary, OutputSummary, TxSummary};
+pub use tx_fetcher::{
+    AddressUtxo, BlockBackend, CacheStats, ChainBackend, FeeEstimates, ScriptBackend, TxBackend,
+    TxFetcher,
+};
+pub use tx_input::{
+    OutPoint, PreTxIndex, ScriptSig, TxHash, TxInput, TxInputBuilder, TxInputSequence,
+};
+pub use tx_output::{ScriptPubKey, ScriptPubKeyType};
+pub use tx_output::{TxOutput, TxOutputAmount};
+pub use tx_version::TxVersion;
 pub use varint::Varint;
+pub use verify::VerifyError;
+
+pub const SIGHASH_ALL: u32 = 0x01;
+pub const SIGHASH_NONE: u32 = 0x02;
+pub const SIGHASH_SINGLE: u32 = 0x03;
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
+/// BIP341 default sighash type, signs like SIGHASH_ALL with a 64 bytes signature
+pub const SIGHASH_DEFAULT: u8 = 0x00;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum SigHashError {
+    #[fail(display = "input index {} out of range", _0)]
+    InputIndexOutOfRange(usize),
+    #[fail(display = "invalid taproot sighash type: {}", _0)]
+    InvalidSigHashType(u8),
+    #[fail(display = "{} prevouts given for {} inputs", _0, _1)]
+    PrevoutsMismatch(usize, usize),
+    #[fail(display = "SIGHASH_SINGLE input {} has no matching output", _0)]
+    NoOutputForSingle(usize),
+}
 
 #[derive(Debug, PartialOrd, PartialEq, Clone, Hash)]
-struct Transaction {
-    version: TxVersion,
-    inputs: Vec<TxInput>,
-    outputs: Vec<TxOutput>,
-    locktime: TxLocktime,
-    testnet: bool,
+pub struct Transaction {
+    pub version: TxVersion,
+    pub inputs: Vec<TxInput>,
+    pub outputs: Vec<TxOutput>,
+    pub locktime: TxLocktime,
+    pub testnet: bool,
 }
 
 impl Transaction {
@@ -44,18 +89,30 @@ impl Transaction {
         }
     }
 
+    /// Legacy or segwit serialization, segwit has a 0x00 marker and 0x01 flag after the version
     pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
         let (input, tx_version) = TxVersion::parse(&input[..])?;
+        let segwit = input.len() >= 2 && input[0] == 0x00 && input[1] == 0x01;
+        let input = if segwit { &input[2..] } else { input };
 
         let (input, inputs_num) = Varint::parse(&input[..])?;
         let input_num = Into::<u64>::into(inputs_num) as usize;
-        let (input, tx_inputs): (&[u8], Vec<TxInput>) = count(TxInput::parse, input_num)(&input)?;
+        let (input, mut tx_inputs): (&[u8], Vec<TxInput>) =
+            count(TxInput::parse, input_num)(&input)?;
 
         let (input, output_num) = Varint::parse(&input[..])?;
         let output_num = Into::<u64>::into(output_num) as usize;
-        let (input, tx_outputs): (&[u8], Vec<TxOutput>) =
+        let (mut input, tx_outputs): (&[u8], Vec<TxOutput>) =
             count(TxOutput::parse, output_num)(&input)?;
 
+        if segwit {
+            for tx_input in tx_inputs.iter_mut() {
+                let (rest, witness) = TxInput::parse_witness(input)?;
+                tx_input.witness = witness;
+                input = rest;
+            }
+        }
+
         let (input, locktime) = TxLocktime::parse(&input[..])?;
         Ok((
             input,
@@ -63,59 +120,444 @@ impl Transaction {
         ))
     }
 
+    /// Transaction id, the hash256 of the legacy serialization displayed in reversed byte order
     pub fn id(&self) -> TxHash {
-        self.hash();
-        unimplemented!()
+        TxHash::parse(&self.hash()).expect("hash256 is 32 bytes").1
+    }
+
+    /// Witness transaction id, equal to the id without witness data
+    pub fn wtxid(&self) -> TxHash {
+        TxHash::parse(&hash256(&self.serialize()))
+            .expect("hash256 is 32 bytes")
+            .1
+    }
+
+    pub fn hash(&self) -> Hash256 {
+        hash256(&self.serialize_legacy())
+    }
+
+    /// A coinbase spends the null outpoint from its only input
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1
+            && self.inputs[0].pre_tx_id.as_ref() == [0u8; 32]
+            && self.inputs[0].pre_tx_index.index() == 0xffff_ffff
+    }
+
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
+    /// BIP141 weight, non witness bytes count four times
+    pub fn weight(&self) -> usize {
+        self.legacy_serialized_len() * 3 + self.serialized_len()
+    }
+
+    pub fn vsize(&self) -> usize {
+        (self.weight() + 3) / 4
     }
 
-    fn hash(&self) -> Hash256 {
-        hash256(
-            &self
-                .serialize()
+    /// Full serialization, with witness data when any input has some
+    pub fn serialize(&self) -> Vec<u8> {
+        self.serialize_with_witness(self.has_witness())
+    }
+
+    /// Serialization without witness data, the one the id commits to
+    pub fn serialize_legacy(&self) -> Vec<u8> {
+        self.serialize_with_witness(false)
+    }
+
+    /// Parse a raw transaction, like `getrawtransaction` prints
+    pub fn from_hex(hex: &str) -> Result<Self, HexError> {
+        decode_hex(hex, "transaction")
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.serialize())
+    }
+
+    /// Exact length of `serialize()`
+    pub fn serialized_len(&self) -> usize {
+        self.serialized_len_with_witness(self.has_witness())
+    }
+
+    /// Exact length of `serialize_legacy()`
+    pub fn legacy_serialized_len(&self) -> usize {
+        self.serialized_len_with_witness(false)
+    }
+
+    fn serialized_len_with_witness(&self, witness: bool) -> usize {
+        let mut len = 4
+            + Varint::encoded_len(self.inputs.len() as u64)
+            + self
+                .inputs
                 .iter()
-                .rev()
-                .map(|i| *i)
-                .collect::<Vec<u8>>(),
-        )
+                .map(TxInput::serialized_len)
+                .sum::<usize>()
+            + Varint::encoded_len(self.outputs.len() as u64)
+            + self
+                .outputs
+                .iter()
+                .map(TxOutput::serialized_len)
+                .sum::<usize>()
+            + 4;
+        if witness {
+            len += 2 + self
+                .inputs
+                .iter()
+                .map(TxInput::witness_serialized_len)
+                .sum::<usize>();
+        }
+        len
     }
 
-    fn serialize(&self) -> Vec<u8> {
-        let mut inputs: Vec<Vec<u8>> = Vec::with_capacity(self.inputs.len());
-        let mut inputs_len = 0;
-        let mut outputs: Vec<Vec<u8>> = Vec::with_capacity(self.outputs.len());
-        let mut outputs_len = 0;
+    fn serialize_with_witness(&self, witness: bool) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.serialized_len_with_witness(witness));
+        self.serialize_into(&mut buf, witness);
+        buf
+    }
 
-        self.inputs.iter().for_each(|i| {
-            let bytes = i.serialize();
-            inputs_len += bytes.len();
-            inputs.push(bytes);
-        });
+    fn serialize_into(&self, buf: &mut Vec<u8>, witness: bool) {
+        buf.extend_from_slice(&u32::from(self.version).to_le_bytes());
+        if witness {
+            buf.extend_from_slice(b"\x00\x01");
+        }
 
-        self.outputs.iter().for_each(|i| {
-            let bytes = i.serialize();
-            outputs_len += bytes.len();
-            outputs.push(bytes);
-        });
+        Varint::from(self.inputs.len() as u64).consensus_encode(buf);
+        self.inputs.iter().for_each(|i| i.serialize_into(buf));
 
-        let mut buf = BytesMut::with_capacity(4 + 9 + inputs_len + 9 + outputs_len + 4 + 4);
+        Varint::from(self.outputs.len() as u64).consensus_encode(buf);
+        self.outputs.iter().for_each(|o| o.serialize_into(buf));
 
+        if witness {
+            self.inputs
+                .iter()
+                .for_each(|i| i.serialize_witness_into(buf));
+        }
+
+        buf.extend_from_slice(&u32::from(self.locktime).to_le_bytes());
+    }
+}
+
+impl Transaction {
+    /// Legacy signature hash of `input_index` signing `script_code`, returned as the little
+    /// endian z the signature verifies against. Out of range inputs and SIGHASH_SINGLE
+    /// without a matching output hash to one, as in Bitcoin Core.
+    pub fn sig_hash(
+        &self,
+        input_index: usize,
+        script_code: &ScriptPubKey,
+        sighash_type: u32,
+    ) -> Hash256 {
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        if input_index >= self.inputs.len()
+            || (base_type == SIGHASH_SINGLE && input_index >= self.outputs.len())
+        {
+            let mut one = [0u8; 32];
+            one[0] = 1;
+            return Hash256::new(&one);
+        }
+
+        let mut buf =
+            BytesMut::with_capacity(self.serialize().len() + script_code.content.len() + 4);
         buf.put_u32_le(u32::from(self.version));
 
-        buf.put(Varint::encode(self.inputs.len() as u64).unwrap());
-        inputs.into_iter().for_each(|i: Vec<u8>| buf.put(&i));
+        let inputs: Vec<(usize, &TxInput)> = if anyone_can_pay {
+            vec![(input_index, &self.inputs[input_index])]
+        } else {
+            self.inputs.iter().enumerate().collect()
+        };
+        buf.put(Varint::encode(inputs.len() as u64).unwrap());
+        for (i, input) in inputs {
+            buf.put(&input.pre_tx_id.to_little_endian());
+            buf.put_u32_le(input.pre_tx_index.index());
+            if i == input_index {
+                buf.put(script_code.serialize());
+            } else {
+                buf.put_u8(0);
+            }
+            if i != input_index && (base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE) {
+                buf.put_u32_le(0);
+            } else {
+                buf.put_u32_le(input.sequence.sequence());
+            }
+        }
 
-        buf.put(Varint::encode(self.outputs.len() as u64).unwrap());
-        outputs.into_iter().for_each(|i: Vec<u8>| buf.put(&i));
+        match base_type {
+            SIGHASH_NONE => buf.put(Varint::encode(0).unwrap()),
+            SIGHASH_SINGLE => {
+                buf.put(Varint::encode(input_index as u64 + 1).unwrap());
+                for _ in 0..input_index {
+                    // blanked output, -1 amount and an empty script
+                    buf.put_u64_le(0xffff_ffff_ffff_ffff);
+                    buf.put_u8(0);
+                }
+                buf.put(self.outputs[input_index].serialize());
+            }
+            _ => {
+                buf.put(Varint::encode(self.outputs.len() as u64).unwrap());
+                self.outputs.iter().for_each(|o| buf.put(o.serialize()));
+            }
+        }
 
         buf.put_u32_le(u32::from(self.locktime));
+        buf.put_u32_le(sighash_type);
 
-        buf.take().to_vec()
+        let mut z = hash256(&buf.take()).to_vec();
+        z.reverse();
+        Hash256::new(&z)
+    }
+
+    /// BIP143 signature hash of a segwit v0 input spending `amount` satoshis, little endian
+    /// like `sig_hash`. `script_code` is the witness script, or the P2PKH script of the key
+    /// hash for P2WPKH.
+    pub fn sig_hash_segwit_v0(
+        &self,
+        input_index: usize,
+        script_code: &ScriptPubKey,
+        amount: u64,
+        sighash_type: u32,
+    ) -> Hash256 {
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        if input_index >= self.inputs.len() {
+            let mut one = [0u8; 32];
+            one[0] = 1;
+            return Hash256::new(&one);
+        }
+
+        let mut hash_prevouts = [0u8; 32];
+        if !anyone_can_pay {
+            let mut buf = BytesMut::with_capacity(36 * self.inputs.len());
+            for input in &self.inputs {
+                buf.put(&input.pre_tx_id.to_little_endian());
+                buf.put_u32_le(input.pre_tx_index.index());
+            }
+            hash_prevouts.copy_from_slice(&hash256(&buf.take()));
+        }
+
+        let mut hash_sequence = [0u8; 32];
+        if !anyone_can_pay && base_type != SIGHASH_SINGLE && base_type != SIGHASH_NONE {
+            let mut buf = BytesMut::with_capacity(4 * self.inputs.len());
+            for input in &self.inputs {
+                buf.put_u32_le(input.sequence.sequence());
+            }
+            hash_sequence.copy_from_slice(&hash256(&buf.take()));
+        }
+
+        let mut hash_outputs = [0u8; 32];
+        if base_type != SIGHASH_SINGLE && base_type != SIGHASH_NONE {
+            let outputs: Vec<u8> = self.outputs.iter().flat_map(|o| o.serialize()).collect();
+            hash_outputs.copy_from_slice(&hash256(&outputs));
+        } else if base_type == SIGHASH_SINGLE && input_index < self.outputs.len() {
+            hash_outputs.copy_from_slice(&hash256(&self.outputs[input_index].serialize()));
+        }
+
+        let input = &self.inputs[input_index];
+        let mut buf = BytesMut::with_capacity(156 + script_code.content.len() + 9);
+        buf.put_u32_le(u32::from(self.version));
+        buf.put(&hash_prevouts[..]);
+        buf.put(&hash_sequence[..]);
+        buf.put(&input.pre_tx_id.to_little_endian());
+        buf.put_u32_le(input.pre_tx_index.index());
+        buf.put(script_code.serialize());
+        buf.put_u64_le(amount);
+        buf.put_u32_le(input.sequence.sequence());
+        buf.put(&hash_outputs[..]);
+        buf.put_u32_le(u32::from(self.locktime));
+        buf.put_u32_le(sighash_type);
+
+        let mut z = hash256(&buf.take()).to_vec();
+        z.reverse();
+        Hash256::new(&z)
+    }
+
+    /// BIP341 signature hash, `prevouts` are the outputs spent by every input. A script path
+    /// spend also commits to the leaf hash and the position of the last executed
+    /// OP_CODESEPARATOR (0xffffffff when none), as `leaf`.
+    pub fn sig_hash_taproot(
+        &self,
+        input_index: usize,
+        prevouts: &[TxOutput],
+        sighash_type: u8,
+        annex: Option<&[u8]>,
+        leaf: Option<(&[u8; 32], u32)>,
+    ) -> Result<[u8; 32], SigHashError> {
+        match sighash_type {
+            0x00..=0x03 | 0x81..=0x83 => {}
+            _ => return Err(SigHashError::InvalidSigHashType(sighash_type)),
+        }
+        if input_index >= self.inputs.len() {
+            return Err(SigHashError::InputIndexOutOfRange(input_index));
+        }
+        if prevouts.len() != self.inputs.len() {
+            return Err(SigHashError::PrevoutsMismatch(
+                prevouts.len(),
+                self.inputs.len(),
+            ));
+        }
+        let base_type = u32::from(sighash_type) & 0x03;
+        let anyone_can_pay = u32::from(sighash_type) & SIGHASH_ANYONECANPAY != 0;
+        let sha256 = |data: &[u8]| Sha256::digest(data);
+
+        let mut buf = BytesMut::with_capacity(256);
+        // epoch
+        buf.put_u8(0x00);
+        buf.put_u8(sighash_type);
+        buf.put_u32_le(u32::from(self.version));
+        buf.put_u32_le(u32::from(self.locktime));
+
+        if !anyone_can_pay {
+            let mut outpoints = BytesMut::with_capacity(36 * self.inputs.len());
+            let mut amounts = BytesMut::with_capacity(8 * prevouts.len());
+            let mut script_pubkeys = BytesMut::with_capacity(35 * prevouts.len());
+            let mut sequences = BytesMut::with_capacity(4 * self.inputs.len());
+            for (input, prevout) in self.inputs.iter().zip(prevouts) {
+                outpoints.put(&input.pre_tx_id.to_little_endian());
+                outpoints.put_u32_le(input.pre_tx_index.index());
+                amounts.put_u64_le(u64::from(prevout.amount));
+                script_pubkeys.put(prevout.script_pub_key.serialize());
+                sequences.put_u32_le(input.sequence.sequence());
+            }
+            buf.put(&sha256(&outpoints)[..]);
+            buf.put(&sha256(&amounts)[..]);
+            buf.put(&sha256(&script_pubkeys)[..]);
+            buf.put(&sha256(&sequences)[..]);
+        }
+        if base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE {
+            let outputs: Vec<u8> = self.outputs.iter().flat_map(|o| o.serialize()).collect();
+            buf.put(&sha256(&outputs)[..]);
+        }
+
+        let spend_type = if leaf.is_some() { 2 } else { 0 } + if annex.is_some() { 1 } else { 0 };
+        buf.put_u8(spend_type);
+        if anyone_can_pay {
+            let input = &self.inputs[input_index];
+            let prevout = &prevouts[input_index];
+            buf.put(&input.pre_tx_id.to_little_endian());
+            buf.put_u32_le(input.pre_tx_index.index());
+            buf.put_u64_le(u64::from(prevout.amount));
+            buf.put(prevout.script_pub_key.serialize());
+            buf.put_u32_le(input.sequence.sequence());
+        } else {
+            buf.put_u32_le(input_index as u32);
+        }
+        if let Some(annex) = annex {
+            let mut annex_buf = Varint::encode(annex.len() as u64).unwrap();
+            annex_buf.extend_from_slice(annex);
+            buf.put(&sha256(&annex_buf)[..]);
+        }
+        if base_type == SIGHASH_SINGLE {
+            let output = self
+                .outputs
+                .get(input_index)
+                .ok_or(SigHashError::NoOutputForSingle(input_index))?;
+            buf.put(&sha256(&output.serialize())[..]);
+        }
+        if let Some((leaf_hash, codesep_pos)) = leaf {
+            buf.put(&leaf_hash[..]);
+            // key version
+            buf.put_u8(0x00);
+            buf.put_u32_le(codesep_pos);
+        }
+
+        Ok(tagged_hash("TapSighash", &buf.take()))
+    }
+
+    /// Same shape as Bitcoin Core's `decoderawtransaction`
+    pub fn to_json(&self) -> serde_json::Value {
+        let size = self.serialize().len();
+        let vin: Vec<serde_json::Value> = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let is_coinbase = input.pre_tx_id.as_ref() == &[0u8; 32][..]
+                    && input.pre_tx_index.index() == 0xffff_ffff;
+                let mut input_json = if is_coinbase {
+                    json!({
+                        "coinbase": hex::encode(&input.script_sig.content),
+                        "sequence": input.sequence.sequence(),
+                    })
+                } else {
+                    json!({
+                        "txid": input.pre_tx_id.to_string(),
+                        "vout": input.pre_tx_index.index(),
+                        "scriptSig": {
+                            "asm": input.script_sig.asm(),
+                            "hex": hex::encode(&input.script_sig.content),
+                        },
+                        "sequence": input.sequence.sequence(),
+                    })
+                };
+                if !input.witness.is_empty() {
+                    let witness: Vec<String> = input.witness.iter().map(hex::encode).collect();
+                    input_json["txinwitness"] = json!(witness);
+                }
+                input_json
+            })
+            .collect();
+        let vout: Vec<serde_json::Value> = self
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(n, output)| {
+                let script_pub_key = &output.script_pub_key;
+                let mut script_json = json!({
+                    "asm": script_pub_key.asm(),
+                    "hex": hex::encode(&script_pub_key.content),
+                    "type": script_pub_key.script_type().to_string(),
+                });
+                if let Some(address) = script_pub_key.address(self.testnet) {
+                    script_json["address"] = json!(address);
+                }
+                json!({
+                    "value": u64::from(output.amount) as f64 / 100_000_000.0,
+                    "n": n,
+                    "scriptPubKey": script_json,
+                })
+            })
+            .collect();
+
+        json!({
+            "txid": self.id().to_string(),
+            "hash": self.wtxid().to_string(),
+            "version": u32::from(self.version),
+            "size": size,
+            "vsize": self.vsize(),
+            "weight": self.weight(),
+            "locktime": u32::from(self.locktime),
+            "vin": vin,
+            "vout": vout,
+        })
     }
 }
 
-impl Hex for Transaction {
-    fn hex(&self) -> String {
-        hex::encode(self.serialize())
+impl Encodable for Transaction {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        self.serialize_into(buf, self.has_witness());
+        buf.len() - start
+    }
+}
+
+impl Decodable for Transaction {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        Transaction::parse(input)
+    }
+}
+
+impl Serialize for Transaction {
+    fn serialize_bytes(&self) -> Vec<u8> {
+        self.serialize()
+    }
+}
+
+impl Parse for Transaction {
+    fn parse_bytes(bytes: &[u8]) -> Option<Self> {
+        match Transaction::parse(bytes) {
+            Ok((rest, tx)) if rest.is_empty() => Some(tx),
+            _ => None,
+        }
     }
 }
 
@@ -123,7 +565,8 @@ mod test {
     use super::super::wallet::Hex;
     use super::locktime::TxLocktime;
     use super::tx_version::TxVersion;
-    use super::Transaction;
+    use super::{ScriptPubKey, Transaction, SIGHASH_ALL};
+    use crate::wallet::{FromHex, Hash256};
 
     #[test]
     fn test_tx() {
@@ -150,10 +593,159 @@ mod test {
         );
 
         assert_eq!(tx.locktime, TxLocktime::new(410393));
+        assert_eq!(
+            format!("{}", tx.id()),
+            "452c629d67e41baec3ac6f04fe744b4b9617f8f859c63b3002f8684e7a4fee03".to_string()
+        );
 
         assert_eq!(
             tx.hex(),
             "0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600".to_string()
         );
     }
+
+    #[test]
+    fn test_segwit_tx() {
+        // BIP143 native P2WPKH example
+        let data = hex!("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000");
+        let (rest, tx) = Transaction::parse(&data[..]).unwrap();
+        assert!(rest.is_empty());
+
+        assert!(tx.has_witness());
+        assert!(tx.inputs[0].witness.is_empty());
+        assert_eq!(tx.inputs[1].witness.len(), 2);
+        assert_eq!(tx.hex(), hex::encode(&data[..]));
+
+        // marker, flag and the witnesses are left out of the id
+        let legacy = tx.serialize_legacy();
+        assert_eq!(legacy.len(), data.len() - 2 - 1 - 1 - 72 - 34);
+        assert_eq!(tx.weight(), legacy.len() * 4 + 2 + 1 + 1 + 72 + 34);
+        assert_ne!(tx.id(), tx.wtxid());
+        assert_eq!(Transaction::parse(&legacy).unwrap().1.id(), tx.id());
+
+        let script_code = ScriptPubKey {
+            content: hex!("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").to_vec(),
+        };
+        let mut z = tx
+            .sig_hash_segwit_v0(1, &script_code, 600_000_000, SIGHASH_ALL)
+            .to_vec();
+        z.reverse();
+        assert_eq!(
+            hex::encode(z),
+            "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"
+        );
+    }
+
+    #[test]
+    fn test_serialized_len() {
+        let legacy = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let segwit = hex!("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000");
+
+        for data in &[&legacy[..], &segwit[..]] {
+            let tx = Transaction::parse(data).unwrap().1;
+            assert_eq!(tx.serialized_len(), data.len());
+
+            // an exact preallocation is never outgrown
+            let bytes = tx.serialize();
+            assert_eq!(bytes.capacity(), bytes.len());
+            let bytes = tx.serialize_legacy();
+            assert_eq!(tx.legacy_serialized_len(), bytes.len());
+            assert_eq!(bytes.capacity(), bytes.len());
+
+            for input in &tx.inputs {
+                let bytes = input.serialize();
+                assert_eq!(input.serialized_len(), bytes.len());
+                assert_eq!(bytes.capacity(), bytes.len());
+                let bytes = input.serialize_witness();
+                assert_eq!(input.witness_serialized_len(), bytes.len());
+                assert_eq!(bytes.capacity(), bytes.len());
+            }
+            for output in &tx.outputs {
+                let bytes = output.serialize();
+                assert_eq!(output.serialized_len(), bytes.len());
+                assert_eq!(bytes.capacity(), bytes.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_tx_sig_hash() {
+        let data = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let (_data, tx) = Transaction::parse(&data[..]).unwrap();
+        let script_code = ScriptPubKey {
+            content: hex!("76a914a802fc56c704ce87c42d7c92eb75e7896bdc41ae88ac").to_vec(),
+        };
+        assert_eq!(
+            tx.sig_hash(0, &script_code, SIGHASH_ALL),
+            Hash256::from_hex(b"27e0c5994dec7824e56dec6b2fcb342eb7cdb0d0957c2fce9882f715e85d81a6")
+        );
+    }
+
+    #[test]
+    fn test_tx_to_json() {
+        let data = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let (_data, tx) = Transaction::parse(&data[..]).unwrap();
+        let json = tx.to_json();
+
+        assert_eq!(
+            json["txid"],
+            "452c629d67e41baec3ac6f04fe744b4b9617f8f859c63b3002f8684e7a4fee03"
+        );
+        assert_eq!(json["size"], 226);
+        assert_eq!(json["locktime"], 410393);
+        assert_eq!(
+            json["vin"][0]["txid"],
+            "d1c789a9c60383bf715f3f6ad9d14b91fe55f3deb369fe5d9280cb1a01793f81"
+        );
+        assert_eq!(json["vin"][0]["scriptSig"]["asm"], "3045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed[ALL] 0349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278a");
+        assert_eq!(json["vin"][0]["sequence"], 4294967294u32);
+        assert_eq!(json["vout"][0]["value"], 0.32454049);
+        assert_eq!(json["vout"][1]["n"], 1);
+        assert_eq!(json["vout"][0]["scriptPubKey"]["type"], "pubkeyhash");
+        assert_eq!(
+            json["vout"][0]["scriptPubKey"]["address"],
+            "1JAHBxA51vwp5C2zpSB15VbxSZK3hVJs2H"
+        );
+    }
+
+    #[test]
+    fn test_snapshots() {
+        use crate::script::Script;
+        use crate::testkit::{Snapshot, TxGenerator};
+
+        let data = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let (_, tx) = Transaction::parse(&data).unwrap();
+        let mut snapshot = Snapshot::new("transaction");
+        snapshot
+            .hex("legacy", &tx.serialize())
+            .text("legacy.id", &tx.id().hex())
+            .hex("legacy.input0", &tx.inputs[0].serialize())
+            .hex("legacy.output0", &tx.outputs[0].serialize())
+            .text(
+                "legacy.script_sig.asm",
+                &Script::parse_lossy(&tx.inputs[0].script_sig.content)
+                    .0
+                    .asm(),
+            )
+            .text(
+                "legacy.script_pubkey.asm",
+                &Script::parse_lossy(&tx.outputs[0].script_pub_key.content)
+                    .0
+                    .asm(),
+            );
+
+        let mut generator = TxGenerator::new(1);
+        for i in 0..4 {
+            let tx = generator.tx();
+            snapshot
+                .hex(&format!("generated{}", i), &tx.serialize())
+                .hex(&format!("generated{}.legacy", i), &tx.serialize_legacy())
+                .text(&format!("generated{}.wtxid", i), &tx.wtxid().hex());
+        }
+        let block = generator.block();
+        snapshot
+            .hex("block.header", &block.header.serialize())
+            .hex("block.coinbase", &block.txs[0].serialize());
+        snapshot.check();
+    }
 }
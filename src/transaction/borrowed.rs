@@ -0,0 +1,277 @@
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::IResult;
+
+use super::{
+    PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxHash, TxInput, TxInputSequence, TxLocktime,
+    TxOutput, TxOutputAmount, TxVersion, Varint,
+};
+use crate::script::{Script, ScriptError};
+use crate::wallet::hash256;
+
+/// Script bytes borrowed from the buffer they were parsed from, without the length prefix
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct ScriptRef<'a> {
+    content: &'a [u8],
+}
+impl<'a> Copy for ScriptRef<'a> {}
+
+impl<'a> ScriptRef<'a> {
+    pub fn new(content: &'a [u8]) -> Self {
+        ScriptRef { content }
+    }
+
+    /// Parse a length prefixed script
+    pub fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self> {
+        let (input, len) = Varint::parse(input)?;
+        let (input, content) = take(Into::<u64>::into(len))(input)?;
+        Ok((input, ScriptRef { content }))
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.content
+    }
+
+    pub fn len(&self) -> usize {
+        self.content.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+    }
+
+    /// Decode the commands, which copies every push
+    pub fn to_script(&self) -> Result<Script, ScriptError> {
+        Script::from_raw(self.content)
+    }
+
+    pub fn to_script_sig(&self) -> ScriptSig {
+        ScriptSig {
+            content: self.content.to_vec(),
+        }
+    }
+
+    pub fn to_script_pub_key(&self) -> ScriptPubKey {
+        ScriptPubKey {
+            content: self.content.to_vec(),
+        }
+    }
+}
+
+/// `TxInput` borrowing its script sig and witness from the parsed buffer
+#[derive(Debug, PartialEq, Clone)]
+pub struct TxInputRef<'a> {
+    pub pre_tx_id: TxHash,
+    pub pre_tx_index: PreTxIndex,
+    pub script_sig: ScriptRef<'a>,
+    pub sequence: TxInputSequence,
+    /// Segwit witness stack, empty for legacy inputs
+    pub witness: Vec<&'a [u8]>,
+}
+
+impl<'a> TxInputRef<'a> {
+    pub fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self> {
+        let (input, pre_tx_id) = TxHash::parse(input)?;
+        let (input, pre_tx_index) = PreTxIndex::parse(input)?;
+        let (input, script_sig) = ScriptRef::parse(input)?;
+        let (input, sequence) = TxInputSequence::parse(input)?;
+        Ok((
+            input,
+            TxInputRef {
+                pre_tx_id,
+                pre_tx_index,
+                script_sig,
+                sequence,
+                witness: Vec::new(),
+            },
+        ))
+    }
+
+    /// Witness stack, follows every input once all outputs are parsed
+    pub fn parse_witness(input: &'a [u8]) -> IResult<&'a [u8], Vec<&'a [u8]>> {
+        let (mut input, items_num) = Varint::parse(input)?;
+        let mut witness = Vec::new();
+        for _ in 0..Into::<u64>::into(items_num) {
+            let (rest, item) = ScriptRef::parse(input)?;
+            witness.push(item.as_bytes());
+            input = rest;
+        }
+        Ok((input, witness))
+    }
+
+    pub fn to_owned(&self) -> TxInput {
+        let mut input = TxInput::new(
+            self.pre_tx_id,
+            self.pre_tx_index,
+            self.script_sig.to_script_sig(),
+            self.sequence,
+        );
+        input.witness = self.witness.iter().map(|item| item.to_vec()).collect();
+        input
+    }
+}
+
+/// `TxOutput` borrowing its script pubkey from the parsed buffer
+#[derive(Debug, PartialEq, Clone)]
+pub struct TxOutputRef<'a> {
+    pub amount: TxOutputAmount,
+    pub script_pub_key: ScriptRef<'a>,
+}
+impl<'a> Copy for TxOutputRef<'a> {}
+
+impl<'a> TxOutputRef<'a> {
+    pub fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self> {
+        let (input, amount) = TxOutputAmount::parse(input)?;
+        let (input, script_pub_key) = ScriptRef::parse(input)?;
+        Ok((
+            input,
+            TxOutputRef {
+                amount,
+                script_pub_key,
+            },
+        ))
+    }
+
+    pub fn to_owned(&self) -> TxOutput {
+        TxOutput {
+            amount: self.amount,
+            script_pub_key: self.script_pub_key.to_script_pub_key(),
+        }
+    }
+}
+
+/// A transaction parsed without copying any script or witness bytes, for scanning many
+/// transactions where most are thrown away
+#[derive(Debug, PartialEq, Clone)]
+pub struct TransactionRef<'a> {
+    pub version: TxVersion,
+    pub inputs: Vec<TxInputRef<'a>>,
+    pub outputs: Vec<TxOutputRef<'a>>,
+    pub locktime: TxLocktime,
+    /// The whole serialization, witness included
+    raw: &'a [u8],
+    /// Input and output section, what the id commits to between version and locktime
+    body: &'a [u8],
+}
+
+impl<'a> TransactionRef<'a> {
+    pub fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self> {
+        let raw = input;
+        let (input, version) = TxVersion::parse(input)?;
+        let segwit = input.len() >= 2 && input[0] == 0x00 && input[1] == 0x01;
+        let input = if segwit { &input[2..] } else { input };
+        let body_start = input;
+
+        let (input, inputs_num) = Varint::parse(input)?;
+        let (input, mut inputs) =
+            count(TxInputRef::parse, Into::<u64>::into(inputs_num) as usize)(input)?;
+        let (mut input, outputs_num) = Varint::parse(input)?;
+        let outputs = {
+            let (rest, outputs) =
+                count(TxOutputRef::parse, Into::<u64>::into(outputs_num) as usize)(input)?;
+            input = rest;
+            outputs
+        };
+        let body = &body_start[..body_start.len() - input.len()];
+
+        if segwit {
+            for tx_input in inputs.iter_mut() {
+                let (rest, witness) = TxInputRef::parse_witness(input)?;
+                tx_input.witness = witness;
+                input = rest;
+            }
+        }
+
+        let (input, locktime) = TxLocktime::parse(input)?;
+        Ok((
+            input,
+            TransactionRef {
+                version,
+                inputs,
+                outputs,
+                locktime,
+                raw: &raw[..raw.len() - input.len()],
+                body,
+            },
+        ))
+    }
+
+    /// The bytes this transaction was parsed from
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
+    /// Transaction id, hashing the parsed bytes directly when there is no witness to strip
+    pub fn id(&self) -> TxHash {
+        let hash = if self.raw.len() != 4 + self.body.len() + 4 {
+            let mut legacy = Vec::with_capacity(4 + self.body.len() + 4);
+            legacy.extend_from_slice(&self.raw[..4]);
+            legacy.extend_from_slice(self.body);
+            legacy.extend_from_slice(&self.raw[self.raw.len() - 4..]);
+            hash256(&legacy)
+        } else {
+            hash256(self.raw)
+        };
+        TxHash::parse(&hash).expect("hash256 is 32 bytes").1
+    }
+
+    /// Witness transaction id, equal to the id without witness data
+    pub fn wtxid(&self) -> TxHash {
+        TxHash::parse(&hash256(self.raw))
+            .expect("hash256 is 32 bytes")
+            .1
+    }
+
+    pub fn to_owned(&self) -> Transaction {
+        Transaction::new(
+            self.version,
+            self.inputs.iter().map(TxInputRef::to_owned).collect(),
+            self.outputs.iter().map(TxOutputRef::to_owned).collect(),
+            self.locktime,
+            false,
+        )
+    }
+}
+
+mod test {
+    use super::{ScriptRef, TransactionRef};
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn test_transaction_ref() {
+        let legacy = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let segwit = hex!("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000");
+
+        for data in &[&legacy[..], &segwit[..]] {
+            // a trailing byte is left for the caller
+            let mut buf = data.to_vec();
+            buf.push(0xff);
+            let (rest, tx_ref) = TransactionRef::parse(&buf).unwrap();
+            assert_eq!(rest, &[0xff]);
+            assert_eq!(tx_ref.as_bytes(), *data);
+
+            let tx = Transaction::parse(data).unwrap().1;
+            assert_eq!(tx_ref.to_owned(), tx);
+            assert_eq!(tx_ref.id(), tx.id());
+            assert_eq!(tx_ref.wtxid(), tx.wtxid());
+            assert_eq!(tx_ref.has_witness(), tx.has_witness());
+        }
+
+        // the script sig points into the parsed buffer
+        let tx_ref = TransactionRef::parse(&legacy).unwrap().1;
+        let script_sig = tx_ref.inputs[0].script_sig.as_bytes();
+        assert_eq!(script_sig.as_ptr(), legacy[42..].as_ptr());
+        assert_eq!(script_sig.len(), 0x6b);
+
+        let script = ScriptRef::new(tx_ref.outputs[0].script_pub_key.as_bytes());
+        assert_eq!(
+            script.to_script().unwrap().raw_serialize().unwrap(),
+            script.as_bytes()
+        );
+        assert!(ScriptRef::new(&[0x4c]).to_script().is_err());
+    }
+}
@@ -0,0 +1,616 @@
+use failure::Error;
+use rand::Rng;
+use std::cmp::Ordering;
+
+use super::{
+    ChainBackend, OutPoint, PreTxIndex, ScriptPubKey, ScriptPubKeyType, ScriptSig, Transaction,
+    TxInput, TxInputSequence, TxLocktime, TxOutput, TxVersion, Varint,
+};
+use crate::script::Script;
+
+/// Outputs below this are not worth spending and nodes do not relay them
+pub const DUST_LIMIT: u64 = 546;
+
+/// What signing will add to an input, known before the signatures exist
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InputWeight {
+    /// Script sig bytes, without the length prefix
+    pub script_sig_len: usize,
+    /// Witness weight with its item count, 0 for a legacy input
+    pub witness_weight: usize,
+}
+impl Copy for InputWeight {}
+
+/// A 72 bytes signature and a compressed key
+pub const P2PKH_INPUT: InputWeight = InputWeight {
+    script_sig_len: 107,
+    witness_weight: 0,
+};
+/// A 72 bytes signature
+pub const P2PK_INPUT: InputWeight = InputWeight {
+    script_sig_len: 73,
+    witness_weight: 0,
+};
+pub const P2WPKH_INPUT: InputWeight = InputWeight {
+    script_sig_len: 0,
+    witness_weight: 108,
+};
+/// The script sig pushes the 22 bytes witness program
+pub const P2SH_P2WPKH_INPUT: InputWeight = InputWeight {
+    script_sig_len: 23,
+    witness_weight: 108,
+};
+/// Key path spend, a 64 bytes schnorr signature
+pub const P2TR_KEY_PATH_INPUT: InputWeight = InputWeight {
+    script_sig_len: 0,
+    witness_weight: 66,
+};
+
+impl InputWeight {
+    /// For an output of `script_type`, None when its satisfaction depends on a script
+    pub fn for_script_type(script_type: ScriptPubKeyType) -> Option<Self> {
+        match script_type {
+            ScriptPubKeyType::PubKeyHash => Some(P2PKH_INPUT),
+            ScriptPubKeyType::PubKey => Some(P2PK_INPUT),
+            ScriptPubKeyType::WitnessV0KeyHash => Some(P2WPKH_INPUT),
+            ScriptPubKeyType::WitnessV1Taproot => Some(P2TR_KEY_PATH_INPUT),
+            _ => None,
+        }
+    }
+
+    /// Spending a P2WSH output of `witness_script`
+    pub fn p2wsh(witness_script: &Script) -> Option<Self> {
+        Some(InputWeight {
+            script_sig_len: 0,
+            witness_weight: witness_script.max_satisfaction_weight()?,
+        })
+    }
+
+    /// Spending a P2SH-P2WSH output of `witness_script`
+    pub fn p2sh_p2wsh(witness_script: &Script) -> Option<Self> {
+        Some(InputWeight {
+            script_sig_len: 35,
+            ..InputWeight::p2wsh(witness_script)?
+        })
+    }
+
+    pub fn is_segwit(&self) -> bool {
+        self.witness_weight > 0
+    }
+
+    /// The signed input: outpoint, script sig and sequence at four times, then the witness
+    pub fn weight(&self) -> usize {
+        let script_sig = Varint::encoded_len(self.script_sig_len as u64) + self.script_sig_len;
+        (32 + 4 + script_sig + 4) * 4 + self.witness_weight
+    }
+}
+
+/// How the change amount is paid back
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ChangePolicy {
+    /// One change output
+    Single,
+    /// This many outputs of equal amounts
+    Split(usize),
+    /// As few outputs as keep each at most this amount
+    MaxOutput(u64),
+}
+impl Copy for ChangePolicy {}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BuilderOptions {
+    /// Put change outputs at random positions instead of after the recipients
+    pub randomize_change: bool,
+    pub change_policy: ChangePolicy,
+    /// Change below this is left to the fee, split parts never go below it
+    pub dust_limit: u64,
+}
+impl Copy for BuilderOptions {}
+
+impl Default for BuilderOptions {
+    fn default() -> Self {
+        BuilderOptions {
+            randomize_change: true,
+            change_policy: ChangePolicy::Single,
+            dust_limit: DUST_LIMIT,
+        }
+    }
+}
+
+impl BuilderOptions {
+    /// Amounts of the change outputs for `change`, none when it is dust
+    pub fn split_change(&self, change: u64) -> Vec<u64> {
+        if change < self.dust_limit {
+            return vec![];
+        }
+        let wanted = match self.change_policy {
+            ChangePolicy::Single => 1,
+            ChangePolicy::Split(count) => count.max(1) as u64,
+            ChangePolicy::MaxOutput(max) => (change + max.max(1) - 1) / max.max(1),
+        };
+        let count = wanted.min(change / self.dust_limit.max(1)).max(1);
+        let part = change / count;
+        let mut parts = vec![part; count as usize];
+        // the remainder goes to the first one
+        parts[0] += change - part * count;
+        parts
+    }
+}
+
+/// Bitcoin Core's anti fee sniping locktime: the tip height, so a miner reorging the tip
+/// cannot take the fee, and one time in ten up to 99 blocks earlier so transactions that
+/// were delayed before broadcast do not stand out
+fn anti_fee_sniping_locktime<R: Rng>(tip_height: u32, rng: &mut R) -> u32 {
+    if rng.gen_range(0, 10) == 0 {
+        tip_height.saturating_sub(rng.gen_range(0, 100))
+    } else {
+        tip_height
+    }
+}
+
+/// Unsigned transaction assembly, inputs get empty script sigs to be signed later
+#[derive(Debug, Clone)]
+pub struct TransactionBuilder {
+    version: TxVersion,
+    inputs: Vec<TxInput>,
+    /// Signed size of each input when known
+    input_weights: Vec<Option<InputWeight>>,
+    outputs: Vec<TxOutput>,
+    change: Option<(ScriptPubKey, u64)>,
+    /// Explicit locktime, anti fee sniping when the tip is known otherwise
+    locktime: Option<TxLocktime>,
+    tip_height: Option<u32>,
+    bip69: bool,
+    testnet: bool,
+    options: BuilderOptions,
+}
+
+impl Default for TransactionBuilder {
+    fn default() -> Self {
+        TransactionBuilder {
+            version: TxVersion::new(2),
+            inputs: Vec::new(),
+            input_weights: Vec::new(),
+            outputs: Vec::new(),
+            change: None,
+            locktime: None,
+            tip_height: None,
+            bip69: false,
+            testnet: false,
+            options: BuilderOptions::default(),
+        }
+    }
+}
+
+impl TransactionBuilder {
+    /// Version 2, locktime 0 until the tip height is known
+    pub fn new() -> Self {
+        TransactionBuilder::default()
+    }
+
+    pub fn version(mut self, version: u32) -> Self {
+        self.version = TxVersion::new(version);
+        self
+    }
+
+    /// Overrides the anti fee sniping locktime
+    pub fn locktime(mut self, locktime: u32) -> Self {
+        self.locktime = Some(TxLocktime::new(locktime));
+        self
+    }
+
+    /// Best chain height, the locktime defaults to it with an occasional random back-off.
+    /// It only takes effect when an input's sequence is below 0xffffffff.
+    pub fn tip_height(mut self, height: u32) -> Self {
+        self.tip_height = Some(height);
+        self
+    }
+
+    /// `tip_height` from the backend's `current_height`
+    pub fn current_height<B: ChainBackend>(self, backend: &mut B) -> Result<Self, Error> {
+        let height = backend.current_height(self.testnet)?;
+        Ok(self.tip_height(height))
+    }
+
+    pub fn testnet(mut self, testnet: bool) -> Self {
+        self.testnet = testnet;
+        self
+    }
+
+    pub fn add_input(mut self, out_point: OutPoint, sequence: u32) -> Self {
+        self.inputs.push(TxInput::new(
+            out_point.txid,
+            PreTxIndex::new(out_point.vout),
+            ScriptSig { content: vec![] },
+            TxInputSequence::new(sequence),
+        ));
+        self.input_weights.push(None);
+        self
+    }
+
+    /// `add_input` of an input whose signed size is `weight`, for fee estimation
+    pub fn add_weighted_input(
+        self,
+        out_point: OutPoint,
+        sequence: u32,
+        weight: InputWeight,
+    ) -> Self {
+        let mut builder = self.add_input(out_point, sequence);
+        *builder.input_weights.last_mut().unwrap() = Some(weight);
+        builder
+    }
+
+    pub fn add_output(mut self, script_pub_key: ScriptPubKey, amount: u64) -> Self {
+        self.outputs.push(TxOutput {
+            amount: amount.into(),
+            script_pub_key,
+        });
+        self
+    }
+
+    /// Pay every recipient from this one transaction
+    pub fn add_recipients<I: IntoIterator<Item = (ScriptPubKey, u64)>>(
+        mut self,
+        recipients: I,
+    ) -> Self {
+        for (script_pub_key, amount) in recipients {
+            self = self.add_output(script_pub_key, amount);
+        }
+        self
+    }
+
+    /// Send `amount` back to `script_pub_key`, laid out by the options change policy
+    pub fn change(mut self, script_pub_key: ScriptPubKey, amount: u64) -> Self {
+        self.change = Some((script_pub_key, amount));
+        self
+    }
+
+    pub fn options(mut self, options: BuilderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Order inputs and outputs the BIP69 way when building, so the order leaks nothing
+    /// about which output is the change
+    pub fn sort_bip69(mut self) -> Self {
+        self.bip69 = true;
+        self
+    }
+
+    /// Weight of the transaction once every input is signed, None unless each input was
+    /// added with its weight
+    pub fn estimated_weight(&self) -> Option<usize> {
+        let weights = self
+            .input_weights
+            .iter()
+            .cloned()
+            .collect::<Option<Vec<_>>>()?;
+        let mut outputs: Vec<usize> = self
+            .outputs
+            .iter()
+            .map(|output| output.script_pub_key.content.len())
+            .collect();
+        if let Some((script_pub_key, amount)) = &self.change {
+            for _ in self.options.split_change(*amount) {
+                outputs.push(script_pub_key.content.len());
+            }
+        }
+        let outputs_len: usize = outputs
+            .iter()
+            .map(|len| 8 + Varint::encoded_len(*len as u64) + len)
+            .sum();
+        let base = 4
+            + Varint::encoded_len(weights.len() as u64)
+            + Varint::encoded_len(outputs.len() as u64)
+            + outputs_len
+            + 4;
+        let inputs: usize = weights.iter().map(InputWeight::weight).sum();
+        let witness = if weights.iter().any(InputWeight::is_segwit) {
+            // marker and flag, and an empty witness for every legacy input
+            2 + weights.iter().filter(|weight| !weight.is_segwit()).count()
+        } else {
+            0
+        };
+        Some(base * 4 + inputs + witness)
+    }
+
+    pub fn estimated_vsize(&self) -> Option<usize> {
+        Some((self.estimated_weight()? + 3) / 4)
+    }
+
+    /// Fee paying `fee_rate` sat/vB once signed
+    pub fn estimated_fee(&self, fee_rate: f64) -> Option<u64> {
+        Some((self.estimated_vsize()? as f64 * fee_rate).ceil() as u64)
+    }
+
+    pub fn build(self) -> Transaction {
+        self.build_with_rng(&mut rand::thread_rng())
+    }
+
+    /// `build` drawing the change positions and the locktime back-off from `rng`
+    pub fn build_with_rng<R: Rng>(self, rng: &mut R) -> Transaction {
+        let locktime = match (self.locktime, self.tip_height) {
+            (Some(locktime), _) => locktime,
+            (None, Some(tip_height)) => TxLocktime::new(anti_fee_sniping_locktime(tip_height, rng)),
+            (None, None) => TxLocktime::new(0),
+        };
+        let mut outputs = self.outputs;
+        if let Some((script_pub_key, amount)) = self.change {
+            for part in self.options.split_change(amount) {
+                let position = if self.options.randomize_change {
+                    rng.gen_range(0, outputs.len() + 1)
+                } else {
+                    outputs.len()
+                };
+                outputs.insert(
+                    position,
+                    TxOutput {
+                        amount: part.into(),
+                        script_pub_key: script_pub_key.clone(),
+                    },
+                );
+            }
+        }
+        let mut tx = Transaction::new(self.version, self.inputs, outputs, locktime, self.testnet);
+        if self.bip69 {
+            tx.sort_bip69();
+        }
+        tx
+    }
+}
+
+/// Inputs by previous txid as displayed, then by output index
+fn bip69_input_order(a: &TxInput, b: &TxInput) -> Ordering {
+    a.out_point().cmp(&b.out_point())
+}
+
+/// Outputs by amount, then by script pubkey bytes
+fn bip69_output_order(a: &TxOutput, b: &TxOutput) -> Ordering {
+    u64::from(a.amount)
+        .cmp(&u64::from(b.amount))
+        .then_with(|| a.script_pub_key.content.cmp(&b.script_pub_key.content))
+}
+
+impl Transaction {
+    /// Sort inputs and outputs in BIP69 order, which invalidates any signature
+    pub fn sort_bip69(&mut self) {
+        self.inputs.sort_by(bip69_input_order);
+        self.outputs.sort_by(bip69_output_order);
+    }
+
+    pub fn is_bip69_sorted(&self) -> bool {
+        self.inputs
+            .windows(2)
+            .all(|pair| bip69_input_order(&pair[0], &pair[1]) != Ordering::Greater)
+            && self
+                .outputs
+                .windows(2)
+                .all(|pair| bip69_output_order(&pair[0], &pair[1]) != Ordering::Greater)
+    }
+}
+
+mod test {
+    use super::{BuilderOptions, ChangePolicy, InputWeight, TransactionBuilder};
+    use super::{P2PKH_INPUT, P2SH_P2WPKH_INPUT, P2WPKH_INPUT};
+    use crate::psbt::Psbt;
+    use crate::script::Script;
+    use crate::transaction::{OutPoint, ScriptPubKey, TxHash, TxOutput};
+    use crate::wallet::{PrivateKey, U256};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use sha2::{Digest, Sha256};
+    use std::str::FromStr;
+
+    fn out_point(txid: &str, vout: u32) -> OutPoint {
+        OutPoint::new(TxHash::from_str(txid).unwrap(), vout)
+    }
+
+    fn script(content: &[u8]) -> ScriptPubKey {
+        ScriptPubKey {
+            content: content.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_sort_bip69() {
+        let a = "0e53ec5dfb2cb8a71fec32dc9a634a35b7e24799295ddd5278217822e0b31f57";
+        let b = "26aa6e6d8b9e49bb0630aac301db6757c02e3619feb4ee0eea81eb1672947024";
+        let c = "7d037ceb2ee0dc03e82f17be7935d238b35d1deabf953a892a4507bfbeeb3ba4";
+
+        let builder = TransactionBuilder::new()
+            .add_input(out_point(c, 0), 0xffff_fffd)
+            .add_input(out_point(a, 1), 0xffff_fffd)
+            .add_input(out_point(a, 0), 0xffff_fffd)
+            .add_input(out_point(b, 7), 0xffff_fffd)
+            .add_output(script(&hex!("0014bb")), 2000)
+            .add_output(script(&hex!("0014aa")), 2000)
+            .add_output(script(&hex!("0014")), 5000)
+            .add_output(script(&hex!("0014ff")), 100);
+        assert!(!builder.clone().build().is_bip69_sorted());
+
+        let tx = builder.sort_bip69().build();
+        assert!(tx.is_bip69_sorted());
+        let inputs: Vec<OutPoint> = tx.inputs.iter().map(|input| input.out_point()).collect();
+        assert_eq!(
+            inputs,
+            vec![
+                out_point(a, 0),
+                out_point(a, 1),
+                out_point(b, 7),
+                out_point(c, 0)
+            ]
+        );
+        let outputs: Vec<(u64, Vec<u8>)> = tx
+            .outputs
+            .iter()
+            .map(|output| {
+                (
+                    u64::from(output.amount),
+                    output.script_pub_key.content.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            outputs,
+            vec![
+                (100, hex!("0014ff").to_vec()),
+                (2000, hex!("0014aa").to_vec()),
+                (2000, hex!("0014bb").to_vec()),
+                (5000, hex!("0014").to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_change() {
+        let mut options = BuilderOptions::default();
+        assert_eq!(options.split_change(100_000), vec![100_000]);
+        assert_eq!(options.split_change(545), Vec::<u64>::new());
+
+        options.change_policy = ChangePolicy::Split(3);
+        assert_eq!(options.split_change(100_000), vec![33_334, 33_333, 33_333]);
+        // no part is left below the dust limit
+        assert_eq!(options.split_change(1_200), vec![600, 600]);
+
+        options.change_policy = ChangePolicy::MaxOutput(40_000);
+        assert_eq!(options.split_change(100_000), vec![33_334, 33_333, 33_333]);
+        assert_eq!(options.split_change(40_000), vec![40_000]);
+    }
+
+    #[test]
+    fn test_batch_and_change() {
+        let change = script(&hex!("0014cc"));
+        let recipients: Vec<(ScriptPubKey, u64)> = (0..4u8)
+            .map(|i| (script(&[0x00, 0x14, i]), 10_000 * (i as u64 + 1)))
+            .collect();
+        let builder = TransactionBuilder::new()
+            .add_input(
+                out_point(
+                    "0e53ec5dfb2cb8a71fec32dc9a634a35b7e24799295ddd5278217822e0b31f57",
+                    0,
+                ),
+                0xffff_fffd,
+            )
+            .add_recipients(recipients.clone())
+            .change(change.clone(), 90_000)
+            .options(BuilderOptions {
+                change_policy: ChangePolicy::Split(2),
+                ..BuilderOptions::default()
+            });
+
+        let mut positions = std::collections::HashSet::new();
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..20 {
+            let tx = builder.clone().build_with_rng(&mut rng);
+            assert_eq!(tx.outputs.len(), 6);
+            let paid: Vec<(ScriptPubKey, u64)> = tx
+                .outputs
+                .iter()
+                .filter(|output| output.script_pub_key != change)
+                .map(|output| (output.script_pub_key.clone(), u64::from(output.amount)))
+                .collect();
+            // recipients keep their order around the change
+            assert_eq!(paid, recipients);
+            let change_outputs: Vec<usize> = (0..6)
+                .filter(|i| tx.outputs[*i].script_pub_key == change)
+                .collect();
+            assert_eq!(change_outputs.len(), 2);
+            positions.insert(change_outputs);
+        }
+        assert!(positions.len() > 1);
+
+        let tx = builder
+            .options(BuilderOptions {
+                randomize_change: false,
+                ..BuilderOptions::default()
+            })
+            .build();
+        assert_eq!(tx.outputs[4].script_pub_key, change);
+        assert_eq!(u64::from(tx.outputs[4].amount), 90_000);
+    }
+
+    #[test]
+    fn test_estimated_fee() {
+        let alice = PrivateKey::new(U256::from(0xa11ce_u32));
+        let bob = PrivateKey::new(U256::from(0xb0b_u32));
+        let mut witness_script = vec![0x52, 0x21];
+        witness_script.extend_from_slice(&alice.point.compressed_sec());
+        witness_script.push(0x21);
+        witness_script.extend_from_slice(&bob.point.compressed_sec());
+        witness_script.extend_from_slice(&[0x52, 0xae]);
+        let multisig = Script::parse_lossy(&witness_script).0;
+        let mut program = vec![0x00, 0x20];
+        program.extend_from_slice(&Sha256::digest(&witness_script));
+
+        let txid = TxHash::new(&[5; 32]).unwrap().1;
+        let spent = vec![
+            (alice.point.p2wpkh_script(), P2WPKH_INPUT),
+            (bob.point.p2pkh_script(), P2PKH_INPUT),
+            (alice.point.p2sh_p2wpkh_script(), P2SH_P2WPKH_INPUT),
+            (script(&program), InputWeight::p2wsh(&multisig).unwrap()),
+        ];
+        let builder = spent
+            .iter()
+            .enumerate()
+            .fold(TransactionBuilder::new(), |builder, (vout, (_, weight))| {
+                builder.add_weighted_input(OutPoint::new(txid, vout as u32), 0xffff_fffd, *weight)
+            })
+            .add_output(script(&[0x51]), 100_000)
+            .change(bob.point.p2wpkh_script(), 50_000);
+        assert!(TransactionBuilder::new()
+            .add_input(OutPoint::new(txid, 0), 0xffff_fffd)
+            .estimated_weight()
+            .is_none());
+        let vsize = builder.estimated_vsize().unwrap();
+        assert_eq!(builder.estimated_fee(5.0), Some(vsize as u64 * 5));
+
+        let mut psbt = Psbt::new(builder.build()).unwrap();
+        for (index, (script_pub_key, _)) in spent.iter().enumerate() {
+            psbt.inputs[index].witness_utxo = Some(TxOutput {
+                amount: 100_000.into(),
+                script_pub_key: script_pub_key.clone(),
+            });
+        }
+        psbt.inputs[2].redeem_script = Some(alice.point.p2wpkh_script().content);
+        psbt.inputs[3].witness_script = Some(witness_script.clone());
+        psbt.sign(0, &alice).unwrap();
+        psbt.sign(1, &bob).unwrap();
+        psbt.sign(2, &alice).unwrap();
+        psbt.sign(3, &alice).unwrap();
+        psbt.sign(3, &bob).unwrap();
+        psbt.finalize().unwrap();
+        let tx = psbt.extract().unwrap();
+        // a signature a byte shorter than the 72 assumed is the only difference
+        assert!(tx.vsize() <= vsize && tx.vsize() + 2 >= vsize);
+    }
+
+    #[test]
+    fn test_anti_fee_sniping() {
+        let builder = TransactionBuilder::new()
+            .add_input(
+                out_point(
+                    "0e53ec5dfb2cb8a71fec32dc9a634a35b7e24799295ddd5278217822e0b31f57",
+                    0,
+                ),
+                0xffff_fffd,
+            )
+            .add_output(script(&hex!("0014aa")), 1_000);
+        assert_eq!(u32::from(builder.clone().build().locktime), 0);
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let locktimes: Vec<u32> = (0..200)
+            .map(|_| {
+                let tx = builder.clone().tip_height(800_000).build_with_rng(&mut rng);
+                u32::from(tx.locktime)
+            })
+            .collect();
+        assert!(locktimes
+            .iter()
+            .all(|locktime| (799_901..=800_000).contains(locktime)));
+        let backed_off = locktimes.iter().filter(|l| **l < 800_000).count();
+        assert!(backed_off > 0 && backed_off < 50);
+
+        let tx = builder.tip_height(800_000).locktime(12).build();
+        assert_eq!(u32::from(tx.locktime), 12);
+    }
+}
@@ -0,0 +1,535 @@
+//! Elements / Liquid confidential transactions, range proofs and surjection proofs are
+//! kept as opaque blobs.
+
+use bytes::{BufMut, BytesMut};
+use nom::bytes::streaming::take;
+use nom::multi::count;
+use nom::number::complete::le_u8;
+use nom::IResult;
+
+use super::locktime::TxLocktime;
+use super::tx_input::{PreTxIndex, ScriptSig, TxHash, TxInputSequence};
+use super::tx_output::ScriptPubKey;
+use super::tx_version::TxVersion;
+use super::Varint;
+use crate::wallet::{hash256, Hex};
+
+const OUTPOINT_ISSUANCE_FLAG: u32 = 0x8000_0000;
+const OUTPOINT_PEGIN_FLAG: u32 = 0x4000_0000;
+const OUTPOINT_INDEX_MASK: u32 = 0x3fff_ffff;
+
+/// 1 byte prefix then either nothing, the explicit data or a 33 bytes commitment
+fn parse_confidential<'a>(
+    input: &'a [u8],
+    explicit_len: usize,
+    commitment_prefixes: &[u8],
+) -> IResult<&'a [u8], (u8, Vec<u8>)> {
+    let (input, prefix) = le_u8(input)?;
+    let len = match prefix {
+        0 => 0,
+        1 => explicit_len,
+        p if commitment_prefixes.contains(&p) => 32,
+        _ => return Err(nom::Err::Error((input, nom::error::ErrorKind::Tag))),
+    };
+    let (input, data) = take(len)(input)?;
+    Ok((input, (prefix, data.to_vec())))
+}
+
+fn serialize_confidential(buf: &mut BytesMut, prefix: u8, data: &[u8]) {
+    buf.put_u8(prefix);
+    buf.put(data);
+}
+
+fn to_commitment(prefix: u8, data: &[u8]) -> [u8; 33] {
+    let mut commitment = [0u8; 33];
+    commitment[0] = prefix;
+    commitment[1..33].copy_from_slice(data);
+    commitment
+}
+
+fn to_array(data: &[u8]) -> [u8; 32] {
+    let mut array = [0u8; 32];
+    array.copy_from_slice(data);
+    array
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum ConfidentialValue {
+    Null,
+    Explicit(u64),
+    /// Pedersen commitment, prefix 0x08 or 0x09
+    Confidential([u8; 33]),
+}
+
+impl ConfidentialValue {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, (prefix, data)) = parse_confidential(input, 8, &[0x08, 0x09])?;
+        let value = match prefix {
+            0 => ConfidentialValue::Null,
+            1 => {
+                let mut value = [0u8; 8];
+                value.copy_from_slice(&data);
+                ConfidentialValue::Explicit(u64::from_be_bytes(value))
+            }
+            _ => ConfidentialValue::Confidential(to_commitment(prefix, &data)),
+        };
+        Ok((input, value))
+    }
+
+    fn serialize_into(&self, buf: &mut BytesMut) {
+        match self {
+            ConfidentialValue::Null => buf.put_u8(0),
+            ConfidentialValue::Explicit(value) => {
+                buf.put_u8(1);
+                buf.put_u64_be(*value);
+            }
+            ConfidentialValue::Confidential(c) => serialize_confidential(buf, c[0], &c[1..]),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum ConfidentialAsset {
+    Null,
+    Explicit([u8; 32]),
+    /// Blinded asset generator, prefix 0x0a or 0x0b
+    Confidential([u8; 33]),
+}
+
+impl ConfidentialAsset {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, (prefix, data)) = parse_confidential(input, 32, &[0x0a, 0x0b])?;
+        let asset = match prefix {
+            0 => ConfidentialAsset::Null,
+            1 => ConfidentialAsset::Explicit(to_array(&data)),
+            _ => ConfidentialAsset::Confidential(to_commitment(prefix, &data)),
+        };
+        Ok((input, asset))
+    }
+
+    fn serialize_into(&self, buf: &mut BytesMut) {
+        match self {
+            ConfidentialAsset::Null => buf.put_u8(0),
+            ConfidentialAsset::Explicit(asset) => serialize_confidential(buf, 1, asset),
+            ConfidentialAsset::Confidential(c) => serialize_confidential(buf, c[0], &c[1..]),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum ConfidentialNonce {
+    Null,
+    Explicit([u8; 32]),
+    /// ECDH public key used to rewind the range proof, prefix 0x02 or 0x03
+    Confidential([u8; 33]),
+}
+
+impl ConfidentialNonce {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, (prefix, data)) = parse_confidential(input, 32, &[0x02, 0x03])?;
+        let nonce = match prefix {
+            0 => ConfidentialNonce::Null,
+            1 => ConfidentialNonce::Explicit(to_array(&data)),
+            _ => ConfidentialNonce::Confidential(to_commitment(prefix, &data)),
+        };
+        Ok((input, nonce))
+    }
+
+    fn serialize_into(&self, buf: &mut BytesMut) {
+        match self {
+            ConfidentialNonce::Null => buf.put_u8(0),
+            ConfidentialNonce::Explicit(nonce) => serialize_confidential(buf, 1, nonce),
+            ConfidentialNonce::Confidential(c) => serialize_confidential(buf, c[0], &c[1..]),
+        }
+    }
+}
+
+/// Varint length prefixed blob
+fn parse_bytes(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
+    let (input, len) = Varint::parse(input)?;
+    let (input, data) = take(Into::<u64>::into(len))(input)?;
+    Ok((input, data.to_vec()))
+}
+
+fn serialize_bytes(buf: &mut BytesMut, data: &[u8]) {
+    buf.put(Varint::encode(data.len() as u64).unwrap());
+    buf.put(data);
+}
+
+fn parse_witness_stack(input: &[u8]) -> IResult<&[u8], Vec<Vec<u8>>> {
+    let (input, items) = Varint::parse(input)?;
+    count(parse_bytes, Into::<u64>::into(items) as usize)(input)
+}
+
+fn serialize_witness_stack(buf: &mut BytesMut, stack: &[Vec<u8>]) {
+    buf.put(Varint::encode(stack.len() as u64).unwrap());
+    stack.iter().for_each(|item| serialize_bytes(buf, item));
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct AssetIssuance {
+    pub asset_blinding_nonce: [u8; 32],
+    pub asset_entropy: [u8; 32],
+    pub amount: ConfidentialValue,
+    pub inflation_keys: ConfidentialValue,
+}
+
+impl AssetIssuance {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, asset_blinding_nonce) = take(32usize)(input)?;
+        let (input, asset_entropy) = take(32usize)(input)?;
+        let (input, amount) = ConfidentialValue::parse(input)?;
+        let (input, inflation_keys) = ConfidentialValue::parse(input)?;
+        Ok((
+            input,
+            AssetIssuance {
+                asset_blinding_nonce: to_array(asset_blinding_nonce),
+                asset_entropy: to_array(asset_entropy),
+                amount,
+                inflation_keys,
+            },
+        ))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Default)]
+pub struct InputWitness {
+    pub amount_range_proof: Vec<u8>,
+    pub inflation_keys_range_proof: Vec<u8>,
+    pub script_witness: Vec<Vec<u8>>,
+    pub pegin_witness: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct ElementsTxInput {
+    pub pre_tx_id: TxHash,
+    pub pre_tx_index: PreTxIndex,
+    pub is_pegin: bool,
+    pub script_sig: ScriptSig,
+    pub sequence: TxInputSequence,
+    pub asset_issuance: Option<AssetIssuance>,
+    pub witness: InputWitness,
+}
+
+impl ElementsTxInput {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, pre_tx_id) = TxHash::parse(input)?;
+        let (input, index) = PreTxIndex::parse(input)?;
+        let index = u32::from(index);
+        let (input, script_sig) = ScriptSig::parse(input)?;
+        let (input, sequence) = TxInputSequence::parse(input)?;
+
+        // the coinbase index 0xffffffff carries no flags
+        let flags = if index == 0xffff_ffff { 0 } else { index };
+        let (input, asset_issuance) = if flags & OUTPOINT_ISSUANCE_FLAG != 0 {
+            let (input, issuance) = AssetIssuance::parse(input)?;
+            (input, Some(issuance))
+        } else {
+            (input, None)
+        };
+        let pre_tx_index = if index == 0xffff_ffff {
+            index
+        } else {
+            index & OUTPOINT_INDEX_MASK
+        };
+
+        Ok((
+            input,
+            ElementsTxInput {
+                pre_tx_id,
+                pre_tx_index: PreTxIndex::new(pre_tx_index),
+                is_pegin: flags & OUTPOINT_PEGIN_FLAG != 0,
+                script_sig,
+                sequence,
+                asset_issuance,
+                witness: InputWitness::default(),
+            },
+        ))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(32 + 4 + 9 + self.script_sig.content.len() + 4 + 150);
+        buf.put(&self.pre_tx_id.to_little_endian());
+        let mut index = self.pre_tx_index.index();
+        if self.asset_issuance.is_some() {
+            index |= OUTPOINT_ISSUANCE_FLAG;
+        }
+        if self.is_pegin {
+            index |= OUTPOINT_PEGIN_FLAG;
+        }
+        buf.put_u32_le(index);
+        buf.put(&self.script_sig.serialize());
+        buf.put_u32_le(self.sequence.sequence());
+        if let Some(issuance) = &self.asset_issuance {
+            buf.put(&issuance.asset_blinding_nonce[..]);
+            buf.put(&issuance.asset_entropy[..]);
+            issuance.amount.serialize_into(&mut buf);
+            issuance.inflation_keys.serialize_into(&mut buf);
+        }
+        buf.take().to_vec()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Default)]
+pub struct OutputWitness {
+    pub surjection_proof: Vec<u8>,
+    pub range_proof: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct ElementsTxOutput {
+    pub asset: ConfidentialAsset,
+    pub value: ConfidentialValue,
+    pub nonce: ConfidentialNonce,
+    pub script_pub_key: ScriptPubKey,
+    pub witness: OutputWitness,
+}
+
+impl ElementsTxOutput {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, asset) = ConfidentialAsset::parse(input)?;
+        let (input, value) = ConfidentialValue::parse(input)?;
+        let (input, nonce) = ConfidentialNonce::parse(input)?;
+        let (input, script_pub_key) = ScriptPubKey::parse(input)?;
+        Ok((
+            input,
+            ElementsTxOutput {
+                asset,
+                value,
+                nonce,
+                script_pub_key,
+                witness: OutputWitness::default(),
+            },
+        ))
+    }
+
+    /// An output with an empty script pays the transaction fee
+    pub fn is_fee(&self) -> bool {
+        self.script_pub_key.content.is_empty()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(33 * 3 + 9 + self.script_pub_key.content.len());
+        self.asset.serialize_into(&mut buf);
+        self.value.serialize_into(&mut buf);
+        self.nonce.serialize_into(&mut buf);
+        buf.put(self.script_pub_key.serialize());
+        buf.take().to_vec()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct ElementsTransaction {
+    pub version: TxVersion,
+    pub inputs: Vec<ElementsTxInput>,
+    pub outputs: Vec<ElementsTxOutput>,
+    pub locktime: TxLocktime,
+}
+
+impl ElementsTransaction {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, version) = TxVersion::parse(input)?;
+        let (input, flags) = le_u8(input)?;
+
+        let (input, inputs_num) = Varint::parse(input)?;
+        let (input, mut inputs) = count(
+            ElementsTxInput::parse,
+            Into::<u64>::into(inputs_num) as usize,
+        )(input)?;
+        let (input, outputs_num) = Varint::parse(input)?;
+        let (mut input, mut outputs) = count(
+            ElementsTxOutput::parse,
+            Into::<u64>::into(outputs_num) as usize,
+        )(input)?;
+        let (rest, locktime) = TxLocktime::parse(input)?;
+        input = rest;
+
+        if flags & 1 == 1 {
+            for tx_input in inputs.iter_mut() {
+                let (rest, amount_range_proof) = parse_bytes(input)?;
+                let (rest, inflation_keys_range_proof) = parse_bytes(rest)?;
+                let (rest, script_witness) = parse_witness_stack(rest)?;
+                let (rest, pegin_witness) = parse_witness_stack(rest)?;
+                tx_input.witness = InputWitness {
+                    amount_range_proof,
+                    inflation_keys_range_proof,
+                    script_witness,
+                    pegin_witness,
+                };
+                input = rest;
+            }
+            for tx_output in outputs.iter_mut() {
+                let (rest, surjection_proof) = parse_bytes(input)?;
+                let (rest, range_proof) = parse_bytes(rest)?;
+                tx_output.witness = OutputWitness {
+                    surjection_proof,
+                    range_proof,
+                };
+                input = rest;
+            }
+        }
+
+        Ok((
+            input,
+            ElementsTransaction {
+                version,
+                inputs,
+                outputs,
+                locktime,
+            },
+        ))
+    }
+
+    pub fn has_witness(&self) -> bool {
+        self.inputs
+            .iter()
+            .any(|i| i.witness != InputWitness::default())
+            || self
+                .outputs
+                .iter()
+                .any(|o| o.witness != OutputWitness::default())
+    }
+
+    fn serialize_with(&self, witness: bool) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(1024);
+        buf.put_u32_le(u32::from(self.version));
+        buf.put_u8(witness as u8);
+
+        buf.put(Varint::encode(self.inputs.len() as u64).unwrap());
+        self.inputs.iter().for_each(|i| buf.put(i.serialize()));
+        buf.put(Varint::encode(self.outputs.len() as u64).unwrap());
+        self.outputs.iter().for_each(|o| buf.put(o.serialize()));
+        buf.put_u32_le(u32::from(self.locktime));
+
+        if witness {
+            for i in &self.inputs {
+                serialize_bytes(&mut buf, &i.witness.amount_range_proof);
+                serialize_bytes(&mut buf, &i.witness.inflation_keys_range_proof);
+                serialize_witness_stack(&mut buf, &i.witness.script_witness);
+                serialize_witness_stack(&mut buf, &i.witness.pegin_witness);
+            }
+            for o in &self.outputs {
+                serialize_bytes(&mut buf, &o.witness.surjection_proof);
+                serialize_bytes(&mut buf, &o.witness.range_proof);
+            }
+        }
+        buf.take().to_vec()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.serialize_with(self.has_witness())
+    }
+
+    /// Like bitcoin the txid does not commit to the witness
+    pub fn id(&self) -> TxHash {
+        let hash = hash256(&self.serialize_with(false));
+        TxHash::parse(&hash).expect("hash256 is 32 bytes").1
+    }
+
+    /// Sum of the explicit fee outputs
+    pub fn fee(&self) -> u64 {
+        self.outputs
+            .iter()
+            .filter(|o| o.is_fee())
+            .filter_map(|o| match o.value {
+                ConfidentialValue::Explicit(value) => Some(value),
+                _ => None,
+            })
+            .sum()
+    }
+}
+
+impl Hex for ElementsTransaction {
+    fn hex(&self) -> String {
+        hex::encode(self.serialize())
+    }
+}
+
+mod test {
+    use super::{ConfidentialAsset, ConfidentialNonce, ConfidentialValue, ElementsTransaction};
+    use crate::wallet::Hex;
+
+    // one input spending output 1, a confidential output, an explicit fee output and witness
+    const TX: &str = concat!(
+        "02000000",
+        "01",
+        "01",
+        "d1c789a9c60383bf715f3f6ad9d14b91fe55f3deb369fe5d9280cb1a01793f81",
+        "01000000",
+        "00",
+        "fdffffff",
+        "02",
+        "0a6d6b8d80a3b2d9ad7bf05f2f6ac8b3ea4f1a4e0bfb1ec89c79d0e13c14d4df6c",
+        "08d8d0ec9de1fd8b8f2f04f02e7f1c9eeee5a0e26fbdd4c2adee7d0cf6b1bb37b2",
+        "03a8fd0e7f2ed3c49ab3e5ebc0c4b38e1b6bfc8a98d6b0a8b8ac1e8d2ba2b7ed92",
+        "160014e8df018c7e326cc253faac7e46cdc51e68542c42",
+        "016d521c38ec1ea15734ae22b7c46064412829c0d0579f0a713d1c04ede979026f",
+        "01000000000000012c",
+        "00",
+        "00",
+        "00000000",
+        "00",
+        "00",
+        "02",
+        "02aabb",
+        "01cc",
+        "00",
+        "03ddeeff",
+        "0411223344",
+        "00",
+        "00"
+    );
+
+    #[test]
+    fn test_parse_elements_tx() {
+        let data = hex::decode(TX).unwrap();
+        let (rest, tx) = ElementsTransaction::parse(&data).unwrap();
+        assert!(rest.is_empty());
+
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(u32::from(tx.inputs[0].pre_tx_index), 1);
+        assert_eq!(tx.inputs[0].asset_issuance, None);
+        assert_eq!(
+            tx.inputs[0].witness.script_witness,
+            vec![vec![0xaa, 0xbb], vec![0xcc]]
+        );
+
+        assert_eq!(tx.outputs.len(), 2);
+        match tx.outputs[0].value {
+            ConfidentialValue::Confidential(c) => assert_eq!(c[0], 0x08),
+            _ => panic!("expected a value commitment"),
+        }
+        match tx.outputs[0].asset {
+            ConfidentialAsset::Confidential(c) => assert_eq!(c[0], 0x0a),
+            _ => panic!("expected an asset commitment"),
+        }
+        match tx.outputs[0].nonce {
+            ConfidentialNonce::Confidential(c) => assert_eq!(c[0], 0x03),
+            _ => panic!("expected an ecdh nonce"),
+        }
+        assert_eq!(
+            tx.outputs[0].witness.surjection_proof,
+            vec![0xdd, 0xee, 0xff]
+        );
+        assert_eq!(
+            tx.outputs[0].witness.range_proof,
+            vec![0x11, 0x22, 0x33, 0x44]
+        );
+        assert!(tx.outputs[1].is_fee());
+        assert_eq!(tx.fee(), 300);
+
+        assert_eq!(tx.hex(), TX.to_string());
+    }
+
+    #[test]
+    fn test_elements_txid_ignores_witness() {
+        let data = hex::decode(TX).unwrap();
+        let (_, tx) = ElementsTransaction::parse(&data).unwrap();
+        let mut stripped = tx.clone();
+        stripped.inputs[0].witness = Default::default();
+        stripped.outputs[0].witness = Default::default();
+
+        assert_ne!(stripped.serialize(), tx.serialize());
+        assert_eq!(stripped.id(), tx.id());
+    }
+}
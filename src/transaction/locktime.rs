@@ -2,6 +2,9 @@ use nom::number::complete::le_u32;
 use nom::IResult;
 use std::fmt::Display;
 
+/// Locktimes below this are block heights, from it on unix timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
 #[derive(Debug, PartialOrd, PartialEq, Clone, Hash)]
 pub struct TxLocktime(u32);
 impl Copy for TxLocktime {}
@@ -27,6 +30,35 @@ impl TxLocktime {
     pub fn new(locktime: u32) -> Self {
         TxLocktime(locktime)
     }
+
+    pub fn kind(&self) -> LocktimeKind {
+        match self.0 {
+            0 => LocktimeKind::Unlocked,
+            height if height < LOCKTIME_THRESHOLD => LocktimeKind::Height(height),
+            time => LocktimeKind::Time(time),
+        }
+    }
+}
+
+/// What a locktime waits for
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum LocktimeKind {
+    Unlocked,
+    /// Minable in the block after this height
+    Height(u32),
+    /// Minable once the median time past is after this unix timestamp
+    Time(u32),
+}
+impl Copy for LocktimeKind {}
+
+impl Display for LocktimeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocktimeKind::Unlocked => write!(f, "none"),
+            LocktimeKind::Height(height) => write!(f, "after block {}", height),
+            LocktimeKind::Time(time) => write!(f, "after unix time {}", time),
+        }
+    }
 }
 
 impl From<TxLocktime> for u32 {
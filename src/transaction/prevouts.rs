@@ -0,0 +1,260 @@
+use failure::Error;
+use std::collections::{HashMap, HashSet};
+
+use super::{OutPoint, Transaction, TxBackend, TxHash, TxOutput};
+
+/// Lookups one `fetch_many` call asks for
+const DEFAULT_BATCH_SIZE: usize = 100;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum PrevoutError {
+    #[fail(display = "backend returned {} for {}", got, wanted)]
+    WrongTransaction { wanted: TxHash, got: TxHash },
+    #[fail(display = "{} does not exist", _0)]
+    MissingOutput(OutPoint),
+}
+
+/// A transaction with the outputs its inputs spend
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedTx {
+    pub tx: Transaction,
+    /// In input order, empty for a coinbase
+    pub prevouts: Vec<TxOutput>,
+}
+
+impl ResolvedTx {
+    pub fn input_value(&self) -> u64 {
+        self.prevouts
+            .iter()
+            .map(|output| u64::from(output.amount))
+            .sum()
+    }
+
+    /// None for a coinbase, which spends nothing
+    pub fn fee(&self) -> Option<u64> {
+        if self.tx.is_coinbase() {
+            return None;
+        }
+        let output_value: u64 = self
+            .tx
+            .outputs
+            .iter()
+            .map(|output| u64::from(output.amount))
+            .sum();
+        self.input_value().checked_sub(output_value)
+    }
+
+    /// sat/vB
+    pub fn fee_rate(&self) -> Option<f64> {
+        Some(self.fee()? as f64 / self.tx.vsize() as f64)
+    }
+
+    /// BIP141 sigop cost with the P2SH and witness sigops
+    pub fn sigop_cost(&self) -> usize {
+        self.tx.total_sigops(&self.prevouts)
+    }
+}
+
+/// Spent outputs of many transactions for fee and fee rate statistics. The previous
+/// transactions are looked up in batches, each txid once, and transactions of the same
+/// call spending each other need no lookup at all.
+pub struct PrevoutResolver {
+    batch_size: usize,
+    /// Outputs already seen, so overlapping calls skip their lookups
+    outputs: HashMap<OutPoint, TxOutput>,
+    lookups: usize,
+}
+
+impl Default for PrevoutResolver {
+    fn default() -> Self {
+        PrevoutResolver {
+            batch_size: DEFAULT_BATCH_SIZE,
+            outputs: HashMap::new(),
+            lookups: 0,
+        }
+    }
+}
+
+impl PrevoutResolver {
+    pub fn new() -> Self {
+        PrevoutResolver::default()
+    }
+
+    /// Txids asked for in one backend call
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Previous transactions looked up so far
+    pub fn lookups(&self) -> usize {
+        self.lookups
+    }
+
+    /// Attach to every transaction of `txs` the outputs its inputs spend
+    pub fn resolve<B: TxBackend>(
+        &mut self,
+        txs: Vec<Transaction>,
+        backend: &mut B,
+        testnet: bool,
+    ) -> Result<Vec<ResolvedTx>, Error> {
+        for tx in &txs {
+            self.remember(tx);
+        }
+        let mut seen = HashSet::new();
+        let wanted: Vec<TxHash> = txs
+            .iter()
+            .filter(|tx| !tx.is_coinbase())
+            .flat_map(|tx| tx.inputs.iter().map(|input| input.out_point()))
+            .filter(|out_point| !self.outputs.contains_key(out_point))
+            .map(|out_point| out_point.txid)
+            .filter(|txid| seen.insert(*txid))
+            .collect();
+        for batch in wanted.chunks(self.batch_size) {
+            let fetched = backend.fetch_many(batch, testnet)?;
+            for (txid, tx) in batch.iter().zip(&fetched) {
+                if tx.id() != *txid {
+                    return Err(PrevoutError::WrongTransaction {
+                        wanted: *txid,
+                        got: tx.id(),
+                    }
+                    .into());
+                }
+                self.remember(tx);
+            }
+            self.lookups += batch.len();
+        }
+
+        txs.into_iter()
+            .map(|tx| {
+                let prevouts = if tx.is_coinbase() {
+                    vec![]
+                } else {
+                    tx.inputs
+                        .iter()
+                        .map(|input| {
+                            let out_point = input.out_point();
+                            self.outputs
+                                .get(&out_point)
+                                .cloned()
+                                .ok_or(PrevoutError::MissingOutput(out_point))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?
+                };
+                Ok(ResolvedTx { tx, prevouts })
+            })
+            .collect()
+    }
+
+    fn remember(&mut self, tx: &Transaction) {
+        let txid = tx.id();
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            self.outputs
+                .insert(OutPoint::new(txid, vout as u32), output.clone());
+        }
+    }
+}
+
+mod test {
+    use super::{PrevoutError, PrevoutResolver};
+    use crate::transaction::{
+        OutPoint, ScriptPubKey, Transaction, TransactionBuilder, TxBackend, TxHash,
+    };
+    use failure::Error;
+
+    /// Serves known transactions and counts the calls
+    struct MockTxs {
+        txs: Vec<Transaction>,
+        calls: usize,
+    }
+
+    impl TxBackend for MockTxs {
+        fn fetch_many(
+            &mut self,
+            tx_ids: &[TxHash],
+            _testnet: bool,
+        ) -> Result<Vec<Transaction>, Error> {
+            self.calls += 1;
+            Ok(tx_ids
+                .iter()
+                .map(|tx_id| {
+                    self.txs
+                        .iter()
+                        .find(|tx| tx.id() == *tx_id)
+                        .unwrap()
+                        .clone()
+                })
+                .collect())
+        }
+    }
+
+    fn pay(out_points: &[OutPoint], amounts: &[u64]) -> Transaction {
+        let builder = out_points
+            .iter()
+            .fold(TransactionBuilder::new(), |builder, out_point| {
+                builder.add_input(*out_point, 0xffff_fffd)
+            });
+        amounts
+            .iter()
+            .fold(builder, |builder, amount| {
+                builder.add_output(
+                    ScriptPubKey {
+                        content: vec![0x51],
+                    },
+                    *amount,
+                )
+            })
+            .build()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let outside = OutPoint::new(TxHash::new(&[1; 32]).unwrap().1, 0);
+        let funding: Vec<Transaction> = (0..5)
+            .map(|i| pay(&[OutPoint::new(outside.txid, i)], &[10_000, 20_000]))
+            .collect();
+        // two spends of each funding transaction, the parent of the last one is in the set
+        let mut txs: Vec<Transaction> = funding
+            .iter()
+            .flat_map(|tx| {
+                vec![
+                    pay(&[OutPoint::new(tx.id(), 0)], &[9_000]),
+                    pay(&[OutPoint::new(tx.id(), 1)], &[19_500]),
+                ]
+            })
+            .collect();
+        let parent = txs[0].clone();
+        txs.push(pay(&[OutPoint::new(parent.id(), 0)], &[8_000]));
+
+        let mut backend = MockTxs {
+            txs: funding,
+            calls: 0,
+        };
+        let mut resolver = PrevoutResolver::new().batch_size(2);
+        let resolved = resolver.resolve(txs, &mut backend, false).unwrap();
+        assert_eq!(resolver.lookups(), 5);
+        assert_eq!(backend.calls, 3);
+        assert_eq!(resolved[0].fee(), Some(1_000));
+        assert_eq!(resolved[1].fee(), Some(500));
+        assert_eq!(resolved[10].input_value(), 9_000);
+        assert_eq!(resolved[10].fee(), Some(1_000));
+        let rate = resolved[0].fee_rate().unwrap();
+        assert!((rate - 1_000.0 / resolved[0].tx.vsize() as f64).abs() < 1e-9);
+
+        // outputs already seen need no lookup
+        let again = pay(&[OutPoint::new(parent.id(), 0)], &[7_000]);
+        resolver.resolve(vec![again], &mut backend, false).unwrap();
+        assert_eq!(backend.calls, 3);
+
+        // the parent is looked up again for an output it does not have
+        backend.txs.push(parent.clone());
+        let missing = pay(&[OutPoint::new(parent.id(), 5)], &[1]);
+        let err = resolver
+            .resolve(vec![missing], &mut backend, false)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<PrevoutError>().unwrap(),
+            PrevoutError::MissingOutput(OutPoint::new(parent.id(), 5))
+        );
+    }
+}
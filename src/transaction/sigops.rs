@@ -0,0 +1,154 @@
+use super::{ScriptPubKey, ScriptPubKeyType, Transaction, TxOutput};
+use crate::script::Script;
+
+/// Consensus limit on the sigop cost of a block
+pub const MAX_BLOCK_SIGOPS_COST: usize = 80_000;
+/// Legacy and P2SH sigops cost this many witness sigops, like bytes and weight
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+
+fn sigops(content: &[u8], accurate: bool) -> usize {
+    Script::parse_lossy(content).0.sigop_count(accurate)
+}
+
+impl Transaction {
+    /// Sigops of the script sigs and script pubkeys, the ones a block limits without
+    /// looking at the spent outputs
+    pub fn legacy_sigops(&self) -> usize {
+        self.inputs
+            .iter()
+            .map(|input| sigops(&input.script_sig.content, false))
+            .chain(
+                self.outputs
+                    .iter()
+                    .map(|output| sigops(&output.script_pub_key.content, false)),
+            )
+            .sum()
+    }
+
+    /// BIP141 sigop cost: legacy and P2SH sigops at four times, witness sigops once. The
+    /// P2SH and witness sigops of an input are counted when its spent output is in
+    /// `prevouts`, given in input order.
+    pub fn total_sigops(&self, prevouts: &[TxOutput]) -> usize {
+        let mut cost = self.legacy_sigops() * WITNESS_SCALE_FACTOR;
+        if self.is_coinbase() {
+            return cost;
+        }
+        for (input, prevout) in self.inputs.iter().zip(prevouts) {
+            let script_pub_key = &prevout.script_pub_key;
+            let mut program = script_pub_key
+                .witness_program()
+                .map(|(version, program)| (version, program.to_vec()));
+            if script_pub_key.script_type() == ScriptPubKeyType::ScriptHash {
+                let script_sig = Script::parse_lossy(&input.script_sig.content).0;
+                if let Some(redeem) = script_sig.last_push().filter(|_| script_sig.is_push_only()) {
+                    cost += sigops(redeem, true) * WITNESS_SCALE_FACTOR;
+                    // nested segwit, the redeem script is the witness program
+                    program = ScriptPubKey {
+                        content: redeem.to_vec(),
+                    }
+                    .witness_program()
+                    .map(|(version, program)| (version, program.to_vec()));
+                }
+            }
+            cost += match program {
+                Some((0, ref program)) if program.len() == 20 => 1,
+                Some((0, ref program)) if program.len() == 32 => input
+                    .witness
+                    .last()
+                    .map_or(0, |witness_script| sigops(witness_script, true)),
+                _ => 0,
+            };
+        }
+        cost
+    }
+}
+
+mod test {
+    use crate::script::Script;
+    use crate::transaction::{
+        PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxHash, TxInput, TxInputSequence,
+        TxLocktime, TxOutput, TxVersion,
+    };
+    use crate::wallet::hash160;
+    use sha2::{Digest, Sha256};
+
+    fn output(content: Vec<u8>) -> TxOutput {
+        TxOutput {
+            amount: 1000.into(),
+            script_pub_key: ScriptPubKey { content },
+        }
+    }
+
+    fn input(vout: u32, script_sig: Vec<u8>, witness: Vec<Vec<u8>>) -> TxInput {
+        let mut input = TxInput::new(
+            TxHash::new(&[4; 32]).unwrap().1,
+            PreTxIndex::new(vout),
+            ScriptSig {
+                content: script_sig,
+            },
+            TxInputSequence::new(0xffff_ffff),
+        );
+        input.witness = witness;
+        input
+    }
+
+    #[test]
+    fn test_sigops() {
+        // 2 of 3 multisig
+        let mut multisig = vec![0x52];
+        for i in 2..5u8 {
+            multisig.push(0x21);
+            multisig.extend_from_slice(&[i; 33]);
+        }
+        multisig.extend_from_slice(&[0x53, 0xae]);
+        let script = Script::parse_lossy(&multisig).0;
+        assert_eq!(script.sigop_count(false), 20);
+        assert_eq!(script.sigop_count(true), 3);
+
+        let mut p2sh = vec![0xa9, 0x14];
+        p2sh.extend_from_slice(&hash160(&multisig));
+        p2sh.push(0x87);
+        let mut p2wsh = vec![0x00, 0x20];
+        p2wsh.extend_from_slice(&Sha256::digest(&multisig));
+        let p2wpkh = [&[0x00, 0x14][..], &[9; 20]].concat();
+        let mut p2sh_p2wpkh = vec![0xa9, 0x14];
+        p2sh_p2wpkh.extend_from_slice(&hash160(&p2wpkh));
+        p2sh_p2wpkh.push(0x87);
+        let p2pkh = [&[0x76, 0xa9, 0x14][..], &[9; 20], &[0x88, 0xac]].concat();
+
+        let push = |data: &[u8]| [&[data.len() as u8][..], data].concat();
+        let p2sh_sig = [
+            &[0x00, 0x01, 0x30, 0x01, 0x30][..],
+            &[0x4c],
+            &push(&multisig),
+        ]
+        .concat();
+        let tx = Transaction::new(
+            TxVersion::new(2),
+            vec![
+                input(0, p2sh_sig, vec![]),
+                input(
+                    1,
+                    vec![],
+                    vec![vec![], vec![0x30], vec![0x30], multisig.clone()],
+                ),
+                input(2, vec![], vec![vec![0x30], vec![2; 33]]),
+                input(3, push(&p2wpkh), vec![vec![0x30], vec![2; 33]]),
+            ],
+            vec![output(p2pkh), output(multisig.clone())],
+            TxLocktime::new(0),
+            false,
+        );
+        // a CHECKSIG and a bare CHECKMULTISIG counted as 20
+        assert_eq!(tx.legacy_sigops(), 21);
+        assert_eq!(tx.total_sigops(&[]), 84);
+        let prevouts = vec![
+            output(p2sh),
+            output(p2wsh),
+            output(p2wpkh.clone()),
+            output(p2sh_p2wpkh),
+        ];
+        // the P2SH redeem script at four times, 3 + 1 + 1 witness sigops
+        assert_eq!(tx.total_sigops(&prevouts), 84 + 12 + 5);
+    }
+}
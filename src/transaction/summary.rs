@@ -0,0 +1,210 @@
+use std::fmt::Display;
+
+use super::locktime::LocktimeKind;
+use super::{OutPoint, Transaction, TxFetcher, TxHash};
+
+/// Sequence below which an input opts in to BIP125 replacement
+const MAX_BIP125_RBF_SEQUENCE: u32 = 0xffff_fffd;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct InputSummary {
+    /// None for the coinbase input
+    pub out_point: Option<OutPoint>,
+    pub value: Option<u64>,
+    pub address: Option<String>,
+    pub sequence: u32,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OutputSummary {
+    pub value: u64,
+    /// None for scripts without an address form, like OP_RETURN
+    pub address: Option<String>,
+    pub script_type: String,
+}
+
+/// Report on a transaction with its previous outputs looked up
+#[derive(Debug, PartialEq, Clone)]
+pub struct TxSummary {
+    pub txid: TxHash,
+    pub inputs: Vec<InputSummary>,
+    pub outputs: Vec<OutputSummary>,
+    /// None for a coinbase, which spends no outputs
+    pub total_in: Option<u64>,
+    pub total_out: u64,
+    pub fee: Option<u64>,
+    pub vsize: usize,
+    pub weight: usize,
+    /// BIP125 replaceability signalled by any input
+    pub rbf: bool,
+    pub locktime: LocktimeKind,
+    /// A locktime only applies when some input sequence is not final
+    pub locktime_enabled: bool,
+}
+
+impl TxSummary {
+    /// Fee rate in sat/vB
+    pub fn fee_rate(&self) -> Option<f64> {
+        self.fee.map(|fee| fee as f64 / self.vsize as f64)
+    }
+}
+
+fn btc(sats: u64) -> String {
+    format!("{}.{:08} BTC", sats / 100_000_000, sats % 100_000_000)
+}
+
+impl Display for TxSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "txid {}", self.txid)?;
+        writeln!(f, "size {} vB, {} WU", self.vsize, self.weight)?;
+        for (i, input) in self.inputs.iter().enumerate() {
+            match input.out_point {
+                Some(out_point) => write!(f, "in  {} {}", i, out_point)?,
+                None => write!(f, "in  {} coinbase", i)?,
+            }
+            if let Some(value) = input.value {
+                write!(f, " {}", btc(value))?;
+            }
+            if let Some(address) = &input.address {
+                write!(f, " {}", address)?;
+            }
+            writeln!(f, " seq {:#010x}", input.sequence)?;
+        }
+        for (i, output) in self.outputs.iter().enumerate() {
+            write!(f, "out {} {}", i, btc(output.value))?;
+            match &output.address {
+                Some(address) => writeln!(f, " {}", address)?,
+                None => writeln!(f, " {}", output.script_type)?,
+            }
+        }
+        if let Some(total_in) = self.total_in {
+            writeln!(f, "total in  {}", btc(total_in))?;
+        }
+        writeln!(f, "total out {}", btc(self.total_out))?;
+        if let (Some(fee), Some(fee_rate)) = (self.fee, self.fee_rate()) {
+            writeln!(f, "fee {} ({:.2} sat/vB)", btc(fee), fee_rate)?;
+        }
+        writeln!(f, "rbf {}", if self.rbf { "yes" } else { "no" })?;
+        match (self.locktime, self.locktime_enabled) {
+            (LocktimeKind::Unlocked, _) => write!(f, "locktime none"),
+            (locktime, true) => write!(f, "locktime {}", locktime),
+            (locktime, false) => write!(f, "locktime {} (disabled, all inputs final)", locktime),
+        }
+    }
+}
+
+impl Transaction {
+    /// Look up every previous output through `fetcher` and summarize the transaction
+    pub fn summary(&self, fetcher: &TxFetcher) -> Result<TxSummary, failure::Error> {
+        let coinbase = self.is_coinbase();
+        let mut inputs = Vec::with_capacity(self.inputs.len());
+        for input in &self.inputs {
+            let sequence = input.sequence.sequence();
+            if coinbase {
+                inputs.push(InputSummary {
+                    out_point: None,
+                    value: None,
+                    address: None,
+                    sequence,
+                });
+                continue;
+            }
+            let out_point = input.out_point();
+            let prev_tx = fetcher.fetch(out_point.txid, self.testnet, false)?;
+            let prevout = prev_tx
+                .outputs
+                .get(out_point.vout as usize)
+                .ok_or_else(|| failure::err_msg(format!("{} does not exist", out_point)))?;
+            inputs.push(InputSummary {
+                out_point: Some(out_point),
+                value: Some(u64::from(prevout.amount)),
+                address: prevout.script_pub_key.address(self.testnet),
+                sequence,
+            });
+        }
+
+        let outputs: Vec<OutputSummary> = self
+            .outputs
+            .iter()
+            .map(|output| OutputSummary {
+                value: u64::from(output.amount),
+                address: output.script_pub_key.address(self.testnet),
+                script_type: output.script_pub_key.script_type().to_string(),
+            })
+            .collect();
+
+        let total_in = if coinbase {
+            None
+        } else {
+            Some(inputs.iter().filter_map(|input| input.value).sum::<u64>())
+        };
+        let total_out = outputs.iter().map(|output| output.value).sum::<u64>();
+        let sequences = || self.inputs.iter().map(|input| input.sequence.sequence());
+
+        Ok(TxSummary {
+            txid: self.id(),
+            inputs,
+            outputs,
+            total_in,
+            total_out,
+            fee: total_in.and_then(|total_in| total_in.checked_sub(total_out)),
+            vsize: self.vsize(),
+            weight: self.weight(),
+            rbf: sequences().any(|sequence| sequence <= MAX_BIP125_RBF_SEQUENCE),
+            locktime: self.locktime.kind(),
+            locktime_enabled: sequences().any(|sequence| sequence != 0xffff_ffff),
+        })
+    }
+}
+
+mod test {
+    use super::super::{PreTxIndex, TxInputSequence, TxLocktime};
+    use super::LocktimeKind;
+    use crate::transaction::{OutPoint, Transaction, TxFetcher};
+
+    #[test]
+    fn test_summary() {
+        let prev = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let prev = Transaction::parse(&prev).unwrap().1;
+        let fetcher = TxFetcher::new();
+        fetcher.insert(prev.clone());
+
+        let mut tx = prev.clone();
+        tx.inputs[0].pre_tx_id = prev.id();
+        tx.inputs[0].pre_tx_index = PreTxIndex::new(1);
+        tx.inputs[0].sequence = TxInputSequence::new(0xffff_fffd);
+        tx.outputs.truncate(1);
+        tx.outputs[0].amount = 10_000_000.into();
+        tx.locktime = TxLocktime::new(650_000);
+
+        let summary = tx.summary(&fetcher).unwrap();
+        assert_eq!(
+            summary.inputs[0].out_point,
+            Some(OutPoint::new(prev.id(), 1))
+        );
+        assert_eq!(summary.inputs[0].value, Some(10_011_545));
+        assert_eq!(
+            summary.inputs[0].address,
+            prev.outputs[1].script_pub_key.address(false)
+        );
+        assert!(summary.inputs[0].address.is_some());
+        assert_eq!(summary.total_in, Some(10_011_545));
+        assert_eq!(summary.total_out, 10_000_000);
+        assert_eq!(summary.fee, Some(11_545));
+        assert_eq!(summary.fee_rate(), Some(11_545.0 / tx.vsize() as f64));
+        assert!(summary.rbf);
+        assert_eq!(summary.locktime, LocktimeKind::Height(650_000));
+        assert!(summary.locktime_enabled);
+        assert!(summary.to_string().contains("fee 0.00011545 BTC"));
+
+        // final sequences turn the locktime off and do not signal replacement
+        tx.inputs[0].sequence = TxInputSequence::new(0xffff_ffff);
+        let summary = tx.summary(&fetcher).unwrap();
+        assert!(!summary.rbf);
+        assert!(!summary.locktime_enabled);
+
+        // a spend of an output the previous transaction does not have
+        tx.inputs[0].pre_tx_index = PreTxIndex::new(9);
+        assert!(tx.summary(&fetcher).is_err());
+    }
+}
@@ -1,59 +1,579 @@
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use super::tx_input::TxHash;
-use super::Transaction;
+use super::tx_input::{OutPoint, TxHash};
+use super::{ScriptPubKey, Transaction};
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::cancel::CancelToken;
+use crate::metrics::{self, Metrics};
+use crate::proxy::ProxyConfig;
+use crate::storage::{Storage, StorageError};
+use crate::wallet::store::BlockRef;
 
 use failure::Error;
 
-#[derive(Fail, Debug)]
+mod esplora;
+mod tx_cache;
+pub use esplora::{AddressUtxo, FeeEstimates};
+pub use tx_cache::CacheStats;
+use tx_cache::TxCache;
+
+/// Little endian txid -> transaction
+const CACHED_TX: &[u8] = b"Xt";
+/// Little endian block hash -> header
+const CACHED_HEADER: &[u8] = b"Xh";
+/// Downloads `fetch_many` runs at once
+const PARALLEL_FETCHES: usize = 8;
+/// `backend` label of the requests it reports
+const METRICS_BACKEND: &str = "tx_fetcher";
+
+#[derive(Fail, Debug, PartialEq, Eq)]
 pub enum TxFetcherError {
     #[fail(display = "hex response decode error")]
     HexDecodeError,
     #[fail(display = "hex transaction parse error")]
     TxParseError,
+    #[fail(display = "bytes left after the fetched transaction")]
+    TrailingDataError,
     #[fail(display = "fetched transaction not has same id")]
     NotSameTxIdError,
+    #[fail(display = "block header parse error")]
+    BlockHeaderParseError,
+    #[fail(display = "fetched block header not has same hash")]
+    NotSameBlockHashError,
+    #[fail(display = "block parse error")]
+    BlockParseError,
+    #[fail(display = "block hash response parse error")]
+    BlockHashParseError,
+    #[fail(display = "block height response parse error")]
+    HeightParseError,
+    #[fail(display = "transaction status response parse error")]
+    TxStatusParseError,
+    #[fail(display = "script pubkey has no address to query")]
+    NoAddressError,
+    #[fail(display = "address response parse error")]
+    AddressParseError,
+    #[fail(display = "fee estimates response parse error")]
+    FeeEstimatesParseError,
+}
+
+/// Chain data a wallet needs to track confirmations, implemented by every backend
+pub trait ChainBackend {
+    fn tip_height(&mut self, testnet: bool) -> Result<u32, Error>;
+
+    /// Height new transactions are built at, for their anti fee sniping locktime. The
+    /// tip by default, a backend that knows it is still syncing can hold it back.
+    fn current_height(&mut self, testnet: bool) -> Result<u32, Error> {
+        self.tip_height(testnet)
+    }
+
+    /// Hash of the best chain block at `height`
+    fn block_hash(&mut self, height: u32, testnet: bool) -> Result<BlockHash, Error>;
+
+    /// Height and hash of the block confirming `tx_id`, None while it is unconfirmed
+    fn tx_confirmation(
+        &mut self,
+        tx_id: TxHash,
+        testnet: bool,
+    ) -> Result<Option<(u32, BlockHash)>, Error>;
+}
+
+/// Full blocks of the best chain, for rescans that look at every transaction
+pub trait BlockBackend: ChainBackend {
+    fn block(&mut self, block_hash: BlockHash, testnet: bool) -> Result<Block, Error>;
+}
+
+/// Per script pubkey data an address discovery scan needs, like Electrum's
+/// `blockchain.scripthash.get_history` and `listunspent`
+pub trait ScriptBackend {
+    /// Every transaction paying to or spending from `script_pub_key`
+    fn script_history(
+        &mut self,
+        script_pub_key: &ScriptPubKey,
+        testnet: bool,
+    ) -> Result<Vec<TxHash>, Error>;
+
+    /// Unspent outputs paying `script_pub_key` with their amount and the height that
+    /// created them, 0 while unconfirmed
+    fn script_utxos(
+        &mut self,
+        script_pub_key: &ScriptPubKey,
+        testnet: bool,
+    ) -> Result<Vec<(OutPoint, u64, u32)>, Error>;
 }
 
+/// Transactions by id, for lookups that need many at once
+pub trait TxBackend {
+    /// Transactions of `tx_ids` in that order. Backends batch or parallelize the lookups,
+    /// one call for many ids takes far fewer round trips than a call for each.
+    fn fetch_many(&mut self, tx_ids: &[TxHash], testnet: bool) -> Result<Vec<Transaction>, Error>;
+}
+
+/// Esplora client with a cache of what it fetched. Every method takes `&self`, one
+/// fetcher behind an `Arc` serves many threads and they share its cache.
 pub struct TxFetcher {
-    cache: HashMap<TxHash, Transaction>,
+    cache: Mutex<TxCache>,
+    headers: RwLock<HashMap<BlockHash, BlockHeader>>,
+    /// Esplora instance queried for both networks instead of blockstream.info
+    api_url: Option<String>,
+    proxy: Option<ProxyConfig>,
+    metrics: Arc<dyn Metrics>,
+    cancel: CancelToken,
 }
 
 impl TxFetcher {
+    /// Client for one request, through the proxy when there is one. Fails once the
+    /// request is cancelled and times out at its deadline.
+    fn client(&self) -> Result<reqwest::Client, Error> {
+        self.cancel.check()?;
+        let builder = match &self.proxy {
+            #[cfg(feature = "proxy")]
+            Some(proxy) => proxy.http_client_builder()?,
+            _ => reqwest::Client::builder(),
+        };
+        let builder = match self.cancel.remaining() {
+            Some(remaining) => builder.timeout(remaining),
+            None => builder,
+        };
+        Ok(builder.build()?)
+    }
+
+    /// Reported as a `method` request, timed until the response arrives
+    fn get(&self, method: &str, url: &str) -> Result<reqwest::Response, Error> {
+        let client = self.client()?;
+        metrics::time_request(&*self.metrics, METRICS_BACKEND, method, || {
+            Self::send(&self.cancel, client.get(url))
+        })
+    }
+
+    /// A request failing because it was cancelled or timed out fails with that
+    fn send(
+        cancel: &CancelToken,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, Error> {
+        request.send().map_err(|e| match cancel.check() {
+            Err(cancelled) => cancelled.into(),
+            Ok(()) => e.into(),
+        })
+    }
+
     fn get_url(testnet: bool) -> &'static str {
         "https://blockchain.info"
     }
 
+    /// Esplora REST api, serves block data for mainnet and testnet
+    fn get_api_url(&self, testnet: bool) -> &str {
+        match &self.api_url {
+            Some(api_url) => api_url,
+            None if testnet => "https://blockstream.info/testnet/api",
+            None => "https://blockstream.info/api",
+        }
+    }
+
+    fn cache(&self) -> MutexGuard<TxCache> {
+        self.cache.lock().expect("tx cache lock poisoned")
+    }
+
+    fn headers(&self) -> RwLockReadGuard<HashMap<BlockHash, BlockHeader>> {
+        self.headers.read().expect("header cache lock poisoned")
+    }
+
+    fn headers_mut(&self) -> RwLockWriteGuard<HashMap<BlockHash, BlockHeader>> {
+        self.headers.write().expect("header cache lock poisoned")
+    }
+
+    pub fn get_block_header(
+        &self,
+        block_hash: BlockHash,
+        testnet: bool,
+    ) -> Result<BlockHeader, Error> {
+        let cached = self.headers().get(&block_hash).cloned();
+        metrics::record_lookup(&*self.metrics, "header", cached.is_some());
+        if let Some(header) = cached {
+            return Ok(header);
+        }
+
+        let url = format!("{}/block/{}/header", self.get_api_url(testnet), block_hash);
+        let body = self.get("block_header", &url)?.text()?;
+
+        let hex = hex::decode(body.trim()).map_err(|_| TxFetcherError::HexDecodeError)?;
+        let header = match BlockHeader::parse(&hex) {
+            Ok((rest, header)) if rest.is_empty() => header,
+            _ => return Err(TxFetcherError::BlockHeaderParseError.into()),
+        };
+        if header.hash() != block_hash {
+            return Err(TxFetcherError::NotSameBlockHashError.into());
+        }
+
+        self.headers_mut().insert(block_hash, header);
+        Ok(header)
+    }
+
+    /// Esplora `/block/:hash/raw`, blocks are too big to cache
+    pub fn get_block(&self, block_hash: BlockHash, testnet: bool) -> Result<Block, Error> {
+        let url = format!("{}/block/{}/raw", self.get_api_url(testnet), block_hash);
+        let mut body = Vec::new();
+        self.get("block", &url)?.copy_to(&mut body)?;
+
+        let block = match Block::parse(&body) {
+            Ok((rest, block)) if rest.is_empty() => block,
+            _ => return Err(TxFetcherError::BlockParseError.into()),
+        };
+        if block.hash() != block_hash {
+            return Err(TxFetcherError::NotSameBlockHashError.into());
+        }
+        Ok(block)
+    }
+
+    /// Hash of the best chain block at `height`, never cached since reorgs can change it
+    pub fn get_block_hash(&self, height: u32, testnet: bool) -> Result<BlockHash, Error> {
+        let url = format!("{}/block-height/{}", self.get_api_url(testnet), height);
+        let body = self.get("block_hash", &url)?.text()?;
+        Ok(BlockHash::from_str(&body).map_err(|_| TxFetcherError::BlockHashParseError)?)
+    }
+
+    pub fn get_tip_height(&self, testnet: bool) -> Result<u32, Error> {
+        let url = format!("{}/blocks/tip/height", self.get_api_url(testnet));
+        let body = self.get("tip_height", &url)?.text()?;
+        Ok(body
+            .trim()
+            .parse()
+            .map_err(|_| TxFetcherError::HeightParseError)?)
+    }
+
+    /// Esplora `/tx/:txid/status`, the block confirming `tx_id` or None while it is
+    /// unconfirmed
+    pub fn get_tx_status(&self, tx_id: TxHash, testnet: bool) -> Result<Option<BlockRef>, Error> {
+        let url = format!("{}/tx/{}/status", self.get_api_url(testnet), tx_id);
+        let body = self.get("tx_status", &url)?.text()?;
+        Ok(esplora::parse_tx_status(&body)?)
+    }
+
+    /// Esplora `/address/:address/txs`, the mempool and the most recent confirmed
+    /// transactions, enough to tell whether an address was ever used
+    pub fn get_address_txs(&self, address: &str, testnet: bool) -> Result<Vec<TxHash>, Error> {
+        let url = format!("{}/address/{}/txs", self.get_api_url(testnet), address);
+        let body = self.get("address_txs", &url)?.text()?;
+        let txs: serde_json::Value =
+            serde_json::from_str(&body).map_err(|_| TxFetcherError::AddressParseError)?;
+
+        txs.as_array()
+            .ok_or(TxFetcherError::AddressParseError)?
+            .iter()
+            .map(|tx| {
+                tx["txid"]
+                    .as_str()
+                    .and_then(|txid| TxHash::from_str(txid).ok())
+                    .ok_or_else(|| TxFetcherError::AddressParseError.into())
+            })
+            .collect()
+    }
+
+    /// Esplora `/address/:address/utxo`, the confirmed and mempool outputs paying
+    /// `address`
+    pub fn get_address_utxos(
+        &self,
+        address: &str,
+        testnet: bool,
+    ) -> Result<Vec<AddressUtxo>, Error> {
+        let url = format!("{}/address/{}/utxo", self.get_api_url(testnet), address);
+        let body = self.get("address_utxos", &url)?.text()?;
+        Ok(esplora::parse_address_utxos(&body)?)
+    }
+
+    /// Esplora `/fee-estimates`, fee rates for confirmation targets from 1 to 1008 blocks
+    pub fn get_fee_estimates(&self, testnet: bool) -> Result<FeeEstimates, Error> {
+        let url = format!("{}/fee-estimates", self.get_api_url(testnet));
+        let body = self.get("fee_estimates", &url)?.text()?;
+        Ok(FeeEstimates::from_json(&body)?)
+    }
+
+    fn download_tx(
+        client: reqwest::Client,
+        metrics: &dyn Metrics,
+        cancel: &CancelToken,
+        tx_id: TxHash,
+        testnet: bool,
+    ) -> Result<Transaction, Error> {
+        let url = format!("{}/tx/{}?format=hex", Self::get_url(testnet), tx_id);
+        let body = metrics::time_request(metrics, METRICS_BACKEND, "tx", || {
+            Self::send(cancel, client.get(&url))?
+                .text()
+                .map_err(Error::from)
+        })?;
+
+        Ok(Self::check_tx_response(&body, tx_id)?)
+    }
+
+    /// The transaction in a hex `body`, only if it is exactly one transaction and has the
+    /// id asked for. Anything an endpoint sends goes into the cache, a buggy or malicious
+    /// one must not get other data in.
+    fn check_tx_response(body: &str, tx_id: TxHash) -> Result<Transaction, TxFetcherError> {
+        let hex = hex::decode(body.trim()).map_err(|_| TxFetcherError::HexDecodeError)?;
+        let tx = match Transaction::parse(&hex) {
+            Ok((rest, tx)) if rest.is_empty() => tx,
+            Ok(_) => return Err(TxFetcherError::TrailingDataError),
+            Err(_) => return Err(TxFetcherError::TxParseError),
+        };
+        if tx.id() != tx_id {
+            return Err(TxFetcherError::NotSameTxIdError);
+        }
+        Ok(tx)
+    }
+
+    /// The transaction `tx_id`, from the cache unless `fresh`. The lock on the cache is
+    /// not held while downloading, threads fetching the same id at once may both download
+    /// it.
     pub fn fetch(
-        &mut self,
+        &self,
         tx_id: TxHash,
         testnet: bool,
         fresh: bool,
-    ) -> Result<&Transaction, Error> {
-        if fresh || !self.cache.contains_key(&tx_id) {
-            let url = format!("{}/tx/{}?format=hex", Self::get_url(testnet), tx_id);
-            let body = reqwest::get(&url)?.text()?;
-
-            let hex = hex::decode(body).map_err(|_| return TxFetcherError::HexDecodeError)?;
-            let (input, tx) =
-                Transaction::parse(&hex).map_err(|_| return TxFetcherError::TxParseError)?;
+    ) -> Result<Arc<Transaction>, Error> {
+        let cached = if fresh {
+            None
+        } else {
+            self.cache().get(&tx_id)
+        };
+        metrics::record_lookup(&*self.metrics, "tx", cached.is_some());
+        let tx = match cached {
+            Some(tx) if tx.testnet == testnet => return Ok(tx),
+            Some(tx) => (*tx).clone(),
+            None => {
+                Self::download_tx(self.client()?, &*self.metrics, &self.cancel, tx_id, testnet)?
+            }
+        };
 
-            // todo tx.id()
-            // if tx.id() != tx_id {
-            //     return Err(TxFetcherError::NotSameTxIdError);
-            // }
+        let tx = Arc::new(Transaction { testnet, ..tx });
+        self.cache().insert(tx.clone());
+        Ok(tx)
+    }
 
-            self.cache.insert(tx_id, tx);
+    /// Transactions of `tx_ids` in that order. Each one not cached is downloaded once,
+    /// `PARALLEL_FETCHES` at a time.
+    pub fn fetch_many(&self, tx_ids: &[TxHash], testnet: bool) -> Result<Vec<Transaction>, Error> {
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let mut cache = self.cache();
+            for tx_id in tx_ids {
+                match cache.get(tx_id) {
+                    Some(tx) => {
+                        found.insert(*tx_id, tx);
+                    }
+                    None => missing.push(*tx_id),
+                }
+            }
+        }
+        missing.sort();
+        missing.dedup();
+        let hits = (tx_ids.len() - missing.len()) as u64;
+        self.metrics.increment(
+            metrics::CACHE_LOOKUPS,
+            &[("cache", "tx"), ("result", "hit")],
+            hits,
+        );
+        self.metrics.increment(
+            metrics::CACHE_LOOKUPS,
+            &[("cache", "tx"), ("result", "miss")],
+            missing.len() as u64,
+        );
+        let metrics = &*self.metrics;
+        let cancel = &self.cancel;
+        for batch in missing.chunks(PARALLEL_FETCHES) {
+            let clients = batch
+                .iter()
+                .map(|_| self.client())
+                .collect::<Result<Vec<_>, _>>()?;
+            let downloaded: Vec<Result<Transaction, Error>> = std::thread::scope(|scope| {
+                let downloads: Vec<_> = batch
+                    .iter()
+                    .zip(clients)
+                    .map(|(tx_id, client)| {
+                        scope.spawn(move || {
+                            Self::download_tx(client, metrics, cancel, *tx_id, testnet)
+                        })
+                    })
+                    .collect();
+                downloads
+                    .into_iter()
+                    .map(|download| download.join().expect("download thread panicked"))
+                    .collect()
+            });
+            for tx in downloaded {
+                let tx = Arc::new(tx?);
+                self.cache().insert(tx.clone());
+                found.insert(tx.id(), tx);
+            }
         }
+        // kept aside, a bounded cache may already have evicted some
+        Ok(tx_ids
+            .iter()
+            .map(|tx_id| {
+                let mut tx = (*found[tx_id]).clone();
+                tx.testnet = testnet;
+                tx
+            })
+            .collect())
+    }
+
+    /// Cache a transaction known from elsewhere, later fetches of its id stay offline
+    pub fn insert(&self, tx: Transaction) {
+        self.cache().insert(Arc::new(tx));
+    }
+
+    /// Keep at most `max_entries` transactions cached, evicting the least recently used
+    pub fn max_cached_txs(self, max_entries: usize) -> Self {
+        self.cache().set_max_entries(Some(max_entries));
+        self
+    }
 
-        self.cache.get_mut(&tx_id).unwrap().testnet = testnet;
-        Ok(self.cache.get(&tx_id).unwrap())
+    /// Keep the cached transactions under `max_bytes` serialized, evicting the least
+    /// recently used
+    pub fn max_cache_bytes(self, max_bytes: usize) -> Self {
+        self.cache().set_max_bytes(Some(max_bytes));
+        self
+    }
+
+    /// Size and hit counts of the transaction cache
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache().stats()
     }
 
     pub fn new() -> Self {
         TxFetcher {
-            cache: HashMap::new(),
+            cache: Mutex::new(TxCache::new()),
+            headers: RwLock::new(HashMap::new()),
+            api_url: None,
+            proxy: None,
+            metrics: metrics::noop(),
+            cancel: CancelToken::new(),
+        }
+    }
+
+    /// Stop requesting once `cancel` is cancelled or times out, the calls then fail with
+    /// its `CancelError`
+    pub fn cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Query the Esplora api at `api_url`, e.g. `https://mempool.space/api` or a self
+    /// hosted instance, whatever the `testnet` argument of the calls. Transactions are
+    /// still downloaded from blockchain.info.
+    pub fn api_url(mut self, api_url: &str) -> Self {
+        self.api_url = Some(api_url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Report cache lookups and request latencies to `metrics`
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Send every request through a SOCKS5 proxy, with stream isolation each one gets
+    /// its own Tor circuit
+    #[cfg(feature = "proxy")]
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Add the transactions and headers saved in `storage` to the cache, returns how many
+    /// transactions it held
+    pub fn load_cache<S: Storage>(&self, storage: &S) -> Result<usize, StorageError> {
+        let txs = storage.scan_prefix(CACHED_TX)?;
+        for (_, value) in &txs {
+            match Transaction::parse(value) {
+                Ok((rest, tx)) if rest.is_empty() => self.insert(tx),
+                _ => return Err(StorageError::Corrupt),
+            }
+        }
+        for (_, value) in storage.scan_prefix(CACHED_HEADER)? {
+            let header = BlockHeader::parse(&value)
+                .map_err(|_| StorageError::Corrupt)?
+                .1;
+            self.headers_mut().insert(header.hash(), header);
         }
+        Ok(txs.len())
+    }
+
+    /// Write every cached transaction and header, they never change once fetched
+    pub fn save_cache<S: Storage>(&self, storage: &mut S) -> Result<(), StorageError> {
+        for (tx_id, tx) in self.cache().iter() {
+            let key = [CACHED_TX, &tx_id.to_little_endian()].concat();
+            storage.put(&key, &tx.serialize())?;
+        }
+        for (hash, header) in self.headers().iter() {
+            let key = [CACHED_HEADER, &hash.to_little_endian()].concat();
+            storage.put(&key, &header.serialize())?;
+        }
+        Ok(())
+    }
+}
+
+impl TxBackend for TxFetcher {
+    fn fetch_many(&mut self, tx_ids: &[TxHash], testnet: bool) -> Result<Vec<Transaction>, Error> {
+        TxFetcher::fetch_many(self, tx_ids, testnet)
+    }
+}
+
+impl ChainBackend for TxFetcher {
+    fn tip_height(&mut self, testnet: bool) -> Result<u32, Error> {
+        self.get_tip_height(testnet)
+    }
+
+    fn block_hash(&mut self, height: u32, testnet: bool) -> Result<BlockHash, Error> {
+        self.get_block_hash(height, testnet)
+    }
+
+    fn tx_confirmation(
+        &mut self,
+        tx_id: TxHash,
+        testnet: bool,
+    ) -> Result<Option<(u32, BlockHash)>, Error> {
+        Ok(self
+            .get_tx_status(tx_id, testnet)?
+            .map(|block| (block.height, block.hash)))
+    }
+}
+
+impl BlockBackend for TxFetcher {
+    fn block(&mut self, block_hash: BlockHash, testnet: bool) -> Result<Block, Error> {
+        self.get_block(block_hash, testnet)
+    }
+}
+
+impl ScriptBackend for TxFetcher {
+    fn script_history(
+        &mut self,
+        script_pub_key: &ScriptPubKey,
+        testnet: bool,
+    ) -> Result<Vec<TxHash>, Error> {
+        let address = script_pub_key
+            .address(testnet)
+            .ok_or(TxFetcherError::NoAddressError)?;
+        self.get_address_txs(&address, testnet)
+    }
+
+    fn script_utxos(
+        &mut self,
+        script_pub_key: &ScriptPubKey,
+        testnet: bool,
+    ) -> Result<Vec<(OutPoint, u64, u32)>, Error> {
+        let address = script_pub_key
+            .address(testnet)
+            .ok_or(TxFetcherError::NoAddressError)?;
+        Ok(self
+            .get_address_utxos(&address, testnet)?
+            .into_iter()
+            .map(|utxo| {
+                let height = utxo.block.map_or(0, |block| block.height);
+                (utxo.out_point, u64::from(utxo.amount), height)
+            })
+            .collect())
     }
 }
 
@@ -65,7 +585,7 @@ mod test {
     #[test]
     fn test_tx_fetch() {
         let data = hex!("0100000002d8c8df6a6fdd2addaf589a83d860f18b44872d13ee6ec3526b2b470d42a96d4d000000008b483045022100b31557e47191936cb14e013fb421b1860b5e4fd5d2bc5ec1938f4ffb1651dc8902202661c2920771fd29dd91cd4100cefb971269836da4914d970d333861819265ba014104c54f8ea9507f31a05ae325616e3024bd9878cb0a5dff780444002d731577be4e2e69c663ff2da922902a4454841aa1754c1b6292ad7d317150308d8cce0ad7abffffffff2ab3fa4f68a512266134085d3260b94d3b6cfd351450cff021c045a69ba120b2000000008b4830450220230110bc99ef311f1f8bda9d0d968bfe5dfa4af171adbef9ef71678d658823bf022100f956d4fcfa0995a578d84e7e913f9bb1cf5b5be1440bcede07bce9cd5b38115d014104c6ec27cffce0823c3fecb162dbd576c88dd7cda0b7b32b0961188a392b488c94ca174d833ee6a9b71c0996620ae71e799fc7c77901db147fa7d97732e49c8226ffffffff02c0175302000000001976a914a3d89c53bb956f08917b44d113c6b2bcbe0c29b788acc01c3d09000000001976a91408338e1d5e26db3fce21b011795b1c3c8a5a5d0788ac00000000");
-        let mut tx_fetcher = TxFetcher::new();
+        let tx_fetcher = TxFetcher::new();
         let tx = tx_fetcher.fetch(
             TxHash::new(&hex!(
                 "9021b49d445c719106c95d561b9c3fac7bcb3650db67684a9226cd7fa1e1c1a0"
@@ -81,4 +601,91 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_check_tx_response() {
+        use super::TxFetcherError;
+
+        let hex = "0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600";
+        let tx_id = crate::transaction::Transaction::from_hex(hex).unwrap().id();
+        assert_eq!(
+            TxFetcher::check_tx_response(hex, TxHash::new(&[0; 32]).unwrap().1),
+            Err(TxFetcherError::NotSameTxIdError)
+        );
+
+        assert_eq!(
+            TxFetcher::check_tx_response(&format!("{}\n", hex), tx_id)
+                .unwrap()
+                .id(),
+            tx_id
+        );
+        assert_eq!(
+            TxFetcher::check_tx_response(&format!("{}00", hex), tx_id),
+            Err(TxFetcherError::TrailingDataError)
+        );
+        assert_eq!(
+            TxFetcher::check_tx_response(&hex[..200], tx_id),
+            Err(TxFetcherError::TxParseError)
+        );
+        assert_eq!(
+            TxFetcher::check_tx_response("Transaction not found", tx_id),
+            Err(TxFetcherError::HexDecodeError)
+        );
+    }
+
+    #[test]
+    fn test_cache_storage() {
+        use crate::storage::MemoryStorage;
+        use crate::transaction::Transaction;
+
+        let data = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let tx = Transaction::parse(&data).unwrap().1;
+        let fetcher = TxFetcher::new();
+        fetcher.insert(tx.clone());
+        let mut storage = MemoryStorage::new();
+        fetcher.save_cache(&mut storage).unwrap();
+
+        let fetcher = TxFetcher::new();
+        assert_eq!(fetcher.load_cache(&storage), Ok(1));
+        // served from the cache, no request goes out
+        assert_eq!(*fetcher.fetch(tx.id(), false, false).unwrap(), tx);
+        assert_eq!(
+            fetcher.fetch_many(&[tx.id(), tx.id()], false).unwrap(),
+            vec![tx.clone(), tx.clone()]
+        );
+        let stats = fetcher.cache_stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (1, 3, 0));
+
+        let bounded = TxFetcher::new().max_cached_txs(0);
+        bounded.insert(tx);
+        assert_eq!(bounded.cache_stats().entries, 0);
+        assert_eq!(bounded.cache_stats().evictions, 1);
+    }
+
+    #[test]
+    fn test_shared_fetcher() {
+        use crate::transaction::Transaction;
+        use std::sync::Arc;
+
+        let data = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let tx = Transaction::parse(&data).unwrap().1;
+        let fetcher = Arc::new(TxFetcher::new());
+        fetcher.insert(tx.clone());
+
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let fetcher = fetcher.clone();
+                let tx_id = tx.id();
+                std::thread::spawn(move || fetcher.fetch(tx_id, i % 2 == 1, false).unwrap())
+            })
+            .collect();
+        for (i, thread) in threads.into_iter().enumerate() {
+            let fetched = thread.join().unwrap();
+            assert_eq!(fetched.id(), tx.id());
+            assert_eq!(fetched.testnet, i % 2 == 1);
+        }
+        // results of two fetches can be held at once
+        let first = fetcher.fetch(tx.id(), false, false).unwrap();
+        let second = fetcher.fetch(tx.id(), false, false).unwrap();
+        assert_eq!(first.outputs[0], second.outputs[0]);
+    }
 }
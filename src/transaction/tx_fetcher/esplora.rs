@@ -0,0 +1,168 @@
+use std::str::FromStr;
+
+use super::TxFetcherError;
+use crate::block::BlockHash;
+use crate::transaction::{OutPoint, TxHash, TxOutputAmount};
+use crate::wallet::store::BlockRef;
+
+/// Block of an Esplora `status` object, e.g.
+/// `{"confirmed":true,"block_height":1,"block_hash":"...","block_time":1}`, None while
+/// unconfirmed. Outer None when a confirmed status lacks its block.
+fn status_block(status: &serde_json::Value) -> Option<Option<BlockRef>> {
+    if !status["confirmed"].as_bool().unwrap_or(false) {
+        return Some(None);
+    }
+    let height = status["block_height"].as_u64()?;
+    let hash = BlockHash::from_str(status["block_hash"].as_str()?).ok()?;
+    Some(Some(BlockRef {
+        height: height as u32,
+        hash,
+    }))
+}
+
+/// `/tx/:txid/status`
+pub(super) fn parse_tx_status(body: &str) -> Result<Option<BlockRef>, TxFetcherError> {
+    let status: serde_json::Value =
+        serde_json::from_str(body).map_err(|_| TxFetcherError::TxStatusParseError)?;
+    status_block(&status).ok_or(TxFetcherError::TxStatusParseError)
+}
+
+/// An unspent output of an address, as Esplora lists them
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressUtxo {
+    pub out_point: OutPoint,
+    pub amount: TxOutputAmount,
+    /// None while unconfirmed
+    pub block: Option<BlockRef>,
+}
+
+/// `/address/:address/utxo`,
+/// e.g. `[{"txid":"...","vout":0,"status":{"confirmed":false},"value":1}]`
+pub(super) fn parse_address_utxos(body: &str) -> Result<Vec<AddressUtxo>, TxFetcherError> {
+    let utxos: serde_json::Value =
+        serde_json::from_str(body).map_err(|_| TxFetcherError::AddressParseError)?;
+    utxos
+        .as_array()
+        .ok_or(TxFetcherError::AddressParseError)?
+        .iter()
+        .map(|utxo| {
+            let txid = utxo["txid"]
+                .as_str()
+                .and_then(|txid| TxHash::from_str(txid).ok());
+            let block = status_block(&utxo["status"]);
+            match (txid, utxo["vout"].as_u64(), utxo["value"].as_u64(), block) {
+                (Some(txid), Some(vout), Some(value), Some(block)) => Ok(AddressUtxo {
+                    out_point: OutPoint::new(txid, vout as u32),
+                    amount: value.into(),
+                    block,
+                }),
+                _ => Err(TxFetcherError::AddressParseError),
+            }
+        })
+        .collect()
+}
+
+/// Fee rates in sat/vB to confirm within a number of blocks, from `/fee-estimates`,
+/// e.g. `{"1":87.882,"2":87.882,"3":85.3,"144":1.027,"1008":1.027}`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FeeEstimates {
+    /// Confirmation target in blocks and its fee rate, by target
+    rates: Vec<(u16, f64)>,
+}
+
+impl FeeEstimates {
+    pub fn from_json(body: &str) -> Result<Self, TxFetcherError> {
+        let estimates: serde_json::Value =
+            serde_json::from_str(body).map_err(|_| TxFetcherError::FeeEstimatesParseError)?;
+        let mut rates = estimates
+            .as_object()
+            .ok_or(TxFetcherError::FeeEstimatesParseError)?
+            .iter()
+            .map(|(target, rate)| match (target.parse(), rate.as_f64()) {
+                (Ok(target), Some(rate)) if rate >= 0.0 => Ok((target, rate)),
+                _ => Err(TxFetcherError::FeeEstimatesParseError),
+            })
+            .collect::<Result<Vec<(u16, f64)>, _>>()?;
+        rates.sort_by_key(|(target, _)| *target);
+        Ok(FeeEstimates { rates })
+    }
+
+    /// Rate to confirm within `blocks`, the one of the longest target that is not longer.
+    /// None when `blocks` is shorter than every target.
+    pub fn fee_rate(&self, blocks: u16) -> Option<f64> {
+        self.rates
+            .iter()
+            .take_while(|(target, _)| *target <= blocks)
+            .last()
+            .map(|(_, rate)| *rate)
+    }
+
+    /// Targets and their rates, shortest target first
+    pub fn iter(&self) -> impl Iterator<Item = (u16, f64)> + '_ {
+        self.rates.iter().cloned()
+    }
+}
+
+mod test {
+    use super::{parse_address_utxos, parse_tx_status, FeeEstimates};
+    use crate::block::BlockHash;
+    use crate::transaction::{OutPoint, TxHash};
+    use crate::wallet::store::BlockRef;
+    use crate::wallet::TxStatus;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_esplora_responses() {
+        let hash = "0000000000000000000320283a032748cef8227873ff4872689bf23f1cda83a5";
+        let block = BlockRef {
+            height: 800_000,
+            hash: BlockHash::from_str(hash).unwrap(),
+        };
+        let confirmed = format!(
+            r#"{{"confirmed":true,"block_height":800000,"block_hash":"{}","block_time":1690168629}}"#,
+            hash
+        );
+        assert_eq!(parse_tx_status(&confirmed), Ok(Some(block)));
+        assert_eq!(parse_tx_status(r#"{"confirmed":false}"#), Ok(None));
+        assert!(parse_tx_status(r#"{"confirmed":true}"#).is_err());
+        assert!(parse_tx_status("Transaction not found").is_err());
+
+        let txid = "9021b49d445c719106c95d561b9c3fac7bcb3650db67684a9226cd7fa1e1c1a0";
+        let utxos = format!(
+            r#"[{{"txid":"{0}","vout":1,"status":{1},"value":39000000}},
+                {{"txid":"{0}","vout":0,"status":{{"confirmed":false}},"value":546}}]"#,
+            txid, confirmed
+        );
+        let utxos = parse_address_utxos(&utxos).unwrap();
+        let txid = TxHash::from_str(txid).unwrap();
+        assert_eq!(utxos[0].out_point, OutPoint::new(txid, 1));
+        assert_eq!(u64::from(utxos[0].amount), 39_000_000);
+        assert_eq!(utxos[0].block, Some(block));
+        assert_eq!(utxos[1].block, None);
+        assert_eq!(
+            TxStatus::from_block(utxos[0].block, 800_005).confirmations(),
+            6
+        );
+        assert_eq!(
+            TxStatus::from_block(utxos[0].block, 799_999),
+            TxStatus::Unconfirmed
+        );
+        assert!(parse_address_utxos(r#"[{"vout":0,"value":1}]"#).is_err());
+
+        let estimates =
+            FeeEstimates::from_json(r#"{"1":87.882,"3":85.3,"2":86.1,"144":1.027,"1008":1}"#)
+                .unwrap();
+        assert_eq!(estimates.fee_rate(1), Some(87.882));
+        assert_eq!(estimates.fee_rate(6), Some(85.3));
+        assert_eq!(estimates.fee_rate(2000), Some(1.0));
+        assert_eq!(estimates.fee_rate(0), None);
+        assert_eq!(
+            estimates
+                .iter()
+                .map(|(target, _)| target)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3, 144, 1008]
+        );
+        assert!(FeeEstimates::from_json(r#"{"soon":1.0}"#).is_err());
+    }
+}
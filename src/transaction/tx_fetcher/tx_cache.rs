@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::transaction::{Transaction, TxHash};
+
+/// What a transaction cache holds and how well it serves
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub entries: usize,
+    /// Serialized size of the cached transactions
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Transactions dropped to stay in the bounds
+    pub evictions: u64,
+}
+impl Copy for CacheStats {}
+
+impl CacheStats {
+    /// Share of lookups that hit, None before the first one
+    pub fn hit_rate(&self) -> Option<f64> {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            None
+        } else {
+            Some(self.hits as f64 / lookups as f64)
+        }
+    }
+}
+
+struct Entry {
+    tx: Arc<Transaction>,
+    size: usize,
+    /// Tick of the last use, its key in `recency`
+    used: u64,
+}
+
+/// Transactions by id, dropping the least recently used once a bound on their count or
+/// serialized size is passed. Unbounded by default.
+#[derive(Default)]
+pub(crate) struct TxCache {
+    entries: HashMap<TxHash, Entry>,
+    /// Least recently used first
+    recency: BTreeMap<u64, TxHash>,
+    tick: u64,
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl TxCache {
+    pub fn new() -> Self {
+        TxCache::default()
+    }
+
+    /// Bound the number of transactions, evicting right away what no longer fits
+    pub fn set_max_entries(&mut self, max_entries: Option<usize>) {
+        self.max_entries = max_entries;
+        self.evict();
+    }
+
+    /// Bound the serialized size of the transactions, evicting right away what no
+    /// longer fits
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) {
+        self.max_bytes = max_bytes;
+        self.evict();
+    }
+
+    fn touch(&mut self, tx_id: &TxHash) {
+        if let Some(entry) = self.entries.get_mut(tx_id) {
+            self.recency.remove(&entry.used);
+            self.tick += 1;
+            entry.used = self.tick;
+            self.recency.insert(self.tick, *tx_id);
+        }
+    }
+
+    /// Counted as a hit or miss, a hit becomes the most recently used
+    pub fn get(&mut self, tx_id: &TxHash) -> Option<Arc<Transaction>> {
+        match self.entries.get(tx_id) {
+            Some(entry) => {
+                let tx = entry.tx.clone();
+                self.hits += 1;
+                self.touch(tx_id);
+                Some(tx)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Add or replace `tx` as the most recently used, then evict down to the bounds. A
+    /// transaction bigger than `max_bytes` on its own is not kept.
+    pub fn insert(&mut self, tx: Arc<Transaction>) {
+        let tx_id = tx.id();
+        self.remove(&tx_id);
+        let size = tx.serialized_len();
+        self.tick += 1;
+        self.entries.insert(
+            tx_id,
+            Entry {
+                tx,
+                size,
+                used: self.tick,
+            },
+        );
+        self.recency.insert(self.tick, tx_id);
+        self.bytes += size;
+        self.evict();
+    }
+
+    fn remove(&mut self, tx_id: &TxHash) -> Option<Entry> {
+        let entry = self.entries.remove(tx_id)?;
+        self.recency.remove(&entry.used);
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    fn over_bounds(&self) -> bool {
+        self.max_entries
+            .map_or(false, |max| self.entries.len() > max)
+            || self.max_bytes.map_or(false, |max| self.bytes > max)
+    }
+
+    fn evict(&mut self) {
+        while self.over_bounds() {
+            let oldest = match self.recency.values().next() {
+                Some(tx_id) => *tx_id,
+                None => return,
+            };
+            self.remove(&oldest);
+            self.evictions += 1;
+        }
+    }
+
+    pub fn contains(&self, tx_id: &TxHash) -> bool {
+        self.entries.contains_key(tx_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&TxHash, &Arc<Transaction>)> {
+        self.entries.iter().map(|(tx_id, entry)| (tx_id, &entry.tx))
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            bytes: self.bytes,
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+        }
+    }
+}
+
+mod test {
+    use super::{CacheStats, TxCache};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_lru_eviction() {
+        use crate::testkit::TxGenerator;
+
+        let mut generator = TxGenerator::new(7);
+        let txs: Vec<_> = (0..4).map(|_| Arc::new(generator.tx())).collect();
+        let mut cache = TxCache::new();
+        cache.set_max_entries(Some(3));
+        for tx in &txs[..3] {
+            cache.insert(tx.clone());
+        }
+        // the first is used again, the second is now the oldest
+        assert_eq!(cache.get(&txs[0].id()), Some(txs[0].clone()));
+        cache.insert(txs[3].clone());
+        assert!(!cache.contains(&txs[1].id()));
+        assert!(cache.contains(&txs[0].id()) && cache.contains(&txs[3].id()));
+        assert_eq!(cache.get(&txs[1].id()), None);
+
+        let bytes = txs[0].serialized_len() + txs[3].serialized_len();
+        cache.set_max_entries(None);
+        cache.set_max_bytes(Some(bytes));
+        assert!(cache.contains(&txs[0].id()) && cache.contains(&txs[3].id()));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                entries: 2,
+                bytes,
+                hits: 1,
+                misses: 1,
+                evictions: 2,
+            }
+        );
+        assert_eq!(cache.stats().hit_rate(), Some(0.5));
+
+        cache.set_max_bytes(Some(1));
+        cache.insert(txs[0].clone());
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.stats().bytes, 0);
+    }
+}
@@ -1,17 +1,22 @@
+mod out_point;
 mod pre_tx_index;
 mod script_sig;
 mod tx_hash;
 mod tx_input_sequence;
 
-use bytes::{BufMut, BytesMut};
+use nom::bytes::streaming::take;
 use nom::IResult;
 use std::fmt::Display;
+use std::sync::Arc;
 
 use super::tx_fetcher::TxFetcher;
 use super::tx_output::ScriptPubKey;
-use super::tx_output::TxOutputAmount;
+use super::tx_output::{TxOutput, TxOutputAmount};
+use super::varint::Varint;
 use super::Transaction;
-use crate::wallet::Hex;
+use crate::encode::{Decodable, Encodable};
+use crate::wallet::{Parse, Serialize};
+pub use out_point::OutPoint;
 pub use pre_tx_index::PreTxIndex;
 pub use script_sig::ScriptSig;
 pub use tx_hash::TxHash;
@@ -23,6 +28,8 @@ pub struct TxInput {
     pub pre_tx_index: PreTxIndex,
     pub script_sig: ScriptSig,
     pub sequence: TxInputSequence,
+    /// Segwit witness stack, empty for legacy inputs
+    pub witness: Vec<Vec<u8>>,
 }
 
 impl TxInput {
@@ -38,9 +45,47 @@ impl TxInput {
                 pre_tx_index,
                 script_sig,
                 sequence,
+                witness: Vec::new(),
             },
         ))
     }
+
+    /// Witness stack, follows every input once all outputs are parsed
+    pub fn parse_witness(input: &[u8]) -> IResult<&[u8], Vec<Vec<u8>>> {
+        let (mut input, items_num) = Varint::parse(input)?;
+        let mut witness = Vec::new();
+        for _ in 0..Into::<u64>::into(items_num) {
+            let (rest, item_len) = Varint::parse(input)?;
+            let (rest, item) = take(Into::<u64>::into(item_len))(rest)?;
+            witness.push(item.to_vec());
+            input = rest;
+        }
+        Ok((input, witness))
+    }
+
+    pub fn serialize_witness(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.witness_serialized_len());
+        self.serialize_witness_into(&mut buf);
+        buf
+    }
+
+    /// Exact length of `serialize_witness()`
+    pub fn witness_serialized_len(&self) -> usize {
+        Varint::encoded_len(self.witness.len() as u64)
+            + self
+                .witness
+                .iter()
+                .map(|item| Varint::encoded_len(item.len() as u64) + item.len())
+                .sum::<usize>()
+    }
+
+    pub(crate) fn serialize_witness_into(&self, buf: &mut Vec<u8>) {
+        Varint::from(self.witness.len() as u64).consensus_encode(buf);
+        for item in &self.witness {
+            Varint::from(item.len() as u64).consensus_encode(buf);
+            buf.extend_from_slice(item);
+        }
+    }
     pub fn new(
         pre_tx_id: TxHash,
         pre_tx_index: PreTxIndex,
@@ -52,42 +97,109 @@ impl TxInput {
             pre_tx_index,
             script_sig,
             sequence,
+            witness: Vec::new(),
         }
     }
 
+    /// Serialization without the witness, which goes at the end of the transaction
     pub fn serialize(&self) -> Vec<u8> {
-        let mut buf = BytesMut::with_capacity(32 + 4 + 9 + self.script_sig.content.len() + 4 + 4);
-        buf.put(&self.pre_tx_id.to_little_endian());
-        buf.put_u32_le(self.pre_tx_index.index());
-        buf.put(&self.script_sig.serialize());
-        buf.put_u32_le(self.sequence.sequence());
-        buf.take().to_vec()
+        let mut buf = Vec::with_capacity(self.serialized_len());
+        self.serialize_into(&mut buf);
+        buf
+    }
+
+    /// Exact length of `serialize()`
+    pub fn serialized_len(&self) -> usize {
+        32 + 4 + self.script_sig.serialized_len() + 4
+    }
+
+    pub(crate) fn serialize_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.pre_tx_id.to_little_endian());
+        buf.extend_from_slice(&self.pre_tx_index.index().to_le_bytes());
+        self.script_sig.serialize_into(buf);
+        buf.extend_from_slice(&self.sequence.sequence().to_le_bytes());
+    }
+
+    /// Input spending `out_point` with an empty script sig and witness and a final
+    /// sequence, to set what differs
+    pub fn builder(out_point: OutPoint) -> TxInputBuilder {
+        TxInputBuilder {
+            input: TxInput::new(
+                out_point.txid,
+                PreTxIndex::new(out_point.vout),
+                ScriptSig { content: vec![] },
+                TxInputSequence::default(),
+            ),
+        }
+    }
+
+    pub fn out_point(&self) -> OutPoint {
+        OutPoint::new(self.pre_tx_id, self.pre_tx_index.index())
     }
 
-    pub fn fetch_tx<'a>(
-        &'a self,
-        fetcher: &'a mut TxFetcher,
+    pub fn fetch_tx(
+        &self,
+        fetcher: &TxFetcher,
         testnet: bool,
-    ) -> Result<&'a Transaction, failure::Error> {
+    ) -> Result<Arc<Transaction>, failure::Error> {
         fetcher.fetch(self.pre_tx_id, testnet, false)
     }
 
-    pub fn value(&self, fetcher: &mut TxFetcher, testnet: bool) -> TxOutputAmount {
+    /// The output this input spends, None when its transaction can't be fetched or has
+    /// no such output. Owned, the prevouts of any number of inputs can be held at once.
+    pub fn prevout(&self, fetcher: &TxFetcher, testnet: bool) -> Option<Arc<TxOutput>> {
+        let tx = self.fetch_tx(fetcher, testnet).ok()?;
+        let output = tx.outputs.get(u32::from(self.pre_tx_index) as usize)?;
+        Some(Arc::new(output.clone()))
+    }
+
+    pub fn value(&self, fetcher: &TxFetcher, testnet: bool) -> TxOutputAmount {
         let tx = self
             .fetch_tx(fetcher, testnet)
             .expect("get pre transaction failed");
         tx.outputs[u32::from(self.pre_tx_index) as usize].amount
     }
 
-    pub fn script_pubkey<'a>(
-        &'a self,
-        fetcher: &'a mut TxFetcher,
-        testnet: bool,
-    ) -> &'a ScriptPubKey {
+    pub fn script_pubkey(&self, fetcher: &TxFetcher, testnet: bool) -> ScriptPubKey {
         let tx = self
             .fetch_tx(fetcher, testnet)
             .expect("get pre transaction failed");
-        &tx.outputs[u32::from(self.pre_tx_index) as usize].script_pub_key
+        tx.outputs[u32::from(self.pre_tx_index) as usize]
+            .script_pub_key
+            .clone()
+    }
+}
+
+/// Sets the fields of a `TxInput`, see `TxInput::builder`
+#[derive(Debug, Clone)]
+pub struct TxInputBuilder {
+    input: TxInput,
+}
+
+impl TxInputBuilder {
+    pub fn script_sig(mut self, content: Vec<u8>) -> Self {
+        self.input.script_sig = ScriptSig { content };
+        self
+    }
+
+    pub fn sequence(mut self, sequence: u32) -> Self {
+        self.input.sequence = TxInputSequence::new(sequence);
+        self
+    }
+
+    /// Signal BIP125 replaceability with the highest sequence that does, 0xfffffffd,
+    /// which keeps the locktime enforced
+    pub fn rbf(self) -> Self {
+        self.sequence(0xffff_fffd)
+    }
+
+    pub fn witness(mut self, witness: Vec<Vec<u8>>) -> Self {
+        self.input.witness = witness;
+        self
+    }
+
+    pub fn build(self) -> TxInput {
+        self.input
     }
 }
 
@@ -97,9 +209,32 @@ impl Display for TxInput {
     }
 }
 
-impl Hex for TxInput {
-    fn hex(&self) -> String {
-        hex::encode(self.serialize())
+impl Encodable for TxInput {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        self.serialize_into(buf);
+        buf.len() - start
+    }
+}
+
+impl Decodable for TxInput {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        TxInput::parse(input)
+    }
+}
+
+impl Serialize for TxInput {
+    fn serialize_bytes(&self) -> Vec<u8> {
+        self.serialize()
+    }
+}
+
+impl Parse for TxInput {
+    fn parse_bytes(bytes: &[u8]) -> Option<Self> {
+        match TxInput::parse(bytes) {
+            Ok((rest, input)) if rest.is_empty() => Some(input),
+            _ => None,
+        }
     }
 }
 
@@ -147,4 +282,56 @@ mod test {
 
         assert_eq!(tx_input.hex(), "813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff".to_string());
     }
+
+    #[test]
+    fn test_prevout() {
+        use crate::transaction::{Transaction, TxFetcher};
+
+        let data = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let prev = Transaction::parse(&data).unwrap().1;
+        let fetcher = TxFetcher::new();
+        fetcher.insert(prev.clone());
+        let spend = |vout| {
+            TxInput::new(
+                prev.id(),
+                PreTxIndex::new(vout),
+                ScriptSig { content: vec![] },
+                TxInputSequence::new(0xffff_ffff),
+            )
+        };
+
+        let first = spend(0).prevout(&fetcher, false).unwrap();
+        let second = spend(1).prevout(&fetcher, false).unwrap();
+        assert_eq!(*first, prev.outputs[0]);
+        assert_eq!(*second, prev.outputs[1]);
+        assert_eq!(
+            u64::from(first.amount) + u64::from(second.amount),
+            42_465_594
+        );
+        assert_eq!(spend(2).prevout(&fetcher, false), None);
+    }
+
+    #[test]
+    fn test_input_builder() {
+        use crate::transaction::OutPoint;
+
+        let out_point = OutPoint::new(TxHash::new(&[7; 32]).unwrap().1, 3);
+        let input = TxInput::builder(out_point).build();
+        assert_eq!(input.out_point(), out_point);
+        assert!(input.script_sig.content.is_empty() && input.witness.is_empty());
+        assert_eq!(input.sequence.sequence(), 0xffff_ffff);
+
+        let input = TxInput::builder(out_point)
+            .rbf()
+            .script_sig(vec![0x51])
+            .witness(vec![vec![1, 2]])
+            .build();
+        assert_eq!(input.sequence.sequence(), 0xffff_fffd);
+        assert_eq!(input.script_sig.content, vec![0x51]);
+        assert_eq!(input.witness, vec![vec![1, 2]]);
+        assert_eq!(
+            TxInput::builder(out_point).sequence(10).build().sequence,
+            TxInputSequence::new(10)
+        );
+    }
 }
@@ -0,0 +1,37 @@
+use nom::IResult;
+use std::fmt::Display;
+
+use super::{PreTxIndex, TxHash};
+
+/// The output a transaction input spends
+#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Clone, Hash)]
+pub struct OutPoint {
+    pub txid: TxHash,
+    pub vout: u32,
+}
+impl Copy for OutPoint {}
+
+impl Display for OutPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.txid, self.vout)
+    }
+}
+
+impl OutPoint {
+    pub fn new(txid: TxHash, vout: u32) -> Self {
+        OutPoint { txid, vout }
+    }
+
+    /// 32 bytes little endian txid then the output index
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, txid) = TxHash::parse(input)?;
+        let (input, vout) = PreTxIndex::parse(input)?;
+        Ok((input, OutPoint::new(txid, vout.index())))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = self.txid.to_little_endian();
+        buf.extend_from_slice(&self.vout.to_le_bytes());
+        buf
+    }
+}
@@ -1,8 +1,9 @@
-use bytes::{BufMut, BytesMut};
 use nom::bytes::streaming::take;
 use nom::IResult;
 
 use super::super::varint::Varint;
+use crate::encode::{Decodable, Encodable};
+use crate::script::Script;
 
 #[derive(Debug, PartialOrd, PartialEq, Clone, Hash)]
 pub struct ScriptSig {
@@ -23,10 +24,31 @@ impl ScriptSig {
     }
 
     pub fn serialize(&self) -> Vec<u8> {
-        let mut buf = BytesMut::with_capacity(9 + self.content.len() + 4);
-        buf.put(Varint::encode(self.content.len() as u64).unwrap());
-        buf.put(&self.content);
-        buf.take().to_vec()
+        let mut buf = Vec::with_capacity(self.serialized_len());
+        self.serialize_into(&mut buf);
+        buf
+    }
+
+    /// Exact length of `serialize()`, length prefix included
+    pub fn serialized_len(&self) -> usize {
+        Varint::encoded_len(self.content.len() as u64) + self.content.len()
+    }
+
+    pub(crate) fn serialize_into(&self, buf: &mut Vec<u8>) {
+        Varint::from(self.content.len() as u64).consensus_encode(buf);
+        buf.extend_from_slice(&self.content);
+    }
+
+    /// Script asm with sighash types decoded like Bitcoin Core, a malformed tail is shown
+    /// as `[error]`
+    pub fn asm(&self) -> String {
+        let (script, bad_regions) = Script::parse_lossy(&self.content);
+        let asm = script.to_asm(true);
+        match (bad_regions.is_empty(), asm.is_empty()) {
+            (true, _) => asm,
+            (false, true) => "[error]".to_string(),
+            (false, false) => format!("{} [error]", asm),
+        }
     }
 }
 
@@ -35,3 +57,17 @@ impl Default for ScriptSig {
         ScriptSig { content: vec![] }
     }
 }
+
+impl Encodable for ScriptSig {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        self.serialize_into(buf);
+        buf.len() - start
+    }
+}
+
+impl Decodable for ScriptSig {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        ScriptSig::parse(input)
+    }
+}
@@ -4,7 +4,7 @@ use nom::IResult;
 use std::fmt::Display;
 use std::str::FromStr;
 
-#[derive(Debug, PartialOrd, PartialEq, Clone, Hash, Eq)]
+#[derive(Debug, PartialOrd, Ord, PartialEq, Clone, Hash, Eq)]
 pub struct TxHash([u8; 32]);
 impl Copy for TxHash {}
 
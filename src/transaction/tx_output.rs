@@ -1,11 +1,14 @@
 mod script_pub_key;
 mod tx_output_amount;
 
-use bytes::{BufMut, BytesMut};
 use nom::IResult;
 use std::fmt::Display;
 
-pub use script_pub_key::ScriptPubKey;
+use crate::encode::{Decodable, Encodable};
+use crate::network::Network;
+use crate::wallet::{Parse, Serialize};
+
+pub use script_pub_key::{ScriptPubKey, ScriptPubKeyType};
 pub use tx_output_amount::TxOutputAmount;
 
 #[derive(Debug, PartialOrd, PartialEq, Clone, Hash)]
@@ -21,6 +24,15 @@ impl Display for TxOutput {
 }
 
 impl TxOutput {
+    /// Pay `amount` satoshis to `address` of `network`, None when the address is
+    /// malformed or of another network
+    pub fn to_address(amount: u64, address: &str, network: &Network) -> Option<Self> {
+        Some(TxOutput {
+            amount: amount.into(),
+            script_pub_key: ScriptPubKey::from_address(address, network)?,
+        })
+    }
+
     pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
         let (input, amount) = TxOutputAmount::parse(input)?;
         let (input, script_pub_key) = ScriptPubKey::parse(input)?;
@@ -34,10 +46,48 @@ impl TxOutput {
     }
 
     pub fn serialize(&self) -> Vec<u8> {
-        let mut buf = BytesMut::with_capacity(8 + 9 + self.script_pub_key.content.len() + 4);
-        buf.put_u64_le(u64::from(self.amount));
-        buf.put(self.script_pub_key.serialize());
-        buf.take().to_vec()
+        let mut buf = Vec::with_capacity(self.serialized_len());
+        self.serialize_into(&mut buf);
+        buf
+    }
+
+    /// Exact length of `serialize()`
+    pub fn serialized_len(&self) -> usize {
+        8 + self.script_pub_key.serialized_len()
+    }
+
+    pub(crate) fn serialize_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&u64::from(self.amount).to_le_bytes());
+        self.script_pub_key.serialize_into(buf);
+    }
+}
+
+impl Encodable for TxOutput {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        self.serialize_into(buf);
+        buf.len() - start
+    }
+}
+
+impl Decodable for TxOutput {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        TxOutput::parse(input)
+    }
+}
+
+impl Serialize for TxOutput {
+    fn serialize_bytes(&self) -> Vec<u8> {
+        self.serialize()
+    }
+}
+
+impl Parse for TxOutput {
+    fn parse_bytes(bytes: &[u8]) -> Option<Self> {
+        match TxOutput::parse(bytes) {
+            Ok((rest, output)) if rest.is_empty() => Some(output),
+            _ => None,
+        }
     }
 }
 
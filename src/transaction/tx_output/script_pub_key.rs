@@ -1,10 +1,50 @@
-use bytes::{BufMut, BytesMut};
 use nom::bytes::streaming::take;
 use nom::IResult;
 
 use std::fmt::Display;
 
+use crate::encode::{Decodable, Encodable};
+use crate::network::Network;
+use crate::script::{OpCode, Script};
 use crate::transaction::varint::Varint;
+use crate::wallet::bech32::{decode_segwit_address, encode_segwit_address};
+use crate::wallet::{decode_base58_checksum, encode_base58_checksum, Parse, Serialize};
+
+/// Standard output types, named like Bitcoin Core's `scriptPubKey.type`
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum ScriptPubKeyType {
+    PubKey,
+    PubKeyHash,
+    ScriptHash,
+    Multisig,
+    NullData,
+    WitnessV0KeyHash,
+    WitnessV0ScriptHash,
+    WitnessV1Taproot,
+    /// A witness program of a version or length no soft fork gave a meaning yet, with its
+    /// version. Anyone can spend it.
+    WitnessUnknown(u8),
+    NonStandard,
+}
+impl Copy for ScriptPubKeyType {}
+
+impl Display for ScriptPubKeyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ScriptPubKeyType::PubKey => "pubkey",
+            ScriptPubKeyType::PubKeyHash => "pubkeyhash",
+            ScriptPubKeyType::ScriptHash => "scripthash",
+            ScriptPubKeyType::Multisig => "multisig",
+            ScriptPubKeyType::NullData => "nulldata",
+            ScriptPubKeyType::WitnessV0KeyHash => "witness_v0_keyhash",
+            ScriptPubKeyType::WitnessV0ScriptHash => "witness_v0_scripthash",
+            ScriptPubKeyType::WitnessV1Taproot => "witness_v1_taproot",
+            ScriptPubKeyType::WitnessUnknown(_) => "witness_unknown",
+            ScriptPubKeyType::NonStandard => "nonstandard",
+        };
+        write!(f, "{}", name)
+    }
+}
 
 #[derive(Debug, PartialOrd, PartialEq, Clone, Hash)]
 pub struct ScriptPubKey {
@@ -31,10 +71,166 @@ impl ScriptPubKey {
     }
 
     pub fn serialize(&self) -> Vec<u8> {
-        let mut buf = BytesMut::with_capacity(9 + self.content.len() + 4);
-        buf.put(Varint::encode(self.content.len() as u64).unwrap());
-        buf.put(&self.content);
-        buf.take().to_vec()
+        let mut buf = Vec::with_capacity(self.serialized_len());
+        self.serialize_into(&mut buf);
+        buf
+    }
+
+    /// Exact length of `serialize()`, length prefix included
+    pub fn serialized_len(&self) -> usize {
+        Varint::encoded_len(self.content.len() as u64) + self.content.len()
+    }
+
+    pub(crate) fn serialize_into(&self, buf: &mut Vec<u8>) {
+        Varint::from(self.content.len() as u64).consensus_encode(buf);
+        buf.extend_from_slice(&self.content);
+    }
+
+    /// Version and program of a segwit output, `OP_n <2 to 40 bytes>`
+    pub fn witness_program(&self) -> Option<(u8, &[u8])> {
+        let content = &self.content;
+        if content.len() < 4 || content.len() > 42 || content[1] as usize != content.len() - 2 {
+            return None;
+        }
+        match content[0] {
+            0x00 => Some((0, &content[2..])),
+            0x51..=0x60 => Some((content[0] - 0x50, &content[2..])),
+            _ => None,
+        }
+    }
+
+    pub fn script_type(&self) -> ScriptPubKeyType {
+        let content = &self.content;
+        let len = content.len();
+        let is_pubkey_push = |push: &[u8]| {
+            (push[0] == 33 && push.len() >= 34) || (push[0] == 65 && push.len() >= 66)
+        };
+
+        if let Some((version, program)) = self.witness_program() {
+            return match (version, program.len()) {
+                (0, 20) => ScriptPubKeyType::WitnessV0KeyHash,
+                (0, 32) => ScriptPubKeyType::WitnessV0ScriptHash,
+                (0, _) => ScriptPubKeyType::NonStandard,
+                (1, 32) => ScriptPubKeyType::WitnessV1Taproot,
+                (version, _) => ScriptPubKeyType::WitnessUnknown(version),
+            };
+        }
+        let op = |index: usize| OpCode::from(content[index]);
+        if len == 25
+            && [op(0), op(1), op(2), op(23), op(24)]
+                == [
+                    OpCode::OpDup,
+                    OpCode::OpHash160,
+                    OpCode::OpPushBytes20,
+                    OpCode::OpEqualVerify,
+                    OpCode::OpCheckSig,
+                ]
+        {
+            return ScriptPubKeyType::PubKeyHash;
+        }
+        if len == 23
+            && [op(0), op(1), op(22)] == [OpCode::OpHash160, OpCode::OpPushBytes20, OpCode::OpEqual]
+        {
+            return ScriptPubKeyType::ScriptHash;
+        }
+        if (len == 35 || len == 67) && is_pubkey_push(content) && op(len - 1) == OpCode::OpCheckSig
+        {
+            return ScriptPubKeyType::PubKey;
+        }
+        if len > 0 && op(0) == OpCode::OpReturn {
+            let data = ScriptPubKey {
+                content: content[1..].to_vec(),
+            };
+            let push_only = Script::parse(&data.serialize())
+                .map(|(_, script)| script.is_push_only())
+                .unwrap_or(false);
+            if push_only {
+                return ScriptPubKeyType::NullData;
+            }
+        }
+        if len >= 3 && op(len - 1) == OpCode::OpCheckMultisig {
+            // OP_m <pubkey>... OP_n OP_CHECKMULTISIG
+            let (m, n) = (content[0], content[len - 2]);
+            if m >= OpCode::Op1.num() && m <= n && n <= OpCode::Op16.num() {
+                let mut rest = &content[1..len - 2];
+                let mut keys = 0;
+                while !rest.is_empty() && is_pubkey_push(rest) {
+                    rest = &rest[rest[0] as usize + 1..];
+                    keys += 1;
+                }
+                if rest.is_empty() && Some(keys) == op(len - 2).small_int() {
+                    return ScriptPubKeyType::Multisig;
+                }
+            }
+        }
+        ScriptPubKeyType::NonStandard
+    }
+
+    /// Address of a single destination output, None for bare multisig, nulldata and
+    /// non standard outputs
+    pub fn address(&self, testnet: bool) -> Option<String> {
+        self.network_address(&Network::from_testnet(testnet))
+    }
+
+    /// `address` with the prefixes and HRP of any network, custom ones included
+    pub fn network_address(&self, network: &Network) -> Option<String> {
+        let content = &self.content;
+        match self.script_type() {
+            ScriptPubKeyType::PubKeyHash => Some(encode_base58_checksum(
+                &[&[network.p2pkh_prefix], &content[3..23]].concat(),
+            )),
+            ScriptPubKeyType::ScriptHash => Some(encode_base58_checksum(
+                &[&[network.p2sh_prefix], &content[2..22]].concat(),
+            )),
+            ScriptPubKeyType::WitnessV0KeyHash
+            | ScriptPubKeyType::WitnessV0ScriptHash
+            | ScriptPubKeyType::WitnessV1Taproot
+            | ScriptPubKeyType::WitnessUnknown(_) => {
+                let (version, program) = self.witness_program()?;
+                Some(encode_segwit_address(&network.bech32_hrp, version, program))
+            }
+            _ => None,
+        }
+    }
+
+    /// Output paying `address` of `network`, the inverse of `network_address`. None for
+    /// a malformed address or one of another network.
+    pub fn from_address(address: &str, network: &Network) -> Option<Self> {
+        let segwit_prefix = format!("{}1", network.bech32_hrp);
+        if address.to_lowercase().starts_with(&segwit_prefix) {
+            let (version, program) = decode_segwit_address(&network.bech32_hrp, address).ok()?;
+            let mut content = vec![if version == 0 { 0x00 } else { 0x50 + version }];
+            content.push(program.len() as u8);
+            content.extend_from_slice(&program);
+            return Some(ScriptPubKey { content });
+        }
+
+        let payload = decode_base58_checksum(address).ok()?;
+        if payload.len() != 21 {
+            return None;
+        }
+        let hash = &payload[1..];
+        let content = if payload[0] == network.p2pkh_prefix {
+            // OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG
+            [&[0x76, 0xa9, 0x14][..], hash, &[0x88, 0xac]].concat()
+        } else if payload[0] == network.p2sh_prefix {
+            // OP_HASH160 <20 bytes> OP_EQUAL
+            [&[0xa9, 0x14][..], hash, &[0x87]].concat()
+        } else {
+            return None;
+        };
+        Some(ScriptPubKey { content })
+    }
+
+    /// Script asm like Bitcoin Core, a malformed tail is shown as `[error]`
+    pub fn asm(&self) -> String {
+        let (script, bad_regions) = Script::parse_lossy(&self.content);
+        let asm = script.asm();
+        match (bad_regions.is_empty(), asm.is_empty()) {
+            (true, _) => asm,
+            (false, true) => "[error]".to_string(),
+            (false, false) => format!("{} [error]", asm),
+        }
     }
 }
 
@@ -43,3 +239,128 @@ impl Default for ScriptPubKey {
         ScriptPubKey { content: vec![] }
     }
 }
+
+impl Encodable for ScriptPubKey {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        self.serialize_into(buf);
+        buf.len() - start
+    }
+}
+
+impl Decodable for ScriptPubKey {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        ScriptPubKey::parse(input)
+    }
+}
+
+impl Serialize for ScriptPubKey {
+    fn serialize_bytes(&self) -> Vec<u8> {
+        self.serialize()
+    }
+}
+
+impl Parse for ScriptPubKey {
+    fn parse_bytes(bytes: &[u8]) -> Option<Self> {
+        match ScriptPubKey::parse(bytes) {
+            Ok((rest, script_pub_key)) if rest.is_empty() => Some(script_pub_key),
+            _ => None,
+        }
+    }
+}
+
+mod test {
+    use super::{ScriptPubKey, ScriptPubKeyType};
+
+    fn script_pub_key(hex: &str) -> ScriptPubKey {
+        ScriptPubKey {
+            content: hex::decode(hex).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_script_type_and_address() {
+        let p2pkh = script_pub_key("76a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac");
+        assert_eq!(p2pkh.script_type(), ScriptPubKeyType::PubKeyHash);
+        assert_eq!(
+            p2pkh.address(false),
+            Some("1JAHBxA51vwp5C2zpSB15VbxSZK3hVJs2H".to_string())
+        );
+        assert_eq!(
+            p2pkh.asm(),
+            "OP_DUP OP_HASH160 bc3b654dca7e56b04dca18f2566cdaf02e8d9ada OP_EQUALVERIFY OP_CHECKSIG"
+                .to_string()
+        );
+
+        let p2wpkh = script_pub_key("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        assert_eq!(p2wpkh.script_type(), ScriptPubKeyType::WitnessV0KeyHash);
+        let v2 = script_pub_key("5210751e76e8199196d454941c45d1b3a323");
+        assert_eq!(v2.script_type(), ScriptPubKeyType::WitnessUnknown(2));
+        assert_eq!(v2.script_type().to_string(), "witness_unknown");
+        assert_eq!(
+            p2wpkh.address(false),
+            Some("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".to_string())
+        );
+
+        let multisig = script_pub_key("512102c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee52102e493dbf1c10d80f3581e4904930b1404cc6c13900ee0758474fa94abe8c4cd1352ae");
+        assert_eq!(multisig.script_type(), ScriptPubKeyType::Multisig);
+        assert_eq!(multisig.address(false), None);
+
+        let null_data = script_pub_key("6a0568656c6c6f");
+        assert_eq!(null_data.script_type(), ScriptPubKeyType::NullData);
+        assert_eq!(
+            format!("{}", null_data.script_type()),
+            "nulldata".to_string()
+        );
+        assert_eq!(
+            script_pub_key("ac").script_type(),
+            ScriptPubKeyType::NonStandard
+        );
+    }
+
+    #[test]
+    fn test_from_address() {
+        use crate::network::Network;
+        use crate::transaction::TxOutput;
+
+        let mainnet = Network::mainnet();
+        for hex in &[
+            "76a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac",
+            "a914748284390f9e263a4b766a75d0633c50426eb87587",
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+        ] {
+            let expected = script_pub_key(hex);
+            let address = expected.address(false).unwrap();
+            assert_eq!(
+                ScriptPubKey::from_address(&address, &mainnet),
+                Some(expected.clone())
+            );
+            let testnet = expected.address(true).unwrap();
+            assert_eq!(ScriptPubKey::from_address(&testnet, &mainnet), None);
+            assert_eq!(
+                ScriptPubKey::from_address(&testnet, &Network::testnet()),
+                Some(expected)
+            );
+        }
+        assert_eq!(
+            ScriptPubKey::from_address("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", &mainnet),
+            Some(script_pub_key(
+                "0014751e76e8199196d454941c45d1b3a323f1433bd6"
+            ))
+        );
+        // a broken checksum
+        assert_eq!(
+            ScriptPubKey::from_address("1JAHBxA51vwp5C2zpSB15VbxSZK3hVJs2J", &mainnet),
+            None
+        );
+
+        let output =
+            TxOutput::to_address(50_000, "1JAHBxA51vwp5C2zpSB15VbxSZK3hVJs2H", &mainnet).unwrap();
+        assert_eq!(u64::from(output.amount), 50_000);
+        assert_eq!(
+            output.script_pub_key.script_type(),
+            ScriptPubKeyType::PubKeyHash
+        );
+    }
+}
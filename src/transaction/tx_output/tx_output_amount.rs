@@ -18,6 +18,12 @@ impl From<TxOutputAmount> for u64 {
     }
 }
 
+impl From<u64> for TxOutputAmount {
+    fn from(amount: u64) -> TxOutputAmount {
+        TxOutputAmount(amount)
+    }
+}
+
 impl TxOutputAmount {
     pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
         let (input, index) = le_u64(input)?;
@@ -4,6 +4,8 @@ use nom::{
     IResult,
 };
 
+use crate::encode::{Decodable, Encodable};
+
 #[derive(Debug, PartialOrd, PartialEq, Clone, Hash)]
 pub enum Varint {
     U8(u8),
@@ -66,6 +68,19 @@ impl Varint {
         Ok(buf.take().to_vec())
     }
 
+    /// Length of `Varint::encode(int)`
+    pub fn encoded_len(int: u64) -> usize {
+        if int < 0xfd_u64 {
+            1
+        } else if int < 0x10000_u64 {
+            3
+        } else if int < 0x100000000_u64 {
+            5
+        } else {
+            9
+        }
+    }
+
     pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
         let i = input[0];
         let (input, varint) = if i == 0xfd {
@@ -86,6 +101,57 @@ impl Varint {
     }
 }
 
+/// Smallest width holding `int`
+impl From<u64> for Varint {
+    fn from(int: u64) -> Self {
+        if int < 0xfd {
+            Varint::U8(int as u8)
+        } else if int < 0x10000 {
+            Varint::U16(int as u16)
+        } else if int < 0x1_0000_0000 {
+            Varint::U32(int as u32)
+        } else {
+            Varint::U64(int)
+        }
+    }
+}
+
+/// Keeps the width it was parsed with
+impl Encodable for Varint {
+    fn consensus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        match *self {
+            Varint::U8(int) => buf.push(int),
+            Varint::U16(int) => {
+                buf.push(0xfd);
+                buf.put_u16_le(int);
+            }
+            Varint::U32(int) => {
+                buf.push(0xfe);
+                buf.put_u32_le(int);
+            }
+            Varint::U64(int) => {
+                buf.push(0xff);
+                buf.put_u64_le(int);
+            }
+        }
+        match self {
+            Varint::U8(_) => 1,
+            Varint::U16(_) => 3,
+            Varint::U32(_) => 5,
+            Varint::U64(_) => 9,
+        }
+    }
+}
+
+impl Decodable for Varint {
+    fn consensus_decode(input: &[u8]) -> IResult<&[u8], Self> {
+        if input.is_empty() {
+            return Err(nom::Err::Incomplete(nom::Needed::Size(1)));
+        }
+        Varint::parse(input)
+    }
+}
+
 mod test {
     use super::Varint;
 
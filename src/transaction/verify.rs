@@ -0,0 +1,472 @@
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+
+use super::{OutPoint, ScriptPubKey, ScriptPubKeyType, Transaction, TxFetcher, TxInput, TxOutput};
+use crate::script::{
+    verify_taproot_input, LockTimeContext, Script, ScriptError, SigHashCache, VerifyFlags,
+};
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    #[fail(display = "input index {} out of range", _0)]
+    InputIndexOutOfRange(usize),
+    #[fail(display = "spent output {} could not be fetched", _0)]
+    MissingPrevout(OutPoint),
+    #[fail(display = "script error: {}", _0)]
+    Script(ScriptError),
+}
+
+impl From<ScriptError> for VerifyError {
+    fn from(err: ScriptError) -> Self {
+        VerifyError::Script(err)
+    }
+}
+
+fn fetch_prevout(
+    input: &TxInput,
+    fetcher: &TxFetcher,
+    testnet: bool,
+) -> Result<Arc<TxOutput>, VerifyError> {
+    input
+        .prevout(fetcher, testnet)
+        .ok_or_else(|| VerifyError::MissingPrevout(input.out_point()))
+}
+
+impl Transaction {
+    /// Verify that input `input_index` may spend its previous output, fetched from
+    /// `fetcher`. The script sig runs followed by the script pubkey, a P2SH redeem script
+    /// then runs on the items the script sig pushed, and witness programs, native or
+    /// nested in P2SH, are checked against the witness. Ok(false) when the scripts end on
+    /// a false result, Err when one fails on the way. The soft fork rules are the ones at
+    /// the chain tip, `VerifyFlags::consensus()`.
+    pub fn verify_input(
+        &self,
+        input_index: usize,
+        fetcher: &TxFetcher,
+    ) -> Result<bool, VerifyError> {
+        self.verify_input_with_flags(input_index, fetcher, VerifyFlags::consensus())
+    }
+
+    /// `verify_input` with exactly the rules in `flags`. `VerifyFlags::consensus_at_height`
+    /// checks a spend of a historical block, `VerifyFlags::consensus()` with policy flags
+    /// on top checks it the way a relaying node does. P2SH, segwit and taproot are always
+    /// checked.
+    pub fn verify_input_with_flags(
+        &self,
+        input_index: usize,
+        fetcher: &TxFetcher,
+        flags: VerifyFlags,
+    ) -> Result<bool, VerifyError> {
+        let input = self
+            .inputs
+            .get(input_index)
+            .ok_or(VerifyError::InputIndexOutOfRange(input_index))?;
+        if self.is_coinbase() {
+            return Ok(true);
+        }
+        let prevout = fetch_prevout(input, fetcher, self.testnet)?;
+        let amount = u64::from(prevout.amount);
+
+        match prevout.script_pub_key.witness_program() {
+            Some((1, program)) if program.len() == 32 => {
+                // the taproot sighash commits to every spent output
+                let prevouts = self
+                    .inputs
+                    .iter()
+                    .map(|input| fetch_prevout(input, fetcher, self.testnet).map(|p| (*p).clone()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(verify_taproot_input(self, input_index, &prevouts)?)
+            }
+            Some((version, program)) => {
+                if !input.script_sig.content.is_empty() {
+                    return Ok(false);
+                }
+                self.verify_witness_program(input_index, version, program, amount, flags)
+            }
+            None => self.verify_legacy(input_index, &prevout.script_pub_key, amount, flags),
+        }
+    }
+
+    /// Verify every input, false as soon as one does not verify
+    pub fn verify(&self, fetcher: &TxFetcher) -> Result<bool, VerifyError> {
+        self.verify_with_flags(fetcher, VerifyFlags::consensus())
+    }
+
+    /// `verify` with exactly the rules in `flags`, like `verify_input_with_flags`
+    pub fn verify_with_flags(
+        &self,
+        fetcher: &TxFetcher,
+        flags: VerifyFlags,
+    ) -> Result<bool, VerifyError> {
+        for input_index in 0..self.inputs.len() {
+            if !self.verify_input_with_flags(input_index, fetcher, flags)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn verify_legacy(
+        &self,
+        input_index: usize,
+        script_pub_key: &ScriptPubKey,
+        amount: u64,
+        flags: VerifyFlags,
+    ) -> Result<bool, VerifyError> {
+        let input = &self.inputs[input_index];
+        let script_sig = Script::try_from(&input.script_sig)?;
+        let script_pubkey = Script::try_from(script_pub_key)?;
+        let locks = LockTimeContext::new(self, input_index);
+        // the script sig runs on its own, with its own OP_CODESEPARATORs and limits, and
+        // hands its stack to the script pubkey
+        let mut items = script_sig.execute_script_sig(
+            &mut |sighash_type, codesep_pos| {
+                let script_code = ScriptPubKey {
+                    content: script_sig.script_code(codesep_pos),
+                };
+                self.sig_hash(input_index, &script_code, sighash_type)
+            },
+            Some(&locks),
+            flags,
+        )?;
+        let mut cache = SigHashCache::new(self, input_index, &script_pubkey);
+        let verified = script_pubkey.evaluate_witness(
+            &items,
+            &mut |sighash_type, codesep_pos| cache.sig_hash(sighash_type, codesep_pos),
+            Some(&locks),
+            flags,
+        )?;
+        if !verified {
+            return Ok(false);
+        }
+        if script_pub_key.script_type() != ScriptPubKeyType::ScriptHash {
+            return Ok(input.witness.is_empty());
+        }
+
+        // BIP16, the last push of the push only script sig is the redeem script the script
+        // pubkey hashed
+        if !script_sig.is_push_only() {
+            return Ok(false);
+        }
+        let redeem = match items.pop() {
+            Some(redeem) => ScriptPubKey { content: redeem },
+            None => return Ok(false),
+        };
+        if let Some((version, program)) = redeem.witness_program() {
+            // nested segwit, the script sig pushes nothing but the program
+            if !items.is_empty() {
+                return Ok(false);
+            }
+            return self.verify_witness_program(input_index, version, program, amount, flags);
+        }
+
+        let redeem = Script::try_from(&redeem)?;
+        let verified = redeem.evaluate_witness(
+            &items,
+            &mut |sighash_type, codesep_pos| {
+                let script_code = ScriptPubKey {
+                    content: redeem.script_code(codesep_pos),
+                };
+                self.sig_hash(input_index, &script_code, sighash_type)
+            },
+            Some(&locks),
+            flags,
+        )?;
+        Ok(verified && input.witness.is_empty())
+    }
+
+    fn verify_witness_program(
+        &self,
+        input_index: usize,
+        version: u8,
+        program: &[u8],
+        amount: u64,
+        flags: VerifyFlags,
+    ) -> Result<bool, VerifyError> {
+        if version != 0 {
+            // unknown versions are left for future soft forks
+            if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM) {
+                return Err(ScriptError::DiscourageUpgradableWitnessProgram(version).into());
+            }
+            return Ok(true);
+        }
+        let witness = &self.inputs[input_index].witness;
+        let (witness_script, items) = match program.len() {
+            20 if witness.len() == 2 => (
+                [&[0x76, 0xa9, 0x14][..], program, &[0x88, 0xac]].concat(),
+                &witness[..],
+            ),
+            32 => match witness.split_last() {
+                Some((witness_script, items)) if Sha256::digest(witness_script)[..] == *program => {
+                    (witness_script.clone(), items)
+                }
+                _ => return Ok(false),
+            },
+            _ => return Ok(false),
+        };
+
+        let script = Script::from_raw(&witness_script)?;
+        Ok(script.evaluate_witness(
+            items,
+            &mut |sighash_type, codesep_pos| {
+                let script_code = ScriptPubKey {
+                    content: script.witness_script_code(codesep_pos),
+                };
+                self.sig_hash_segwit_v0(input_index, &script_code, amount, sighash_type)
+            },
+            Some(&LockTimeContext::new(self, input_index)),
+            flags | VerifyFlags::CLEANSTACK,
+        )?)
+    }
+}
+
+mod test {
+    use super::VerifyError;
+    use crate::script::{ScriptError, VerifyFlags};
+    use crate::transaction::{
+        OutPoint, ScriptPubKey, Transaction, TxFetcher, TxHash, TxInput, TxLocktime, TxOutput,
+        TxVersion, SIGHASH_ALL,
+    };
+    use crate::wallet::{hash160, Hash256, PrivateKey, U256};
+    use sha2::{Digest, Sha256};
+
+    fn funding(outputs: Vec<ScriptPubKey>) -> Transaction {
+        Transaction::new(
+            TxVersion::new(2),
+            vec![TxInput::builder(OutPoint::new(TxHash::new(&[3; 32]).unwrap().1, 0)).build()],
+            outputs
+                .into_iter()
+                .map(|script_pub_key| TxOutput {
+                    amount: 50_000.into(),
+                    script_pub_key,
+                })
+                .collect(),
+            TxLocktime::new(0),
+            false,
+        )
+    }
+
+    fn der_sig(key: &PrivateKey, z: Hash256) -> Vec<u8> {
+        let mut sig = key.sign(U256::from_little_endian(&z)).der();
+        sig.push(SIGHASH_ALL as u8);
+        sig
+    }
+
+    fn push(data: &[u8]) -> Vec<u8> {
+        [&[data.len() as u8][..], data].concat()
+    }
+
+    #[test]
+    fn test_verify_input() {
+        let key = PrivateKey::new(U256::from(8_675_309u32));
+        let other = PrivateKey::new(U256::from(31_337u32));
+        let sec = key.point.compressed_sec().to_vec();
+        // 1 of 2 multisig redeem script
+        let redeem = [
+            &[0x51, 0x21][..],
+            &other.point.compressed_sec(),
+            &[0x21],
+            &sec,
+            &[0x52, 0xae],
+        ]
+        .concat();
+        let p2sh = ScriptPubKey {
+            content: [&[0xa9, 0x14][..], &hash160(&redeem), &[0x87]].concat(),
+        };
+        let prev = funding(vec![
+            key.point.p2pkh_script(),
+            key.point.p2wpkh_script(),
+            key.point.p2sh_p2wpkh_script(),
+            p2sh.clone(),
+        ]);
+        let fetcher = TxFetcher::new();
+        fetcher.insert(prev.clone());
+
+        let mut tx = Transaction::new(
+            TxVersion::new(2),
+            (0..4)
+                .map(|vout| TxInput::builder(OutPoint::new(prev.id(), vout)).build())
+                .collect(),
+            vec![TxOutput {
+                amount: 190_000.into(),
+                script_pub_key: key.point.p2wpkh_script(),
+            }],
+            TxLocktime::new(0),
+            false,
+        );
+        let p2pkh_code = key.point.p2pkh_script();
+
+        let z = tx.sig_hash(0, &prev.outputs[0].script_pub_key, SIGHASH_ALL);
+        tx.inputs[0].script_sig.content = [push(&der_sig(&key, z)), push(&sec)].concat();
+        let z = tx.sig_hash_segwit_v0(1, &p2pkh_code, 50_000, SIGHASH_ALL);
+        tx.inputs[1].witness = vec![der_sig(&key, z), sec.clone()];
+        let z = tx.sig_hash_segwit_v0(2, &p2pkh_code, 50_000, SIGHASH_ALL);
+        tx.inputs[2].script_sig.content = push(&key.point.p2wpkh_script().content);
+        tx.inputs[2].witness = vec![der_sig(&key, z), sec.clone()];
+        let z = tx.sig_hash(
+            3,
+            &ScriptPubKey {
+                content: redeem.clone(),
+            },
+            SIGHASH_ALL,
+        );
+        // OP_0 for the CHECKMULTISIG off by one
+        tx.inputs[3].script_sig.content =
+            [vec![0x00], push(&der_sig(&key, z)), push(&redeem)].concat();
+
+        for input_index in 0..4 {
+            assert_eq!(tx.verify_input(input_index, &fetcher), Ok(true));
+        }
+        assert_eq!(tx.verify(&fetcher), Ok(true));
+        assert_eq!(
+            tx.verify_input(4, &fetcher),
+            Err(VerifyError::InputIndexOutOfRange(4))
+        );
+
+        // a non empty multisig dummy was valid until BIP147 activated
+        let mut dummy = tx.clone();
+        dummy.inputs[3].script_sig.content[0] = 0x51;
+        assert_eq!(
+            dummy.verify_input(3, &fetcher),
+            Err(VerifyError::Script(ScriptError::SigNullDummy))
+        );
+        let historical = VerifyFlags::consensus_at_height(481_823, false);
+        assert_eq!(
+            dummy.verify_input_with_flags(3, &fetcher, historical),
+            Ok(true)
+        );
+        assert_eq!(dummy.verify_with_flags(&fetcher, historical), Ok(true));
+
+        // signatures commit to the outputs
+        let mut changed = tx.clone();
+        changed.outputs[0].amount = 189_000.into();
+        assert_eq!(changed.verify(&fetcher), Ok(false));
+        for input_index in 1..4 {
+            assert_eq!(changed.verify_input(input_index, &fetcher), Ok(false));
+        }
+        // a witness key hash spend with a key that does not hash to the program
+        let mut wrong_key = tx.clone();
+        wrong_key.inputs[1].witness[1] = other.point.compressed_sec().to_vec();
+        assert!(wrong_key.verify_input(1, &fetcher).is_err());
+
+        let mut unknown = tx.clone();
+        unknown.inputs[0] = TxInput::builder(OutPoint::new(prev.id(), 9)).build();
+        assert_eq!(
+            unknown.verify_input(0, &fetcher),
+            Err(VerifyError::MissingPrevout(OutPoint::new(prev.id(), 9)))
+        );
+    }
+
+    #[test]
+    fn test_codeseparator_script_code() {
+        let key = PrivateKey::new(U256::from(8_675_309u32));
+        let sec = key.point.compressed_sec().to_vec();
+        let p2pk = ScriptPubKey {
+            content: [push(&sec), vec![0xac]].concat(),
+        };
+        // <pk> OP_CHECKSIGVERIFY OP_CODESEPARATOR <pk> OP_CHECKSIG
+        let witness_script = [push(&sec), vec![0xad, 0xab], p2pk.content.clone()].concat();
+        let p2wsh = ScriptPubKey {
+            content: [&[0x00, 0x20][..], &Sha256::digest(&witness_script)].concat(),
+        };
+        let prev = funding(vec![p2pk.clone(), p2wsh]);
+        let fetcher = TxFetcher::new();
+        fetcher.insert(prev.clone());
+        let mut tx = Transaction::new(
+            TxVersion::new(2),
+            (0..2)
+                .map(|vout| TxInput::builder(OutPoint::new(prev.id(), vout)).build())
+                .collect(),
+            vec![],
+            TxLocktime::new(0),
+            false,
+        );
+
+        // an OP_CODESEPARATOR in the script sig does not cut the script pubkey
+        let z = tx.sig_hash(0, &p2pk, SIGHASH_ALL);
+        tx.inputs[0].script_sig.content = [vec![0xab], push(&der_sig(&key, z))].concat();
+        // the BIP143 script code keeps the OP_CODESEPARATORs after the last one executed
+        let whole = ScriptPubKey {
+            content: witness_script.clone(),
+        };
+        let first = der_sig(&key, tx.sig_hash_segwit_v0(1, &whole, 50_000, SIGHASH_ALL));
+        let second = der_sig(&key, tx.sig_hash_segwit_v0(1, &p2pk, 50_000, SIGHASH_ALL));
+        tx.inputs[1].witness = vec![second, first, witness_script];
+
+        assert_eq!(tx.verify_input(0, &fetcher), Ok(true));
+        assert_eq!(tx.verify_input(1, &fetcher), Ok(true));
+    }
+
+    #[test]
+    fn test_witness_clean_stack() {
+        // OP_1 as witness script and as P2SH redeem script
+        let script = vec![0x51];
+        let p2wsh = ScriptPubKey {
+            content: [&[0x00, 0x20][..], &Sha256::digest(&script)].concat(),
+        };
+        let p2sh = ScriptPubKey {
+            content: [&[0xa9, 0x14][..], &hash160(&script), &[0x87]].concat(),
+        };
+        let prev = funding(vec![p2wsh, p2sh]);
+        let fetcher = TxFetcher::new();
+        fetcher.insert(prev.clone());
+        let mut tx = Transaction::new(
+            TxVersion::new(2),
+            (0..2)
+                .map(|vout| TxInput::builder(OutPoint::new(prev.id(), vout)).build())
+                .collect(),
+            vec![],
+            TxLocktime::new(0),
+            false,
+        );
+
+        tx.inputs[0].witness = vec![script.clone()];
+        assert_eq!(tx.verify_input(0, &fetcher), Ok(true));
+        tx.inputs[0].witness = vec![vec![0x01], script.clone()];
+        assert_eq!(
+            tx.verify_input(0, &fetcher),
+            Err(VerifyError::Script(ScriptError::CleanStack))
+        );
+        // only a policy for legacy scripts
+        tx.inputs[1].script_sig.content = [push(&[0x01]), push(&script)].concat();
+        assert_eq!(tx.verify_input(1, &fetcher), Ok(true));
+    }
+
+    #[test]
+    fn test_upgradable_witness_program() {
+        // version 2, then version 1 nested in P2SH, which is no taproot output
+        let v2 = ScriptPubKey {
+            content: [&[0x52, 0x10][..], &[7; 16]].concat(),
+        };
+        let v1 = [&[0x51, 0x20][..], &[7; 32]].concat();
+        let p2sh_v1 = ScriptPubKey {
+            content: [&[0xa9, 0x14][..], &hash160(&v1), &[0x87]].concat(),
+        };
+        let prev = funding(vec![v2, p2sh_v1]);
+        let fetcher = TxFetcher::new();
+        fetcher.insert(prev.clone());
+        let mut tx = Transaction::new(
+            TxVersion::new(2),
+            (0..2)
+                .map(|vout| TxInput::builder(OutPoint::new(prev.id(), vout)).build())
+                .collect(),
+            vec![],
+            TxLocktime::new(0),
+            false,
+        );
+        tx.inputs[1].script_sig.content = push(&v1);
+
+        let policy = VerifyFlags::consensus() | VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM;
+        for (input_index, version) in [(0, 2), (1, 1)].iter() {
+            assert_eq!(tx.verify_input(*input_index, &fetcher), Ok(true));
+            assert_eq!(
+                tx.verify_input_with_flags(*input_index, &fetcher, policy),
+                Err(VerifyError::Script(
+                    ScriptError::DiscourageUpgradableWitnessProgram(*version)
+                ))
+            );
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use std::fmt::Display;
+
+use crate::block::BlockHeader;
+
+/// Top 3 bits of a header version taking part in version bits signaling
+pub const VERSIONBITS_TOP_BITS: u32 = 0x2000_0000;
+pub const VERSIONBITS_TOP_MASK: u32 = 0xe000_0000;
+/// Blocks in a retarget window
+pub const RETARGET_PERIOD: u32 = 2016;
+
+/// BIP9 deployment state, the same for every block of a window
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum ThresholdState {
+    /// Before the start time
+    Defined,
+    /// Counting signals
+    Started,
+    /// Enough signals, active from the next window
+    LockedIn,
+    Active,
+    /// Timed out before locking in
+    Failed,
+}
+impl Copy for ThresholdState {}
+
+impl Display for ThresholdState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ThresholdState::Defined => "defined",
+            ThresholdState::Started => "started",
+            ThresholdState::LockedIn => "locked_in",
+            ThresholdState::Active => "active",
+            ThresholdState::Failed => "failed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A soft fork signaled on one version bit
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Deployment {
+    pub name: String,
+    /// 0 to 28
+    pub bit: u8,
+    /// Median time past from which signals count
+    pub start_time: u32,
+    /// Median time past after which an unlocked deployment fails
+    pub timeout: u32,
+    /// Locked in deployments wait for this height to activate, BIP341 speedy trial
+    pub min_activation_height: u32,
+}
+
+impl Deployment {
+    /// Whether `version` sets this deployment's bit with the version bits top bits
+    pub fn signals(&self, version: u32) -> bool {
+        version & VERSIONBITS_TOP_MASK == VERSIONBITS_TOP_BITS && version >> self.bit & 1 == 1
+    }
+}
+
+/// Signaling of a deployment in the window of some block, like `getdeploymentinfo`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SignalStats {
+    pub period: u32,
+    pub threshold: u32,
+    /// Blocks of the window so far
+    pub elapsed: u32,
+    /// Signaling blocks among them
+    pub count: u32,
+    /// Whether the threshold can still be reached in this window
+    pub possible: bool,
+}
+impl Copy for SignalStats {}
+
+/// Version bits deployment tracking over headers, `headers[h]` being the block at height h
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VersionBits {
+    pub period: u32,
+    /// Signaling blocks a window needs to lock in
+    pub threshold: u32,
+    pub deployments: Vec<Deployment>,
+}
+
+impl VersionBits {
+    pub fn new(period: u32, threshold: u32) -> Self {
+        VersionBits {
+            period,
+            threshold,
+            deployments: Vec::new(),
+        }
+    }
+
+    /// 90% of a retarget window, the threshold taproot used
+    pub fn mainnet() -> Self {
+        VersionBits::new(RETARGET_PERIOD, 1815)
+    }
+
+    /// 75% of a retarget window
+    pub fn testnet() -> Self {
+        VersionBits::new(RETARGET_PERIOD, 1512)
+    }
+
+    pub fn with_deployment(mut self, deployment: Deployment) -> Self {
+        self.deployments.push(deployment);
+        self
+    }
+
+    /// State of `deployment` for the block at `height`, which may be the one after the
+    /// last header. Windows before it are walked from the start of `headers`.
+    pub fn state(
+        &self,
+        headers: &[BlockHeader],
+        deployment: &Deployment,
+        height: u32,
+    ) -> ThresholdState {
+        let window_start = height - height % self.period;
+        let mut state = ThresholdState::Defined;
+        // the state of a window follows from the last block of the window before
+        for last in (self.period - 1..window_start).step_by(self.period as usize) {
+            let time = median_time_past(headers, last);
+            state = match state {
+                ThresholdState::Defined if time >= deployment.timeout => ThresholdState::Failed,
+                ThresholdState::Defined if time >= deployment.start_time => ThresholdState::Started,
+                ThresholdState::Started => {
+                    let first = last + 1 - self.period;
+                    if self.count(headers, deployment, first, last + 1) >= self.threshold {
+                        ThresholdState::LockedIn
+                    } else if time >= deployment.timeout {
+                        ThresholdState::Failed
+                    } else {
+                        ThresholdState::Started
+                    }
+                }
+                ThresholdState::LockedIn if last + 1 >= deployment.min_activation_height => {
+                    ThresholdState::Active
+                }
+                state => state,
+            };
+        }
+        state
+    }
+
+    /// States of every deployment for the block after the last header
+    pub fn states(&self, headers: &[BlockHeader]) -> Vec<(&Deployment, ThresholdState)> {
+        self.deployments
+            .iter()
+            .map(|deployment| {
+                (
+                    deployment,
+                    self.state(headers, deployment, headers.len() as u32),
+                )
+            })
+            .collect()
+    }
+
+    /// Signals for `deployment` in the window of the block at `height`, up to that block
+    pub fn stats(
+        &self,
+        headers: &[BlockHeader],
+        deployment: &Deployment,
+        height: u32,
+    ) -> SignalStats {
+        let first = height - height % self.period;
+        let elapsed = height + 1 - first;
+        let count = self.count(headers, deployment, first, height + 1);
+        SignalStats {
+            period: self.period,
+            threshold: self.threshold,
+            elapsed,
+            count,
+            possible: self.period - elapsed >= self.threshold.saturating_sub(count),
+        }
+    }
+
+    /// Signaling headers in heights [from, to), missing ones do not signal
+    fn count(&self, headers: &[BlockHeader], deployment: &Deployment, from: u32, to: u32) -> u32 {
+        headers
+            .iter()
+            .take(to as usize)
+            .skip(from as usize)
+            .filter(|header| deployment.signals(header.version))
+            .count() as u32
+    }
+}
+
+/// Median timestamp of the block at `height` and up to 10 before it
+pub fn median_time_past(headers: &[BlockHeader], height: u32) -> u32 {
+    let end = (height as usize + 1).min(headers.len());
+    let mut times: Vec<u32> = headers[end.saturating_sub(11)..end]
+        .iter()
+        .map(|header| header.timestamp)
+        .collect();
+    if times.is_empty() {
+        return 0;
+    }
+    times.sort();
+    times[times.len() / 2]
+}
+
+mod test {
+    use super::{median_time_past, Deployment, ThresholdState, VersionBits};
+    use crate::block::{BlockHash, BlockHeader};
+
+    fn header(version: u32, timestamp: u32) -> BlockHeader {
+        BlockHeader {
+            version,
+            prev_block: BlockHash::default(),
+            merkle_root: [0u8; 32],
+            timestamp,
+            bits: 0x207f_ffff,
+            nonce: 0,
+        }
+    }
+
+    /// Windows of 4 blocks, one timestamp per window and `signals` signaling blocks in each
+    fn chain(windows: &[(u32, u32)]) -> Vec<BlockHeader> {
+        windows
+            .iter()
+            .flat_map(|&(time, signals)| {
+                (0..4).map(move |i| {
+                    let version = if i < signals {
+                        0x2000_0004
+                    } else {
+                        0x2000_0000
+                    };
+                    header(version, time)
+                })
+            })
+            .collect()
+    }
+
+    fn deployment(min_activation_height: u32) -> Deployment {
+        Deployment {
+            name: "testdummy".to_string(),
+            bit: 2,
+            start_time: 100,
+            timeout: 1000,
+            min_activation_height,
+        }
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        let headers: Vec<BlockHeader> = [5, 1, 4, 2, 3].iter().map(|&t| header(1, t)).collect();
+        assert_eq!(median_time_past(&headers, 0), 5);
+        assert_eq!(median_time_past(&headers, 4), 3);
+        assert_eq!(median_time_past(&[], 0), 0);
+    }
+
+    #[test]
+    fn test_activation() {
+        let bits = VersionBits::new(4, 3).with_deployment(deployment(0));
+        let deployment = &bits.deployments[0];
+        // defined, started, one short, locked in, active
+        let headers = chain(&[(50, 4), (100, 0), (200, 2), (300, 3), (400, 0)]);
+        let states: Vec<ThresholdState> = (0..6)
+            .map(|window| bits.state(&headers, deployment, window * 4))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ThresholdState::Defined,
+                ThresholdState::Defined,
+                ThresholdState::Started,
+                ThresholdState::Started,
+                ThresholdState::LockedIn,
+                ThresholdState::Active,
+            ]
+        );
+        assert_eq!(bits.states(&headers)[0].1, ThresholdState::Active);
+        // signals outside the top bits scheme do not count
+        assert!(!deployment.signals(0x0000_0004));
+
+        let stats = bits.stats(&headers, deployment, 9);
+        assert_eq!((stats.elapsed, stats.count), (2, 2));
+        assert!(stats.possible);
+        let stats = bits.stats(&headers, deployment, 11);
+        assert_eq!((stats.elapsed, stats.count), (4, 2));
+        assert!(!stats.possible);
+
+        // speedy trial, locked in until height 24
+        let delayed = Deployment {
+            min_activation_height: 24,
+            ..deployment.clone()
+        };
+        let headers = chain(&[(100, 0), (200, 4), (300, 0), (400, 0), (500, 0)]);
+        assert_eq!(bits.state(&headers, &delayed, 12), ThresholdState::LockedIn);
+        assert_eq!(bits.state(&headers, &delayed, 20), ThresholdState::LockedIn);
+        assert_eq!(bits.state(&headers, &delayed, 24), ThresholdState::Active);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let bits = VersionBits::new(4, 3);
+        let deployment = deployment(0);
+        let headers = chain(&[(100, 0), (500, 2), (1000, 2), (1000, 2), (1100, 4)]);
+        assert_eq!(
+            bits.state(&headers, &deployment, 12),
+            ThresholdState::Started
+        );
+        assert_eq!(
+            bits.state(&headers, &deployment, 16),
+            ThresholdState::Failed
+        );
+        assert_eq!(
+            bits.state(&headers, &deployment, 20),
+            ThresholdState::Failed
+        );
+
+        // a window reaching the threshold locks in even when it ends past the timeout
+        let headers = chain(&[(100, 0), (1000, 4)]);
+        assert_eq!(
+            bits.state(&headers, &deployment, 8),
+            ThresholdState::LockedIn
+        );
+
+        // timed out before it even started
+        let headers = chain(&[(2000, 4), (2000, 4)]);
+        assert_eq!(bits.state(&headers, &deployment, 8), ThresholdState::Failed);
+    }
+}
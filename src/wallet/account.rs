@@ -0,0 +1,396 @@
+use super::bech32::encode_segwit_address;
+use super::extended_key::{
+    DeriveError, ExtendedKeyError, ExtendedPubKey, ScriptType, HARDENED_INDEX,
+};
+use super::key_source::KeySource;
+use super::secp256k1::utils::encode_base58_checksum;
+use super::{hash160, S256Point};
+use crate::transaction::{OutPoint, ScriptBackend, ScriptPubKey};
+use failure::Error;
+use std::str::FromStr;
+
+const RECEIVE_CHAIN: u32 = 0;
+const CHANGE_CHAIN: u32 = 1;
+
+/// An unspent output found by an account scan, with the key that receives it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedUtxo {
+    pub out_point: OutPoint,
+    pub amount: u64,
+    /// 0 while unconfirmed
+    pub height: u32,
+    pub change: bool,
+    pub index: u32,
+}
+
+/// What a restore scan found, the indices are None for a chain never used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountScan {
+    pub utxos: Vec<ScannedUtxo>,
+    pub last_receive: Option<u32>,
+    pub last_change: Option<u32>,
+}
+
+impl AccountScan {
+    pub fn balance(&self) -> u64 {
+        self.utxos.iter().map(|utxo| utxo.amount).sum()
+    }
+
+    /// First receive index after the last used one
+    pub fn next_receive(&self) -> u32 {
+        self.last_receive.map_or(0, |index| index + 1)
+    }
+
+    pub fn next_change(&self) -> u32 {
+        self.last_change.map_or(0, |index| index + 1)
+    }
+}
+
+/// Watch-only account built from an account level extended public key (m/purpose'/coin'/account').
+/// It derives receive and change addresses and their script pubkeys, so balances can be
+/// monitored on an online machine while the signing keys stay somewhere else.
+/// The address type follows the SLIP-132 version of the key: xpub P2PKH, ypub P2SH-P2WPKH
+/// and zpub P2WPKH.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    xpub: ExtendedPubKey,
+    origin: Option<KeySource>,
+    receive: ExtendedPubKey,
+    change: ExtendedPubKey,
+}
+
+impl Account {
+    /// Fails in the unlikely case that a chain key is invalid, the account is unusable then
+    pub fn new(xpub: ExtendedPubKey) -> Result<Self, DeriveError> {
+        Ok(Account {
+            xpub,
+            origin: None,
+            receive: xpub.derive_child(RECEIVE_CHAIN)?,
+            change: xpub.derive_child(CHANGE_CHAIN)?,
+        })
+    }
+
+    /// Account whose xpub was derived at `origin`, e.g. `[d34db33f/84'/0'/0']`
+    pub fn with_origin(xpub: ExtendedPubKey, origin: KeySource) -> Result<Self, DeriveError> {
+        Ok(Account {
+            origin: Some(origin),
+            ..Account::new(xpub)?
+        })
+    }
+
+    pub fn xpub(&self) -> &ExtendedPubKey {
+        &self.xpub
+    }
+
+    pub fn origin(&self) -> Option<&KeySource> {
+        self.origin.as_ref()
+    }
+
+    pub fn testnet(&self) -> bool {
+        self.xpub.testnet
+    }
+
+    pub fn script_type(&self) -> ScriptType {
+        self.xpub.script_type
+    }
+
+    /// Key `index` of a chain, an error for an index of 2^31 or more or an invalid key,
+    /// which a wallet skips
+    pub fn public_key(&self, change: bool, index: u32) -> Result<S256Point, DeriveError> {
+        if index >= HARDENED_INDEX {
+            return Err(DeriveError::ChildNumberOverflow(index));
+        }
+        let chain = if change { &self.change } else { &self.receive };
+        Ok(chain.derive_child(index)?.public_key)
+    }
+
+    /// Full origin of a derived key, known only when the account origin is
+    pub fn key_source(&self, change: bool, index: u32) -> Option<KeySource> {
+        let chain = if change { CHANGE_CHAIN } else { RECEIVE_CHAIN };
+        self.origin
+            .as_ref()
+            .map(|origin| origin.child(chain).child(index))
+    }
+
+    pub fn address(&self, change: bool, index: u32) -> Result<String, DeriveError> {
+        let point = self.public_key(change, index)?;
+        Ok(key_hash_address(
+            &point.hash160(true),
+            self.xpub.testnet,
+            self.xpub.script_type,
+        ))
+    }
+
+    pub fn receive_address(&self, index: u32) -> Result<String, DeriveError> {
+        self.address(false, index)
+    }
+
+    pub fn change_address(&self, index: u32) -> Result<String, DeriveError> {
+        self.address(true, index)
+    }
+
+    /// Script pubkey paying to the derived key, used to recognize our outputs
+    pub fn script_pubkey(&self, change: bool, index: u32) -> Result<ScriptPubKey, DeriveError> {
+        let point = self.public_key(change, index)?;
+        Ok(match self.xpub.script_type {
+            ScriptType::P2pkh => point.p2pkh_script(),
+            ScriptType::P2shP2wpkh => point.p2sh_p2wpkh_script(),
+            ScriptType::P2wpkh => point.p2wpkh_script(),
+        })
+    }
+
+    /// Wallet restore, derives the receive then the change addresses in order until
+    /// `gap_limit` of them in a row have no history, 20 in BIP44, and collects the unspent
+    /// outputs of the used ones
+    pub fn scan<B: ScriptBackend>(
+        &self,
+        backend: &mut B,
+        gap_limit: u32,
+    ) -> Result<AccountScan, Error> {
+        let mut utxos = Vec::new();
+        let last_receive = self.scan_chain(backend, false, gap_limit, &mut utxos)?;
+        let last_change = self.scan_chain(backend, true, gap_limit, &mut utxos)?;
+        Ok(AccountScan {
+            utxos,
+            last_receive,
+            last_change,
+        })
+    }
+
+    /// Last used index of one chain, every used address moves the end of the scan
+    fn scan_chain<B: ScriptBackend>(
+        &self,
+        backend: &mut B,
+        change: bool,
+        gap_limit: u32,
+        utxos: &mut Vec<ScannedUtxo>,
+    ) -> Result<Option<u32>, Error> {
+        let mut last_used = None;
+        let mut index = 0;
+        while index < last_used.map_or(0, |used| used + 1) + gap_limit {
+            let script_pub_key = match self.script_pubkey(change, index) {
+                Ok(script_pub_key) => script_pub_key,
+                // BIP32 skips an index without a valid key
+                Err(DeriveError::InvalidChild(_)) => {
+                    index += 1;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if !backend
+                .script_history(&script_pub_key, self.testnet())?
+                .is_empty()
+            {
+                last_used = Some(index);
+                let found = backend.script_utxos(&script_pub_key, self.testnet())?;
+                utxos.extend(
+                    found
+                        .into_iter()
+                        .map(|(out_point, amount, height)| ScannedUtxo {
+                            out_point,
+                            amount,
+                            height,
+                            change,
+                            index,
+                        }),
+                );
+            }
+            index += 1;
+        }
+        Ok(last_used)
+    }
+}
+
+/// OP_0 <20 bytes>, the P2WPKH script pubkey and the P2SH-P2WPKH redeem script
+fn key_hash_witness_program(key_hash: &[u8]) -> Vec<u8> {
+    let mut content = Vec::with_capacity(22);
+    content.extend_from_slice(&[0x00, 0x14]);
+    content.extend_from_slice(key_hash);
+    content
+}
+
+/// Address of the compressed key hashing to `key_hash` for `script_type`
+pub(crate) fn key_hash_address(key_hash: &[u8], testnet: bool, script_type: ScriptType) -> String {
+    match script_type {
+        ScriptType::P2pkh => {
+            let prefix = if testnet { 0x6f } else { 0x00 };
+            let mut bytes = vec![prefix];
+            bytes.extend_from_slice(key_hash);
+            encode_base58_checksum(&bytes)
+        }
+        ScriptType::P2shP2wpkh => {
+            let prefix = if testnet { 0xc4 } else { 0x05 };
+            let mut bytes = vec![prefix];
+            bytes.extend_from_slice(&hash160(&key_hash_witness_program(key_hash)));
+            encode_base58_checksum(&bytes)
+        }
+        ScriptType::P2wpkh => {
+            let hrp = if testnet { "tb" } else { "bc" };
+            encode_segwit_address(hrp, 0, key_hash)
+        }
+    }
+}
+
+impl FromStr for Account {
+    type Err = ExtendedKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Account::new(ExtendedPubKey::from_str(s)?)?)
+    }
+}
+
+mod test {
+    use super::super::extended_key::{DeriveError, ExtendedPubKey, HARDENED_INDEX};
+    use super::super::key_source::KeySource;
+    use super::Account;
+    use crate::transaction::{OutPoint, ScriptBackend, ScriptPubKey, TxHash};
+    use failure::Error;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    // m/44'/0'/0' of the "abandon abandon ... about" mnemonic
+    const ACCOUNT_XPUB: &str = "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj";
+
+    #[test]
+    fn test_account_address() {
+        let account = Account::from_str(ACCOUNT_XPUB).unwrap();
+        assert!(!account.testnet());
+        assert_eq!(
+            account.receive_address(0).unwrap(),
+            "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA".to_string()
+        );
+    }
+
+    #[test]
+    fn test_account_slip132_address() {
+        // m/84'/0'/0' of the same mnemonic
+        let account = Account::from_str("zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs").unwrap();
+        assert_eq!(
+            account.receive_address(0).unwrap(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu".to_string()
+        );
+        assert_eq!(
+            account.change_address(0).unwrap(),
+            "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el".to_string()
+        );
+        assert_eq!(account.script_pubkey(false, 0).unwrap().content.len(), 22);
+
+        // m/49'/0'/0' of the same mnemonic
+        let account = Account::from_str("ypub6Ww3ibxVfGzLrAH1PNcjyAWenMTbbAosGNB6VvmSEgytSER9azLDWCxoJwW7Ke7icmizBMXrzBx9979FfaHxHcrArf3zbeJJJUZPf663zsP").unwrap();
+        assert_eq!(
+            account.receive_address(0).unwrap(),
+            "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf".to_string()
+        );
+        assert_eq!(account.script_pubkey(false, 0).unwrap().content.len(), 23);
+    }
+
+    #[test]
+    fn test_account_script_pubkey() {
+        let account = Account::from_str(ACCOUNT_XPUB).unwrap();
+        let script_pubkey = account.script_pubkey(true, 0).unwrap();
+        let h160 = account.public_key(true, 0).unwrap().hash160(true);
+
+        assert_eq!(script_pubkey.content.len(), 25);
+        assert_eq!(&script_pubkey.content[3..23], &h160[..]);
+        assert_eq!(
+            account.script_pubkey(true, HARDENED_INDEX),
+            Err(DeriveError::ChildNumberOverflow(HARDENED_INDEX))
+        );
+    }
+
+    #[test]
+    fn test_account_key_source() {
+        let account = Account::from_str(ACCOUNT_XPUB).unwrap();
+        assert_eq!(account.key_source(false, 0), None);
+
+        let origin = KeySource::from_str("[73c5da0a/44'/0'/0']").unwrap();
+        let xpub = ExtendedPubKey::from_str(ACCOUNT_XPUB).unwrap();
+        let account = Account::with_origin(xpub, origin).unwrap();
+        let source = account.key_source(true, 3).unwrap();
+        assert_eq!(
+            source.path,
+            vec![44 + HARDENED_INDEX, HARDENED_INDEX, HARDENED_INDEX, 1, 3].into()
+        );
+        assert_eq!(
+            format!("{}", source),
+            "[73c5da0a/44'/0'/0'/1/3]".to_string()
+        );
+    }
+
+    /// Used scripts with their unspent outputs, counting queries
+    struct MockBackend {
+        used: HashMap<Vec<u8>, Vec<(OutPoint, u64, u32)>>,
+        queries: u32,
+    }
+
+    impl ScriptBackend for MockBackend {
+        fn script_history(
+            &mut self,
+            script_pub_key: &ScriptPubKey,
+            _testnet: bool,
+        ) -> Result<Vec<TxHash>, Error> {
+            self.queries += 1;
+            Ok(match self.used.get(&script_pub_key.content) {
+                Some(_) => vec![TxHash::new(&[1; 32]).unwrap().1],
+                None => vec![],
+            })
+        }
+
+        fn script_utxos(
+            &mut self,
+            script_pub_key: &ScriptPubKey,
+            _testnet: bool,
+        ) -> Result<Vec<(OutPoint, u64, u32)>, Error> {
+            Ok(self
+                .used
+                .get(&script_pub_key.content)
+                .cloned()
+                .unwrap_or_default())
+        }
+    }
+
+    #[test]
+    fn test_account_scan() {
+        let account = Account::from_str(ACCOUNT_XPUB).unwrap();
+        let utxo = |vout: u32, amount: u64| {
+            (
+                OutPoint::new(TxHash::new(&[2; 32]).unwrap().1, vout),
+                amount,
+                100,
+            )
+        };
+        let mut used = HashMap::new();
+        // spent from, still used
+        used.insert(account.script_pubkey(false, 0).unwrap().content, vec![]);
+        used.insert(
+            account.script_pubkey(false, 3).unwrap().content,
+            vec![utxo(0, 1000)],
+        );
+        used.insert(
+            account.script_pubkey(false, 9).unwrap().content,
+            vec![utxo(1, 2000)],
+        );
+        used.insert(
+            account.script_pubkey(true, 1).unwrap().content,
+            vec![utxo(2, 300)],
+        );
+        let mut backend = MockBackend { used, queries: 0 };
+
+        // index 9 lies past a gap of 5 after index 3
+        let scan = account.scan(&mut backend, 5).unwrap();
+        assert_eq!((scan.last_receive, scan.last_change), (Some(3), Some(1)));
+        assert_eq!(scan.balance(), 1300);
+        assert_eq!((scan.next_receive(), scan.next_change()), (4, 2));
+        assert!(scan.utxos.iter().any(|u| u.change && u.index == 1));
+        assert_eq!(backend.queries, 9 + 7);
+
+        let scan = account.scan(&mut backend, 6).unwrap();
+        assert_eq!(scan.last_receive, Some(9));
+        assert_eq!(scan.balance(), 3300);
+
+        backend.used.clear();
+        let scan = account.scan(&mut backend, 20).unwrap();
+        assert_eq!((scan.last_receive, scan.last_change), (None, None));
+        assert_eq!(scan.next_receive(), 0);
+    }
+}
@@ -0,0 +1,239 @@
+const CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+
+/// BIP173 bech32 is used by witness version 0, BIP350 bech32m by version 1 and later
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Variant {
+    Bech32,
+    Bech32m,
+}
+impl Copy for Variant {}
+
+impl Variant {
+    fn constant(self) -> u32 {
+        match self {
+            Variant::Bech32 => BECH32_CONST,
+            Variant::Bech32m => BECH32M_CONST,
+        }
+    }
+}
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum Bech32Error {
+    #[fail(display = "invalid bech32 character: {}", _0)]
+    InvalidCharacter(char),
+    #[fail(display = "bech32 string mixes upper and lower case")]
+    MixedCase,
+    #[fail(display = "bech32 string has no separator or a wrong length")]
+    InvalidLength,
+    #[fail(display = "bech32 checksum mismatch")]
+    InvalidChecksum,
+    #[fail(display = "invalid bit group padding")]
+    InvalidPadding,
+    #[fail(display = "bech32 human readable part mismatch")]
+    HrpMismatch,
+    #[fail(display = "invalid witness version: {}", _0)]
+    InvalidWitnessVersion(u8),
+    #[fail(display = "invalid witness program length: {}", _0)]
+    InvalidProgramLength(usize),
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    let generator = [
+        0x3b6a_57b2u32,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let mut chk = 1u32;
+    for v in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x01ff_ffff) << 5) ^ u32::from(*v);
+        for (i, g) in generator.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut ret: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    ret.push(0);
+    ret.extend(hrp.bytes().map(|b| b & 0x1f));
+    ret
+}
+
+fn create_checksum(hrp: &str, data: &[u8], variant: Variant) -> Vec<u8> {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0u8; 6]);
+    let modulus = polymod(&values) ^ variant.constant();
+    (0..6)
+        .map(|i| ((modulus >> (5 * (5 - i))) & 0x1f) as u8)
+        .collect()
+}
+
+/// Encode 5-bit groups `data` with the human readable part `hrp`
+pub fn encode(hrp: &str, data: &[u8], variant: Variant) -> String {
+    let checksum = create_checksum(hrp, data, variant);
+    let mut ret = String::with_capacity(hrp.len() + 1 + data.len() + 6);
+    ret.push_str(hrp);
+    ret.push('1');
+    for i in data.iter().chain(checksum.iter()) {
+        ret.push(CHARSET[*i as usize] as char);
+    }
+    ret
+}
+
+/// Decode a bech32 or bech32m string into its human readable part and 5-bit groups
+pub fn decode(s: &str) -> Result<(String, Vec<u8>, Variant), Bech32Error> {
+    let has_lower = s.chars().any(|c| c.is_ascii_lowercase());
+    let has_upper = s.chars().any(|c| c.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return Err(Bech32Error::MixedCase);
+    }
+    let s = s.to_ascii_lowercase();
+
+    let pos = s.rfind('1').ok_or(Bech32Error::InvalidLength)?;
+    if pos < 1 || pos + 7 > s.len() {
+        return Err(Bech32Error::InvalidLength);
+    }
+    let hrp = &s[..pos];
+    if let Some(c) = hrp.chars().find(|c| *c < '!' || *c > '~') {
+        return Err(Bech32Error::InvalidCharacter(c));
+    }
+
+    let mut data = Vec::with_capacity(s.len() - pos - 1);
+    for c in s[pos + 1..].chars() {
+        let v = CHARSET
+            .iter()
+            .position(|i| *i as char == c)
+            .ok_or(Bech32Error::InvalidCharacter(c))?;
+        data.push(v as u8);
+    }
+
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    let variant = match polymod(&values) {
+        BECH32_CONST => Variant::Bech32,
+        BECH32M_CONST => Variant::Bech32m,
+        _ => return Err(Bech32Error::InvalidChecksum),
+    };
+
+    data.truncate(data.len() - 6);
+    Ok((hrp.to_string(), data, variant))
+}
+
+/// Regroup bits, e.g. 8-bit bytes into 5-bit bech32 groups and back
+pub fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, Bech32Error> {
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let max_value = (1u32 << to) - 1;
+    let mut ret = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for v in data {
+        let v = u32::from(*v);
+        if v >> from != 0 {
+            return Err(Bech32Error::InvalidPadding);
+        }
+        acc = (acc << from) | v;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            ret.push(((acc >> bits) & max_value) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            ret.push(((acc << (to - bits)) & max_value) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max_value) != 0 {
+        return Err(Bech32Error::InvalidPadding);
+    }
+    Ok(ret)
+}
+
+/// Segwit address of a witness program, version 0 uses bech32 and later versions bech32m
+pub fn encode_segwit_address(hrp: &str, version: u8, program: &[u8]) -> String {
+    let variant = if version == 0 {
+        Variant::Bech32
+    } else {
+        Variant::Bech32m
+    };
+    let mut data = vec![version];
+    data.extend(convert_bits(program, 8, 5, true).expect("8 bit bytes always convert"));
+    encode(hrp, &data, variant)
+}
+
+/// Decode a segwit address into its witness version and program
+pub fn decode_segwit_address(hrp: &str, address: &str) -> Result<(u8, Vec<u8>), Bech32Error> {
+    let (address_hrp, data, variant) = decode(address)?;
+    if address_hrp != hrp {
+        return Err(Bech32Error::HrpMismatch);
+    }
+    if data.is_empty() {
+        return Err(Bech32Error::InvalidLength);
+    }
+
+    let version = data[0];
+    if version > 16 {
+        return Err(Bech32Error::InvalidWitnessVersion(version));
+    }
+    if (version == 0 && variant != Variant::Bech32) || (version != 0 && variant != Variant::Bech32m)
+    {
+        return Err(Bech32Error::InvalidChecksum);
+    }
+
+    let program = convert_bits(&data[1..], 5, 8, false)?;
+    if program.len() < 2 || program.len() > 40 {
+        return Err(Bech32Error::InvalidProgramLength(program.len()));
+    }
+    if version == 0 && program.len() != 20 && program.len() != 32 {
+        return Err(Bech32Error::InvalidProgramLength(program.len()));
+    }
+    Ok((version, program))
+}
+
+mod test {
+    use super::{decode, decode_segwit_address, encode_segwit_address, Bech32Error, Variant};
+
+    #[test]
+    fn test_segwit_address_round_trip() {
+        let address = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+        let (version, program) = decode_segwit_address("bc", address).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(program.len(), 20);
+        assert_eq!(encode_segwit_address("bc", version, &program), address);
+
+        let address = "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr";
+        let (version, program) = decode_segwit_address("bc", address).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(program.len(), 32);
+        assert_eq!(encode_segwit_address("bc", version, &program), address);
+    }
+
+    #[test]
+    fn test_bech32_errors() {
+        let address = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+        assert_eq!(
+            decode_segwit_address("tb", address),
+            Err(Bech32Error::HrpMismatch)
+        );
+        assert_eq!(
+            decode("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyU"),
+            Err(Bech32Error::MixedCase)
+        );
+        assert_eq!(
+            decode("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyv"),
+            Err(Bech32Error::InvalidChecksum)
+        );
+        assert_eq!(
+            decode(&address.to_ascii_uppercase()).unwrap().2,
+            Variant::Bech32
+        );
+    }
+}
@@ -0,0 +1,367 @@
+use sha2::{Digest, Sha256};
+
+use super::bech32::decode_segwit_address;
+use super::private_key::PrivateKey;
+use super::taproot::TaprootSpendInfo;
+use super::{hash160, tagged_hash, S256Point, Signature, U256};
+use crate::script::{verify_taproot_input, LockTimeContext, Script, VerifyFlags};
+use crate::transaction::{
+    PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxHash, TxInput, TxInputSequence, TxLocktime,
+    TxOutput, TxOutputAmount, TxVersion, SIGHASH_ALL,
+};
+
+const BIP322_TAG: &str = "BIP0322-signed-message";
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum Bip322Error {
+    #[fail(display = "address is not a segwit address: {}", _0)]
+    InvalidAddress(String),
+    #[fail(display = "only P2WPKH, P2WSH and P2TR are supported by simple signatures")]
+    UnsupportedScript,
+    #[fail(display = "key does not own the challenge script")]
+    KeyMismatch,
+    #[fail(display = "signature is not base64 encoded witness")]
+    InvalidSignatureEncoding,
+}
+
+/// Tagged hash of the message the virtual to_spend transaction commits to
+pub fn message_hash(message: &[u8]) -> [u8; 32] {
+    tagged_hash(BIP322_TAG, message)
+}
+
+/// Output script of a segwit `address`, mainnet, testnet or regtest
+pub fn address_script_pubkey(address: &str) -> Result<ScriptPubKey, Bip322Error> {
+    let hrp = ["bc", "tb", "bcrt"]
+        .iter()
+        .filter(|hrp| address.to_lowercase().starts_with(&format!("{}1", hrp)))
+        .last()
+        .ok_or_else(|| Bip322Error::InvalidAddress(address.to_string()))?;
+    let (version, program) = decode_segwit_address(hrp, address)
+        .map_err(|_| Bip322Error::InvalidAddress(address.to_string()))?;
+
+    let mut content = vec![if version == 0 { 0x00 } else { 0x50 + version }];
+    content.push(program.len() as u8);
+    content.extend_from_slice(&program);
+    Ok(ScriptPubKey { content })
+}
+
+/// Virtual transaction paying to `script_pubkey` from a fake coinbase like input that
+/// commits to the message
+pub fn to_spend(script_pubkey: &ScriptPubKey, message: &[u8]) -> Transaction {
+    let mut script_sig = vec![0x00, 0x20];
+    script_sig.extend_from_slice(&message_hash(message));
+    let input = TxInput::new(
+        TxHash::new(&[0u8; 32]).unwrap().1,
+        PreTxIndex::new(0xffff_ffff),
+        ScriptSig {
+            content: script_sig,
+        },
+        TxInputSequence::new(0),
+    );
+    let output = TxOutput {
+        amount: TxOutputAmount::from(0),
+        script_pub_key: script_pubkey.clone(),
+    };
+    Transaction::new(
+        TxVersion::new(0),
+        vec![input],
+        vec![output],
+        TxLocktime::new(0),
+        false,
+    )
+}
+
+/// Virtual transaction spending `to_spend` into an OP_RETURN, its witness is the signature
+pub fn to_sign(to_spend: &Transaction, witness: Vec<Vec<u8>>) -> Transaction {
+    let mut input = TxInput::new(
+        to_spend.id(),
+        PreTxIndex::new(0),
+        ScriptSig { content: vec![] },
+        TxInputSequence::new(0),
+    );
+    input.witness = witness;
+    let output = TxOutput {
+        amount: TxOutputAmount::from(0),
+        script_pub_key: ScriptPubKey {
+            content: vec![0x6a],
+        },
+    };
+    Transaction::new(
+        TxVersion::new(0),
+        vec![input],
+        vec![output],
+        TxLocktime::new(0),
+        false,
+    )
+}
+
+fn p2pkh_script_code(key_hash: &[u8]) -> ScriptPubKey {
+    let mut content = vec![0x76, 0xa9, 0x14];
+    content.extend_from_slice(key_hash);
+    content.extend_from_slice(&[0x88, 0xac]);
+    ScriptPubKey { content }
+}
+
+/// Key path signing key of an output with no script tree, the internal key negated to an
+/// even y and tweaked
+fn taproot_tweaked_key(key: &PrivateKey) -> PrivateKey {
+    let (_, y) = key
+        .point
+        .coordinate()
+        .expect("private key point is not infinity");
+    let key = if y.is_even() {
+        PrivateKey::new(key.secret().num())
+    } else {
+        PrivateKey::new((-key.secret()).num())
+    };
+    key.add_tweak(&tagged_hash("TapTweak", &key.point.x_only()))
+        .expect("taproot tweak is a valid scalar")
+}
+
+/// BIP322 simple signature of `message` for `script_pubkey`, the witness of to_sign.
+/// P2WPKH is signed with the compressed key, P2TR by key path with no script tree.
+pub fn sign_simple(
+    key: &PrivateKey,
+    script_pubkey: &ScriptPubKey,
+    message: &[u8],
+) -> Result<Vec<Vec<u8>>, Bip322Error> {
+    let to_spend = to_spend(script_pubkey, message);
+    let to_sign = to_sign(&to_spend, vec![]);
+
+    match script_pubkey.witness_program() {
+        Some((0, program)) if program.len() == 20 => {
+            if program != &key.point.hash160(true).to_vec()[..] {
+                return Err(Bip322Error::KeyMismatch);
+            }
+            let z = to_sign.sig_hash_segwit_v0(0, &p2pkh_script_code(program), 0, SIGHASH_ALL);
+            let mut sig = key.sign(U256::from_little_endian(&z)).der();
+            sig.push(SIGHASH_ALL as u8);
+            Ok(vec![sig, key.point.compressed_sec().to_vec()])
+        }
+        Some((1, program)) if program.len() == 32 => {
+            let info =
+                TaprootSpendInfo::new(&key.point, None).map_err(|_| Bip322Error::KeyMismatch)?;
+            if program != &info.output_key.serialize()[..] {
+                return Err(Bip322Error::KeyMismatch);
+            }
+            let msg = to_sign
+                .sig_hash_taproot(0, &to_spend.outputs, 0x00, None, None)
+                .expect("to_sign has one input and one prevout");
+            let mut aux = [0u8; 32];
+            U256::from_random().to_big_endian(&mut aux);
+            let sig = taproot_tweaked_key(key).sign_schnorr(&msg, &aux);
+            Ok(vec![sig.serialize().to_vec()])
+        }
+        _ => Err(Bip322Error::UnsupportedScript),
+    }
+}
+
+/// Check a BIP322 simple signature, `witness` is the to_sign witness
+pub fn verify_simple(
+    script_pubkey: &ScriptPubKey,
+    message: &[u8],
+    witness: &[Vec<u8>],
+) -> Result<bool, Bip322Error> {
+    let to_spend = to_spend(script_pubkey, message);
+    let to_sign = to_sign(&to_spend, witness.to_vec());
+
+    match script_pubkey.witness_program() {
+        Some((0, program)) if program.len() == 20 => {
+            if witness.len() != 2 || witness[0].is_empty() || witness[1].len() != 33 {
+                return Ok(false);
+            }
+            if hash160(&witness[1]).to_vec() != program {
+                return Ok(false);
+            }
+            let (sig, sighash_type) = witness[0].split_at(witness[0].len() - 1);
+            let z = to_sign.sig_hash_segwit_v0(
+                0,
+                &p2pkh_script_code(program),
+                0,
+                u32::from(sighash_type[0]),
+            );
+            let sig = match Signature::try_parse_der(sig) {
+                Ok(sig) => sig,
+                Err(_) => return Ok(false),
+            };
+            Ok(S256Point::parse_sec(&witness[1])
+                .map(|point| point.verify(z, sig))
+                .unwrap_or(false))
+        }
+        Some((0, program)) if program.len() == 32 => {
+            let witness_script = match witness.last() {
+                Some(witness_script) => witness_script,
+                None => return Ok(false),
+            };
+            if Sha256::digest(witness_script)[..] != program[..] {
+                return Ok(false);
+            }
+            let (script, bad_regions) = Script::parse_lossy(witness_script);
+            if !bad_regions.is_empty() {
+                return Ok(false);
+            }
+            Ok(script
+                .evaluate_witness(
+                    &witness[..witness.len() - 1],
+                    &mut |sighash_type, codesep_pos| {
+                        let script_code = ScriptPubKey {
+                            content: script.witness_script_code(codesep_pos),
+                        };
+                        to_sign.sig_hash_segwit_v0(0, &script_code, 0, sighash_type)
+                    },
+                    Some(&LockTimeContext::new(&to_sign, 0)),
+                    VerifyFlags::MINIMALDATA | VerifyFlags::MINIMALIF | VerifyFlags::CLEANSTACK,
+                )
+                .unwrap_or(false))
+        }
+        Some((1, program)) if program.len() == 32 => {
+            Ok(verify_taproot_input(&to_sign, 0, &to_spend.outputs).unwrap_or(false))
+        }
+        _ => Err(Bip322Error::UnsupportedScript),
+    }
+}
+
+/// Sign `message` for a segwit `address`, returns the base64 simple signature
+pub fn sign_message(
+    key: &PrivateKey,
+    address: &str,
+    message: &[u8],
+) -> Result<String, Bip322Error> {
+    let witness = sign_simple(key, &address_script_pubkey(address)?, message)?;
+    Ok(encode_witness(&witness))
+}
+
+pub fn verify_message(address: &str, message: &[u8], signature: &str) -> Result<bool, Bip322Error> {
+    let witness = decode_witness(signature)?;
+    verify_simple(&address_script_pubkey(address)?, message, &witness)
+}
+
+/// Base64 of the consensus encoded witness stack
+pub fn encode_witness(witness: &[Vec<u8>]) -> String {
+    let mut input = TxInput::new(
+        TxHash::new(&[0u8; 32]).unwrap().1,
+        PreTxIndex::new(0),
+        ScriptSig { content: vec![] },
+        TxInputSequence::default(),
+    );
+    input.witness = witness.to_vec();
+    base64_encode(&input.serialize_witness())
+}
+
+pub fn decode_witness(signature: &str) -> Result<Vec<Vec<u8>>, Bip322Error> {
+    let bytes = base64_decode(signature).ok_or(Bip322Error::InvalidSignatureEncoding)?;
+    match TxInput::parse_witness(&bytes) {
+        Ok((rest, witness)) if rest.is_empty() => Ok(witness),
+        _ => Err(Bip322Error::InvalidSignatureEncoding),
+    }
+}
+
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    let mut ret = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | u32::from(*byte) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(BASE64_CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+    ret
+}
+
+pub(crate) fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim().trim_end_matches('=');
+    let mut ret = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+        let value = BASE64_CHARS.iter().position(|b| *b == c)? as u32;
+        acc = acc << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            ret.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(ret)
+}
+
+mod test {
+    use super::{
+        address_script_pubkey, decode_witness, encode_witness, message_hash, sign_message, to_sign,
+        to_spend, verify_message,
+    };
+    use crate::wallet::bech32::encode_segwit_address;
+    use crate::wallet::private_key::PrivateKey;
+    use crate::wallet::taproot::TaprootSpendInfo;
+    use crate::wallet::U256;
+
+    const ADDRESS: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+
+    #[test]
+    fn test_virtual_transactions() {
+        assert_eq!(
+            hex::encode(message_hash(b"")),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            hex::encode(message_hash(b"Hello World")),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+
+        let script_pubkey = address_script_pubkey(ADDRESS).unwrap();
+        let to_spend = to_spend(&script_pubkey, b"");
+        assert_eq!(
+            to_spend.id().hex(),
+            "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7"
+        );
+        assert_eq!(
+            to_sign(&to_spend, vec![]).id().hex(),
+            "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6"
+        );
+    }
+
+    #[test]
+    fn test_verify_vector() {
+        let signature = "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+        assert_eq!(verify_message(ADDRESS, b"Hello World", signature), Ok(true));
+        assert_eq!(
+            verify_message(ADDRESS, b"Hello World!", signature),
+            Ok(false)
+        );
+
+        // malformed DER signatures fail instead of panicking
+        let witness = decode_witness(signature).unwrap();
+        let der = witness[0].clone();
+        for bad in &[vec![0x01], der[..20].to_vec(), vec![0xff; 72]] {
+            let mut witness = witness.clone();
+            witness[0] = bad.clone();
+            assert_eq!(
+                verify_message(ADDRESS, b"Hello World", &encode_witness(&witness)),
+                Ok(false)
+            );
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = PrivateKey::new(U256::from(0xb1b2_b3b4u32));
+
+        let p2wpkh = encode_segwit_address("bc", 0, &key.point.hash160(true).to_vec());
+        let signature = sign_message(&key, &p2wpkh, b"message").unwrap();
+        assert_eq!(verify_message(&p2wpkh, b"message", &signature), Ok(true));
+        assert_eq!(verify_message(&p2wpkh, b"other", &signature), Ok(false));
+
+        let output_key = TaprootSpendInfo::new(&key.point, None).unwrap().output_key;
+        let p2tr = encode_segwit_address("bc", 1, &output_key.serialize());
+        let signature = sign_message(&key, &p2tr, b"message").unwrap();
+        assert_eq!(verify_message(&p2tr, b"message", &signature), Ok(true));
+        assert_eq!(verify_message(&p2tr, b"other", &signature), Ok(false));
+    }
+}
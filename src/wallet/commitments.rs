@@ -0,0 +1,102 @@
+use super::private_key::PrivateKey;
+use super::secp256k1::ec::utils::U256;
+use super::secp256k1::s256_point::S256Point;
+use super::secp256k1::s256_scalar::S256Scalar;
+use super::secp256k1::signature::Signature;
+use super::tagged_hash;
+use sha2::{Digest, Sha256};
+
+/// Nothing-up-my-sleeve generator: hash `seed` and keep hashing until the result is the x
+/// coordinate of a curve point, so nobody knows its discrete log with respect to G
+pub fn nums_generator(seed: &[u8]) -> S256Point {
+    let mut x = [0u8; 32];
+    x.copy_from_slice(&Sha256::digest(seed));
+    loop {
+        if let Some(point) = S256Point::lift_x(&x, false) {
+            return point;
+        }
+        let next = Sha256::digest(&x);
+        x.copy_from_slice(&next);
+    }
+}
+
+/// The second generator H of Pedersen commitments, sha256 of the uncompressed G.
+/// Same point as the generator H of Elements confidential transactions.
+pub fn second_generator() -> S256Point {
+    nums_generator(&S256Point::gen_point().sec())
+}
+
+/// Pedersen commitment value_blind*G + value*H, hides `value` but commitments still add up
+pub fn commit(value_blind: U256, value: u64) -> S256Point {
+    S256Point::gen_point() * value_blind + second_generator() * value
+}
+
+/// Open a commitment, check it was made to `value` with `value_blind`
+pub fn verify_commitment(commitment: &S256Point, value_blind: U256, value: u64) -> bool {
+    commit(value_blind, value) == *commitment
+}
+
+/// tagged_hash("SignToContract", R || contract) as a scalar
+fn contract_tweak(nonce: &S256Point, contract: &[u8]) -> S256Scalar {
+    let mut msg = nonce.compressed_sec().to_vec();
+    msg.extend_from_slice(contract);
+    S256Scalar::reduce_be_bytes(&tagged_hash("SignToContract", &msg))
+}
+
+/// Sign `z` while committing to `contract` inside the signature nonce: R' = R + hash(R || contract)*G.
+/// The signature looks like any other one, the returned original nonce R proves the commitment.
+pub fn sign_to_contract(key: &PrivateKey, z: U256, contract: &[u8]) -> (Signature, S256Point) {
+    let z = S256Scalar::new(z);
+    let k = key.deterministic_k(z);
+    let nonce = S256Point::gen_point() * k;
+    let tweaked_k = k + contract_tweak(&nonce, contract);
+    (key.sign_with_nonce(z, tweaked_k), nonce)
+}
+
+/// Check `sig` commits to `contract` given the original nonce
+pub fn verify_sign_to_contract(sig: &Signature, nonce: &S256Point, contract: &[u8]) -> bool {
+    let tweaked_nonce = *nonce + S256Point::gen_point() * contract_tweak(nonce, contract);
+    match tweaked_nonce.coordinate() {
+        Some((x, _)) => S256Scalar::new(x) == S256Scalar::new(sig.r),
+        None => false,
+    }
+}
+
+mod test {
+    use super::{
+        commit, second_generator, sign_to_contract, verify_commitment, verify_sign_to_contract,
+    };
+    use crate::wallet::private_key::PrivateKey;
+    use crate::wallet::secp256k1::ec::utils::U256;
+    use crate::wallet::{Hash256, Hex};
+
+    #[test]
+    fn test_second_generator() {
+        let (x, y) = second_generator().coordinate().unwrap();
+        assert_eq!(
+            x.hex(),
+            "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0".to_string()
+        );
+        assert!(y.is_even());
+    }
+
+    #[test]
+    fn test_commitments_add_up() {
+        let c1 = commit(U256::from(1234u32), 50);
+        let c2 = commit(U256::from(5678u32), 70);
+        assert_eq!(c1 + c2, commit(U256::from(1234u32 + 5678), 120));
+        assert!(verify_commitment(&c1, U256::from(1234u32), 50));
+        assert!(!verify_commitment(&c1, U256::from(1234u32), 51));
+    }
+
+    #[test]
+    fn test_sign_to_contract() {
+        let key = PrivateKey::new(U256::from(12345u32));
+        let z = U256::from_hex(b"bc62d4b80d9e36da29c16c5d4d9f11731f36052c72401a76c23c0fb5a9b74423");
+        let (sig, nonce) = sign_to_contract(&key, z, b"timestamp me");
+
+        assert!(key.point.verify(Hash256::from(z), sig));
+        assert!(verify_sign_to_contract(&sig, &nonce, b"timestamp me"));
+        assert!(!verify_sign_to_contract(&sig, &nonce, b"something else"));
+    }
+}
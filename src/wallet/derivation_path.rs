@@ -0,0 +1,170 @@
+use super::extended_key::HARDENED_INDEX;
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum DerivationPathError {
+    #[fail(display = "derivation path must start with m")]
+    MissingMaster,
+    #[fail(display = "invalid child number: {}", _0)]
+    InvalidChildNumber(String),
+}
+
+/// One child number like `84'`, `84h` or `5`, hardened ones with `HARDENED_INDEX` added
+pub(super) fn parse_child_number(part: &str) -> Result<u32, DerivationPathError> {
+    let (number, hardened) = match part.chars().last() {
+        Some('\'') | Some('h') | Some('H') => (&part[..part.len() - 1], true),
+        _ => (part, false),
+    };
+    let index: u32 = number
+        .parse()
+        .map_err(|_| DerivationPathError::InvalidChildNumber(part.to_string()))?;
+    if index >= HARDENED_INDEX {
+        return Err(DerivationPathError::InvalidChildNumber(part.to_string()));
+    }
+    Ok(if hardened {
+        index + HARDENED_INDEX
+    } else {
+        index
+    })
+}
+
+/// Child numbers leading from a master key to a derived key, hardened ones with
+/// `HARDENED_INDEX` added. Written `m/84'/0'/0'/0/5`, `h` works for `'` too.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    /// `m`, the master key itself
+    pub fn master() -> Self {
+        DerivationPath::default()
+    }
+
+    /// The path of child `index` of the key at this path
+    pub fn child(&self, index: u32) -> Self {
+        let mut path = self.0.clone();
+        path.push(index);
+        DerivationPath(path)
+    }
+
+    /// This path followed by `other`, e.g. an account path and then `0/5`
+    pub fn join(&self, other: &DerivationPath) -> Self {
+        DerivationPath([&self.0[..], &other.0[..]].concat())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.iter().cloned()
+    }
+
+    /// Depth of the derived key
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `/84'/0'/0'` without the `m`, as key origins write it after the fingerprint
+    pub(super) fn fmt_children(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for index in &self.0 {
+            if *index >= HARDENED_INDEX {
+                write!(f, "/{}'", index - HARDENED_INDEX)?;
+            } else {
+                write!(f, "/{}", index)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<Vec<u32>> for DerivationPath {
+    fn from(path: Vec<u32>) -> Self {
+        DerivationPath(path)
+    }
+}
+
+impl AsRef<[u32]> for DerivationPath {
+    fn as_ref(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl<'a> IntoIterator for &'a DerivationPath {
+    type Item = u32;
+    type IntoIter = std::iter::Cloned<std::slice::Iter<'a, u32>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter().cloned()
+    }
+}
+
+impl Display for DerivationPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "m")?;
+        self.fmt_children(f)
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = DerivationPathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(DerivationPathError::MissingMaster);
+        }
+        parts
+            .map(parse_child_number)
+            .collect::<Result<Vec<u32>, _>>()
+            .map(DerivationPath)
+    }
+}
+
+mod test {
+    use super::super::extended_key::HARDENED_INDEX;
+    use super::{DerivationPath, DerivationPathError};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_derivation_path() {
+        let path = DerivationPath::from_str("m/84'/0'/0'/0/5").unwrap();
+        assert_eq!(
+            path.iter().collect::<Vec<u32>>(),
+            vec![84 + HARDENED_INDEX, HARDENED_INDEX, HARDENED_INDEX, 0, 5]
+        );
+        assert_eq!(
+            DerivationPath::from_str("m/84h/0H/0'/0/5"),
+            Ok(path.clone())
+        );
+        assert_eq!(path.to_string(), "m/84'/0'/0'/0/5");
+        assert_eq!(path.len(), 5);
+
+        let account = DerivationPath::from_str("m/84'/0'/0'").unwrap();
+        let address = DerivationPath::from(vec![0, 5]);
+        assert_eq!(account.join(&address), path);
+        assert_eq!(account.child(0).child(5), path);
+        assert_eq!(DerivationPath::from_str("m"), Ok(DerivationPath::master()));
+        assert_eq!(DerivationPath::master().to_string(), "m");
+        assert_eq!((&path).into_iter().last(), Some(5));
+
+        assert_eq!(
+            DerivationPath::from_str("84'/0'"),
+            Err(DerivationPathError::MissingMaster)
+        );
+        assert_eq!(
+            DerivationPath::from_str("m/"),
+            Err(DerivationPathError::InvalidChildNumber("".to_string()))
+        );
+        assert_eq!(
+            DerivationPath::from_str("m/2147483648"),
+            Err(DerivationPathError::InvalidChildNumber(
+                "2147483648".to_string()
+            ))
+        );
+        assert_eq!(
+            DerivationPath::from_str("m/1''"),
+            Err(DerivationPathError::InvalidChildNumber("1''".to_string()))
+        );
+    }
+}
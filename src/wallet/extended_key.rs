@@ -0,0 +1,719 @@
+use super::derivation_path::DerivationPath;
+use super::key_source::{Fingerprint, KeySource};
+use super::private_key::PrivateKey;
+use super::secp256k1::ec::utils::U256;
+use super::secp256k1::s256_point::S256Point;
+use super::secp256k1::s256_scalar::S256Scalar;
+use super::secp256k1::utils::{decode_base58_checksum, encode_base58_checksum, Base58Error};
+use hmac::{Hmac, Mac};
+use sha2::Sha512;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Child numbers from here on are hardened, they can only be derived from a private key
+pub const HARDENED_INDEX: u32 = 0x8000_0000;
+
+/// 32 big endian bytes as a scalar, None unless below n
+fn scalar(bytes: &[u8]) -> Option<S256Scalar> {
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(bytes);
+    S256Scalar::from_be_bytes(&buf)
+}
+
+/// Script type implied by the SLIP-132 version bytes of an extended key
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ScriptType {
+    /// xpub/xprv, tpub/tprv
+    P2pkh,
+    /// ypub/yprv, upub/uprv
+    P2shP2wpkh,
+    /// zpub/zprv, vpub/vprv
+    P2wpkh,
+}
+impl Copy for ScriptType {}
+
+// (private version, public version, testnet, script type)
+const VERSIONS: [([u8; 4], [u8; 4], bool, ScriptType); 6] = [
+    (
+        [0x04, 0x88, 0xad, 0xe4],
+        [0x04, 0x88, 0xb2, 0x1e],
+        false,
+        ScriptType::P2pkh,
+    ),
+    (
+        [0x04, 0x9d, 0x78, 0x78],
+        [0x04, 0x9d, 0x7c, 0xb2],
+        false,
+        ScriptType::P2shP2wpkh,
+    ),
+    (
+        [0x04, 0xb2, 0x43, 0x0c],
+        [0x04, 0xb2, 0x47, 0x46],
+        false,
+        ScriptType::P2wpkh,
+    ),
+    (
+        [0x04, 0x35, 0x83, 0x94],
+        [0x04, 0x35, 0x87, 0xcf],
+        true,
+        ScriptType::P2pkh,
+    ),
+    (
+        [0x04, 0x4a, 0x4e, 0x28],
+        [0x04, 0x4a, 0x52, 0x62],
+        true,
+        ScriptType::P2shP2wpkh,
+    ),
+    (
+        [0x04, 0x5f, 0x18, 0xbc],
+        [0x04, 0x5f, 0x1c, 0xf6],
+        true,
+        ScriptType::P2wpkh,
+    ),
+];
+
+fn version_bytes(private: bool, testnet: bool, script_type: ScriptType) -> [u8; 4] {
+    let (prv, publ, _, _) = VERSIONS
+        .iter()
+        .find(|(_, _, t, s)| *t == testnet && *s == script_type)
+        .expect("every network and script type has version bytes");
+    if private {
+        *prv
+    } else {
+        *publ
+    }
+}
+
+/// Returns (private, testnet, script type) of the version bytes
+fn parse_version(version: &[u8]) -> Option<(bool, bool, ScriptType)> {
+    VERSIONS
+        .iter()
+        .find_map(|(prv, publ, testnet, script_type)| {
+            if version == &prv[..] {
+                Some((true, *testnet, *script_type))
+            } else if version == &publ[..] {
+                Some((false, *testnet, *script_type))
+            } else {
+                None
+            }
+        })
+}
+
+fn hmac_sha512_digest(key: &[u8], data: &[u8]) -> Vec<u8> {
+    type HmacSha512 = Hmac<Sha512>;
+    let mut mac = HmacSha512::new_varkey(key).expect("HMAC new with key failed");
+    mac.input(data);
+    mac.result().code().to_vec()
+}
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum ExtendedKeyError {
+    #[fail(display = "extended key base58 error: {}", _0)]
+    Base58Error(Base58Error),
+    #[fail(display = "extended key must be 78 bytes, got {}", _0)]
+    InvalidLength(usize),
+    #[fail(display = "unknown extended key version: {}", _0)]
+    UnknownVersion(String),
+    #[fail(display = "expected an extended {} key", _0)]
+    UnexpectedKeyType(&'static str),
+    #[fail(display = "invalid extended key key data")]
+    InvalidKeyData,
+    #[fail(display = "master key with a parent fingerprint or child number")]
+    InvalidMaster,
+    #[fail(display = "extended key derivation error: {}", _0)]
+    DeriveError(DeriveError),
+}
+
+impl From<DeriveError> for ExtendedKeyError {
+    fn from(e: DeriveError) -> Self {
+        ExtendedKeyError::DeriveError(e)
+    }
+}
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum DeriveError {
+    #[fail(display = "child number {} is not below 2^31", _0)]
+    ChildNumberOverflow(u32),
+    #[fail(
+        display = "can not derive hardened child {} from an extended public key",
+        _0
+    )]
+    HardenedFromPublic(u32),
+    #[fail(display = "child {} is an invalid key, use the next index", _0)]
+    InvalidChild(u32),
+    #[fail(display = "seed gives an invalid master key, use another seed")]
+    InvalidSeed,
+    #[fail(display = "extended keys can not be deeper than 255")]
+    MaxDepth,
+}
+
+/// k_par + I_L, the CKDpriv child key, invalid when I_L is not below n or the sum is zero
+fn child_secret(parent: S256Scalar, i_l: &[u8], index: u32) -> Result<S256Scalar, DeriveError> {
+    match scalar(i_l).map(|tweak| tweak + parent) {
+        Some(secret) if !secret.is_zero() => Ok(secret),
+        _ => Err(DeriveError::InvalidChild(index)),
+    }
+}
+
+/// Fields shared by the serialized extended public and private keys
+struct RawExtendedKey {
+    private: bool,
+    testnet: bool,
+    script_type: ScriptType,
+    depth: u8,
+    parent_fingerprint: Fingerprint,
+    child_number: u32,
+    chain_code: [u8; 32],
+    key_data: [u8; 33],
+}
+
+impl RawExtendedKey {
+    fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(78);
+        buf.extend_from_slice(&version_bytes(self.private, self.testnet, self.script_type));
+        buf.push(self.depth);
+        buf.extend_from_slice(self.parent_fingerprint.as_ref());
+        buf.extend_from_slice(&self.child_number.to_be_bytes());
+        buf.extend_from_slice(&self.chain_code);
+        buf.extend_from_slice(&self.key_data);
+        buf
+    }
+}
+
+impl FromStr for RawExtendedKey {
+    type Err = ExtendedKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = decode_base58_checksum(s).map_err(ExtendedKeyError::Base58Error)?;
+        if bytes.len() != 78 {
+            return Err(ExtendedKeyError::InvalidLength(bytes.len()));
+        }
+
+        let (private, testnet, script_type) = parse_version(&bytes[0..4])
+            .ok_or_else(|| ExtendedKeyError::UnknownVersion(hex::encode(&bytes[0..4])))?;
+
+        let mut parent_fingerprint = [0u8; 4];
+        parent_fingerprint.copy_from_slice(&bytes[5..9]);
+        let mut child_number = [0u8; 4];
+        child_number.copy_from_slice(&bytes[9..13]);
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&bytes[13..45]);
+        let mut key_data = [0u8; 33];
+        key_data.copy_from_slice(&bytes[45..78]);
+        if bytes[4] == 0 && (parent_fingerprint != [0u8; 4] || child_number != [0u8; 4]) {
+            return Err(ExtendedKeyError::InvalidMaster);
+        }
+
+        Ok(RawExtendedKey {
+            private,
+            testnet,
+            script_type,
+            depth: bytes[4],
+            parent_fingerprint: Fingerprint::new(parent_fingerprint),
+            child_number: u32::from_be_bytes(child_number),
+            chain_code,
+            key_data,
+        })
+    }
+}
+
+/// BIP32 extended public key, enough to derive every non-hardened child public key
+/// without touching any private material
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedPubKey {
+    pub testnet: bool,
+    pub script_type: ScriptType,
+    pub depth: u8,
+    pub parent_fingerprint: Fingerprint,
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub public_key: S256Point,
+}
+
+impl Copy for ExtendedPubKey {}
+
+impl ExtendedPubKey {
+    /// First 4 bytes of the hash160 of the compressed public key
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::from_point(&self.public_key)
+    }
+
+    /// The same key serialized with the SLIP-132 version of another script type
+    pub fn with_script_type(&self, script_type: ScriptType) -> Self {
+        ExtendedPubKey {
+            script_type,
+            ..*self
+        }
+    }
+
+    /// CKDpub, public parent key to public child key
+    pub fn derive_child(&self, index: u32) -> Result<Self, DeriveError> {
+        if index >= HARDENED_INDEX {
+            return Err(DeriveError::HardenedFromPublic(index));
+        }
+        let depth = self.depth.checked_add(1).ok_or(DeriveError::MaxDepth)?;
+
+        let mut data = self.public_key.compressed_sec().to_vec();
+        data.extend_from_slice(&index.to_be_bytes());
+        let i = hmac_sha512_digest(&self.chain_code, &data);
+
+        let mut tweak = [0u8; 32];
+        tweak.copy_from_slice(&i[0..32]);
+        let public_key = self
+            .public_key
+            .add_tweak(&tweak)
+            .map_err(|_| DeriveError::InvalidChild(index))?;
+
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&i[32..64]);
+
+        Ok(ExtendedPubKey {
+            testnet: self.testnet,
+            script_type: self.script_type,
+            depth,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            public_key,
+        })
+    }
+
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self, DeriveError> {
+        path.iter()
+            .try_fold(*self, |xpub, index| xpub.derive_child(index))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        RawExtendedKey {
+            private: false,
+            testnet: self.testnet,
+            script_type: self.script_type,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            key_data: self.public_key.compressed_sec(),
+        }
+        .serialize()
+    }
+}
+
+impl Display for ExtendedPubKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", encode_base58_checksum(&self.serialize()))
+    }
+}
+
+impl FromStr for ExtendedPubKey {
+    type Err = ExtendedKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = RawExtendedKey::from_str(s)?;
+        if raw.private {
+            return Err(ExtendedKeyError::UnexpectedKeyType("public"));
+        }
+        if raw.key_data[0] != 0x02 && raw.key_data[0] != 0x03 {
+            return Err(ExtendedKeyError::InvalidKeyData);
+        }
+
+        Ok(ExtendedPubKey {
+            testnet: raw.testnet,
+            script_type: raw.script_type,
+            depth: raw.depth,
+            parent_fingerprint: raw.parent_fingerprint,
+            child_number: raw.child_number,
+            chain_code: raw.chain_code,
+            public_key: S256Point::parse_sec(&raw.key_data)
+                .map_err(|_| ExtendedKeyError::InvalidKeyData)?,
+        })
+    }
+}
+
+/// BIP32 extended private key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedPrivKey {
+    pub testnet: bool,
+    pub script_type: ScriptType,
+    pub depth: u8,
+    pub parent_fingerprint: Fingerprint,
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    secret: S256Scalar,
+}
+
+impl Copy for ExtendedPrivKey {}
+
+impl ExtendedPrivKey {
+    /// Master key generation from a BIP32 seed
+    pub fn from_seed(seed: &[u8], testnet: bool) -> Result<Self, DeriveError> {
+        let i = hmac_sha512_digest(b"Bitcoin seed", seed);
+        let secret = match scalar(&i[0..32]) {
+            Some(secret) if !secret.is_zero() => secret,
+            _ => return Err(DeriveError::InvalidSeed),
+        };
+
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&i[32..64]);
+
+        Ok(ExtendedPrivKey {
+            testnet,
+            script_type: ScriptType::P2pkh,
+            depth: 0,
+            parent_fingerprint: Fingerprint::default(),
+            child_number: 0,
+            chain_code,
+            secret,
+        })
+    }
+
+    pub fn private_key(&self) -> PrivateKey {
+        PrivateKey::new(self.secret.num())
+    }
+
+    pub fn public_key(&self) -> S256Point {
+        S256Point::gen_point() * self.secret
+    }
+
+    /// Neuter, drop the private part and keep everything needed for public derivation
+    pub fn extended_pub_key(&self) -> ExtendedPubKey {
+        ExtendedPubKey {
+            testnet: self.testnet,
+            script_type: self.script_type,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            public_key: self.public_key(),
+        }
+    }
+
+    /// Of a master key this is the master fingerprint used in key origins
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.extended_pub_key().fingerprint()
+    }
+
+    /// Origin of the key derived from this master key along `path`
+    pub fn key_source(&self, path: &DerivationPath) -> KeySource {
+        KeySource::new(self.fingerprint(), path.clone())
+    }
+
+    /// The same key serialized with the SLIP-132 version of another script type
+    pub fn with_script_type(&self, script_type: ScriptType) -> Self {
+        ExtendedPrivKey {
+            script_type,
+            ..*self
+        }
+    }
+
+    /// CKDpriv, private parent key to private child key
+    pub fn derive_child(&self, index: u32) -> Result<Self, DeriveError> {
+        let depth = self.depth.checked_add(1).ok_or(DeriveError::MaxDepth)?;
+        let mut data = Vec::with_capacity(37);
+        if index >= HARDENED_INDEX {
+            data.push(0u8);
+            data.extend_from_slice(&self.secret.to_be_bytes());
+        } else {
+            data.extend_from_slice(&self.public_key().compressed_sec());
+        }
+        data.extend_from_slice(&index.to_be_bytes());
+        let i = hmac_sha512_digest(&self.chain_code, &data);
+
+        let secret = child_secret(self.secret, &i[0..32], index)?;
+
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&i[32..64]);
+
+        Ok(ExtendedPrivKey {
+            testnet: self.testnet,
+            script_type: self.script_type,
+            depth,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            secret,
+        })
+    }
+
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self, DeriveError> {
+        path.iter()
+            .try_fold(*self, |xprv, index| xprv.derive_child(index))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut key_data = [0u8; 33];
+        key_data[1..33].copy_from_slice(&self.secret.to_be_bytes());
+        RawExtendedKey {
+            private: true,
+            testnet: self.testnet,
+            script_type: self.script_type,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            key_data,
+        }
+        .serialize()
+    }
+}
+
+impl Display for ExtendedPrivKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", encode_base58_checksum(&self.serialize()))
+    }
+}
+
+impl FromStr for ExtendedPrivKey {
+    type Err = ExtendedKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = RawExtendedKey::from_str(s)?;
+        if !raw.private {
+            return Err(ExtendedKeyError::UnexpectedKeyType("private"));
+        }
+        let secret = match scalar(&raw.key_data[1..33]) {
+            Some(secret) if raw.key_data[0] == 0x00 && !secret.is_zero() => secret,
+            _ => return Err(ExtendedKeyError::InvalidKeyData),
+        };
+
+        Ok(ExtendedPrivKey {
+            testnet: raw.testnet,
+            script_type: raw.script_type,
+            depth: raw.depth,
+            parent_fingerprint: raw.parent_fingerprint,
+            child_number: raw.child_number,
+            chain_code: raw.chain_code,
+            secret,
+        })
+    }
+}
+
+mod test {
+    use super::super::secp256k1::utils::encode_base58_checksum;
+    use super::{
+        child_secret, DerivationPath, DeriveError, ExtendedKeyError, ExtendedPrivKey,
+        ExtendedPubKey, ScriptType, HARDENED_INDEX,
+    };
+    use std::str::FromStr;
+
+    // BIP32 test vector 1
+    const SEED: [u8; 16] = hex!("000102030405060708090a0b0c0d0e0f");
+    const M_XPRV: &str = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi";
+    const M_XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+    const M_0H_XPRV: &str = "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7";
+    const M_0H: &str = "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw";
+    const M_0H_1: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+    const M_0H_1_2H: &str = "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5";
+    const M_0H_1_2H_2: &str = "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV";
+
+    // BIP84 account m/84'/0'/0' of the "abandon abandon ... about" mnemonic
+    const ZPUB: &str = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+
+    #[test]
+    fn test_xpub_round_trip() {
+        let xpub = ExtendedPubKey::from_str(M_0H).unwrap();
+        assert_eq!(xpub.depth, 1);
+        assert_eq!(xpub.child_number, HARDENED_INDEX);
+        assert!(!xpub.testnet);
+        assert_eq!(xpub.script_type, ScriptType::P2pkh);
+        assert_eq!(format!("{}", xpub), M_0H.to_string());
+    }
+
+    #[test]
+    fn test_xpub_derive_child() {
+        let xpub = ExtendedPubKey::from_str(M_0H).unwrap();
+        let child = xpub.derive_child(1).unwrap();
+        assert_eq!(child.parent_fingerprint, xpub.fingerprint());
+        assert_eq!(format!("{}", child), M_0H_1.to_string());
+
+        let xpub = ExtendedPubKey::from_str(M_0H_1_2H).unwrap();
+        assert_eq!(
+            format!(
+                "{}",
+                xpub.derive_path(&DerivationPath::from_str("m/2").unwrap())
+                    .unwrap()
+            ),
+            M_0H_1_2H_2.to_string()
+        );
+    }
+
+    #[test]
+    fn test_derive_errors() {
+        let xpub = ExtendedPubKey::from_str(M_0H).unwrap();
+        assert_eq!(
+            xpub.derive_child(HARDENED_INDEX),
+            Err(DeriveError::HardenedFromPublic(HARDENED_INDEX))
+        );
+        assert_eq!(
+            xpub.derive_path(&DerivationPath::from_str("m/1/2'").unwrap()),
+            Err(DeriveError::HardenedFromPublic(2 + HARDENED_INDEX))
+        );
+        let deepest = ExtendedPubKey { depth: 255, ..xpub };
+        assert_eq!(deepest.derive_child(0), Err(DeriveError::MaxDepth));
+
+        let master = ExtendedPrivKey::from_seed(&SEED, false).unwrap();
+        assert!(master.derive_child(u32::max_value()).is_ok());
+        let deepest = ExtendedPrivKey {
+            depth: 255,
+            ..master
+        };
+        assert_eq!(
+            deepest.derive_child(HARDENED_INDEX),
+            Err(DeriveError::MaxDepth)
+        );
+
+        // I_L of n or more, and I_L + k_par = 0
+        let parent = master.secret;
+        let n = hex!("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141");
+        assert_eq!(
+            child_secret(parent, &n, 7),
+            Err(DeriveError::InvalidChild(7))
+        );
+        assert_eq!(
+            child_secret(parent, &[0xff; 32], 7),
+            Err(DeriveError::InvalidChild(7))
+        );
+        assert_eq!(
+            child_secret(parent, &(-parent).to_be_bytes(), 7),
+            Err(DeriveError::InvalidChild(7))
+        );
+        assert_eq!(child_secret(parent, &[0; 32], 7), Ok(parent));
+    }
+
+    #[test]
+    fn test_bip32_invalid_keys() {
+        // BIP32 test vector 5
+        let invalid_xpubs = [
+            // pubkey version / prvkey mismatch
+            ("xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6LBpB85b3D2yc8sfvZU521AAwdZafEz7mnzBBsz4wKY5fTtTQBm", ExtendedKeyError::InvalidKeyData),
+            // invalid pubkey prefix 04
+            ("xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6Txnt3siSujt9RCVYsx4qHZGc62TG4McvMGcAUjeuwZdduYEvFn", ExtendedKeyError::InvalidKeyData),
+            // invalid pubkey prefix 01
+            ("xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6N8ZMMXctdiCjxTNq964yKkwrkBJJwpzZS4HS2fxvyYUA4q2Xe4", ExtendedKeyError::InvalidKeyData),
+            // zero depth with non-zero parent fingerprint
+            ("xpub661no6RGEX3uJkY4bNnPcw4URcQTrSibUZ4NqJEw5eBkv7ovTwgiT91XX27VbEXGENhYRCf7hyEbWrR3FewATdCEebj6znwMfQkhRYHRLpJ", ExtendedKeyError::InvalidMaster),
+            // zero depth with non-zero index
+            ("xpub661MyMwAuDcm6CRQ5N4qiHKrJ39Xe1R1NyfouMKTTWcguwVcfrZJaNvhpebzGerh7gucBvzEQWRugZDuDXjNDRmXzSZe4c7mnTK97pTvGS8", ExtendedKeyError::InvalidMaster),
+            // invalid pubkey 020000000000000000000000000000000000000000000000000000000000000007
+            ("xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6Q5JXayek4PRsn35jii4veMimro1xefsM58PgBMrvdYre8QyULY", ExtendedKeyError::InvalidKeyData),
+        ];
+        for (xpub, error) in invalid_xpubs.iter() {
+            assert_eq!(
+                ExtendedPubKey::from_str(xpub).as_ref(),
+                Err(error),
+                "{}",
+                xpub
+            );
+        }
+
+        let invalid_xprvs = [
+            // prvkey version / pubkey mismatch
+            ("xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFGTQQD3dC4H2D5GBj7vWvSQaaBv5cxi9gafk7NF3pnBju6dwKvH", ExtendedKeyError::InvalidKeyData),
+            // invalid prvkey prefix 04
+            ("xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFGpWnsj83BHtEy5Zt8CcDr1UiRXuWCmTQLxEK9vbz5gPstX92JQ", ExtendedKeyError::InvalidKeyData),
+            // invalid prvkey prefix 01
+            ("xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFAzHGBP2UuGCqWLTAPLcMtD9y5gkZ6Eq3Rjuahrv17fEQ3Qen6J", ExtendedKeyError::InvalidKeyData),
+            // zero depth with non-zero parent fingerprint
+            ("xprv9s2SPatNQ9Vc6GTbVMFPFo7jsaZySyzk7L8n2uqKXJen3KUmvQNTuLh3fhZMBoG3G4ZW1N2kZuHEPY53qmbZzCHshoQnNf4GvELZfqTUrcv", ExtendedKeyError::InvalidMaster),
+            // zero depth with non-zero index
+            ("xprv9s21ZrQH4r4TsiLvyLXqM9P7k1K3EYhA1kkD6xuquB5i39AU8KF42acDyL3qsDbU9NmZn6MsGSUYZEsuoePmjzsB3eFKSUEh3Gu1N3cqVUN", ExtendedKeyError::InvalidMaster),
+            // private key 0 not in 1..n-1
+            ("xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzF93Y5wvzdUayhgkkFoicQZcP3y52uPPxFnfoLZB21Teqt1VvEHx", ExtendedKeyError::InvalidKeyData),
+            // private key n not in 1..n-1
+            ("xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFAzHGBP2UuGCqWLTAPLcMtD5SDKr24z3aiUvKr9bJpdrcLg1y3G", ExtendedKeyError::InvalidKeyData),
+        ];
+        for (xprv, error) in invalid_xprvs.iter() {
+            assert_eq!(
+                ExtendedPrivKey::from_str(xprv).as_ref(),
+                Err(error),
+                "{}",
+                xprv
+            );
+        }
+
+        // unknown extended key version
+        for key in &[
+            "DMwo58pR1QLEFihHiXPVykYB6fJmsTeHvyTp7hRThAtCX8CvYzgPcn8XnmdfHGMQzT7ayAmfo4z3gY5KfbrZWZ6St24UVf2Qgo6oujFktLHdHY4",
+            "DMwo58pR1QLEFihHiXPVykYB6fJmsTeHvyTp7hRThAtCX8CvYzgPcn8XnmdfHPmHJiEDXkTiJTVV9rHEBUem2mwVbbNfvT2MTcAqj3nesx8uBf9",
+        ] {
+            match ExtendedPrivKey::from_str(key) {
+                Err(ExtendedKeyError::UnknownVersion(_)) => {}
+                other => panic!("{}: {:?}", key, other),
+            }
+        }
+
+        // invalid checksum
+        match ExtendedPrivKey::from_str("xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHL") {
+            Err(ExtendedKeyError::Base58Error(_)) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_xpub_parse_error() {
+        let mut broken = M_0H.to_string();
+        broken.pop();
+        assert!(ExtendedPubKey::from_str(&broken).is_err());
+        assert_eq!(
+            ExtendedPubKey::from_str(&encode_base58_checksum(&[0u8; 10])),
+            Err(ExtendedKeyError::InvalidLength(10))
+        );
+        assert_eq!(
+            ExtendedPubKey::from_str(M_XPRV),
+            Err(ExtendedKeyError::UnexpectedKeyType("public"))
+        );
+    }
+
+    #[test]
+    fn test_xprv_from_seed_and_derive() {
+        let master = ExtendedPrivKey::from_seed(&SEED, false).unwrap();
+        assert_eq!(format!("{}", master), M_XPRV.to_string());
+        assert_eq!(format!("{}", master.extended_pub_key()), M_XPUB.to_string());
+
+        let child = master.derive_child(HARDENED_INDEX).unwrap();
+        assert_eq!(format!("{}", child), M_0H_XPRV.to_string());
+        assert_eq!(format!("{}", child.extended_pub_key()), M_0H.to_string());
+
+        let parsed = ExtendedPrivKey::from_str(M_0H_XPRV).unwrap();
+        assert_eq!(parsed, child);
+        assert_eq!(
+            format!("{}", parsed.derive_child(1).unwrap().extended_pub_key()),
+            M_0H_1.to_string()
+        );
+
+        let path = DerivationPath::from_str("m/0h/1/2h").unwrap();
+        assert_eq!(
+            format!("{}", master.derive_path(&path).unwrap().extended_pub_key()),
+            M_0H_1_2H.to_string()
+        );
+        assert_eq!(master.key_source(&path).to_string(), "[3442193e/0'/1/2']");
+    }
+
+    #[test]
+    fn test_slip132_versions() {
+        let zpub = ExtendedPubKey::from_str(ZPUB).unwrap();
+        assert_eq!(zpub.script_type, ScriptType::P2wpkh);
+        assert!(!zpub.testnet);
+        assert_eq!(format!("{}", zpub), ZPUB.to_string());
+
+        let ypub = format!("{}", zpub.with_script_type(ScriptType::P2shP2wpkh));
+        assert!(ypub.starts_with("ypub"));
+        let xpub = format!("{}", zpub.with_script_type(ScriptType::P2pkh));
+        assert!(xpub.starts_with("xpub"));
+        assert_eq!(
+            ExtendedPubKey::from_str(&ypub).unwrap().public_key,
+            zpub.public_key
+        );
+
+        let master = ExtendedPrivKey::from_seed(&SEED, true).unwrap();
+        assert!(format!("{}", master).starts_with("tprv"));
+        let vprv = master.with_script_type(ScriptType::P2wpkh);
+        assert!(format!("{}", vprv).starts_with("vprv"));
+        assert!(format!("{}", vprv.extended_pub_key()).starts_with("vpub"));
+        let uprv = format!("{}", master.with_script_type(ScriptType::P2shP2wpkh));
+        assert!(uprv.starts_with("uprv"));
+        assert_eq!(
+            ExtendedPrivKey::from_str(&uprv).unwrap().script_type,
+            ScriptType::P2shP2wpkh
+        );
+    }
+}
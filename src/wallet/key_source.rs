@@ -0,0 +1,199 @@
+use super::derivation_path::{parse_child_number, DerivationPath, DerivationPathError};
+use super::{hash160, S256Point};
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum KeySourceError {
+    #[fail(display = "fingerprint must be 4 hex bytes")]
+    InvalidFingerprint,
+    #[fail(display = "invalid child number: {}", _0)]
+    InvalidChildNumber(String),
+    #[fail(display = "key origin must look like [fingerprint/path]")]
+    InvalidFormat,
+}
+
+impl From<DerivationPathError> for KeySourceError {
+    fn from(e: DerivationPathError) -> Self {
+        match e {
+            DerivationPathError::InvalidChildNumber(part) => {
+                KeySourceError::InvalidChildNumber(part)
+            }
+            DerivationPathError::MissingMaster => KeySourceError::InvalidFormat,
+        }
+    }
+}
+
+/// First 4 bytes of the hash160 of a compressed public key, the fingerprint of the master
+/// key identifies which wallet a derived key belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Fingerprint([u8; 4]);
+impl Copy for Fingerprint {}
+
+impl Fingerprint {
+    pub fn new(bytes: [u8; 4]) -> Self {
+        Fingerprint(bytes)
+    }
+
+    pub fn from_point(point: &S256Point) -> Self {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&hash160(&point.compressed_sec())[0..4]);
+        Fingerprint(bytes)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 4] {
+        self.0
+    }
+}
+
+impl AsRef<[u8]> for Fingerprint {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self))
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = KeySourceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|_| KeySourceError::InvalidFingerprint)?;
+        if bytes.len() != 4 {
+            return Err(KeySourceError::InvalidFingerprint);
+        }
+        let mut fingerprint = [0u8; 4];
+        fingerprint.copy_from_slice(&bytes);
+        Ok(Fingerprint(fingerprint))
+    }
+}
+
+/// Key origin: the master key fingerprint plus the derivation path from the master key,
+/// as used by PSBT bip32_derivation fields and descriptor key expressions
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeySource {
+    pub fingerprint: Fingerprint,
+    pub path: DerivationPath,
+}
+
+impl KeySource {
+    pub fn new(fingerprint: Fingerprint, path: DerivationPath) -> Self {
+        KeySource { fingerprint, path }
+    }
+
+    /// The origin of a child of the key this origin describes
+    pub fn child(&self, index: u32) -> Self {
+        KeySource {
+            fingerprint: self.fingerprint,
+            path: self.path.child(index),
+        }
+    }
+
+    /// PSBT serialization, fingerprint followed by each child number as little endian u32
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4 + 4 * self.path.len());
+        buf.extend_from_slice(&self.fingerprint.0);
+        for index in &self.path {
+            buf.extend_from_slice(&index.to_le_bytes());
+        }
+        buf
+    }
+
+    /// From the PSBT serialization, None unless it is a fingerprint and whole child numbers
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 4 || bytes.len() % 4 != 0 {
+            return None;
+        }
+        let mut fingerprint = [0u8; 4];
+        fingerprint.copy_from_slice(&bytes[..4]);
+        let path = bytes[4..]
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect::<Vec<u32>>();
+        Some(KeySource::new(Fingerprint(fingerprint), path.into()))
+    }
+}
+
+/// Descriptor notation, e.g. `[d34db33f/84'/0'/0']`
+impl Display for KeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}", self.fingerprint)?;
+        self.path.fmt_children(f)?;
+        write!(f, "]")
+    }
+}
+
+impl FromStr for KeySource {
+    type Err = KeySourceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.starts_with('[') || !s.ends_with(']') {
+            return Err(KeySourceError::InvalidFormat);
+        }
+        let mut parts = s[1..s.len() - 1].split('/');
+        let fingerprint = Fingerprint::from_str(parts.next().unwrap_or(""))?;
+
+        let path = parts
+            .map(parse_child_number)
+            .collect::<Result<Vec<u32>, _>>()?;
+
+        Ok(KeySource {
+            fingerprint,
+            path: path.into(),
+        })
+    }
+}
+
+mod test {
+    use super::super::extended_key::{ExtendedPrivKey, HARDENED_INDEX};
+    use super::{DerivationPath, Fingerprint, KeySource, KeySourceError};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_master_fingerprint() {
+        // BIP32 test vector 1
+        let master =
+            ExtendedPrivKey::from_seed(&hex!("000102030405060708090a0b0c0d0e0f"), false).unwrap();
+        assert_eq!(format!("{}", master.fingerprint()), "3442193e".to_string());
+
+        let child = master.derive_child(HARDENED_INDEX).unwrap();
+        assert_eq!(child.parent_fingerprint, master.fingerprint());
+    }
+
+    #[test]
+    fn test_key_source_display_and_parse() {
+        let source = KeySource::new(
+            Fingerprint::new([0xd3, 0x4d, 0xb3, 0x3f]),
+            DerivationPath::from_str("m/84'/0'/0'").unwrap(),
+        );
+        assert_eq!(format!("{}", source), "[d34db33f/84'/0'/0']".to_string());
+        assert_eq!(KeySource::from_str("[d34db33f/84h/0h/0h]").unwrap(), source);
+
+        let child = source.child(0).child(5);
+        assert_eq!(format!("{}", child), "[d34db33f/84'/0'/0'/0/5]".to_string());
+        assert_eq!(child.serialize().len(), 4 + 4 * 5);
+        assert_eq!(&child.serialize()[4..8], &hex!("54000080")[..]);
+        assert_eq!(KeySource::parse(&child.serialize()), Some(child));
+        assert_eq!(KeySource::parse(&[0xd3, 0x4d, 0xb3]), None);
+    }
+
+    #[test]
+    fn test_key_source_parse_error() {
+        assert_eq!(
+            KeySource::from_str("d34db33f/0"),
+            Err(KeySourceError::InvalidFormat)
+        );
+        assert_eq!(
+            KeySource::from_str("[d34db3/0]"),
+            Err(KeySourceError::InvalidFingerprint)
+        );
+        assert_eq!(
+            KeySource::from_str("[d34db33f/x]"),
+            Err(KeySourceError::InvalidChildNumber("x".to_string()))
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use super::account::key_hash_address;
+use super::extended_key::ScriptType;
+use super::private_key::{KeyError, PrivateKey};
+use super::secp256k1::ec::utils::U256;
+use super::secp256k1::utils::Hash160;
+use super::{hash160, S256Point, SchnorrSignature, Signature, XOnlyPublicKey};
+
+/// A private key with its public forms worked out once: the point, the compressed SEC, its
+/// hash160 and the x only key
+pub struct Keypair {
+    private_key: PrivateKey,
+    compressed_sec: [u8; 33],
+    hash160: Hash160,
+    x_only: XOnlyPublicKey,
+    /// Whether the point has an odd y, which the x only key drops
+    odd_y: bool,
+}
+
+impl Keypair {
+    pub fn new(private_key: PrivateKey) -> Self {
+        let compressed_sec = private_key.point.compressed_sec();
+        let (x_only, odd_y) = XOnlyPublicKey::from_point(&private_key.point);
+        Keypair {
+            hash160: hash160(&compressed_sec),
+            compressed_sec,
+            x_only,
+            odd_y,
+            private_key,
+        }
+    }
+
+    /// 32 bytes big endian secret, 1 <= secret < n
+    pub fn from_bytes(bytes: &[u8; 32]) -> Result<Self, KeyError> {
+        PrivateKey::from_bytes(bytes).map(Keypair::new)
+    }
+
+    pub fn private_key(&self) -> &PrivateKey {
+        &self.private_key
+    }
+
+    pub fn public_key(&self) -> &S256Point {
+        &self.private_key.point
+    }
+
+    pub fn compressed_sec(&self) -> &[u8; 33] {
+        &self.compressed_sec
+    }
+
+    /// hash160 of the compressed SEC
+    pub fn hash160(&self) -> Hash160 {
+        self.hash160
+    }
+
+    /// The x only key and whether the full point has an odd y
+    pub fn x_only_public_key(&self) -> (XOnlyPublicKey, bool) {
+        (self.x_only, self.odd_y)
+    }
+
+    /// Address of the compressed key for `script_type`
+    pub fn address(&self, testnet: bool, script_type: ScriptType) -> String {
+        key_hash_address(&self.hash160, testnet, script_type)
+    }
+
+    pub fn sign(&self, z: U256) -> Signature {
+        self.private_key.sign(z)
+    }
+
+    pub fn sign_schnorr(&self, msg: &[u8], aux: &[u8; 32]) -> SchnorrSignature {
+        self.private_key.sign_schnorr(msg, aux)
+    }
+}
+
+impl From<PrivateKey> for Keypair {
+    fn from(private_key: PrivateKey) -> Self {
+        Keypair::new(private_key)
+    }
+}
+
+mod test {
+    use super::Keypair;
+    use crate::wallet::extended_key::ScriptType;
+    use crate::wallet::private_key::PrivateKey;
+    use crate::wallet::{Hash256, S256Point, U256};
+
+    #[test]
+    fn test_keypair() {
+        let keypair = Keypair::new(PrivateKey::new(U256::from(888u16).pow(U256::from(3u8))));
+        assert_eq!(
+            keypair.address(false, ScriptType::P2pkh),
+            "148dY81A9BmdpMhvYEVznrM45kWN32vSCN"
+        );
+        assert_eq!(
+            keypair.address(true, ScriptType::P2pkh),
+            keypair.public_key().address(true, true)
+        );
+        assert_eq!(
+            keypair.compressed_sec(),
+            &keypair.public_key().compressed_sec()
+        );
+        assert_eq!(keypair.hash160(), keypair.public_key().hash160(true));
+        assert!(keypair
+            .address(false, ScriptType::P2wpkh)
+            .starts_with("bc1q"));
+        assert!(keypair
+            .address(true, ScriptType::P2shP2wpkh)
+            .starts_with('2'));
+
+        let z = U256::from(999u16);
+        assert!(keypair
+            .public_key()
+            .verify(Hash256::from(z), keypair.sign(z)));
+        let (x_only, _) = keypair.x_only_public_key();
+        let sig = keypair.sign_schnorr(&[1u8; 32], &[0u8; 32]);
+        assert!(sig.verify(&x_only, &[1u8; 32]));
+
+        let mut bytes = [0u8; 32];
+        assert!(Keypair::from_bytes(&bytes).is_err());
+        bytes[31] = 1;
+        assert_eq!(
+            Keypair::from_bytes(&bytes).unwrap().public_key(),
+            &S256Point::gen_point()
+        );
+    }
+
+    #[test]
+    fn test_snapshots() {
+        use crate::testkit::Snapshot;
+        use crate::wallet::taproot::TaprootSpendInfo;
+
+        let keypair = Keypair::new(PrivateKey::new(U256::from(888u16).pow(U256::from(3u8))));
+        let mut snapshot = Snapshot::new("addresses");
+        for &(name, script_type) in &[
+            ("p2pkh", ScriptType::P2pkh),
+            ("p2sh-p2wpkh", ScriptType::P2shP2wpkh),
+            ("p2wpkh", ScriptType::P2wpkh),
+        ] {
+            snapshot
+                .text(name, &keypair.address(false, script_type))
+                .text(
+                    &format!("{}.testnet", name),
+                    &keypair.address(true, script_type),
+                );
+        }
+        let taproot = TaprootSpendInfo::new(&keypair.private_key.point, None).unwrap();
+        snapshot
+            .text("p2tr", &taproot.address(false))
+            .text("p2tr.testnet", &taproot.address(true))
+            .hex("p2tr.script_pubkey", &taproot.script_pubkey().content)
+            .text("wif", &keypair.private_key.wif(true, false));
+        snapshot.check();
+    }
+}
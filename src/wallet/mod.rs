@@ -1,10 +1,40 @@
+pub mod account;
+pub mod bech32;
+pub mod bip322;
+pub mod commitments;
+pub mod derivation_path;
+pub mod extended_key;
+pub mod key_source;
+pub mod keypair;
+pub mod nostr;
 pub mod private_key;
 mod secp256k1;
+pub mod silent_payments;
+pub mod store;
+pub mod taproot;
 
-pub use secp256k1::ec::hex::{FromHex, Hex};
+pub use secp256k1::ec::hex::{FromHex, Hex, Parse, Serialize};
+pub use secp256k1::ec::utils::U256;
 pub use secp256k1::s256_point::S256Point;
-pub use secp256k1::signature::Signature;
+pub use secp256k1::s256_scalar::{S256Scalar, TweakError};
+pub use secp256k1::schnorr::SchnorrSignature;
+pub use secp256k1::signature::{DerParseError, DerViolation, RecoverableSignature, Signature};
 pub use secp256k1::utils::hash160;
 pub use secp256k1::utils::hash256;
+pub use secp256k1::utils::tagged_hash;
 pub use secp256k1::utils::Hash160;
 pub use secp256k1::utils::Hash256;
+pub use secp256k1::utils::{decode_base58_checksum, encode_base58_checksum};
+pub use secp256k1::x_only_key::XOnlyPublicKey;
+
+pub use account::{Account, AccountScan, ScannedUtxo};
+pub use derivation_path::{DerivationPath, DerivationPathError};
+pub use extended_key::{ExtendedPrivKey, ExtendedPubKey, ScriptType};
+pub use key_source::{Fingerprint, KeySource};
+pub use keypair::Keypair;
+pub use private_key::{KeyError, PrivateKey};
+pub use silent_payments::{SilentPaymentAddress, SilentPaymentReceiver};
+pub use store::{
+    ConsolidationPlan, Label, LabelRef, RescanOutcome, RescanProgress, TxStatus, Utxo, WalletStore,
+};
+pub use taproot::{ControlBlock, TapLeaf, TapTree, TaprootSpendInfo};
@@ -0,0 +1,210 @@
+use super::bech32::{convert_bits, decode, encode, Bech32Error, Variant};
+use super::private_key::PrivateKey;
+use super::{SchnorrSignature, XOnlyPublicKey};
+use sha2::{Digest, Sha256};
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum NostrError {
+    #[fail(display = "{}", _0)]
+    Bech32Error(Bech32Error),
+    #[fail(display = "expected a {} string", _0)]
+    UnexpectedPrefix(&'static str),
+    #[fail(display = "invalid key length: {}", _0)]
+    InvalidLength(usize),
+    #[fail(display = "secret key out of range")]
+    InvalidSecret,
+}
+
+/// NIP-19 bech32 entity, plain bech32 checksum over a 32 bytes payload
+fn encode_entity(hrp: &str, bytes: &[u8; 32]) -> String {
+    let data = convert_bits(bytes, 8, 5, true).expect("8 bit bytes always convert");
+    encode(hrp, &data, Variant::Bech32)
+}
+
+fn decode_entity(hrp: &'static str, s: &str) -> Result<[u8; 32], NostrError> {
+    let (s_hrp, data, variant) = decode(s).map_err(NostrError::Bech32Error)?;
+    if s_hrp != hrp || variant != Variant::Bech32 {
+        return Err(NostrError::UnexpectedPrefix(hrp));
+    }
+    let bytes = convert_bits(&data, 5, 8, false).map_err(NostrError::Bech32Error)?;
+    if bytes.len() != 32 {
+        return Err(NostrError::InvalidLength(bytes.len()));
+    }
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(&bytes);
+    Ok(buf)
+}
+
+/// npub of an x only public key
+pub fn npub_encode(pubkey: &[u8; 32]) -> String {
+    encode_entity("npub", pubkey)
+}
+
+pub fn npub_decode(s: &str) -> Result<[u8; 32], NostrError> {
+    decode_entity("npub", s)
+}
+
+pub fn nsec_encode(key: &PrivateKey) -> String {
+    encode_entity("nsec", &key.to_bytes())
+}
+
+pub fn nsec_decode(s: &str) -> Result<PrivateKey, NostrError> {
+    PrivateKey::from_bytes(&decode_entity("nsec", s)?).map_err(|_| NostrError::InvalidSecret)
+}
+
+/// NIP-01 string escaping, only these characters are escaped, everything else is kept verbatim
+fn escape_json(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            '\u{8}' => ret.push_str("\\b"),
+            '\u{c}' => ret.push_str("\\f"),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Nostr event, the id commits to every field and the signature signs the id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub id: [u8; 32],
+    pub pubkey: [u8; 32],
+    pub created_at: u64,
+    pub kind: u32,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: Option<SchnorrSignature>,
+}
+
+impl Event {
+    /// Unsigned event with its id
+    pub fn new(
+        pubkey: [u8; 32],
+        created_at: u64,
+        kind: u32,
+        tags: Vec<Vec<String>>,
+        content: String,
+    ) -> Self {
+        let mut event = Event {
+            id: [0u8; 32],
+            pubkey,
+            created_at,
+            kind,
+            tags,
+            content,
+            sig: None,
+        };
+        event.id = event.compute_id();
+        event
+    }
+
+    /// Signed event, `aux` is the BIP340 auxiliary randomness
+    pub fn sign(
+        key: &PrivateKey,
+        created_at: u64,
+        kind: u32,
+        tags: Vec<Vec<String>>,
+        content: String,
+        aux: &[u8; 32],
+    ) -> Self {
+        let mut event = Event::new(key.point.x_only(), created_at, kind, tags, content);
+        event.sig = Some(key.sign_schnorr(&event.id, aux));
+        event
+    }
+
+    /// `[0,<pubkey>,<created_at>,<kind>,<tags>,<content>]` without any whitespace
+    pub fn serialize_for_id(&self) -> String {
+        let tags = self
+            .tags
+            .iter()
+            .map(|tag| {
+                let items: Vec<String> = tag.iter().map(|item| escape_json(item)).collect();
+                format!("[{}]", items.join(","))
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        format!(
+            "[0,\"{}\",{},{},[{}],{}]",
+            hex::encode(&self.pubkey),
+            self.created_at,
+            self.kind,
+            tags,
+            escape_json(&self.content)
+        )
+    }
+
+    pub fn compute_id(&self) -> [u8; 32] {
+        let mut id = [0u8; 32];
+        id.copy_from_slice(&Sha256::digest(self.serialize_for_id().as_bytes()));
+        id
+    }
+
+    /// The id matches the content and the signature is valid for the pubkey
+    pub fn verify(&self) -> bool {
+        match self.sig {
+            Some(sig) => {
+                self.id == self.compute_id()
+                    && XOnlyPublicKey::parse(&self.pubkey)
+                        .map_or(false, |pubkey| sig.verify(&pubkey, &self.id))
+            }
+            None => false,
+        }
+    }
+}
+
+mod test {
+    use super::{npub_decode, npub_encode, nsec_decode, nsec_encode, Event, NostrError};
+    use crate::wallet::private_key::PrivateKey;
+    use crate::wallet::secp256k1::ec::utils::U256;
+
+    #[test]
+    fn test_npub_nsec() {
+        // NIP-19 examples
+        let pubkey = hex!("3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d");
+        let npub = "npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6";
+        assert_eq!(npub_encode(&pubkey), npub.to_string());
+        assert_eq!(npub_decode(npub).unwrap(), pubkey);
+
+        let key = PrivateKey::new(U256::from_hex(
+            b"67dea2ed018072d675f5415ecfaed7d2597555e202d85b3d65ea4e58d2d92ffa",
+        ));
+        let nsec = "nsec1vl029mgpspedva04g90vltkh6fvh240zqtv9k0t9af8935ke9laqsnlfe5";
+        assert_eq!(nsec_encode(&key), nsec.to_string());
+        assert_eq!(nsec_decode(nsec).unwrap().to_bytes(), key.to_bytes());
+
+        assert_eq!(
+            npub_decode(nsec).err(),
+            Some(NostrError::UnexpectedPrefix("npub"))
+        );
+    }
+
+    #[test]
+    fn test_event_sign_and_verify() {
+        let key = PrivateKey::new(U256::from(3u8));
+        let tags = vec![vec!["t".to_string(), "bitcoin".to_string()]];
+        let mut event = Event::sign(
+            &key,
+            1_700_000_000,
+            1,
+            tags,
+            "hello \"nostr\"\n".to_string(),
+            &[0u8; 32],
+        );
+        assert_eq!(
+            event.serialize_for_id(),
+            "[0,\"f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9\",1700000000,1,[[\"t\",\"bitcoin\"]],\"hello \\\"nostr\\\"\\n\"]".to_string()
+        );
+        assert!(event.verify());
+
+        event.content = "tampered".to_string();
+        assert!(!event.verify());
+    }
+}
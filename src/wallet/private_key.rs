@@ -1,11 +1,14 @@
 use super::secp256k1::ec::utils::U256;
-use super::secp256k1::s256_point::{S256Point, Secp256K1EllipticCurve};
-use super::secp256k1::signature::Signature;
+use super::secp256k1::s256_point::S256Point;
+use super::secp256k1::s256_scalar::{S256Scalar, TweakError};
+use super::secp256k1::schnorr::{challenge, SchnorrSignature};
+use super::secp256k1::signature::{RecoverableSignature, Signature};
 use super::secp256k1::utils::encode_base58_checksum;
+use super::secp256k1::utils::tagged_hash;
+use super::secp256k1::x_only_key::XOnlyPublicKey;
 use crate::wallet::Hex;
 use bytes::{BufMut, BytesMut};
 use hmac::{Hmac, Mac};
-use num_bigint::BigUint;
 use sha2::Sha256;
 
 fn hmac_sha256_digest(key: &[u8], data: &[u8]) -> Vec<u8> {
@@ -15,60 +18,166 @@ fn hmac_sha256_digest(key: &[u8], data: &[u8]) -> Vec<u8> {
     mac.result().code().to_vec()
 }
 
+/// Why a secret is not a private key
+#[derive(Fail, Debug, PartialEq, Eq, Clone)]
+pub enum KeyError {
+    #[fail(display = "secret key is zero")]
+    Zero,
+    #[fail(display = "secret key is not below the curve order")]
+    OutOfRange,
+    #[fail(display = "secret key of {} bytes, expected 32", _0)]
+    InvalidLength(usize),
+}
+impl Copy for KeyError {}
+
 pub struct PrivateKey {
-    secret: U256,
+    secret: S256Scalar,
     pub point: S256Point,
 }
 
 impl PrivateKey {
+    /// Panics unless 1 <= secret < n, `from_bytes` reports it instead
     pub fn new(secret: U256) -> Self {
-        PrivateKey {
+        let mut bytes = [0u8; 32];
+        secret.to_big_endian(&mut bytes);
+        match PrivateKey::from_bytes(&bytes) {
+            Ok(key) => key,
+            Err(e) => panic!("invalid private key: {}", e),
+        }
+    }
+
+    /// 32 bytes big endian secret, 1 <= secret < n
+    pub fn from_bytes(bytes: &[u8; 32]) -> Result<Self, KeyError> {
+        let secret = S256Scalar::from_be_bytes(bytes).ok_or(KeyError::OutOfRange)?;
+        if secret.is_zero() {
+            return Err(KeyError::Zero);
+        }
+        Ok(PrivateKey {
             secret,
             point: S256Point::gen_point() * secret,
+        })
+    }
+
+    /// `from_bytes` of a slice that has to be 32 bytes long
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, KeyError> {
+        if bytes.len() != 32 {
+            return Err(KeyError::InvalidLength(bytes.len()));
         }
+        let mut buf = [0u8; 32];
+        buf.copy_from_slice(bytes);
+        PrivateKey::from_bytes(&buf)
     }
 
+    pub fn secret(&self) -> S256Scalar {
+        self.secret
+    }
+
+    /// secret + t, refused when the sum is zero
+    pub fn add_tweak(&self, tweak: &[u8; 32]) -> Result<PrivateKey, TweakError> {
+        let secret = self.secret + S256Scalar::from_tweak(tweak)?;
+        if secret.is_zero() {
+            return Err(TweakError::InvalidResult);
+        }
+        Ok(PrivateKey::new(secret.num()))
+    }
+
+    /// secret * t, a zero tweak is refused
+    pub fn mul_tweak(&self, tweak: &[u8; 32]) -> Result<PrivateKey, TweakError> {
+        let tweak = S256Scalar::from_tweak(tweak)?;
+        if tweak.is_zero() {
+            return Err(TweakError::ZeroTweak);
+        }
+        Ok(PrivateKey::new((self.secret * tweak).num()))
+    }
+
+    /// 32 bytes big endian secret
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.secret.to_be_bytes()
+    }
+
+    /// ECDSA signature of the hash `z`, taken mod n
     pub fn sign(&self, z: U256) -> Signature {
-        let n = Secp256K1EllipticCurve::n();
+        let z = S256Scalar::new(z);
+        let mut k = self.deterministic_k(z);
+        while k.is_zero() {
+            k = S256Scalar::new(U256::from_random());
+        }
+        self.sign_with_nonce(z, k)
+    }
+
+    /// `sign` keeping the recovery id, for a compressed key
+    pub fn sign_recoverable(&self, z: U256) -> RecoverableSignature {
+        let z = S256Scalar::new(z);
         let mut k = self.deterministic_k(z);
-        while k > n {
-            k = U256::from_random();
+        while k.is_zero() {
+            k = S256Scalar::new(U256::from_random());
         }
+        self.sign_recoverable_with_nonce(z, k)
+    }
 
-        let gen_point = S256Point::gen_point();
-        let r = (gen_point * k).coordinate().unwrap().0;
-        let k_inv = k.modpow(n - U256::from(2u32), n);
+    /// ECDSA signature with a caller chosen nonce `k`, never reuse a nonce
+    pub(crate) fn sign_with_nonce(&self, z: S256Scalar, k: S256Scalar) -> Signature {
+        self.sign_recoverable_with_nonce(z, k).sig
+    }
 
-        // let mut s = u256_modmul(z + r * self.secret, k_inv, n);
-        let mut s = (Into::<BigUint>::into(z)
-            + Into::<BigUint>::into(r) * Into::<BigUint>::into(self.secret))
-            * Into::<BigUint>::into(k_inv);
-        s = s % Into::<BigUint>::into(n);
-        let mut s: U256 = s.into();
+    fn sign_recoverable_with_nonce(&self, z: S256Scalar, k: S256Scalar) -> RecoverableSignature {
+        let (x, y) = (S256Point::gen_point() * k).coordinate().unwrap();
+        // r is the x coordinate of kG, a field element, taken mod n
+        let r = S256Scalar::new(x);
+        let mut recovery_id = if y.is_even() { 0 } else { 1 };
+        if r.num() != x {
+            recovery_id |= 2;
+        }
+        let mut s = (z + r * self.secret) * k.invert();
         // It turns out that using the low-s value will get nodes to relay our transactions.
-        // This is for malleability reasons.
-        if s > n / U256::from(2u32) {
-            s = n - s;
+        // This is for malleability reasons. -s signs for -kG, which has the other y.
+        if s.is_high() {
+            s = -s;
+            recovery_id ^= 1;
         }
 
-        Signature::new(r, s)
+        RecoverableSignature {
+            sig: Signature::new(r.num(), s.num()),
+            recovery_id,
+            compressed: true,
+        }
     }
 
-    /// RFC 6979 use *secret* and *z* to create a unique, deterministic **K** every time
-    fn deterministic_k(&self, z: U256) -> U256 {
-        let n: U256 = Secp256K1EllipticCurve::n();
+    /// BIP340 schnorr signature of `msg` with auxiliary randomness `aux`
+    pub fn sign_schnorr(&self, msg: &[u8], aux: &[u8; 32]) -> SchnorrSignature {
+        // negate the secret when the public key has an odd y
+        let (pubkey, odd) = XOnlyPublicKey::from_point(&self.point);
+        let d = if odd { -self.secret } else { self.secret };
+        let pubkey = pubkey.serialize();
+
+        let d_bytes = d.to_be_bytes();
+        let aux_hash = tagged_hash("BIP0340/aux", aux);
+        let mut buf = Vec::with_capacity(64 + msg.len());
+        buf.extend(d_bytes.iter().zip(aux_hash.iter()).map(|(a, b)| a ^ b));
+        buf.extend_from_slice(&pubkey);
+        buf.extend_from_slice(msg);
+
+        let k = S256Scalar::reduce_be_bytes(&tagged_hash("BIP0340/nonce", &buf));
+        assert!(
+            !k.is_zero(),
+            "invalid schnorr nonce, use other aux randomness"
+        );
+        let nonce_point = S256Point::gen_point() * k;
+        let (_, y) = nonce_point.coordinate().unwrap();
+        let k = if y.is_even() { k } else { -k };
+        let r = nonce_point.x_only();
+
+        let e = S256Scalar::new(challenge(&r, &pubkey, msg));
+        SchnorrSignature::new(r, (k + e * d).num())
+    }
 
+    /// RFC 6979 use *secret* and *z* to create a unique, deterministic **K** every time
+    pub(crate) fn deterministic_k(&self, z: S256Scalar) -> S256Scalar {
         let mut k = vec![b'\x00'; 32];
         let mut v = vec![b'\x01'; 32];
-        let mut z = z;
-        if z > n {
-            z = z - n;
-        }
 
-        let mut z_bytes = [0u8; 32];
-        z.to_big_endian(&mut z_bytes);
-        let mut secret_bytes = [0u8; 32];
-        self.secret.to_big_endian(&mut secret_bytes);
+        let z_bytes = z.to_be_bytes();
+        let secret_bytes = self.secret.to_be_bytes();
 
         let mut buf = BytesMut::with_capacity(256);
 
@@ -90,9 +199,11 @@ impl PrivateKey {
 
         loop {
             v = hmac_sha256_digest(&k, &v);
-            let candidate = U256::from_big_endian(&v);
-            if candidate >= U256::from(1u8) && candidate < n {
-                return candidate;
+            let mut candidate = [0u8; 32];
+            candidate.copy_from_slice(&v);
+            match S256Scalar::from_be_bytes(&candidate) {
+                Some(candidate) if !candidate.is_zero() => return candidate,
+                _ => {}
             }
 
             buf.put(&v[..]);
@@ -104,8 +215,7 @@ impl PrivateKey {
     }
 
     pub fn wif(&self, compressed: bool, testnet: bool) -> String {
-        let mut secret_bytes = [0u8; 32];
-        self.secret.to_big_endian(&mut secret_bytes);
+        let secret_bytes = self.secret.to_be_bytes();
 
         let prefix = if testnet {
             vec![b'\xef']
@@ -122,13 +232,14 @@ impl PrivateKey {
 
 impl Hex for PrivateKey {
     fn hex(&self) -> String {
-        self.secret.hex()
+        self.secret.num().hex()
     }
 }
 
 mod test {
     use super::super::secp256k1::ec::utils::{pow, U256};
-    use super::PrivateKey;
+    use super::super::secp256k1::s256_point::Secp256K1EllipticCurve;
+    use super::{KeyError, PrivateKey, TweakError, XOnlyPublicKey};
     use crate::wallet::Hash256;
     use num_bigint::BigUint;
 
@@ -169,6 +280,33 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_from_bytes() {
+        let mut bytes = [0u8; 32];
+        assert_eq!(PrivateKey::from_bytes(&bytes).err(), Some(KeyError::Zero));
+        bytes[31] = 5;
+        let key = PrivateKey::from_bytes(&bytes).unwrap();
+        assert_eq!(key.to_bytes(), bytes);
+        assert_eq!(key.point, PrivateKey::new(U256::from(5u8)).point);
+        assert!(PrivateKey::from_slice(&bytes).is_ok());
+        assert_eq!(
+            PrivateKey::from_slice(&bytes[1..]).err(),
+            Some(KeyError::InvalidLength(31))
+        );
+
+        let mut n = [0u8; 32];
+        Secp256K1EllipticCurve::n().to_big_endian(&mut n);
+        assert_eq!(PrivateKey::from_bytes(&n).err(), Some(KeyError::OutOfRange));
+        n[31] -= 1;
+        assert!(PrivateKey::from_bytes(&n).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid private key")]
+    fn test_new_zero() {
+        PrivateKey::new(U256::zero());
+    }
+
     #[test]
     fn test_address() {
         let secret: BigUint = pow(BigUint::from(888u16), BigUint::from(3u8));
@@ -194,4 +332,61 @@ mod test {
         let sig = pk.sign(z);
         assert_eq!(pk.point.verify(Hash256::from(z), sig), true);
     }
+
+    #[test]
+    fn test_sign_schnorr() {
+        // BIP340 test vectors 0 and 1
+        let pk = PrivateKey::new(U256::from(3u8));
+        let sig = pk.sign_schnorr(&[0u8; 32], &[0u8; 32]);
+        assert_eq!(
+            hex::encode(&sig.serialize()[..]),
+            "e907831f80848d1069a5371b402410364bdf1c5f8307b0084c55f1ce2dca821525f66a4a85ea8b71e482a74f382d2ce5ebeee8fdb2172f477df4900d310536c0".to_string()
+        );
+        assert!(sig.verify(&XOnlyPublicKey::from_point(&pk.point).0, &[0u8; 32]));
+
+        let pk = PrivateKey::new(U256::from_hex(
+            b"b7e151628aed2a6abf7158809cf4f3c762e7160f38b4da56a784d9045190cfef",
+        ));
+        let mut aux = [0u8; 32];
+        aux[31] = 1;
+        let msg = hex!("243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89");
+        let sig = pk.sign_schnorr(&msg, &aux);
+        assert_eq!(
+            hex::encode(&sig.serialize()[..]),
+            "6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de33418906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a".to_string()
+        );
+    }
+
+    #[test]
+    fn test_tweak() {
+        let pk = PrivateKey::new(U256::from(1_000u16));
+        let mut tweak = [0u8; 32];
+        tweak[31] = 7;
+
+        let added = pk.add_tweak(&tweak).unwrap();
+        assert_eq!(added.point, PrivateKey::new(U256::from(1_007u16)).point);
+        assert_eq!(pk.point.add_tweak(&tweak).unwrap(), added.point);
+        let multiplied = pk.mul_tweak(&tweak).unwrap();
+        assert_eq!(
+            multiplied.point,
+            PrivateKey::new(U256::from(7_000u16)).point
+        );
+        assert_eq!(pk.point.mul_tweak(&tweak).unwrap(), multiplied.point);
+
+        assert_eq!(
+            pk.add_tweak(&[0xff; 32]).err(),
+            Some(TweakError::OutOfRange)
+        );
+        assert_eq!(
+            pk.point.mul_tweak(&[0u8; 32]).err(),
+            Some(TweakError::ZeroTweak)
+        );
+        // n - 1000 brings the key to zero
+        let minus = (-pk.secret()).to_be_bytes();
+        assert_eq!(pk.add_tweak(&minus).err(), Some(TweakError::InvalidResult));
+        assert_eq!(
+            pk.point.add_tweak(&minus).err(),
+            Some(TweakError::InvalidResult)
+        );
+    }
 }
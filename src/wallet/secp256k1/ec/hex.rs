@@ -18,12 +18,58 @@ pub trait FromHex {
     fn from_hex(hex: &[u8]) -> Self;
 }
 
+/// Wire encoding of a type, every implementor is `Hex`
+pub trait Serialize {
+    fn serialize_bytes(&self) -> Vec<u8>;
+}
+
+/// Wire decoding of a whole value, None on malformed or trailing bytes. Every implementor
+/// is `FromHex`.
+pub trait Parse: Sized {
+    fn parse_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl<T: Serialize> Hex for T {
+    fn hex(&self) -> String {
+        hex::encode(self.serialize_bytes())
+    }
+}
+
+impl<T: Parse> FromHex for T {
+    fn from_hex(hex: &[u8]) -> Self {
+        let bytes = hex::decode(hex).expect("hex str decode error");
+        T::parse_bytes(&bytes).expect("hex does not encode a whole value")
+    }
+}
+
 mod test {
-    use super::Hex;
+    use super::{FromHex, Hex, Parse};
+    use crate::block::BlockHeader;
+    use crate::transaction::{Transaction, TxOutput};
+    use crate::wallet::{S256Point, Signature};
 
     #[test]
     fn test_vec_u8_hex() {
         let s = vec![1, 2, 15, 16u8];
         assert_eq!("01020f10".to_string(), s.hex());
     }
+
+    #[test]
+    fn test_wire_types_round_trip() {
+        let tx = "0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600";
+        assert_eq!(Transaction::from_hex(tx).unwrap().hex(), tx);
+        let output = "a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac";
+        assert_eq!(TxOutput::from_hex(output.as_bytes()).hex(), output);
+        let header = "020000208ec39428b17323fa0ddec8e887b4a7c53b8c0a0a220cfd0000000000000000005b0750fce0a889502d40508d39576821155e9c9e3f5c3157f961db38fd8b25be1e77a759e93c0118a4ffd71d";
+        assert_eq!(BlockHeader::from_hex(header).unwrap().hex(), header);
+        let sig = "3045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed";
+        assert_eq!(Signature::from_hex(sig.as_bytes()).hex(), sig);
+        let point = "0349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278a";
+        assert_eq!(S256Point::from_hex(point.as_bytes()).hex(), point);
+
+        // trailing bytes and malformed encodings are rejected
+        assert!(Transaction::parse_bytes(&hex::decode(format!("{}00", tx)).unwrap()).is_none());
+        assert!(Signature::parse_bytes(&hex::decode(&sig[..sig.len() - 2]).unwrap()).is_none());
+        assert!(S256Point::parse_bytes(&[0x05; 33]).is_none());
+    }
 }
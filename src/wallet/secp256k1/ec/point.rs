@@ -1,7 +1,7 @@
 use super::field_element::FieldElement;
 use super::utils::U256;
 use std::fmt;
-use std::ops::{Add, Mul};
+use std::ops::{Add, Mul, Neg, Sub};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum PointValue {
@@ -72,12 +72,15 @@ impl Copy for Point {}
 pub enum PointError {
     NotInEllipticCurves,
     NotInSameEllipticCurves,
+    /// SEC bytes with an unknown prefix or the wrong length
+    InvalidSecEncoding,
 }
 impl fmt::Display for PointError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             PointError::NotInEllipticCurves => write!(f, "NotInEllipticCurves Error"),
             PointError::NotInSameEllipticCurves => write!(f, "NotInSameEllipticCurves Error"),
+            PointError::InvalidSecEncoding => write!(f, "InvalidSecEncoding Error"),
         }
     }
 }
@@ -86,6 +89,7 @@ impl std::error::Error for PointError {
         match self {
             PointError::NotInEllipticCurves => "The Point NotInEllipticCurves",
             PointError::NotInSameEllipticCurves => "The Points NotInSameEllipticCurves",
+            PointError::InvalidSecEncoding => "The Point InvalidSecEncoding",
         }
     }
 }
@@ -162,6 +166,32 @@ impl Add<Point> for Point {
     }
 }
 
+impl Neg for Point {
+    type Output = Self;
+
+    /// The point with y mirrored, P + (-P) is infinity
+    fn neg(self) -> Self::Output {
+        match self.point {
+            PointValue::NormalPoint { x, y } => Point {
+                point: PointValue::NormalPoint {
+                    x,
+                    y: FieldElement::new(U256::zero(), y.prime()) - y,
+                },
+                elliptic_curve: self.elliptic_curve,
+            },
+            PointValue::InfPoint => self,
+        }
+    }
+}
+
+impl Sub<Point> for Point {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self + -rhs
+    }
+}
+
 impl<T> Mul<T> for Point
 where
     T: Into<U256>,
@@ -246,6 +276,43 @@ mod test {
         assert_eq!(inf + p1 + p1, p1 + p1);
     }
 
+    #[test]
+    fn test_neg_sub() {
+        let prime = 223;
+        let a = FieldElement::new(0, prime);
+        let b = FieldElement::new(7, prime);
+        let p1 = Point::new(
+            FieldElement::new(192, prime),
+            FieldElement::new(105, prime),
+            a,
+            b,
+        )
+        .unwrap();
+        let p2 = Point::new(
+            FieldElement::new(17, prime),
+            FieldElement::new(56, prime),
+            a,
+            b,
+        )
+        .unwrap();
+        let inf = Point::inf(a, b);
+
+        assert_eq!(
+            -p1,
+            Point::new(
+                FieldElement::new(192, prime),
+                FieldElement::new(118, prime),
+                a,
+                b
+            )
+            .unwrap()
+        );
+        assert_eq!(p1 - p1, inf);
+        assert_eq!(p1 + p2 - p2, p1);
+        assert_eq!(-inf, inf);
+        assert_eq!(inf - p1, -p1);
+    }
+
     #[test]
     fn test_scalar_mul() {
         let prime = 223;
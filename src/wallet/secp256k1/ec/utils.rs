@@ -13,8 +13,10 @@ construct_uint! {
     pub struct U512(8);
 }
 
-// todo
-// U256 is used by default for calculation. If there is an overflow panic, use BigUint.
+// Overflow policy: the `+`, `-` and `*` operators of U256 panic on overflow in every build,
+// so key and signature code only uses them where the operands are known to be in range.
+// Anything that may exceed 256 bits goes through `checked_*` / `overflowing_*` (from
+// `construct_uint!`), `widening_mul`, or the `mod_*` helpers which never overflow.
 impl U256 {
     pub fn is_even(&self) -> bool {
         self % U256::from(2u8) == U256::from(0u8)
@@ -34,19 +36,51 @@ impl U256 {
         value.modpow(&exp, &modulus).into()
     }
 
+    /// Product that must fit 256 bits, panics with a clear message otherwise
     pub fn mul(self, rhs: U256) -> U256 {
-        let lhs: BigUint = self.into();
-        let rhs: BigUint = rhs.into();
-
-        (lhs * rhs).into()
+        self.checked_mul(rhs).expect("U256 multiplication overflow")
+    }
+
+    /// Full 512 bits product, never overflows
+    pub fn widening_mul(self, rhs: U256) -> U512 {
+        let mut wide = [0u64; 8];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let t =
+                    u128::from(wide[i + j]) + u128::from(self.0[i]) * u128::from(rhs.0[j]) + carry;
+                wide[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            wide[i + 4] = carry as u64;
+        }
+        U512(wide)
     }
 
     pub fn modmul(self, rhs: U256, modulus: U256) -> U256 {
-        let lhs: BigUint = self.into();
-        let rhs: BigUint = rhs.into();
-        let modulus: BigUint = modulus.into();
+        let modulus: U512 = modulus.into();
+        (self.widening_mul(rhs) % modulus).into()
+    }
 
-        (lhs * rhs % modulus).into()
+    /// (self + rhs) % modulus for any operands, the carry out of 256 bits is kept
+    pub fn mod_add(self, rhs: U256, modulus: U256) -> U256 {
+        let (a, b) = (self % modulus, rhs % modulus);
+        let (sum, overflow) = a.overflowing_add(b);
+        if overflow || sum >= modulus {
+            sum.overflowing_sub(modulus).0
+        } else {
+            sum
+        }
+    }
+
+    /// (self - rhs) % modulus for any operands
+    pub fn mod_sub(self, rhs: U256, modulus: U256) -> U256 {
+        let (a, b) = (self % modulus, rhs % modulus);
+        if a >= b {
+            a - b
+        } else {
+            modulus - (b - a)
+        }
     }
 
     pub fn from_hex(hex: &[u8]) -> U256 {
@@ -150,3 +184,34 @@ pub fn sha256_to_u256(str: &[u8]) -> U256 {
 
     U256::from_little_endian(&e[0..32])
 }
+
+mod test {
+    use super::{U256, U512};
+    use num_bigint::BigUint;
+
+    #[test]
+    fn test_overflow_safe_arithmetic() {
+        let max = U256::max_value();
+        assert_eq!(max.checked_add(U256::one()), None);
+        assert_eq!(max.overflowing_add(U256::one()), (U256::zero(), true));
+        assert_eq!(U256::zero().checked_sub(U256::one()), None);
+        assert_eq!(max.checked_mul(U256::from(2u32)), None);
+
+        let wide = max.widening_mul(max);
+        let expected: BigUint = max.to_big_uint() * max.to_big_uint();
+        assert_eq!(Into::<BigUint>::into(wide), expected);
+        assert_eq!(
+            U256::from(3u32).widening_mul(U256::from(5u32)),
+            U512::from(15u32)
+        );
+
+        // operands near 2^256 keep their carry
+        let modulus = max - U256::from(10u32);
+        assert_eq!(max.mod_add(max, modulus), U256::from(20u32));
+        assert_eq!(max.modmul(max, modulus), U256::from(100u32));
+        assert_eq!(
+            U256::one().mod_sub(U256::from(3u32), modulus),
+            modulus - U256::from(2u32)
+        );
+    }
+}
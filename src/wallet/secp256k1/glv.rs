@@ -0,0 +1,157 @@
+use num_bigint::{BigInt, Sign};
+use num_integer::Integer;
+
+use super::ec::utils::U256;
+use super::s256_field::S256Field;
+use super::s256_point::{S256Point, Secp256K1EllipticCurve};
+
+/// Cube root of unity mod n, lambda * (x, y) = (beta * x, y)
+const LAMBDA: &[u8] = b"5363ad4cc05c30e0a5261c028812645a122e22ea20816678df02967c1b23bd72";
+/// Cube root of unity mod p matching `LAMBDA`
+const BETA: &[u8] = b"7ae96a2b657c07106e64479eac3434e99cf0497512f58995c1396c28719501ee";
+/// Short basis of the lattice of (a, b) with a + b * lambda = 0 mod n, b1 is negative
+const A1: &[u8] = b"3086d221a7d46bcde86c90e49284eb15";
+const MINUS_B1: &[u8] = b"e4437ed6010e88286f547fa90abfe4c3";
+const A2: &[u8] = b"114ca50f7a8e2f3f657c1108d9d44cfd8";
+
+fn big(hex: &[u8]) -> BigInt {
+    BigInt::parse_bytes(hex, 16).expect("literal number convert to BigInt failed")
+}
+
+fn to_big_int(v: U256) -> BigInt {
+    BigInt::from_biguint(Sign::Plus, v.to_big_uint())
+}
+
+/// Sign and magnitude of a split scalar half
+fn to_signed(v: BigInt) -> (bool, U256) {
+    let negative = v.sign() == Sign::Minus;
+    let magnitude = if negative { -v } else { v };
+    (
+        negative,
+        magnitude
+            .to_biguint()
+            .expect("magnitude is not negative")
+            .into(),
+    )
+}
+
+pub(crate) fn lambda() -> U256 {
+    U256::from_hex(LAMBDA)
+}
+
+/// (beta * x, y), the same point as lambda * `point` for one field multiplication
+pub(crate) fn endomorphism(point: &S256Point) -> S256Point {
+    match point.coordinate() {
+        Some((x, y)) => S256Point::new(S256Field::new(x) * U256::from_hex(BETA), S256Field::new(y))
+            .expect("endomorphism keeps points on the curve"),
+        None => S256Point::inf(),
+    }
+}
+
+/// k = k1 + k2 * lambda mod n with k1 and k2 of about 128 bits, each as (negative, magnitude)
+pub(crate) fn split_scalar(k: U256) -> ((bool, U256), (bool, U256)) {
+    let n = to_big_int(Secp256K1EllipticCurve::n());
+    let k = to_big_int(k) % &n;
+    let (a1, minus_b1, a2) = (big(A1), big(MINUS_B1), big(A2));
+    let b2 = a1.clone();
+    let half_n = &n / BigInt::from(2);
+
+    // c1 = round(b2 * k / n), c2 = round(-b1 * k / n)
+    let c1 = (&b2 * &k + &half_n).div_floor(&n);
+    let c2 = (&minus_b1 * &k + &half_n).div_floor(&n);
+    let k1 = &k - &c1 * &a1 - &c2 * &a2;
+    let k2 = &c1 * &minus_b1 - &c2 * &b2;
+    (to_signed(k1), to_signed(k2))
+}
+
+/// Sum of `scalar * point` over `terms`, sharing one chain of doublings between all of
+/// them (Straus) after splitting each scalar in two half length ones
+pub(crate) fn multi_mul(terms: &[(S256Point, U256)]) -> S256Point {
+    let mut points = Vec::with_capacity(terms.len() * 2);
+    let mut scalars = Vec::with_capacity(terms.len() * 2);
+    for (point, scalar) in terms {
+        let ((k1_neg, k1), (k2_neg, k2)) = split_scalar(*scalar);
+        let phi = endomorphism(point);
+        points.push(if k1_neg { -*point } else { *point });
+        points.push(if k2_neg { -phi } else { phi });
+        scalars.push(k1);
+        scalars.push(k2);
+    }
+
+    // table[mask] is the sum of the points whose bit is set in mask
+    let mut table = vec![S256Point::inf(); 1 << points.len()];
+    for mask in 1..table.len() {
+        let low = mask.trailing_zeros() as usize;
+        table[mask] = table[mask & (mask - 1)] + points[low];
+    }
+
+    let bits = scalars.iter().map(|s| s.bits()).max().unwrap_or(0);
+    let mut result = S256Point::inf();
+    for bit in (0..bits).rev() {
+        result = result + result;
+        let mask = scalars
+            .iter()
+            .enumerate()
+            .filter(|(_, scalar)| scalar.bit(bit))
+            .fold(0, |mask, (i, _)| mask | 1 << i);
+        if mask != 0 {
+            result = result + table[mask];
+        }
+    }
+    result
+}
+
+mod test {
+    use super::super::ec::utils::U256;
+    use super::super::s256_point::{S256Point, Secp256K1EllipticCurve};
+    use super::{endomorphism, lambda, multi_mul, split_scalar};
+
+    #[test]
+    fn test_endomorphism() {
+        let g = S256Point::gen_point();
+        assert_eq!(endomorphism(&g), g * lambda());
+    }
+
+    #[test]
+    fn test_split_scalar() {
+        let n = Secp256K1EllipticCurve::n().to_big_uint();
+        let lambda = lambda().to_big_uint();
+        let scalars = (0..64)
+            .map(|_| U256::from_random() % Secp256K1EllipticCurve::n())
+            .chain(vec![
+                U256::zero(),
+                U256::one(),
+                Secp256K1EllipticCurve::n() - U256::one(),
+            ]);
+        for k in scalars {
+            let ((k1_neg, k1), (k2_neg, k2)) = split_scalar(k);
+            assert!(k1.bits() <= 129 && k2.bits() <= 129, "{} splits long", k);
+            // move both halves to [0, n) and check k1 + k2 * lambda = k
+            let signed = |neg: bool, v: U256| {
+                if neg {
+                    (&n - v.to_big_uint() % &n) % &n
+                } else {
+                    v.to_big_uint()
+                }
+            };
+            let sum = (signed(k1_neg, k1) + signed(k2_neg, k2) * &lambda) % &n;
+            assert_eq!(sum, k.to_big_uint());
+        }
+    }
+
+    #[test]
+    fn test_multi_mul() {
+        let g = S256Point::gen_point();
+        for _ in 0..4 {
+            let (u, v) = (U256::from_random(), U256::from_random());
+            let p = g * U256::from_random();
+            assert_eq!(multi_mul(&[(p, v)]), p * v);
+            assert_eq!(multi_mul(&[(g, u), (p, v)]), g * u + p * v);
+        }
+        assert!(multi_mul(&[(g, U256::zero())]).is_inf());
+        assert_eq!(
+            multi_mul(&[(g, Secp256K1EllipticCurve::n())]),
+            S256Point::inf()
+        );
+    }
+}
@@ -1,5 +1,10 @@
 pub mod ec;
+#[cfg(feature = "glv")]
+mod glv;
 pub mod s256_field;
 pub mod s256_point;
+pub mod s256_scalar;
+pub mod schnorr;
 pub mod signature;
 pub mod utils;
+pub mod x_only_key;
@@ -4,7 +4,7 @@ use std::fmt::{self, Display};
 use std::ops::{Add, Div, Mul, Sub};
 
 use super::ec::field_element::FieldElementError;
-use super::ec::utils::{U256, U512};
+use super::ec::utils::U256;
 
 /// Secp256k1 Finite field element
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -42,19 +42,129 @@ impl S256Field {
     }
 
     pub fn prime() -> U256 {
-        let p = U512::from(2u32).pow(U512::from(256u32))
-            - U512::from(2u32).pow(U512::from(32u32))
-            - U512::from(977u32);
-        p.into()
+        // 2^256 - 2^32 - 977
+        U256([
+            0xffff_fffe_ffff_fc2f,
+            u64::max_value(),
+            u64::max_value(),
+            u64::max_value(),
+        ])
     }
 
-    pub fn sqrt(&self) -> Self {
+    /// Square root, None when `self` is not a quadratic residue
+    pub fn sqrt(&self) -> Option<Self> {
         let prime = Into::<BigUint>::into(self.prime);
         let power = (prime.clone() + BigUint::from(1u8)) / BigUint::from(4u8);
         let new_num = Into::<BigUint>::into(self.num).modpow(&power, &prime);
-        S256Field {
+        let root = S256Field {
             num: new_num.into(),
             prime: self.prime,
+        };
+        // p = 3 mod 4 gives a root of self or of -self, only the first squares back
+        if root.mul_native(root) == *self {
+            Some(root)
+        } else {
+            None
+        }
+    }
+}
+
+/// 2^256 mod p, p = 2^256 - 2^32 - 977
+const P_COMPLEMENT: u128 = 0x1_0000_03d1;
+
+/// `wide` mod p, folding the high half down with 2^256 = P_COMPLEMENT (mod p)
+fn reduce_wide(wide: [u64; 8]) -> U256 {
+    let mut folded = [0u64; 5];
+    let mut carry = 0u128;
+    for i in 0..4 {
+        let t = u128::from(wide[i]) + u128::from(wide[i + 4]) * P_COMPLEMENT + carry;
+        folded[i] = t as u64;
+        carry = t >> 64;
+    }
+    folded[4] = carry as u64;
+
+    let mut limbs = [0u64; 4];
+    let mut carry = u128::from(folded[4]) * P_COMPLEMENT;
+    for i in 0..4 {
+        let t = u128::from(folded[i]) + carry;
+        limbs[i] = t as u64;
+        carry = t >> 64;
+    }
+    let mut num = U256(limbs);
+    if carry != 0 {
+        // the lost 2^256 is worth P_COMPLEMENT, num is tiny here so this can not overflow
+        num = num + U256::from(P_COMPLEMENT as u64);
+    }
+    let prime = S256Field::prime();
+    if num >= prime {
+        num = num - prime;
+    }
+    num
+}
+
+impl S256Field {
+    /// Product on the native limbs, without going through BigUint
+    fn mul_native(self, rhs: S256Field) -> S256Field {
+        S256Field {
+            num: reduce_wide(self.num.widening_mul(rhs.num).0),
+            prime: self.prime,
+        }
+    }
+
+    fn square_times(self, times: usize) -> S256Field {
+        (0..times).fold(self, |x, _| x.mul_native(x))
+    }
+
+    /// Multiplicative inverse, self^(p-2) through the secp256k1 addition chain of 255
+    /// squarings and 15 multiplications. Zero has no inverse and gives zero.
+    pub fn invert(&self) -> S256Field {
+        let x = *self;
+        // xN = x^(2^N - 1)
+        let x2 = x.square_times(1).mul_native(x);
+        let x3 = x2.square_times(1).mul_native(x);
+        let x6 = x3.square_times(3).mul_native(x3);
+        let x9 = x6.square_times(3).mul_native(x3);
+        let x11 = x9.square_times(2).mul_native(x2);
+        let x22 = x11.square_times(11).mul_native(x11);
+        let x44 = x22.square_times(22).mul_native(x22);
+        let x88 = x44.square_times(44).mul_native(x44);
+        let x176 = x88.square_times(88).mul_native(x88);
+        let x220 = x176.square_times(44).mul_native(x44);
+        let x223 = x220.square_times(3).mul_native(x3);
+
+        // p - 2 is 223 ones, a zero, 22 ones, then 0000101101
+        x223.square_times(23)
+            .mul_native(x22)
+            .square_times(5)
+            .mul_native(x)
+            .square_times(3)
+            .mul_native(x2)
+            .square_times(2)
+            .mul_native(x)
+    }
+
+    /// Invert every element of `elements` in place with a single field inversion
+    /// (Montgomery's trick), zeros are left as they are
+    pub fn batch_invert(elements: &mut [S256Field]) {
+        let one = S256Field::new(1u32);
+        // prefix[i] is the product of the non zero elements before i
+        let mut prefix = Vec::with_capacity(elements.len());
+        let mut acc = one;
+        for element in elements.iter() {
+            prefix.push(acc);
+            if !element.num.is_zero() {
+                acc = acc.mul_native(*element);
+            }
+        }
+
+        let mut inv = acc.invert();
+        for (element, prefix) in elements.iter_mut().zip(prefix).rev() {
+            if element.num.is_zero() {
+                continue;
+            }
+            let next = inv.mul_native(*element);
+            *element = inv.mul_native(prefix);
+            inv = next;
         }
     }
 }
@@ -210,12 +320,7 @@ impl Div<Self> for S256Field {
     type Output = Self;
 
     fn div(self, rhs: Self) -> Self::Output {
-        let t = Into::<BigUint>::into(self.prime - 2);
-        let num: BigUint = (Into::<BigUint>::into(self.num)
-            * Into::<BigUint>::into(rhs.num).modpow(&t, &Into::<BigUint>::into(self.prime)))
-            % Into::<BigUint>::into(self.prime);
-
-        S256Field::new(num)
+        self.mul_native(rhs.invert())
     }
 }
 
@@ -223,12 +328,7 @@ impl Div<U256> for S256Field {
     type Output = Self;
 
     fn div(self, rhs: U256) -> Self::Output {
-        let t: BigUint = (self.prime - 2).into();
-        let num: BigUint = (Into::<BigUint>::into(self.num)
-            * Into::<BigUint>::into(rhs).modpow(&t, &Into::<BigUint>::into(self.prime)))
-            % Into::<BigUint>::into(self.prime);
-
-        S256Field::new(num)
+        self / S256Field::new(rhs % Self::prime())
     }
 }
 
@@ -237,3 +337,64 @@ impl Display for S256Field {
         write!(f, "{}", self.num)
     }
 }
+
+mod test {
+    use super::super::ec::utils::U256;
+    use super::S256Field;
+    use num_bigint::BigUint;
+
+    #[test]
+    fn test_invert() {
+        let prime = S256Field::prime();
+        let values = (0..32).map(|_| U256::from_random() % prime).chain(vec![
+            U256::one(),
+            U256::from(2u32),
+            prime - U256::one(),
+        ]);
+        for v in values {
+            let x = S256Field::new(v);
+            let expected: BigUint = v.to_big_uint().modpow(
+                &(prime - U256::from(2u32)).to_big_uint(),
+                &prime.to_big_uint(),
+            );
+            assert_eq!(x.invert().num.to_big_uint(), expected);
+            assert_eq!(x * x.invert(), S256Field::new(1u32));
+            assert_eq!(x.mul_native(x), x * x);
+        }
+        assert_eq!(S256Field::new(0u32).invert(), S256Field::new(0u32));
+        assert_eq!(
+            prime.to_big_uint(),
+            (BigUint::from(1u32) << 256) - (BigUint::from(1u32) << 32) - BigUint::from(977u32)
+        );
+        assert_eq!(
+            S256Field::new(6u32) / S256Field::new(3u32),
+            S256Field::new(2u32)
+        );
+    }
+
+    #[test]
+    fn test_sqrt() {
+        let x = S256Field::new(U256::from_random() % S256Field::prime());
+        let square = x * x;
+        let root = square.sqrt().unwrap();
+        assert!(root == x || root == S256Field::new(0u32) - x);
+        // -1 is not a square mod p = 3 mod 4
+        assert_eq!((S256Field::new(0u32) - square).sqrt(), None);
+        assert_eq!(S256Field::new(0u32).sqrt(), Some(S256Field::new(0u32)));
+    }
+
+    #[test]
+    fn test_batch_invert() {
+        let prime = S256Field::prime();
+        let mut elements: Vec<S256Field> = (0..9)
+            .map(|_| S256Field::new(U256::from_random() % prime))
+            .collect();
+        elements[4] = S256Field::new(0u32);
+        let expected: Vec<S256Field> = elements.iter().map(S256Field::invert).collect();
+        S256Field::batch_invert(&mut elements);
+        assert_eq!(elements, expected);
+
+        let mut empty: Vec<S256Field> = vec![];
+        S256Field::batch_invert(&mut empty);
+    }
+}
@@ -1,14 +1,17 @@
 use super::s256_field::S256Field;
+use super::s256_scalar::{S256Scalar, TweakError, ORDER};
 
 use super::ec::point::PointError;
 
+use super::ec::hex::{Parse, Serialize};
 use super::ec::utils::U256;
 use super::signature::Signature;
 use super::utils::{encode_base58_checksum, hash160};
+use crate::transaction::ScriptPubKey;
 use crate::wallet::secp256k1::utils::Hash160;
 use crate::wallet::Hash256;
 use std::fmt;
-use std::ops::{Add, Mul};
+use std::ops::{Add, Mul, Neg, Sub};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum PointValue {
@@ -53,7 +56,7 @@ impl Secp256K1EllipticCurve {
 
     /// Secp256K1 elliptic curve group order, nG=0
     pub fn n() -> U256 {
-        U256::from_hex(b"fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141")
+        ORDER
     }
 }
 
@@ -132,16 +135,49 @@ impl S256Point {
     }
 
     pub fn verify(&self, z: Hash256, sig: Signature) -> bool {
-        let z = U256::from_little_endian(&z);
-        let n = Secp256K1EllipticCurve::n();
-        let s_inv = sig.s.modpow(n - U256::from(2u32), n);
+        let (r, s) = match sig.scalars() {
+            Some(scalars) => scalars,
+            None => return false,
+        };
+        let z = S256Scalar::new(U256::from_little_endian(&z));
+        let s_inv = s.invert();
 
-        let u = z.modmul(s_inv, n);
-        let v = sig.r.modmul(s_inv, n);
+        let u = z * s_inv;
+        let v = r * s_inv;
 
         let g = S256Point::gen_point();
+        #[cfg(feature = "glv")]
+        let t = super::glv::multi_mul(&[(g, u.num()), (*self, v.num())]);
+        #[cfg(not(feature = "glv"))]
         let t = g * u + *self * v;
-        sig.r == t.coordinate().unwrap().0
+        // the x coordinate is mod p, r is mod n
+        match t.coordinate() {
+            Some((x, _)) => S256Scalar::new(x) == r,
+            None => false,
+        }
+    }
+
+    /// self + t*G, the public side of `PrivateKey::add_tweak`
+    pub fn add_tweak(&self, tweak: &[u8; 32]) -> Result<Self, TweakError> {
+        let tweak = S256Scalar::from_tweak(tweak)?;
+        let point = *self + S256Point::gen_point() * tweak;
+        if point.is_inf() {
+            return Err(TweakError::InvalidResult);
+        }
+        Ok(point)
+    }
+
+    /// t*self, the public side of `PrivateKey::mul_tweak`
+    pub fn mul_tweak(&self, tweak: &[u8; 32]) -> Result<Self, TweakError> {
+        let tweak = S256Scalar::from_tweak(tweak)?;
+        if tweak.is_zero() {
+            return Err(TweakError::ZeroTweak);
+        }
+        let point = *self * tweak;
+        if point.is_inf() {
+            return Err(TweakError::InvalidResult);
+        }
+        Ok(point)
     }
 
     pub fn sec(&self) -> [u8; 65] {
@@ -188,39 +224,72 @@ impl S256Point {
         bytes
     }
 
-    pub fn parse_sec(sec_bytes: &[u8]) -> Self {
-        assert!(sec_bytes.len() >= 33);
-        if sec_bytes[0] == 4 {
-            let x = U256::from_big_endian(&sec_bytes[1..33]);
-            let y = U256::from_big_endian(&sec_bytes[33..65]);
-            let x = S256Field::new(x);
-            let y = S256Field::new(y);
-            return S256Point::new(x, y)
-                .expect("can not parse uncompressed sec format bytes to S256Point");
-        }
+    /// Compressed or uncompressed sec, coordinates must be below p and on the curve
+    pub fn parse_sec(sec_bytes: &[u8]) -> Result<Self, PointError> {
+        let prime = S256Field::prime();
+        let coordinate = |bytes: &[u8]| {
+            let v = U256::from_big_endian(bytes);
+            if v >= prime {
+                Err(PointError::NotInEllipticCurves)
+            } else {
+                Ok(S256Field::new(v))
+            }
+        };
+        let is_even = match (sec_bytes.len(), sec_bytes.first()) {
+            (65, Some(4)) => {
+                let x = coordinate(&sec_bytes[1..33])?;
+                let y = coordinate(&sec_bytes[33..65])?;
+                return S256Point::new(x, y);
+            }
+            (33, Some(2)) => true,
+            (33, Some(3)) => false,
+            _ => return Err(PointError::InvalidSecEncoding),
+        };
 
-        let is_even = if sec_bytes[0] == 2 { true } else { false };
-        let x = S256Field::new(U256::from_big_endian(&sec_bytes[1..33]));
+        let x = coordinate(&sec_bytes[1..33])?;
         // y^2 = x^3 + 7
         let alpha = x.pow(3) + Secp256K1EllipticCurve::ec_b();
-        let beta = alpha.sqrt();
+        let beta = alpha.sqrt().ok_or(PointError::NotInEllipticCurves)?;
 
-        let prime = S256Field::prime();
         let (even_beta, odd_beta) = if beta.num.is_even() {
-            (beta, S256Field::new(prime - beta.num))
+            (beta, S256Field::new(0u32) - beta)
         } else {
-            (S256Field::new(prime - beta.num), beta)
+            (S256Field::new(0u32) - beta, beta)
         };
 
         if is_even {
             S256Point::new(x, even_beta)
-                .expect("can not parse compressed sec format bytes to S256Point")
         } else {
             S256Point::new(x, odd_beta)
-                .expect("can not parse compressed sec format bytes to S256Point")
         }
     }
 
+    /// The point with x coordinate `x` and an odd y when `odd_y`, None if `x` is not on the
+    /// curve. BIP340 keys lift with an even y.
+    pub fn lift_x(x: &[u8; 32], odd_y: bool) -> Option<Self> {
+        let x = U256::from_big_endian(x);
+        let prime = S256Field::prime();
+        if x >= prime {
+            return None;
+        }
+        let x = S256Field::new(x);
+        // y^2 = x^3 + 7
+        let beta = (x.pow(3) + Secp256K1EllipticCurve::ec_b()).sqrt()?;
+        let y = if beta.num.is_even() != odd_y {
+            beta
+        } else {
+            S256Field::new(prime - beta.num)
+        };
+        S256Point::new(x, y).ok()
+    }
+
+    /// 32 bytes x coordinate, BIP340 keys implicitly have an even y
+    pub(crate) fn x_only(&self) -> [u8; 32] {
+        let mut buf = [0u8; 32];
+        buf.copy_from_slice(&self.compressed_sec()[1..33]);
+        buf
+    }
+
     pub fn hash160(&self, compressed: bool) -> Hash160 {
         if compressed {
             hash160(&self.compressed_sec())
@@ -239,6 +308,32 @@ impl S256Point {
 
         encode_base58_checksum(&[&prefix[..], &h160[..]].concat())
     }
+
+    /// OP_DUP OP_HASH160 <hash160 of the compressed key> OP_EQUALVERIFY OP_CHECKSIG
+    pub fn p2pkh_script(&self) -> ScriptPubKey {
+        let mut content = Vec::with_capacity(25);
+        content.extend_from_slice(&[0x76, 0xa9, 0x14]);
+        content.extend_from_slice(&self.hash160(true));
+        content.extend_from_slice(&[0x88, 0xac]);
+        ScriptPubKey { content }
+    }
+
+    /// OP_0 <hash160 of the compressed key>, also the P2SH-P2WPKH redeem script
+    pub fn p2wpkh_script(&self) -> ScriptPubKey {
+        let mut content = Vec::with_capacity(22);
+        content.extend_from_slice(&[0x00, 0x14]);
+        content.extend_from_slice(&self.hash160(true));
+        ScriptPubKey { content }
+    }
+
+    /// OP_HASH160 <hash160 of the P2WPKH redeem script> OP_EQUAL
+    pub fn p2sh_p2wpkh_script(&self) -> ScriptPubKey {
+        let mut content = Vec::with_capacity(23);
+        content.extend_from_slice(&[0xa9, 0x14]);
+        content.extend_from_slice(&hash160(&self.p2wpkh_script().content));
+        content.push(0x87);
+        ScriptPubKey { content }
+    }
 }
 
 impl Add<S256Point> for S256Point {
@@ -280,33 +375,176 @@ impl Add<S256Point> for S256Point {
     }
 }
 
+/// (X, Y, Z) standing for the affine point (X / Z^2, Y / Z^3), additions need no inversion
+#[derive(Clone, Debug)]
+struct JacobianPoint {
+    x: S256Field,
+    y: S256Field,
+    z: S256Field,
+}
+impl Copy for JacobianPoint {}
+
+impl JacobianPoint {
+    fn from_affine(x: S256Field, y: S256Field) -> Self {
+        JacobianPoint {
+            x,
+            y,
+            z: S256Field::new(1u32),
+        }
+    }
+
+    /// self + (x, y) for a distinct affine point that is not -self
+    fn add_affine(&self, x: S256Field, y: S256Field) -> Self {
+        let z1z1 = self.z * self.z;
+        let h = x * z1z1 - self.x;
+        let r = y * self.z * z1z1 - self.y;
+        let hh = h * h;
+        let hhh = h * hh;
+        let v = self.x * hh;
+        let x3 = r * r - hhh - v - v;
+        JacobianPoint {
+            x: x3,
+            y: r * (v - x3) - self.y * hhh,
+            z: self.z * h,
+        }
+    }
+
+    /// Affine points of `points` sharing one field inversion for all their Z
+    fn batch_to_affine(points: &[JacobianPoint]) -> Vec<S256Point> {
+        let mut z_inv: Vec<S256Field> = points.iter().map(|point| point.z).collect();
+        S256Field::batch_invert(&mut z_inv);
+        points
+            .iter()
+            .zip(z_inv)
+            .map(|(point, z_inv)| {
+                let z_inv2 = z_inv * z_inv;
+                S256Point {
+                    point: PointValue::NormalPoint {
+                        x: point.x * z_inv2,
+                        y: point.y * z_inv2 * z_inv,
+                    },
+                    elliptic_curve: Secp256K1EllipticCurve::default(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Window width of the wNAF recoding, odd multiples up to 15P are precomputed
+const WNAF_WINDOW: u32 = 5;
+
+/// Width-w non-adjacent form of `k`, least significant digit first. Digits are zero or odd
+/// in (-2^(w-1), 2^(w-1)) and any w consecutive ones hold at most one non zero.
+fn wnaf(k: U256, w: u32) -> Vec<i8> {
+    let window = U256::from(1u32 << w);
+    let half = 1i32 << (w - 1);
+    let mut k = k;
+    let mut digits = Vec::with_capacity(k.bits() + 1);
+    while !k.is_zero() {
+        if k.bit(0) {
+            let mut digit = (k % window).low_u32() as i32;
+            if digit >= half {
+                digit -= 1 << w;
+            }
+            if digit < 0 {
+                k = k + U256::from(-digit as u32);
+            } else {
+                k = k - U256::from(digit as u32);
+            }
+            digits.push(digit as i8);
+        } else {
+            digits.push(0);
+        }
+        k = k >> 1;
+    }
+    digits
+}
+
+impl Neg for S256Point {
+    type Output = Self;
+
+    /// The point with y mirrored, P + (-P) is infinity
+    fn neg(self) -> Self::Output {
+        match self.point {
+            PointValue::NormalPoint { x, y } => S256Point {
+                point: PointValue::NormalPoint {
+                    x,
+                    y: S256Field::new(U256::zero()) - y,
+                },
+                elliptic_curve: self.elliptic_curve,
+            },
+            PointValue::InfPoint => self,
+        }
+    }
+}
+
+impl Sub<S256Point> for S256Point {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self + -rhs
+    }
+}
+
 impl<T> Mul<T> for S256Point
 where
     T: Into<U256>,
 {
     type Output = Self;
     fn mul(self, rhs: T) -> Self::Output {
-        let mut coef = rhs.into() % Secp256K1EllipticCurve::n();
-        let mut current = self;
+        let coef = rhs.into() % Secp256K1EllipticCurve::n();
+        let (point, double) = match (self.point, (self + self).point) {
+            (PointValue::NormalPoint { x, y }, PointValue::NormalPoint { x: dx, y: dy }) => {
+                ((x, y), (dx, dy))
+            }
+            // infinity, or a point of order two which secp256k1 does not have
+            _ => return S256Point::inf(),
+        };
+
+        // P, 3P, 5P, ... built in Jacobian coordinates and normalized together
+        let mut odd_multiples = vec![JacobianPoint::from_affine(point.0, point.1)];
+        for i in 1..1 << (WNAF_WINDOW - 2) {
+            let next = odd_multiples[i - 1].add_affine(double.0, double.1);
+            odd_multiples.push(next);
+        }
+        let odd_multiples = JacobianPoint::batch_to_affine(&odd_multiples);
 
         let mut result = S256Point::inf();
-        while coef > U256::from(0) {
-            if coef & U256::from(1u32) == U256::from(1u32) {
-                result = result + current;
+        for digit in wnaf(coef, WNAF_WINDOW).into_iter().rev() {
+            result = result + result;
+            if digit > 0 {
+                result = result + odd_multiples[(digit as usize - 1) / 2];
+            } else if digit < 0 {
+                result = result + -odd_multiples[(-digit as usize - 1) / 2];
             }
-            current = current + current;
-            coef = coef >> 1;
         }
         result
     }
 }
 
+impl Serialize for S256Point {
+    /// Compressed sec
+    fn serialize_bytes(&self) -> Vec<u8> {
+        self.compressed_sec().to_vec()
+    }
+}
+
+impl Parse for S256Point {
+    /// Compressed or uncompressed sec, None if the point is not on the curve
+    fn parse_bytes(bytes: &[u8]) -> Option<Self> {
+        S256Point::parse_sec(bytes).ok()
+    }
+}
+
 mod test {
+    use super::super::ec::point::PointError;
     use super::super::ec::utils::sha256_to_u256;
     use super::super::ec::utils::U256;
-    use super::super::s256_point::{S256Point, Secp256K1EllipticCurve};
+    use super::super::s256_field::S256Field;
+    use super::super::s256_point::{wnaf, S256Point, Secp256K1EllipticCurve};
     use super::super::signature::Signature;
-    use crate::wallet::Hash256;
+    use crate::transaction::ScriptPubKeyType;
+    use crate::wallet::{hash160, Hash256};
     use num_bigint::BigUint;
 
     #[test]
@@ -317,6 +555,89 @@ mod test {
         assert_eq!(S256Point::inf(), gen_point * n)
     }
 
+    #[test]
+    fn test_wnaf_mul() {
+        let n = Secp256K1EllipticCurve::n();
+        let gen_point = S256Point::gen_point();
+        // plain double and add
+        let reference = |k: U256| {
+            let mut result = S256Point::inf();
+            for bit in (0..k.bits()).rev() {
+                result = result + result;
+                if k.bit(bit) {
+                    result = result + gen_point;
+                }
+            }
+            result
+        };
+
+        let scalars = (0..8).map(|_| U256::from_random() % n).chain(vec![
+            U256::one(),
+            U256::from(15u32),
+            n - U256::one(),
+        ]);
+        for k in scalars {
+            let digits = wnaf(k, 5);
+            assert!(digits
+                .windows(5)
+                .all(|window| window.iter().filter(|d| **d != 0).count() <= 1));
+            let value = digits
+                .iter()
+                .rev()
+                .fold(num_bigint::BigInt::from(0), |acc, d| {
+                    acc * 2 + num_bigint::BigInt::from(*d)
+                });
+            assert_eq!(value.to_biguint(), Some(k.to_big_uint()));
+            assert_eq!(gen_point * k, reference(k));
+        }
+        assert!((gen_point * U256::zero()).is_inf());
+        assert_eq!(-gen_point + gen_point, S256Point::inf());
+    }
+
+    #[test]
+    fn test_standard_scripts() {
+        // the key of the BIP143 native P2WPKH example
+        let point = S256Point::parse_sec(&hex!(
+            "025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357"
+        ))
+        .unwrap();
+        assert_eq!(
+            point.p2pkh_script().content,
+            hex!("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").to_vec()
+        );
+        assert_eq!(
+            point.p2wpkh_script().content,
+            hex!("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1").to_vec()
+        );
+        let p2sh = point.p2sh_p2wpkh_script();
+        assert_eq!(p2sh.script_type(), ScriptPubKeyType::ScriptHash);
+        assert_eq!(
+            p2sh.content[2..22],
+            hash160(&point.p2wpkh_script().content)[..]
+        );
+    }
+
+    #[test]
+    fn test_neg_sub_lift_x() {
+        let g = S256Point::gen_point();
+        let inf = S256Point::inf();
+        assert_eq!(g * 5u8 - g * 3u8, g * 2u8);
+        assert_eq!(g - g, inf);
+        assert_eq!(-inf, inf);
+        assert_eq!(inf - g, -g);
+
+        let p = g * 7u8;
+        let (_, y) = p.coordinate().unwrap();
+        let x = p.x_only();
+        let (even, odd) = if y.is_even() { (p, -p) } else { (-p, p) };
+        assert_eq!(S256Point::lift_x(&x, false), Some(even));
+        assert_eq!(S256Point::lift_x(&x, true), Some(odd));
+        // x = 5 is not on the curve
+        let mut x = [0u8; 32];
+        x[31] = 5;
+        assert_eq!(S256Point::lift_x(&x, false), None);
+    }
+
     #[test]
     fn test_verify_sig() {
         let z = U256::from_hex(b"bc62d4b80d9e36da29c16c5d4d9f11731f36052c72401a76c23c0fb5a9b74423");
@@ -329,7 +650,13 @@ mod test {
         let py =
             U256::from_hex(b"82b51eab8c27c66e26c858a079bcdf4f1ada34cec420cafc7eac1a42216fb6c4");
         let point = S256Point::new(px.into(), py.into()).unwrap();
-        assert!(point.verify(Hash256::from(z), sig))
+        assert!(point.verify(Hash256::from(z), sig));
+
+        // r and s have to be in [1, n)
+        let n = Secp256K1EllipticCurve::n();
+        assert!(!point.verify(Hash256::from(z), Signature::new(r, n)));
+        assert!(!point.verify(Hash256::from(z), Signature::new(r, U256::zero())));
+        assert!(!point.verify(Hash256::from(z), Signature::new(U256::zero(), s)));
     }
 
     #[test]
@@ -343,13 +670,8 @@ mod test {
         let n = Secp256K1EllipticCurve::n();
         let k_inv = k.modpow(n - U256::from(2), n);
 
-        // U256 mul will overflow
-        // let s = u256_modmul(z + r * e, k_inv, n);
-        let s: BigUint = (Into::<BigUint>::into(z)
-            + Into::<BigUint>::into(r) * Into::<BigUint>::into(e))
-            * Into::<BigUint>::into(k_inv);
-        let s = s % Into::<BigUint>::into(n);
-        let s: U256 = s.into();
+        // z + r * e overflows 256 bits, stay mod n all along
+        let s = z.mod_add(r.modmul(e, n), n).modmul(k_inv, n);
 
         let point = S256Point::gen_point() * e;
         assert_eq!(
@@ -385,7 +707,7 @@ mod test {
         let point = S256Point::gen_point();
         let uncompressed_sec = point.sec();
 
-        let parsed_point = S256Point::parse_sec(&uncompressed_sec);
+        let parsed_point = S256Point::parse_sec(&uncompressed_sec).unwrap();
         assert_eq!(point, parsed_point);
     }
 
@@ -394,7 +716,29 @@ mod test {
         let point = S256Point::gen_point();
         let compressed_sec = point.compressed_sec();
 
-        let parsed_point = S256Point::parse_sec(&compressed_sec);
+        let parsed_point = S256Point::parse_sec(&compressed_sec).unwrap();
         assert_eq!(point, parsed_point);
     }
+
+    #[test]
+    fn test_parse_sec_off_curve() {
+        // the first x whose x^3 + 7 has no square root
+        let x = (1u32..)
+            .find(|x| {
+                let x = S256Field::new(*x);
+                (x.pow(3) + Secp256K1EllipticCurve::ec_b()).sqrt().is_none()
+            })
+            .unwrap();
+        let mut sec = [0u8; 33];
+        sec[0] = 0x02;
+        sec[29..].copy_from_slice(&x.to_be_bytes());
+        assert_eq!(
+            S256Point::parse_sec(&sec),
+            Err(PointError::NotInEllipticCurves)
+        );
+        assert_eq!(
+            S256Point::parse_sec(&sec[..32]),
+            Err(PointError::InvalidSecEncoding)
+        );
+    }
 }
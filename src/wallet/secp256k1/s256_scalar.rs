@@ -0,0 +1,187 @@
+use std::fmt::{self, Display};
+use std::ops::{Add, Mul, Neg, Sub};
+
+use super::ec::utils::U256;
+
+/// Secp256k1 group order n, nG = 0
+pub(crate) const ORDER: U256 = U256([
+    0xbfd2_5e8c_d036_4141,
+    0xbaae_dce6_af48_a03b,
+    0xffff_ffff_ffff_fffe,
+    0xffff_ffff_ffff_ffff,
+]);
+
+/// Why a key tweak was refused
+#[derive(Fail, Debug, PartialEq, Eq, Clone)]
+pub enum TweakError {
+    #[fail(display = "tweak is not below the curve order")]
+    OutOfRange,
+    #[fail(display = "multiplying by a zero tweak")]
+    ZeroTweak,
+    #[fail(display = "tweaked key is zero or the point at infinity")]
+    InvalidResult,
+}
+impl Copy for TweakError {}
+
+/// Secp256k1 scalar, an integer mod the group order n, for private keys, nonces, signature
+/// values and hashes being signed. Field elements mod p are `S256Field`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct S256Scalar(U256);
+
+impl Copy for S256Scalar {}
+
+impl S256Scalar {
+    /// `num` mod n
+    pub fn new<T: Into<U256>>(num: T) -> Self {
+        S256Scalar(num.into() % ORDER)
+    }
+
+    pub fn zero() -> Self {
+        S256Scalar(U256::zero())
+    }
+
+    pub fn one() -> Self {
+        S256Scalar(U256::one())
+    }
+
+    /// 32 bytes big endian, None unless the number is below n
+    pub fn from_be_bytes(bytes: &[u8; 32]) -> Option<Self> {
+        let num = U256::from_big_endian(bytes);
+        if num < ORDER {
+            Some(S256Scalar(num))
+        } else {
+            None
+        }
+    }
+
+    /// A 32 bytes big endian tweak, refused unless below n
+    pub fn from_tweak(tweak: &[u8; 32]) -> Result<Self, TweakError> {
+        Self::from_be_bytes(tweak).ok_or(TweakError::OutOfRange)
+    }
+
+    /// 32 bytes big endian, reduced mod n like a hash to sign
+    pub fn reduce_be_bytes(bytes: &[u8; 32]) -> Self {
+        S256Scalar::new(U256::from_big_endian(bytes))
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut buf = [0u8; 32];
+        self.0.to_big_endian(&mut buf);
+        buf
+    }
+
+    /// The number, always below n
+    pub fn num(&self) -> U256 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// Above n / 2, the half low-s signatures avoid
+    pub fn is_high(&self) -> bool {
+        self.0 > ORDER >> 1
+    }
+
+    /// Multiplicative inverse by Fermat, a^(n-2), zero stays zero
+    pub fn invert(&self) -> Self {
+        S256Scalar(self.0.modpow(ORDER - U256::from(2u8), ORDER))
+    }
+}
+
+impl From<S256Scalar> for U256 {
+    fn from(scalar: S256Scalar) -> Self {
+        scalar.0
+    }
+}
+
+impl Add for S256Scalar {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        S256Scalar(self.0.mod_add(rhs.0, ORDER))
+    }
+}
+
+impl Sub for S256Scalar {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        S256Scalar(self.0.mod_sub(rhs.0, ORDER))
+    }
+}
+
+impl Mul for S256Scalar {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        S256Scalar(self.0.modmul(rhs.0, ORDER))
+    }
+}
+
+impl Neg for S256Scalar {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        S256Scalar::zero() - self
+    }
+}
+
+impl Display for S256Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "S256Scalar({:064x})", self.0)
+    }
+}
+
+mod test {
+    use super::super::ec::utils::U256;
+    use super::super::s256_point::Secp256K1EllipticCurve;
+    use super::{S256Scalar, ORDER};
+
+    #[test]
+    fn test_order() {
+        assert_eq!(
+            ORDER,
+            U256::from_hex(b"fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141")
+        );
+        assert_eq!(Secp256K1EllipticCurve::n(), ORDER);
+    }
+
+    #[test]
+    fn test_scalar_arithmetic() {
+        let minus_one = S256Scalar::new(ORDER - U256::one());
+        assert_eq!(S256Scalar::new(ORDER), S256Scalar::zero());
+        assert_eq!(
+            S256Scalar::new(ORDER + U256::from(5u8)),
+            S256Scalar::new(5u8)
+        );
+        assert_eq!(minus_one + S256Scalar::new(2u8), S256Scalar::one());
+        assert_eq!(S256Scalar::one() - S256Scalar::new(2u8), minus_one);
+        assert_eq!(-S256Scalar::one(), minus_one);
+        assert_eq!(-S256Scalar::zero(), S256Scalar::zero());
+        assert_eq!(minus_one * minus_one, S256Scalar::one());
+        assert!(minus_one.is_high());
+        assert!(!S256Scalar::new(ORDER >> 1).is_high());
+
+        for _ in 0..8 {
+            let a = S256Scalar::new(U256::from_random());
+            if a.is_zero() {
+                continue;
+            }
+            assert_eq!(a * a.invert(), S256Scalar::one());
+        }
+    }
+
+    #[test]
+    fn test_scalar_bytes() {
+        let max = [0xffu8; 32];
+        assert_eq!(S256Scalar::from_be_bytes(&max), None);
+        assert_eq!(
+            S256Scalar::reduce_be_bytes(&max),
+            S256Scalar::new(U256::max_value() - ORDER)
+        );
+        let a = S256Scalar::new(U256::from_random());
+        assert_eq!(S256Scalar::from_be_bytes(&a.to_be_bytes()), Some(a));
+    }
+}
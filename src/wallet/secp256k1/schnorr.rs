@@ -0,0 +1,90 @@
+use super::ec::utils::U256;
+use super::s256_field::S256Field;
+use super::s256_point::{S256Point, Secp256K1EllipticCurve};
+use super::utils::tagged_hash;
+use super::x_only_key::XOnlyPublicKey;
+use std::fmt::Display;
+
+/// BIP340 schnorr signature, x coordinate of the nonce point R followed by s
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SchnorrSignature {
+    pub r: [u8; 32],
+    pub s: U256,
+}
+
+impl Copy for SchnorrSignature {}
+
+impl Display for SchnorrSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(&self.serialize()[..]))
+    }
+}
+
+/// e = tagged_hash("BIP0340/challenge", R || P || m) mod n
+pub(crate) fn challenge(r: &[u8; 32], pubkey: &[u8; 32], msg: &[u8]) -> U256 {
+    let mut buf = Vec::with_capacity(64 + msg.len());
+    buf.extend_from_slice(r);
+    buf.extend_from_slice(pubkey);
+    buf.extend_from_slice(msg);
+    U256::from_big_endian(&tagged_hash("BIP0340/challenge", &buf)) % Secp256K1EllipticCurve::n()
+}
+
+impl SchnorrSignature {
+    pub fn new(r: [u8; 32], s: U256) -> Self {
+        SchnorrSignature { r, s }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 64 {
+            return None;
+        }
+        let mut r = [0u8; 32];
+        r.copy_from_slice(&bytes[0..32]);
+        Some(SchnorrSignature {
+            r,
+            s: U256::from_big_endian(&bytes[32..64]),
+        })
+    }
+
+    pub fn serialize(&self) -> [u8; 64] {
+        let mut buf = [0u8; 64];
+        buf[0..32].copy_from_slice(&self.r);
+        self.s.to_big_endian(&mut buf[32..64]);
+        buf
+    }
+
+    /// Verify against the x only public key `pubkey`, R = s*G - e*P must have an even y
+    /// and x coordinate r
+    pub fn verify(&self, pubkey: &XOnlyPublicKey, msg: &[u8]) -> bool {
+        let n = Secp256K1EllipticCurve::n();
+        let point = pubkey.to_point();
+        if U256::from_big_endian(&self.r) >= S256Field::prime() || self.s >= n {
+            return false;
+        }
+
+        let e = challenge(&self.r, &pubkey.serialize(), msg);
+        let r = S256Point::gen_point() * self.s + point * (n - e);
+        match r.coordinate() {
+            Some((_, y)) => y.is_even() && r.x_only() == self.r,
+            None => false,
+        }
+    }
+}
+
+mod test {
+    use super::{SchnorrSignature, XOnlyPublicKey};
+
+    #[test]
+    fn test_schnorr_verify() {
+        // BIP340 test vector 1
+        let pubkey = XOnlyPublicKey::parse(&hex!(
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659"
+        ))
+        .unwrap();
+        let msg = hex!("243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89");
+        let sig = SchnorrSignature::parse(&hex!("6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de33418906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a")).unwrap();
+        assert!(sig.verify(&pubkey, &msg));
+        assert!(!sig.verify(&pubkey, &[0u8; 32]));
+        assert_eq!(SchnorrSignature::parse(&sig.serialize()).unwrap(), sig);
+    }
+}
@@ -1,7 +1,59 @@
+use super::ec::hex::{Parse, Serialize};
 use super::ec::utils::U256;
+use super::s256_field::S256Field;
+use super::s256_point::S256Point;
+use super::s256_scalar::{S256Scalar, ORDER};
 use std::collections::VecDeque;
 use std::fmt::Display;
 
+/// The BIP66 rule a signature encoding breaks
+#[derive(Fail, Debug, PartialEq, Eq, Clone)]
+pub enum DerViolation {
+    #[fail(display = "signature of {} bytes is shorter than 9 bytes", _0)]
+    TooShort(usize),
+    #[fail(display = "signature of {} bytes is longer than 73 bytes", _0)]
+    TooLong(usize),
+    #[fail(display = "signature does not start with the 0x30 compound tag")]
+    NotCompound,
+    #[fail(display = "compound length {} does not cover the signature", _0)]
+    WrongLength(usize),
+    #[fail(display = "R length {} runs past the signature", _0)]
+    RLengthOverflow(usize),
+    #[fail(display = "R and S lengths do not add up to the signature length")]
+    LengthMismatch,
+    #[fail(display = "R is not an integer")]
+    RNotInteger,
+    #[fail(display = "R has zero length")]
+    RZeroLength,
+    #[fail(display = "R is negative")]
+    RNegative,
+    #[fail(display = "R has excessive zero padding")]
+    RPadding,
+    #[fail(display = "S is not an integer")]
+    SNotInteger,
+    #[fail(display = "S has zero length")]
+    SZeroLength,
+    #[fail(display = "S is negative")]
+    SNegative,
+    #[fail(display = "S has excessive zero padding")]
+    SPadding,
+}
+impl Copy for DerViolation {}
+
+/// Why a DER signature could not be read at all
+#[derive(Fail, Debug, PartialEq, Eq, Clone)]
+pub enum DerParseError {
+    #[fail(display = "signature ends inside an element")]
+    Truncated,
+    #[fail(display = "signature does not start with the 0x30 compound tag")]
+    NotCompound,
+    #[fail(display = "R or S is not an integer")]
+    NotInteger,
+    #[fail(display = "integer of {} bytes does not fit 32 bytes", _0)]
+    IntegerOverflow(usize),
+}
+impl Copy for DerParseError {}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Signature {
     pub r: U256,
@@ -21,16 +73,26 @@ impl Signature {
         Signature { r, s }
     }
 
+    /// r and s as scalars, None unless both are in [1, n)
+    pub fn scalars(&self) -> Option<(S256Scalar, S256Scalar)> {
+        let mut buf = [0u8; 32];
+        self.r.to_big_endian(&mut buf);
+        let r = S256Scalar::from_be_bytes(&buf)?;
+        self.s.to_big_endian(&mut buf);
+        let s = S256Scalar::from_be_bytes(&buf)?;
+        if r.is_zero() || s.is_zero() {
+            return None;
+        }
+        Some((r, s))
+    }
+
     fn u256_der(v: U256) -> VecDeque<u8> {
         let mut buf = [0u8; 32];
         v.to_big_endian(&mut buf);
 
-        let mut ret = VecDeque::new();
-        for i in buf.iter() {
-            if *i != b'\x00' {
-                ret.push_back(*i);
-            }
-        }
+        // only the leading zeros go, zero itself keeps one byte
+        let start = buf.iter().position(|i| *i != b'\x00').unwrap_or(31);
+        let mut ret: VecDeque<u8> = buf[start..].iter().cloned().collect();
         if ret.front().expect("VecDeque is empty") & 0x80 > 0u8 {
             ret.push_front(b'\x00');
         }
@@ -41,6 +103,26 @@ impl Signature {
         ret
     }
 
+    /// 64 bytes r || s, both big endian
+    pub fn to_compact(&self) -> [u8; 64] {
+        let mut buf = [0u8; 64];
+        self.r.to_big_endian(&mut buf[0..32]);
+        self.s.to_big_endian(&mut buf[32..64]);
+        buf
+    }
+
+    /// 64 bytes r || s, None unless both are in [1, n)
+    pub fn from_compact(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 64 {
+            return None;
+        }
+        let sig = Signature::new(
+            U256::from_big_endian(&bytes[0..32]),
+            U256::from_big_endian(&bytes[32..64]),
+        );
+        sig.scalars().map(|_| sig)
+    }
+
     pub fn der(&self) -> Vec<u8> {
         let mut ret: VecDeque<u8> = VecDeque::new();
         ret.append(&mut Self::u256_der(self.r));
@@ -51,43 +133,216 @@ impl Signature {
         ret.into_iter().collect()
     }
 
-    fn parse_der_u256(bytes: &[u8]) -> U256 {
+    /// BIP66 check of `sig`, a DER signature followed by its sighash byte, in the order
+    /// the rules are listed there so the first one broken is reported
+    pub fn is_strict_der(sig: &[u8]) -> Result<(), DerViolation> {
+        let len = sig.len();
+        if len < 9 {
+            return Err(DerViolation::TooShort(len));
+        }
+        if len > 73 {
+            return Err(DerViolation::TooLong(len));
+        }
+        if sig[0] != 0x30 {
+            return Err(DerViolation::NotCompound);
+        }
+        if sig[1] as usize != len - 3 {
+            return Err(DerViolation::WrongLength(sig[1] as usize));
+        }
+        let r_len = sig[3] as usize;
+        if 5 + r_len >= len {
+            return Err(DerViolation::RLengthOverflow(r_len));
+        }
+        let s_len = sig[5 + r_len] as usize;
+        if r_len + s_len + 7 != len {
+            return Err(DerViolation::LengthMismatch);
+        }
+
+        if sig[2] != 0x02 {
+            return Err(DerViolation::RNotInteger);
+        }
+        if r_len == 0 {
+            return Err(DerViolation::RZeroLength);
+        }
+        if sig[4] & 0x80 != 0 {
+            return Err(DerViolation::RNegative);
+        }
+        if r_len > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+            return Err(DerViolation::RPadding);
+        }
+
+        let s = 6 + r_len;
+        if sig[s - 2] != 0x02 {
+            return Err(DerViolation::SNotInteger);
+        }
+        if s_len == 0 {
+            return Err(DerViolation::SZeroLength);
+        }
+        if sig[s] & 0x80 != 0 {
+            return Err(DerViolation::SNegative);
+        }
+        if s_len > 1 && sig[s] == 0x00 && sig[s + 1] & 0x80 == 0 {
+            return Err(DerViolation::SPadding);
+        }
+        Ok(())
+    }
+
+    /// The integer at `offset`, tag, length and value, and the offset after it. Leading
+    /// zeros are dropped, whatever their number.
+    fn parse_der_int(bytes: &[u8], offset: usize) -> Result<(U256, usize), DerParseError> {
+        if bytes.len() < offset + 2 {
+            return Err(DerParseError::Truncated);
+        }
+        if bytes[offset] != 0x02 {
+            return Err(DerParseError::NotInteger);
+        }
+        let end = offset + 2 + bytes[offset + 1] as usize;
+        if bytes.len() < end {
+            return Err(DerParseError::Truncated);
+        }
+        let value = &bytes[offset + 2..end];
+        let value = &value[value.iter().take_while(|b| **b == 0).count()..];
+        if value.len() > 32 {
+            return Err(DerParseError::IntegerOverflow(value.len()));
+        }
         let mut buf = [0u8; 32];
-        assert_eq!(bytes[0], b'\x02');
-        let len = bytes[1] as usize;
-        assert!(len <= 33);
-        let slice = if bytes[2] == b'\x00' {
-            &bytes[3..2 + len]
-        } else {
-            &bytes[2..2 + len]
-        };
-        let zero_count = 32 - slice.len();
-        for i in 0..zero_count {
-            buf[i] = 0u8;
+        buf[32 - value.len()..].copy_from_slice(value);
+        Ok((U256::from_big_endian(&buf), end))
+    }
+
+    /// R and S of a DER signature without the sighash byte. Lax like the signatures from
+    /// before BIP66, the lengths are followed but padding and the compound length are not
+    /// checked. R or S out of range only fails the verification.
+    pub fn try_parse_der(der_bytes: &[u8]) -> Result<Self, DerParseError> {
+        if der_bytes.len() < 2 {
+            return Err(DerParseError::Truncated);
         }
-        for (i, v) in slice.iter().enumerate() {
-            buf[zero_count + i] = *v;
+        if der_bytes[0] != 0x30 {
+            return Err(DerParseError::NotCompound);
         }
-        U256::from_big_endian(&buf)
+        let (r, s_offset) = Self::parse_der_int(der_bytes, 2)?;
+        let (s, _) = Self::parse_der_int(der_bytes, s_offset)?;
+        Ok(Signature::new(r, s))
     }
 
     pub fn parse_der(der_bytes: &[u8]) -> Self {
-        assert_eq!(der_bytes[0], b'\x30');
-        assert!(der_bytes.len() > der_bytes[1] as usize + 1);
+        Self::try_parse_der(der_bytes).expect("invalid DER signature")
+    }
+}
+
+/// Header byte of the 65 bytes compact form: 27 + recovery id, plus 4 for a compressed key
+const COMPACT_HEADER_BASE: u8 = 27;
+const COMPACT_HEADER_COMPRESSED: u8 = 4;
+
+/// Signature carrying what is needed to get the public key back from it, as in signed
+/// messages
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecoverableSignature {
+    pub sig: Signature,
+    /// Bit 0 is the parity of R's y, bit 1 is set when R's x is r + n
+    pub recovery_id: u8,
+    /// Whether the key is meant to be used compressed, only kept in the header byte
+    pub compressed: bool,
+}
 
-        let r_len = der_bytes[3] as usize;
-        let r = Self::parse_der_u256(&der_bytes[2..4 + r_len]);
+impl Copy for RecoverableSignature {}
 
-        let s_len = der_bytes[5 + r_len] as usize;
-        let s = Self::parse_der_u256(&der_bytes[4 + r_len..6 + r_len + s_len]);
+impl RecoverableSignature {
+    /// Public key that made this signature of `z`, None if there is none
+    pub fn recover(&self, z: U256) -> Option<S256Point> {
+        let (r, s) = self.sig.scalars()?;
+        if self.recovery_id > 3 {
+            return None;
+        }
+        // R's x is r, or r + n when that is still a field element
+        let mut x = r.num();
+        if self.recovery_id & 2 != 0 {
+            x = x.checked_add(ORDER)?;
+            if x >= S256Field::prime() {
+                return None;
+            }
+        }
+        let mut x_bytes = [0u8; 32];
+        x.to_big_endian(&mut x_bytes);
+        let nonce_point = S256Point::lift_x(&x_bytes, self.recovery_id & 1 != 0)?;
+
+        // Q = r^-1 * (s*R - z*G)
+        let r_inv = r.invert();
+        let z = S256Scalar::new(z);
+        let point = nonce_point * (s * r_inv) + S256Point::gen_point() * (-(z * r_inv));
+        if point.is_inf() {
+            None
+        } else {
+            Some(point)
+        }
+    }
+}
+
+impl Serialize for RecoverableSignature {
+    /// Header byte then r || s
+    fn serialize_bytes(&self) -> Vec<u8> {
+        let mut header = COMPACT_HEADER_BASE + self.recovery_id;
+        if self.compressed {
+            header += COMPACT_HEADER_COMPRESSED;
+        }
+        let mut buf = vec![header];
+        buf.extend_from_slice(&self.sig.to_compact());
+        buf
+    }
+}
 
-        Signature::new(r, s)
+impl Parse for RecoverableSignature {
+    fn parse_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 65 {
+            return None;
+        }
+        let header = bytes[0].checked_sub(COMPACT_HEADER_BASE)?;
+        if header >= 2 * COMPACT_HEADER_COMPRESSED {
+            return None;
+        }
+        Some(RecoverableSignature {
+            sig: Signature::from_compact(&bytes[1..])?,
+            recovery_id: header % COMPACT_HEADER_COMPRESSED,
+            compressed: header >= COMPACT_HEADER_COMPRESSED,
+        })
+    }
+}
+
+impl Serialize for Signature {
+    fn serialize_bytes(&self) -> Vec<u8> {
+        self.der()
+    }
+}
+
+impl Parse for Signature {
+    /// DER without the sighash byte, the lengths must add up to the whole input
+    fn parse_bytes(bytes: &[u8]) -> Option<Self> {
+        let valid_int = |offset: usize| {
+            bytes.len() > offset + 1
+                && bytes[offset] == 0x02
+                && bytes[offset + 1] as usize <= 33
+                && bytes[offset + 1] != 0
+        };
+        if bytes.len() < 8 || bytes[0] != 0x30 || bytes[1] as usize != bytes.len() - 2 {
+            return None;
+        }
+        if !valid_int(2) {
+            return None;
+        }
+        let s_offset = 4 + bytes[3] as usize;
+        if !valid_int(s_offset) || s_offset + 2 + bytes[s_offset + 1] as usize != bytes.len() {
+            return None;
+        }
+        Signature::try_parse_der(bytes).ok()
     }
 }
 
 mod test {
+    use super::super::ec::hex::{Parse, Serialize};
     use super::super::ec::utils::U256;
-    use super::Signature;
+    use super::{DerParseError, DerViolation, RecoverableSignature, Signature};
+    use crate::wallet::private_key::PrivateKey;
+    use crate::wallet::Hash256;
 
     #[test]
     fn test_sig_der_and_parse() {
@@ -97,6 +352,141 @@ mod test {
         let der = sig.der();
 
         let parsed_sig = Signature::parse_der(&der);
-        assert_eq!(sig, parsed_sig)
+        assert_eq!(sig, parsed_sig);
+
+        // truncated anywhere
+        for len in 0..der.len() {
+            assert_eq!(
+                Signature::try_parse_der(&der[..len]),
+                Err(DerParseError::Truncated)
+            );
+        }
+        assert_eq!(
+            Signature::try_parse_der(&hex!("310602010102010101")),
+            Err(DerParseError::NotCompound)
+        );
+
+        // a 33 bytes R without the sign byte is strict DER but no 256 bit number
+        let mut long_r = vec![0x30, 0x26, 0x02, 0x21, 0x01];
+        long_r.extend_from_slice(&[0x11; 32]);
+        long_r.extend_from_slice(&[0x02, 0x01, 0x01]);
+        assert_eq!(
+            Signature::try_parse_der(&long_r),
+            Err(DerParseError::IntegerOverflow(33))
+        );
+        let mut long_s = vec![0x30, 0x27, 0x02, 0x01, 0x01, 0x02, 0x22];
+        long_s.extend_from_slice(&[0x7f; 34]);
+        assert_eq!(
+            Signature::try_parse_der(&long_s),
+            Err(DerParseError::IntegerOverflow(34))
+        );
+        // any zero padding is dropped
+        let mut padded = vec![0x30, 0x2a, 0x02, 0x23, 0x00, 0x00, 0x00];
+        padded.extend_from_slice(&[0x11; 32]);
+        padded.extend_from_slice(&[0x02, 0x01, 0x05]);
+        assert_eq!(
+            Signature::try_parse_der(&padded),
+            Ok(Signature::new(
+                U256::from_big_endian(&[0x11; 32]),
+                U256::from(5)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_compact() {
+        let r = U256::from_hex(b"37206a0610995c58074999cb9767b87af4c4978db68c06e8e6e81d282047a7c6");
+        let s = U256::from_hex(b"8ca63759c1157ebeaec0d03cecca119fc9a75bf8e6d0fa65c841c8e2738cdaec");
+        let sig = Signature::new(r, s);
+        let compact = sig.to_compact();
+        assert_eq!(
+            hex::encode(&compact[..]),
+            "37206a0610995c58074999cb9767b87af4c4978db68c06e8e6e81d282047a7c68ca63759c1157ebeaec0d03cecca119fc9a75bf8e6d0fa65c841c8e2738cdaec"
+        );
+        assert_eq!(Signature::from_compact(&compact), Some(sig));
+        assert_eq!(Signature::from_compact(&compact[1..]), None);
+        assert_eq!(Signature::from_compact(&[0xff; 64]), None);
+        assert_eq!(Signature::from_compact(&[0u8; 64]), None);
+    }
+
+    #[test]
+    fn test_recoverable() {
+        for _ in 0..4 {
+            let key = PrivateKey::new(U256::from_random());
+            let z = U256::from_random();
+            let sig = key.sign_recoverable(z);
+            assert_eq!(sig.recover(z), Some(key.point));
+            assert!(key.point.verify(Hash256::from(z), sig.sig));
+            assert_ne!(sig.recover(z ^ U256::one()), Some(key.point));
+
+            let bytes = sig.serialize_bytes();
+            assert_eq!(bytes[0], 31 + sig.recovery_id);
+            assert_eq!(RecoverableSignature::parse_bytes(&bytes), Some(sig));
+        }
+
+        let mut bytes = PrivateKey::new(U256::from(7u8))
+            .sign_recoverable(U256::one())
+            .serialize_bytes();
+        bytes[0] = 26;
+        assert_eq!(RecoverableSignature::parse_bytes(&bytes), None);
+        bytes[0] = 35;
+        assert_eq!(RecoverableSignature::parse_bytes(&bytes), None);
+        bytes[0] = 28;
+        let uncompressed = RecoverableSignature::parse_bytes(&bytes).unwrap();
+        assert!(!uncompressed.compressed);
+        assert_eq!(uncompressed.recovery_id, 1);
+    }
+
+    #[test]
+    fn test_is_strict_der() {
+        let r = U256::from_hex(b"37206a0610995c58074999cb9767b87af4c4978db68c06e8e6e81d282047a7c6");
+        let s = U256::from_hex(b"8ca63759c1157ebeaec0d03cecca119fc9a75bf8e6d0fa65c841c8e2738cdaec");
+        let mut sig = Signature::new(r, s).der();
+        sig.push(0x01);
+        assert_eq!(Signature::is_strict_der(&sig), Ok(()));
+
+        let broken = |f: &dyn Fn(&mut Vec<u8>)| {
+            let mut sig = sig.clone();
+            f(&mut sig);
+            Signature::is_strict_der(&sig)
+        };
+        assert_eq!(
+            broken(&|sig| sig.truncate(8)),
+            Err(DerViolation::TooShort(8))
+        );
+        assert_eq!(
+            broken(&|sig| sig.resize(74, 0)),
+            Err(DerViolation::TooLong(74))
+        );
+        assert_eq!(broken(&|sig| sig[0] = 0x31), Err(DerViolation::NotCompound));
+        assert_eq!(
+            broken(&|sig| sig[1] += 1),
+            Err(DerViolation::WrongLength(0x46))
+        );
+        assert_eq!(
+            broken(&|sig| sig[3] = 0x50),
+            Err(DerViolation::RLengthOverflow(0x50))
+        );
+        assert_eq!(
+            broken(&|sig| sig[3] -= 1),
+            Err(DerViolation::LengthMismatch)
+        );
+        assert_eq!(broken(&|sig| sig[2] = 0x03), Err(DerViolation::RNotInteger));
+        assert_eq!(broken(&|sig| sig[4] = 0x80), Err(DerViolation::RNegative));
+        assert_eq!(broken(&|sig| sig[4] = 0x00), Err(DerViolation::RPadding));
+        // S has a leading zero because its top bit is set
+        assert_eq!(
+            broken(&|sig| sig[36] = 0x03),
+            Err(DerViolation::SNotInteger)
+        );
+        assert_eq!(broken(&|sig| sig[38] = 0x80), Err(DerViolation::SNegative));
+        assert_eq!(broken(&|sig| sig[39] = 0x0c), Err(DerViolation::SPadding));
+
+        let mut zero_r = hex!("3006020002020101").to_vec();
+        zero_r.push(0x01);
+        assert_eq!(
+            Signature::is_strict_der(&zero_r),
+            Err(DerViolation::RZeroLength)
+        );
     }
 }
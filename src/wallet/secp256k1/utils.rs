@@ -9,9 +9,19 @@ use std::str::FromStr;
 use crate::wallet::secp256k1::ec::hex::{FromHex, Hex};
 use crate::wallet::secp256k1::ec::utils::U256;
 
-pub fn encode_base58(bytes: &[u8]) -> String {
-    let base58_alphabet = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum Base58Error {
+    #[fail(display = "invalid base58 character: {}", _0)]
+    InvalidCharacter(char),
+    #[fail(display = "base58 payload too short for a checksum")]
+    TooShort,
+    #[fail(display = "base58 checksum mismatch")]
+    InvalidChecksum,
+}
 
+pub fn encode_base58(bytes: &[u8]) -> String {
     let mut prefix = "".to_string();
     for i in bytes.iter() {
         if *i == 0u8 {
@@ -27,7 +37,7 @@ pub fn encode_base58(bytes: &[u8]) -> String {
         let (quotient, remainder) = div_rem(v, BigUint::from(58u8));
         v = quotient;
         ret.push(
-            base58_alphabet
+            BASE58_ALPHABET
                 .chars()
                 .nth(remainder.to_usize().unwrap())
                 .unwrap(),
@@ -45,6 +55,36 @@ pub fn encode_base58_checksum(bytes: &[u8]) -> String {
     encode_base58(&bytes)
 }
 
+pub fn decode_base58(s: &str) -> Result<Vec<u8>, Base58Error> {
+    let mut num = BigUint::from(0u8);
+    for c in s.chars() {
+        let digit = BASE58_ALPHABET
+            .find(c)
+            .ok_or(Base58Error::InvalidCharacter(c))?;
+        num = num * BigUint::from(58u8) + BigUint::from(digit);
+    }
+
+    // every leading '1' stands for a leading zero byte
+    let zeros = s.chars().take_while(|c| *c == '1').count();
+    let mut ret = vec![0u8; zeros];
+    if num > BigUint::from(0u8) {
+        ret.extend(num.to_bytes_be());
+    }
+    Ok(ret)
+}
+
+pub fn decode_base58_checksum(s: &str) -> Result<Vec<u8>, Base58Error> {
+    let bytes = decode_base58(s)?;
+    if bytes.len() < 4 {
+        return Err(Base58Error::TooShort);
+    }
+    let (payload, checksum) = bytes.split_at(bytes.len() - 4);
+    if hash256(payload)[0..4] != checksum[..] {
+        return Err(Base58Error::InvalidChecksum);
+    }
+    Ok(payload.to_vec())
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Hash256([u8; 32]);
 
@@ -136,8 +176,24 @@ pub fn hash256(bytes: &[u8]) -> Hash256 {
     Hash256(buf)
 }
 
+/// BIP340 tagged hash, sha256(sha256(tag) || sha256(tag) || msg)
+pub fn tagged_hash(tag: &str, msg: &[u8]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let hash = Sha256::new()
+        .chain(&tag_hash)
+        .chain(&tag_hash)
+        .chain(msg)
+        .result();
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(&hash[0..32]);
+    buf
+}
+
 mod test {
-    use super::{encode_base58, encode_base58_checksum, hash160, hash256, Hash160, Hash256};
+    use super::{
+        decode_base58, decode_base58_checksum, encode_base58, encode_base58_checksum, hash160,
+        hash256, Base58Error, Hash160, Hash256,
+    };
 
     #[test]
     fn test_hash160() {
@@ -180,4 +236,26 @@ mod test {
             "2BnRyzAHqgBgec9ahUkMZ1uchLFa5Dha2BLTuzCS1orPri4j2f".to_string()
         );
     }
+
+    #[test]
+    fn test_decode_base58() {
+        let v = hash256(b"1");
+        assert_eq!(decode_base58(&encode_base58(&v)).unwrap(), v.to_vec());
+        assert_eq!(decode_base58("1112").unwrap(), vec![0u8, 0, 0, 1]);
+        assert_eq!(
+            decode_base58("0OIl"),
+            Err(Base58Error::InvalidCharacter('0'))
+        );
+
+        let checked = encode_base58_checksum(&v);
+        assert_eq!(decode_base58_checksum(&checked).unwrap(), v.to_vec());
+
+        let mut tampered = checked.clone();
+        tampered.pop();
+        tampered.push('1');
+        assert_eq!(
+            decode_base58_checksum(&tampered),
+            Err(Base58Error::InvalidChecksum)
+        );
+    }
 }
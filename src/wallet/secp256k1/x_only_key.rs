@@ -0,0 +1,90 @@
+use std::fmt::{self, Display};
+
+use super::s256_point::S256Point;
+use super::s256_scalar::TweakError;
+
+/// BIP340 public key, the 32 bytes x coordinate of a point standing for the one with an
+/// even y
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XOnlyPublicKey {
+    /// Always has an even y
+    point: S256Point,
+}
+
+impl Copy for XOnlyPublicKey {}
+
+impl XOnlyPublicKey {
+    /// The x only key of `point` and whether `point` had an odd y, which is dropped
+    pub fn from_point(point: &S256Point) -> (Self, bool) {
+        let (_, y) = point
+            .coordinate()
+            .expect("point at infinity has no x only key");
+        let odd = !y.is_even();
+        let point = if odd { -*point } else { *point };
+        (XOnlyPublicKey { point }, odd)
+    }
+
+    /// 32 bytes x coordinate, None if no point has it
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 32 {
+            return None;
+        }
+        let mut x = [0u8; 32];
+        x.copy_from_slice(bytes);
+        S256Point::lift_x(&x, false).map(|point| XOnlyPublicKey { point })
+    }
+
+    pub fn serialize(&self) -> [u8; 32] {
+        self.point.x_only()
+    }
+
+    /// The full point, with an even y
+    pub fn to_point(&self) -> S256Point {
+        self.point
+    }
+
+    /// P + t*G brought back to an even y, with whether the tweaked point had an odd y as a
+    /// taproot output key needs it for the control block
+    pub fn add_tweak(&self, tweak: &[u8; 32]) -> Result<(Self, bool), TweakError> {
+        let point = self.point.add_tweak(tweak)?;
+        Ok(XOnlyPublicKey::from_point(&point))
+    }
+}
+
+impl Display for XOnlyPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.serialize()[..]))
+    }
+}
+
+mod test {
+    use super::XOnlyPublicKey;
+    use crate::wallet::private_key::PrivateKey;
+    use crate::wallet::U256;
+
+    #[test]
+    fn test_x_only_key() {
+        // BIP340 test vector 1
+        let bytes = hex!("dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659");
+        let key = XOnlyPublicKey::parse(&bytes).unwrap();
+        assert_eq!(key.serialize(), bytes);
+        assert!(key.to_point().coordinate().unwrap().1.is_even());
+        assert_eq!(XOnlyPublicKey::from_point(&key.to_point()), (key, false));
+        assert_eq!(XOnlyPublicKey::from_point(&-key.to_point()), (key, true));
+
+        // x = 5 is not on the curve
+        let mut x = [0u8; 32];
+        x[31] = 5;
+        assert_eq!(XOnlyPublicKey::parse(&x), None);
+        assert_eq!(XOnlyPublicKey::parse(&bytes[1..]), None);
+
+        let mut tweak = [0u8; 32];
+        tweak[31] = 3;
+        let point = PrivateKey::new(U256::from(5u8)).point;
+        let (key, _) = XOnlyPublicKey::from_point(&point);
+        let (tweaked, odd) = key.add_tweak(&tweak).unwrap();
+        let expected = key.to_point().add_tweak(&tweak).unwrap();
+        assert_eq!(tweaked.serialize(), expected.x_only());
+        assert_eq!(odd, !expected.coordinate().unwrap().1.is_even());
+    }
+}
@@ -0,0 +1,548 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use super::bech32::{convert_bits, decode, encode, Bech32Error, Variant};
+use super::private_key::PrivateKey;
+use super::secp256k1::ec::hex::Parse;
+use super::secp256k1::s256_point::S256Point;
+use super::secp256k1::s256_scalar::S256Scalar;
+use super::{hash160, tagged_hash};
+use crate::block::Block;
+use crate::transaction::{OutPoint, ScriptPubKey, ScriptPubKeyType, Transaction, TxOutput};
+
+/// x coordinate of the BIP341 unspendable internal key, script path spends using it do
+/// not take part in silent payments
+const NUMS_H: [u8; 32] = hex!("50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0");
+/// First byte of a taproot annex
+const ANNEX_TAG: u8 = 0x50;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum SilentPaymentError {
+    #[fail(display = "{}", _0)]
+    Bech32Error(Bech32Error),
+    #[fail(display = "expected a sp or tsp address")]
+    UnexpectedPrefix,
+    #[fail(display = "unsupported silent payment version: {}", _0)]
+    UnsupportedVersion(u8),
+    #[fail(display = "invalid silent payment key")]
+    InvalidKey,
+    #[fail(display = "no input key to derive the shared secret from")]
+    NoInputKeys,
+    #[fail(display = "tweak is not a valid scalar")]
+    InvalidTweak,
+}
+
+/// BIP352 address, a scan key to find payments with and a spend key they are paid to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SilentPaymentAddress {
+    pub scan: S256Point,
+    pub spend: S256Point,
+    pub testnet: bool,
+}
+impl Copy for SilentPaymentAddress {}
+
+impl SilentPaymentAddress {
+    pub fn new(scan: S256Point, spend: S256Point, testnet: bool) -> Self {
+        SilentPaymentAddress {
+            scan,
+            spend,
+            testnet,
+        }
+    }
+
+    /// bech32m "sp" or "tsp" address, version 0 then both compressed keys
+    pub fn parse(s: &str) -> Result<Self, SilentPaymentError> {
+        let (hrp, data, variant) = decode(s).map_err(SilentPaymentError::Bech32Error)?;
+        let testnet = match hrp.as_str() {
+            "sp" => false,
+            "tsp" => true,
+            _ => return Err(SilentPaymentError::UnexpectedPrefix),
+        };
+        let (version, program) = data
+            .split_first()
+            .ok_or(SilentPaymentError::Bech32Error(Bech32Error::InvalidLength))?;
+        if variant != Variant::Bech32m || *version != 0 {
+            return Err(SilentPaymentError::UnsupportedVersion(*version));
+        }
+        let keys = convert_bits(program, 5, 8, false).map_err(SilentPaymentError::Bech32Error)?;
+        if keys.len() != 66 {
+            return Err(SilentPaymentError::InvalidKey);
+        }
+        let scan = S256Point::parse_bytes(&keys[..33]).ok_or(SilentPaymentError::InvalidKey)?;
+        let spend = S256Point::parse_bytes(&keys[33..]).ok_or(SilentPaymentError::InvalidKey)?;
+        Ok(SilentPaymentAddress::new(scan, spend, testnet))
+    }
+}
+
+impl Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut keys = self.scan.compressed_sec().to_vec();
+        keys.extend_from_slice(&self.spend.compressed_sec());
+        let mut data = vec![0u8];
+        data.extend(convert_bits(&keys, 8, 5, true).expect("8 bit bytes always convert"));
+        let hrp = if self.testnet { "tsp" } else { "sp" };
+        write!(f, "{}", encode(hrp, &data, Variant::Bech32m))
+    }
+}
+
+/// tagged_hash("BIP0352/Inputs", smallest outpoint || A)
+fn input_hash(
+    smallest: &OutPoint,
+    input_key_sum: &S256Point,
+) -> Result<S256Scalar, SilentPaymentError> {
+    let mut msg = smallest.serialize();
+    msg.extend_from_slice(&input_key_sum.compressed_sec());
+    match S256Scalar::from_be_bytes(&tagged_hash("BIP0352/Inputs", &msg)) {
+        Some(hash) if !hash.is_zero() => Ok(hash),
+        _ => Err(SilentPaymentError::InvalidTweak),
+    }
+}
+
+/// t_k = tagged_hash("BIP0352/SharedSecret", S || k)
+fn shared_secret_tweak(
+    shared_secret: &S256Point,
+    k: u32,
+) -> Result<S256Scalar, SilentPaymentError> {
+    let mut msg = shared_secret.compressed_sec().to_vec();
+    msg.extend_from_slice(&k.to_be_bytes());
+    match S256Scalar::from_be_bytes(&tagged_hash("BIP0352/SharedSecret", &msg)) {
+        Some(tweak) if !tweak.is_zero() => Ok(tweak),
+        _ => Err(SilentPaymentError::InvalidTweak),
+    }
+}
+
+/// The smallest outpoint in serialized byte order
+fn smallest_out_point<'a, I: IntoIterator<Item = &'a OutPoint>>(out_points: I) -> Option<OutPoint> {
+    out_points
+        .into_iter()
+        .min_by_key(|out_point| out_point.serialize())
+        .copied()
+}
+
+fn taproot_script_pub_key(output_key: &[u8; 32]) -> ScriptPubKey {
+    let mut content = Vec::with_capacity(34);
+    content.extend_from_slice(&[0x51, 0x20]);
+    content.extend_from_slice(output_key);
+    ScriptPubKey { content }
+}
+
+/// Sender side: the taproot outputs paying each of `recipients`, in their order.
+///
+/// `out_points` are those of every input of the transaction, `input_keys` the private keys
+/// of the inputs that take part, each flagged when it spends a taproot output so its key
+/// is used with an even y.
+pub fn create_outputs(
+    out_points: &[OutPoint],
+    input_keys: &[(&PrivateKey, bool)],
+    recipients: &[SilentPaymentAddress],
+) -> Result<Vec<ScriptPubKey>, SilentPaymentError> {
+    let secret = input_keys
+        .iter()
+        .fold(S256Scalar::zero(), |sum, (key, taproot)| {
+            let (_, y) = key
+                .point
+                .coordinate()
+                .expect("private key point is not infinity");
+            if *taproot && !y.is_even() {
+                sum - key.secret()
+            } else {
+                sum + key.secret()
+            }
+        });
+    if secret.is_zero() {
+        return Err(SilentPaymentError::NoInputKeys);
+    }
+    let smallest = smallest_out_point(out_points).ok_or(SilentPaymentError::NoInputKeys)?;
+    let input_key_sum = S256Point::gen_point() * secret;
+    let secret = input_hash(&smallest, &input_key_sum)? * secret;
+
+    let mut shared_secrets: HashMap<[u8; 33], (S256Point, u32)> = HashMap::new();
+    recipients
+        .iter()
+        .map(|recipient| {
+            let (shared_secret, k) = shared_secrets
+                .entry(recipient.scan.compressed_sec())
+                .or_insert_with(|| (recipient.scan * secret, 0));
+            let tweak = shared_secret_tweak(shared_secret, *k)?;
+            *k += 1;
+            let output_key = recipient.spend + S256Point::gen_point() * tweak;
+            Ok(taproot_script_pub_key(&output_key.x_only()))
+        })
+        .collect()
+}
+
+/// Public key an input contributes, None for inputs that do not take part
+fn input_public_key(
+    script_sig: &[u8],
+    witness: &[Vec<u8>],
+    prevout: &ScriptPubKey,
+) -> Option<S256Point> {
+    let compressed_key = |bytes: &[u8]| {
+        if bytes.len() == 33 {
+            S256Point::parse_bytes(bytes)
+        } else {
+            None
+        }
+    };
+    match prevout.script_type() {
+        ScriptPubKeyType::PubKeyHash => {
+            // the last 33 bytes of the script sig hashing to the key hash, a malleated
+            // script sig may have more after the key
+            (33..=script_sig.len())
+                .rev()
+                .map(|end| &script_sig[end - 33..end])
+                .filter(|key| *hash160(key) == prevout.content[3..23])
+                .find_map(compressed_key)
+        }
+        ScriptPubKeyType::ScriptHash => {
+            // only nested P2WPKH
+            if script_sig.len() != 23 || script_sig[..3] != [0x16, 0x00, 0x14] {
+                return None;
+            }
+            match witness {
+                [_, key] => compressed_key(key),
+                _ => None,
+            }
+        }
+        ScriptPubKeyType::WitnessV0KeyHash => match witness {
+            [_, key] => compressed_key(key),
+            _ => None,
+        },
+        ScriptPubKeyType::WitnessV1Taproot => {
+            let mut witness = witness;
+            if witness.len() > 1 && witness.last()?.first() == Some(&ANNEX_TAG) {
+                witness = &witness[..witness.len() - 1];
+            }
+            if witness.len() > 1 {
+                let control_block = witness.last()?;
+                if control_block.get(1..33)? == NUMS_H {
+                    return None;
+                }
+            }
+            let mut x = [0u8; 32];
+            x.copy_from_slice(&prevout.content[2..34]);
+            S256Point::lift_x(&x, false)
+        }
+        _ => None,
+    }
+}
+
+/// A silent payment found while scanning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SilentPaymentOutput {
+    pub out_point: OutPoint,
+    pub amount: u64,
+    pub output_key: [u8; 32],
+    /// Added to the spend secret to get the output's secret
+    pub tweak: [u8; 32],
+}
+impl Copy for SilentPaymentOutput {}
+
+impl SilentPaymentOutput {
+    /// Private key of the output from the receiver's spend key
+    pub fn spend_key(&self, spend_key: &PrivateKey) -> PrivateKey {
+        let tweak = S256Scalar::reduce_be_bytes(&self.tweak);
+        PrivateKey::new((spend_key.secret() + tweak).num())
+    }
+}
+
+/// Receiver side, holds the scan secret and only the public spend key
+pub struct SilentPaymentReceiver {
+    scan_key: PrivateKey,
+    spend: S256Point,
+    testnet: bool,
+}
+
+impl SilentPaymentReceiver {
+    pub fn new(scan_key: PrivateKey, spend: S256Point, testnet: bool) -> Self {
+        SilentPaymentReceiver {
+            scan_key,
+            spend,
+            testnet,
+        }
+    }
+
+    pub fn address(&self) -> SilentPaymentAddress {
+        SilentPaymentAddress::new(self.scan_key.point, self.spend, self.testnet)
+    }
+
+    /// Outputs of `tx` paying this receiver, `prevouts` are the outputs its inputs spend
+    /// in input order
+    pub fn scan_transaction(
+        &self,
+        tx: &Transaction,
+        prevouts: &[TxOutput],
+    ) -> Result<Vec<SilentPaymentOutput>, SilentPaymentError> {
+        let is_taproot = |output: &TxOutput| {
+            output.script_pub_key.script_type() == ScriptPubKeyType::WitnessV1Taproot
+        };
+        // spends of witness versions above 1 are left to future upgrades of BIP352
+        let spends_future_segwit = prevouts.iter().any(|prevout| {
+            prevout
+                .script_pub_key
+                .witness_program()
+                .map_or(false, |(version, _)| version > 1)
+        });
+        if tx.is_coinbase()
+            || prevouts.len() != tx.inputs.len()
+            || spends_future_segwit
+            || !tx.outputs.iter().any(is_taproot)
+        {
+            return Ok(vec![]);
+        }
+
+        let input_key_sum = tx
+            .inputs
+            .iter()
+            .zip(prevouts)
+            .filter_map(|(input, prevout)| {
+                input_public_key(
+                    &input.script_sig.content,
+                    &input.witness,
+                    &prevout.script_pub_key,
+                )
+            })
+            .fold(S256Point::inf(), |sum, key| sum + key);
+        if input_key_sum.is_inf() {
+            return Ok(vec![]);
+        }
+        let out_points: Vec<OutPoint> = tx.inputs.iter().map(|input| input.out_point()).collect();
+        let smallest = smallest_out_point(&out_points).ok_or(SilentPaymentError::NoInputKeys)?;
+        let secret = input_hash(&smallest, &input_key_sum)? * self.scan_key.secret();
+        let shared_secret = input_key_sum * secret;
+
+        let txid = tx.id();
+        let mut found = vec![];
+        let mut k = 0;
+        loop {
+            let tweak = shared_secret_tweak(&shared_secret, k)?;
+            let output_key = (self.spend + S256Point::gen_point() * tweak).x_only();
+            let vout = tx.outputs.iter().enumerate().position(|(vout, output)| {
+                is_taproot(output)
+                    && output.script_pub_key.content[2..] == output_key
+                    && found
+                        .iter()
+                        .all(|out: &SilentPaymentOutput| out.out_point.vout != vout as u32)
+            });
+            let vout = match vout {
+                Some(vout) => vout,
+                None => break,
+            };
+            found.push(SilentPaymentOutput {
+                out_point: OutPoint::new(txid, vout as u32),
+                amount: u64::from(tx.outputs[vout].amount),
+                output_key,
+                tweak: tweak.to_be_bytes(),
+            });
+            k += 1;
+        }
+        Ok(found)
+    }
+
+    /// Every payment to this receiver in `block`. Outputs created earlier in the block are
+    /// resolved from it, any other spent output is asked from `prevout`; transactions with
+    /// an unknown prevout are skipped.
+    pub fn scan_block<F>(
+        &self,
+        block: &Block,
+        mut prevout: F,
+    ) -> Result<Vec<SilentPaymentOutput>, SilentPaymentError>
+    where
+        F: FnMut(&OutPoint) -> Option<TxOutput>,
+    {
+        let mut created: HashMap<OutPoint, TxOutput> = HashMap::new();
+        let mut found = vec![];
+        for tx in &block.txs {
+            if !tx.is_coinbase() {
+                let prevouts: Option<Vec<TxOutput>> = tx
+                    .inputs
+                    .iter()
+                    .map(|input| {
+                        let out_point = input.out_point();
+                        created
+                            .get(&out_point)
+                            .cloned()
+                            .or_else(|| prevout(&out_point))
+                    })
+                    .collect();
+                if let Some(prevouts) = prevouts {
+                    found.extend(self.scan_transaction(tx, &prevouts)?);
+                }
+            }
+            let txid = tx.id();
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                created.insert(OutPoint::new(txid, vout as u32), output.clone());
+            }
+        }
+        Ok(found)
+    }
+}
+
+mod test {
+    use super::{create_outputs, SilentPaymentAddress, SilentPaymentError, SilentPaymentReceiver};
+    use crate::block::{Block, BlockHeader};
+    use crate::transaction::{OutPoint, ScriptPubKey, TransactionBuilder, TxHash, TxOutput};
+    use crate::wallet::private_key::PrivateKey;
+    use crate::wallet::U256;
+
+    fn p2wpkh(key: &PrivateKey) -> ScriptPubKey {
+        key.point.p2wpkh_script()
+    }
+
+    #[test]
+    fn test_address_round_trip() {
+        let scan = PrivateKey::new(U256::from(0x5ca9u32)).point;
+        let spend = PrivateKey::new(U256::from(0x5be9du32)).point;
+        let address = SilentPaymentAddress::new(scan, spend, false);
+        let encoded = address.to_string();
+        assert!(encoded.starts_with("sp1q"));
+        assert_eq!(encoded.len(), 116);
+        assert_eq!(SilentPaymentAddress::parse(&encoded), Ok(address));
+
+        let testnet = SilentPaymentAddress::new(scan, spend, true).to_string();
+        assert!(testnet.starts_with("tsp1q"));
+        assert_eq!(
+            SilentPaymentAddress::parse(&testnet).map(|address| address.testnet),
+            Ok(true)
+        );
+        assert_eq!(
+            SilentPaymentAddress::parse("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"),
+            Err(SilentPaymentError::UnexpectedPrefix)
+        );
+    }
+
+    #[test]
+    fn test_send_and_scan() {
+        let scan_key = PrivateKey::new(U256::from(0x5ca9u32));
+        let spend_key = PrivateKey::new(U256::from(0x5be9du32));
+        let receiver = SilentPaymentReceiver::new(scan_key, spend_key.point, false);
+        let address = receiver.address();
+
+        let alice = PrivateKey::new(U256::from(0xa11ceu32));
+        let bob = PrivateKey::new(U256::from(0xb0bu32));
+        let funding = TransactionBuilder::new()
+            .add_input(
+                OutPoint::new(TxHash::new(&[7; 32]).unwrap().1, 0),
+                0xffff_ffff,
+            )
+            .add_output(p2wpkh(&alice), 60_000)
+            .add_output(p2wpkh(&bob), 40_000)
+            .build();
+        let out_points = vec![
+            OutPoint::new(funding.id(), 1),
+            OutPoint::new(funding.id(), 0),
+        ];
+
+        let outputs = create_outputs(
+            &out_points,
+            &[(&bob, false), (&alice, false)],
+            &[address, address],
+        )
+        .unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert_ne!(outputs[0], outputs[1]);
+
+        let mut tx = out_points
+            .iter()
+            .fold(TransactionBuilder::new(), |builder, out_point| {
+                builder.add_input(*out_point, 0xffff_fffd)
+            })
+            .add_output(p2wpkh(&alice), 10_000)
+            .add_output(outputs[1].clone(), 50_000)
+            .add_output(outputs[0].clone(), 39_000)
+            .build();
+        // only the keys in the witness matter to the scanner
+        tx.inputs[0].witness = vec![vec![0x30; 71], bob.point.compressed_sec().to_vec()];
+        tx.inputs[1].witness = vec![vec![0x30; 71], alice.point.compressed_sec().to_vec()];
+
+        let prevouts: Vec<TxOutput> = vec![funding.outputs[1].clone(), funding.outputs[0].clone()];
+        let found = receiver.scan_transaction(&tx, &prevouts).unwrap();
+        let mut amounts: Vec<u64> = found.iter().map(|out| out.amount).collect();
+        amounts.sort();
+        assert_eq!(amounts, vec![39_000, 50_000]);
+        for out in &found {
+            let key = out.spend_key(&spend_key);
+            assert_eq!(key.point.x_only(), out.output_key);
+        }
+
+        // someone else's scan key finds nothing
+        let other = SilentPaymentReceiver::new(
+            PrivateKey::new(U256::from(0x07e5u32)),
+            spend_key.point,
+            false,
+        );
+        assert!(other.scan_transaction(&tx, &prevouts).unwrap().is_empty());
+
+        // the funding output spent in the same block is resolved from the block
+        let header = BlockHeader::parse(&[0; 80]).unwrap().1;
+        let block = Block {
+            header,
+            txs: vec![funding.clone(), tx.clone()],
+        };
+        let mut found_in_block = receiver.scan_block(&block, |_| None).unwrap();
+        found_in_block.sort_by_key(|out| out.amount);
+        let mut found = found;
+        found.sort_by_key(|out| out.amount);
+        assert_eq!(found_in_block, found);
+    }
+
+    #[test]
+    fn test_scan_input_rules() {
+        let scan_key = PrivateKey::new(U256::from(0x5ca9u32));
+        let spend_key = PrivateKey::new(U256::from(0x5be9du32));
+        let receiver = SilentPaymentReceiver::new(scan_key, spend_key.point, false);
+
+        let alice = PrivateKey::new(U256::from(0xa11ceu32));
+        let bob = PrivateKey::new(U256::from(0xb0bu32));
+        let funding = TransactionBuilder::new()
+            .add_input(
+                OutPoint::new(TxHash::new(&[7; 32]).unwrap().1, 0),
+                0xffff_ffff,
+            )
+            .add_output(alice.point.p2pkh_script(), 60_000)
+            .add_output(p2wpkh(&bob), 40_000)
+            .add_output(
+                ScriptPubKey {
+                    content: [&[0x52, 0x20][..], &[9; 32]].concat(),
+                },
+                1_000,
+            )
+            .build();
+        let out_points: Vec<OutPoint> = (0..3)
+            .map(|vout| OutPoint::new(funding.id(), vout))
+            .collect();
+        let outputs = create_outputs(
+            &out_points,
+            &[(&alice, false), (&bob, false)],
+            &[receiver.address()],
+        )
+        .unwrap();
+
+        let spend = |inputs: usize| {
+            let mut tx = out_points[..inputs]
+                .iter()
+                .fold(TransactionBuilder::new(), |builder, out_point| {
+                    builder.add_input(*out_point, 0xffff_fffd)
+                })
+                .add_output(outputs[0].clone(), 90_000)
+                .build();
+            // <sig> <key> OP_1 OP_DROP, the key is not the last push
+            let key = alice.point.compressed_sec();
+            tx.inputs[0].script_sig.content =
+                [&[0x47][..], &[0x30; 71], &[0x21], &key, &[0x51, 0x75]].concat();
+            tx.inputs[1].witness = vec![vec![0x30; 71], bob.point.compressed_sec().to_vec()];
+            tx
+        };
+        let found = receiver
+            .scan_transaction(&spend(2), &funding.outputs[..2])
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].amount, 90_000);
+
+        // an input spending a segwit v2 output, contributing no key, skips the transaction
+        assert!(receiver
+            .scan_transaction(&spend(3), &funding.outputs)
+            .unwrap()
+            .is_empty());
+    }
+}
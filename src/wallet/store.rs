@@ -0,0 +1,674 @@
+mod consolidation;
+mod labels;
+mod rescan;
+mod watch;
+
+use std::collections::HashMap;
+
+use crate::block::BlockHash;
+use crate::headers::ChainEvent;
+use crate::storage::{Storage, StorageError};
+use crate::transaction::{ChainBackend, OutPoint, ScriptPubKey, Transaction, TxHash, TxOutput};
+pub use consolidation::ConsolidationPlan;
+use failure::Error;
+pub use labels::{Label, LabelError, LabelRef};
+pub use rescan::{RescanOutcome, RescanProgress};
+
+/// Tip height and network of a stored wallet
+const WALLET_INFO: &[u8] = b"Wi";
+/// Big endian insertion index -> block height and hash if confirmed, transaction
+const WALLET_TX: &[u8] = b"Wx";
+/// Big endian index -> script pubkey
+const WALLET_SCRIPT: &[u8] = b"Ws";
+/// Big endian index -> watch only script pubkey
+const WALLET_WATCHED: &[u8] = b"Ww";
+/// Big endian index -> BIP329 record
+const WALLET_LABEL: &[u8] = b"Wl";
+/// Height an interrupted rescan resumes from
+const WALLET_RESCAN: &[u8] = b"Wr";
+
+/// Block a transaction was confirmed in
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BlockRef {
+    pub height: u32,
+    pub hash: BlockHash,
+}
+impl Copy for BlockRef {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TxStatus {
+    Unconfirmed,
+    Confirmed {
+        confirmations: u32,
+        block: BlockRef,
+    },
+    /// One of its inputs is spent by another confirmed transaction, it can never confirm
+    Conflicted,
+}
+impl Copy for TxStatus {}
+
+impl TxStatus {
+    /// Status of a transaction confirmed in `block`, or not at all, with the chain at
+    /// `tip_height`. A block above the tip is not counted, the tip is behind.
+    pub fn from_block(block: Option<BlockRef>, tip_height: u32) -> Self {
+        match block {
+            Some(block) if block.height <= tip_height => TxStatus::Confirmed {
+                confirmations: tip_height - block.height + 1,
+                block,
+            },
+            _ => TxStatus::Unconfirmed,
+        }
+    }
+
+    pub fn confirmations(&self) -> u32 {
+        match self {
+            TxStatus::Confirmed { confirmations, .. } => *confirmations,
+            _ => 0,
+        }
+    }
+}
+
+/// An output the wallet can spend
+#[derive(Debug, Clone, PartialEq)]
+pub struct Utxo {
+    pub out_point: OutPoint,
+    pub output: TxOutput,
+    pub confirmations: u32,
+}
+
+struct StoredTx {
+    tx: Transaction,
+    block: Option<BlockRef>,
+}
+
+/// Wallet transactions and where they sit in the best chain
+pub struct WalletStore {
+    testnet: bool,
+    tip_height: u32,
+    txs: HashMap<TxHash, StoredTx>,
+    order: Vec<TxHash>,
+    /// Every stored transaction spending an outpoint, more than one is a double spend
+    spends: HashMap<OutPoint, Vec<TxHash>>,
+    /// Scripts the wallet holds keys for, the first one receives consolidations
+    scripts: Vec<ScriptPubKey>,
+    /// Outputs of contracts the wallet follows without holding their keys
+    watched: Vec<ScriptPubKey>,
+    labels: Vec<Label>,
+    rescan_checkpoint: Option<u32>,
+}
+
+impl WalletStore {
+    pub fn new(testnet: bool) -> Self {
+        WalletStore {
+            testnet,
+            tip_height: 0,
+            txs: HashMap::new(),
+            order: Vec::new(),
+            spends: HashMap::new(),
+            scripts: Vec::new(),
+            watched: Vec::new(),
+            labels: Vec::new(),
+            rescan_checkpoint: None,
+        }
+    }
+
+    pub fn tip_height(&self) -> u32 {
+        self.tip_height
+    }
+
+    /// Track `tx`, unconfirmed until the next sync
+    pub fn insert(&mut self, tx: Transaction) -> TxHash {
+        let tx_id = tx.id();
+        if !self.txs.contains_key(&tx_id) {
+            self.order.push(tx_id);
+            for input in &tx.inputs {
+                self.spends
+                    .entry(input.out_point())
+                    .or_insert_with(Vec::new)
+                    .push(tx_id);
+            }
+        }
+        self.txs.insert(tx_id, StoredTx { tx, block: None });
+        tx_id
+    }
+
+    /// Count outputs paying `script_pub_key` as the wallet's own
+    pub fn add_script(&mut self, script_pub_key: ScriptPubKey) {
+        if !self.scripts.contains(&script_pub_key) {
+            self.scripts.push(script_pub_key);
+        }
+    }
+
+    pub fn is_mine(&self, script_pub_key: &ScriptPubKey) -> bool {
+        self.scripts.contains(script_pub_key)
+    }
+
+    pub fn get(&self, tx_id: &TxHash) -> Option<&Transaction> {
+        self.txs.get(tx_id).map(|stored| &stored.tx)
+    }
+
+    /// Mark `tx_id` confirmed in `block`, for callers that learn confirmations from blocks
+    pub fn confirm(&mut self, tx_id: &TxHash, block: BlockRef) {
+        if let Some(stored) = self.txs.get_mut(tx_id) {
+            stored.block = Some(block);
+        }
+        if block.height > self.tip_height {
+            self.tip_height = block.height;
+        }
+    }
+
+    /// Roll back every transaction confirmed in a block that left the best chain,
+    /// returns the transactions that became unconfirmed again
+    pub fn disconnect_block(&mut self, block_hash: &BlockHash) -> Vec<TxHash> {
+        let mut rolled_back = Vec::new();
+        for tx_id in &self.order {
+            let stored = self.txs.get_mut(tx_id).unwrap();
+            if stored.block.map(|b| b.hash) == Some(*block_hash) {
+                stored.block = None;
+                rolled_back.push(*tx_id);
+            }
+        }
+        rolled_back
+    }
+
+    /// The wallet saved in `storage`, None if there is none
+    pub fn load<S: Storage>(storage: &S) -> Result<Option<Self>, StorageError> {
+        let info = match storage.get(WALLET_INFO)? {
+            Some(info) if info.len() == 5 => info,
+            Some(_) => return Err(StorageError::Corrupt),
+            None => return Ok(None),
+        };
+        let mut tip_height = [0u8; 4];
+        tip_height.copy_from_slice(&info[..4]);
+        let mut store = WalletStore::new(info[4] == 1);
+        store.tip_height = u32::from_le_bytes(tip_height);
+
+        for (_, value) in storage.scan_prefix(WALLET_TX)? {
+            let (block, rest) = match value.split_first() {
+                Some((0, rest)) => (None, rest),
+                Some((1, rest)) if rest.len() >= 36 => {
+                    let mut height = [0u8; 4];
+                    height.copy_from_slice(&rest[..4]);
+                    let hash = BlockHash::parse(&rest[4..36])
+                        .map_err(|_| StorageError::Corrupt)?
+                        .1;
+                    let block = BlockRef {
+                        height: u32::from_le_bytes(height),
+                        hash,
+                    };
+                    (Some(block), &rest[36..])
+                }
+                _ => return Err(StorageError::Corrupt),
+            };
+            let tx = match Transaction::parse(rest) {
+                Ok((left, tx)) if left.is_empty() => tx,
+                _ => return Err(StorageError::Corrupt),
+            };
+            let tx_id = store.insert(tx);
+            store.txs.get_mut(&tx_id).unwrap().block = block;
+        }
+        for (_, script) in storage.scan_prefix(WALLET_SCRIPT)? {
+            store.add_script(ScriptPubKey { content: script });
+        }
+        for (_, script) in storage.scan_prefix(WALLET_WATCHED)? {
+            store.watch_script_pubkey(ScriptPubKey { content: script });
+        }
+        for (_, record) in storage.scan_prefix(WALLET_LABEL)? {
+            let record = String::from_utf8(record).map_err(|_| StorageError::Corrupt)?;
+            match Label::from_json(&record, 1) {
+                Ok(Some(label)) => store.set_label(label),
+                _ => return Err(StorageError::Corrupt),
+            }
+        }
+        store.rescan_checkpoint = match storage.get(WALLET_RESCAN)? {
+            Some(height) if height.len() == 4 => {
+                let mut bytes = [0u8; 4];
+                bytes.copy_from_slice(&height);
+                Some(u32::from_le_bytes(bytes))
+            }
+            Some(_) => return Err(StorageError::Corrupt),
+            None => None,
+        };
+        Ok(Some(store))
+    }
+
+    /// Write every transaction with its confirmation, the scripts, labels, the tip and
+    /// the checkpoint of an unfinished rescan
+    pub fn save<S: Storage>(&self, storage: &mut S) -> Result<(), StorageError> {
+        let mut info = self.tip_height.to_le_bytes().to_vec();
+        info.push(self.testnet as u8);
+        storage.put(WALLET_INFO, &info)?;
+        for (index, tx_id) in self.order.iter().enumerate() {
+            let stored = &self.txs[tx_id];
+            let mut value = match stored.block {
+                Some(block) => [
+                    &[1u8][..],
+                    &block.height.to_le_bytes(),
+                    &block.hash.to_little_endian(),
+                ]
+                .concat(),
+                None => vec![0u8],
+            };
+            value.extend_from_slice(&stored.tx.serialize());
+            let key = [WALLET_TX, &(index as u32).to_be_bytes()].concat();
+            storage.put(&key, &value)?;
+        }
+        for (index, script) in self.scripts.iter().enumerate() {
+            let key = [WALLET_SCRIPT, &(index as u32).to_be_bytes()].concat();
+            storage.put(&key, &script.content)?;
+        }
+        for (index, script) in self.watched.iter().enumerate() {
+            let key = [WALLET_WATCHED, &(index as u32).to_be_bytes()].concat();
+            storage.put(&key, &script.content)?;
+        }
+        for (index, label) in self.labels.iter().enumerate() {
+            let key = [WALLET_LABEL, &(index as u32).to_be_bytes()].concat();
+            storage.put(&key, label.to_json().as_bytes())?;
+        }
+        match self.rescan_checkpoint {
+            Some(height) => storage.put(WALLET_RESCAN, &height.to_le_bytes())?,
+            None => storage.delete(WALLET_RESCAN)?,
+        }
+        Ok(())
+    }
+
+    /// Follow a `HeaderChain`: the tip moves with every event and a disconnected block
+    /// rolls back its confirmations, returns the transactions that became unconfirmed
+    pub fn apply_chain_event(&mut self, event: &ChainEvent) -> Vec<TxHash> {
+        match *event {
+            ChainEvent::Connected { height, .. } => {
+                self.tip_height = height;
+                vec![]
+            }
+            ChainEvent::Disconnected { height, hash } => {
+                self.tip_height = height.saturating_sub(1);
+                self.disconnect_block(&hash)
+            }
+        }
+    }
+
+    /// Refresh the tip and every confirmation from `backend`. A confirmed transaction whose
+    /// block hash is no longer in the best chain at its height is rolled back first.
+    pub fn sync<B: ChainBackend>(&mut self, backend: &mut B) -> Result<Vec<TxHash>, Error> {
+        let tip_height = backend.tip_height(self.testnet)?;
+
+        let mut stale_blocks = Vec::new();
+        for stored in self.txs.values() {
+            if let Some(block) = stored.block {
+                if stale_blocks.contains(&block.hash) {
+                    continue;
+                }
+                if block.height > tip_height
+                    || backend.block_hash(block.height, self.testnet)? != block.hash
+                {
+                    stale_blocks.push(block.hash);
+                }
+            }
+        }
+        let mut rolled_back = Vec::new();
+        for block_hash in &stale_blocks {
+            rolled_back.extend(self.disconnect_block(block_hash));
+        }
+
+        for tx_id in &self.order {
+            let stored = self.txs.get_mut(tx_id).unwrap();
+            if stored.block.is_none() {
+                stored.block = backend
+                    .tx_confirmation(*tx_id, self.testnet)?
+                    .map(|(height, hash)| BlockRef { height, hash });
+            }
+        }
+
+        self.tip_height = tip_height;
+        Ok(rolled_back)
+    }
+
+    /// Other stored transactions spending an outpoint `tx_id` spends, in insertion order
+    pub fn conflicts_of(&self, tx_id: &TxHash) -> Vec<TxHash> {
+        let stored = match self.txs.get(tx_id) {
+            Some(stored) => stored,
+            None => return vec![],
+        };
+        let mut conflicts: Vec<TxHash> = Vec::new();
+        for input in &stored.tx.inputs {
+            for other in &self.spends[&input.out_point()] {
+                if other != tx_id && !conflicts.contains(other) {
+                    conflicts.push(*other);
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Lost a double spend or spends an output of a transaction that did. An unconfirmed
+    /// transaction loses to a confirmed conflict, else to a higher fee rate when every fee
+    /// is known, else to the conflict seen last since replacements come after what they
+    /// replace.
+    fn is_conflicted(&self, tx_id: &TxHash) -> bool {
+        let stored = &self.txs[tx_id];
+        if stored.block.is_some() {
+            return false;
+        }
+        let conflicts = self.conflicts_of(tx_id);
+        if !conflicts.is_empty() && self.conflict_winner(tx_id, &conflicts) != *tx_id {
+            return true;
+        }
+        stored.tx.inputs.iter().any(|input| {
+            let parent = input.out_point().txid;
+            self.txs.contains_key(&parent) && self.is_conflicted(&parent)
+        })
+    }
+
+    fn conflict_winner(&self, tx_id: &TxHash, conflicts: &[TxHash]) -> TxHash {
+        let candidates: Vec<TxHash> = std::iter::once(*tx_id)
+            .chain(conflicts.iter().cloned())
+            .collect();
+        if let Some(confirmed) = candidates.iter().find(|id| self.txs[id].block.is_some()) {
+            return *confirmed;
+        }
+        let seen = |id: &TxHash| self.order.iter().position(|o| o == id);
+        let fees: Option<Vec<(u64, usize)>> = candidates.iter().map(|id| self.fee(id)).collect();
+        match fees {
+            Some(fees) => {
+                let mut best = 0;
+                for (i, &(fee, vsize)) in fees.iter().enumerate() {
+                    let (best_fee, best_vsize) = fees[best];
+                    // fee / vsize > best_fee / best_vsize, ties go to the later one
+                    let (rate, best_rate) = (fee * best_vsize as u64, best_fee * vsize as u64);
+                    if rate > best_rate
+                        || rate == best_rate && seen(&candidates[i]) > seen(&candidates[best])
+                    {
+                        best = i;
+                    }
+                }
+                candidates[best]
+            }
+            None => *candidates.iter().max_by_key(|id| seen(id)).unwrap(),
+        }
+    }
+
+    /// Fee and virtual size of `tx_id`, None unless every spent output is stored
+    fn fee(&self, tx_id: &TxHash) -> Option<(u64, usize)> {
+        let tx = &self.txs.get(tx_id)?.tx;
+        let mut input_total = 0u64;
+        for input in &tx.inputs {
+            let out_point = input.out_point();
+            let parent = &self.txs.get(&out_point.txid)?.tx;
+            input_total += u64::from(parent.outputs.get(out_point.vout as usize)?.amount);
+        }
+        let output_total: u64 = tx.outputs.iter().map(|o| u64::from(o.amount)).sum();
+        Some((input_total.checked_sub(output_total)?, tx.vsize()))
+    }
+
+    pub fn status(&self, tx_id: &TxHash) -> Option<TxStatus> {
+        let stored = self.txs.get(tx_id)?;
+        let status = match stored.block {
+            None if self.is_conflicted(tx_id) => TxStatus::Conflicted,
+            block => TxStatus::from_block(block, self.tip_height),
+        };
+        Some(status)
+    }
+
+    /// Outputs paying the wallet that no stored transaction spends, conflicted
+    /// transactions neither create nor spend any
+    pub fn utxos(&self) -> Vec<Utxo> {
+        self.unspent(|script_pub_key| self.is_mine(script_pub_key))
+    }
+
+    fn unspent<F: Fn(&ScriptPubKey) -> bool>(&self, owned: F) -> Vec<Utxo> {
+        let live: Vec<(&TxHash, TxStatus)> = self
+            .order
+            .iter()
+            .map(|tx_id| (tx_id, self.status(tx_id).unwrap()))
+            .filter(|(_, status)| *status != TxStatus::Conflicted)
+            .collect();
+        let spent: Vec<OutPoint> = live
+            .iter()
+            .flat_map(|(tx_id, _)| self.txs[*tx_id].tx.inputs.iter().map(|i| i.out_point()))
+            .collect();
+
+        let mut utxos = Vec::new();
+        for (tx_id, status) in live {
+            for (vout, output) in self.txs[tx_id].tx.outputs.iter().enumerate() {
+                let out_point = OutPoint::new(*tx_id, vout as u32);
+                if owned(&output.script_pub_key) && !spent.contains(&out_point) {
+                    utxos.push(Utxo {
+                        out_point,
+                        output: output.clone(),
+                        confirmations: status.confirmations(),
+                    });
+                }
+            }
+        }
+        utxos
+    }
+
+    /// Every transaction with its status, unconfirmed ones first then the newest confirmations
+    pub fn history(&self) -> Vec<(TxHash, TxStatus)> {
+        let mut history: Vec<(TxHash, TxStatus)> = self
+            .order
+            .iter()
+            .map(|tx_id| (*tx_id, self.status(tx_id).unwrap()))
+            .collect();
+        // stable sort keeps the insertion order inside the same height
+        history.sort_by_key(|(_, status)| match status {
+            TxStatus::Confirmed { block, .. } => u64::from(u32::max_value() - block.height) + 1,
+            _ => 0,
+        });
+        history
+    }
+}
+
+mod test {
+    use super::{BlockRef, Label, LabelRef, TxStatus, WalletStore};
+    use crate::block::BlockHash;
+    use crate::headers::ChainEvent;
+    use crate::transaction::{
+        ChainBackend, PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxHash, TxInput,
+        TxInputSequence, TxLocktime, TxOutput, TxVersion,
+    };
+    use failure::Error;
+    use std::collections::HashMap;
+
+    struct MockBackend {
+        chain: Vec<BlockHash>,
+        confirmations: HashMap<TxHash, u32>,
+    }
+
+    impl ChainBackend for MockBackend {
+        fn tip_height(&mut self, _testnet: bool) -> Result<u32, Error> {
+            Ok(self.chain.len() as u32 - 1)
+        }
+
+        fn block_hash(&mut self, height: u32, _testnet: bool) -> Result<BlockHash, Error> {
+            Ok(self.chain[height as usize])
+        }
+
+        fn tx_confirmation(
+            &mut self,
+            tx_id: TxHash,
+            _testnet: bool,
+        ) -> Result<Option<(u32, BlockHash)>, Error> {
+            Ok(self
+                .confirmations
+                .get(&tx_id)
+                .map(|height| (*height, self.chain[*height as usize])))
+        }
+    }
+
+    fn block_hash(n: u8) -> BlockHash {
+        BlockHash::parse(&[n; 32]).unwrap().1
+    }
+
+    fn tx(index: &str, locktime: &str) -> Transaction {
+        let data = hex::decode(format!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1{}6b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac{}", index, locktime)).unwrap();
+        Transaction::parse(&data).unwrap().1
+    }
+
+    #[test]
+    fn test_history_confirmations() {
+        let mut store = WalletStore::new(false);
+        let confirmed = store.insert(tx("00000000", "19430600"));
+        let pending = store.insert(tx("01000000", "00000000"));
+
+        let mut backend = MockBackend {
+            chain: (0..5).map(block_hash).collect(),
+            confirmations: vec![(confirmed, 2)].into_iter().collect(),
+        };
+        store.sync(&mut backend).unwrap();
+
+        let history = store.history();
+        assert_eq!(history[0], (pending, TxStatus::Unconfirmed));
+        assert_eq!(history[1].0, confirmed);
+        assert_eq!(history[1].1.confirmations(), 3);
+        assert_eq!(
+            store.status(&confirmed),
+            Some(TxStatus::Confirmed {
+                confirmations: 3,
+                block: BlockRef {
+                    height: 2,
+                    hash: block_hash(2)
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_conflicted_and_reorg() {
+        let mut store = WalletStore::new(false);
+        // both spend the same outpoint
+        let a = store.insert(tx("00000000", "19430600"));
+        let b = store.insert(tx("00000000", "00000000"));
+
+        let mut backend = MockBackend {
+            chain: (0..5).map(block_hash).collect(),
+            confirmations: vec![(a, 3)].into_iter().collect(),
+        };
+        store.sync(&mut backend).unwrap();
+        assert_eq!(store.status(&b), Some(TxStatus::Conflicted));
+
+        // block 3 is reorged out and the competing transaction confirms instead
+        backend.chain[3] = block_hash(33);
+        backend.chain.push(block_hash(5));
+        backend.confirmations = vec![(b, 4)].into_iter().collect();
+        let rolled_back = store.sync(&mut backend).unwrap();
+
+        assert_eq!(rolled_back, vec![a]);
+        assert_eq!(store.status(&a), Some(TxStatus::Conflicted));
+        assert_eq!(store.status(&b).unwrap().confirmations(), 2);
+    }
+
+    fn spend(txid: TxHash, vout: u32, amount: u64) -> Transaction {
+        Transaction::new(
+            TxVersion::new(2),
+            vec![TxInput::new(
+                txid,
+                PreTxIndex::new(vout),
+                ScriptSig { content: vec![] },
+                TxInputSequence::new(0xffff_fffd),
+            )],
+            vec![TxOutput {
+                amount: amount.into(),
+                script_pub_key: ScriptPubKey {
+                    content: vec![0x51],
+                },
+            }],
+            TxLocktime::new(0),
+            false,
+        )
+    }
+
+    #[test]
+    fn test_double_spend() {
+        let mut store = WalletStore::new(false);
+        let outside = TxHash::new(&[7; 32]).unwrap().1;
+        let parent = store.insert(spend(outside, 0, 10_000));
+        // the higher fee rate wins over the one seen last
+        let replacement = store.insert(spend(parent, 0, 8_000));
+        let original = store.insert(spend(parent, 0, 9_000));
+        let child = store.insert(spend(original, 0, 8_500));
+
+        assert_eq!(store.conflicts_of(&original), vec![replacement]);
+        assert!(store.conflicts_of(&child).is_empty());
+        assert_eq!(store.status(&replacement), Some(TxStatus::Unconfirmed));
+        assert_eq!(store.status(&original), Some(TxStatus::Conflicted));
+        assert_eq!(store.status(&child), Some(TxStatus::Conflicted));
+        // fees of spends of unknown outputs are unknown, the one seen last wins
+        assert_eq!(store.status(&parent), Some(TxStatus::Unconfirmed));
+        let other = store.insert(spend(outside, 0, 9_999));
+        assert_eq!(store.status(&parent), Some(TxStatus::Conflicted));
+        assert_eq!(store.status(&replacement), Some(TxStatus::Conflicted));
+
+        // a confirmation beats everything else
+        let block = |height| BlockRef {
+            height,
+            hash: block_hash(height as u8),
+        };
+        store.confirm(&parent, block(1));
+        store.confirm(&original, block(2));
+        assert_eq!(store.status(&other), Some(TxStatus::Conflicted));
+        assert_eq!(store.status(&replacement), Some(TxStatus::Conflicted));
+        assert_eq!(store.status(&child), Some(TxStatus::Unconfirmed));
+    }
+
+    #[test]
+    fn test_apply_chain_event() {
+        let mut store = WalletStore::new(false);
+        let a = store.insert(tx("00000000", "19430600"));
+        for height in 1..4 {
+            store.apply_chain_event(&ChainEvent::Connected {
+                height,
+                hash: block_hash(height as u8),
+            });
+        }
+        store.confirm(
+            &a,
+            BlockRef {
+                height: 3,
+                hash: block_hash(3),
+            },
+        );
+        assert_eq!(store.status(&a).unwrap().confirmations(), 1);
+
+        let rolled_back = store.apply_chain_event(&ChainEvent::Disconnected {
+            height: 3,
+            hash: block_hash(3),
+        });
+        assert_eq!(rolled_back, vec![a]);
+        assert_eq!(store.tip_height(), 2);
+        assert_eq!(store.status(&a), Some(TxStatus::Unconfirmed));
+    }
+
+    #[test]
+    fn test_save_load() {
+        use crate::storage::MemoryStorage;
+
+        let mut store = WalletStore::new(true);
+        let a = store.insert(tx("00000000", "19430600"));
+        let b = store.insert(tx("01000000", "00000000"));
+        store.confirm(
+            &a,
+            BlockRef {
+                height: 7,
+                hash: block_hash(7),
+            },
+        );
+        let script = store.get(&a).unwrap().outputs[0].script_pub_key.clone();
+        store.add_script(script);
+        store.set_label(Label::new(LabelRef::Tx(b), "rent"));
+
+        let mut storage = MemoryStorage::new();
+        assert!(WalletStore::load(&storage).unwrap().is_none());
+        store.save(&mut storage).unwrap();
+        let loaded = WalletStore::load(&storage).unwrap().unwrap();
+        assert_eq!(loaded.tip_height(), 7);
+        assert_eq!(loaded.history(), store.history());
+        assert_eq!(loaded.status(&b), Some(TxStatus::Unconfirmed));
+        assert_eq!(loaded.utxos(), store.utxos());
+        assert_eq!(loaded.utxos().len(), 2);
+        assert_eq!(loaded.labels(), store.labels());
+        assert!(loaded.is_mine(&ScriptPubKey {
+            content: hex::decode("76a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac").unwrap()
+        }));
+    }
+}
@@ -0,0 +1,159 @@
+use super::{Utxo, WalletStore};
+use crate::transaction::{BuilderOptions, InputWeight, Transaction, TransactionBuilder, TxOutput};
+
+fn fee_for(weight: usize, fee_rate: f64) -> u64 {
+    ((weight as f64 / 4.0) * fee_rate).ceil() as u64
+}
+
+/// A self spend merging small outputs into one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsolidationPlan {
+    /// Unsigned, inputs in the order of `inputs`
+    pub tx: Transaction,
+    pub inputs: Vec<Utxo>,
+    pub input_total: u64,
+    pub fee: u64,
+    /// Expected size once signed
+    pub vsize: usize,
+    /// The single output left after the consolidation
+    pub output: TxOutput,
+}
+
+impl WalletStore {
+    /// Merge up to `max_inputs` of the smallest confirmed outputs into one paying the
+    /// wallet's first script, at `fee_rate` sat/vB. Outputs costing more to spend than
+    /// they hold are left out, and None is returned unless at least two are worth it.
+    pub fn plan_consolidation(
+        &self,
+        fee_rate: f64,
+        max_inputs: usize,
+    ) -> Option<ConsolidationPlan> {
+        let destination = self.scripts.first()?.clone();
+
+        let mut candidates: Vec<(Utxo, InputWeight)> = self
+            .utxos()
+            .into_iter()
+            .filter(|utxo| utxo.confirmations > 0)
+            .filter_map(|utxo| {
+                let weight =
+                    InputWeight::for_script_type(utxo.output.script_pub_key.script_type())?;
+                if u64::from(utxo.output.amount) > fee_for(weight.weight(), fee_rate) {
+                    Some((utxo, weight))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        candidates.sort_by_key(|(utxo, _)| u64::from(utxo.output.amount));
+        candidates.truncate(max_inputs);
+        if candidates.len() < 2 {
+            return None;
+        }
+
+        let mut builder = TransactionBuilder::new().testnet(self.testnet);
+        if self.tip_height > 0 {
+            builder = builder.tip_height(self.tip_height);
+        }
+        let builder = candidates.iter().fold(builder, |builder, (utxo, weight)| {
+            builder.add_weighted_input(utxo.out_point, 0xffff_fffd, *weight)
+        });
+        let weight = builder
+            .clone()
+            .add_output(destination.clone(), 0)
+            .estimated_weight()?;
+        let fee = fee_for(weight, fee_rate);
+        let input_total: u64 = candidates
+            .iter()
+            .map(|(utxo, _)| u64::from(utxo.output.amount))
+            .sum();
+        let amount = input_total.checked_sub(fee)?;
+        if amount < BuilderOptions::default().dust_limit {
+            return None;
+        }
+
+        let inputs: Vec<Utxo> = candidates.into_iter().map(|(utxo, _)| utxo).collect();
+        let tx = builder.add_output(destination, amount).build();
+        Some(ConsolidationPlan {
+            output: tx.outputs[0].clone(),
+            tx,
+            inputs,
+            input_total,
+            fee,
+            vsize: (weight + 3) / 4,
+        })
+    }
+}
+
+mod test {
+    use super::super::{BlockRef, WalletStore};
+    use crate::block::BlockHash;
+    use crate::transaction::{OutPoint, ScriptPubKey, TransactionBuilder, TxHash};
+
+    fn script(byte: u8) -> ScriptPubKey {
+        let mut content = vec![0x00, 0x14];
+        content.extend_from_slice(&[byte; 20]);
+        ScriptPubKey { content }
+    }
+
+    #[test]
+    fn test_plan_consolidation() {
+        let mine = script(0xaa);
+        let mut store = WalletStore::new(false);
+        store.add_script(mine.clone());
+
+        let funding = TransactionBuilder::new()
+            .add_input(
+                OutPoint::new(TxHash::new(&[1; 32]).unwrap().1, 0),
+                0xffff_ffff,
+            )
+            .add_recipients(vec![
+                (mine.clone(), 5_000),
+                (mine.clone(), 500),
+                (script(0xbb), 1_000_000),
+                (mine.clone(), 20_000),
+                (mine.clone(), 3_000),
+                (mine.clone(), 8_000),
+            ])
+            .build();
+        let tx_id = store.insert(funding);
+        let unconfirmed = TransactionBuilder::new()
+            .add_input(
+                OutPoint::new(TxHash::new(&[2; 32]).unwrap().1, 0),
+                0xffff_ffff,
+            )
+            .add_output(mine.clone(), 1_000)
+            .build();
+        store.insert(unconfirmed);
+        store.confirm(
+            &tx_id,
+            BlockRef {
+                height: 100,
+                hash: BlockHash::parse(&[3; 32]).unwrap().1,
+            },
+        );
+
+        // 680 sats to spend an input, the 500 one is not worth it
+        let plan = store.plan_consolidation(10.0, 3).unwrap();
+        let amounts: Vec<u64> = plan
+            .inputs
+            .iter()
+            .map(|utxo| u64::from(utxo.output.amount))
+            .collect();
+        assert_eq!(amounts, vec![3_000, 5_000, 8_000]);
+        assert_eq!(plan.input_total, 16_000);
+        // 10.5 vB overhead with the marker, three 68 vB inputs, a 31 bytes output
+        assert_eq!(plan.vsize, 246);
+        assert_eq!(plan.fee, 2_455);
+        assert_eq!(u64::from(plan.output.amount), 16_000 - 2_455);
+        assert_eq!(plan.output.script_pub_key, mine);
+        assert_eq!(plan.tx.inputs.len(), 3);
+        assert_eq!(plan.tx.inputs[0].out_point(), OutPoint::new(tx_id, 4));
+        // anti fee sniping from the wallet's tip
+        let locktime = u32::from(plan.tx.locktime);
+        assert!(locktime <= 100 && locktime > 0);
+
+        assert!(store.plan_consolidation(10.0, 1).is_none());
+        // at this rate only one output pays for itself
+        assert!(store.plan_consolidation(200.0, 10).is_none());
+    }
+}
@@ -0,0 +1,253 @@
+use serde_json::json;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use super::WalletStore;
+use crate::transaction::{OutPoint, TxHash};
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum LabelError {
+    #[fail(display = "label line {} is not a JSON object", _0)]
+    InvalidJson(usize),
+    #[fail(display = "label line {} is missing `{}`", _0, _1)]
+    MissingField(usize, &'static str),
+    #[fail(display = "label line {} has an invalid ref: {}", _0, _1)]
+    InvalidRef(usize, String),
+}
+
+/// What a label is attached to, the BIP329 `type` and `ref`
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum LabelRef {
+    Tx(TxHash),
+    Addr(String),
+    PubKey(String),
+    Input(OutPoint),
+    Output(OutPoint),
+    Xpub(String),
+}
+
+impl LabelRef {
+    /// The BIP329 `type` field
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            LabelRef::Tx(_) => "tx",
+            LabelRef::Addr(_) => "addr",
+            LabelRef::PubKey(_) => "pubkey",
+            LabelRef::Input(_) => "input",
+            LabelRef::Output(_) => "output",
+            LabelRef::Xpub(_) => "xpub",
+        }
+    }
+
+    /// From a BIP329 `type` and `ref`, Ok(None) for a type this crate does not know
+    fn parse(type_name: &str, reference: &str) -> Result<Option<Self>, ()> {
+        let label_ref = match type_name {
+            "tx" => LabelRef::Tx(TxHash::from_str(reference).map_err(|_| ())?),
+            "addr" => LabelRef::Addr(reference.to_string()),
+            "pubkey" => LabelRef::PubKey(reference.to_string()),
+            "input" => LabelRef::Input(parse_out_point(reference).ok_or(())?),
+            "output" => LabelRef::Output(parse_out_point(reference).ok_or(())?),
+            "xpub" => LabelRef::Xpub(reference.to_string()),
+            _ => return Ok(None),
+        };
+        Ok(Some(label_ref))
+    }
+}
+
+/// The BIP329 `ref`, `txid:vout` for inputs and outputs
+impl Display for LabelRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LabelRef::Tx(txid) => write!(f, "{}", txid),
+            LabelRef::Input(out_point) | LabelRef::Output(out_point) => {
+                write!(f, "{}", out_point)
+            }
+            LabelRef::Addr(s) | LabelRef::PubKey(s) | LabelRef::Xpub(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+fn parse_out_point(s: &str) -> Option<OutPoint> {
+    let mut parts = s.rsplitn(2, ':');
+    let vout = parts.next()?.parse().ok()?;
+    let txid = TxHash::from_str(parts.next()?).ok()?;
+    Some(OutPoint::new(txid, vout))
+}
+
+/// A user label, one BIP329 record
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Label {
+    pub reference: LabelRef,
+    pub label: String,
+    /// Key origin of an address or script descriptor, `wpkh([d34db33f/84'/0'/0'])`
+    pub origin: Option<String>,
+    /// For outputs, whether coin selection may use it
+    pub spendable: Option<bool>,
+}
+
+impl Label {
+    pub fn new(reference: LabelRef, label: &str) -> Self {
+        Label {
+            reference,
+            label: label.to_string(),
+            origin: None,
+            spendable: None,
+        }
+    }
+
+    /// One BIP329 JSON line
+    pub fn to_json(&self) -> String {
+        let mut record = json!({
+            "type": self.reference.type_name(),
+            "ref": self.reference.to_string(),
+            "label": self.label,
+        });
+        if let Some(origin) = &self.origin {
+            record["origin"] = json!(origin);
+        }
+        if let Some(spendable) = self.spendable {
+            record["spendable"] = json!(spendable);
+        }
+        record.to_string()
+    }
+
+    /// A BIP329 JSON line numbered `line` for errors, Ok(None) for an unknown type
+    pub fn from_json(json: &str, line: usize) -> Result<Option<Self>, LabelError> {
+        let record: serde_json::Value =
+            serde_json::from_str(json).map_err(|_| LabelError::InvalidJson(line))?;
+        if !record.is_object() {
+            return Err(LabelError::InvalidJson(line));
+        }
+        let field = |name: &'static str| {
+            record[name]
+                .as_str()
+                .ok_or(LabelError::MissingField(line, name))
+        };
+        let reference = field("ref")?;
+        let reference = match LabelRef::parse(field("type")?, reference) {
+            Ok(Some(reference)) => reference,
+            Ok(None) => return Ok(None),
+            Err(()) => return Err(LabelError::InvalidRef(line, reference.to_string())),
+        };
+        Ok(Some(Label {
+            reference,
+            // BIP329 lets a record carry only `spendable`
+            label: record["label"].as_str().unwrap_or_default().to_string(),
+            origin: record["origin"].as_str().map(|s| s.to_string()),
+            spendable: record["spendable"].as_bool(),
+        }))
+    }
+}
+
+impl WalletStore {
+    /// Attach `label` to what it refers to, replacing an earlier label of the same thing
+    pub fn set_label(&mut self, label: Label) {
+        match self
+            .labels
+            .iter_mut()
+            .find(|l| l.reference == label.reference)
+        {
+            Some(existing) => *existing = label,
+            None => self.labels.push(label),
+        }
+    }
+
+    pub fn label(&self, reference: &LabelRef) -> Option<&Label> {
+        self.labels.iter().find(|l| l.reference == *reference)
+    }
+
+    pub fn remove_label(&mut self, reference: &LabelRef) -> Option<Label> {
+        let index = self.labels.iter().position(|l| l.reference == *reference)?;
+        Some(self.labels.remove(index))
+    }
+
+    /// Every label in the order they were first set
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    /// BIP329 export, one JSON record per line
+    pub fn export_labels(&self) -> String {
+        self.labels
+            .iter()
+            .map(|label| label.to_json() + "\n")
+            .collect()
+    }
+
+    /// BIP329 import, a record replaces a label of the same thing and records of unknown
+    /// types are skipped. Returns how many were imported, nothing is imported on error.
+    pub fn import_labels(&mut self, jsonl: &str) -> Result<usize, LabelError> {
+        let labels = jsonl
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| Label::from_json(line, i + 1))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut count = 0;
+        for label in labels.into_iter().flatten() {
+            self.set_label(label);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+mod test {
+    use super::{Label, LabelError, LabelRef};
+    use crate::transaction::{OutPoint, TxHash};
+    use crate::wallet::WalletStore;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_labels() {
+        let txid =
+            TxHash::from_str("f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd")
+                .unwrap();
+        let mut store = WalletStore::new(false);
+        store.set_label(Label::new(LabelRef::Tx(txid), "Transaction"));
+        store.set_label(Label {
+            origin: Some("wpkh([d34db33f/84'/0'/0'])".to_string()),
+            ..Label::new(
+                LabelRef::Addr("bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c".to_string()),
+                "Address",
+            )
+        });
+        store.set_label(Label {
+            spendable: Some(false),
+            ..Label::new(LabelRef::Output(OutPoint::new(txid, 1)), "Output")
+        });
+        store.set_label(Label::new(LabelRef::Tx(txid), "Renamed"));
+
+        assert_eq!(store.labels().len(), 3);
+        assert_eq!(store.label(&LabelRef::Tx(txid)).unwrap().label, "Renamed");
+
+        let exported = store.export_labels();
+        assert_eq!(
+            exported.lines().nth(2).unwrap(),
+            r#"{"label":"Output","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1","spendable":false,"type":"output"}"#
+        );
+        let mut imported = WalletStore::new(false);
+        assert_eq!(imported.import_labels(&exported), Ok(3));
+        assert_eq!(imported.labels(), store.labels());
+
+        // unknown types are skipped, a bad ref fails the whole import
+        assert_eq!(
+            imported.import_labels(r#"{"type":"future","ref":"x","label":"y"}"#),
+            Ok(0)
+        );
+        assert_eq!(
+            imported.import_labels(
+                "{\"type\":\"tx\",\"ref\":\"00\",\"label\":\"a\"}\n{\"type\":\"xpub\"}"
+            ),
+            Err(LabelError::InvalidRef(1, "00".to_string()))
+        );
+        assert_eq!(
+            imported.import_labels("not json"),
+            Err(LabelError::InvalidJson(1))
+        );
+        assert!(imported
+            .remove_label(&LabelRef::Output(OutPoint::new(txid, 1)))
+            .is_some());
+        assert_eq!(imported.labels().len(), 2);
+    }
+}
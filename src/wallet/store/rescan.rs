@@ -0,0 +1,294 @@
+use failure::Error;
+
+use super::{BlockRef, WalletStore};
+use crate::cancel::CancelToken;
+use crate::transaction::{BlockBackend, Transaction, TxHash};
+
+/// Where a rescan is, handed to its callback after every block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RescanProgress {
+    /// Last block scanned
+    pub height: u32,
+    pub tip_height: u32,
+    /// Wallet transactions found so far
+    pub found: usize,
+}
+impl Copy for RescanProgress {}
+
+impl RescanProgress {
+    /// Share of the blocks up to the tip already scanned, for progress bars
+    pub fn fraction(&self, from_height: u32) -> f64 {
+        if self.tip_height < from_height {
+            return 1.0;
+        }
+        let total = self.tip_height - from_height + 1;
+        f64::from(self.height + 1 - from_height) / f64::from(total)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RescanOutcome {
+    /// Every block up to the tip was scanned
+    Completed { found: Vec<TxHash> },
+    /// The callback stopped the rescan, `resume_height` is the next block to scan
+    Cancelled {
+        found: Vec<TxHash>,
+        resume_height: u32,
+    },
+}
+
+impl WalletStore {
+    /// Walk the best chain from `from_height` to the tip, storing every transaction that
+    /// pays a wallet or watched script or spends such an output as confirmed in its block.
+    /// `progress` is called after each block and cancels the rescan by returning false.
+    /// Until the rescan completes the next height to scan is kept as a checkpoint, which
+    /// is saved with the wallet so a cancelled or failed rescan can resume.
+    pub fn rescan<B, F>(
+        &mut self,
+        from_height: u32,
+        backend: &mut B,
+        progress: F,
+    ) -> Result<RescanOutcome, Error>
+    where
+        B: BlockBackend,
+        F: FnMut(&RescanProgress) -> bool,
+    {
+        self.rescan_until(from_height, backend, &CancelToken::new(), progress)
+    }
+
+    /// `rescan` failing with the `CancelError` of `cancel` before the first block after
+    /// it is cancelled or times out, the checkpoint resumes from that block
+    pub fn rescan_until<B, F>(
+        &mut self,
+        from_height: u32,
+        backend: &mut B,
+        cancel: &CancelToken,
+        mut progress: F,
+    ) -> Result<RescanOutcome, Error>
+    where
+        B: BlockBackend,
+        F: FnMut(&RescanProgress) -> bool,
+    {
+        cancel.check()?;
+        let tip_height = backend.tip_height(self.testnet)?;
+        self.rescan_checkpoint = Some(from_height);
+
+        let mut found = Vec::new();
+        for height in from_height..=tip_height {
+            cancel.check()?;
+            let hash = backend.block_hash(height, self.testnet)?;
+            let block = backend.block(hash, self.testnet)?;
+            for tx in block.txs {
+                if self.is_relevant(&tx) {
+                    let tx_id = self.insert(tx);
+                    self.confirm(&tx_id, BlockRef { height, hash });
+                    found.push(tx_id);
+                }
+            }
+            self.rescan_checkpoint = Some(height + 1);
+
+            let keep_going = progress(&RescanProgress {
+                height,
+                tip_height,
+                found: found.len(),
+            });
+            if !keep_going && height < tip_height {
+                return Ok(RescanOutcome::Cancelled {
+                    found,
+                    resume_height: height + 1,
+                });
+            }
+        }
+
+        self.rescan_checkpoint = None;
+        self.tip_height = self.tip_height.max(tip_height);
+        Ok(RescanOutcome::Completed { found })
+    }
+
+    /// Next height of an unfinished rescan
+    pub fn rescan_checkpoint(&self) -> Option<u32> {
+        self.rescan_checkpoint
+    }
+
+    /// Continue an unfinished rescan from its checkpoint, None if there is none
+    pub fn resume_rescan<B, F>(
+        &mut self,
+        backend: &mut B,
+        progress: F,
+    ) -> Option<Result<RescanOutcome, Error>>
+    where
+        B: BlockBackend,
+        F: FnMut(&RescanProgress) -> bool,
+    {
+        let from_height = self.rescan_checkpoint?;
+        Some(self.rescan(from_height, backend, progress))
+    }
+
+    /// Pays a wallet or watched script or spends an output of a stored transaction that
+    /// does
+    fn is_relevant(&self, tx: &Transaction) -> bool {
+        let followed =
+            |script_pub_key| self.is_mine(script_pub_key) || self.is_watched(script_pub_key);
+        tx.outputs
+            .iter()
+            .any(|output| followed(&output.script_pub_key))
+            || tx.inputs.iter().any(|input| {
+                let out_point = input.out_point();
+                self.txs
+                    .get(&out_point.txid)
+                    .and_then(|stored| stored.tx.outputs.get(out_point.vout as usize))
+                    .map_or(false, |output| followed(&output.script_pub_key))
+            })
+    }
+}
+
+mod test {
+    use super::{RescanOutcome, RescanProgress};
+    use crate::block::{Block, BlockHash, BlockHeader};
+    use crate::cancel::{CancelError, CancelToken};
+    use crate::storage::MemoryStorage;
+    use crate::transaction::{
+        BlockBackend, ChainBackend, PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxHash,
+        TxInput, TxInputSequence, TxLocktime, TxOutput, TxVersion,
+    };
+    use crate::wallet::WalletStore;
+    use failure::Error;
+    use std::time::Instant;
+
+    struct MockBlocks {
+        blocks: Vec<Block>,
+    }
+
+    impl ChainBackend for MockBlocks {
+        fn tip_height(&mut self, _testnet: bool) -> Result<u32, Error> {
+            Ok(self.blocks.len() as u32 - 1)
+        }
+
+        fn block_hash(&mut self, height: u32, _testnet: bool) -> Result<BlockHash, Error> {
+            Ok(self.blocks[height as usize].hash())
+        }
+
+        fn tx_confirmation(
+            &mut self,
+            _tx_id: TxHash,
+            _testnet: bool,
+        ) -> Result<Option<(u32, BlockHash)>, Error> {
+            Ok(None)
+        }
+    }
+
+    impl BlockBackend for MockBlocks {
+        fn block(&mut self, block_hash: BlockHash, _testnet: bool) -> Result<Block, Error> {
+            Ok(self
+                .blocks
+                .iter()
+                .find(|block| block.hash() == block_hash)
+                .unwrap()
+                .clone())
+        }
+    }
+
+    fn script(byte: u8) -> ScriptPubKey {
+        let mut content = vec![0x00, 0x14];
+        content.extend_from_slice(&[byte; 20]);
+        ScriptPubKey { content }
+    }
+
+    fn spend(txid: TxHash, vout: u32, to: u8) -> Transaction {
+        Transaction::new(
+            TxVersion::new(2),
+            vec![TxInput::new(
+                txid,
+                PreTxIndex::new(vout),
+                ScriptSig { content: vec![] },
+                TxInputSequence::new(0xffff_ffff),
+            )],
+            vec![TxOutput {
+                amount: 1000.into(),
+                script_pub_key: script(to),
+            }],
+            TxLocktime::new(0),
+            false,
+        )
+    }
+
+    fn block(nonce: u32, txs: Vec<Transaction>) -> Block {
+        let header = BlockHeader {
+            version: 1,
+            prev_block: BlockHash::default(),
+            merkle_root: [0u8; 32],
+            timestamp: 0,
+            bits: 0x207f_ffff,
+            nonce,
+        };
+        Block { header, txs }
+    }
+
+    #[test]
+    fn test_rescan() {
+        let outside = TxHash::new(&[7; 32]).unwrap().1;
+        let received = spend(outside, 0, 0xaa);
+        let unrelated = spend(outside, 1, 0xbb);
+        let spent = spend(received.id(), 0, 0xcc);
+        let mut backend = MockBlocks {
+            blocks: vec![
+                block(0, vec![]),
+                block(1, vec![received.clone(), unrelated.clone()]),
+                block(2, vec![]),
+                block(3, vec![spent.clone()]),
+                block(4, vec![]),
+            ],
+        };
+        let mut store = WalletStore::new(false);
+        store.add_script(script(0xaa));
+
+        // cancelled after block 2, the checkpoint survives a save
+        let mut seen = Vec::new();
+        let outcome = store
+            .rescan(1, &mut backend, |progress: &RescanProgress| {
+                seen.push(progress.fraction(1));
+                progress.height < 2
+            })
+            .unwrap();
+        assert_eq!(
+            outcome,
+            RescanOutcome::Cancelled {
+                found: vec![received.id()],
+                resume_height: 3,
+            }
+        );
+        assert_eq!(seen, vec![0.25, 0.5]);
+        let mut storage = MemoryStorage::new();
+        store.save(&mut storage).unwrap();
+        let mut store = WalletStore::load(&storage).unwrap().unwrap();
+        assert_eq!(store.rescan_checkpoint(), Some(3));
+
+        // a timed out rescan leaves the checkpoint alone
+        let cancel = CancelToken::new().deadline(Instant::now());
+        let err = store
+            .rescan_until(3, &mut backend, &cancel, |_| true)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<CancelError>().unwrap(),
+            CancelError::TimedOut
+        );
+        assert_eq!(store.rescan_checkpoint(), Some(3));
+
+        let outcome = store.resume_rescan(&mut backend, |_| true).unwrap();
+        assert_eq!(
+            outcome.unwrap(),
+            RescanOutcome::Completed {
+                found: vec![spent.id()]
+            }
+        );
+        assert_eq!(store.rescan_checkpoint(), None);
+        assert!(store.resume_rescan(&mut backend, |_| true).is_none());
+        assert!(store.get(&unrelated.id()).is_none());
+        assert_eq!(store.status(&spent.id()).unwrap().confirmations(), 2);
+        assert!(store.utxos().is_empty());
+
+        store.save(&mut storage).unwrap();
+        let store = WalletStore::load(&storage).unwrap().unwrap();
+        assert_eq!(store.rescan_checkpoint(), None);
+    }
+}
@@ -0,0 +1,172 @@
+use sha2::{Digest, Sha256};
+
+use super::{TxStatus, Utxo, WalletStore};
+use crate::script::{Script, ScriptError};
+use crate::transaction::{ScriptPubKey, TxHash};
+use crate::wallet::hash160;
+
+/// `OP_HASH160 <hash160(script)> OP_EQUAL`
+fn p2sh(script: &[u8]) -> ScriptPubKey {
+    let mut content = Vec::with_capacity(23);
+    content.extend_from_slice(&[0xa9, 0x14]);
+    content.extend_from_slice(&hash160(script));
+    content.push(0x87);
+    ScriptPubKey { content }
+}
+
+/// `OP_0 <sha256(script)>`
+fn p2wsh(script: &[u8]) -> ScriptPubKey {
+    let mut content = Vec::with_capacity(34);
+    content.extend_from_slice(&[0x00, 0x20]);
+    content.extend_from_slice(&Sha256::digest(script));
+    ScriptPubKey { content }
+}
+
+impl WalletStore {
+    /// Follow a contract the wallet has no keys for, an HTLC or a vault. Its outputs can
+    /// take any of the P2WSH, P2SH-P2WSH and P2SH forms of `script`, all three are watched
+    /// and returned in that order.
+    pub fn watch_script(&mut self, script: &Script) -> Result<Vec<ScriptPubKey>, ScriptError> {
+        let raw = script.raw_serialize()?;
+        let witness_program = p2wsh(&raw);
+        let script_pub_keys = vec![
+            witness_program.clone(),
+            p2sh(&witness_program.content),
+            p2sh(&raw),
+        ];
+        for script_pub_key in &script_pub_keys {
+            self.watch_script_pubkey(script_pub_key.clone());
+        }
+        Ok(script_pub_keys)
+    }
+
+    /// Follow outputs paying `script_pub_key` without counting them as spendable
+    pub fn watch_script_pubkey(&mut self, script_pub_key: ScriptPubKey) {
+        if !self.watched.contains(&script_pub_key) {
+            self.watched.push(script_pub_key);
+        }
+    }
+
+    pub fn is_watched(&self, script_pub_key: &ScriptPubKey) -> bool {
+        self.watched.contains(script_pub_key)
+    }
+
+    pub fn watched_scripts(&self) -> &[ScriptPubKey] {
+        &self.watched
+    }
+
+    /// Unspent outputs of the watched scripts, kept apart from `utxos` so coin selection
+    /// never picks an output the wallet cannot sign for
+    pub fn watched_utxos(&self) -> Vec<Utxo> {
+        self.unspent(|script_pub_key| self.is_watched(script_pub_key))
+    }
+
+    pub fn watched_balance(&self) -> u64 {
+        self.watched_utxos()
+            .iter()
+            .map(|utxo| u64::from(utxo.output.amount))
+            .sum()
+    }
+
+    /// Transactions paying `script_pub_key` or spending one of its outputs, in the order
+    /// of `history`
+    pub fn script_history(&self, script_pub_key: &ScriptPubKey) -> Vec<(TxHash, TxStatus)> {
+        let pays = |tx_id: &TxHash, vout: usize| {
+            self.txs.get(tx_id).map_or(false, |stored| {
+                stored
+                    .tx
+                    .outputs
+                    .get(vout)
+                    .map_or(false, |output| output.script_pub_key == *script_pub_key)
+            })
+        };
+        self.history()
+            .into_iter()
+            .filter(|(tx_id, _)| {
+                let tx = &self.txs[tx_id].tx;
+                (0..tx.outputs.len()).any(|vout| pays(tx_id, vout))
+                    || tx.inputs.iter().any(|input| {
+                        let out_point = input.out_point();
+                        pays(&out_point.txid, out_point.vout as usize)
+                    })
+            })
+            .collect()
+    }
+}
+
+mod test {
+    use super::WalletStore;
+    use crate::script::{OpCode, Script};
+    use crate::storage::MemoryStorage;
+    use crate::transaction::{
+        PreTxIndex, ScriptPubKey, ScriptSig, Transaction, TxHash, TxInput, TxInputSequence,
+        TxLocktime, TxOutput, TxVersion,
+    };
+    use sha2::{Digest, Sha256};
+
+    fn pay(txid: TxHash, vout: u32, to: &ScriptPubKey, amount: u64) -> Transaction {
+        Transaction::new(
+            TxVersion::new(2),
+            vec![TxInput::new(
+                txid,
+                PreTxIndex::new(vout),
+                ScriptSig { content: vec![] },
+                TxInputSequence::new(0xffff_ffff),
+            )],
+            vec![TxOutput {
+                amount: amount.into(),
+                script_pub_key: to.clone(),
+            }],
+            TxLocktime::new(0),
+            false,
+        )
+    }
+
+    #[test]
+    fn test_watch_script() {
+        // OP_SHA256 <hash> OP_EQUAL, a bare hashlock
+        let mut script = Script::new();
+        script.push_opcode(OpCode::OpSha256);
+        script.push_data_ele(&[0x11; 32]);
+        script.push_opcode(OpCode::OpEqual);
+
+        let mut store = WalletStore::new(false);
+        let watched = store.watch_script(&script).unwrap();
+        assert_eq!(watched.len(), 3);
+        let raw = script.raw_serialize().unwrap();
+        assert_eq!(watched[0].content[2..], Sha256::digest(&raw)[..]);
+        assert_eq!(watched[1].content.len(), 23);
+        assert_eq!(store.watched_scripts().len(), 3);
+
+        let outside = TxHash::new(&[7; 32]).unwrap().1;
+        let funding = store.insert(pay(outside, 0, &watched[0], 50_000));
+        let other = store.insert(pay(outside, 1, &watched[2], 20_000));
+        assert_eq!(store.watched_balance(), 70_000);
+        // watch only outputs are not the wallet's to spend
+        assert!(store.utxos().is_empty());
+
+        let claim = store.insert(pay(
+            funding,
+            0,
+            &ScriptPubKey {
+                content: vec![0x51],
+            },
+            49_000,
+        ));
+        assert_eq!(store.watched_balance(), 20_000);
+        let history: Vec<TxHash> = store
+            .script_history(&watched[0])
+            .into_iter()
+            .map(|(tx_id, _)| tx_id)
+            .collect();
+        assert_eq!(history, vec![funding, claim]);
+        assert_eq!(store.script_history(&watched[2]).len(), 1);
+        assert_eq!(store.script_history(&watched[2])[0].0, other);
+
+        let mut storage = MemoryStorage::new();
+        store.save(&mut storage).unwrap();
+        let loaded = WalletStore::load(&storage).unwrap().unwrap();
+        assert_eq!(loaded.watched_scripts(), store.watched_scripts());
+        assert_eq!(loaded.watched_utxos(), store.watched_utxos());
+    }
+}
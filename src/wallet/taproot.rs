@@ -0,0 +1,417 @@
+use super::bech32::encode_segwit_address;
+use super::secp256k1::ec::utils::U256;
+use super::secp256k1::s256_point::S256Point;
+use super::{tagged_hash, XOnlyPublicKey};
+use crate::transaction::{ScriptPubKey, Varint};
+
+/// Leaf version of BIP342 tapscript
+pub const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+const TAPROOT_LEAF_MASK: u8 = 0xfe;
+const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum TaprootError {
+    #[fail(display = "invalid control block length: {}", _0)]
+    InvalidControlBlockLength(usize),
+    #[fail(display = "x coordinate is not on the curve")]
+    InvalidInternalKey,
+    #[fail(display = "tweak is not a valid scalar")]
+    InvalidTweak,
+}
+
+/// A script committed in the taproot tree together with its leaf version
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TapLeaf {
+    pub version: u8,
+    pub script: Vec<u8>,
+}
+
+impl TapLeaf {
+    pub fn new(script: Vec<u8>) -> Self {
+        TapLeaf {
+            version: TAPROOT_LEAF_TAPSCRIPT,
+            script,
+        }
+    }
+
+    /// tagged_hash("TapLeaf", leaf_version || compact_size(script) || script)
+    pub fn leaf_hash(&self) -> [u8; 32] {
+        let mut msg = vec![self.version];
+        msg.extend(Varint::encode(self.script.len() as u64).expect("script length fits u64"));
+        msg.extend_from_slice(&self.script);
+        tagged_hash("TapLeaf", &msg)
+    }
+}
+
+/// Binary tree of leaf scripts, the root hash is committed in the output key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TapTree {
+    Leaf(TapLeaf),
+    Branch(Box<TapTree>, Box<TapTree>),
+}
+
+impl TapTree {
+    pub fn leaf(script: Vec<u8>) -> Self {
+        TapTree::Leaf(TapLeaf::new(script))
+    }
+
+    pub fn branch(left: TapTree, right: TapTree) -> Self {
+        TapTree::Branch(Box::new(left), Box::new(right))
+    }
+
+    /// Balanced tree, neighbouring nodes are paired level by level
+    pub fn from_scripts(scripts: Vec<Vec<u8>>) -> Option<Self> {
+        let mut nodes: Vec<TapTree> = scripts.into_iter().map(TapTree::leaf).collect();
+        while nodes.len() > 1 {
+            let mut next = Vec::with_capacity((nodes.len() + 1) / 2);
+            let mut iter = nodes.into_iter();
+            while let Some(left) = iter.next() {
+                match iter.next() {
+                    Some(right) => next.push(TapTree::branch(left, right)),
+                    None => next.push(left),
+                }
+            }
+            nodes = next;
+        }
+        nodes.pop()
+    }
+
+    /// Merkle root of the tree, equal to the leaf hash for a single leaf
+    pub fn hash(&self) -> [u8; 32] {
+        match self {
+            TapTree::Leaf(leaf) => leaf.leaf_hash(),
+            TapTree::Branch(left, right) => tap_branch_hash(&left.hash(), &right.hash()),
+        }
+    }
+
+    /// Every leaf with its merkle branch, the sibling hashes from the leaf up to the root
+    pub fn leaves(&self) -> Vec<(TapLeaf, Vec<[u8; 32]>)> {
+        match self {
+            TapTree::Leaf(leaf) => vec![(leaf.clone(), vec![])],
+            TapTree::Branch(left, right) => {
+                let mut leaves = Vec::new();
+                for (leaf, mut path) in left.leaves() {
+                    path.push(right.hash());
+                    leaves.push((leaf, path));
+                }
+                for (leaf, mut path) in right.leaves() {
+                    path.push(left.hash());
+                    leaves.push((leaf, path));
+                }
+                leaves
+            }
+        }
+    }
+}
+
+/// Children are sorted, so a merkle branch needs no left/right flags
+fn tap_branch_hash(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let mut msg = Vec::with_capacity(64);
+    if a <= b {
+        msg.extend_from_slice(a);
+        msg.extend_from_slice(b);
+    } else {
+        msg.extend_from_slice(b);
+        msg.extend_from_slice(a);
+    }
+    tagged_hash("TapBranch", &msg)
+}
+
+/// Q = P + tagged_hash("TapTweak", P || merkle_root) * G, returns Q and whether its y is odd
+fn tweak_internal_key(
+    internal_key: &XOnlyPublicKey,
+    merkle_root: Option<&[u8; 32]>,
+) -> Result<(XOnlyPublicKey, bool), TaprootError> {
+    let mut msg = internal_key.serialize().to_vec();
+    if let Some(root) = merkle_root {
+        msg.extend_from_slice(root);
+    }
+    internal_key
+        .add_tweak(&tagged_hash("TapTweak", &msg))
+        .map_err(|_| TaprootError::InvalidTweak)
+}
+
+/// Everything needed to pay to a taproot output and later spend it by key or by script path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaprootSpendInfo {
+    pub internal_key: XOnlyPublicKey,
+    pub merkle_root: Option<[u8; 32]>,
+    pub output_key: XOnlyPublicKey,
+    pub output_key_parity: bool,
+    leaves: Vec<(TapLeaf, Vec<[u8; 32]>)>,
+}
+
+impl TaprootSpendInfo {
+    pub fn new(internal_key: &S256Point, tree: Option<&TapTree>) -> Result<Self, TaprootError> {
+        let (internal_key, _) = XOnlyPublicKey::from_point(internal_key);
+        let merkle_root = tree.map(TapTree::hash);
+        let (output_key, output_key_parity) =
+            tweak_internal_key(&internal_key, merkle_root.as_ref())?;
+        Ok(TaprootSpendInfo {
+            internal_key,
+            merkle_root,
+            output_key,
+            output_key_parity,
+            leaves: tree.map(TapTree::leaves).unwrap_or_default(),
+        })
+    }
+
+    /// OP_1 <32 bytes output key>
+    pub fn script_pubkey(&self) -> ScriptPubKey {
+        let mut content = Vec::with_capacity(34);
+        content.extend_from_slice(&[0x51, 0x20]);
+        content.extend_from_slice(&self.output_key.serialize());
+        ScriptPubKey { content }
+    }
+
+    pub fn address(&self, testnet: bool) -> String {
+        let hrp = if testnet { "tb" } else { "bc" };
+        encode_segwit_address(hrp, 1, &self.output_key.serialize())
+    }
+
+    /// Control block for spending through `leaf`, None if the leaf is not in the tree
+    pub fn control_block(&self, leaf: &TapLeaf) -> Option<ControlBlock> {
+        self.leaves
+            .iter()
+            .find(|(l, _)| l == leaf)
+            .map(|(leaf, path)| ControlBlock {
+                leaf_version: leaf.version,
+                output_key_parity: self.output_key_parity,
+                internal_key: self.internal_key,
+                merkle_branch: path.clone(),
+            })
+    }
+}
+
+/// Last witness element of a script path spend, proves the script is committed in the output key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlBlock {
+    pub leaf_version: u8,
+    pub output_key_parity: bool,
+    pub internal_key: XOnlyPublicKey,
+    pub merkle_branch: Vec<[u8; 32]>,
+}
+
+impl ControlBlock {
+    pub fn parse(bytes: &[u8]) -> Result<Self, TaprootError> {
+        let len = bytes.len();
+        if len < TAPROOT_CONTROL_BASE_SIZE
+            || (len - TAPROOT_CONTROL_BASE_SIZE) % TAPROOT_CONTROL_NODE_SIZE != 0
+            || (len - TAPROOT_CONTROL_BASE_SIZE) / TAPROOT_CONTROL_NODE_SIZE
+                > TAPROOT_CONTROL_MAX_NODE_COUNT
+        {
+            return Err(TaprootError::InvalidControlBlockLength(len));
+        }
+
+        let internal_key =
+            XOnlyPublicKey::parse(&bytes[1..33]).ok_or(TaprootError::InvalidInternalKey)?;
+
+        let merkle_branch = bytes[TAPROOT_CONTROL_BASE_SIZE..]
+            .chunks(TAPROOT_CONTROL_NODE_SIZE)
+            .map(|chunk| {
+                let mut node = [0u8; 32];
+                node.copy_from_slice(chunk);
+                node
+            })
+            .collect();
+
+        Ok(ControlBlock {
+            leaf_version: bytes[0] & TAPROOT_LEAF_MASK,
+            output_key_parity: bytes[0] & 1 == 1,
+            internal_key,
+            merkle_branch,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(
+            TAPROOT_CONTROL_BASE_SIZE + TAPROOT_CONTROL_NODE_SIZE * self.merkle_branch.len(),
+        );
+        buf.push(self.leaf_version | self.output_key_parity as u8);
+        buf.extend_from_slice(&self.internal_key.serialize());
+        for node in &self.merkle_branch {
+            buf.extend_from_slice(node);
+        }
+        buf
+    }
+
+    /// Check `script` together with this control block commits to the x only `output_key`
+    pub fn verify(&self, output_key: &XOnlyPublicKey, script: &[u8]) -> bool {
+        let leaf = TapLeaf {
+            version: self.leaf_version,
+            script: script.to_vec(),
+        };
+        let root = self
+            .merkle_branch
+            .iter()
+            .fold(leaf.leaf_hash(), |node, sibling| {
+                tap_branch_hash(&node, sibling)
+            });
+
+        match tweak_internal_key(&self.internal_key, Some(&root)) {
+            Ok((key, parity)) => key == *output_key && parity == self.output_key_parity,
+            Err(_) => false,
+        }
+    }
+}
+
+mod test {
+    use super::{ControlBlock, TapLeaf, TapTree, TaprootError, TaprootSpendInfo, U256};
+    use crate::script::{verify_taproot_input, ScriptError};
+    use crate::transaction::{Transaction, TxOutput, TxOutputAmount};
+    use crate::wallet::private_key::PrivateKey;
+    use crate::wallet::S256Point;
+
+    fn point(x_only: &[u8]) -> S256Point {
+        let mut sec = vec![0x02];
+        sec.extend_from_slice(x_only);
+        S256Point::parse_sec(&sec).unwrap()
+    }
+
+    #[test]
+    fn test_key_path_only() {
+        // BIP341 wallet test vector, no script tree
+        let internal_key = point(&hex!(
+            "d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d"
+        ));
+        let info = TaprootSpendInfo::new(&internal_key, None).unwrap();
+        assert_eq!(
+            info.output_key.serialize(),
+            hex!("53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343")
+        );
+        assert_eq!(
+            info.address(false),
+            "bc1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dps59h4z5".to_string()
+        );
+        assert_eq!(info.script_pubkey().content[0..2], [0x51, 0x20]);
+    }
+
+    #[test]
+    fn test_single_leaf_control_block() {
+        // BIP341 wallet test vector, one leaf
+        let internal_key = point(&hex!(
+            "187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27"
+        ));
+        let script =
+            hex!("20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac").to_vec();
+        let tree = TapTree::leaf(script.clone());
+        assert_eq!(
+            tree.hash(),
+            hex!("5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21")
+        );
+
+        let info = TaprootSpendInfo::new(&internal_key, Some(&tree)).unwrap();
+        assert_eq!(
+            info.output_key.serialize(),
+            hex!("147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3")
+        );
+        assert_eq!(
+            info.address(false),
+            "bc1pz37fc4cn9ah8anwm4xqqhvxygjf9rjf2resrw8h8w4tmvcs0863sa2e586".to_string()
+        );
+
+        let control_block = info.control_block(&TapLeaf::new(script.clone())).unwrap();
+        assert_eq!(
+            control_block.serialize(),
+            hex!("c1187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27").to_vec()
+        );
+        assert!(control_block.verify(&info.output_key, &script));
+    }
+
+    #[test]
+    fn test_script_tree_control_blocks() {
+        let internal_key = point(&hex!(
+            "187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27"
+        ));
+        let scripts = vec![vec![0x51], vec![0x52], vec![0x53]];
+        let tree = TapTree::from_scripts(scripts.clone()).unwrap();
+        let info = TaprootSpendInfo::new(&internal_key, Some(&tree)).unwrap();
+
+        for script in &scripts {
+            let control_block = info.control_block(&TapLeaf::new(script.clone())).unwrap();
+            let parsed = ControlBlock::parse(&control_block.serialize()).unwrap();
+            assert_eq!(parsed, control_block);
+            assert!(parsed.verify(&info.output_key, script));
+            assert!(!parsed.verify(&info.output_key, &[0x54]));
+        }
+        assert_eq!(info.control_block(&TapLeaf::new(vec![0x54])), None);
+        assert_eq!(
+            ControlBlock::parse(&[0xc0; 40]),
+            Err(TaprootError::InvalidControlBlockLength(40))
+        );
+    }
+
+    #[test]
+    fn test_script_path_spend() {
+        let alice = PrivateKey::new(U256::from(11u8));
+        let bob = PrivateKey::new(U256::from(12u8));
+        let internal_key = PrivateKey::new(U256::from(13u8)).point;
+
+        // <alice> OP_CHECKSIG
+        let mut single = vec![0x20];
+        single.extend_from_slice(&alice.point.x_only());
+        single.push(0xac);
+        // <alice> OP_CHECKSIG <bob> OP_CHECKSIGADD 2 OP_NUMEQUAL
+        let mut multi = single.clone();
+        multi.push(0x20);
+        multi.extend_from_slice(&bob.point.x_only());
+        multi.extend_from_slice(&[0xba, 0x52, 0x9c]);
+
+        let tree = TapTree::from_scripts(vec![single.clone(), multi.clone()]).unwrap();
+        let info = TaprootSpendInfo::new(&internal_key, Some(&tree)).unwrap();
+        let prevouts = vec![TxOutput {
+            amount: TxOutputAmount::from(100_000),
+            script_pub_key: info.script_pubkey(),
+        }];
+        let data = hex!("0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600");
+        let (_, mut tx) = Transaction::parse(&data[..]).unwrap();
+
+        let sign = |tx: &Transaction, key: &PrivateKey, script: &[u8]| {
+            let leaf_hash = TapLeaf::new(script.to_vec()).leaf_hash();
+            let msg = tx
+                .sig_hash_taproot(0, &prevouts, 0x00, None, Some((&leaf_hash, 0xffff_ffff)))
+                .unwrap();
+            key.sign_schnorr(&msg, &[0u8; 32]).serialize().to_vec()
+        };
+        let control_block = |script: &[u8]| {
+            info.control_block(&TapLeaf::new(script.to_vec()))
+                .unwrap()
+                .serialize()
+        };
+
+        tx.inputs[0].witness = vec![
+            sign(&tx, &alice, &single),
+            single.clone(),
+            control_block(&single),
+        ];
+        assert_eq!(verify_taproot_input(&tx, 0, &prevouts), Ok(true));
+
+        tx.inputs[0].witness = vec![
+            sign(&tx, &bob, &multi),
+            sign(&tx, &alice, &multi),
+            multi.clone(),
+            control_block(&multi),
+        ];
+        assert_eq!(verify_taproot_input(&tx, 0, &prevouts), Ok(true));
+
+        // a missing signature only lowers the count
+        tx.inputs[0].witness[0] = vec![];
+        assert_eq!(verify_taproot_input(&tx, 0, &prevouts), Ok(false));
+
+        // a signature over another transaction fails the script
+        tx.inputs[0].witness[0] = sign(&tx, &bob, &multi);
+        tx.outputs[0].amount = TxOutputAmount::from(1);
+        assert_eq!(
+            verify_taproot_input(&tx, 0, &prevouts),
+            Err(ScriptError::SchnorrSigInvalid)
+        );
+
+        tx.inputs[0].witness[2] = single.clone();
+        assert_eq!(
+            verify_taproot_input(&tx, 0, &prevouts),
+            Err(ScriptError::WitnessProgramMismatch)
+        );
+    }
+}
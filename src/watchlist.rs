@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use crate::block::Block;
+use crate::indexer::script_hash;
+use crate::network::Network;
+use crate::transaction::{OutPoint, ScriptPubKey, Transaction, TxHash, TxOutput};
+
+#[derive(Fail, Debug)]
+pub enum WatchlistError {
+    #[fail(display = "line {}: {} is no address or script", line, entry)]
+    InvalidEntry { line: usize, entry: String },
+    #[fail(display = "{}", _0)]
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for WatchlistError {
+    fn from(e: std::io::Error) -> Self {
+        WatchlistError::Io(e)
+    }
+}
+
+/// Where in a transaction a watched script showed up
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum MatchSide {
+    /// Output `vout` pays the script
+    Output(u32),
+    /// Input `index` spends `spent`, an output paying the script
+    Input { index: usize, spent: OutPoint },
+}
+impl Copy for MatchSide {}
+
+/// A transaction touching a watched script
+#[derive(Debug, PartialEq, Clone)]
+pub struct WatchMatch<'a> {
+    pub txid: TxHash,
+    pub side: MatchSide,
+    pub amount: u64,
+    /// Label the script was added with
+    pub label: &'a str,
+}
+
+/// Scripts to look out for, e.g. the addresses of a sanctions list, checked against every
+/// output and input of the scanned transactions. Scripts are kept by their 32 byte script
+/// hash whatever their length, and labels once however many scripts share them, so
+/// millions of entries fit. Each output check is a single hash lookup.
+///
+/// Inputs carry no script pubkey, a spend matches when it spends an output this watchlist
+/// saw paying a watched script while scanning, or when the spent outputs are given.
+#[derive(Debug, Clone, Default)]
+pub struct Watchlist {
+    /// Script hash -> index into `labels`
+    scripts: HashMap<[u8; 32], usize>,
+    labels: Vec<String>,
+    label_indexes: HashMap<String, usize>,
+    /// Unspent outputs paying a watched script seen while scanning, with their amount and
+    /// label
+    funded: HashMap<OutPoint, (u64, usize)>,
+}
+
+impl Watchlist {
+    pub fn new() -> Self {
+        Watchlist::default()
+    }
+
+    /// One entry per line, an address of `network` or a hex script pubkey, then an
+    /// optional label after whitespace. Empty lines and lines starting with `#` are
+    /// skipped, an entry without a label is its own label.
+    pub fn load<R: BufRead>(reader: R, network: &Network) -> Result<Self, WatchlistError> {
+        let mut watchlist = Watchlist::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let entry = parts.next().unwrap_or_default();
+            let label = parts.next().map_or(entry, str::trim);
+            let script_pub_key = ScriptPubKey::from_address(entry, network)
+                .or_else(|| {
+                    hex::decode(entry)
+                        .ok()
+                        .map(|content| ScriptPubKey { content })
+                })
+                .ok_or_else(|| WatchlistError::InvalidEntry {
+                    line: index + 1,
+                    entry: entry.to_string(),
+                })?;
+            watchlist.insert(&script_pub_key, label);
+        }
+        Ok(watchlist)
+    }
+
+    /// Watch `script_pub_key`, a script added again takes the new label
+    pub fn insert(&mut self, script_pub_key: &ScriptPubKey, label: &str) {
+        let label_index = match self.label_indexes.get(label) {
+            Some(label_index) => *label_index,
+            None => {
+                self.labels.push(label.to_string());
+                self.label_indexes
+                    .insert(label.to_string(), self.labels.len() - 1);
+                self.labels.len() - 1
+            }
+        };
+        self.scripts
+            .insert(script_hash(script_pub_key), label_index);
+    }
+
+    /// Watch the output script of `address`, false for an address not of `network`
+    pub fn insert_address(&mut self, address: &str, network: &Network, label: &str) -> bool {
+        match ScriptPubKey::from_address(address, network) {
+            Some(script_pub_key) => {
+                self.insert(&script_pub_key, label);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Label of a watched script
+    pub fn get(&self, script_pub_key: &ScriptPubKey) -> Option<&str> {
+        self.scripts
+            .get(&script_hash(script_pub_key))
+            .map(|label_index| self.labels[*label_index].as_str())
+    }
+
+    pub fn contains(&self, script_pub_key: &ScriptPubKey) -> bool {
+        self.scripts.contains_key(&script_hash(script_pub_key))
+    }
+
+    /// Scripts watched
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// Outputs paying a watched script seen while scanning and not spent since
+    pub fn funded(&self) -> impl Iterator<Item = (&OutPoint, u64)> {
+        self.funded
+            .iter()
+            .map(|(out_point, (amount, _))| (out_point, *amount))
+    }
+
+    /// Call `on_match` for every input and output of `tx` touching a watched script,
+    /// inputs first. `prevouts` are the outputs the inputs spend in input order, or empty
+    /// to only match spends of outputs seen before. Returns the number of matches.
+    pub fn scan_tx<F>(&mut self, tx: &Transaction, prevouts: &[TxOutput], mut on_match: F) -> usize
+    where
+        F: FnMut(&WatchMatch),
+    {
+        let txid = tx.id();
+        let mut matches = Vec::new();
+        if !tx.is_coinbase() {
+            for (index, input) in tx.inputs.iter().enumerate() {
+                let spent = input.out_point();
+                let found = self.funded.remove(&spent).or_else(|| {
+                    let prevout = prevouts.get(index)?;
+                    let label_index = self.scripts.get(&script_hash(&prevout.script_pub_key))?;
+                    Some((u64::from(prevout.amount), *label_index))
+                });
+                if let Some((amount, label_index)) = found {
+                    matches.push((MatchSide::Input { index, spent }, amount, label_index));
+                }
+            }
+        }
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            if let Some(label_index) = self.scripts.get(&script_hash(&output.script_pub_key)) {
+                let amount = u64::from(output.amount);
+                self.funded
+                    .insert(OutPoint::new(txid, vout as u32), (amount, *label_index));
+                matches.push((MatchSide::Output(vout as u32), amount, *label_index));
+            }
+        }
+
+        for (side, amount, label_index) in &matches {
+            on_match(&WatchMatch {
+                txid,
+                side: *side,
+                amount: *amount,
+                label: &self.labels[*label_index],
+            });
+        }
+        matches.len()
+    }
+
+    /// `scan_tx` every transaction of `block` in order, so spends within the block match
+    pub fn scan_block<F>(&mut self, block: &Block, mut on_match: F) -> usize
+    where
+        F: FnMut(&WatchMatch),
+    {
+        block
+            .txs
+            .iter()
+            .map(|tx| self.scan_tx(tx, &[], &mut on_match))
+            .sum()
+    }
+}
+
+mod test {
+    use super::{MatchSide, Watchlist, WatchlistError};
+    use crate::network::Network;
+    use crate::transaction::{OutPoint, ScriptPubKey, TransactionBuilder, TxHash, TxOutput};
+
+    #[test]
+    fn test_watchlist() {
+        let network = Network::mainnet();
+        let list = "\
+# sanctioned
+1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2 mixer
+bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq   mixer
+a91400112233445566778899aabbccddeeff0011223387
+";
+        let mut watchlist = Watchlist::load(list.as_bytes(), &network).unwrap();
+        assert_eq!(watchlist.len(), 3);
+        let p2pkh =
+            ScriptPubKey::from_address("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", &network).unwrap();
+        let p2sh = ScriptPubKey {
+            content: hex::decode("a91400112233445566778899aabbccddeeff0011223387").unwrap(),
+        };
+        assert_eq!(watchlist.get(&p2pkh), Some("mixer"));
+        assert_eq!(
+            watchlist.get(&p2sh),
+            Some("a91400112233445566778899aabbccddeeff0011223387")
+        );
+        assert!(!watchlist.contains(&ScriptPubKey {
+            content: vec![0x6a]
+        }));
+        assert!(!watchlist.insert_address(
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+            &network,
+            "testnet"
+        ));
+        match Watchlist::load("\n\nnot-an-address x\n".as_bytes(), &network) {
+            Err(WatchlistError::InvalidEntry { line, entry }) => {
+                assert_eq!((line, entry.as_str()), (3, "not-an-address"));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let funding = TransactionBuilder::new()
+            .add_input(OutPoint::new(TxHash::new(&[1; 32]).unwrap().1, 0), 0)
+            .add_output(
+                ScriptPubKey {
+                    content: vec![0x51],
+                },
+                5_000,
+            )
+            .add_output(p2pkh.clone(), 7_000)
+            .build();
+        let spend = TransactionBuilder::new()
+            .add_input(OutPoint::new(funding.id(), 1), 0)
+            .add_output(p2sh.clone(), 6_000)
+            .build();
+
+        let mut seen = Vec::new();
+        let mut on_match =
+            |m: &super::WatchMatch| seen.push((m.txid, m.side, m.amount, m.label.to_string()));
+        assert_eq!(watchlist.scan_tx(&funding, &[], &mut on_match), 1);
+        assert_eq!(watchlist.funded().count(), 1);
+        assert_eq!(watchlist.scan_tx(&spend, &[], &mut on_match), 2);
+        assert_eq!(watchlist.funded().count(), 1);
+        let spent = OutPoint::new(funding.id(), 1);
+        assert_eq!(
+            seen,
+            vec![
+                (
+                    funding.id(),
+                    MatchSide::Output(1),
+                    7_000,
+                    "mixer".to_string()
+                ),
+                (
+                    spend.id(),
+                    MatchSide::Input { index: 0, spent },
+                    7_000,
+                    "mixer".to_string()
+                ),
+                (
+                    spend.id(),
+                    MatchSide::Output(0),
+                    6_000,
+                    "a91400112233445566778899aabbccddeeff0011223387".to_string()
+                ),
+            ]
+        );
+
+        // a spend of an output from before the scan needs the prevouts
+        let mut fresh = Watchlist::load(list.as_bytes(), &network).unwrap();
+        assert_eq!(fresh.scan_tx(&spend, &[], |_| {}), 1);
+        let mut fresh = Watchlist::load(list.as_bytes(), &network).unwrap();
+        let prevout = TxOutput {
+            amount: 7_000.into(),
+            script_pub_key: p2pkh,
+        };
+        assert_eq!(fresh.scan_tx(&spend, &[prevout], |_| {}), 2);
+    }
+
+    #[test]
+    fn test_scan_blocks() {
+        use crate::testkit::TxGenerator;
+        use std::collections::HashSet;
+
+        let blocks = TxGenerator::new(3).blocks(40);
+        let watched = blocks[10].txs[0].outputs[0].script_pub_key.clone();
+        let mut watchlist = Watchlist::new();
+        watchlist.insert(&watched, "payout");
+
+        let paying: HashSet<OutPoint> = blocks
+            .iter()
+            .flat_map(|block| block.txs.iter())
+            .flat_map(|tx| {
+                let txid = tx.id();
+                tx.outputs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, output)| output.script_pub_key == watched)
+                    .map(move |(vout, _)| OutPoint::new(txid, vout as u32))
+            })
+            .collect();
+        let spends = blocks
+            .iter()
+            .flat_map(|block| block.txs.iter())
+            .flat_map(|tx| tx.inputs.iter())
+            .filter(|input| paying.contains(&input.out_point()))
+            .count();
+        assert!(spends > 0);
+
+        let (mut outputs, mut inputs) = (0, 0);
+        for block in &blocks {
+            watchlist.scan_block(block, |m| match m.side {
+                MatchSide::Output(_) => outputs += 1,
+                MatchSide::Input { .. } => inputs += 1,
+            });
+        }
+        assert_eq!((outputs, inputs), (paying.len(), spends));
+        assert_eq!(watchlist.funded().count(), paying.len() - spends);
+    }
+}